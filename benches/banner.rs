@@ -0,0 +1,74 @@
+//! The banner path: a fresh 4 KiB buffer and String per open port, as
+//! banners used to be read, against `read_banner` with one reused buffer.
+//!
+//! Run with `cargo bench --bench banner`; bytes allocated per banner are
+//! printed before the timings.
+
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use port_scanner::banner::{read_banner, BANNER_CAP};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::AsyncReadExt;
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const GREETING: &[u8] = b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n";
+
+async fn fresh_buffer(mut stream: &[u8]) -> Option<String> {
+    let mut buffer = vec![0u8; BANNER_CAP];
+    let n = stream.read(&mut buffer).await.ok()?;
+    (n > 0).then(|| String::from_utf8_lossy(&buffer[..n]).trim_end().to_string())
+}
+
+fn allocated_per_call(mut f: impl FnMut()) -> usize {
+    const CALLS: usize = 1000;
+    f();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    for _ in 0..CALLS {
+        f();
+    }
+    (ALLOCATED.load(Ordering::Relaxed) - before) / CALLS
+}
+
+fn banner(c: &mut Criterion) {
+    let mut buf = BytesMut::with_capacity(BANNER_CAP);
+    println!(
+        "bytes allocated per banner: fresh buffer {}, reused buffer {}",
+        allocated_per_call(|| {
+            black_box(block_on(fresh_buffer(GREETING)));
+        }),
+        allocated_per_call(|| {
+            black_box(block_on(read_banner(&mut &GREETING[..], &mut buf)).unwrap());
+        }),
+    );
+
+    let mut group = c.benchmark_group("banner");
+    group.bench_function("fresh_buffer", |b| {
+        b.iter(|| block_on(fresh_buffer(black_box(GREETING))))
+    });
+    group.bench_function("reused_buffer", |b| {
+        b.iter(|| block_on(read_banner(&mut black_box(GREETING), &mut buf)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, banner);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+//! Feeds arbitrary text to the port and target grammar; run with
+//! `cargo +nightly fuzz run specs`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use port_scanner::parse_ports;
+use port_scanner::plan::TargetSet;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(spec) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = parse_ports(spec);
+    for target in spec.split(',') {
+        let mut targets = TargetSet::new();
+        if targets.insert(target).is_ok() {
+            assert!(!targets.is_empty());
+            let _ = targets.to_string();
+        }
+    }
+});
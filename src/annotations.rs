@@ -0,0 +1,495 @@
+//! What an asset inventory says each port is (`--annotations`), merged into
+//! the results so reports show it and ports that don't look the way they
+//! should stand out.
+//!
+//! The file is TOML with one `[[annotation]]` table per entry:
+//!
+//! ```toml
+//! [[annotation]]
+//! target = "10.0.0.5"
+//! port = 5432
+//! note = "prod postgres"
+//! expected = "open"
+//!
+//! [[annotation]]
+//! target = "10.0.*"
+//! port = "8000-8100"
+//! expected = "closed"
+//! ```
+//!
+//! `target` is an address, CIDR block or range, or a pattern in which `*`
+//! stands for any run of characters of the address; `port` is a number or a
+//! list such as `"22,8000-8100"`. Left out (or `"*"`), either matches
+//! everything. The first entry matching a port applies.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use thiserror::Error;
+use toml_edit::{Document, Item, Table};
+
+use crate::plan::{PortSet, TargetSet};
+use crate::scanner::{PortResult, PortStatus};
+
+/// Why an annotations file could not be loaded.
+#[derive(Error, Debug)]
+pub enum AnnotationsError {
+    /// The file could not be read.
+    #[error("Cannot read annotations {path}: {source}")]
+    Read {
+        /// The annotations path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is not TOML.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The annotations path.
+        path: String,
+        /// The underlying error.
+        source: toml_edit::TomlError,
+    },
+    /// The file has something other than `[[annotation]]` tables.
+    #[error("{path}: line {line}: unexpected '{key}', entries are [[annotation]] tables")]
+    Layout {
+        /// The annotations path.
+        path: String,
+        /// The unexpected top-level key.
+        key: String,
+        /// Line it is on, from 1.
+        line: usize,
+    },
+    /// An entry is not a valid annotation.
+    #[error("{path}: annotation {number} (line {line}): {reason}")]
+    Entry {
+        /// The annotations path.
+        path: String,
+        /// Position of the entry in the file, from 1.
+        number: usize,
+        /// Line the entry starts on, from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+enum TargetPattern {
+    Any,
+    Set(TargetSet),
+    Glob(String),
+}
+
+impl TargetPattern {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if spec == "*" {
+            Ok(TargetPattern::Any)
+        } else if spec.contains('*') {
+            Ok(TargetPattern::Glob(spec.to_ascii_lowercase()))
+        } else {
+            let mut set = TargetSet::new();
+            set.insert(spec)
+                .map_err(|e| format!("invalid target '{}': {}", spec, e))?;
+            Ok(TargetPattern::Set(set))
+        }
+    }
+
+    fn matches(&self, target: IpAddr) -> bool {
+        match self {
+            TargetPattern::Any => true,
+            TargetPattern::Set(set) => set.contains(target),
+            TargetPattern::Glob(pattern) => glob(pattern, &target.to_string()),
+        }
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of
+/// characters, however short.
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// One entry of the file.
+struct Annotation {
+    target: TargetPattern,
+    ports: Option<PortSet>,
+    note: Option<String>,
+    expected: Option<PortStatus>,
+}
+
+impl Annotation {
+    fn from_table(table: &Table) -> Result<Self, String> {
+        let mut annotation = Annotation {
+            target: TargetPattern::Any,
+            ports: None,
+            note: None,
+            expected: None,
+        };
+        for (key, item) in table.iter() {
+            match key {
+                "target" => {
+                    annotation.target = TargetPattern::parse(string(key, item)?)?;
+                }
+                "port" => {
+                    let spec = match item.as_integer() {
+                        Some(port) => port.to_string(),
+                        None => string(key, item)?.trim().to_string(),
+                    };
+                    if spec != "*" {
+                        let ports = PortSet::parse(&spec)
+                            .map_err(|e| format!("invalid port '{}': {}", spec, e))?;
+                        annotation.ports = Some(ports);
+                    }
+                }
+                "note" => annotation.note = Some(string(key, item)?.to_string()),
+                "expected" => {
+                    let status = string(key, item)?;
+                    annotation.expected = Some(match status.trim().to_ascii_lowercase().as_str() {
+                        "open" => PortStatus::Open,
+                        "closed" => PortStatus::Closed,
+                        "filtered" => PortStatus::Filtered,
+                        _ => {
+                            return Err(format!(
+                                "unknown status '{}' (expected: open, closed, filtered)",
+                                status
+                            ))
+                        }
+                    });
+                }
+                other => {
+                    return Err(format!(
+                        "unknown key '{}' (expected: target, port, note, expected)",
+                        other
+                    ))
+                }
+            }
+        }
+        if annotation.note.is_none() && annotation.expected.is_none() {
+            return Err("needs a note or an expected status".to_string());
+        }
+        Ok(annotation)
+    }
+
+    fn matches(&self, r: &PortResult) -> bool {
+        self.target.matches(r.target) && self.ports.as_ref().is_none_or(|p| p.contains(r.port))
+    }
+}
+
+fn string<'a>(key: &str, item: &'a Item) -> Result<&'a str, String> {
+    item.as_str()
+        .ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+/// The entries of an annotations file.
+pub struct Annotations {
+    entries: Vec<Annotation>,
+}
+
+impl Annotations {
+    /// Reads and parses an annotations file.
+    pub fn load(path: &Path) -> Result<Self, AnnotationsError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| AnnotationsError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses annotations from their TOML text; `name` appears in errors.
+    pub fn parse(name: &str, text: &str) -> Result<Self, AnnotationsError> {
+        let doc = Document::parse(text).map_err(|source| AnnotationsError::Parse {
+            path: name.to_string(),
+            source,
+        })?;
+        let line = |span: Option<std::ops::Range<usize>>| {
+            let offset = span.map_or(0, |s| s.start);
+            text[..offset].matches('\n').count() + 1
+        };
+        let mut entries = Vec::new();
+        for (key, item) in doc.iter() {
+            let Some(tables) = item.as_array_of_tables().filter(|_| key == "annotation") else {
+                return Err(AnnotationsError::Layout {
+                    path: name.to_string(),
+                    key: key.to_string(),
+                    line: line(item.span()),
+                });
+            };
+            for (i, table) in tables.iter().enumerate() {
+                let annotation =
+                    Annotation::from_table(table).map_err(|reason| AnnotationsError::Entry {
+                        path: name.to_string(),
+                        number: i + 1,
+                        line: line(table.span()),
+                        reason,
+                    })?;
+                entries.push(annotation);
+            }
+        }
+        Ok(Annotations { entries })
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True when the file had no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sets the note and expected status of the first entry matching `r`.
+    pub fn apply(&self, r: &mut PortResult) {
+        if let Some(a) = self.entries.iter().find(|a| a.matches(r)) {
+            r.note = a.note.clone();
+            r.expected_status = a.expected.clone();
+        }
+    }
+}
+
+/// How a port differs from what its annotation expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deviation {
+    /// Open, though expected closed or filtered.
+    UnexpectedOpen,
+    /// Expected open, but closed or filtered.
+    NotOpen,
+    /// Closed where filtered was expected, or the other way round.
+    Other,
+}
+
+impl Deviation {
+    /// How `r` deviates from its expected status, if it does.
+    pub fn of(r: &PortResult) -> Option<Self> {
+        let expected = r.expected_status.as_ref()?;
+        if *expected == r.status {
+            None
+        } else if r.status == PortStatus::Open {
+            Some(Deviation::UnexpectedOpen)
+        } else if *expected == PortStatus::Open {
+            Some(Deviation::NotOpen)
+        } else {
+            Some(Deviation::Other)
+        }
+    }
+}
+
+/// Number of annotated ports that deviate from their expected status, as
+/// recorded in the scan summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviationCounts {
+    /// Ports with an expected status.
+    pub annotated: usize,
+    /// Open ports expected to be closed or filtered.
+    pub unexpected_open: usize,
+    /// Ports expected open but found closed or filtered.
+    pub not_open: usize,
+    /// Closed ports expected filtered, or the other way round.
+    pub other: usize,
+}
+
+impl DeviationCounts {
+    /// Counts the deviations among `results`; none when no result had an
+    /// expected status.
+    pub fn of<'a>(results: impl IntoIterator<Item = &'a PortResult>) -> Option<Self> {
+        let mut counts = None;
+        for r in results {
+            DeviationCounts::record(&mut counts, r);
+        }
+        counts
+    }
+
+    /// Adds `r` to `counts` if it had an expected status.
+    pub fn record(counts: &mut Option<Self>, r: &PortResult) {
+        if r.expected_status.is_none() {
+            return;
+        }
+        let counts = counts.get_or_insert_with(DeviationCounts::default);
+        counts.annotated += 1;
+        match Deviation::of(r) {
+            Some(Deviation::UnexpectedOpen) => counts.unexpected_open += 1,
+            Some(Deviation::NotOpen) => counts.not_open += 1,
+            Some(Deviation::Other) => counts.other += 1,
+            None => {}
+        }
+    }
+
+    /// Ports that deviate, of any kind.
+    pub fn total(&self) -> usize {
+        self.unexpected_open + self.not_open + self.other
+    }
+}
+
+impl fmt::Display for DeviationCounts {
+    /// `2 of 40 annotated ports deviate: 1 unexpectedly open, 1 not open`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} annotated ports deviate",
+            self.total(),
+            self.annotated
+        )?;
+        let parts: Vec<String> = [
+            (self.unexpected_open, "unexpectedly open"),
+            (self.not_open, "not open"),
+            (self.other, "closed/filtered swapped"),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, label)| format!("{} {}", n, label))
+        .collect();
+        if !parts.is_empty() {
+            write!(f, ": {}", parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(target: &str, port: u16, status: PortStatus) -> PortResult {
+        PortResult::new(target.parse().unwrap(), port, status)
+    }
+
+    const INVENTORY: &str = r#"
+        [[annotation]]
+        target = "10.0.0.5"
+        port = 5432
+        note = "prod postgres"
+        expected = "open"
+
+        [[annotation]]
+        target = "10.0.*"
+        port = "8000-8100"
+        note = "dev servers"
+        expected = "closed"
+
+        [[annotation]]
+        port = "23"
+        expected = "filtered"
+
+        [[annotation]]
+        target = "192.168.1.0/24"
+        note = "office"
+    "#;
+
+    #[test]
+    fn the_first_matching_entry_applies() {
+        let annotations = Annotations::parse("inventory.toml", INVENTORY).unwrap();
+        assert_eq!(annotations.len(), 4);
+        let mut results = vec![
+            result("10.0.0.5", 5432, PortStatus::Filtered),
+            result("10.0.7.1", 8080, PortStatus::Open),
+            result("10.0.7.1", 8200, PortStatus::Open),
+            result("172.16.0.1", 23, PortStatus::Closed),
+            result("192.168.1.40", 443, PortStatus::Open),
+            result("10.1.0.1", 8080, PortStatus::Closed),
+        ];
+        for r in &mut results {
+            annotations.apply(r);
+        }
+        let notes: Vec<Option<&str>> = results.iter().map(|r| r.note.as_deref()).collect();
+        assert_eq!(
+            notes,
+            [
+                Some("prod postgres"),
+                Some("dev servers"),
+                None,
+                None,
+                Some("office"),
+                None
+            ]
+        );
+        let deviations: Vec<Option<Deviation>> = results.iter().map(Deviation::of).collect();
+        assert_eq!(
+            deviations,
+            [
+                Some(Deviation::NotOpen),
+                Some(Deviation::UnexpectedOpen),
+                None,
+                Some(Deviation::Other),
+                None,
+                None
+            ]
+        );
+
+        let counts = DeviationCounts::of(&results).unwrap();
+        assert_eq!(counts.annotated, 3);
+        assert_eq!(
+            counts.to_string(),
+            "3 of 3 annotated ports deviate: 1 unexpectedly open, 1 not open, \
+             1 closed/filtered swapped"
+        );
+        assert_eq!(DeviationCounts::of(&results[4..]), None);
+    }
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        assert!(glob("10.0.*", "10.0.3.4"));
+        assert!(glob("10.*.*.1", "10.20.30.1"));
+        assert!(!glob("10.*.*.1", "10.20.30.11"));
+        assert!(glob("fe80::*", "fe80::1"));
+        assert!(glob("*", ""));
+        assert!(!glob("10.0.*", "110.0.3.4"));
+        assert!(!glob("*.1", "10.0.0.2"));
+    }
+
+    #[test]
+    fn errors_name_the_entry() {
+        let text = "[[annotation]]\nport = 22\nexpected = \"up\"\n";
+        let e = Annotations::parse("a.toml", text).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "a.toml: annotation 1 (line 1): unknown status 'up' (expected: open, closed, filtered)"
+        );
+
+        let text = format!(
+            "{}\n[[annotation]]\ntarget = \"10.0.0.300\"\nnote = \"x\"\n",
+            INVENTORY
+        );
+        let e = Annotations::parse("a.toml", &text)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            e.starts_with("a.toml: annotation 5 (line 22): invalid target '10.0.0.300'"),
+            "{}",
+            e
+        );
+
+        let e = Annotations::parse("a.toml", "[[annotation]]\nport = 22\n")
+            .err()
+            .unwrap();
+        assert!(e
+            .to_string()
+            .ends_with("needs a note or an expected status"));
+        let e = Annotations::parse("a.toml", "[[annotations]]\nport = 22\n")
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("unexpected 'annotations'"));
+        assert!(matches!(
+            Annotations::parse("a.toml", "[[annotation]\n"),
+            Err(AnnotationsError::Parse { .. })
+        ));
+    }
+}
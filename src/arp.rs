@@ -0,0 +1,385 @@
+//! ARP discovery of targets on the scanning machine's own subnets
+//! (`--arp-discovery`): every such target is asked for its MAC address
+//! before the scan, and those that don't answer can be left out.
+//!
+//! Needs Linux, a raw `AF_PACKET` socket (root or `CAP_NET_RAW`) and builds
+//! with the `arp` feature. Targets on no local subnet are never asked.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::oui::MacAddr;
+use crate::plan::TargetSet;
+
+/// What ARP found out about the local targets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Discovery {
+    /// The targets that answered, with their MAC addresses.
+    pub found: HashMap<Ipv4Addr, MacAddr>,
+    /// The local targets that didn't answer, in ascending order.
+    pub silent: Vec<Ipv4Addr>,
+}
+
+/// Why ARP discovery could not run.
+#[derive(Error, Debug)]
+pub enum ArpError {
+    /// This build or platform has no ARP support.
+    #[error("ARP discovery unavailable: {0}")]
+    Unsupported(String),
+    /// The raw socket was refused, usually for lack of privileges.
+    #[error("ARP discovery needs root or CAP_NET_RAW: {0}")]
+    Permission(std::io::Error),
+    /// Sending or receiving failed.
+    #[error("ARP discovery failed: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Asks every IPv4 target on a local subnet for its MAC address, twice for
+/// those that stay silent, waiting `wait` after each round for the answers.
+pub async fn discover(targets: &TargetSet, wait: Duration) -> Result<Discovery, ArpError> {
+    imp::discover(targets, wait).await
+}
+
+/// The ARP packets themselves, built and read on every platform so they can
+/// be tested anywhere.
+#[cfg_attr(not(all(feature = "arp", target_os = "linux")), allow(dead_code))]
+mod packet {
+    use crate::oui::MacAddr;
+    use std::net::Ipv4Addr;
+
+    const ARP_REQUEST: u16 = 1;
+    const ARP_REPLY: u16 = 2;
+
+    /// An ARP request from `sender_mac`/`sender_ip` asking who has `target`.
+    pub fn request(sender_mac: MacAddr, sender_ip: Ipv4Addr, target: Ipv4Addr) -> [u8; 28] {
+        let mut packet = [0u8; 28];
+        packet[0..2].copy_from_slice(&1u16.to_be_bytes()); // Ethernet
+        packet[2..4].copy_from_slice(&0x0800u16.to_be_bytes()); // IPv4
+        packet[4] = 6;
+        packet[5] = 4;
+        packet[6..8].copy_from_slice(&ARP_REQUEST.to_be_bytes());
+        packet[8..14].copy_from_slice(&sender_mac.0);
+        packet[14..18].copy_from_slice(&sender_ip.octets());
+        packet[24..28].copy_from_slice(&target.octets());
+        packet
+    }
+
+    /// The sender of an Ethernet/IPv4 ARP reply; `None` for anything else.
+    pub fn parse_reply(packet: &[u8]) -> Option<(Ipv4Addr, MacAddr)> {
+        let header = packet.get(..8)?;
+        if header != [0, 1, 8, 0, 6, 4, 0, ARP_REPLY as u8] {
+            return None;
+        }
+        let mac: [u8; 6] = packet.get(8..14)?.try_into().ok()?;
+        let ip: [u8; 4] = packet.get(14..18)?.try_into().ok()?;
+        Some((Ipv4Addr::from(ip), MacAddr(mac)))
+    }
+}
+
+#[cfg(all(feature = "arp", target_os = "linux"))]
+mod imp {
+    use super::packet::{parse_reply, request};
+    use super::{ArpError, Discovery};
+    use crate::oui::MacAddr;
+    use crate::plan::TargetSet;
+    use std::collections::{BTreeSet, HashMap};
+    use std::ffi::CStr;
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+    use tokio::io::unix::AsyncFd;
+    use tokio::time::Instant;
+
+    /// How many times a silent host is asked.
+    const ROUNDS: usize = 2;
+
+    /// An up, non-loopback interface with an IPv4 subnet.
+    #[derive(Debug, Clone)]
+    struct Interface {
+        name: String,
+        index: i32,
+        mac: MacAddr,
+        ip: Ipv4Addr,
+        mask: u32,
+    }
+
+    impl Interface {
+        fn contains(&self, ip: Ipv4Addr) -> bool {
+            u32::from(ip) & self.mask == u32::from(self.ip) & self.mask
+        }
+    }
+
+    pub async fn discover(targets: &TargetSet, wait: Duration) -> Result<Discovery, ArpError> {
+        let interfaces = interfaces()?;
+        let mut discovery = Discovery::default();
+        for interface in &interfaces {
+            // This machine knows its own address without asking.
+            if targets.contains(interface.ip.into()) {
+                discovery.found.insert(interface.ip, interface.mac);
+            }
+            let wanted: BTreeSet<Ipv4Addr> = targets
+                .iter()
+                .filter_map(|t| match t {
+                    IpAddr::V4(ip) if interface.contains(ip) => Some(ip),
+                    _ => None,
+                })
+                .filter(|ip| !discovery.found.contains_key(ip))
+                .collect();
+            if wanted.is_empty() {
+                continue;
+            }
+            tracing::debug!("ARP: asking {} hosts on {}", wanted.len(), interface.name);
+            let found = ask(interface, &wanted, wait).await?;
+            discovery
+                .silent
+                .extend(wanted.iter().filter(|ip| !found.contains_key(ip)));
+            discovery.found.extend(found);
+        }
+        discovery.silent.sort();
+        discovery.silent.dedup();
+        Ok(discovery)
+    }
+
+    async fn ask(
+        interface: &Interface,
+        wanted: &BTreeSet<Ipv4Addr>,
+        wait: Duration,
+    ) -> Result<HashMap<Ipv4Addr, MacAddr>, ArpError> {
+        let socket = AsyncFd::new(open(interface.index)?)?;
+        let mut found = HashMap::new();
+        for _ in 0..ROUNDS {
+            for &ip in wanted.iter().filter(|ip| !found.contains_key(*ip)) {
+                let packet = request(interface.mac, interface.ip, ip);
+                loop {
+                    let mut guard = socket.writable().await?;
+                    match guard.try_io(|fd| send(fd.get_ref(), interface.index, &packet)) {
+                        Ok(sent) => break sent?,
+                        Err(_would_block) => continue,
+                    }
+                }
+            }
+            let deadline = Instant::now() + wait;
+            let mut buf = [0u8; 64];
+            while found.len() < wanted.len() {
+                let Ok(readable) = tokio::time::timeout_at(deadline, socket.readable()).await
+                else {
+                    break;
+                };
+                let mut guard = readable?;
+                let n = match guard.try_io(|fd| recv(fd.get_ref(), &mut buf)) {
+                    Ok(n) => n?,
+                    Err(_would_block) => continue,
+                };
+                if let Some((ip, mac)) = parse_reply(&buf[..n]) {
+                    if wanted.contains(&ip) {
+                        found.insert(ip, mac);
+                    }
+                }
+            }
+            if found.len() == wanted.len() {
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    fn link_address(index: i32, to: Option<[u8; 6]>) -> libc::sockaddr_ll {
+        // SAFETY: sockaddr_ll is plain data; zeroed is a valid value.
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = (libc::ETH_P_ARP as u16).to_be();
+        addr.sll_ifindex = index;
+        if let Some(to) = to {
+            addr.sll_halen = 6;
+            addr.sll_addr[..6].copy_from_slice(&to);
+        }
+        addr
+    }
+
+    fn open(index: i32) -> Result<OwnedFd, ArpError> {
+        // SAFETY: socket returns a new descriptor we own, or -1.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                (libc::ETH_P_ARP as u16).to_be() as i32,
+            )
+        };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            return Err(match e.kind() {
+                io::ErrorKind::PermissionDenied => ArpError::Permission(e),
+                _ => ArpError::Io(e),
+            });
+        }
+        // SAFETY: fd is a fresh, valid descriptor nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let addr = link_address(index, None);
+        // SAFETY: addr is a sockaddr_ll of the size given.
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(fd)
+    }
+
+    fn send(fd: &OwnedFd, index: i32, packet: &[u8]) -> io::Result<()> {
+        let to = link_address(index, Some([0xff; 6]));
+        // SAFETY: packet and to outlive the call, with the sizes given.
+        let n = unsafe {
+            libc::sendto(
+                fd.as_raw_fd(),
+                packet.as_ptr().cast(),
+                packet.len(),
+                0,
+                &to as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn recv(fd: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: buf outlives the call, with the size given.
+        let n = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    /// The up, non-loopback interfaces with both a MAC and an IPv4 address.
+    fn interfaces() -> io::Result<Vec<Interface>> {
+        let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+        // SAFETY: getifaddrs fills in head, freed below.
+        if unsafe { libc::getifaddrs(&mut head) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut macs: HashMap<String, (i32, MacAddr)> = HashMap::new();
+        let mut ips: Vec<(String, Ipv4Addr, u32)> = Vec::new();
+        let mut entry = head;
+        // SAFETY: the list and its addresses stay valid until freeifaddrs;
+        // each address is read as the family it names.
+        unsafe {
+            while !entry.is_null() {
+                let ifa = &*entry;
+                entry = ifa.ifa_next;
+                let flags = ifa.ifa_flags as libc::c_int;
+                if ifa.ifa_addr.is_null()
+                    || flags & libc::IFF_UP == 0
+                    || flags & libc::IFF_LOOPBACK != 0
+                {
+                    continue;
+                }
+                let name = CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+                match (*ifa.ifa_addr).sa_family as libc::c_int {
+                    libc::AF_PACKET => {
+                        let ll = &*(ifa.ifa_addr as *const libc::sockaddr_ll);
+                        if ll.sll_halen == 6 {
+                            let mut mac = [0u8; 6];
+                            mac.copy_from_slice(&ll.sll_addr[..6]);
+                            macs.insert(name, (ll.sll_ifindex, MacAddr(mac)));
+                        }
+                    }
+                    libc::AF_INET if !ifa.ifa_netmask.is_null() => {
+                        let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                        let mask = &*(ifa.ifa_netmask as *const libc::sockaddr_in);
+                        ips.push((
+                            name,
+                            Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                            u32::from_be(mask.sin_addr.s_addr),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            libc::freeifaddrs(head);
+        }
+        Ok(ips
+            .into_iter()
+            .filter_map(|(name, ip, mask)| {
+                let &(index, mac) = macs.get(&name)?;
+                Some(Interface {
+                    name,
+                    index,
+                    mac,
+                    ip,
+                    mask,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(not(all(feature = "arp", target_os = "linux")))]
+mod imp {
+    use super::{ArpError, Discovery};
+    use crate::plan::TargetSet;
+    use std::time::Duration;
+
+    pub async fn discover(_: &TargetSet, _: Duration) -> Result<Discovery, ArpError> {
+        Err(ArpError::Unsupported(
+            if cfg!(target_os = "linux") {
+                "built without the arp feature"
+            } else {
+                "only supported on Linux"
+            }
+            .to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::packet::{parse_reply, request};
+    use super::*;
+
+    #[test]
+    fn requests_ask_who_has_the_target() {
+        let mac: MacAddr = "02:fc:00:00:00:01".parse().unwrap();
+        let packet = request(
+            mac,
+            Ipv4Addr::new(192, 0, 2, 2),
+            Ipv4Addr::new(192, 0, 2, 1),
+        );
+        assert_eq!(&packet[..8], &[0, 1, 8, 0, 6, 4, 0, 1]);
+        assert_eq!(&packet[8..14], &mac.0);
+        assert_eq!(&packet[14..18], &[192, 0, 2, 2]);
+        assert_eq!(&packet[18..24], &[0; 6]);
+        assert_eq!(&packet[24..], &[192, 0, 2, 1]);
+        assert_eq!(parse_reply(&packet), None, "a request is not a reply");
+    }
+
+    #[test]
+    fn replies_give_the_sender() {
+        let mut reply = request(
+            "00:50:56:aa:bb:cc".parse().unwrap(),
+            Ipv4Addr::new(10, 0, 0, 7),
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        reply[7] = 2;
+        assert_eq!(
+            parse_reply(&reply),
+            Some((
+                Ipv4Addr::new(10, 0, 0, 7),
+                "00:50:56:aa:bb:cc".parse().unwrap()
+            ))
+        );
+        assert_eq!(parse_reply(&reply[..17]), None);
+    }
+}
@@ -0,0 +1,186 @@
+//! The audit log written with `--log-file`.
+//!
+//! Scan lifecycle events — the scan starting with its command line, each
+//! host starting and finishing, throttle changes, cancellation — are
+//! logged with the [`TARGET`] target. They go to the log file alongside
+//! every other event at info level or above, and to the console only with
+//! `-v verbose` or `-v debug`, so the file keeps the whole story however
+//! quiet the console is. [`RotatingFile`] rolls the file over to
+//! `<path>.1` once it reaches `--log-max-size`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The target of lifecycle events.
+pub const TARGET: &str = "audit";
+
+/// Options whose values are secrets, left out of the logged command line.
+const SECRET_OPTIONS: [&str; 2] = ["--smtp-password", "--token"];
+
+/// The command line for the scan-start event, with secret values masked.
+pub fn command_line(args: impl IntoIterator<Item = String>) -> String {
+    let mut masked = Vec::new();
+    let mut secret_next = false;
+    for arg in args {
+        if secret_next {
+            masked.push("***".to_string());
+            secret_next = false;
+        } else if let Some((option, _)) = arg
+            .split_once('=')
+            .filter(|(option, _)| SECRET_OPTIONS.contains(option))
+        {
+            masked.push(format!("{}=***", option));
+        } else {
+            secret_next = SECRET_OPTIONS.contains(&arg.as_str());
+            masked.push(arg);
+        }
+    }
+    masked.join(" ")
+}
+
+/// Parses a `--log-max-size` such as `10M`: bytes, or `K`, `M` and `G`
+/// (powers of 1024, optionally followed by `B` or `iB`).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let shift = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        _ => return Err(format!("invalid size '{}' (e.g. 500K, 10M or 1G)", s)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("invalid size '{}' (e.g. 500K, 10M or 1G)", s))
+}
+
+/// A log file that is renamed to `<path>.1`, replacing the previous one,
+/// before a write would take it past the size limit.
+#[derive(Debug, Clone)]
+pub struct RotatingFile {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_size: Option<u64>,
+}
+
+impl RotatingFile {
+    /// Appends to `path`, creating it if needed; `None` never rotates.
+    pub fn open(path: &Path, max_size: Option<u64>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            inner: Arc::new(Mutex::new(Inner {
+                path: path.to_path_buf(),
+                file,
+                written,
+                max_size,
+            })),
+        })
+    }
+
+    /// The path of the rolled-over file.
+    pub fn rolled_path(path: &Path) -> PathBuf {
+        let mut rolled = path.as_os_str().to_owned();
+        rolled.push(".1");
+        PathBuf::from(rolled)
+    }
+}
+
+impl Inner {
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        std::fs::rename(&self.path, RotatingFile::rolled_path(&self.path))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Writes one event to a [`RotatingFile`].
+pub struct RotatingWriter<'a>(MutexGuard<'a, Inner>);
+
+impl Write for RotatingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut *self.0;
+        if let Some(max) = inner.max_size {
+            if inner.written > 0 && inner.written + buf.len() as u64 > max {
+                inner.rotate()?;
+            }
+        }
+        let n = inner.file.write(buf)?;
+        inner.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingWriter(self.inner.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_masked_in_the_command_line() {
+        let args = [
+            "port-scanner",
+            "-t",
+            "10.0.0.1",
+            "--smtp-password",
+            "hunter2",
+            "--token=abc",
+        ];
+        assert_eq!(
+            command_line(args.map(String::from)),
+            "port-scanner -t 10.0.0.1 --smtp-password *** --token=***"
+        );
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert_eq!(parse_size("512kib"), Ok(512 << 10));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("1T").is_err());
+    }
+
+    #[test]
+    fn the_file_rolls_over_once_full() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", std::process::id()));
+        let rolled = RotatingFile::rolled_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&rolled);
+        let log = RotatingFile::open(&path, Some(10)).unwrap();
+        for line in ["first\n", "second\n", "third\n"] {
+            log.make_writer().write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&rolled).unwrap(), "second\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third\n");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rolled).unwrap();
+    }
+}
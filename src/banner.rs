@@ -0,0 +1,197 @@
+//! Banners: the first bytes an open port sends, read into reusable buffers.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::charset::{self, BannerEncoding};
+
+/// Most bytes read from a banner; the rest is left unread.
+pub const BANNER_CAP: usize = 4096;
+
+/// The bytes a service greeted the scanner with, trailing whitespace
+/// trimmed. Kept as read and only decoded for display and serialization,
+/// where it becomes a string: as UTF-8, or in the charset
+/// [`charset::decode`] detects.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Banner(Bytes);
+
+impl Banner {
+    /// The raw bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The text: borrowed when the banner is UTF-8, otherwise decoded in
+    /// the charset detected or escaped.
+    pub fn to_str(&self) -> Cow<'_, str> {
+        charset::decode(&self.0).0
+    }
+
+    /// How [`Banner::to_str`] got the text when the banner isn't UTF-8.
+    pub fn encoding(&self) -> Option<BannerEncoding> {
+        charset::decode(&self.0).1
+    }
+
+    /// Length in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// True when the service sent only whitespace.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<&str> for Banner {
+    fn from(s: &str) -> Self {
+        Banner(Bytes::copy_from_slice(s.as_bytes()))
+    }
+}
+
+impl From<Bytes> for Banner {
+    fn from(bytes: Bytes) -> Self {
+        Banner(bytes)
+    }
+}
+
+impl From<String> for Banner {
+    fn from(s: String) -> Self {
+        Banner(Bytes::from(s))
+    }
+}
+
+impl fmt::Display for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_str())
+    }
+}
+
+impl Serialize for Banner {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Banner {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Banner::from)
+    }
+}
+
+/// Reads one banner with a single read of at most [`BANNER_CAP`] bytes into
+/// `buf`, which is cleared first and can be reused for the next port. An
+/// immediate close is no banner.
+///
+/// `buf` keeps the bytes read, untrimmed; the banner is an exact-size copy.
+pub async fn read_banner<R: AsyncRead + Unpin + ?Sized>(
+    stream: &mut R,
+    buf: &mut BytesMut,
+) -> io::Result<Option<Banner>> {
+    buf.clear();
+    buf.reserve(BANNER_CAP);
+    let n = stream.read_buf(&mut (&mut *buf).limit(BANNER_CAP)).await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Banner(Bytes::copy_from_slice(buf.trim_ascii_end()))))
+}
+
+/// Reads on after [`read_banner`], appending to `buf` until it holds `cap`
+/// bytes or the stream ends. Stop it with a timeout for services that keep
+/// the connection open: `buf` keeps everything read until then.
+pub async fn read_rest<R: AsyncRead + Unpin + ?Sized>(
+    stream: &mut R,
+    buf: &mut BytesMut,
+    cap: usize,
+) -> io::Result<()> {
+    while buf.len() < cap {
+        let room = cap - buf.len();
+        buf.reserve(room.min(BANNER_CAP));
+        if stream.read_buf(&mut (&mut *buf).limit(room)).await? == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Read buffers handed from one open-port probe to the next, so a scan
+/// allocates about one per concurrent probe instead of one per open port.
+#[derive(Debug, Default)]
+pub(crate) struct BannerBuffers {
+    free: Mutex<Vec<BytesMut>>,
+}
+
+impl BannerBuffers {
+    /// A free buffer, or a new one.
+    pub(crate) fn take(&self) -> BytesMut {
+        self.free
+            .lock()
+            .expect("banner buffer lock poisoned")
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(BANNER_CAP))
+    }
+
+    /// Returns a buffer for the next probe.
+    pub(crate) fn give_back(&self, buf: BytesMut) {
+        self.free
+            .lock()
+            .expect("banner buffer lock poisoned")
+            .push(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn banners_are_trimmed_capped_and_reuse_the_buffer() {
+        let mut buf = BytesMut::with_capacity(BANNER_CAP);
+        let ptr = buf.as_ptr();
+
+        let mut ssh: &[u8] = b"SSH-2.0-Mock\r\n";
+        let banner = read_banner(&mut ssh, &mut buf).await.unwrap().unwrap();
+        assert_eq!(banner.to_str(), "SSH-2.0-Mock");
+        assert_eq!(serde_json::to_string(&banner).unwrap(), "\"SSH-2.0-Mock\"");
+
+        let long = vec![b'a'; 3 * BANNER_CAP];
+        let banner = read_banner(&mut &long[..], &mut buf)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(banner.len(), BANNER_CAP);
+        assert_eq!(buf.as_ptr(), ptr);
+
+        let mut closed: &[u8] = b"";
+        assert_eq!(read_banner(&mut closed, &mut buf).await.unwrap(), None);
+
+        let mut binary: &[u8] = b"\xff\xfeok";
+        let banner = read_banner(&mut binary, &mut buf).await.unwrap().unwrap();
+        assert_eq!(banner.as_bytes(), b"\xff\xfeok");
+        assert_eq!(banner.to_str(), "ÿþok");
+        assert_eq!(banner.encoding(), Some(BannerEncoding::Windows1252));
+    }
+
+    #[tokio::test]
+    async fn the_rest_is_read_up_to_the_cap() {
+        let mut buf = BytesMut::new();
+        let long = vec![b'a'; 3 * BANNER_CAP];
+        let mut stream = &long[..];
+        read_banner(&mut stream, &mut buf).await.unwrap();
+        read_rest(&mut stream, &mut buf, 2 * BANNER_CAP + 10)
+            .await
+            .unwrap();
+        assert_eq!(buf.len(), 2 * BANNER_CAP + 10);
+
+        let mut short: &[u8] = b"220 hello\r\n250 more\r\n";
+        read_banner(&mut short, &mut buf).await.unwrap();
+        read_rest(&mut short, &mut buf, BANNER_CAP).await.unwrap();
+        assert_eq!(&buf[..], b"220 hello\r\n250 more\r\n");
+    }
+}
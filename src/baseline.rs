@@ -0,0 +1,878 @@
+//! The expected state of a network (`--baseline`) and the drift of a scan
+//! from it, for continuous compliance scanning.
+//!
+//! A baseline is either an earlier report, in any format [`import`] reads,
+//! whose open ports are the ones expected, or a hand-written TOML file with
+//! one `[[host]]` table per host:
+//!
+//! ```toml
+//! [[host]]
+//! target = "10.0.0.5"
+//! open = [22, 443]
+//! services = { 22 = "SSH" }
+//! products = { 22 = "OpenSSH 9.6" }
+//!
+//! [[host]]
+//! target = "10.0.0.6"
+//! open = "80,8000-8010"
+//! ```
+//!
+//! A `target` is an address or a host name. Hosts are matched on their key
+//! in the mode of the scan (see [`identity`](crate::identity)), the hosts
+//! of a report by the names it recorded for them; a host name in the TOML
+//! stands for the scanned hosts it names.
+//!
+//! Every scanned host is expected to have exactly its baseline ports open,
+//! none for a host the baseline doesn't list. Three kinds of drift are
+//! reported, each also as a finding on the port: a port open that the
+//! baseline doesn't have, a baseline port scanned and found closed or
+//! filtered, and an open baseline port whose service or product is not the
+//! one expected. A service or product is only compared when both the
+//! baseline and the scan know it, so a banner that didn't arrive this time
+//! is no drift. Baseline ports this scan didn't probe are left unchecked.
+//! Baselines are of TCP ports; UDP results, which only an imported report
+//! can hold, are left out on both sides.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+use toml_edit::{Document, Table};
+
+use crate::identity::{self, HostKey, Identities, IdentityMode};
+use crate::import::{self, ImportError, InputFormat};
+use crate::plan::PortSet;
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+use crate::spec::Protocol;
+use crate::vuln::{Finding, FindingCounts, Severity};
+
+/// Why a baseline could not be loaded.
+#[derive(Error, Debug)]
+pub enum BaselineError {
+    /// The file could not be read.
+    #[error("Cannot read baseline {path}: {source}")]
+    Read {
+        /// The baseline path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is a report that could not be read.
+    #[error(transparent)]
+    Report(#[from] ImportError),
+    /// The file is neither a report nor TOML.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The baseline path.
+        path: String,
+        /// The underlying error.
+        source: toml_edit::TomlError,
+    },
+    /// The TOML has something other than `[[host]]` tables.
+    #[error("{path}: line {line}: unexpected '{key}', hosts are [[host]] tables")]
+    Layout {
+        /// The baseline path.
+        path: String,
+        /// The unexpected top-level key.
+        key: String,
+        /// Line it is on, from 1.
+        line: usize,
+    },
+    /// A `[[host]]` table is not a valid host.
+    #[error("{path}: host {number} (line {line}): {reason}")]
+    Host {
+        /// The baseline path.
+        path: String,
+        /// Position of the host in the file, from 1.
+        number: usize,
+        /// Line the host starts on, from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+/// What is expected of an open port.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Expected {
+    service: Option<String>,
+    /// The product, alone or followed by its version.
+    product: Option<String>,
+}
+
+/// The ports expected open on each host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baseline {
+    hosts: BTreeMap<HostKey, BTreeMap<u16, Expected>>,
+    /// What the report identified hosts by, for a report.
+    identity: Option<IdentityMode>,
+    /// The names of the hosts of the report.
+    names: Identities,
+}
+
+impl Baseline {
+    /// Reads a baseline: a report, or a hand-written TOML file.
+    pub fn load(path: &Path) -> Result<Self, BaselineError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| BaselineError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses a baseline from its text; `name` appears in errors. Our JSON
+    /// report and nmap's XML are taken for reports, anything else for TOML
+    /// if it parses as TOML, and for one of masscan's formats otherwise.
+    pub fn parse(name: &str, text: &str) -> Result<Self, BaselineError> {
+        let sniffed = InputFormat::sniff(text);
+        if !matches!(sniffed, Some(InputFormat::Json | InputFormat::NmapXml)) {
+            match Document::parse(text) {
+                Ok(doc) => return Self::from_toml(name, text, &doc),
+                Err(source) if sniffed.is_none() => {
+                    return Err(BaselineError::Parse {
+                        path: name.to_string(),
+                        source,
+                    })
+                }
+                Err(_) => {}
+            }
+        }
+        let summary = import::parse(text, sniffed, name)?;
+        Ok(Baseline {
+            identity: Some(summary.identity),
+            names: Identities::of(&summary, summary.identity),
+            ..Self::from_results(&summary.results)
+        })
+    }
+
+    /// The baseline of an earlier scan: its open ports, with the service
+    /// and product found on each.
+    pub fn from_results(results: &[PortResult]) -> Self {
+        let mut baseline = Baseline::default();
+        for r in results
+            .iter()
+            .filter(|r| r.status == PortStatus::Open && r.protocol == Protocol::Tcp)
+        {
+            let host = HostKey::Address(r.target);
+            baseline.hosts.entry(host).or_default().insert(
+                r.port,
+                Expected {
+                    service: r.service.clone(),
+                    product: r.product_version(),
+                },
+            );
+        }
+        baseline
+    }
+
+    fn from_toml(name: &str, text: &str, doc: &Document<&str>) -> Result<Self, BaselineError> {
+        let line = |span: Option<std::ops::Range<usize>>| {
+            let offset = span.map_or(0, |s| s.start);
+            text[..offset].matches('\n').count() + 1
+        };
+        let mut baseline = Baseline::default();
+        for (key, item) in doc.iter() {
+            let Some(tables) = item.as_array_of_tables().filter(|_| key == "host") else {
+                return Err(BaselineError::Layout {
+                    path: name.to_string(),
+                    key: key.to_string(),
+                    line: line(item.span()),
+                });
+            };
+            for (i, table) in tables.iter().enumerate() {
+                let (target, ports) = host(table).map_err(|reason| BaselineError::Host {
+                    path: name.to_string(),
+                    number: i + 1,
+                    line: line(table.span()),
+                    reason,
+                })?;
+                baseline.hosts.entry(target).or_default().extend(ports);
+            }
+        }
+        Ok(baseline)
+    }
+
+    /// A warning when the baseline is a report that identified hosts
+    /// otherwise than `mode`; `name` names the baseline in it.
+    pub fn mismatch(&self, name: &str, mode: IdentityMode) -> Option<String> {
+        identity::mismatch(name, self.identity?, mode)
+    }
+
+    /// Number of ports expected open, over every host.
+    pub fn expected_open(&self) -> usize {
+        self.hosts.values().map(BTreeMap::len).sum()
+    }
+
+    /// Checks the results of `summary` against the baseline, its hosts
+    /// keyed by `identities`, adds a finding to each result that drifted
+    /// and returns the outcome; `name` names the baseline in it.
+    pub fn check(
+        &self,
+        name: &str,
+        summary: &mut ScanSummary,
+        severities: &DriftSeverities,
+        identities: &Identities,
+    ) -> std::io::Result<Compliance> {
+        // Results are read through any spill file since closed ones may
+        // only be there.
+        let mut scanned = BTreeSet::new();
+        for r in summary.all_results()? {
+            scanned.insert(r?.target);
+        }
+        let (keys, hosts) = self.keyed(identities, &scanned);
+        let expected = |target: IpAddr, port: u16| hosts.get(&keys.key(target))?.get(&port);
+
+        // What became of the baseline ports.
+        let mut found: HashMap<(HostKey, u16), (IpAddr, PortStatus)> = HashMap::new();
+        for r in summary.all_results()? {
+            let r = r?;
+            if r.protocol == Protocol::Tcp
+                && expected(r.target, r.port).is_some()
+                && !r.error.as_ref().is_some_and(|e| e.is_local())
+            {
+                found.insert((keys.key(r.target), r.port), (r.target, r.status.clone()));
+            }
+        }
+
+        let mut drift = Vec::new();
+        for (key, ports) in &hosts {
+            for &port in ports.keys() {
+                match found.get(&(key.clone(), port)) {
+                    Some((_, PortStatus::Open)) | None => {}
+                    Some((target, status)) => drift.push(Drift::new(
+                        DriftKind::Closed,
+                        severities,
+                        *target,
+                        port,
+                        format!("{}, open in the baseline", status_name(status)),
+                    )),
+                }
+            }
+        }
+        for r in summary
+            .results
+            .iter()
+            .filter(|r| r.status == PortStatus::Open && r.protocol == Protocol::Tcp)
+        {
+            let Some(expected) = expected(r.target, r.port) else {
+                let what = r
+                    .product_version()
+                    .or_else(|| r.service.clone())
+                    .map_or(String::new(), |s| format!(" ({})", s));
+                drift.push(Drift::new(
+                    DriftKind::NewOpen,
+                    severities,
+                    r.target,
+                    r.port,
+                    format!("open, not in the baseline{}", what),
+                ));
+                continue;
+            };
+            let changes = [
+                ("service", &expected.service, r.service.clone()),
+                ("product", &expected.product, r.product_version()),
+            ];
+            for (what, expected, found) in changes {
+                if let (Some(expected), Some(found)) = (expected, found) {
+                    let same = found.eq_ignore_ascii_case(expected)
+                        || (what == "product"
+                            && r.product
+                                .as_deref()
+                                .is_some_and(|p| p.eq_ignore_ascii_case(expected)));
+                    if !same {
+                        drift.push(Drift::new(
+                            DriftKind::Changed,
+                            severities,
+                            r.target,
+                            r.port,
+                            format!("{} changed: {} -> {}", what, expected, found),
+                        ));
+                    }
+                }
+            }
+        }
+        drift.sort_by(|a, b| (b.severity, a.target, a.port).cmp(&(a.severity, b.target, b.port)));
+
+        for r in &mut summary.results {
+            for d in drift
+                .iter()
+                .filter(|d| d.target == r.target && d.port == r.port && r.protocol == Protocol::Tcp)
+            {
+                r.findings.push(Finding {
+                    id: d.kind.finding_id().to_string(),
+                    severity: d.severity,
+                    summary: format!("Drift from the baseline: {}", d.message),
+                });
+            }
+        }
+        summary.findings = FindingCounts::of(&summary.results);
+
+        let checked = found.len();
+        let missing = drift.iter().filter(|d| d.kind == DriftKind::Closed).count();
+        Ok(Compliance {
+            baseline: name.to_string(),
+            expected_open: self.expected_open(),
+            checked,
+            matched: checked - missing,
+            drift,
+        })
+    }
+
+    /// The identities to key the `scanned` hosts by, and the expected
+    /// ports by the key of their host in the same mode. A host name stands
+    /// for the scanned hosts it names, or else for a host of that key.
+    fn keyed(
+        &self,
+        identities: &Identities,
+        scanned: &BTreeSet<IpAddr>,
+    ) -> (Identities, BTreeMap<HostKey, BTreeMap<u16, Expected>>) {
+        let addresses: BTreeSet<IpAddr> = self
+            .hosts
+            .keys()
+            .filter_map(|key| match key {
+                HostKey::Address(address) => Some(*address),
+                HostKey::Name(_) => None,
+            })
+            .collect();
+        let (now, then) = identity::exchange(identities, scanned, &self.names, &addresses);
+        let mut hosts: BTreeMap<HostKey, BTreeMap<u16, Expected>> = BTreeMap::new();
+        for (key, ports) in &self.hosts {
+            let keys = match key {
+                HostKey::Address(address) => vec![then.key(*address)],
+                HostKey::Name(name) => {
+                    let named: Vec<HostKey> = scanned
+                        .iter()
+                        .filter(|&&host| now.names(host).contains(name))
+                        .map(|&host| now.key(host))
+                        .collect();
+                    if named.is_empty() {
+                        vec![key.clone()]
+                    } else {
+                        named
+                    }
+                }
+            };
+            for key in keys {
+                hosts.entry(key).or_default().extend(ports.clone());
+            }
+        }
+        (now, hosts)
+    }
+}
+
+/// The target and expected ports of a `[[host]]` table.
+fn host(table: &Table) -> Result<(HostKey, BTreeMap<u16, Expected>), String> {
+    let mut target = None;
+    let mut ports = BTreeMap::new();
+    let mut services = BTreeMap::new();
+    let mut products = BTreeMap::new();
+    for (key, item) in table.iter() {
+        match key {
+            "target" => {
+                let text = item.as_str().ok_or("'target' must be a string")?;
+                let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_');
+                let key = HostKey::parse(text);
+                if key
+                    .name()
+                    .is_some_and(|n| n.is_empty() || !n.chars().all(valid))
+                {
+                    return Err(format!("'{}' is not an IP address or host name", text));
+                }
+                target = Some(key);
+            }
+            "open" => {
+                let spec = if let Some(array) = item.as_array() {
+                    let numbers: Option<Vec<String>> = array
+                        .iter()
+                        .map(|v| v.as_integer().map(|n| n.to_string()))
+                        .collect();
+                    numbers.ok_or("'open' must list port numbers")?.join(",")
+                } else if let Some(port) = item.as_integer() {
+                    port.to_string()
+                } else {
+                    item.as_str()
+                        .ok_or("'open' must be a list of ports or a string such as \"22,80\"")?
+                        .to_string()
+                };
+                let set = if spec.trim().is_empty() {
+                    PortSet::new()
+                } else {
+                    PortSet::parse(&spec).map_err(|e| format!("invalid ports '{}': {}", spec, e))?
+                };
+                for port in set.iter() {
+                    ports.insert(port, Expected::default());
+                }
+            }
+            "services" | "products" => {
+                let names = item
+                    .as_table_like()
+                    .ok_or_else(|| format!("'{}' must be a table of port = \"name\"", key))?;
+                for (port, name) in names.iter() {
+                    let number: u16 = port
+                        .parse()
+                        .map_err(|_| format!("'{}' in '{}' is not a port", port, key))?;
+                    let name = name
+                        .as_str()
+                        .ok_or_else(|| format!("'{}.{}' must be a string", key, port))?
+                        .to_string();
+                    if key == "services" {
+                        services.insert(number, name);
+                    } else {
+                        products.insert(number, name);
+                    }
+                }
+            }
+            other => {
+                return Err(format!(
+                    "unknown key '{}' (expected: target, open, services, products)",
+                    other
+                ))
+            }
+        }
+    }
+    let target = target.ok_or("needs a target")?;
+    for port in services.keys().chain(products.keys()) {
+        if !ports.contains_key(port) {
+            return Err(format!(
+                "port {} has a service or product but isn't open",
+                port
+            ));
+        }
+    }
+    for (port, expected) in &mut ports {
+        expected.service = services.remove(port);
+        expected.product = products.remove(port);
+    }
+    Ok((target, ports))
+}
+
+fn status_name(status: &PortStatus) -> &'static str {
+    match status {
+        PortStatus::Open => "open",
+        PortStatus::Closed => "closed",
+        PortStatus::Filtered => "filtered",
+    }
+}
+
+/// How a port drifted from the baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DriftKind {
+    /// Open, but not in the baseline.
+    NewOpen,
+    /// Open in the baseline, but found closed or filtered.
+    Closed,
+    /// Open as expected, with another service or product.
+    Changed,
+}
+
+impl DriftKind {
+    /// Every kind, by name.
+    pub const ALL: [DriftKind; 3] = [DriftKind::NewOpen, DriftKind::Closed, DriftKind::Changed];
+
+    /// The name, as `--drift-severity` and the JSON report have it.
+    pub fn name(self) -> &'static str {
+        match self {
+            DriftKind::NewOpen => "new-open",
+            DriftKind::Closed => "closed",
+            DriftKind::Changed => "changed",
+        }
+    }
+
+    /// The id of the finding the drift adds to its port.
+    pub fn finding_id(self) -> &'static str {
+        match self {
+            DriftKind::NewOpen => "DRIFT-NEW-OPEN",
+            DriftKind::Closed => "DRIFT-CLOSED",
+            DriftKind::Changed => "DRIFT-CHANGED",
+        }
+    }
+}
+
+/// The severity of each kind of drift, from `--drift-severity` such as
+/// `new-open=critical,changed=medium`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriftSeverities {
+    /// Of a port open but not in the baseline; high by default.
+    pub new_open: Severity,
+    /// Of a baseline port found closed or filtered; medium by default.
+    pub closed: Severity,
+    /// Of a service or product change; low by default.
+    pub changed: Severity,
+}
+
+impl Default for DriftSeverities {
+    fn default() -> Self {
+        DriftSeverities {
+            new_open: Severity::High,
+            closed: Severity::Medium,
+            changed: Severity::Low,
+        }
+    }
+}
+
+impl DriftSeverities {
+    /// The severity of `kind`.
+    pub fn of(&self, kind: DriftKind) -> Severity {
+        match kind {
+            DriftKind::NewOpen => self.new_open,
+            DriftKind::Closed => self.closed,
+            DriftKind::Changed => self.changed,
+        }
+    }
+}
+
+impl FromStr for DriftSeverities {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut severities = DriftSeverities::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (kind, severity) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected <kind>=<severity>, got '{}'", part))?;
+            let kind = DriftKind::ALL
+                .into_iter()
+                .find(|k| k.name().eq_ignore_ascii_case(kind.trim()))
+                .ok_or_else(|| {
+                    format!(
+                        "unknown drift '{}' (valid kinds: new-open, closed, changed)",
+                        kind.trim()
+                    )
+                })?;
+            let severity = severity.parse()?;
+            match kind {
+                DriftKind::NewOpen => severities.new_open = severity,
+                DriftKind::Closed => severities.closed = severity,
+                DriftKind::Changed => severities.changed = severity,
+            }
+        }
+        Ok(severities)
+    }
+}
+
+/// One port that drifted from the baseline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Drift {
+    /// How it drifted.
+    pub kind: DriftKind,
+    /// How much that matters, from [`DriftSeverities`].
+    pub severity: Severity,
+    /// Host of the port.
+    pub target: IpAddr,
+    /// The port.
+    pub port: u16,
+    /// What was found, such as `closed, open in the baseline`.
+    pub message: String,
+}
+
+impl Drift {
+    fn new(
+        kind: DriftKind,
+        severities: &DriftSeverities,
+        target: IpAddr,
+        port: u16,
+        message: String,
+    ) -> Self {
+        Drift {
+            kind,
+            severity: severities.of(kind),
+            target,
+            port,
+            message,
+        }
+    }
+}
+
+/// The outcome of checking a scan against a baseline, as recorded in the
+/// scan summary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Compliance {
+    /// The baseline file.
+    pub baseline: String,
+    /// Ports the baseline expects open.
+    pub expected_open: usize,
+    /// Of those, the ports this scan probed.
+    pub checked: usize,
+    /// Of those, the ports found open.
+    pub matched: usize,
+    /// Every drift, most severe first.
+    pub drift: Vec<Drift>,
+}
+
+impl Compliance {
+    /// True when nothing drifted.
+    pub fn passed(&self) -> bool {
+        self.drift.is_empty()
+    }
+
+    /// Whether any drift is `min` or worse.
+    pub fn fails(&self, min: Severity) -> bool {
+        self.drift.iter().any(|d| d.severity >= min)
+    }
+
+    /// The subset about one host; its counts stay the whole scan's.
+    pub fn for_target(&self, target: IpAddr) -> Compliance {
+        Compliance {
+            drift: self
+                .drift
+                .iter()
+                .filter(|d| d.target == target)
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Compliance {
+    /// A heading with the totals, then a line per drift.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} expected open ports checked, {} open as expected, ",
+            self.baseline, self.checked, self.expected_open, self.matched
+        )?;
+        match self.drift.len() {
+            0 => write!(f, "no drift")?,
+            1 => write!(f, "1 drift")?,
+            n => write!(f, "{} drifts", n)?,
+        }
+        for d in &self.drift {
+            write!(
+                f,
+                "\n  {:<8} {:<9} {} {}",
+                d.severity.to_string().to_uppercase(),
+                d.kind.name(),
+                std::net::SocketAddr::new(d.target, d.port),
+                d.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ScanMetrics;
+
+    const TOML: &str = r#"
+[[host]]
+target = "10.0.0.5"
+open = [22, 443]
+services = { 22 = "SSH" }
+products = { 22 = "OpenSSH 9.6" }
+
+[[host]]
+target = "10.0.0.6"
+open = "80"
+"#;
+
+    fn result(target: &str, port: u16, status: PortStatus) -> PortResult {
+        PortResult::new(target.parse().unwrap(), port, status)
+    }
+
+    fn ssh(version: &str) -> PortResult {
+        let mut r = result("10.0.0.5", 22, PortStatus::Open);
+        r.service = Some("SSH".to_string());
+        r.product = Some("OpenSSH".to_string());
+        r.version = Some(version.to_string());
+        r
+    }
+
+    fn summary(results: Vec<PortResult>) -> ScanSummary {
+        ScanSummary::from_results(
+            "baseline-test".to_string(),
+            "10.0.0.0/24".to_string(),
+            results,
+            1000,
+            &ScanMetrics::new("10.0.0.5"),
+            false,
+        )
+    }
+
+    fn check(results: Vec<PortResult>) -> (Compliance, ScanSummary) {
+        let baseline = Baseline::parse("baseline.toml", TOML).unwrap();
+        let mut summary = summary(results);
+        let compliance = baseline
+            .check(
+                "baseline.toml",
+                &mut summary,
+                &DriftSeverities::default(),
+                &Identities::default(),
+            )
+            .unwrap();
+        (compliance, summary)
+    }
+
+    #[test]
+    fn an_exact_match_has_no_drift() {
+        let (compliance, summary) = check(vec![
+            ssh("9.6"),
+            result("10.0.0.5", 443, PortStatus::Open),
+            result("10.0.0.5", 23, PortStatus::Closed),
+            result("10.0.0.6", 80, PortStatus::Open),
+        ]);
+        assert!(compliance.passed(), "{}", compliance);
+        assert_eq!(
+            (
+                compliance.expected_open,
+                compliance.checked,
+                compliance.matched
+            ),
+            (3, 3, 3)
+        );
+        assert_eq!(summary.findings.total(), 0);
+        assert_eq!(
+            compliance.to_string(),
+            "baseline.toml: 3 of 3 expected open ports checked, 3 open as expected, no drift"
+        );
+    }
+
+    #[test]
+    fn drift_in_both_directions_becomes_findings() {
+        let (compliance, summary) = check(vec![
+            ssh("9.7"),
+            result("10.0.0.5", 443, PortStatus::Filtered),
+            result("10.0.0.5", 3389, PortStatus::Open),
+            result("10.0.0.6", 80, PortStatus::Closed),
+        ]);
+        let kinds: Vec<(DriftKind, Severity, u16)> = compliance
+            .drift
+            .iter()
+            .map(|d| (d.kind, d.severity, d.port))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (DriftKind::NewOpen, Severity::High, 3389),
+                (DriftKind::Closed, Severity::Medium, 443),
+                (DriftKind::Closed, Severity::Medium, 80),
+                (DriftKind::Changed, Severity::Low, 22),
+            ]
+        );
+        assert_eq!(compliance.matched, 1);
+        assert_eq!(
+            compliance.drift[3].message,
+            "product changed: OpenSSH 9.6 -> OpenSSH 9.7"
+        );
+        assert_eq!(summary.findings.total(), 4);
+        let rdp = summary.results.iter().find(|r| r.port == 3389).unwrap();
+        assert_eq!(rdp.findings[0].id, "DRIFT-NEW-OPEN");
+        assert!(compliance.fails(Severity::High));
+        assert!(!compliance
+            .for_target("10.0.0.6".parse().unwrap())
+            .fails(Severity::High));
+        assert!(compliance
+            .to_string()
+            .contains("\n  HIGH     new-open  10.0.0.5:3389 open, not in the baseline"));
+    }
+
+    #[test]
+    fn unprobed_ports_and_unknown_products_are_no_drift() {
+        let mut quiet_ssh = result("10.0.0.5", 22, PortStatus::Open);
+        quiet_ssh.service = Some("SSH".to_string());
+        let (compliance, _) = check(vec![quiet_ssh]);
+        assert!(compliance.passed(), "{}", compliance);
+        assert_eq!(compliance.checked, 1);
+    }
+
+    #[test]
+    fn an_earlier_report_is_a_baseline() {
+        let earlier = summary(vec![ssh("9.6"), result("10.0.0.5", 80, PortStatus::Closed)]);
+        let json = serde_json::to_string(&earlier).unwrap();
+        let baseline = Baseline::parse("earlier.json", &json).unwrap();
+        assert_eq!(baseline.expected_open(), 1);
+
+        let mut now = summary(vec![ssh("9.6"), result("10.0.0.5", 80, PortStatus::Open)]);
+        let compliance = baseline
+            .check(
+                "earlier.json",
+                &mut now,
+                &"new-open=critical".parse().unwrap(),
+                &Identities::default(),
+            )
+            .unwrap();
+        assert_eq!(compliance.drift.len(), 1);
+        assert_eq!(compliance.drift[0].severity, Severity::Critical);
+    }
+
+    #[test]
+    fn hosts_are_matched_by_name_across_addresses() {
+        let toml = "[[host]]\ntarget = \"DB01.corp.\"\nopen = [5432]\n";
+        let baseline = Baseline::parse("baseline.toml", toml).unwrap();
+        let mut identities = Identities::new(IdentityMode::Address);
+        identities.add("10.0.0.9".parse().unwrap(), "db01.corp");
+        let mut now = summary(vec![
+            result("10.0.0.9", 5432, PortStatus::Closed),
+            result("10.0.0.9", 22, PortStatus::Open),
+        ]);
+        let compliance = baseline
+            .check(
+                "baseline.toml",
+                &mut now,
+                &DriftSeverities::default(),
+                &identities,
+            )
+            .unwrap();
+        let kinds: Vec<(DriftKind, u16)> =
+            compliance.drift.iter().map(|d| (d.kind, d.port)).collect();
+        assert_eq!(kinds, [(DriftKind::NewOpen, 22), (DriftKind::Closed, 5432)]);
+
+        // Scanned by name, then by IP: the report's name still matches.
+        let mut earlier = summary(vec![ssh("9.6")]);
+        earlier.annotate_hosts(None, &[]);
+        earlier.identify(&{
+            let mut names = Identities::new(IdentityMode::Name);
+            names.add("10.0.0.5".parse().unwrap(), "db01.corp");
+            names
+        });
+        let json = serde_json::to_string(&earlier).unwrap();
+        let baseline = Baseline::parse("earlier.json", &json).unwrap();
+        assert_eq!(baseline.mismatch("earlier.json", IdentityMode::Name), None);
+        assert!(baseline
+            .mismatch("earlier.json", IdentityMode::Address)
+            .is_some());
+        let mut now = summary(vec![ssh("9.6")]);
+        let compliance = baseline
+            .check(
+                "earlier.json",
+                &mut now,
+                &DriftSeverities::default(),
+                &Identities::new(IdentityMode::Name),
+            )
+            .unwrap();
+        assert!(compliance.passed(), "{}", compliance);
+        assert_eq!(compliance.matched, 1);
+    }
+
+    #[test]
+    fn bad_baselines_say_where() {
+        let err = Baseline::parse("b.toml", "[[host]]\nopen = [22]\n").unwrap_err();
+        assert_eq!(err.to_string(), "b.toml: host 1 (line 1): needs a target");
+        let err = Baseline::parse(
+            "b.toml",
+            "[[host]]\ntarget = \"10.0.0.5\"\nservices = { 22 = \"SSH\" }\n",
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("port 22 has a service or product but isn't open"));
+        let err = Baseline::parse("b.toml", "[[host]]\ntarget = \"db 01\"\n").unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("'db 01' is not an IP address or host name"));
+        let err = Baseline::parse("b.toml", "[[hosts]]\ntarget = \"10.0.0.5\"\n").unwrap_err();
+        assert!(err.to_string().contains("unexpected 'hosts'"), "{}", err);
+        assert!("changed=urgent".parse::<DriftSeverities>().is_err());
+        assert!("moved=low"
+            .parse::<DriftSeverities>()
+            .unwrap_err()
+            .starts_with("unknown drift 'moved'"));
+    }
+}
@@ -0,0 +1,600 @@
+//! Many differently configured scans from one jobs file (`batch`).
+//!
+//! A jobs file lists scans, each a `[[job]]` with a `name` and the options
+//! of a daemon config ([`ScanConfig`]), parsed the same way:
+//!
+//! ```toml
+//! [[job]]
+//! name = "web"
+//! target = ["10.0.0.0/24"]
+//! ports = "@web"
+//! http_probe = true
+//! json = "reports/web-{date}.json"
+//!
+//! [[job]]
+//! name = "db"
+//! target = "10.0.1.0/24"
+//! ports = "1433,3306,5432"
+//! concurrency = 64
+//! ```
+//!
+//! The same jobs can be written as JSON, `{"job": [{"name": "web", ...}]}`.
+//! [`run`] runs each job as a scan of its own. The batch reads every job's JSON
+//! report for its [`Rollup`], so a job without a `json` option writes one,
+//! to `NAME.json` in the report directory.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, Document, InlineTable, Item, Table, Value};
+use tracing::{error, info, warn};
+
+use crate::daemon::{CheckOptions, ConfigError, RunError, ScanConfig};
+use crate::import::{self, InputFormat};
+
+/// One scan of a jobs file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    /// What the job is called in logs and the rollup; `job-N` for the Nth
+    /// job when the file doesn't name it.
+    pub name: String,
+    /// The job's scan options.
+    pub config: ScanConfig,
+}
+
+impl Job {
+    /// The `scan` arguments of the job, started at `at`, writing its JSON
+    /// report to [`Job::report`].
+    pub fn args(&self, at: DateTime<Utc>, report_dir: &Path) -> Vec<String> {
+        let mut args = self.config.args(at);
+        if !self.config.has("json") {
+            args.push("--json".to_string());
+            args.push(self.report(at, report_dir).display().to_string());
+        }
+        args
+    }
+
+    /// Where the job, started at `at`, writes its JSON report: where its
+    /// `json` option says, or `NAME.json` in `report_dir`.
+    pub fn report(&self, at: DateTime<Utc>, report_dir: &Path) -> PathBuf {
+        self.config
+            .json_report(at)
+            .unwrap_or_else(|| report_dir.join(format!("{}.json", self.name)))
+    }
+}
+
+/// Reads and parses a jobs file.
+pub fn load(path: &Path) -> Result<Vec<Job>, ConfigError> {
+    let name = path.display().to_string();
+    let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: name.clone(),
+        source,
+    })?;
+    parse(&name, &text)
+}
+
+/// Parses the jobs of a jobs file from its TOML or JSON text; `name`
+/// appears in errors.
+pub fn parse(name: &str, text: &str) -> Result<Vec<Job>, ConfigError> {
+    let invalid = |reason: String| ConfigError::Invalid {
+        path: name.to_string(),
+        reason,
+    };
+    let tables = if text.trim_start().starts_with('{') {
+        let json: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        let jobs = json
+            .get("job")
+            .and_then(serde_json::Value::as_array)
+            .ok_or_else(|| invalid("expected a \"job\" list of jobs".to_string()))?;
+        jobs.iter()
+            .enumerate()
+            .map(|(i, job)| {
+                job.as_object()
+                    .map(table)
+                    .ok_or_else(|| invalid(format!("job {}: expected an object", i + 1)))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let doc = Document::parse(text).map_err(|source| ConfigError::Parse {
+            path: name.to_string(),
+            source,
+        })?;
+        if let Some(key) = doc.iter().map(|(key, _)| key).find(|&key| key != "job") {
+            return Err(invalid(format!(
+                "{}: options go in a [[job]], the file has nothing else",
+                key
+            )));
+        }
+        doc.get("job")
+            .and_then(Item::as_array_of_tables)
+            .map(|jobs| jobs.iter().cloned().collect())
+            .unwrap_or_default()
+    };
+    if tables.is_empty() {
+        return Err(invalid("no [[job]] to run".to_string()));
+    }
+    let mut names = BTreeSet::new();
+    let mut jobs = Vec::new();
+    for (i, mut table) in tables.into_iter().enumerate() {
+        let job = match table.remove("name") {
+            Some(item) => item
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(format!("job {}: name: expected a string", i + 1)))?,
+            None => format!("job-{}", i + 1),
+        };
+        if job.is_empty() || job.contains(['/', '\\']) {
+            return Err(invalid(format!(
+                "job {}: name '{}' can't be a file name",
+                i + 1,
+                job
+            )));
+        }
+        if !names.insert(job.clone()) {
+            return Err(invalid(format!("two jobs are named '{}'", job)));
+        }
+        let config = ScanConfig::from_table(&format!("{}: job {}", name, job), text, &table)?;
+        jobs.push(Job { name: job, config });
+    }
+    Ok(jobs)
+}
+
+/// The options of a JSON job as the TOML table of a `[[job]]`.
+fn table(job: &serde_json::Map<String, serde_json::Value>) -> Table {
+    let mut table = Table::new();
+    for (key, value) in job {
+        if let Some(value) = value_of(value) {
+            table.insert(key, Item::Value(value));
+        }
+    }
+    table
+}
+
+/// A JSON value as TOML; none for `null`, which TOML hasn't got.
+fn value_of(value: &serde_json::Value) -> Option<Value> {
+    Some(match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(b) => Value::from(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::from(i),
+            None => Value::from(n.as_f64()?),
+        },
+        serde_json::Value::String(s) => Value::from(s.as_str()),
+        serde_json::Value::Array(items) => {
+            Value::Array(items.iter().filter_map(value_of).collect::<Array>())
+        }
+        serde_json::Value::Object(fields) => {
+            let mut inline = InlineTable::new();
+            for (key, value) in fields {
+                if let Some(value) = value_of(value) {
+                    inline.insert(key, value);
+                }
+            }
+            Value::InlineTable(inline)
+        }
+    })
+}
+
+/// How a batch runs.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Jobs run at a time, in the order of the file.
+    pub parallel_jobs: NonZeroUsize,
+    /// Whether a failed job stops the rest from starting.
+    pub fail_fast: bool,
+    /// Where jobs without a `json` option write their report.
+    pub report_dir: PathBuf,
+}
+
+/// Runs the jobs of the file at `path`, up to `parallel_jobs` at a time,
+/// each as a `scan` child process of `exe`. Every job's options are checked
+/// before the first one starts.
+pub async fn run(
+    path: &Path,
+    options: &Options,
+    exe: &Path,
+    check: &CheckOptions,
+) -> Result<Rollup, RunError> {
+    let jobs = load(path)?;
+    let now = Utc::now();
+    for job in &jobs {
+        check(job.args(now, &options.report_dir)).map_err(|reason| ConfigError::Invalid {
+            path: format!("{}: job {}", path.display(), job.name),
+            reason,
+        })?;
+    }
+    std::fs::create_dir_all(&options.report_dir)?;
+    let mut outcomes: Vec<Option<JobOutcome>> = vec![None; jobs.len()];
+    let mut running = tokio::task::JoinSet::new();
+    let mut next = 0;
+    let mut stopped = false;
+    loop {
+        while !stopped && next < jobs.len() && running.len() < options.parallel_jobs.get() {
+            let job = &jobs[next];
+            let started = Utc::now();
+            let report = job.report(started, &options.report_dir);
+            info!("Starting job {}", job.name);
+            let mut scan = tokio::process::Command::new(exe);
+            scan.arg("scan")
+                .args(job.args(started, &options.report_dir))
+                .kill_on_drop(true);
+            let i = next;
+            running.spawn(async move { (i, started, report, scan.status().await) });
+            next += 1;
+        }
+        let Some(done) = running.join_next().await else {
+            break;
+        };
+        let (i, started, report, exited) = done.map_err(std::io::Error::other)?;
+        let outcome = finish_job(&jobs[i], started, report, exited);
+        if outcome.status == JobStatus::Failed && options.fail_fast && !stopped {
+            warn!(
+                "Job {} failed, starting no more jobs (--fail-fast)",
+                outcome.name
+            );
+            stopped = true;
+        }
+        outcomes[i] = Some(outcome);
+    }
+    let outcomes = outcomes
+        .into_iter()
+        .zip(&jobs)
+        .map(|(outcome, job)| outcome.unwrap_or_else(|| JobOutcome::skipped(&job.name)))
+        .collect();
+    Ok(Rollup::of(outcomes))
+}
+
+/// Records a finished job of a batch, with the open ports of its report.
+fn finish_job(
+    job: &Job,
+    started: DateTime<Utc>,
+    report: PathBuf,
+    exited: std::io::Result<std::process::ExitStatus>,
+) -> JobOutcome {
+    let status = match exited {
+        Ok(status) => status,
+        Err(e) => {
+            error!("Cannot run job {}: {}", job.name, e);
+            return JobOutcome::failed(&job.name, e);
+        }
+    };
+    let mut outcome = JobOutcome::ran(&job.name, status.code(), started, Utc::now());
+    match import::load(&report, Some(InputFormat::Json)) {
+        Ok(summary) => {
+            outcome.open_ports = Some(summary.open_ports);
+            outcome.report = Some(report);
+        }
+        // A scan that failed early, or found what the last one did with
+        // --write-on-change, leaves no report.
+        Err(e) if report.exists() => outcome.error = Some(e.to_string()),
+        Err(_) => {}
+    }
+    info!(
+        "Job {} ended with exit code {}",
+        job.name,
+        outcome
+            .exit_code
+            .map_or("-".to_string(), |code| code.to_string())
+    );
+    outcome
+}
+
+/// How a job of a batch ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// The scan completed, with open ports or without.
+    Succeeded,
+    /// The scan failed, was interrupted or violated a policy, or didn't
+    /// start.
+    Failed,
+    /// The job didn't run, as an earlier one failed with `--fail-fast`.
+    Skipped,
+}
+
+/// A job of a batch, as the rollup reports it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobOutcome {
+    /// The job's name.
+    pub name: String,
+    /// How it ended.
+    pub status: JobStatus,
+    /// The scan's exit code; missing if it didn't run or was killed by a
+    /// signal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// When the scan started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started: Option<DateTime<Utc>>,
+    /// When the scan ended.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<DateTime<Utc>>,
+    /// The job's JSON report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<PathBuf>,
+    /// Open ports in the report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_ports: Option<usize>,
+    /// Why the scan couldn't start, or its report couldn't be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobOutcome {
+    /// A job that ran from `started` to `finished` and exited with
+    /// `exit_code`: succeeded on 0 and 1, the codes of a completed scan
+    /// with and without open ports.
+    pub fn ran(
+        name: &str,
+        exit_code: Option<i32>,
+        started: DateTime<Utc>,
+        finished: DateTime<Utc>,
+    ) -> Self {
+        JobOutcome {
+            name: name.to_string(),
+            status: match exit_code {
+                Some(0 | 1) => JobStatus::Succeeded,
+                _ => JobStatus::Failed,
+            },
+            exit_code,
+            started: Some(started),
+            finished: Some(finished),
+            report: None,
+            open_ports: None,
+            error: None,
+        }
+    }
+
+    /// A job whose scan couldn't start, for `error`.
+    pub fn failed(name: &str, error: impl fmt::Display) -> Self {
+        JobOutcome {
+            status: JobStatus::Failed,
+            error: Some(error.to_string()),
+            ..JobOutcome::skipped(name)
+        }
+    }
+
+    /// A job left out, as an earlier one failed with `--fail-fast`.
+    pub fn skipped(name: &str) -> Self {
+        JobOutcome {
+            name: name.to_string(),
+            status: JobStatus::Skipped,
+            exit_code: None,
+            started: None,
+            finished: None,
+            report: None,
+            open_ports: None,
+            error: None,
+        }
+    }
+}
+
+/// What came of every job of a batch, printed at its end and written by
+/// `--json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rollup {
+    /// Jobs in the file.
+    pub jobs: usize,
+    /// Jobs whose scan completed.
+    pub succeeded: usize,
+    /// Jobs that failed.
+    pub failed: usize,
+    /// Jobs that didn't run.
+    pub skipped: usize,
+    /// Open ports over the reports of every job.
+    pub open_ports: usize,
+    /// Each job, in the order of the file.
+    pub results: Vec<JobOutcome>,
+}
+
+impl Rollup {
+    /// The rollup of `results`.
+    pub fn of(results: Vec<JobOutcome>) -> Self {
+        let count = |status: JobStatus| results.iter().filter(|r| r.status == status).count();
+        Rollup {
+            jobs: results.len(),
+            succeeded: count(JobStatus::Succeeded),
+            failed: count(JobStatus::Failed),
+            skipped: count(JobStatus::Skipped),
+            open_ports: results.iter().filter_map(|r| r.open_ports).sum(),
+            results,
+        }
+    }
+
+    /// Whether every job succeeded.
+    pub fn passed(&self) -> bool {
+        self.succeeded == self.jobs
+    }
+
+    /// Writes the rollup to `path` as JSON.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        std::fs::write(path, json)
+    }
+}
+
+/// A line per job, then the totals.
+impl fmt::Display for Rollup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+        for r in &self.results {
+            let status = match r.status {
+                JobStatus::Succeeded => "succeeded",
+                JobStatus::Failed => "failed",
+                JobStatus::Skipped => "skipped",
+            };
+            let mut line = format!("{:<width$}  {:<9}", r.name, status, width = width);
+            if let Some(code) = r.exit_code {
+                line += &format!("  exit {}", code);
+            }
+            if let Some(open) = r.open_ports {
+                line += &format!("  {} open", open);
+            }
+            if let Some(report) = &r.report {
+                line += &format!("  {}", report.display());
+            }
+            if let Some(error) = &r.error {
+                line += &format!("  {}", error);
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        write!(
+            f,
+            "{} jobs: {} succeeded, {} failed, {} skipped; {} open ports",
+            self.jobs, self.succeeded, self.failed, self.skipped, self.open_ports
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at() -> DateTime<Utc> {
+        "2026-10-15T02:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn toml_and_json_jobs_become_scan_arguments() {
+        let toml = r#"
+[[job]]
+name = "web"
+target = ["10.0.0.0/24", "10.0.1.5"]
+ports = "80,443"
+http_probe = true
+json = "reports/web-{date}.json"
+
+[[job]]
+target = "10.0.2.1"
+concurrency = 64
+
+[job.port_groups]
+admin = "8080-8090"
+"#;
+        let jobs = parse("jobs.toml", toml).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].name, "web");
+        let out = Path::new("out");
+        assert_eq!(
+            jobs[0].args(at(), out),
+            [
+                "--target",
+                "10.0.0.0/24",
+                "--target",
+                "10.0.1.5",
+                "--ports",
+                "80,443",
+                "--http-probe",
+                "--json",
+                "reports/web-2026-10-15.json",
+            ]
+        );
+        assert_eq!(
+            jobs[0].report(at(), out),
+            Path::new("reports/web-2026-10-15.json")
+        );
+        // Unnamed, and without a report of its own.
+        assert_eq!(jobs[1].name, "job-2");
+        let report = out.join("job-2.json");
+        assert_eq!(
+            jobs[1].args(at(), out),
+            [
+                "--target",
+                "10.0.2.1",
+                "--concurrency",
+                "64",
+                "--port-group",
+                "admin=8080-8090",
+                "--json",
+                &report.display().to_string(),
+            ]
+        );
+        assert_eq!(jobs[1].report(at(), out), report);
+
+        // The same jobs as JSON, whose objects come in key order.
+        let json = r#"{"job": [
+            {"name": "web", "target": ["10.0.0.0/24", "10.0.1.5"], "ports": "80,443",
+             "http_probe": true, "json": "reports/web-{date}.json"},
+            {"target": "10.0.2.1", "concurrency": 64, "port_groups": {"admin": "8080-8090"}}
+        ]}"#;
+        let from_json = parse("jobs.json", json).unwrap();
+        assert_eq!(from_json.len(), 2);
+        for (job, same) in jobs.iter().zip(&from_json) {
+            assert_eq!(job.name, same.name);
+            let mut args = job.args(at(), out);
+            let mut same = same.args(at(), out);
+            args.sort();
+            same.sort();
+            assert_eq!(args, same);
+        }
+    }
+
+    #[test]
+    fn bad_jobs_files_are_refused() {
+        let error = |text: &str| parse("jobs.toml", text).unwrap_err().to_string();
+        assert_eq!(error(""), "jobs.toml: no [[job]] to run");
+        assert_eq!(
+            error("ports = \"22\"\n[[job]]\ntarget = \"10.0.0.1\"\n"),
+            "jobs.toml: ports: options go in a [[job]], the file has nothing else"
+        );
+        assert_eq!(
+            error("[[job]]\nname = \"a\"\n[[job]]\nname = \"a\"\n"),
+            "jobs.toml: two jobs are named 'a'"
+        );
+        assert_eq!(
+            error("[[job]]\nname = \"../a\"\n"),
+            "jobs.toml: job 1: name '../a' can't be a file name"
+        );
+        assert_eq!(
+            error("[[job]]\nname = \"a\"\n\n[[job]]\nname = \"b\"\nports = {}\n"),
+            "jobs.toml: job b: line 6: ports: expected a string, number, boolean or list of them"
+        );
+        assert_eq!(
+            error(r#"{"jobs": []}"#),
+            "jobs.toml: expected a \"job\" list of jobs"
+        );
+    }
+
+    #[test]
+    fn the_rollup_counts_jobs_and_open_ports() {
+        let mut web = JobOutcome::ran("web", Some(0), at(), at());
+        web.open_ports = Some(3);
+        web.report = Some(PathBuf::from("web.json"));
+        let mut quiet = JobOutcome::ran("quiet", Some(1), at(), at());
+        quiet.open_ports = Some(0);
+        let policy = JobOutcome::ran("db", Some(4), at(), at());
+        let missing = JobOutcome::failed("gone", "No such file or directory");
+        let rollup = Rollup::of(vec![
+            web,
+            quiet,
+            policy,
+            missing,
+            JobOutcome::skipped("late"),
+        ]);
+        assert_eq!(
+            (
+                rollup.jobs,
+                rollup.succeeded,
+                rollup.failed,
+                rollup.skipped,
+                rollup.open_ports
+            ),
+            (5, 2, 2, 1, 3)
+        );
+        assert!(!rollup.passed());
+        assert_eq!(
+            rollup.to_string(),
+            "web    succeeded  exit 0  3 open  web.json\n\
+             quiet  succeeded  exit 1  0 open\n\
+             db     failed     exit 4\n\
+             gone   failed     No such file or directory\n\
+             late   skipped\n\
+             5 jobs: 2 succeeded, 2 failed, 1 skipped; 3 open ports"
+        );
+    }
+}
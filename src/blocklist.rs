@@ -0,0 +1,124 @@
+//! Addresses that must never be probed (`--blocklist`).
+//!
+//! The file lists one address, CIDR block or range per line; blank lines
+//! and `#` comments are skipped:
+//!
+//! ```text
+//! # payment network, never scanned
+//! 10.20.0.0/16
+//! 192.0.2.7
+//! ```
+//!
+//! Blocked hosts are taken out of the targets like `--exclude`d ones, but
+//! a target naming a blocked host on its own is an error rather than
+//! something to skip quietly.
+
+use std::path::Path;
+use thiserror::Error;
+
+use crate::plan::TargetSet;
+use crate::spec::SpecError;
+
+/// Why a blocklist could not be loaded.
+#[derive(Error, Debug)]
+pub enum BlocklistError {
+    /// The file could not be read.
+    #[error("Cannot read blocklist {path}: {source}")]
+    Read {
+        /// The blocklist path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// A line is not an address, CIDR block or range.
+    #[error("{path}: line {line}: {source}")]
+    Entry {
+        /// The blocklist path.
+        path: String,
+        /// Line it is on, from 1.
+        line: usize,
+        /// What is wrong with it.
+        source: SpecError,
+    },
+}
+
+/// The hosts of a blocklist file.
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    hosts: TargetSet,
+}
+
+impl Blocklist {
+    /// Reads and parses a blocklist file.
+    pub fn load(path: &Path) -> Result<Self, BlocklistError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| BlocklistError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses a blocklist from its text; `name` appears in errors.
+    pub fn parse(name: &str, text: &str) -> Result<Self, BlocklistError> {
+        let mut hosts = TargetSet::new();
+        for (i, line) in text.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            hosts
+                .insert(entry)
+                .map_err(|source| BlocklistError::Entry {
+                    path: name.to_string(),
+                    line: i + 1,
+                    source,
+                })?;
+        }
+        Ok(Blocklist { hosts })
+    }
+
+    /// Every blocked host.
+    pub fn hosts(&self) -> &TargetSet {
+        &self.hosts
+    }
+
+    /// The hosts of `targets` that are blocked.
+    pub fn blocked(&self, targets: &TargetSet) -> TargetSet {
+        let mut allowed = targets.clone();
+        allowed.remove_all(&self.hosts);
+        let mut blocked = targets.clone();
+        blocked.remove_all(&allowed);
+        blocked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_addresses_blocks_and_ranges() {
+        let blocklist = Blocklist::parse(
+            "blocked.txt",
+            "# regulated\n10.20.0.0/16\n\n192.0.2.7  # one host\n2001:db8::1-2001:db8::3\n",
+        )
+        .unwrap();
+        let mut targets = TargetSet::new();
+        targets.insert("10.19.255.254-10.20.0.1").unwrap();
+        targets.insert("192.0.2.0/24").unwrap();
+        targets.insert("2001:db8::2").unwrap();
+        assert_eq!(
+            blocklist.blocked(&targets).to_string(),
+            "10.20.0.0/31,192.0.2.7,2001:db8::2"
+        );
+
+        let e = Blocklist::parse("blocked.txt", "10.0.0.1\n10.0.0.300\n").unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("blocked.txt: line 2: Invalid address"),
+            "{}",
+            e
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! A memory budget for what results keep of what services sent
+//! (`--max-capture-mem`).
+//!
+//! Banners, HTTP response heads, certificate details and raw responses are
+//! each capped per port, but a wide scan holds thousands of them. A
+//! [`CaptureBudget`] counts the bytes every result keeps; once a result
+//! would take the total past the limit, its captures are cut to previews of
+//! [`PREVIEW`] bytes and it is flagged
+//! [`capture_truncated`](PortResult::capture_truncated). The previews are
+//! counted too but never refused, so the total stays within the limit and
+//! one preview per port.
+//!
+//! Results that leave memory give their bytes back
+//! ([`CaptureBudget::release`]), as those a spilled scan writes to disk do.
+
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::banner::Banner;
+use crate::scanner::PortResult;
+
+/// How much of each capture a result over the budget keeps.
+pub const PREVIEW: usize = 64;
+
+/// The bytes results may keep in all, shared by everything that keeps or
+/// lets go of them.
+#[derive(Debug, Clone)]
+pub struct CaptureBudget {
+    limit: u64,
+    used: Arc<AtomicU64>,
+}
+
+impl CaptureBudget {
+    /// A budget of `limit` bytes.
+    pub fn new(limit: u64) -> Self {
+        CaptureBudget {
+            limit,
+            used: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The limit, in bytes.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The bytes kept by results now.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Counts what `r` keeps, cutting its captures to previews first when
+    /// they don't fit.
+    pub fn retain(&self, r: &mut PortResult) {
+        let size = retained(r);
+        let fits = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(size).filter(|total| *total <= self.limit)
+            })
+            .is_ok();
+        if !fits {
+            truncate(r);
+            self.used.fetch_add(retained(r), Ordering::Relaxed);
+        }
+    }
+
+    /// Gives back what `r` keeps, once it is no longer held in memory.
+    pub fn release(&self, r: &PortResult) {
+        let size = retained(r);
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+}
+
+/// The bytes of what services sent that `r` keeps: its banner, extra
+/// information and raw response.
+pub fn retained(r: &PortResult) -> u64 {
+    let banner = r.banner.as_ref().map_or(0, Banner::len);
+    let extra = r.extra_info.as_ref().map_or(0, String::len);
+    let response = r.response.as_ref().map_or(0, Bytes::len);
+    (banner + extra + response) as u64
+}
+
+/// Cuts the captures of `r` to their previews, copying them so that the
+/// buffers they were cut from are freed.
+fn truncate(r: &mut PortResult) {
+    let mut cut = false;
+    if let Some(banner) = r.banner.as_mut().filter(|b| b.len() > PREVIEW) {
+        *banner = Banner::from(Bytes::copy_from_slice(&banner.as_bytes()[..PREVIEW]));
+        cut = true;
+    }
+    if let Some(extra) = r.extra_info.as_mut().filter(|e| e.len() > PREVIEW) {
+        let mut end = PREVIEW;
+        while !extra.is_char_boundary(end) {
+            end -= 1;
+        }
+        extra.truncate(end);
+        cut = true;
+    }
+    if let Some(response) = r.response.as_mut().filter(|b| b.len() > PREVIEW) {
+        *response = Bytes::copy_from_slice(&response[..PREVIEW]);
+        cut = true;
+    }
+    r.capture_truncated |= cut;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn result(banner: usize) -> PortResult {
+        let mut r = PortResult::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 80, PortStatus::Open);
+        r.banner = Some(Banner::from("x".repeat(banner)));
+        r.extra_info = Some(format!("a{}", "é".repeat(40)));
+        r
+    }
+
+    #[test]
+    fn results_over_the_budget_keep_previews() {
+        let budget = CaptureBudget::new(500);
+        let mut first = result(300);
+        budget.retain(&mut first);
+        assert!(!first.capture_truncated);
+        assert_eq!(budget.used(), 381);
+
+        let mut second = result(300);
+        budget.retain(&mut second);
+        assert!(second.capture_truncated);
+        assert_eq!(second.banner.as_ref().unwrap().len(), PREVIEW);
+        // Cut at a character boundary.
+        assert_eq!(second.extra_info.as_ref().unwrap().len(), 63);
+        assert_eq!(budget.used(), 381 + 127);
+
+        // A small result that fits is kept whole, even past a cut one.
+        budget.release(&first);
+        let mut third = result(10);
+        budget.retain(&mut third);
+        assert!(!third.capture_truncated);
+        assert_eq!(budget.used(), 127 + 91);
+    }
+}
@@ -0,0 +1,189 @@
+//! Telling whether a scan found the same thing as the previous one
+//! (`--write-on-change`), so a scheduled scan only writes reports and sends
+//! notifications when something changed.
+//!
+//! The comparison is by [`fingerprint`], which is kept stable across
+//! versions: a state file written by one version must not make the next
+//! one see every scan as changed. Its rules, version 1:
+//!
+//! - each port result becomes the line `<target> <port> <status> <service>`,
+//!   where the target is the address in its usual text form (`10.0.0.5`,
+//!   `2001:db8::1`), the port is the number followed by `/udp` for a UDP
+//!   port (TCP ports are the bare number), the status is `open`, `closed` or `filtered` and the
+//!   service is the detected name, or `-` when there is none;
+//! - the lines are sorted bytewise, duplicates dropped, and each ends in
+//!   `\n`;
+//! - the text is hashed with 64-bit FNV-1a, and the fingerprint is `v1:`
+//!   followed by the hash as 16 lowercase hex digits.
+//!
+//! Timings, banners, products, errors, evidence and everything else a
+//! result holds are left out, so a port that only answered faster or slower
+//! is no change. New rules get a new version prefix, which never matches an
+//! old fingerprint.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::io;
+use std::path::Path;
+
+use crate::hash::fnv1a64;
+use crate::scanner::{PortResult, PortStatus};
+
+/// The fingerprint of a result set, as described in the module docs.
+pub fn fingerprint<R: Borrow<PortResult>>(results: impl IntoIterator<Item = R>) -> String {
+    let mut lines: Vec<String> = results
+        .into_iter()
+        .map(|r| {
+            let r = r.borrow();
+            let status = match r.status {
+                PortStatus::Open => "open",
+                PortStatus::Closed => "closed",
+                PortStatus::Filtered => "filtered",
+            };
+            format!(
+                "{} {} {} {}\n",
+                r.target,
+                r.port_label(),
+                status,
+                r.service.as_deref().unwrap_or("-")
+            )
+        })
+        .collect();
+    lines.sort();
+    lines.dedup();
+    format!("v1:{:016x}", fnv1a64(lines.concat().as_bytes()))
+}
+
+/// What the `--write-on-change` state file remembers of the last scan that
+/// finished.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeState {
+    /// The fingerprint of its results.
+    pub fingerprint: String,
+    /// The scan that last found something different, whose reports are
+    /// the current ones.
+    pub changed_scan_id: String,
+    /// When that scan finished.
+    pub changed_at: DateTime<Utc>,
+    /// When a scan last finished, changed or not: the time the current
+    /// reports were last known to be right.
+    pub verified_at: DateTime<Utc>,
+}
+
+impl ChangeState {
+    /// Reads the state file; `None` when there is none yet.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether a scan with `fingerprint` found the same as the last one.
+    pub fn unchanged(state: Option<&Self>, fingerprint: &str) -> bool {
+        state.is_some_and(|s| s.fingerprint == fingerprint)
+    }
+
+    /// The state after a scan `scan_id` with `fingerprint` finished at `now`:
+    /// only the verification time moves when nothing changed.
+    pub fn next(state: Option<Self>, fingerprint: &str, scan_id: &str, now: DateTime<Utc>) -> Self {
+        match state.filter(|s| s.fingerprint == fingerprint) {
+            Some(state) => ChangeState {
+                verified_at: now,
+                ..state
+            },
+            None => ChangeState {
+                fingerprint: fingerprint.to_string(),
+                changed_scan_id: scan_id.to_string(),
+                changed_at: now,
+                verified_at: now,
+            },
+        }
+    }
+
+    /// Replaces the state file, through a temporary file beside it so a
+    /// crash never leaves half of one.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)? + "\n")?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn result(target: &str, port: u16, status: PortStatus, service: Option<&str>) -> PortResult {
+        let mut r = PortResult::new(target.parse::<IpAddr>().unwrap(), port, status);
+        r.service = service.map(str::to_string);
+        r
+    }
+
+    fn baseline() -> Vec<PortResult> {
+        vec![
+            result("10.0.0.5", 22, PortStatus::Open, Some("ssh")),
+            result("10.0.0.5", 23, PortStatus::Closed, None),
+            result("2001:db8::1", 443, PortStatus::Filtered, None),
+        ]
+    }
+
+    #[test]
+    fn the_fingerprint_is_stable_and_ignores_order_and_timings() {
+        // Pinned: a different value here breaks every state file in use.
+        assert_eq!(fingerprint(baseline()), "v1:9a6dd788338e2be4");
+
+        let mut slower = baseline();
+        slower.reverse();
+        for r in &mut slower {
+            r.duration_ms = 750;
+            r.timeout_ms = Some(800);
+            r.banner = Some("SSH-2.0-OpenSSH_9.6".into());
+            r.evidence.push(crate::evidence::Evidence::new(
+                crate::evidence::Step::TcpConnect,
+                crate::evidence::Outcome::Success,
+            ));
+        }
+        assert_eq!(fingerprint(&slower), fingerprint(baseline()));
+    }
+
+    #[test]
+    fn a_new_open_port_or_service_is_a_change() {
+        let mut opened = baseline();
+        opened[1].status = PortStatus::Open;
+        assert_ne!(fingerprint(&opened), fingerprint(baseline()));
+
+        let mut renamed = baseline();
+        renamed[0].service = Some("http".to_string());
+        assert_ne!(fingerprint(&renamed), fingerprint(baseline()));
+
+        let mut more = baseline();
+        more.push(result("10.0.0.6", 80, PortStatus::Open, Some("http")));
+        assert_ne!(fingerprint(&more), fingerprint(baseline()));
+    }
+
+    #[test]
+    fn an_unchanged_scan_only_moves_the_verification_time() {
+        let first: DateTime<Utc> = "2026-01-01T02:00:00Z".parse().unwrap();
+        let second: DateTime<Utc> = "2026-01-02T02:00:00Z".parse().unwrap();
+        let print = fingerprint(baseline());
+
+        assert!(!ChangeState::unchanged(None, &print));
+        let state = ChangeState::next(None, &print, "a1", first);
+        assert!(ChangeState::unchanged(Some(&state), &print));
+        let state = ChangeState::next(Some(state), &print, "b2", second);
+        assert_eq!(state.changed_scan_id, "a1");
+        assert_eq!(state.changed_at, first);
+        assert_eq!(state.verified_at, second);
+
+        let state = ChangeState::next(Some(state), "v1:0000000000000000", "c3", second);
+        assert_eq!(state.changed_scan_id, "c3");
+        assert_eq!(state.changed_at, second);
+    }
+}
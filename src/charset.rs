@@ -0,0 +1,276 @@
+//! Banners that aren't UTF-8: a small detector for the legacy charsets
+//! devices still greet with, such as Latin-1 telnet and FTP banners and
+//! Shift_JIS from Japanese printers, and an escaped form for bytes no
+//! charset explains.
+//!
+//! Detection is a heuristic. Shift_JIS is only taken when every byte
+//! decodes and the double-byte characters look like Japanese text: mostly
+//! kana, symbols or common kanji, with some kana or two of them in a row.
+//! windows-1252 (a superset of Latin-1) is only taken when nearly every
+//! byte above ASCII is a letter next to another letter or common
+//! punctuation, and there are no control characters but whitespace and
+//! escape. Anything else is escaped, as `\xNN` for bytes above ASCII.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// JIS X 0208 as Windows (code page 932) extends it: one line of 94
+/// characters per row, U+FFFD for unassigned cells.
+const JIS0208: &str = include_str!("jis0208.txt");
+
+/// How a banner that isn't UTF-8 was turned into text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BannerEncoding {
+    /// Latin-1 and its Windows superset.
+    #[serde(rename = "windows-1252")]
+    Windows1252,
+    /// Japanese, as Windows extends it (code page 932).
+    #[serde(rename = "Shift_JIS")]
+    ShiftJis,
+    /// No charset fit: bytes above ASCII and control characters are
+    /// escaped.
+    #[serde(rename = "escaped")]
+    Escaped,
+}
+
+impl BannerEncoding {
+    /// The label, as in the JSON report: the WHATWG name of the charset,
+    /// or `escaped`.
+    pub fn label(self) -> &'static str {
+        match self {
+            BannerEncoding::Windows1252 => "windows-1252",
+            BannerEncoding::ShiftJis => "Shift_JIS",
+            BannerEncoding::Escaped => "escaped",
+        }
+    }
+}
+
+impl fmt::Display for BannerEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// `bytes` as text: borrowed when they are UTF-8, otherwise decoded in the
+/// charset detected, or escaped when none fits, with how.
+pub fn decode(bytes: &[u8]) -> (Cow<'_, str>, Option<BannerEncoding>) {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (Cow::Borrowed(text), None);
+    }
+    if let Some(text) = shift_jis(bytes) {
+        return (Cow::Owned(text), Some(BannerEncoding::ShiftJis));
+    }
+    if let Some(text) = windows_1252(bytes) {
+        return (Cow::Owned(text), Some(BannerEncoding::Windows1252));
+    }
+    (
+        Cow::Owned(bytes.escape_ascii().to_string()),
+        Some(BannerEncoding::Escaped),
+    )
+}
+
+/// Characters of JIS X 0208 by `(row - 1) * 94 + cell - 1`.
+fn jis0208() -> &'static [char] {
+    static TABLE: OnceLock<Vec<char>> = OnceLock::new();
+    TABLE.get_or_init(|| JIS0208.lines().flat_map(str::chars).collect())
+}
+
+/// The JIS X 0208 row and cell of a Shift_JIS byte pair.
+fn row_cell(lead: u8, trail: u8) -> Option<(usize, usize)> {
+    let row = match lead {
+        0x81..=0x9f => (lead - 0x81) as usize * 2 + 1,
+        0xe0..=0xef => (lead - 0xc1) as usize * 2 + 1,
+        _ => return None,
+    };
+    match trail {
+        0x40..=0x7e => Some((row, (trail - 0x3f) as usize)),
+        0x80..=0x9e => Some((row, (trail - 0x40) as usize)),
+        0x9f..=0xfc => Some((row + 1, (trail - 0x9e) as usize)),
+        _ => None,
+    }
+}
+
+/// `bytes` decoded as Shift_JIS, if they are that and read like Japanese.
+fn shift_jis(bytes: &[u8]) -> Option<String> {
+    let table = jis0208();
+    let mut text = String::with_capacity(bytes.len() * 2);
+    let (mut wide, mut common, mut kana, mut longest_run, mut run) = (0, 0, 0, 0, 0);
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match b {
+            0x00..=0x7f => {
+                text.push(b as char);
+                run = 0;
+                i += 1;
+            }
+            0xa1..=0xdf => {
+                text.push(char::from_u32(0xff61 + (b - 0xa1) as u32)?);
+                kana += 1;
+                run = 0;
+                i += 1;
+            }
+            _ => {
+                let (row, cell) = row_cell(b, *bytes.get(i + 1)?)?;
+                let c = table[(row - 1) * 94 + cell - 1];
+                if c == char::REPLACEMENT_CHARACTER {
+                    return None;
+                }
+                text.push(c);
+                wide += 1;
+                // Symbols, kana and the first level of kanji, the
+                // commonly used ones.
+                if matches!(row, 1..=8 | 16..=47) {
+                    common += 1;
+                }
+                if matches!(row, 4 | 5) {
+                    kana += 1;
+                }
+                run += 1;
+                longest_run = usize::max(longest_run, run);
+                i += 2;
+            }
+        }
+    }
+    (wide > 0 && common * 2 >= wide && (kana > 0 || longest_run >= 2)).then_some(text)
+}
+
+/// What windows-1252 has at 0x80..=0x9f; `None` for the five bytes it
+/// leaves undefined.
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('€'),
+    None,
+    Some('‚'),
+    Some('ƒ'),
+    Some('„'),
+    Some('…'),
+    Some('†'),
+    Some('‡'),
+    Some('ˆ'),
+    Some('‰'),
+    Some('Š'),
+    Some('‹'),
+    Some('Œ'),
+    None,
+    Some('Ž'),
+    None,
+    None,
+    Some('‘'),
+    Some('’'),
+    Some('“'),
+    Some('”'),
+    Some('•'),
+    Some('–'),
+    Some('—'),
+    Some('˜'),
+    Some('™'),
+    Some('š'),
+    Some('›'),
+    Some('œ'),
+    None,
+    Some('ž'),
+    Some('Ÿ'),
+];
+
+/// `bytes` decoded as windows-1252, if they read like Latin text.
+fn windows_1252(bytes: &[u8]) -> Option<String> {
+    let mut text = String::with_capacity(bytes.len() * 2);
+    let (mut high, mut plausible) = (0, 0);
+    for (i, &b) in bytes.iter().enumerate() {
+        let c = match b {
+            b'\t' | b'\n' | b'\x0c' | b'\r' | b'\x1b' | 0x20..=0x7e => b as char,
+            0x80..=0x9f => WINDOWS_1252_HIGH[(b - 0x80) as usize]?,
+            0xa0..=0xff => b as char,
+            _ => return None,
+        };
+        text.push(c);
+        if b < 0x80 {
+            continue;
+        }
+        high += 1;
+        let is_letter = |b: Option<&u8>| {
+            b.is_some_and(|&b| b.is_ascii_alphabetic() || (b >= 0xc0 && b != 0xd7 && b != 0xf7))
+        };
+        plausible += if c.is_alphabetic() {
+            let before = i.checked_sub(1).and_then(|i| bytes.get(i));
+            usize::from(is_letter(before) || is_letter(bytes.get(i + 1)))
+        } else {
+            1
+        };
+    }
+    (plausible * 10 >= high * 9).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_borrowed_as_is() {
+        let (text, encoding) = decode("220 Grüß Gott FTP".as_bytes());
+        assert!(matches!(text, Cow::Borrowed("220 Grüß Gott FTP")));
+        assert_eq!(encoding, None);
+    }
+
+    #[test]
+    fn latin1_telnet_banners_are_decoded() {
+        // "Bienvenue sur le routeur de la société\r\nMot de passe :" in
+        // Latin-1.
+        let fixture = b"Bienvenue sur le routeur de la soci\xe9t\xe9\r\nMot de passe :";
+        let (text, encoding) = decode(fixture);
+        assert_eq!(
+            text,
+            "Bienvenue sur le routeur de la société\r\nMot de passe :"
+        );
+        assert_eq!(encoding, Some(BannerEncoding::Windows1252));
+
+        let (text, encoding) = decode(b"220 T\xe9l\xe9com \x93FTP\x94 \xa9 2024");
+        assert_eq!(text, "220 Télécom “FTP” © 2024");
+        assert_eq!(encoding, Some(BannerEncoding::Windows1252));
+    }
+
+    #[test]
+    fn shift_jis_printer_banners_are_decoded() {
+        // "HTTP/1.0 200 OK\r\nServer: プリンタ Web サーバー 印刷" in Shift_JIS.
+        let fixture: &[u8] = b"HTTP/1.0 200 OK\r\nServer: \x83v\x83\x8a\x83\x93\x83^ Web \
+            \x83T\x81[\x83o\x81[ \x88\xf3\x8d\xfc";
+        let (text, encoding) = decode(fixture);
+        assert_eq!(
+            text,
+            "HTTP/1.0 200 OK\r\nServer: プリンタ Web サーバー 印刷"
+        );
+        assert_eq!(encoding, Some(BannerEncoding::ShiftJis));
+        assert_eq!(encoding.unwrap().label(), "Shift_JIS");
+
+        // Half-width katakana are single bytes.
+        let (text, _) = decode(b"\x83v\x83\x8a\x83\x93\x83^ \xb2\xdd\xbb\xc2");
+        assert_eq!(text, "プリンタ ｲﾝｻﾂ");
+    }
+
+    #[test]
+    fn binary_and_unconfident_bytes_are_escaped() {
+        // Telnet option negotiation.
+        let (text, encoding) = decode(b"\xff\xfb\x01\xff\xfb\x03\xff\xfd\x18");
+        assert_eq!(text, "\\xff\\xfb\\x01\\xff\\xfb\\x03\\xff\\xfd\\x18");
+        assert_eq!(encoding, Some(BannerEncoding::Escaped));
+
+        // Lone high bytes between digits are neither Latin text nor
+        // Japanese.
+        let (text, encoding) = decode(b"ID 12\xe934\xfe56\xd7");
+        assert_eq!(encoding, Some(BannerEncoding::Escaped));
+        assert_eq!(text, "ID 12\\xe934\\xfe56\\xd7");
+    }
+
+    #[test]
+    fn the_table_covers_every_row() {
+        let table = jis0208();
+        assert_eq!(table.len(), 94 * 94);
+        let (row, cell) = row_cell(0x88, 0xf3).unwrap();
+        assert_eq!(table[(row - 1) * 94 + cell - 1], '印');
+        let (row, cell) = row_cell(0xea, 0xa4).unwrap();
+        assert_eq!((row, cell), (84, 6));
+        assert_eq!(table[(row - 1) * 94 + cell - 1], '熙');
+    }
+}
@@ -0,0 +1,1363 @@
+//! Command-line definition: a bare invocation scans, subcommands cover
+//! everything else.
+
+use crate::progress::ProgressSink;
+use chrono::{DateTime, Utc};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use port_scanner::audit;
+use port_scanner::baseline::DriftSeverities;
+use port_scanner::console::ConsoleMode;
+use port_scanner::cron::{Schedule, Zone};
+use port_scanner::daemon::Overlap;
+use port_scanner::duration::{parse_millis, parse_secs};
+use port_scanner::email;
+use port_scanner::fields::Field;
+use port_scanner::filter::Filter;
+use port_scanner::groups::PortGroup;
+use port_scanner::heatmap::{self, Bins};
+use port_scanner::history;
+use port_scanner::identity::IdentityMode;
+use port_scanner::jump::{self, JumpHost};
+use port_scanner::live::{self, StreamUrl};
+use port_scanner::metadata::Tag;
+use port_scanner::output::OutputOptions;
+use port_scanner::policy::{AllowOpen, FailOn};
+use port_scanner::preset::{self, AppliedPreset, Presets};
+use port_scanner::probe::{self, ProbeTimeouts};
+use port_scanner::proxy::{self, ProbeUrl};
+use port_scanner::publish::{self, PublishKey, PublishUrl};
+use port_scanner::pushgateway::GroupUrl;
+use port_scanner::responses;
+use port_scanner::s3::{Endpoint, S3Url};
+use port_scanner::shared;
+use port_scanner::sniff::{self, Rung};
+use port_scanner::targets::TargetOptions;
+use port_scanner::template::FilenameTemplate;
+use port_scanner::timeouts::PortTimeouts;
+use port_scanner::tlsreport;
+use port_scanner::vuln::Severity;
+use port_scanner::window::Window;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const EXIT_CODES: &str = "Exit codes:\n  0  scan completed, open ports found\n  1  scan completed, no open ports\n  2  usage or target error\n  3  scan interrupted or --max-scan-time reached, results are partial\n  4  a --fail-on or --allow-open policy was violated, or --fail-on-drift found drift\n  5  an --exec command failed and --exec-fail-fatal is set\n  6  some targets could not be resolved or were invalid, the rest were scanned\n  7  a probe panicked; its ports are reported with the panic as their error\n  8  the scan stopped on an error, such as results it could not spill; results are partial";
+
+/// The default --timeout: long enough on Windows for a refused connect to
+/// be reported after the system's SYN retries.
+#[cfg(windows)]
+const DEFAULT_TIMEOUT: &str = "1500ms";
+#[cfg(not(windows))]
+const DEFAULT_TIMEOUT: &str = "800ms";
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "port-scanner",
+    version = "0.1.0",
+    about = "Fast async TCP port scanner",
+    after_help = EXIT_CODES,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+impl Cli {
+    /// Parses the command line like [`Parser::parse`], then fills in the
+    /// options of `--preset` that weren't given and parses again.
+    pub fn parse_with_preset() -> Self {
+        let argv: Vec<std::ffi::OsString> = std::env::args_os().collect();
+        let matches = Cli::command().get_matches_from(&argv);
+        let scan = match matches.subcommand() {
+            Some(("scan", scan)) => scan,
+            Some(_) => return Cli::from_matches(&matches),
+            None => &matches,
+        };
+        let Some(name) = scan.get_one::<String>("preset") else {
+            return Cli::from_matches(&matches);
+        };
+        let applied = resolve_preset(name, scan)
+            .unwrap_or_else(|e| Cli::command().error(ErrorKind::InvalidValue, e).exit());
+        let mut cli = Cli::parse_from(argv.into_iter().chain(applied.args.iter().map(Into::into)));
+        match &mut cli.command {
+            Some(Command::Scan(args)) => args.applied_preset = Some(applied),
+            _ => cli.scan.applied_preset = Some(applied),
+        }
+        cli
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        Cli::from_arg_matches(matches).unwrap_or_else(|e| e.exit())
+    }
+}
+
+/// The options preset `name` adds to the scan options `given`.
+fn resolve_preset(name: &str, given: &ArgMatches) -> Result<AppliedPreset, String> {
+    let presets = Presets::load(preset::user_file().as_deref()).map_err(|e| e.to_string())?;
+    let preset = presets.get(name).map_err(|e| e.to_string())?;
+    let command = Cli::command();
+    let id = |option: &str| {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(option))
+            .map(|arg| arg.get_id().clone())
+    };
+    if let Some(unknown) = preset.options().find(|option| id(option).is_none()) {
+        return Err(format!(
+            "preset '{}' ({}) sets --{}, which is not a scan option",
+            preset.name, preset.source, unknown
+        ));
+    }
+    Ok(preset.apply(|option| {
+        id(option).is_some_and(|id| {
+            matches!(
+                given.value_source(id.as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        })
+    }))
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Scan targets (the default when no subcommand is given)
+    #[command(after_help = EXIT_CODES)]
+    Scan(Box<ScanArgs>),
+
+    /// Run scans submitted over an HTTP API
+    Serve(ServeArgs),
+
+    /// Run the scan of a config file on a cron schedule; SIGHUP re-reads
+    /// the config, SIGTERM or Ctrl-C stops
+    Daemon(DaemonArgs),
+
+    /// Run the scans of a jobs file, each its own [[job]] of daemon config
+    /// options, and print a rollup; exits 4 if any job failed
+    Batch(BatchArgs),
+
+    /// List the devices on the local network that answer mDNS and SSDP
+    /// queries; `scan --local-discovery` scans them too
+    Discover(DiscoverArgs),
+
+    /// Print annotated examples of common scans
+    Examples,
+
+    /// Look back over the reports of earlier scans
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+
+    /// Look up the names of the addresses of a range from their PTR
+    /// records, without connecting to the hosts
+    Ptr(PtrArgs),
+
+    /// Compare the TLS of the ports of a report side by side and point out
+    /// the odd ones: old versions, expiring certificates, short keys
+    TlsReport(TlsReportArgs),
+
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Print the man page in roff format to stdout
+    Manpage,
+
+    /// Print the JSON Schema of the --json report to stdout, versioned with
+    /// the scanner; each NDJSON line is its "#/$defs/PortResult"
+    Schema,
+
+    /// Scan loopback listeners of known behavior (silent, banner, reset,
+    /// unbound, never accepting) and check what the scanner makes of
+    /// them, printing a pass/fail table; exits 4 if any check fails
+    SelfTest {
+        /// Connect and banner timeout of the scan (bare numbers are
+        /// milliseconds)
+        #[arg(long, value_name = "DURATION", default_value = "500", value_parser = parse_millis)]
+        timeout: Duration,
+    },
+
+    /// Run service detection, --fingerprints rules and --vuln-db tagging
+    /// again over the banners of a --json report, without scanning, and
+    /// write the report with a "reanalysis" list of what changed
+    Reanalyze {
+        /// The --json report of an earlier scan
+        #[arg(value_name = "REPORT")]
+        input: PathBuf,
+
+        /// TOML file of [[fingerprint]] rules (match, service, product,
+        /// cpe) tried on each banner before the built-in detection
+        #[arg(long, value_name = "PATH")]
+        fingerprints: Option<PathBuf>,
+
+        /// JSON file of advisories to tag the ports with again, as for scans
+        #[arg(long, value_name = "PATH")]
+        vuln_db: Option<PathBuf>,
+
+        /// Write the report to PATH instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Options of a scan, accepted with or without the `scan` subcommand.
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// Target host name, IP address, CIDR block (10.0.0.0/24) or range
+    /// (10.0.0.5-10.0.0.20); repeat or separate with commas for several
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["local_discovery", "ipv6_local_discovery", "list_port_groups", "list_presets"],
+        value_delimiter = ','
+    )]
+    pub target: Vec<String>,
+
+    /// Hosts to leave out, in the same forms as --target
+    #[arg(long, value_name = "TARGET", value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// File of addresses, CIDR blocks and ranges that must never be
+    /// probed, one per line; they are left out of every target, and a
+    /// target that names a blocked host on its own is an error
+    #[arg(long, value_name = "PATH")]
+    pub blocklist: Option<PathBuf>,
+
+    /// Leave out a target that names a blocked host on its own instead of
+    /// failing
+    #[arg(long, requires = "blocklist")]
+    pub skip_blocked: bool,
+
+    /// Scan public internet addresses without asking for confirmation
+    #[arg(long)]
+    pub allow_public: bool,
+
+    /// Leave out public internet addresses and scan only private and
+    /// reserved ones
+    #[arg(long, conflicts_with = "allow_public")]
+    pub private_only: bool,
+
+    /// Ports and ranges to scan, e.g. "22,80,8000-8100"; "@web", "@db" and
+    /// the other groups of --list-port-groups stand for their ports. "T:"
+    /// and "U:" switch what follows to TCP or UDP, as in "T:22,80,U:53";
+    /// UDP ports can't be scanned yet
+    #[arg(short, long, default_value = "1-1024")]
+    pub ports: String,
+
+    /// Ports to leave out, e.g. "25,135-139" or "T:25,U:53"
+    #[arg(long, value_name = "PORTS")]
+    pub exclude_ports: Option<String>,
+
+    /// Define a port group for --ports and --exclude-ports, e.g.
+    /// "admin=8080-8090,@web"; repeat for several
+    #[arg(long, value_name = "NAME=PORTS")]
+    pub port_group: Vec<PortGroup>,
+
+    /// Print the built-in port groups and those of --port-group, then exit
+    #[arg(long)]
+    pub list_port_groups: bool,
+
+    /// Fill in the options of a named preset, such as web-audit,
+    /// exposure-check or db-discovery, where they aren't given
+    #[arg(long, value_name = "NAME")]
+    pub preset: Option<String>,
+
+    /// Print the built-in presets and those of the user presets file, then
+    /// exit
+    #[arg(long)]
+    pub list_presets: bool,
+
+    /// What --preset filled in, once the command line is parsed.
+    #[arg(skip)]
+    pub applied_preset: Option<AppliedPreset>,
+
+    #[arg(short = 'c', long, default_value_t = 512)]
+    pub concurrency: usize,
+
+    /// Lower --concurrency to what the open-file limit and ephemeral port
+    /// range allow, instead of only warning
+    #[arg(long)]
+    pub auto_tune: bool,
+
+    /// Probe every port of a host even after it came back unreachable
+    /// (EHOSTUNREACH, ENETUNREACH) three times in a row
+    #[arg(long)]
+    pub no_unreachable_cache: bool,
+
+    /// Scan at most N hosts at once, each to the end of its ports before
+    /// the next one starts
+    #[arg(long, value_name = "N")]
+    pub host_concurrency: Option<usize>,
+
+    /// Keep at most N connections open to any one host; --concurrency
+    /// still caps the total
+    #[arg(long, value_name = "N")]
+    pub per_host_concurrency: Option<usize>,
+
+    /// Hold at most N established connections to any one host at once,
+    /// banner reads and probes included; attempts in flight don't count
+    #[arg(long, value_name = "N")]
+    pub max_established_per_host: Option<NonZeroUsize>,
+
+    /// Hold at most N established connections at once across all hosts
+    #[arg(long, value_name = "N")]
+    pub max_established: Option<NonZeroUsize>,
+
+    /// Read banners and run follow-up probes on at most N open ports at
+    /// once, apart from the --concurrency connection attempts, so slow
+    /// banners don't hold up the connect sweep
+    #[arg(long, value_name = "N", default_value_t = 128)]
+    pub probe_concurrency: usize,
+
+    /// Connect timeout: 800ms, 1.5s, or bare milliseconds; longer by
+    /// default on Windows, which takes about a second to report a closed
+    /// port
+    #[arg(
+        short = 'T',
+        long,
+        alias = "timeout-ms",
+        value_name = "DURATION",
+        default_value = DEFAULT_TIMEOUT,
+        value_parser = parse_millis
+    )]
+    pub timeout: Duration,
+
+    /// Connect timeouts for particular ports or ranges, overriding
+    /// --timeout there, e.g. "1433:3s,3389:2s,8000-8100:1500"
+    #[arg(long, value_name = "PORTS:DURATION,...")]
+    pub port_timeout: Option<PortTimeouts>,
+
+    /// How long to wait for a banner on open ports (bare numbers are
+    /// milliseconds)
+    #[arg(long, value_name = "DURATION", default_value = "1200ms", value_parser = parse_millis)]
+    pub banner_timeout: Duration,
+
+    /// Connect once more to an open port whose banner read was reset or
+    /// that closed without a word, 200ms later, and read its banner again;
+    /// the retry comes out of --probe-budget
+    #[arg(long)]
+    pub banner_retry: bool,
+
+    /// Make at most N retries in the whole scan, --banner-retry reads and
+    /// connects that found no free local port alike; ports that would be
+    /// retried after that keep what their last attempt gave
+    #[arg(long, value_name = "N")]
+    pub max_total_retries: Option<u64>,
+
+    #[arg(short, long, value_enum, default_value_t = Verbosity::Normal)]
+    pub verbose: Verbosity,
+
+    /// What stdout shows per port: "silent" nothing; "minimal" one
+    /// ADDRESS:PORT per open port, for xargs; "normal" a line per open port
+    /// with its service and product; "detailed" a line per scanned port
+    /// with status, service, version, latency and banner preview. Without
+    /// it, -v verbose is detailed unless --quiet, and anything else normal
+    #[arg(long, value_name = "MODE", conflicts_with = "tui")]
+    pub console: Option<ConsoleMode>,
+
+    /// With -v verbose or --console detailed, print each run of consecutive closed or filtered
+    /// ports that failed alike as one line, e.g. "1025-3305 | closed | 2281
+    /// ports", once the scan is done, instead of a line per port
+    #[arg(long)]
+    pub collapse_runs: bool,
+
+    /// Columns of the CSV and TXT reports and the detailed console line, in
+    /// order, e.g. "port,status,service,product,version,latency"; the banner
+    /// only when listed
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+    pub fields: Option<Vec<Field>>,
+
+    /// Only print and report the results matching EXPR, such as
+    /// 'status == "open" && port < 10000 && service != "http"'; the summary
+    /// still counts them all
+    ///
+    /// EXPR compares the fields of --fields (target, port, protocol, status,
+    /// service, product, version, extra_info, cpe, banner, latency,
+    /// started_at, finished_at, source_port, attempt, error, findings,
+    /// cluster, open_proxy, response_file, notes, note, expected_status) with
+    /// numbers, "strings" or 'strings', true, false and null, using ==, !=,
+    /// <, <=, >, >= and contains (a substring), combined with &&, || and !
+    /// and grouped with parentheses. Strings compare regardless of case. A
+    /// field a result lacks is null, so 'service != "http"' keeps ports
+    /// without a service while 'product contains "nginx"' drops them; a
+    /// field on its own, such as 'banner', is true when it has a value
+    #[arg(long, value_name = "EXPR")]
+    pub filter: Option<Filter>,
+
+    /// Tag the scan, e.g. "env=prod"; may be repeated with different
+    /// keys. Tags are kept verbatim with --operator and --reason in every
+    /// report, and in the emails and published messages
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub tag: Vec<Tag>,
+
+    /// Who ran the scan, e.g. "jsmith", recorded in every report
+    #[arg(long, value_name = "NAME")]
+    pub operator: Option<String>,
+
+    /// Why the scan was run, e.g. "change CHG-1234", recorded in every
+    /// report
+    #[arg(long, value_name = "TEXT")]
+    pub reason: Option<String>,
+
+    /// Record on every result when its connect attempt started and
+    /// completed (UTC, to the microsecond), the local source port and the
+    /// attempt number, in the JSON, NDJSON and CSV reports
+    #[arg(long)]
+    pub audit: bool,
+
+    /// Record on every result how it was classified, step by step: the
+    /// connect, the banner read, the service match, any follow-up probes
+    /// with the connection each ran on and any proxy check,
+    /// with their outcomes, in the JSON and NDJSON reports; on with -v
+    /// verbose or debug
+    #[arg(long)]
+    pub evidence: bool,
+
+    /// Print internal counters once done, also kept under "stats" in the
+    /// JSON report: how connects and banner reads ended, how follow-up
+    /// probes got their connections, how long answered connects took, how
+    /// long probes waited for a connection slot and the time spent in each
+    /// phase
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Connect again, at the end of the scan, to ports that answered far
+    /// slower than their host usually does, keeping both times under
+    /// "retime" in the JSON report; --stats counts the faster one
+    #[arg(long)]
+    pub retime_outliers: bool,
+
+    /// Bin the connect and banner times of the ports that answered by host
+    /// and range of ports, as "heatmap" in the JSON report and a table in
+    /// the text report; each result keeps its times under "timing"
+    #[arg(long)]
+    pub heatmap: bool,
+
+    /// Upper edges of the --heatmap latency bins, in milliseconds; a last
+    /// bin counts everything slower
+    #[arg(
+        long,
+        value_name = "MS,...",
+        default_value = "1,5,10,50,100,500,1000",
+        requires = "heatmap"
+    )]
+    pub heatmap_bins: Bins,
+
+    /// Ports in each --heatmap row; widened on large scans to keep the
+    /// map small
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = heatmap::DEFAULT_PORT_WIDTH,
+        value_parser = clap::value_parser!(u32).range(1..=65536),
+        requires = "heatmap"
+    )]
+    pub heatmap_port_width: u32,
+
+    /// Close every connection with a reset instead of a FIN, so its local
+    /// port skips TIME_WAIT; for scans fast enough to run out of ephemeral
+    /// ports
+    #[arg(long, conflicts_with = "ssh_jump")]
+    pub linger_zero: bool,
+
+    /// Scan from an SSH bastion: every connect becomes a channel of one
+    /// login to the bastion, authenticated with your keys or agent (needs
+    /// the ssh-jump feature, on Unix)
+    #[arg(long, value_name = "[USER@]HOST[:PORT]")]
+    pub ssh_jump: Option<JumpHost>,
+
+    /// Private key for --ssh-jump, besides the agent's and ~/.ssh/config's
+    #[arg(long, value_name = "FILE", requires = "ssh_jump")]
+    pub ssh_identity: Option<PathBuf>,
+
+    /// Channels --ssh-jump keeps open at once over its login
+    #[arg(long, value_name = "N", default_value_t = jump::DEFAULT_CHANNELS, value_parser = clap::value_parser!(usize), requires = "ssh_jump")]
+    pub ssh_channels: usize,
+
+    /// The OpenSSH client --ssh-jump logs in with
+    #[arg(
+        long,
+        value_name = "PROGRAM",
+        default_value = "ssh",
+        requires = "ssh_jump"
+    )]
+    pub ssh_command: PathBuf,
+
+    /// Write a JSON report; with --dry-run, the plan ("-" for stdout)
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Write one CSV row per port as results arrive
+    #[arg(long, value_name = "PATH")]
+    pub csv: Option<PathBuf>,
+
+    /// Write one JSON object per line per port as results arrive
+    #[arg(long, value_name = "PATH")]
+    pub ndjson: Option<PathBuf>,
+
+    #[arg(short = 'q', long)]
+    pub quiet: bool,
+
+    /// Format of log lines on stderr and in --log-file
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+
+    /// Also log to this file: every event at info level or above, however
+    /// quiet the console, plus the scan's lifecycle (start with the command
+    /// line, each host starting and finishing, throttle changes,
+    /// cancellation)
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Roll --log-file over to PATH.1 when it would grow past SIZE, such as
+    /// 10M
+    #[arg(long, value_name = "SIZE", value_parser = audit::parse_size, requires = "log_file")]
+    pub log_max_size: Option<u64>,
+
+    /// Write Prometheus gauges to this file at the end of the scan
+    #[arg(long, value_name = "PATH")]
+    pub metrics_textfile: Option<PathBuf>,
+
+    /// Serve live Prometheus gauges on ADDR at /metrics while scanning
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Push the gauges of --metrics-textfile to a Prometheus Pushgateway
+    /// when the scan is done, into the group of URL, such as
+    /// "http://push:9091/metrics/job/portscan/instance/{target}"; label
+    /// values may use {target}, {scan_id}, {operator} and {tag:KEY}
+    #[arg(long, value_name = "URL")]
+    pub pushgateway: Option<GroupUrl>,
+
+    /// Also push every DURATION while scanning (bare numbers are seconds)
+    #[arg(long, value_name = "DURATION", value_parser = parse_secs, requires = "pushgateway")]
+    pub pushgateway_interval: Option<Duration>,
+
+    /// Delete the --pushgateway group when the scan is done rather than
+    /// leave the last push there
+    #[arg(long, requires = "pushgateway")]
+    pub pushgateway_delete: bool,
+
+    /// User to log in to the --pushgateway as, with basic authentication
+    #[arg(long, value_name = "USER", env = "PUSHGATEWAY_USERNAME")]
+    pub pushgateway_username: Option<String>,
+
+    /// Password for --pushgateway-username
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        env = "PUSHGATEWAY_PASSWORD",
+        hide_env_values = true
+    )]
+    pub pushgateway_password: Option<String>,
+
+    /// Fail (exit 4) when the condition holds, e.g. "open:23,3389", or
+    /// "severity:high" for --vuln-db or --proxy-check findings of high
+    /// severity or worse
+    #[arg(long, value_name = "RULE")]
+    pub fail_on: Vec<FailOn>,
+
+    /// Fail (exit 4) when any port outside this list is open
+    #[arg(long, value_name = "PORTS")]
+    pub allow_open: Option<AllowOpen>,
+
+    /// Re-scan some of the ports of an earlier report: its results for the
+    /// ports this scan doesn't probe are carried into the reports as stale,
+    /// and those it probes again keep their earlier status
+    #[arg(long, value_name = "PATH", conflicts_with = "low_memory")]
+    pub from: Option<PathBuf>,
+
+    /// Keep a banner once in the JSON report when more than this many open
+    /// ports of a host answer with it, as behind a transparent proxy or
+    /// captive portal, and flag the host; 0 keeps every copy
+    #[arg(long, value_name = "N", default_value_t = shared::DEFAULT_THRESHOLD)]
+    pub shared_responses: usize,
+
+    /// What hosts are matched on against --from and --baseline reports:
+    /// their address, or the name they were scanned by, which follows a
+    /// host whose address changes
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = IdentityMode::Address)]
+    pub identity_by: IdentityMode,
+
+    /// Check the scan against the expected open ports of an earlier report
+    /// or a TOML file of [[host]] entries (target, open, services,
+    /// products), reporting drift as findings
+    #[arg(long, value_name = "PATH")]
+    pub baseline: Option<PathBuf>,
+
+    /// Severity of each kind of --baseline drift, e.g.
+    /// "new-open=critical,closed=high,changed=low"
+    #[arg(long, value_name = "KIND=SEVERITY,...", requires = "baseline")]
+    pub drift_severity: Option<DriftSeverities>,
+
+    /// Fail (exit 4) on --baseline drift of this severity or worse, any
+    /// drift when no severity is given
+    #[arg(
+        long,
+        value_name = "SEVERITY",
+        num_args = 0..=1,
+        default_missing_value = "low",
+        requires = "baseline"
+    )]
+    pub fail_on_drift: Option<Severity>,
+
+    /// Write a JUnit XML report with one test case per port or policy assertion
+    #[arg(long, value_name = "PATH")]
+    pub junit: Option<PathBuf>,
+
+    /// Also write one JSON summary per host into DIR
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// File name for per-host summaries: {target}, {date}, {time}, {scan_id}
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{target}.json",
+        requires = "output_dir"
+    )]
+    pub filename_template: FilenameTemplate,
+
+    /// Keep the fingerprint of the results (status and service of every
+    /// port) in this state file, and only write reports, publish, upload
+    /// and mail when it differs from the last scan's; otherwise only the
+    /// file's verified_at time moves
+    #[arg(long, value_name = "PATH")]
+    pub write_on_change: Option<PathBuf>,
+
+    /// After the scan, upload the report files written to an
+    /// s3://bucket/prefix/ destination, with credentials from the usual AWS
+    /// environment variables or shared credentials file
+    #[arg(long, value_name = "URL")]
+    pub upload_s3: Option<S3Url>,
+
+    /// S3-compatible endpoint for --upload-s3, such as
+    /// http://127.0.0.1:9000 for MinIO; objects are addressed path-style
+    #[arg(long, value_name = "URL", requires = "upload_s3")]
+    pub s3_endpoint: Option<Endpoint>,
+
+    /// Folder of the uploaded files under the prefix: {target}, {date},
+    /// {time}, {scan_id}
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{date}/{scan_id}",
+        requires = "upload_s3"
+    )]
+    pub s3_key_template: FilenameTemplate,
+
+    /// Fail the scan (exit 2) when a report could not be uploaded
+    #[arg(long, requires = "upload_s3")]
+    pub upload_required: bool,
+
+    /// Publish a JSON message per open port and one with the summary to a
+    /// kafka://broker:9092/topic or nats://server:4222/subject destination;
+    /// undelivered messages fail the scan (exit 2)
+    #[arg(long, value_name = "URL")]
+    pub publish: Option<PublishUrl>,
+
+    /// Message key, which picks the Kafka partition: target, target-port
+    /// or none
+    #[arg(
+        long,
+        value_name = "KEY",
+        default_value = "target",
+        requires = "publish"
+    )]
+    pub publish_key: PublishKey,
+
+    /// Most messages waiting for the broker; more are dropped and counted
+    /// as undelivered
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = publish::DEFAULT_BUFFER,
+        requires = "publish"
+    )]
+    pub publish_buffer: usize,
+
+    /// Stream NDJSON result, progress and summary events to a consumer at
+    /// unix:///path/to.sock or tcp://host:port
+    #[arg(long, value_name = "URL")]
+    pub stream_to: Option<StreamUrl>,
+
+    /// Listen on the --stream-to address for consumers instead of
+    /// connecting to one
+    #[arg(long, requires = "stream_to")]
+    pub stream_listen: bool,
+
+    /// Most events held for a slow or missing consumer; more are dropped
+    /// and counted
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = live::DEFAULT_BUFFER,
+        requires = "stream_to"
+    )]
+    pub stream_buffer: usize,
+
+    /// Mail the text report, with the JSON report attached, to ADDR after
+    /// the scan when ports are open; repeat or comma-separate for more
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_delimiter = ',',
+        requires = "smtp_server"
+    )]
+    pub email_to: Vec<String>,
+
+    /// SMTP relay for --email-to, as HOST:PORT (port 25 when left out)
+    #[arg(long, value_name = "HOST:PORT", requires = "email_to")]
+    pub smtp_server: Option<String>,
+
+    /// Upgrade the SMTP connection with STARTTLS, verifying the relay's
+    /// certificate; needed to log in with SMTP_USERNAME and SMTP_PASSWORD
+    #[arg(long, requires = "email_to")]
+    pub smtp_starttls: bool,
+
+    /// Sender address of the --email-to report
+    #[arg(long, value_name = "ADDR", default_value = email::DEFAULT_FROM, requires = "email_to")]
+    pub email_from: String,
+
+    /// Mail the report even when no port is open
+    #[arg(long, requires = "email_to")]
+    pub email_always: bool,
+
+    /// User to log in to the SMTP relay as
+    #[arg(long, value_name = "USER", env = "SMTP_USERNAME")]
+    pub smtp_username: Option<String>,
+
+    /// Password for --smtp-username
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        env = "SMTP_PASSWORD",
+        hide_env_values = true
+    )]
+    pub smtp_password: Option<String>,
+
+    /// Save everything each open port sent, untrimmed, as
+    /// DIR/<target>/<port>.bin; reading goes on after the banner until
+    /// --response-cap or --banner-timeout
+    #[arg(long, value_name = "DIR")]
+    pub save_responses: Option<PathBuf>,
+
+    /// Most bytes --save-responses keeps per port
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = responses::DEFAULT_CAP,
+        requires = "save_responses"
+    )]
+    pub response_cap: usize,
+
+    /// Most memory the results of the whole scan keep of banners, extra
+    /// information and --save-responses data, e.g. 256MiB; past it they
+    /// are cut to 64-byte previews, flagged "capture_truncated"
+    #[arg(long, value_name = "SIZE", value_parser = audit::parse_size)]
+    pub max_capture_mem: Option<u64>,
+
+    /// Replace with "[REDACTED]" what looks like credentials, private key
+    /// headers and email addresses in banners and extra information, and
+    /// whatever matches PATTERNS, comma-separated regular expressions, e.g.
+    /// "corp\.example\.com,/srv/[a-z]+"; every report counts them under
+    /// "redactions"
+    #[arg(long, value_name = "PATTERNS", num_args = 0..=1)]
+    pub redact: Option<Vec<String>>,
+
+    /// Redact the --save-responses files too
+    #[arg(long, requires = "redact")]
+    pub redact_raw: bool,
+
+    /// Stop the scan after DURATION and report the partial results (exit 3);
+    /// bare numbers are seconds. Time paused outside the --window doesn't
+    /// count
+    #[arg(long, value_name = "DURATION", value_parser = parse_secs)]
+    pub max_scan_time: Option<Duration>,
+
+    /// Only start probes within this window, pausing outside it, such as
+    /// "01:00-05:00" or "mon-fri 22:00-06:00 UTC" (local time by default)
+    #[arg(long, value_name = "[DAYS] HH:MM-HH:MM [ZONE]")]
+    pub window: Option<Window>,
+
+    /// Resolve target names with the DNS server at ADDR (such as
+    /// 1.1.1.1:53) instead of the system resolver; repeat for several, asked
+    /// in turn and failing over to the next
+    #[arg(long, value_name = "ADDR", value_delimiter = ',')]
+    pub resolver: Vec<SocketAddr>,
+
+    /// How long to wait for each DNS server (bare numbers are seconds)
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_secs)]
+    pub resolver_timeout: Duration,
+
+    /// Resolve target names over DNS-over-HTTPS with the server at URL, such
+    /// as https://cloudflare-dns.com/dns-query (needs the doh feature)
+    #[arg(long, value_name = "URL")]
+    pub doh: Vec<String>,
+
+    /// Annotate hosts with their country from a MaxMind City or Country
+    /// database (needs the geoip feature)
+    #[arg(long, value_name = "PATH")]
+    pub geoip_db: Option<PathBuf>,
+
+    /// Annotate hosts with their autonomous system from a MaxMind ASN
+    /// database (needs the geoip feature)
+    #[arg(long, value_name = "PATH")]
+    pub asn_db: Option<PathBuf>,
+
+    /// Tag open ports with the advisories of an offline JSON database that
+    /// match their product and version
+    #[arg(long, value_name = "PATH")]
+    pub vuln_db: Option<PathBuf>,
+
+    /// Merge notes and expected statuses from a TOML inventory of
+    /// [[annotation]] entries (target, port, note, expected) into the
+    /// results, flagging ports that deviate
+    #[arg(long, value_name = "PATH")]
+    pub annotations: Option<PathBuf>,
+
+    /// After the scan, guess each host's operating system from its open
+    /// ports, banners and TCP window (one more connect per host)
+    #[arg(long)]
+    pub os_guess: bool,
+
+    /// Guess with the rules of a JSON file instead of the bundled ones
+    #[arg(long, value_name = "PATH", requires = "os_guess")]
+    pub os_rules: Option<PathBuf>,
+
+    /// Run the on_open, filter and summarize hooks of a rhai script
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    /// Run CMD for every open port; {target}, {port}, {service} and {banner}
+    /// are substituted
+    #[arg(long, value_name = "CMD")]
+    pub exec: Option<String>,
+
+    /// Run --exec through `sh -c` instead of splitting it into arguments
+    #[arg(long, requires = "exec")]
+    pub exec_shell: bool,
+
+    /// How many --exec commands may run at once
+    #[arg(long, value_name = "N", default_value_t = 4, requires = "exec")]
+    pub exec_concurrency: usize,
+
+    /// Kill an --exec command after DURATION (bare numbers are seconds)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "60s",
+        value_parser = parse_secs,
+        requires = "exec"
+    )]
+    pub exec_timeout: Duration,
+
+    /// Exit 5 when any --exec command fails
+    #[arg(long, requires = "exec")]
+    pub exec_fail_fatal: bool,
+
+    /// Send an HTTP GET to open ports that sent no banner, on the connection
+    /// the banner read left open, to name web servers and their product
+    /// from the response
+    #[arg(long)]
+    pub http_probe: bool,
+
+    /// Tell the service of an open port from the first bytes it sends,
+    /// such as "SSH-", "RFB", a TLS record or a MySQL greeting, and ask
+    /// silent ports with the --probe-ladder until one answers in a way
+    /// those signatures know
+    #[arg(long)]
+    pub sniff: bool,
+
+    /// The requests --sniff asks silent ports with, in order, stopping at
+    /// the first answer it knows: "tls" (a ClientHello), "http" (a GET of
+    /// "/") and "crlf" (a bare line end)
+    #[arg(
+        long,
+        value_name = "RUNGS",
+        value_delimiter = ',',
+        default_values_t = sniff::DEFAULT_LADDER.to_vec(),
+        requires = "sniff"
+    )]
+    pub probe_ladder: Vec<Rung>,
+
+    /// How long the --banner-retry and follow-up probes of an open port,
+    /// such as --http-probe, may take in all, new connections included
+    /// (bare numbers are milliseconds)
+    #[arg(long, value_name = "DURATION", default_value = "3s", value_parser = parse_millis)]
+    pub probe_budget: Duration,
+
+    /// How long each follow-up probe may take within the --probe-budget:
+    /// a duration for every probe, KIND:DURATION for one type of probe, or
+    /// both, e.g. "2s,tls:5s" (default 1s; bare numbers are milliseconds).
+    /// A probe that times out is skipped and the next one runs
+    #[arg(long, value_name = "[KIND:]DURATION,...")]
+    pub probe_timeout: Option<ProbeTimeouts>,
+
+    /// Never run these types of follow-up probe: "http" (--http-probe and
+    /// the ladder's http rung), "tls" or "crlf"
+    #[arg(long, value_name = "KINDS", value_delimiter = ',', value_parser = probe::parse_kind)]
+    pub disable_probe: Vec<String>,
+
+    /// Run only these types of follow-up probe, of those the other options
+    /// turn on
+    #[arg(long, value_name = "KINDS", value_delimiter = ',', value_parser = probe::parse_kind)]
+    pub only_probe: Vec<String>,
+
+    /// Check the open --proxy-ports for HTTP and SOCKS5 proxies that relay
+    /// for anyone
+    ///
+    /// Only with this option does the scanner ask other hosts to relay
+    /// anything. For each open port in --proxy-ports it tries a SOCKS5
+    /// handshake without credentials, then an HTTP GET for an absolute URL.
+    /// Unless --proxy-check-url is given, the destination is a listener the
+    /// scanner runs on an ephemeral port for the length of the scan, reached
+    /// at the address the proxy sees the scan come from; a proxy that
+    /// delivers the request there is open. A relay marks the port
+    /// "open_proxy": true and adds a high-severity OPEN-PROXY finding.
+    #[arg(long)]
+    pub proxy_check: bool,
+
+    /// Ports --proxy-check looks at
+    #[arg(
+        long,
+        value_name = "PORTS",
+        value_delimiter = ',',
+        default_values_t = proxy::DEFAULT_PORTS.to_vec(),
+        requires = "proxy_check"
+    )]
+    pub proxy_ports: Vec<u16>,
+
+    /// Have --proxy-check fetch this benign http:// URL instead of reaching
+    /// back to the scanner; a proxy that answers it with 2xx counts as open
+    #[arg(long, value_name = "URL", value_parser = ProbeUrl::parse, requires = "proxy_check")]
+    pub proxy_check_url: Option<ProbeUrl>,
+
+    /// Time between status lines when stderr is not a terminal (bare numbers
+    /// are seconds, at least 1s)
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_secs)]
+    pub status_interval: Duration,
+
+    /// Write progress as one JSON object per line, even with --quiet, to an
+    /// inherited file descriptor such as 3 or to a file or FIFO; the last
+    /// event names the saved reports
+    #[arg(long, value_name = "FD|PATH")]
+    pub progress_json: Option<ProgressSink>,
+
+    /// Time between --progress-json events (bare numbers are milliseconds,
+    /// at least 100ms)
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_millis, requires = "progress_json")]
+    pub progress_json_interval: Duration,
+
+    /// Make reports reproducible: results sorted by target and port, every
+    /// duration zero and timestamps taken from --fake-time
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// The time reported by --deterministic (RFC 3339, default the Unix epoch)
+    #[arg(long, value_name = "TIME", requires = "deterministic")]
+    pub fake_time: Option<DateTime<Utc>>,
+
+    /// Spill results to a temporary file and keep only the open ports in
+    /// memory (on by default above a million target/port pairs)
+    #[arg(long, conflicts_with = "deterministic")]
+    pub low_memory: bool,
+
+    /// Full-screen live view of open ports, with keys to pause, resize and
+    /// stop the scan (needs a terminal on stdout)
+    #[arg(long, conflicts_with = "quiet")]
+    pub tui: bool,
+
+    /// Before the scan, ask the targets on this machine's own subnets for
+    /// their MAC address over ARP and leave out those that don't answer
+    /// (needs Linux, root and the arp feature)
+    #[arg(long)]
+    pub arp_discovery: bool,
+
+    /// How long --arp-discovery waits for answers, twice (bare numbers are
+    /// seconds)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "1s",
+        value_parser = parse_secs,
+        requires = "arp_discovery"
+    )]
+    pub arp_timeout: Duration,
+
+    /// Scan the hosts that didn't answer --arp-discovery too
+    #[arg(long, alias = "Pn", requires = "arp_discovery")]
+    pub no_ping: bool,
+
+    /// Before the scan, ping the all-nodes group ff02::1 and add every IPv6
+    /// link-local host that answers to the targets (needs Linux, root and
+    /// the ndp feature)
+    #[arg(long)]
+    pub ipv6_local_discovery: bool,
+
+    /// Interface --ipv6-local-discovery pings on (default: every up
+    /// interface with a link-local address)
+    #[arg(long, value_name = "NAME", requires = "ipv6_local_discovery")]
+    pub ipv6_interface: Option<String>,
+
+    /// How long --ipv6-local-discovery waits for answers, twice (bare
+    /// numbers are seconds)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "1s",
+        value_parser = parse_secs,
+        requires = "ipv6_local_discovery"
+    )]
+    pub ipv6_discovery_timeout: Duration,
+
+    /// Before the scan, find devices on the local network over mDNS and
+    /// SSDP, add them to the targets and report what they advertise
+    #[arg(long)]
+    pub local_discovery: bool,
+
+    /// How long --local-discovery queries and listens (bare numbers are
+    /// seconds)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "3s",
+        value_parser = parse_secs,
+        requires = "local_discovery"
+    )]
+    pub discovery_window: Duration,
+
+    /// After the scan, trace the network path to every host over TCP to one
+    /// of its ports (needs Linux and the traceroute feature)
+    #[arg(long)]
+    pub traceroute: bool,
+
+    /// How many hops --traceroute goes out at most
+    #[arg(long, value_name = "N", default_value_t = 30, requires = "traceroute")]
+    pub max_hops: u8,
+
+    /// Print what the scan would do, without connecting to anything
+    #[arg(long, conflicts_with = "tui")]
+    pub dry_run: bool,
+}
+
+impl ScanArgs {
+    /// Where the hosts to scan come from. Discovery doesn't run for a dry
+    /// run.
+    pub fn target_options(&self) -> TargetOptions {
+        TargetOptions {
+            targets: self.target.clone(),
+            exclude: self.exclude.clone(),
+            resolvers: self.resolver.clone(),
+            resolver_timeout: self.resolver_timeout,
+            doh: self.doh.clone(),
+            skip_blocked: self.skip_blocked,
+            local_discovery: (self.local_discovery && !self.dry_run)
+                .then_some(self.discovery_window),
+            ipv6_discovery: (self.ipv6_local_discovery && !self.dry_run)
+                .then_some(self.ipv6_discovery_timeout),
+            ipv6_interface: self.ipv6_interface.clone(),
+        }
+    }
+
+    /// The reports and sinks asked for.
+    pub fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            json: self.json.clone(),
+            text: self.output.clone(),
+            csv: self.csv.clone(),
+            ndjson: self.ndjson.clone(),
+            host_dir: self.output_dir.clone(),
+            filename_template: self.filename_template.clone(),
+            junit: self.junit.clone(),
+            fields: self.fields.clone(),
+            audit: self.audit,
+            publish: self.publish.clone(),
+            publish_key: self.publish_key,
+            publish_buffer: self.publish_buffer,
+            stream_to: self.stream_to.clone(),
+            stream_listen: self.stream_listen,
+            stream_buffer: self.stream_buffer,
+        }
+    }
+}
+
+/// Options of the API server.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    /// Require "Authorization: Bearer TOKEN" on every request
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        env = "PORT_SCANNER_TOKEN",
+        hide_env_values = true
+    )]
+    pub token: Option<String>,
+
+    /// How many scan jobs may run at once; later ones are queued
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    pub max_jobs: usize,
+
+    /// How many finished jobs to keep for retrieval
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    pub retain: usize,
+
+    #[arg(short, long, value_enum, default_value_t = Verbosity::Normal)]
+    pub verbose: Verbosity,
+
+    /// Format of log lines on stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+}
+
+/// Options of the scheduling daemon.
+#[derive(Args, Debug)]
+pub struct DaemonArgs {
+    /// When to scan, as a cron expression such as "0 2 * * *" (minute hour
+    /// day month weekday, optionally with seconds first) or @daily
+    #[arg(long, value_name = "CRON")]
+    pub schedule: Schedule,
+
+    /// TOML file of scan options, e.g. target = ["10.0.0.0/24"] and
+    /// json = "reports/{date}-{time}.json"
+    #[arg(long, value_name = "PATH")]
+    pub config: PathBuf,
+
+    /// Clock the schedule is read on: local, UTC or an offset such as
+    /// +02:00
+    #[arg(long, value_name = "ZONE", default_value = "local")]
+    pub timezone: Zone,
+
+    /// What happens to a run due while the previous scan is still going:
+    /// skip, or queue it to start right after
+    #[arg(long, value_name = "MODE", default_value = "skip")]
+    pub overlap: Overlap,
+
+    /// Keep the schedule, the next run and the last run's results in this
+    /// JSON file
+    #[arg(long, value_name = "PATH")]
+    pub status_file: Option<PathBuf>,
+
+    /// Stop after this many scans
+    #[arg(long, value_name = "N")]
+    pub max_runs: Option<u64>,
+
+    /// Refuse a config without a blocklist, at start and on SIGHUP
+    #[arg(long)]
+    pub require_blocklist: bool,
+
+    #[arg(short, long, value_enum, default_value_t = Verbosity::Normal)]
+    pub verbose: Verbosity,
+
+    /// Format of log lines on stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+}
+
+/// Options of `batch`.
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+    /// TOML or JSON file of jobs: each [[job]] a name and the scan options
+    /// of a daemon config, e.g. target, ports, concurrency, http_probe and
+    /// json
+    #[arg(value_name = "JOBS")]
+    pub jobs: PathBuf,
+
+    /// Run up to N jobs at a time, in the order of the file
+    #[arg(long, value_name = "N", default_value = "1")]
+    pub parallel_jobs: NonZeroUsize,
+
+    /// Start no more jobs once one fails; those running finish, the rest
+    /// are skipped
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Where jobs without a json report of their own write one, as
+    /// NAME.json
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub report_dir: PathBuf,
+
+    /// Write the rollup of the jobs to PATH as JSON
+    #[arg(long, value_name = "PATH")]
+    pub json: Option<PathBuf>,
+
+    #[arg(short, long, value_enum, default_value_t = Verbosity::Normal)]
+    pub verbose: Verbosity,
+
+    /// Format of log lines on stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+}
+
+/// What `history` does with the reports.
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// How steadily each port found open was open over the scans: the share
+    /// of the scans that observed it, when it was first and last seen, and
+    /// whether it flaps
+    Stability(StabilityArgs),
+}
+
+/// Options of `history stability`.
+#[derive(Args, Debug)]
+pub struct StabilityArgs {
+    /// --json reports (or nmap and masscan reports) of earlier scans, or
+    /// directories of them
+    #[arg(value_name = "REPORT", required = true)]
+    pub reports: Vec<PathBuf>,
+
+    /// Only the ports of these hosts, in the forms of the scan's --target
+    /// except host names
+    #[arg(short, long, value_delimiter = ',')]
+    pub target: Vec<String>,
+
+    /// Only the scans run with this --tag; repeat to require several
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub tag: Vec<Tag>,
+
+    /// Changes between open and not open at or above which a port is
+    /// flapping
+    #[arg(long, value_name = "CHANGES", default_value_t = history::DEFAULT_FLAPPING)]
+    pub flapping: usize,
+
+    /// What a host's ports are followed by from one report to the next:
+    /// its address, or the name it was scanned by
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = IdentityMode::Address)]
+    pub identity_by: IdentityMode,
+
+    /// Print a table, JSON or CSV
+    #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+    pub format: ListFormat,
+}
+
+/// How a subcommand prints what it found.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ListFormat {
+    /// Aligned lines, one per entry
+    Table,
+    /// The whole report as a JSON object
+    Json,
+    /// Comma-separated lines, one per entry, with a header
+    Csv,
+}
+
+/// Options of `tls-report`.
+#[derive(Args, Debug)]
+pub struct TlsReportArgs {
+    /// An nmap report with the output of the ssl-enum-ciphers, ssl-cert
+    /// and tls-alpn scripts, or a --json report carrying it
+    #[arg(value_name = "REPORT")]
+    pub report: PathBuf,
+
+    /// Certificates expiring within this many days stand out
+    #[arg(long, value_name = "DAYS", default_value_t = tlsreport::DEFAULT_EXPIRING_DAYS)]
+    pub expiring_within: u32,
+
+    /// Check the certificates against this time (RFC 3339) instead of now
+    #[arg(long, value_name = "TIME")]
+    pub at: Option<DateTime<Utc>>,
+
+    /// Print a table, a Markdown table, or the JSON report with a
+    /// "tls_report" block
+    #[arg(long, value_enum, default_value_t = TlsReportFormat::Table)]
+    pub format: TlsReportFormat,
+}
+
+/// How `tls-report` prints the comparison.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TlsReportFormat {
+    /// Aligned lines, one per port, a `!` after what stands out
+    Table,
+    /// A Markdown table, what stands out in bold
+    Markdown,
+    /// The whole report as JSON, with the comparison in "tls_report"
+    Json,
+}
+
+/// Options of `ptr`.
+#[derive(Args, Debug)]
+pub struct PtrArgs {
+    /// Addresses, CIDR blocks (10.0.0.0/24) or ranges (10.0.0.5-10.0.0.20)
+    /// to look up; repeat or separate with commas for several
+    #[arg(short, long, required = true, value_delimiter = ',')]
+    pub target: Vec<String>,
+
+    /// Addresses to leave out, in the same forms as --target
+    #[arg(long, value_name = "TARGET", value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Ask the DNS server at ADDR (such as 10.0.0.1:53) instead of the name
+    /// servers of /etc/resolv.conf; repeat for several, asked in order
+    /// when one fails or doesn't answer
+    #[arg(long, value_name = "ADDR", value_delimiter = ',')]
+    pub resolver: Vec<SocketAddr>,
+
+    /// Ask the DNS-over-HTTPS server at URL too (needs the doh feature)
+    #[arg(long, value_name = "URL")]
+    pub doh: Vec<String>,
+
+    /// How long to wait for each server's answer (bare numbers are seconds)
+    #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = parse_secs)]
+    pub timeout: Duration,
+
+    /// Lookups in flight at once
+    #[arg(short, long, default_value_t = 64)]
+    pub concurrency: usize,
+
+    /// Print a table, JSON or CSV
+    #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+    pub format: ListFormat,
+}
+
+/// Options of local service discovery.
+#[derive(Args, Debug)]
+pub struct DiscoverArgs {
+    /// How long to query and listen (bare numbers are seconds)
+    #[arg(long, value_name = "DURATION", default_value = "3s", value_parser = parse_secs)]
+    pub window: Duration,
+
+    /// Print the devices as JSON, by address
+    #[arg(long)]
+    pub json: bool,
+
+    #[arg(short, long, value_enum, default_value_t = Verbosity::Normal)]
+    pub verbose: Verbosity,
+
+    /// Format of log lines on stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines with the target and port spans inline
+    Pretty,
+    /// One JSON object per event, including the span fields
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
@@ -0,0 +1,60 @@
+//! Where the scanner reads the time, so reports can be made reproducible.
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The source of timestamps and measured durations.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// The current wall-clock time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Time passed since `start`.
+    fn elapsed(&self, start: Instant) -> Duration;
+}
+
+impl<C: Clock + ?Sized> Clock for std::sync::Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+
+    fn elapsed(&self, start: Instant) -> Duration {
+        (**self).elapsed(start)
+    }
+}
+
+/// The real clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn elapsed(&self, start: Instant) -> Duration {
+        start.elapsed()
+    }
+}
+
+/// A clock stopped at one instant: `now` never moves and every duration is
+/// zero, for reports that must not change between runs.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Default for FixedClock {
+    /// Stopped at the Unix epoch.
+    fn default() -> Self {
+        FixedClock(DateTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+
+    fn elapsed(&self, _start: Instant) -> Duration {
+        Duration::ZERO
+    }
+}
@@ -0,0 +1,289 @@
+//! Groups the open ports that front the same service, such as dozens of
+//! ports answered by one reverse proxy, so reports can list them once.
+//!
+//! Banners are compared after dropping the HTTP headers that change on every
+//! response (`Date`, cookies and the like): equal ones are found by hash, and
+//! the rest join the first group whose banner shares at least
+//! [`SIMILARITY`] of its words. Results are visited in target and port
+//! order, so the same results always give the same groups and ids.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::scanner::{PortResult, PortStatus};
+
+/// Share of words (Jaccard index) two banners need in common to be grouped.
+pub const SIMILARITY: f64 = 0.8;
+
+/// Headers whose values differ between otherwise identical responses.
+const VOLATILE_HEADERS: &[&str] = &[
+    "age",
+    "content-length",
+    "date",
+    "etag",
+    "expires",
+    "last-modified",
+    "set-cookie",
+    "x-request-id",
+];
+
+/// Ports that answered with the same, or nearly the same, banner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    /// The id given to its results, from 1.
+    pub id: u32,
+    /// Its ports, in target and port order.
+    pub members: Vec<(IpAddr, u16)>,
+    /// Whether every banner was the same once volatile headers were dropped.
+    pub identical: bool,
+    /// What the service is: product and version, service name or the first
+    /// line of the banner.
+    pub label: String,
+}
+
+impl fmt::Display for Cluster {
+    /// `ports 8000-8010, 9000: identical service (nginx 1.18.0)`, with the
+    /// host before each run of ports when there are several.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let single_host = self.members.iter().all(|(t, _)| *t == self.members[0].0);
+        let mut runs: Vec<String> = Vec::new();
+        let mut i = 0;
+        while i < self.members.len() {
+            let (target, first) = self.members[i];
+            let mut last = first;
+            while let Some(&(t, p)) = self.members.get(i + 1) {
+                if t != target || Some(p) != last.checked_add(1) {
+                    break;
+                }
+                last = p;
+                i += 1;
+            }
+            let ports = if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            };
+            runs.push(if single_host {
+                ports
+            } else {
+                format!("{}:{}", target, ports)
+            });
+            i += 1;
+        }
+        write!(
+            f,
+            "{} {}: {} service ({})",
+            if self.members.len() == 1 {
+                "port"
+            } else {
+                "ports"
+            },
+            runs.join(", "),
+            if self.identical {
+                "identical"
+            } else {
+                "similar"
+            },
+            self.label
+        )
+    }
+}
+
+/// A banner with its volatile headers dropped.
+fn normalize(banner: &str) -> String {
+    banner
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| {
+            let name = line.split_once(':').map_or("", |(name, _)| name);
+            !VOLATILE_HEADERS.contains(&name.trim().to_ascii_lowercase().as_str())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn words(text: &str) -> BTreeSet<&str> {
+    text.split(|c: char| c.is_whitespace() || c == ';' || c == ',')
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+fn jaccard(a: &BTreeSet<&str>, b: &BTreeSet<&str>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+fn label(r: &PortResult) -> String {
+    r.product_version()
+        .or_else(|| r.service.clone())
+        .or_else(|| {
+            let banner = r.banner_str()?;
+            let line = banner.lines().next()?.trim();
+            Some(line.chars().take(60).collect())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Groups the open ports of `results` by banner, sets the `cluster` of every
+/// port that shares its service with another one, and returns the groups.
+/// Ports whose banner is unlike any other keep no cluster.
+pub fn assign(results: &mut [PortResult]) -> Vec<Cluster> {
+    let mut order: Vec<usize> = (0..results.len())
+        .filter(|&i| {
+            results[i].status == PortStatus::Open
+                && results[i].banner.as_ref().is_some_and(|b| !b.is_empty())
+        })
+        .collect();
+    order.sort_by_key(|&i| (results[i].target, results[i].port));
+    let texts: Vec<String> = order
+        .iter()
+        .map(|&i| normalize(&results[i].banner_str().unwrap_or_default()))
+        .collect();
+
+    // Each group: its first member's words and its members' positions in
+    // `order`.
+    let mut groups: Vec<(BTreeSet<&str>, Vec<usize>)> = Vec::new();
+    let mut exact: HashMap<&str, usize> = HashMap::new();
+    let mut identical: Vec<bool> = Vec::new();
+    for (n, text) in texts.iter().enumerate() {
+        if let Some(&g) = exact.get(text.as_str()) {
+            groups[g].1.push(n);
+            continue;
+        }
+        let own = words(text);
+        let similar = groups
+            .iter()
+            .position(|(first, _)| jaccard(first, &own) >= SIMILARITY);
+        let g = match similar {
+            Some(g) => {
+                groups[g].1.push(n);
+                identical[g] = false;
+                g
+            }
+            None => {
+                groups.push((own, vec![n]));
+                identical.push(true);
+                groups.len() - 1
+            }
+        };
+        exact.insert(text, g);
+    }
+
+    for r in results.iter_mut() {
+        r.cluster = None;
+    }
+    let mut clusters = Vec::new();
+    for ((_, members), identical) in groups.into_iter().zip(identical) {
+        if members.len() < 2 {
+            continue;
+        }
+        let id = clusters.len() as u32 + 1;
+        for &n in &members {
+            results[order[n]].cluster = Some(id);
+        }
+        clusters.push(Cluster {
+            id,
+            members: members
+                .iter()
+                .map(|&n| (results[order[n]].target, results[order[n]].port))
+                .collect(),
+            identical,
+            label: label(&results[order[members[0]]]),
+        });
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(target: &str, port: u16, banner: &str) -> PortResult {
+        let mut r = PortResult::new(target.parse().unwrap(), port, PortStatus::Open);
+        r.banner = Some(banner.into());
+        r
+    }
+
+    fn nginx(date: &str, length: u32) -> String {
+        format!(
+            "HTTP/1.1 404 Not Found\r\nServer: nginx/1.18.0\r\nDate: {}\r\n\
+             Content-Type: text/html\r\nContent-Length: {}\r\nConnection: close",
+            date, length
+        )
+    }
+
+    #[test]
+    fn a_proxy_on_many_ports_is_one_cluster() {
+        let mut results: Vec<PortResult> = (8000..=8010)
+            .chain([9000])
+            .map(|port| {
+                let date = format!("Tue, 14 Oct 2026 09:00:{:02} GMT", port % 60);
+                let mut r = open("10.0.0.1", port, &nginx(&date, 153));
+                r.product = Some("nginx".into());
+                r.version = Some("1.18.0".into());
+                r
+            })
+            .collect();
+        results.push(open("10.0.0.1", 22, "SSH-2.0-OpenSSH_9.6"));
+        results.reverse();
+
+        let clusters = assign(&mut results);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(
+            clusters[0].to_string(),
+            "ports 8000-8010, 9000: identical service (nginx 1.18.0)"
+        );
+        assert!(results
+            .iter()
+            .all(|r| r.cluster == (r.port != 22).then_some(1)));
+    }
+
+    #[test]
+    fn near_identical_banners_are_similar_and_different_ones_apart() {
+        let mut results = vec![
+            open("10.0.0.2", 80, &nginx("Tue, 14 Oct 2026 09:00:00 GMT", 153)),
+            open(
+                "10.0.0.1",
+                80,
+                "HTTP/1.1 404 Not Found\r\nServer: nginx/1.18.0\r\n\
+                 Content-Type: text/html\r\nX-Served-By: edge-2\r\nConnection: close",
+            ),
+            open("10.0.0.1", 22, "SSH-2.0-OpenSSH_9.6"),
+            open("10.0.0.3", 22, "SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6"),
+            open("10.0.0.3", 21, "220 (vsFTPd 3.0.5)"),
+            open("10.0.0.4", 21, "220 (vsFTPd 3.0.5)"),
+        ];
+        let clusters = assign(&mut results);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(
+            clusters[0].to_string(),
+            "ports 10.0.0.1:80, 10.0.0.2:80: similar service (HTTP/1.1 404 Not Found)"
+        );
+        assert_eq!(
+            clusters[1].to_string(),
+            "ports 10.0.0.3:21, 10.0.0.4:21: identical service (220 (vsFTPd 3.0.5))"
+        );
+        let ids: Vec<Option<u32>> = results.iter().map(|r| r.cluster).collect();
+        assert_eq!(ids, [Some(1), Some(1), None, None, Some(2), Some(2)]);
+    }
+
+    #[test]
+    fn clusters_do_not_depend_on_result_order() {
+        let mut results = vec![
+            open("10.0.0.1", 443, "same"),
+            open("10.0.0.1", 80, "other"),
+            open("10.0.0.1", 8443, "same"),
+            open("10.0.0.1", 8080, "other"),
+        ];
+        let forward = assign(&mut results);
+        results.reverse();
+        assert_eq!(assign(&mut results), forward);
+        let host: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(forward[0].members, [(host, 80), (host, 8080)]);
+        assert_eq!(forward[1].members, [(host, 443), (host, 8443)]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Why a scan ended, as printed on its last line and recorded in the report
+//! as its `completion`.
+//!
+//! A scan either runs to its end or is stopped: by the user (Ctrl-C, or
+//! quitting the TUI), by `--max-scan-time`, or by an error it cannot go on
+//! after, such as results that can no longer be spilled to disk. Every one
+//! of those stops goes through a [`Shutdown`], which cancels the scan once
+//! and keeps the first reason it was given; the results of a stopped scan
+//! are partial.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// What ended a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionReason {
+    /// It probed every port.
+    Completed,
+    /// The user stopped it.
+    CancelledByUser,
+    /// It ran for its `--max-scan-time`.
+    DeadlineExceeded,
+    /// It could not go on.
+    Error,
+}
+
+/// Why a scan ended, with what stopped it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Completion {
+    /// What ended it.
+    pub reason: CompletionReason,
+    /// How the user stopped it, its time limit, or the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Completion {
+    /// A scan that probed every port.
+    pub fn completed() -> Self {
+        Completion {
+            reason: CompletionReason::Completed,
+            detail: None,
+        }
+    }
+
+    /// A scan the user stopped, `how`.
+    pub fn cancelled(how: &str) -> Self {
+        Completion {
+            reason: CompletionReason::CancelledByUser,
+            detail: Some(how.to_string()),
+        }
+    }
+
+    /// A scan stopped after its `--max-scan-time` of `limit`.
+    pub fn deadline(limit: Duration) -> Self {
+        Completion {
+            reason: CompletionReason::DeadlineExceeded,
+            detail: Some(format!("--max-scan-time {}s", limit.as_secs_f64())),
+        }
+    }
+
+    /// A scan stopped by `error`.
+    pub fn error(error: impl fmt::Display) -> Self {
+        Completion {
+            reason: CompletionReason::Error,
+            detail: Some(error.to_string()),
+        }
+    }
+
+    /// Whether the scan was stopped before its end.
+    pub fn is_partial(&self) -> bool {
+        self.reason != CompletionReason::Completed
+    }
+}
+
+/// E.g. `completed`, or `deadline exceeded (--max-scan-time 60s)`.
+impl fmt::Display for Completion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.reason {
+            CompletionReason::Completed => "completed",
+            CompletionReason::CancelledByUser => "cancelled by the user",
+            CompletionReason::DeadlineExceeded => "deadline exceeded",
+            CompletionReason::Error => "stopped on an error",
+        })?;
+        match &self.detail {
+            Some(detail) => write!(f, " ({})", detail),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Stops a scan, once, for the first reason given.
+#[derive(Debug)]
+pub struct Shutdown {
+    cancel: CancellationToken,
+    reason: Option<Completion>,
+}
+
+impl Shutdown {
+    /// Stops the scan `cancel` cancels.
+    pub fn new(cancel: CancellationToken) -> Self {
+        Shutdown {
+            cancel,
+            reason: None,
+        }
+    }
+
+    /// Stops the scan for `why`. False, changing nothing, when it is
+    /// stopping already.
+    pub fn stop(&mut self, why: Completion) -> bool {
+        if self.is_stopping() {
+            return false;
+        }
+        self.cancel.cancel();
+        self.reason = Some(why);
+        true
+    }
+
+    /// Whether the scan is stopping.
+    pub fn is_stopping(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Why the scan ended: what it was stopped for, or else it completed.
+    pub fn completion(&self) -> Completion {
+        match &self.reason {
+            Some(why) => why.clone(),
+            None if self.is_stopping() => Completion {
+                reason: CompletionReason::CancelledByUser,
+                detail: None,
+            },
+            None => Completion::completed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_first_reason_to_stop_is_the_one_kept() {
+        let cancel = CancellationToken::new();
+        let mut shutdown = Shutdown::new(cancel.clone());
+        assert_eq!(shutdown.completion(), Completion::completed());
+        assert!(!shutdown.completion().is_partial());
+
+        assert!(shutdown.stop(Completion::deadline(Duration::from_secs(60))));
+        assert!(cancel.is_cancelled());
+        assert!(!shutdown.stop(Completion::cancelled("Ctrl-C")));
+        assert!(!shutdown.stop(Completion::error("disk full")));
+        let completion = shutdown.completion();
+        assert_eq!(completion.reason, CompletionReason::DeadlineExceeded);
+        assert!(completion.is_partial());
+        assert_eq!(
+            completion.to_string(),
+            "deadline exceeded (--max-scan-time 60s)"
+        );
+    }
+
+    #[test]
+    fn every_reason_reads_and_serializes() {
+        let reasons = [
+            (
+                Completion::completed(),
+                "completed",
+                r#"{"reason":"completed"}"#,
+            ),
+            (
+                Completion::cancelled("Ctrl-C"),
+                "cancelled by the user (Ctrl-C)",
+                r#"{"reason":"cancelled_by_user","detail":"Ctrl-C"}"#,
+            ),
+            (
+                Completion::deadline(Duration::from_millis(1500)),
+                "deadline exceeded (--max-scan-time 1.5s)",
+                r#"{"reason":"deadline_exceeded","detail":"--max-scan-time 1.5s"}"#,
+            ),
+            (
+                Completion::error("No space left on device"),
+                "stopped on an error (No space left on device)",
+                r#"{"reason":"error","detail":"No space left on device"}"#,
+            ),
+        ];
+        for (completion, text, json) in reasons {
+            assert_eq!(completion.to_string(), text);
+            assert_eq!(serde_json::to_string(&completion).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<Completion>(json).unwrap(),
+                completion
+            );
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! How the scanner opens connections: real TCP, or a scripted mock for tests.
+
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+
+/// A bidirectional byte stream the scanner can read banners from.
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncReadWrite for T {}
+
+/// An established connection returned by a [`Connector`].
+pub type Connection = Box<dyn AsyncReadWrite>;
+
+/// Opens connections for the scanner.
+///
+/// The scanner applies its own timeout around `connect` and classifies the
+/// outcome: success is open, [`io::ErrorKind::ConnectionRefused`] is closed,
+/// [`io::ErrorKind::ConnectionReset`], which only follows a completed
+/// handshake, is open and reset on read, and anything else, including the
+/// timeout, is filtered.
+pub trait Connector: Send + Sync {
+    /// Connects to `addr`.
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>>;
+
+    /// Connects like [`Connector::connect`], setting `source` to the local
+    /// address of the attempt as soon as it is known, so that it is there
+    /// even when the attempt is refused or times out. By default the source
+    /// stays unknown.
+    fn connect_from<'a>(
+        &'a self,
+        addr: SocketAddr,
+        source: &'a OnceLock<SocketAddr>,
+    ) -> BoxFuture<'a, io::Result<Connection>> {
+        let _ = source;
+        self.connect(addr)
+    }
+}
+
+impl<C: Connector + ?Sized> Connector for std::sync::Arc<C> {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>> {
+        (**self).connect(addr)
+    }
+
+    fn connect_from<'a>(
+        &'a self,
+        addr: SocketAddr,
+        source: &'a OnceLock<SocketAddr>,
+    ) -> BoxFuture<'a, io::Result<Connection>> {
+        (**self).connect_from(addr, source)
+    }
+}
+
+/// Plain TCP connects through the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpConnector {
+    zero_linger: bool,
+}
+
+impl TcpConnector {
+    /// Closes every connection with a reset instead of a FIN, so that its
+    /// local port skips `TIME_WAIT` and can be used again at once; what a
+    /// scan fast enough to run out of ephemeral ports needs.
+    pub fn zero_linger(mut self) -> Self {
+        self.zero_linger = true;
+        self
+    }
+
+    fn connected(&self, stream: TcpStream) -> io::Result<Connection> {
+        if self.zero_linger {
+            stream.set_zero_linger()?;
+        }
+        Ok(Box::new(stream))
+    }
+}
+
+impl Connector for TcpConnector {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move { self.connected(TcpStream::connect(addr).await?) })
+    }
+
+    /// Binds to an ephemeral port before connecting, which is what makes
+    /// the source port known for refused and timed-out attempts too.
+    fn connect_from<'a>(
+        &'a self,
+        addr: SocketAddr,
+        source: &'a OnceLock<SocketAddr>,
+    ) -> BoxFuture<'a, io::Result<Connection>> {
+        Box::pin(async move {
+            let (socket, any) = if addr.is_ipv4() {
+                (
+                    TcpSocket::new_v4()?,
+                    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                )
+            } else {
+                (
+                    TcpSocket::new_v6()?,
+                    SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                )
+            };
+            socket.bind(any)?;
+            let _ = source.set(socket.local_addr()?);
+            self.connected(socket.connect(addr).await?)
+        })
+    }
+}
+
+/// What a [`MockConnector`] does for a port.
+#[derive(Debug, Clone)]
+pub enum MockBehavior {
+    /// Accept the connection, send these bytes and close it.
+    Open(Vec<u8>),
+    /// Refuse the connection.
+    Refused,
+    /// Never answer, so the scanner's timeout fires.
+    Hang,
+    /// Fail with an error of this kind.
+    Error(io::ErrorKind),
+    /// Fail with this raw OS error code, e.g. `EMFILE`.
+    Os(i32),
+    /// Wait this long, then behave as the inner behavior.
+    After(Duration, Box<MockBehavior>),
+}
+
+/// A scripted [`Connector`] for exercising the scan pipeline without sockets.
+///
+/// Ports without a script are refused, on hosts without one.
+#[derive(Debug, Clone, Default)]
+pub struct MockConnector {
+    ports: HashMap<u16, MockBehavior>,
+    hosts: HashMap<IpAddr, MockBehavior>,
+    sequences: HashMap<u16, Vec<MockBehavior>>,
+    /// Connects so far to each address with a sequence.
+    calls: Arc<Mutex<HashMap<SocketAddr, usize>>>,
+}
+
+impl MockConnector {
+    /// Creates a mock that refuses every port.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scripts `port` on every target.
+    pub fn port(mut self, port: u16, behavior: MockBehavior) -> Self {
+        self.ports.insert(port, behavior);
+        self
+    }
+
+    /// Scripts every port of `host`, ahead of the scripts of ports.
+    pub fn host(mut self, host: IpAddr, behavior: MockBehavior) -> Self {
+        self.hosts.insert(host, behavior);
+        self
+    }
+
+    /// Scripts `port` on every target connect by connect: the first
+    /// connect to a target's port behaves as the first of `behaviors`, the
+    /// second as the second, and so on, the last one repeating. Ahead of
+    /// [`MockConnector::port`], behind [`MockConnector::host`].
+    pub fn sequence(mut self, port: u16, behaviors: Vec<MockBehavior>) -> Self {
+        assert!(!behaviors.is_empty(), "a sequence needs a behavior");
+        self.sequences.insert(port, behaviors);
+        self
+    }
+
+    fn behavior(&self, addr: SocketAddr) -> MockBehavior {
+        if let Some(behavior) = self.hosts.get(&addr.ip()) {
+            return behavior.clone();
+        }
+        if let Some(sequence) = self.sequences.get(&addr.port()) {
+            let mut calls = self.calls.lock().expect("mock call counts");
+            let call = calls.entry(addr).or_default();
+            let behavior = &sequence[(*call).min(sequence.len() - 1)];
+            *call += 1;
+            return behavior.clone();
+        }
+        self.ports
+            .get(&addr.port())
+            .cloned()
+            .unwrap_or(MockBehavior::Refused)
+    }
+}
+
+fn act(behavior: MockBehavior) -> BoxFuture<'static, io::Result<Connection>> {
+    Box::pin(async move {
+        match behavior {
+            MockBehavior::Open(banner) => {
+                let (client, mut server) = tokio::io::duplex(banner.len().max(1));
+                server.write_all(&banner).await?;
+                Ok(Box::new(client) as Connection)
+            }
+            MockBehavior::Refused => Err(io::ErrorKind::ConnectionRefused.into()),
+            MockBehavior::Hang => futures::future::pending().await,
+            MockBehavior::Error(kind) => Err(kind.into()),
+            MockBehavior::Os(code) => Err(io::Error::from_raw_os_error(code)),
+            MockBehavior::After(delay, then) => {
+                tokio::time::sleep(delay).await;
+                act(*then).await
+            }
+        }
+    })
+}
+
+impl Connector for MockConnector {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>> {
+        act(self.behavior(addr))
+    }
+}
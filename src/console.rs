@@ -0,0 +1,338 @@
+//! What the console prints for each scanned port.
+//!
+//! The flags pick one [`ConsoleMode`]; the mode alone then decides which
+//! results get lines and what the lines hold, so a new flag has to go
+//! through [`ConsoleMode::from_flags`] to change what is printed.
+
+use crate::fields::Field;
+use crate::scanner::{PortResult, PortStatus};
+use crate::spec::Protocol;
+use crate::vuln::Severity;
+use colored::*;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// How results are printed on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleMode {
+    /// Nothing; the reports or the TUI carry the results.
+    Silent,
+    /// One `address:port` token per open port and nothing else, for
+    /// piping into `xargs` and the like.
+    Minimal,
+    /// A line per open port with its service and product, and any
+    /// findings under it.
+    Normal,
+    /// A line per scanned port, whatever its status, with the service,
+    /// product and version, latency, a preview of the banner and the error.
+    Detailed,
+}
+
+impl ConsoleMode {
+    /// Every mode, by name.
+    pub const ALL: [ConsoleMode; 4] = [
+        ConsoleMode::Silent,
+        ConsoleMode::Minimal,
+        ConsoleMode::Normal,
+        ConsoleMode::Detailed,
+    ];
+
+    /// The mode of a run: none under the TUI, which shows the results
+    /// itself; otherwise `console` when given; otherwise detailed with
+    /// `-v verbose` unless `--quiet` keeps the console to open ports, and
+    /// normal.
+    pub fn from_flags(console: Option<ConsoleMode>, verbose: bool, quiet: bool, tui: bool) -> Self {
+        if tui {
+            ConsoleMode::Silent
+        } else if let Some(mode) = console {
+            mode
+        } else if verbose && !quiet {
+            ConsoleMode::Detailed
+        } else {
+            ConsoleMode::Normal
+        }
+    }
+
+    /// The name, as `--console` takes it.
+    pub fn name(self) -> &'static str {
+        match self {
+            ConsoleMode::Silent => "silent",
+            ConsoleMode::Minimal => "minimal",
+            ConsoleMode::Normal => "normal",
+            ConsoleMode::Detailed => "detailed",
+        }
+    }
+
+    /// The console lines of `r` in this mode, each ending in a newline;
+    /// empty when the mode leaves the port out.
+    pub fn print_result(self, r: &PortResult, layout: &Layout) -> String {
+        let open = r.status == PortStatus::Open;
+        let mut text = match self {
+            ConsoleMode::Silent => return String::new(),
+            ConsoleMode::Minimal => {
+                return if open {
+                    format!("{}\n", SocketAddr::new(r.target, r.port))
+                } else {
+                    String::new()
+                };
+            }
+            ConsoleMode::Normal if !open => return String::new(),
+            ConsoleMode::Normal => normal_line(r, layout),
+            ConsoleMode::Detailed => match layout.fields.as_deref() {
+                Some(fields) => format!("{}\n", field_line(r, fields)),
+                None => detailed_line(r, layout),
+            },
+        };
+        for f in &r.findings {
+            let label = format!("{:>8}", f.severity.to_string().to_uppercase());
+            let label = match f.severity {
+                Severity::Critical => label.bright_red().bold(),
+                Severity::High => label.red(),
+                Severity::Medium => label.yellow(),
+                Severity::Low => label.normal(),
+            };
+            text.push_str(&format!("      {} {} {}\n", label, f.id.bold(), f.summary));
+        }
+        text
+    }
+}
+
+impl fmt::Display for ConsoleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ConsoleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        ConsoleMode::ALL
+            .into_iter()
+            .find(|m| m.name() == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown console mode '{}' (valid modes: silent, minimal, normal, detailed)",
+                    s
+                )
+            })
+    }
+}
+
+/// How lines are laid out, whatever the mode.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// Whether ports are shown with their address, as when scanning
+    /// several targets.
+    pub show_target: bool,
+    /// The `--fields` of the detailed line.
+    pub fields: Option<Vec<Field>>,
+}
+
+impl Layout {
+    /// The port of `r` as it starts a line.
+    pub fn port(&self, r: &PortResult) -> ColoredString {
+        if self.show_target {
+            match r.protocol {
+                Protocol::Tcp => SocketAddr::new(r.target, r.port).to_string().bright_blue(),
+                protocol => {
+                    format!("{}/{}", SocketAddr::new(r.target, r.port), protocol).bright_blue()
+                }
+            }
+        } else {
+            format!("{:>5}", r.port_label()).bright_blue()
+        }
+    }
+}
+
+/// The first 60 characters of a long console value, marked as cut.
+pub fn preview(value: &str) -> String {
+    let preview: String = value.chars().take(60).collect();
+    if value.len() > 60 {
+        format!("{}...", preview)
+    } else {
+        preview
+    }
+}
+
+fn normal_line(r: &PortResult, layout: &Layout) -> String {
+    let serv = r.service.as_deref().unwrap_or("-").bright_cyan();
+    match r.product_version() {
+        Some(product) => format!("{} open   {} ({})\n", layout.port(r), serv, product),
+        None => format!("{} open   {}\n", layout.port(r), serv),
+    }
+}
+
+fn detailed_line(r: &PortResult, layout: &Layout) -> String {
+    let serv = r.service.as_deref().unwrap_or("-").bright_cyan();
+    let ban = r.banner_str().map_or("-".to_string(), |b| preview(&b));
+    let product = r
+        .product_version()
+        .map_or(String::new(), |p| format!(" | Product: {}", p));
+    let reset = if r.reset_on_read {
+        format!(" | {}", "Reset on read".yellow())
+    } else {
+        String::new()
+    };
+    let error = r
+        .error
+        .as_ref()
+        .map_or(String::new(), |e| format!(" | Error: {}", e));
+    format!(
+        "{} | {} | Service: {}{} | Latency: {}ms | Banner: {}{}{}\n",
+        layout.port(r),
+        r.status,
+        serv,
+        product,
+        r.duration_ms,
+        ban,
+        reset,
+        error
+    )
+}
+
+/// The detailed line for `--fields`: the values in order, colored like the
+/// usual line.
+fn field_line(r: &PortResult, fields: &[Field]) -> String {
+    let values: Vec<String> = fields
+        .iter()
+        .map(|&f| {
+            let value = f.value(r).map_or("-".to_string(), |v| preview(&v));
+            match f {
+                Field::Target | Field::Port => value.bright_blue().to_string(),
+                Field::Status => match r.status {
+                    PortStatus::Open => value.bright_green().to_string(),
+                    PortStatus::Closed => value.bright_red().to_string(),
+                    PortStatus::Filtered => value.yellow().to_string(),
+                },
+                Field::Service => value.bright_cyan().to_string(),
+                _ => value,
+            }
+        })
+        .collect();
+    values.join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vuln::Finding;
+
+    fn results() -> Vec<PortResult> {
+        let target = "10.0.0.1".parse().unwrap();
+        let mut ssh = PortResult::new(target, 22, PortStatus::Open);
+        ssh.service = Some("SSH".to_string());
+        ssh.product = Some("OpenSSH".to_string());
+        ssh.version = Some("9.6".to_string());
+        ssh.banner = Some("SSH-2.0-OpenSSH_9.6".into());
+        ssh.duration_ms = 3;
+        let closed = PortResult::new(target, 23, PortStatus::Closed);
+        let filtered = PortResult::new(target, 25, PortStatus::Filtered);
+        vec![ssh, closed, filtered]
+    }
+
+    fn printed(mode: ConsoleMode, layout: &Layout) -> String {
+        colored::control::set_override(false);
+        results()
+            .iter()
+            .map(|r| mode.print_result(r, layout))
+            .collect()
+    }
+
+    #[test]
+    fn flags_pick_the_mode() {
+        use ConsoleMode::*;
+        // (--console, -v verbose, --quiet, --tui) => mode
+        let matrix = [
+            ((None, false, false, false), Normal),
+            ((None, true, false, false), Detailed),
+            ((None, false, true, false), Normal),
+            ((None, true, true, false), Normal),
+            ((None, false, false, true), Silent),
+            ((None, true, false, true), Silent),
+            ((Some(Minimal), false, false, false), Minimal),
+            ((Some(Minimal), true, false, false), Minimal),
+            ((Some(Minimal), false, true, false), Minimal),
+            ((Some(Detailed), false, true, false), Detailed),
+            ((Some(Silent), true, false, false), Silent),
+            ((Some(Normal), true, false, false), Normal),
+            ((Some(Detailed), false, false, true), Silent),
+        ];
+        for ((console, verbose, quiet, tui), mode) in matrix {
+            assert_eq!(
+                ConsoleMode::from_flags(console, verbose, quiet, tui),
+                mode,
+                "--console {:?} verbose {} quiet {} tui {}",
+                console,
+                verbose,
+                quiet,
+                tui
+            );
+        }
+    }
+
+    #[test]
+    fn each_mode_prints_its_own_lines() {
+        let layout = Layout::default();
+        assert_eq!(printed(ConsoleMode::Silent, &layout), "");
+        assert_eq!(printed(ConsoleMode::Minimal, &layout), "10.0.0.1:22\n");
+        assert_eq!(
+            printed(ConsoleMode::Normal, &layout),
+            "   22 open   SSH (OpenSSH 9.6)\n"
+        );
+        assert_eq!(
+            printed(ConsoleMode::Detailed, &layout),
+            "   22 | open | Service: SSH | Product: OpenSSH 9.6 | Latency: 3ms | Banner: SSH-2.0-OpenSSH_9.6\n\
+             \x20  23 | closed | Service: - | Latency: 0ms | Banner: -\n\
+             \x20  25 | filtered | Service: - | Latency: 0ms | Banner: -\n"
+        );
+
+        let layout = Layout {
+            show_target: true,
+            fields: Some(vec![Field::Port, Field::Status, Field::Latency]),
+        };
+        assert_eq!(
+            printed(ConsoleMode::Normal, &layout),
+            "10.0.0.1:22 open   SSH (OpenSSH 9.6)\n"
+        );
+        assert_eq!(
+            printed(ConsoleMode::Detailed, &layout),
+            "22 | open | 3\n23 | closed | 0\n25 | filtered | 0\n"
+        );
+        assert_eq!(printed(ConsoleMode::Minimal, &layout), "10.0.0.1:22\n");
+    }
+
+    #[test]
+    fn findings_follow_the_line_except_when_minimal() {
+        colored::control::set_override(false);
+        let mut r = results().remove(0);
+        r.findings.push(Finding {
+            id: "CVE-2024-6387".to_string(),
+            severity: Severity::High,
+            summary: "regreSSHion".to_string(),
+        });
+        let layout = Layout::default();
+        assert_eq!(
+            ConsoleMode::Normal.print_result(&r, &layout),
+            "   22 open   SSH (OpenSSH 9.6)\n          HIGH CVE-2024-6387 regreSSHion\n"
+        );
+        assert_eq!(
+            ConsoleMode::Minimal.print_result(&r, &layout),
+            "10.0.0.1:22\n"
+        );
+    }
+
+    #[test]
+    fn modes_parse_by_name() {
+        assert_eq!(" Minimal".parse(), Ok(ConsoleMode::Minimal));
+        for mode in ConsoleMode::ALL {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+        }
+        assert!("brief"
+            .parse::<ConsoleMode>()
+            .unwrap_err()
+            .starts_with("unknown console mode 'brief'"));
+    }
+}
@@ -0,0 +1,375 @@
+//! Cron schedules for `daemon --schedule`.
+//!
+//! A schedule has the five classic fields (minute, hour, day of month,
+//! month, day of week), or six with seconds in front as the `cron` crate
+//! writes them. Fields take `*`, numbers, ranges (`1-5`), lists (`1,15`)
+//! and steps (`*/15`, `10-50/20`); months and days of week also take
+//! English names (`jan`, `mon-fri`), and `7` is Sunday like `0`. The
+//! `@hourly`, `@daily` (`@midnight`), `@weekly`, `@monthly` and `@yearly`
+//! (`@annually`) shorthands are accepted. When both day fields are
+//! restricted, a day matching either one counts, as in Vixie cron.
+//!
+//! Times are matched on the clock of a [`Zone`]. A local time skipped by a
+//! daylight-saving change never fires; one repeated fires the first time.
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime,
+    TimeZone, Timelike, Utc,
+};
+use std::fmt;
+use std::str::FromStr;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+pub(crate) const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead a schedule is searched for its next time; `0 0 30 2 *`
+/// never comes.
+const HORIZON_YEARS: i32 = 8;
+
+/// The clock a schedule's times are read on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// The system time zone, as set by `TZ` or the system.
+    Local,
+    /// Coordinated Universal Time.
+    Utc,
+    /// A fixed offset from UTC, such as `+02:00`.
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local);
+        }
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(Zone::Utc);
+        }
+        let offset = || {
+            let (sign, rest) = match s.as_bytes().first()? {
+                b'+' => (1, &s[1..]),
+                b'-' => (-1, &s[1..]),
+                _ => return None,
+            };
+            let (hours, minutes) = match rest.split_once(':') {
+                Some((h, m)) => (h, m),
+                None if rest.len() == 4 => rest.split_at(2),
+                None => (rest, "0"),
+            };
+            let hours: i32 = hours.parse().ok().filter(|h| *h <= 14)?;
+            let minutes: i32 = minutes.parse().ok().filter(|m| *m < 60)?;
+            FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+        };
+        offset().map(Zone::Fixed).ok_or_else(|| {
+            format!(
+                "unknown time zone '{}' (expected local, UTC or an offset such as +02:00; \
+                 set TZ for a named zone)",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Zone::Local => f.write_str("local"),
+            Zone::Utc => f.write_str("UTC"),
+            Zone::Fixed(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+impl Zone {
+    /// `t` on this zone's clock.
+    pub(crate) fn local(&self, t: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => t.with_timezone(&Local).naive_local(),
+            Zone::Utc => t.naive_utc(),
+            Zone::Fixed(offset) => t.with_timezone(offset).naive_local(),
+        }
+    }
+
+    /// The instant this zone's clock reads `t`, the first of two when the
+    /// clock goes back; none when it skips `t`.
+    pub(crate) fn utc(&self, t: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let earliest = |r: LocalResult<DateTime<Utc>>| match r {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => Some(t),
+            LocalResult::None => None,
+        };
+        match self {
+            Zone::Local => earliest(Local.from_local_datetime(&t).map(|t| t.to_utc())),
+            Zone::Utc => Some(t.and_utc()),
+            Zone::Fixed(offset) => earliest(offset.from_local_datetime(&t).map(|t| t.to_utc())),
+        }
+    }
+}
+
+/// One field's allowed values as a bit set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Field {
+    pub(crate) bits: u64,
+    /// Written as `*` (or `*/1`), which matters for the day fields.
+    any: bool,
+}
+
+impl Field {
+    fn has(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+
+    pub(crate) fn parse(spec: &str, min: u32, max: u32, names: &[&str]) -> Result<Self, String> {
+        let value = |s: &str| -> Result<u32, String> {
+            let lower = s.to_ascii_lowercase();
+            if let Some(i) = names.iter().position(|n| *n == lower) {
+                return Ok(i as u32 + min);
+            }
+            match s.parse::<u32>() {
+                Ok(v) if (min..=max).contains(&v) => Ok(v),
+                _ => Err(format!("'{}' is not a value from {} to {}", s, min, max)),
+            }
+        };
+        let mut bits = 0u64;
+        for item in spec.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step
+                        .parse()
+                        .ok()
+                        .filter(|s| *s > 0)
+                        .ok_or_else(|| format!("'{}' is not a valid step", step))?;
+                    (range, step)
+                }
+                None => (item, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((a, b)) = range.split_once('-') {
+                (value(a)?, value(b)?)
+            } else if item.contains('/') {
+                (value(range)?, max)
+            } else {
+                let v = value(range)?;
+                (v, v)
+            };
+            if start > end {
+                return Err(format!("'{}' runs backwards", range));
+            }
+            for v in (start..=end).step_by(step as usize) {
+                bits |= 1 << v;
+            }
+        }
+        Ok(Field {
+            bits,
+            any: spec == "*" || spec == "*/1",
+        })
+    }
+}
+
+/// A parsed cron expression; see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    text: String,
+    seconds: Field,
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim();
+        let expanded = match text.to_ascii_lowercase().as_str() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            macro_ if macro_.starts_with('@') => {
+                return Err(format!("unknown schedule '{}'", text));
+            }
+            _ => text,
+        };
+        let mut fields: Vec<&str> = expanded.split_whitespace().collect();
+        match fields.len() {
+            5 => fields.insert(0, "0"),
+            6 => {}
+            n => {
+                return Err(format!(
+                    "'{}' has {} fields, expected 5 (minute hour day month weekday) or 6 \
+                     (with seconds first)",
+                    text, n
+                ))
+            }
+        }
+        let field = |i: usize, name: &str, min, max, names: &[&str]| {
+            Field::parse(fields[i], min, max, names)
+                .map_err(|e| format!("{} field of '{}': {}", name, text, e))
+        };
+        let mut weekdays = field(5, "weekday", 0, 7, &DAYS)?;
+        if weekdays.has(7) {
+            weekdays.bits = (weekdays.bits & !(1 << 7)) | 1;
+        }
+        Ok(Schedule {
+            text: text.to_string(),
+            seconds: field(0, "second", 0, 59, &[])?,
+            minutes: field(1, "minute", 0, 59, &[])?,
+            hours: field(2, "hour", 0, 23, &[])?,
+            days: field(3, "day", 1, 31, &[])?,
+            months: field(4, "month", 1, 12, &MONTHS)?,
+            weekdays,
+        })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Schedule {
+    /// The first time strictly after `after` that the schedule fires on
+    /// `zone`'s clock, if it fires within the next few years.
+    pub fn next_after(&self, after: DateTime<Utc>, zone: Zone) -> Option<DateTime<Utc>> {
+        let start = zone.local(after).with_nanosecond(0)? + Duration::seconds(1);
+        let horizon = start.year() + HORIZON_YEARS;
+        let mut t = start;
+        while t.year() <= horizon {
+            if !self.months.has(t.month()) {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !self.hours.has(t.hour()) {
+                t = t.date().and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+            } else if !self.minutes.has(t.minute()) {
+                t = t.date().and_hms_opt(t.hour(), t.minute(), 0)? + Duration::minutes(1);
+            } else if !self.seconds.has(t.second()) {
+                t += Duration::seconds(1);
+            } else if let Some(utc) = zone.utc(t).filter(|utc| *utc > after) {
+                return Some(utc);
+            } else {
+                t += Duration::seconds(1);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days.has(date.day());
+        let weekday = self.weekdays.has(date.weekday().num_days_from_sunday());
+        if self.days.any || self.weekdays.any {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn next(schedule: &str, after: &str) -> String {
+        let schedule: Schedule = schedule.parse().unwrap();
+        schedule
+            .next_after(at(after), Zone::Utc)
+            .map_or("never".to_string(), |t| t.to_rfc3339())
+    }
+
+    #[test]
+    fn schedules_fire_at_the_next_matching_time() {
+        assert_eq!(
+            next("0 2 * * *", "2024-03-09T14:05:07Z"),
+            "2024-03-10T02:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 2 * * *", "2024-03-10T02:00:00Z"),
+            "2024-03-11T02:00:00+00:00"
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2024-03-09T14:05:07Z"),
+            "2024-03-09T14:15:00+00:00"
+        );
+        assert_eq!(
+            next("*/10 * * * * *", "2024-03-09T14:05:07Z"),
+            "2024-03-09T14:05:10+00:00"
+        );
+        assert_eq!(
+            next("30 9 * * mon-fri", "2024-03-08T10:00:00Z"),
+            "2024-03-11T09:30:00+00:00"
+        );
+        assert_eq!(
+            next("0 0 1 jan *", "2024-03-09T00:00:00Z"),
+            "2025-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2024-03-01T00:00:00Z"),
+            "2028-02-29T00:00:00+00:00"
+        );
+        assert_eq!(next("0 0 30 2 *", "2024-03-01T00:00:00Z"), "never");
+        assert_eq!(
+            next("@weekly", "2024-03-09T14:05:07Z"),
+            "2024-03-10T00:00:00+00:00"
+        );
+        // Day of month or Sunday (7), whichever comes first.
+        assert_eq!(
+            next("0 0 15 * 7", "2024-03-11T00:00:00Z"),
+            "2024-03-15T00:00:00+00:00"
+        );
+        assert_eq!(
+            next("0 0 15 * 7", "2024-03-15T12:00:00Z"),
+            "2024-03-17T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn times_are_read_on_the_zone_clock() {
+        let schedule: Schedule = "0 2 * * *".parse().unwrap();
+        let zone: Zone = "+05:30".parse().unwrap();
+        assert_eq!(
+            schedule.next_after(at("2024-03-09T14:05:07Z"), zone),
+            Some(at("2024-03-09T20:30:00Z"))
+        );
+        assert_eq!("-0800".parse::<Zone>().unwrap().to_string(), "-08:00");
+        assert_eq!("utc".parse::<Zone>(), Ok(Zone::Utc));
+        assert!("Europe/Berlin".parse::<Zone>().is_err());
+    }
+
+    #[test]
+    fn malformed_schedules_are_rejected() {
+        for bad in [
+            "0 2 * *",
+            "60 * * * *",
+            "0 2 * * funday",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "@often",
+        ] {
+            assert!(bad.parse::<Schedule>().is_err(), "{}", bad);
+        }
+        let e = "0 25 * * *".parse::<Schedule>().unwrap_err();
+        assert_eq!(
+            e,
+            "hour field of '0 25 * * *': '25' is not a value from 0 to 23"
+        );
+    }
+}
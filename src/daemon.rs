@@ -0,0 +1,867 @@
+//! Scans on a cron schedule (`daemon`).
+//!
+//! The scan options come from a TOML file of `--long-option` names and
+//! values, read again on SIGHUP:
+//!
+//! ```toml
+//! target = ["10.0.0.0/24"]
+//! ports = "1-1024"
+//! json = "/var/lib/scans/{date}/nightly-{time}.json"
+//! fail_on = ["open:23"]
+//! quiet = true
+//! ```
+//!
+//! Strings and numbers are passed as the option's value, lists repeat the
+//! option and `true` passes a flag. A `[port_groups]` table defines groups
+//! for `ports`, one `--port-group` per entry:
+//!
+//! ```toml
+//! ports = "@web,@admin"
+//!
+//! [port_groups]
+//! admin = "8080-8090,9000"
+//! ```
+//! The report paths (`json`, `output`,
+//! `csv`, `ndjson`, `junit`, `output_dir`) are templates with `{date}`,
+//! `{time}` and `{target}`, filled in with each run's scheduled time.
+//!
+//! The [`Scheduler`] decides when runs start and what happens to a run
+//! that comes due while the previous one is still going; the [`Status`]
+//! of the daemon is written to a file after every change. [`run`] drives
+//! them, each scan a child process of the executable.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+use toml_edit::{Document, Item, Table, Value};
+use tracing::{error, info, warn};
+
+use crate::clock::{Clock, SystemClock};
+use crate::cron::{Schedule, Zone};
+use crate::import::{self, InputFormat};
+use crate::scanner::PortStatus;
+use crate::template::FilenameTemplate;
+
+/// Options whose values are report paths, rendered for each run.
+const REPORT_PATHS: [&str; 6] = ["json", "output", "csv", "ndjson", "junit", "output_dir"];
+
+/// Why a daemon config could not be loaded.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    #[error("Cannot read config {path}: {source}")]
+    Read {
+        /// The config path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is not TOML.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The config path.
+        path: String,
+        /// The underlying error.
+        source: toml_edit::TomlError,
+    },
+    /// The file is not what it should be, e.g. a jobs file without jobs.
+    #[error("{path}: {reason}")]
+    Invalid {
+        /// The config path.
+        path: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+    /// An option has a value that can't be passed on the command line.
+    #[error("{path}: line {line}: {key}: {reason}")]
+    Option {
+        /// The config path.
+        path: String,
+        /// The option.
+        key: String,
+        /// Line it is on, from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Setting {
+    Flag,
+    Values(Vec<String>),
+    Report(FilenameTemplate),
+}
+
+/// The scan options of a daemon config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanConfig {
+    options: Vec<(String, Setting)>,
+    target: String,
+}
+
+impl ScanConfig {
+    /// Reads and parses a config file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses a config from its TOML text; `name` appears in errors.
+    pub fn parse(name: &str, text: &str) -> Result<Self, ConfigError> {
+        let doc = Document::parse(text).map_err(|source| ConfigError::Parse {
+            path: name.to_string(),
+            source,
+        })?;
+        Self::from_table(name, text, &doc)
+    }
+
+    /// The config of the options in `table`, parsed from `text`, which
+    /// gives the line numbers of errors.
+    pub(crate) fn from_table(name: &str, text: &str, table: &Table) -> Result<Self, ConfigError> {
+        let mut options = Vec::new();
+        let mut target = String::new();
+        for (key, item) in table.iter() {
+            let option = key.replace('_', "-");
+            let error = |reason: &str| ConfigError::Option {
+                path: name.to_string(),
+                key: key.to_string(),
+                line: item
+                    .span()
+                    .map_or(1, |s| text[..s.start].matches('\n').count() + 1),
+                reason: reason.to_string(),
+            };
+            if option == "port-groups" {
+                let groups = item
+                    .as_table_like()
+                    .ok_or_else(|| error("expected a table of group names and ports"))?;
+                let mut definitions = Vec::new();
+                for (group, ports) in groups.iter() {
+                    let ports = ports
+                        .as_str()
+                        .ok_or_else(|| error(&format!("{}: expected a string of ports", group)))?;
+                    definitions.push(format!("{}={}", group, ports));
+                }
+                options.push(("port-group".to_string(), Setting::Values(definitions)));
+                continue;
+            }
+            let values = match item {
+                Item::Value(Value::Array(array)) => {
+                    array.iter().map(scalar).collect::<Option<Vec<_>>>()
+                }
+                Item::Value(value) => scalar(value).map(|v| vec![v]),
+                _ => None,
+            }
+            .ok_or_else(|| error("expected a string, number, boolean or list of them"))?;
+            if option == "target" {
+                target = values.join(",");
+            }
+            let setting = match item.as_bool() {
+                Some(true) => Setting::Flag,
+                Some(false) => continue,
+                None if REPORT_PATHS.contains(&key.replace('-', "_").as_str()) => {
+                    let [path] = &values[..] else {
+                        return Err(error("expected one path"));
+                    };
+                    let template: FilenameTemplate = path.parse().map_err(|e: String| error(&e))?;
+                    if path.contains("{scan_id}") {
+                        return Err(error("{scan_id} is not known before the scan starts"));
+                    }
+                    Setting::Report(template)
+                }
+                None => Setting::Values(values),
+            };
+            options.push((option, setting));
+        }
+        Ok(ScanConfig { options, target })
+    }
+
+    /// The command-line arguments of the run scheduled for `at`.
+    pub fn args(&self, at: DateTime<Utc>) -> Vec<String> {
+        let mut args = Vec::new();
+        for (option, setting) in &self.options {
+            let flag = format!("--{}", option);
+            match setting {
+                Setting::Flag => args.push(flag),
+                Setting::Values(values) => {
+                    for value in values {
+                        args.push(flag.clone());
+                        args.push(value.clone());
+                    }
+                }
+                Setting::Report(template) => {
+                    args.push(flag);
+                    args.push(template.render(&self.target, "", at));
+                }
+            }
+        }
+        args
+    }
+
+    /// Whether the config sets `option`, given by its long name such as
+    /// `blocklist`.
+    pub fn has(&self, option: &str) -> bool {
+        self.options.iter().any(|(name, _)| name == option)
+    }
+
+    /// Where the run scheduled for `at` saves its JSON report, if it does.
+    pub fn json_report(&self, at: DateTime<Utc>) -> Option<PathBuf> {
+        self.options
+            .iter()
+            .find_map(|(option, setting)| match setting {
+                Setting::Report(template) if option == "json" => {
+                    Some(PathBuf::from(template.render(&self.target, "", at)))
+                }
+                _ => None,
+            })
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        Value::Boolean(b) => Some(b.value().to_string()),
+        _ => None,
+    }
+}
+
+/// The open ports of a JSON report, as `target:port`.
+pub fn open_ports(report: &Path) -> Result<BTreeSet<String>, import::ImportError> {
+    let summary = import::load(report, Some(InputFormat::Json))?;
+    Ok(summary
+        .results
+        .iter()
+        .filter(|r| r.status == PortStatus::Open)
+        .map(|r| match r.target {
+            std::net::IpAddr::V6(ip) => format!("[{}]:{}", ip, r.port),
+            ip => format!("{}:{}", ip, r.port),
+        })
+        .collect())
+}
+
+/// What happens to a run that comes due while the previous scan is still
+/// going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    /// It is left out.
+    Skip,
+    /// It starts as soon as the previous scan ends; at most one waits.
+    Queue,
+}
+
+impl FromStr for Overlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "skip" => Ok(Overlap::Skip),
+            "queue" => Ok(Overlap::Queue),
+            _ => Err(format!(
+                "unknown overlap '{}' (expected skip or queue)",
+                s.trim()
+            )),
+        }
+    }
+}
+
+/// What to do about a run that has come due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Due {
+    /// Start the scan now.
+    Start(DateTime<Utc>),
+    /// The previous scan is still going: the run waits for it.
+    Queued(DateTime<Utc>),
+    /// The previous scan is still going: the run is left out.
+    Skipped(DateTime<Utc>),
+}
+
+/// When the runs of a schedule start.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    schedule: Schedule,
+    zone: Zone,
+    overlap: Overlap,
+    next: Option<DateTime<Utc>>,
+    queued: Option<DateTime<Utc>>,
+}
+
+impl Scheduler {
+    /// A scheduler whose first run is the schedule's first time after
+    /// `now`.
+    pub fn new(schedule: Schedule, zone: Zone, overlap: Overlap, now: DateTime<Utc>) -> Self {
+        let next = schedule.next_after(now, zone);
+        Scheduler {
+            schedule,
+            zone,
+            overlap,
+            next,
+            queued: None,
+        }
+    }
+
+    /// When the next run comes due; `None` when the schedule never fires
+    /// again.
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.next
+    }
+
+    /// The schedule.
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// The zone the schedule is read in.
+    pub fn zone(&self) -> Zone {
+        self.zone
+    }
+
+    /// Handles the run that came due at [`Scheduler::next_run`], called at
+    /// `now`. Runs missed while the daemon was held up (a suspended
+    /// machine, a long queue) collapse into this one.
+    pub fn due(&mut self, now: DateTime<Utc>, running: bool) -> Option<Due> {
+        let scheduled = self.next.filter(|next| *next <= now)?;
+        self.next = self.schedule.next_after(now, self.zone);
+        if !running {
+            Some(Due::Start(scheduled))
+        } else if self.overlap == Overlap::Queue && self.queued.is_none() {
+            self.queued = Some(scheduled);
+            Some(Due::Queued(scheduled))
+        } else {
+            Some(Due::Skipped(scheduled))
+        }
+    }
+
+    /// Called when a scan ends: the queued run to start now, if any.
+    pub fn finished(&mut self) -> Option<DateTime<Utc>> {
+        self.queued.take()
+    }
+}
+
+/// One finished run, as the status file reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    /// When the run was due.
+    pub scheduled: DateTime<Utc>,
+    /// When the scan started.
+    pub started: DateTime<Utc>,
+    /// When the scan ended.
+    pub finished: DateTime<Utc>,
+    /// The scan's exit code; missing if it was killed by a signal.
+    pub exit_code: Option<i32>,
+    /// The JSON report, if the config writes one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<PathBuf>,
+    /// Open ports in the report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_ports: Option<usize>,
+    /// Ports open now that weren't in the previous run's report.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub opened: Vec<String>,
+    /// Ports open in the previous run's report that aren't now.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub closed: Vec<String>,
+}
+
+/// What the daemon is doing, written to `--status-file`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    /// The cron expression.
+    pub schedule: String,
+    /// The zone it is read in.
+    pub timezone: String,
+    /// When the next run is due.
+    pub next_run: Option<DateTime<Utc>>,
+    /// When the running scan started, if one is.
+    pub running_since: Option<DateTime<Utc>>,
+    /// Scans run so far.
+    pub runs: u64,
+    /// Runs left out because the previous scan was still going.
+    pub skipped: u64,
+    /// The last finished run.
+    pub last_run: Option<Run>,
+}
+
+impl Status {
+    /// The status before the first run.
+    pub fn new(scheduler: &Scheduler) -> Self {
+        Status {
+            schedule: scheduler.schedule().to_string(),
+            timezone: scheduler.zone().to_string(),
+            next_run: scheduler.next_run(),
+            running_since: None,
+            runs: 0,
+            skipped: 0,
+            last_run: None,
+        }
+    }
+
+    /// Replaces the file at `path` with the status, so readers never see
+    /// half of it.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".tmp");
+        let partial = PathBuf::from(partial);
+        let mut json = serde_json::to_vec_pretty(self)?;
+        json.push(b'\n');
+        std::fs::write(&partial, json)?;
+        std::fs::rename(&partial, path)
+    }
+}
+
+/// Why the daemon, or a batch, stopped short.
+#[derive(Error, Debug)]
+pub enum RunError {
+    /// A config or jobs file is unusable.
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// A scan could not be started, or a file could not be written.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Checks that `scan` takes the options of a config: what the command
+/// line parser objects to, if anything.
+pub type CheckOptions = dyn Fn(Vec<String>) -> Result<(), String> + Send + Sync;
+
+/// How the daemon runs.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The config of the scan, read again on SIGHUP.
+    pub config: PathBuf,
+    /// When to scan.
+    pub schedule: Schedule,
+    /// The clock the schedule is read on.
+    pub timezone: Zone,
+    /// What happens to a run due while the previous scan is still going.
+    pub overlap: Overlap,
+    /// Where the [`Status`] is kept.
+    pub status_file: Option<PathBuf>,
+    /// Scans to run before stopping.
+    pub max_runs: Option<u64>,
+    /// Whether a config without a blocklist is refused.
+    pub require_blocklist: bool,
+}
+
+/// Loads a daemon config and checks that `scan` takes its options and, with
+/// `require_blocklist`, that it sets a blocklist.
+pub fn load_config(
+    path: &Path,
+    require_blocklist: bool,
+    check: &CheckOptions,
+) -> Result<ScanConfig, ConfigError> {
+    let config = ScanConfig::load(path)?;
+    let invalid = |reason: String| ConfigError::Invalid {
+        path: path.display().to_string(),
+        reason,
+    };
+    if require_blocklist && !config.has("blocklist") {
+        return Err(invalid(
+            "no blocklist, which --require-blocklist asks for".to_string(),
+        ));
+    }
+    check(config.args(Utc::now())).map_err(invalid)?;
+    Ok(config)
+}
+
+/// A scheduled scan in progress.
+struct ScheduledScan {
+    child: tokio::process::Child,
+    scheduled: DateTime<Utc>,
+    started: DateTime<Utc>,
+    report: Option<PathBuf>,
+    /// Whether the scan leaves out its report when nothing changed.
+    write_on_change: bool,
+}
+
+/// What the daemon was woken by.
+enum Wakeup {
+    Due,
+    Exited(std::io::Result<std::process::ExitStatus>),
+    Reload,
+    Stop,
+}
+
+/// Runs the config's scan on the schedule until SIGTERM or Ctrl-C, each
+/// scan a `scan` child process of `exe`.
+pub async fn run(options: Options, exe: &Path, check: &CheckOptions) -> Result<(), RunError> {
+    let clock = SystemClock;
+    let mut config = load_config(&options.config, options.require_blocklist, check)?;
+    let mut scheduler = Scheduler::new(
+        options.schedule.clone(),
+        options.timezone,
+        options.overlap,
+        clock.now(),
+    );
+    let mut status = Status::new(&scheduler);
+    let mut signals = Signals::new()?;
+    let mut running: Option<ScheduledScan> = None;
+    let mut previous: Option<BTreeSet<String>> = None;
+    info!(
+        "Scanning on schedule \"{}\" ({}), next at {}",
+        options.schedule,
+        options.timezone,
+        scheduler
+            .next_run()
+            .map_or("never".to_string(), |t| t.to_rfc3339())
+    );
+
+    loop {
+        status.next_run = scheduler.next_run();
+        status.running_since = running.as_ref().map(|r| r.started);
+        if let Some(ref path) = options.status_file {
+            if let Err(e) = status.write(path) {
+                warn!("Cannot write the status file {}: {}", path.display(), e);
+            }
+        }
+        let runs_left = options.max_runs.is_none_or(|max| status.runs < max);
+        if running.is_none() && (!runs_left || scheduler.next_run().is_none()) {
+            if runs_left {
+                info!("The schedule does not fire again");
+            }
+            return Ok(());
+        }
+
+        let wait = scheduler
+            .next_run()
+            .map(|next| (next - clock.now()).to_std().unwrap_or_default());
+        let wakeup = tokio::select! {
+            _ = tokio::time::sleep(wait.unwrap_or_default()), if wait.is_some() && runs_left => Wakeup::Due,
+            exited = async { running.as_mut().expect("a scan is running").child.wait().await },
+                if running.is_some() => Wakeup::Exited(exited),
+            wakeup = signals.next() => wakeup,
+        };
+        match wakeup {
+            Wakeup::Due => match scheduler.due(clock.now(), running.is_some()) {
+                Some(Due::Start(scheduled)) => {
+                    running = Some(start_scan(exe, &config, scheduled, clock.now())?);
+                    status.runs += 1;
+                }
+                Some(Due::Queued(scheduled)) => {
+                    info!(
+                        "The previous scan is still running, the {} run waits for it",
+                        scheduled.to_rfc3339()
+                    );
+                }
+                Some(Due::Skipped(scheduled)) => {
+                    warn!(
+                        "The previous scan is still running, skipping the {} run",
+                        scheduled.to_rfc3339()
+                    );
+                    status.skipped += 1;
+                }
+                None => {}
+            },
+            Wakeup::Exited(exited) => {
+                let scan = running.take().expect("a scan was running");
+                let run = finish_scan(scan, exited, clock.now(), &mut previous);
+                status.last_run = Some(run);
+                if let Some(scheduled) = scheduler.finished() {
+                    if options.max_runs.is_none_or(|max| status.runs < max) {
+                        running = Some(start_scan(exe, &config, scheduled, clock.now())?);
+                        status.runs += 1;
+                    }
+                }
+            }
+            Wakeup::Reload => {
+                match load_config(&options.config, options.require_blocklist, check) {
+                    Ok(reloaded) => {
+                        config = reloaded;
+                        info!("Reloaded {}", options.config.display());
+                    }
+                    Err(e) => error!("Keeping the previous config: {}", e),
+                }
+            }
+            Wakeup::Stop => {
+                info!("Shutting down");
+                if let Some(mut scan) = running.take() {
+                    info!("Waiting for the running scan to save its partial results");
+                    interrupt(&mut scan.child);
+                    let exited = scan.child.wait().await;
+                    status.last_run = Some(finish_scan(scan, exited, clock.now(), &mut previous));
+                }
+                status.next_run = None;
+                status.running_since = None;
+                if let Some(ref path) = options.status_file {
+                    if let Err(e) = status.write(path) {
+                        warn!("Cannot write the status file {}: {}", path.display(), e);
+                    }
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn start_scan(
+    exe: &Path,
+    config: &ScanConfig,
+    scheduled: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> std::io::Result<ScheduledScan> {
+    info!("Starting the scan scheduled for {}", scheduled.to_rfc3339());
+    let child = tokio::process::Command::new(exe)
+        .arg("scan")
+        .args(config.args(scheduled))
+        .kill_on_drop(true)
+        .spawn()?;
+    Ok(ScheduledScan {
+        child,
+        scheduled,
+        started: now,
+        report: config.json_report(scheduled),
+        write_on_change: config.has("write-on-change"),
+    })
+}
+
+/// Records a finished scan, and compares its open ports with the previous
+/// report's.
+fn finish_scan(
+    scan: ScheduledScan,
+    exited: std::io::Result<std::process::ExitStatus>,
+    now: DateTime<Utc>,
+    previous: &mut Option<BTreeSet<String>>,
+) -> Run {
+    let exit_code = match exited {
+        Ok(status) => status.code(),
+        Err(e) => {
+            error!("Lost track of the scan: {}", e);
+            None
+        }
+    };
+    let mut run = Run {
+        scheduled: scan.scheduled,
+        started: scan.started,
+        finished: now,
+        exit_code,
+        report: scan.report,
+        open_ports: None,
+        opened: Vec::new(),
+        closed: Vec::new(),
+    };
+    // A scan that found what the last one did writes no report.
+    let skipped = scan.write_on_change
+        && exit_code.is_some()
+        && run.report.as_deref().is_some_and(|report| !report.exists());
+    if skipped {
+        run.report = None;
+        run.open_ports = previous.as_ref().map(BTreeSet::len);
+    }
+    let open = run.report.as_deref().and_then(|report| {
+        open_ports(report)
+            .map_err(|e| warn!("Cannot compare the report with the last one: {}", e))
+            .ok()
+    });
+    if let Some(open) = open {
+        if let Some(before) = previous.as_ref() {
+            run.opened = open.difference(before).cloned().collect();
+            run.closed = before.difference(&open).cloned().collect();
+            if !run.opened.is_empty() || !run.closed.is_empty() {
+                warn!(
+                    "Open ports changed since the last run: opened [{}], closed [{}]",
+                    run.opened.join(", "),
+                    run.closed.join(", ")
+                );
+            }
+        }
+        run.open_ports = Some(open.len());
+        *previous = Some(open);
+    }
+    info!(
+        "The scan scheduled for {} ended with exit code {}",
+        run.scheduled.to_rfc3339(),
+        run.exit_code
+            .map_or("-".to_string(), |code| code.to_string())
+    );
+    run
+}
+
+/// Asks a scan to stop the way Ctrl-C does, so it saves partial results.
+fn interrupt(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: kill has no memory-safety preconditions.
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGINT);
+        }
+        return;
+    }
+    let _ = child.start_kill();
+}
+
+/// The signals the daemon acts on.
+struct Signals {
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            Ok(Signals {
+                hangup: signal(SignalKind::hangup())?,
+                terminate: signal(SignalKind::terminate())?,
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(Signals {})
+    }
+
+    /// SIGHUP reloads; SIGTERM and Ctrl-C stop.
+    async fn next(&mut self) -> Wakeup {
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                _ = self.hangup.recv() => Wakeup::Reload,
+                _ = self.terminate.recv() => Wakeup::Stop,
+                _ = tokio::signal::ctrl_c() => Wakeup::Stop,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            Wakeup::Stop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, FixedClock};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn configs_become_scan_arguments() {
+        let config = ScanConfig::parse(
+            "nightly.toml",
+            r#"
+target = ["10.0.0.0/24", "10.0.1.5"]
+ports = "22,80"
+timeout_ms = 500
+quiet = true
+tui = false
+json = "/srv/{date}/{target}-{time}.json"
+"#,
+        )
+        .unwrap();
+        let run = at("2024-03-10T02:00:00Z");
+        assert_eq!(
+            config.args(run),
+            [
+                "--target",
+                "10.0.0.0/24",
+                "--target",
+                "10.0.1.5",
+                "--ports",
+                "22,80",
+                "--timeout-ms",
+                "500",
+                "--quiet",
+                "--json",
+                "/srv/2024-03-10/10.0.0.0_24,10.0.1.5-020000.json",
+            ]
+        );
+        assert_eq!(
+            config.json_report(run),
+            Some(PathBuf::from(
+                "/srv/2024-03-10/10.0.0.0_24,10.0.1.5-020000.json"
+            ))
+        );
+
+        let e = ScanConfig::parse("x.toml", "target = \"a\"\n[ports]\nlist = 1\n").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "x.toml: line 2: ports: expected a string, number, boolean or list of them"
+        );
+        let e = ScanConfig::parse("x.toml", "json = \"{scan_id}.json\"").unwrap_err();
+        assert!(e.to_string().contains("{scan_id} is not known"), "{}", e);
+    }
+
+    #[test]
+    fn port_groups_become_port_group_options() {
+        let config = ScanConfig::parse(
+            "groups.toml",
+            r#"
+ports = "@admin,@web"
+
+[port_groups]
+admin = "8080-8090,@legacy"
+legacy = "9999"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.args(at("2024-03-10T02:00:00Z")),
+            [
+                "--ports",
+                "@admin,@web",
+                "--port-group",
+                "admin=8080-8090,@legacy",
+                "--port-group",
+                "legacy=9999",
+            ]
+        );
+        assert!(config.has("port-group"));
+        assert!(!config.has("blocklist"));
+        let e = ScanConfig::parse("x.toml", "[port_groups]\nadmin = 8080\n").unwrap_err();
+        assert!(
+            e.to_string().contains("admin: expected a string of ports"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn runs_due_during_a_scan_are_skipped_or_queued() {
+        let mut clock = FixedClock(at("2024-03-09T14:05:07Z"));
+        let schedule: Schedule = "*/10 * * * *".parse().unwrap();
+        let mut skip = Scheduler::new(schedule.clone(), Zone::Utc, Overlap::Skip, clock.now());
+        let mut queue = Scheduler::new(schedule, Zone::Utc, Overlap::Queue, clock.now());
+        assert_eq!(skip.next_run(), Some(at("2024-03-09T14:10:00Z")));
+        assert_eq!(skip.due(clock.now(), false), None, "not due yet");
+
+        clock = FixedClock(at("2024-03-09T14:10:00Z"));
+        let first = Due::Start(at("2024-03-09T14:10:00Z"));
+        assert_eq!(skip.due(clock.now(), false), Some(first));
+        assert_eq!(queue.due(clock.now(), false), Some(first));
+
+        // The scan is still going at 14:20 and 14:30.
+        clock = FixedClock(at("2024-03-09T14:20:00Z"));
+        let second = at("2024-03-09T14:20:00Z");
+        assert_eq!(skip.due(clock.now(), true), Some(Due::Skipped(second)));
+        assert_eq!(queue.due(clock.now(), true), Some(Due::Queued(second)));
+        clock = FixedClock(at("2024-03-09T14:30:00Z"));
+        let third = at("2024-03-09T14:30:00Z");
+        assert_eq!(
+            queue.due(clock.now(), true),
+            Some(Due::Skipped(third)),
+            "one waits at most"
+        );
+        assert_eq!(queue.finished(), Some(second));
+        assert_eq!(queue.finished(), None);
+
+        // A daemon held up for an hour runs once, then keeps to the schedule.
+        clock = FixedClock(at("2024-03-09T15:34:00Z"));
+        assert_eq!(
+            skip.due(clock.now(), false),
+            Some(Due::Start(at("2024-03-09T14:30:00Z")))
+        );
+        assert_eq!(skip.next_run(), Some(at("2024-03-09T15:40:00Z")));
+    }
+}
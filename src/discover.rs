@@ -0,0 +1,298 @@
+//! Local service discovery (`discover`, `--local-discovery`): asks the local
+//! network over mDNS and SSDP what it offers, listens for the devices that
+//! announce themselves, and collects what they say about themselves.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::debug;
+
+use crate::{mdns, ssdp};
+
+/// What a device on the local network said about itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    /// Friendly name, such as `Living Room TV`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Maker and model, such as `Chromecast` or `Signify BSB002`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The UPnP `SERVER` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Advertised mDNS service types and UPnP device and service types.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<String>,
+    /// Where the UPnP device description is.
+    #[serde(skip)]
+    location: Option<String>,
+}
+
+impl Device {
+    fn add_service(&mut self, service: String) {
+        if !self.services.contains(&service) {
+            self.services.push(service);
+        }
+    }
+
+    fn add_mdns(&mut self, found: mdns::Announcement) {
+        let instance = found.instances.into_iter().next();
+        self.name = self.name.take().or(found.name).or(instance);
+        self.model = self.model.take().or(found.model);
+        found.services.into_iter().for_each(|s| self.add_service(s));
+    }
+
+    fn add_ssdp(&mut self, found: ssdp::Announcement) {
+        self.server = self.server.take().or(found.server);
+        self.location = self.location.take().or(found.location);
+        found.service.into_iter().for_each(|s| self.add_service(s));
+    }
+}
+
+impl fmt::Display for Device {
+    /// `Living Room TV (Chromecast) [_googlecast._tcp]`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        parts.extend(self.name.clone());
+        parts.extend(self.model.as_ref().map(|m| format!("({})", m)));
+        if !self.services.is_empty() {
+            parts.push(format!("[{}]", self.services.join(", ")));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+/// Why discovery could not run at all.
+#[derive(Error, Debug)]
+#[error("Local discovery failed: {0}")]
+pub struct DiscoverError(#[from] io::Error);
+
+/// Queries and listens for `window`, then returns every device heard from,
+/// by address.
+pub async fn discover(window: Duration) -> Result<BTreeMap<IpAddr, Device>, DiscoverError> {
+    let query = multicast::sender()?;
+    // Unsolicited announcements only reach the well-known ports, which
+    // another responder on this machine may hold; queries work without them.
+    let listen = |group| {
+        multicast::listener(group)
+            .map_err(|e| debug!("Not listening on {}: {}", group, e))
+            .ok()
+    };
+    let mdns_listener = listen(mdns::GROUP);
+    let ssdp_listener = listen(ssdp::GROUP);
+
+    let mx = window.as_secs().saturating_sub(1);
+    let deadline = tokio::time::Instant::now() + window;
+    let resend = tokio::time::Instant::now() + window / 2;
+    let mut resent = false;
+    let mut devices: BTreeMap<IpAddr, Device> = BTreeMap::new();
+    let mut asked: HashSet<String> = HashSet::new();
+    send_queries(&query, &[mdns::SERVICES], mx).await;
+
+    let mut buf = [[0u8; 9000]; 3];
+    let [query_buf, mdns_buf, ssdp_buf] = &mut buf;
+    loop {
+        let (n, from) = tokio::select! {
+            r = query.recv_from(query_buf) => (r, 0),
+            r = recv(&mdns_listener, mdns_buf) => (r, 1),
+            r = recv(&ssdp_listener, ssdp_buf) => (r, 2),
+            _ = tokio::time::sleep_until(resend), if !resent => {
+                resent = true;
+                send_queries(&query, &[mdns::SERVICES], mx).await;
+                continue;
+            }
+            _ = tokio::time::sleep_until(deadline) => break,
+        };
+        let Ok((n, sender)) = n else {
+            continue;
+        };
+        let datagram = &[&query_buf[..], &mdns_buf[..], &ssdp_buf[..]][from][..n];
+        let device = devices.entry(sender.ip()).or_default();
+        if let Some(found) = ssdp::parse(datagram) {
+            device.add_ssdp(found);
+        } else if let Some(found) = mdns::parse(datagram) {
+            let new: Vec<String> = found
+                .services
+                .iter()
+                .map(|s| format!("{}.local.", s))
+                .filter(|s| asked.insert(s.clone()))
+                .collect();
+            device.add_mdns(found);
+            if !new.is_empty() {
+                let names: Vec<&str> = new.iter().map(String::as_str).collect();
+                let _ = query.send_to(&mdns::query(&names), mdns::GROUP).await;
+            }
+        }
+        if *device == Device::default() {
+            devices.remove(&sender.ip());
+        }
+    }
+
+    for (address, device) in &mut devices {
+        let Some(location) = device.location.clone() else {
+            continue;
+        };
+        if let Some(description) = describe(*address, &location).await {
+            device.name = description.name.or(device.name.take());
+            device.model = device.model.take().or(description.model);
+        }
+    }
+    Ok(devices)
+}
+
+async fn send_queries(socket: &UdpSocket, mdns_names: &[&str], mx: u64) {
+    for (datagram, group) in [
+        (mdns::query(mdns_names), mdns::GROUP),
+        (ssdp::search(mx), ssdp::GROUP),
+    ] {
+        if let Err(e) = socket.send_to(&datagram, group).await {
+            debug!("Cannot query {}: {}", group, e);
+        }
+    }
+}
+
+async fn recv(socket: &Option<UdpSocket>, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Fetches the UPnP device description at `location`, but only from the
+/// device that announced it.
+async fn describe(address: IpAddr, location: &str) -> Option<ssdp::Description> {
+    let (host, port, path) = ssdp::split_location(location)?;
+    if host.parse::<IpAddr>().ok()? != address {
+        debug!(
+            "{} points to a description elsewhere: {}",
+            address, location
+        );
+        return None;
+    }
+    let fetch = async {
+        let mut stream = TcpStream::connect((address, port)).await.ok()?;
+        let request = format!("GET {} HTTP/1.0\r\nHost: {}:{}\r\n\r\n", path, host, port);
+        stream.write_all(request.as_bytes()).await.ok()?;
+        let mut response = Vec::new();
+        stream
+            .take(256 * 1024)
+            .read_to_end(&mut response)
+            .await
+            .ok()?;
+        let response = String::from_utf8_lossy(&response);
+        let (_, xml) = response.split_once("\r\n\r\n")?;
+        Some(ssdp::parse_description(xml))
+    };
+    tokio::time::timeout(Duration::from_secs(2), fetch)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// The multicast sockets, whose setup differs per operating system.
+mod multicast {
+    use socket2::{Domain, Protocol, Socket, Type};
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use tokio::net::UdpSocket;
+
+    /// A socket on an ephemeral port for sending queries and receiving the
+    /// answers sent straight back to it.
+    pub fn sender() -> io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        // SSDP asks for a TTL of 2; mDNS never leaves the link anyway.
+        socket.set_multicast_ttl_v4(2)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+        into_tokio(socket)
+    }
+
+    /// A socket receiving what is sent to `group`, next to any other program
+    /// listening there.
+    pub fn listener(group: SocketAddr) -> io::Result<UdpSocket> {
+        let SocketAddr::V4(group) = group else {
+            return Err(io::ErrorKind::Unsupported.into());
+        };
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        // The BSDs and macOS share a port only with SO_REUSEPORT; Linux
+        // accepts either, Windows has only SO_REUSEADDR.
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+        // Binding the group address filters other traffic on Unix; Windows
+        // can only bind an interface address.
+        #[cfg(unix)]
+        let bind = SocketAddr::from((*group.ip(), group.port()));
+        #[cfg(not(unix))]
+        let bind = SocketAddr::from((Ipv4Addr::UNSPECIFIED, group.port()));
+        socket.bind(&bind.into())?;
+        socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        into_tokio(socket)
+    }
+
+    fn into_tokio(socket: Socket) -> io::Result<UdpSocket> {
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_merge_what_each_protocol_says() {
+        let mut device = Device::default();
+        device.add_mdns(mdns::Announcement {
+            services: vec!["_googlecast._tcp".into()],
+            instances: vec!["Chromecast-1a2b".into()],
+            name: Some("Living Room TV".into()),
+            model: Some("Chromecast".into()),
+        });
+        device.add_ssdp(ssdp::Announcement {
+            service: Some("urn:dial-multiscreen-org:service:dial:1".into()),
+            server: Some("Linux/3.8.13+, UPnP/1.0, Portable SDK for UPnP devices/1.6.18".into()),
+            location: Some("http://192.168.1.40:8008/ssdp/device-desc.xml".into()),
+        });
+        device.add_mdns(mdns::Announcement {
+            services: vec!["_googlecast._tcp".into()],
+            ..Default::default()
+        });
+        assert_eq!(
+            device.to_string(),
+            "Living Room TV (Chromecast) [_googlecast._tcp, urn:dial-multiscreen-org:service:dial:1]"
+        );
+        let json = serde_json::to_value(&device).unwrap();
+        assert!(json["server"]
+            .as_str()
+            .unwrap()
+            .starts_with("Linux/3.8.13+"));
+        assert!(json.get("location").is_none());
+    }
+
+    #[test]
+    fn instance_names_stand_in_for_missing_friendly_names() {
+        let mut device = Device::default();
+        device.add_mdns(mdns::Announcement {
+            services: vec!["_ipp._tcp".into()],
+            instances: vec!["Office Printer".into()],
+            ..Default::default()
+        });
+        assert_eq!(device.to_string(), "Office Printer [_ipp._tcp]");
+    }
+
+    #[tokio::test]
+    async fn descriptions_come_only_from_the_announcing_device() {
+        let elsewhere = "http://192.0.2.99:80/desc.xml";
+        assert_eq!(
+            describe("127.0.0.1".parse().unwrap(), elsewhere).await,
+            None
+        );
+    }
+}
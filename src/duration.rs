@@ -0,0 +1,141 @@
+//! Durations on the command line: `800ms`, `1.5s`, `2m`, or a bare number
+//! in the unit the flag has always taken.
+
+use std::time::Duration;
+use thiserror::Error;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Units a duration may carry, with their length in nanoseconds.
+const UNITS: &[(&str, u128)] = &[
+    ("ms", 1_000_000),
+    ("s", NANOS_PER_SEC),
+    ("m", 60 * NANOS_PER_SEC),
+    ("h", 3600 * NANOS_PER_SEC),
+];
+
+/// A duration that could not be parsed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DurationError {
+    /// Not a number followed by an optional unit.
+    #[error("invalid duration {0:?}; expected a number with an optional unit: ms, s, m or h (e.g. 800ms, 1.5s, 2m)")]
+    Invalid(String),
+    /// A unit other than `ms`, `s`, `m` and `h`.
+    #[error("unknown unit {unit:?} in {value:?}; expected ms, s, m or h (e.g. 800ms, 1.5s, 2m)")]
+    UnknownUnit {
+        /// The whole value.
+        value: String,
+        /// The unit it carried.
+        unit: String,
+    },
+    /// Longer than a [`Duration`] can hold.
+    #[error("duration {0:?} is too long")]
+    Overflow(String),
+}
+
+/// Parses a duration whose bare numbers are milliseconds, like `--timeout`.
+pub fn parse_millis(s: &str) -> Result<Duration, DurationError> {
+    parse(s, "ms")
+}
+
+/// Parses a duration whose bare numbers are seconds, like `--max-scan-time`.
+pub fn parse_secs(s: &str) -> Result<Duration, DurationError> {
+    parse(s, "s")
+}
+
+/// Parses `s`, reading a number without a unit as `bare_unit`.
+pub fn parse(s: &str, bare_unit: &str) -> Result<Duration, DurationError> {
+    let value = s.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let unit = match unit.trim() {
+        "" => bare_unit,
+        unit => unit,
+    };
+    let Some(&(_, unit_nanos)) = UNITS.iter().find(|(name, _)| *name == unit) else {
+        return Err(DurationError::UnknownUnit {
+            value: value.to_string(),
+            unit: unit.to_string(),
+        });
+    };
+    let invalid = || DurationError::Invalid(value.to_string());
+    let overflow = || DurationError::Overflow(value.to_string());
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() && fraction.is_empty() || fraction.contains('.') {
+        return Err(invalid());
+    }
+    let whole: u128 = match whole {
+        "" => 0,
+        digits => digits.parse().map_err(|_| overflow())?,
+    };
+    let mut nanos = whole.checked_mul(unit_nanos).ok_or_else(overflow)?;
+    // Digits past nanosecond precision are dropped.
+    let mut scale = unit_nanos;
+    for digit in fraction.bytes() {
+        scale /= 10;
+        nanos = nanos.saturating_add(u128::from(digit - b'0') * scale);
+    }
+    let secs = u64::try_from(nanos / NANOS_PER_SEC).map_err(|_| overflow())?;
+    Ok(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_units_fractions_and_bare_numbers() {
+        assert_eq!(parse_millis("800"), Ok(Duration::from_millis(800)));
+        assert_eq!(parse_millis("800ms"), Ok(Duration::from_millis(800)));
+        assert_eq!(parse_millis("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_millis(" 2 m "), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_millis("0.25"), Ok(Duration::from_micros(250)));
+        assert_eq!(parse_millis(".5s"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_secs("60"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_secs("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_secs("0.000000001"), Ok(Duration::from_nanos(1)));
+        assert_eq!(parse_secs("0.0000000019"), Ok(Duration::from_nanos(1)));
+    }
+
+    #[test]
+    fn zero_is_a_duration() {
+        assert_eq!(parse_millis("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_secs("0.0s"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn bad_durations_say_what_is_accepted() {
+        for bad in ["", "ms", ".", "1.2.3s", "-5s", "1e3"] {
+            assert!(parse_millis(bad).is_err(), "{:?}", bad);
+        }
+        let err = parse_millis("5 seconds").unwrap_err();
+        assert_eq!(
+            err,
+            DurationError::UnknownUnit {
+                value: "5 seconds".into(),
+                unit: "seconds".into()
+            }
+        );
+        assert!(err.to_string().contains("ms, s, m or h"));
+    }
+
+    #[test]
+    fn overlong_durations_overflow() {
+        let max_secs = u64::MAX.to_string();
+        assert_eq!(parse_secs(&max_secs), Ok(Duration::from_secs(u64::MAX)));
+        assert!(matches!(
+            parse_secs(&format!("{}0", max_secs)),
+            Err(DurationError::Overflow(_))
+        ));
+        assert!(matches!(
+            parse_secs(&format!("{}h", u64::MAX)),
+            Err(DurationError::Overflow(_))
+        ));
+        assert!(matches!(
+            parse_secs(&"9".repeat(60)),
+            Err(DurationError::Overflow(_))
+        ));
+    }
+}
@@ -0,0 +1,601 @@
+//! Mailing the report to `--email-to` through an SMTP relay.
+//!
+//! The client speaks just enough SMTP to hand one message to a relay:
+//! EHLO, optionally STARTTLS and AUTH, then one MAIL/RCPT/DATA
+//! transaction. The message is the text report with the JSON report
+//! attached.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::io;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, error, info, warn};
+
+use crate::connector::Connection;
+use crate::fields::Field;
+use crate::output::render_text;
+use crate::scanner::ScanSummary;
+use crate::tls;
+
+/// Default for `--email-from`.
+pub const DEFAULT_FROM: &str = "port-scanner@localhost";
+
+/// How often a message is offered before giving up.
+pub const ATTEMPTS: u32 = 3;
+
+/// Longest wait for the relay to connect or answer one command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest line SMTP allows, without its CRLF.
+const MAX_LINE: usize = 998;
+
+/// Why a message couldn't be handed to the relay.
+#[derive(Error, Debug)]
+pub enum EmailError {
+    /// Connecting, reading or writing failed.
+    #[error("SMTP connection failed: {0}")]
+    Io(#[from] io::Error),
+
+    /// The relay took longer than 30s to connect or answer.
+    #[error("SMTP relay did not answer within {}s", REPLY_TIMEOUT.as_secs())]
+    Timeout,
+
+    /// The relay answered a command with an unexpected code.
+    #[error("SMTP relay rejected {command}: {reply}")]
+    Rejected {
+        /// The command, such as `RCPT TO`.
+        command: String,
+        /// The code and text of the reply.
+        reply: String,
+    },
+
+    /// A reply line without a code.
+    #[error("SMTP relay sent a malformed reply: {0:?}")]
+    Malformed(String),
+
+    /// The relay lacks an extension the settings need.
+    #[error("SMTP relay does not offer {0}")]
+    Unsupported(&'static str),
+
+    /// The TLS handshake failed, or this build can't do one.
+    #[error("STARTTLS failed: {0}")]
+    Tls(String),
+
+    /// The settings can't be used as they are, such as credentials
+    /// without STARTTLS.
+    #[error("{0}")]
+    Settings(&'static str),
+}
+
+/// The login for `AUTH`, from `SMTP_USERNAME` and `SMTP_PASSWORD`.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// The user to log in as.
+    pub username: String,
+    /// Their password.
+    pub password: String,
+}
+
+/// A message ready to be sent.
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// The subject line.
+    pub subject: String,
+    /// The plain-text body.
+    pub body: String,
+    /// File name and contents of the JSON report.
+    pub attachment: Option<(String, Vec<u8>)>,
+}
+
+impl Message {
+    /// The report of `summary`: the text report as the body, with
+    /// `fields` as its columns, and the JSON report attached.
+    pub fn report(summary: &ScanSummary, fields: Option<&[Field]>) -> io::Result<Self> {
+        let mut body = Vec::new();
+        render_text(summary, fields, &mut body)?;
+        let json = serde_json::to_vec_pretty(summary)?;
+        Ok(Message {
+            subject: format!(
+                "Scan of {}: {} open port{}",
+                summary.target,
+                summary.open_ports,
+                if summary.open_ports == 1 { "" } else { "s" }
+            ),
+            body: String::from_utf8_lossy(&body).into_owned(),
+            attachment: Some((format!("scan-{}.json", summary.scan_id), json)),
+        })
+    }
+}
+
+/// Sends messages through one relay.
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    server: String,
+    from: String,
+    to: Vec<String>,
+    starttls: bool,
+    credentials: Option<Credentials>,
+    retry_delay: Duration,
+}
+
+impl Mailer {
+    /// Sends from `from` to every address in `to` through `server`,
+    /// given as `host:port` or a bare host for port 25.
+    pub fn new(server: impl Into<String>, from: impl Into<String>, to: Vec<String>) -> Self {
+        Mailer {
+            server: server.into(),
+            from: from.into(),
+            to,
+            starttls: false,
+            credentials: None,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+
+    /// Logs in as `username` with `password`, given both or neither, and
+    /// upgrades the connection with STARTTLS when `starttls`: credentials
+    /// are only sent over STARTTLS.
+    pub fn secured(
+        self,
+        starttls: bool,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<Self, EmailError> {
+        if starttls && !STARTTLS_SUPPORTED {
+            return Err(EmailError::Settings(
+                "--smtp-starttls needs a build with the starttls feature",
+            ));
+        }
+        let credentials = match (username, password) {
+            (Some(username), Some(password)) => Some(Credentials { username, password }),
+            (None, None) => None,
+            _ => {
+                return Err(EmailError::Settings(
+                    "SMTP_USERNAME and SMTP_PASSWORD must be set together",
+                ))
+            }
+        };
+        if credentials.is_some() && !starttls {
+            return Err(EmailError::Settings(
+                "Not sending SMTP credentials without --smtp-starttls",
+            ));
+        }
+        Ok(self.starttls(starttls).credentials(credentials))
+    }
+
+    /// Upgrades the connection with STARTTLS before anything else is sent.
+    pub fn starttls(mut self, on: bool) -> Self {
+        self.starttls = on;
+        self
+    }
+
+    /// Logs in with `AUTH PLAIN` or `AUTH LOGIN`, whichever the relay offers.
+    pub fn credentials(mut self, credentials: Option<Credentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Wait before the second attempt; each further one waits twice as long.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    fn host(&self) -> &str {
+        match self.server.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => {
+                host.trim_start_matches('[').trim_end_matches(']')
+            }
+            _ => &self.server,
+        }
+    }
+
+    fn address(&self) -> String {
+        if self.host() == self.server {
+            format!("{}:25", self.server)
+        } else {
+            self.server.clone()
+        }
+    }
+
+    /// Mails the report of `summary`, with `fields` as the columns of its
+    /// text, logging whether it went: a report that couldn't be mailed
+    /// doesn't fail the scan.
+    pub async fn mail_report(&self, summary: &ScanSummary, fields: Option<&[Field]>) {
+        let sent = match Message::report(summary, fields) {
+            Ok(message) => self.deliver(&message).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match sent {
+            Ok(()) => info!("Mailed the report to {}", self.to.join(", ")),
+            Err(e) => error!("Failed to mail the report: {}", e),
+        }
+    }
+
+    /// Offers `message` up to [`ATTEMPTS`] times, warning about each
+    /// failed attempt, and returns the last error if none succeeded.
+    pub async fn deliver(&self, message: &Message) -> Result<(), EmailError> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+        loop {
+            match self.send(message).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt == ATTEMPTS => return Err(e),
+                Err(e) => {
+                    warn!("Email attempt {} of {} failed: {}", attempt, ATTEMPTS, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Offers `message` to the relay once.
+    pub async fn send(&self, message: &Message) -> Result<(), EmailError> {
+        let stream = tokio::time::timeout(REPLY_TIMEOUT, TcpStream::connect(self.address()))
+            .await
+            .map_err(|_| EmailError::Timeout)??;
+        let mut session = Session::new(Box::new(stream));
+        session.expect("greeting", &[220]).await?;
+        let mut extensions = session.command("EHLO", &ehlo(), &[250]).await?;
+        if self.starttls {
+            if !offers(&extensions, "STARTTLS") {
+                return Err(EmailError::Unsupported("STARTTLS"));
+            }
+            session.command("STARTTLS", "STARTTLS", &[220]).await?;
+            let stream = tls::connect(session.stream, self.host())
+                .await
+                .map_err(|e| EmailError::Tls(e.to_string()))?;
+            session = Session::new(stream);
+            extensions = session.command("EHLO", &ehlo(), &[250]).await?;
+        }
+        if let Some(credentials) = &self.credentials {
+            session.authenticate(&extensions, credentials).await?;
+        }
+
+        let from = format!("MAIL FROM:<{}>", self.from);
+        session.command("MAIL FROM", &from, &[250]).await?;
+        for to in &self.to {
+            let rcpt = format!("RCPT TO:<{}>", to);
+            session.command("RCPT TO", &rcpt, &[250, 251]).await?;
+        }
+        session.command("DATA", "DATA", &[354]).await?;
+        let data = stuff(&self.format(message));
+        session.stream.write_all(data.as_bytes()).await?;
+        session.command("message", ".", &[250]).await?;
+        // The message is accepted; a relay that drops the line on QUIT
+        // doesn't make it less so.
+        let _ = session.command("QUIT", "QUIT", &[221]).await;
+        Ok(())
+    }
+
+    /// The message as sent after DATA, with CRLF line ends.
+    fn format(&self, message: &Message) -> String {
+        let boundary = format!("port-scanner-{:016x}", rand_u64());
+        let mut out = String::new();
+        header(&mut out, "From", &self.from);
+        header(&mut out, "To", &self.to.join(", "));
+        header(&mut out, "Subject", &encode_header(&message.subject));
+        header(&mut out, "Date", &chrono::Utc::now().to_rfc2822());
+        header(&mut out, "MIME-Version", "1.0");
+        let Some((name, bytes)) = &message.attachment else {
+            text_part(&mut out, &message.body);
+            return out;
+        };
+        header(
+            &mut out,
+            "Content-Type",
+            &format!("multipart/mixed; boundary=\"{}\"", boundary),
+        );
+        out.push_str("\r\n");
+        out.push_str(&format!("--{}\r\n", boundary));
+        text_part(&mut out, &message.body);
+        out.push_str(&format!("--{}\r\n", boundary));
+        header(
+            &mut out,
+            "Content-Type",
+            &format!("application/json; name=\"{}\"", name),
+        );
+        header(
+            &mut out,
+            "Content-Disposition",
+            &format!("attachment; filename=\"{}\"", name),
+        );
+        header(&mut out, "Content-Transfer-Encoding", "base64");
+        out.push_str("\r\n");
+        base64_lines(&mut out, bytes);
+        out.push_str(&format!("--{}--\r\n", boundary));
+        out
+    }
+}
+
+/// One SMTP connection and the reply bytes read but not yet consumed.
+struct Session {
+    stream: Connection,
+    buf: Vec<u8>,
+}
+
+impl Session {
+    fn new(stream: Connection) -> Self {
+        Session {
+            stream,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sends `line` and reads the reply, which must have one of the
+    /// `expected` codes. Returns the reply's text lines.
+    async fn command(
+        &mut self,
+        name: &str,
+        line: &str,
+        expected: &[u16],
+    ) -> Result<Vec<String>, EmailError> {
+        debug!("SMTP > {}", if name == "AUTH" { "AUTH ..." } else { line });
+        self.stream
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await?;
+        self.expect(name, expected).await
+    }
+
+    async fn expect(&mut self, name: &str, expected: &[u16]) -> Result<Vec<String>, EmailError> {
+        let (code, lines) = self.reply().await?;
+        if !expected.contains(&code) {
+            return Err(EmailError::Rejected {
+                command: name.to_string(),
+                reply: format!("{} {}", code, lines.join(" ")),
+            });
+        }
+        Ok(lines)
+    }
+
+    /// Reads one reply, which may span several `NNN-` lines ended by a
+    /// `NNN ` line.
+    async fn reply(&mut self) -> Result<(u16, Vec<String>), EmailError> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.line().await?;
+            debug!("SMTP < {}", line);
+            let code = line
+                .get(..3)
+                .and_then(|c| c.parse::<u16>().ok())
+                .ok_or_else(|| EmailError::Malformed(line.clone()))?;
+            let more = line.as_bytes().get(3) == Some(&b'-');
+            lines.push(line.get(4..).unwrap_or("").to_string());
+            if !more {
+                return Ok((code, lines));
+            }
+        }
+    }
+
+    async fn line(&mut self) -> Result<String, EmailError> {
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+                self.buf.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > 4 * MAX_LINE {
+                return Err(EmailError::Malformed(
+                    String::from_utf8_lossy(&self.buf).into_owned(),
+                ));
+            }
+            let mut chunk = [0u8; 1024];
+            let n = tokio::time::timeout(REPLY_TIMEOUT, self.stream.read(&mut chunk))
+                .await
+                .map_err(|_| EmailError::Timeout)??;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    async fn authenticate(
+        &mut self,
+        extensions: &[String],
+        credentials: &Credentials,
+    ) -> Result<(), EmailError> {
+        let mechanisms: Vec<String> = extensions
+            .iter()
+            .filter_map(|e| {
+                let (keyword, rest) = e.split_once(' ')?;
+                keyword.eq_ignore_ascii_case("AUTH").then_some(rest)
+            })
+            .flat_map(|rest| rest.split_whitespace().map(str::to_ascii_uppercase))
+            .collect();
+        if mechanisms.iter().any(|m| m == "PLAIN") {
+            let line = format!("AUTH PLAIN {}", auth_plain(credentials));
+            self.command("AUTH", &line, &[235]).await?;
+        } else if mechanisms.iter().any(|m| m == "LOGIN") {
+            self.command("AUTH", "AUTH LOGIN", &[334]).await?;
+            let user = BASE64.encode(&credentials.username);
+            self.command("AUTH", &user, &[334]).await?;
+            let password = BASE64.encode(&credentials.password);
+            self.command("AUTH", &password, &[235]).await?;
+        } else {
+            return Err(EmailError::Unsupported("AUTH PLAIN or AUTH LOGIN"));
+        }
+        Ok(())
+    }
+}
+
+fn ehlo() -> String {
+    let name = std::env::var("HOSTNAME")
+        .ok()
+        .filter(|h| !h.is_empty() && h.bytes().all(|b| b.is_ascii_graphic()))
+        .unwrap_or_else(|| "localhost".to_string());
+    format!("EHLO {}", name)
+}
+
+fn offers(extensions: &[String], keyword: &str) -> bool {
+    extensions.iter().any(|e| {
+        e.split_whitespace()
+            .next()
+            .is_some_and(|k| k.eq_ignore_ascii_case(keyword))
+    })
+}
+
+fn auth_plain(credentials: &Credentials) -> String {
+    BASE64.encode(format!(
+        "\0{}\0{}",
+        credentials.username, credentials.password
+    ))
+}
+
+/// Whether this build can use `--smtp-starttls`.
+pub const STARTTLS_SUPPORTED: bool = tls::SUPPORTED;
+
+fn header(out: &mut String, name: &str, value: &str) {
+    out.push_str(name);
+    out.push_str(": ");
+    out.push_str(value);
+    out.push_str("\r\n");
+}
+
+/// `value` as an RFC 2047 encoded word when it isn't plain ASCII.
+fn encode_header(value: &str) -> String {
+    if value.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", BASE64.encode(value))
+    }
+}
+
+/// The body as a text/plain part: sent as is when it is ASCII in short
+/// lines, base64 encoded otherwise.
+fn text_part(out: &mut String, body: &str) {
+    header(out, "Content-Type", "text/plain; charset=utf-8");
+    let plain = body.is_ascii() && body.lines().all(|l| l.len() <= MAX_LINE);
+    if plain {
+        header(out, "Content-Transfer-Encoding", "7bit");
+        out.push_str("\r\n");
+        for line in body.lines() {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    } else {
+        header(out, "Content-Transfer-Encoding", "base64");
+        out.push_str("\r\n");
+        base64_lines(out, body.as_bytes());
+    }
+}
+
+fn base64_lines(out: &mut String, bytes: &[u8]) {
+    let encoded = BASE64.encode(bytes);
+    for chunk in encoded.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(chunk).expect("base64 is ASCII"));
+        out.push_str("\r\n");
+    }
+}
+
+/// Doubles the dot at the start of every line, so no line of the message
+/// reads as the end of DATA.
+fn stuff(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for line in message.split_inclusive("\r\n") {
+        if line.starts_with('.') {
+            out.push('.');
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+fn rand_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut h = RandomState::new().build_hasher();
+    h.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    h.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn message() -> Message {
+        Message {
+            subject: "Scan of 10.0.0.1: 1 open port".into(),
+            body: "Port    22 | open\n.hidden line\n".into(),
+            attachment: Some(("scan.json".into(), b"{\"open_ports\": 1}".to_vec())),
+        }
+    }
+
+    #[test]
+    fn the_report_is_the_body_and_the_json_an_attachment() {
+        let mailer = Mailer::new(
+            "relay",
+            "scanner@example.com",
+            vec!["ops@example.com".into()],
+        );
+        let data = stuff(&mailer.format(&message()));
+        assert!(data.contains("From: scanner@example.com\r\n"));
+        assert!(data.contains("Subject: Scan of 10.0.0.1: 1 open port\r\n"));
+        assert!(data.contains("\r\nPort    22 | open\r\n..hidden line\r\n"));
+        assert!(data.contains("Content-Disposition: attachment; filename=\"scan.json\"\r\n"));
+        assert!(data.contains(&BASE64.encode(b"{\"open_ports\": 1}")));
+        assert!(data.lines().all(|l| l.len() <= MAX_LINE));
+        assert_eq!(mailer.address(), "relay:25");
+        assert_eq!(Mailer::new("[::1]:2525", "", vec![]).host(), "::1");
+    }
+
+    #[test]
+    fn wide_or_non_ascii_bodies_are_base64() {
+        let mut out = String::new();
+        text_part(&mut out, "Banner: caf\u{e9}\n");
+        assert!(out.contains("Content-Transfer-Encoding: base64\r\n"));
+        assert!(out.contains(&BASE64.encode("Banner: caf\u{e9}\n")));
+        assert_eq!(encode_header("plain subject"), "plain subject");
+        assert_eq!(encode_header("\u{e9}"), "=?utf-8?B?w6k=?=");
+        assert_eq!(
+            auth_plain(&Credentials {
+                username: "scanner".into(),
+                password: "secret".into(),
+            }),
+            BASE64.encode("\0scanner\0secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_rejected_recipient_fails_the_attempt() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 relay ready\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = if line.starts_with("EHLO") {
+                    b"250-relay\r\n250-SIZE 1000000\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("RCPT") {
+                    b"550 5.1.1 no such user\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                write.write_all(reply).await.unwrap();
+            }
+        });
+
+        let e = Mailer::new(server, DEFAULT_FROM, vec!["nobody@example.com".into()])
+            .send(&message())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "SMTP relay rejected RCPT TO: 550 5.1.1 no such user"
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Caps on the connections a scan holds established at once
+//! (`--max-established-per-host`, `--max-established`).
+//!
+//! Attempt concurrency counts connects in flight, most of which, on a
+//! filtered port, never reach the host. These caps count only connections
+//! the host accepted, from the moment the handshake completes until the
+//! stream is dropped, banner reads and follow-up probes included. A
+//! connection that completes while its host is at the limit is closed at
+//! once and made again when a slot frees up ([`EstablishedLimit::deferred`]);
+//! connects the scanner makes again on its own wait for a slot first.
+//!
+//! The highest number held, overall and per host, is reported as an
+//! [`EstablishedReport`], so that a scan can show it kept to the limit.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::connector::Connection;
+
+/// How many connections a scan may hold established, per host and in all,
+/// shared by every probe of the scan.
+#[derive(Debug, Clone)]
+pub struct EstablishedLimit {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    per_host: Option<usize>,
+    total: Option<usize>,
+    all: Option<Arc<Semaphore>>,
+    count: Count,
+    hosts: Mutex<HashMap<IpAddr, Arc<Host>>>,
+    deferred: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Host {
+    slots: Option<Arc<Semaphore>>,
+    count: Count,
+}
+
+/// Connections held now and the most held at once.
+#[derive(Debug, Default)]
+struct Count {
+    held: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Count {
+    fn up(&self) {
+        let held = self.held.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(held, Ordering::Relaxed);
+    }
+
+    fn down(&self) {
+        self.held.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+/// The most connections a scan held established, overall and per host.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EstablishedReport {
+    /// The per-host limit, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_host: Option<usize>,
+    /// The scan-wide limit, if there was one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total: Option<usize>,
+    /// The most connections held at once across all hosts.
+    pub peak: usize,
+    /// The most connections held at once to each host that accepted one.
+    pub hosts: BTreeMap<IpAddr, usize>,
+    /// Connections closed as soon as they were made because a limit was
+    /// reached, and made again later.
+    #[serde(default)]
+    pub deferred: u64,
+}
+
+impl EstablishedReport {
+    /// The report narrowed to `host`.
+    pub fn for_host(&self, host: IpAddr) -> Self {
+        let peak = self.hosts.get(&host).copied();
+        EstablishedReport {
+            max_per_host: self.max_per_host,
+            max_total: self.max_total,
+            peak: peak.unwrap_or(0),
+            hosts: peak.map(|peak| (host, peak)).into_iter().collect(),
+            deferred: 0,
+        }
+    }
+}
+
+/// The permits of one established connection, taken before it is made.
+pub struct Slot {
+    limit: EstablishedLimit,
+    host: Arc<Host>,
+    permits: (Option<OwnedSemaphorePermit>, Option<OwnedSemaphorePermit>),
+}
+
+impl Slot {
+    /// Counts `conn` against the limits until it is dropped.
+    pub fn hold(self, conn: Connection) -> Connection {
+        self.limit.inner.count.up();
+        self.host.count.up();
+        Box::new(Held {
+            conn,
+            limit: self.limit,
+            host: self.host,
+            _permits: self.permits,
+        })
+    }
+}
+
+impl EstablishedLimit {
+    /// At most `per_host` connections to any one host and `total` in all;
+    /// none is no limit, but still counted.
+    pub fn new(per_host: Option<usize>, total: Option<usize>) -> Self {
+        EstablishedLimit {
+            inner: Arc::new(Inner {
+                per_host,
+                total,
+                all: total.map(|n| Arc::new(Semaphore::new(n))),
+                count: Count::default(),
+                hosts: Mutex::new(HashMap::new()),
+                deferred: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn host(&self, ip: IpAddr) -> Arc<Host> {
+        let mut hosts = self.inner.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        hosts
+            .entry(ip)
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    slots: self.inner.per_host.map(|n| Arc::new(Semaphore::new(n))),
+                    count: Count::default(),
+                })
+            })
+            .clone()
+    }
+
+    /// Counts `conn`, just made to `ip`, if a slot is free; otherwise
+    /// hands it back, to be closed.
+    pub fn try_hold(&self, ip: IpAddr, conn: Connection) -> Result<Connection, Connection> {
+        let host = self.host(ip);
+        let per_host = match &host.slots {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return Err(self.defer(conn)),
+            },
+            None => None,
+        };
+        let all = match &self.inner.all {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return Err(self.defer(conn)),
+            },
+            None => None,
+        };
+        let slot = Slot {
+            limit: self.clone(),
+            host,
+            permits: (per_host, all),
+        };
+        Ok(slot.hold(conn))
+    }
+
+    fn defer(&self, conn: Connection) -> Connection {
+        self.inner.deferred.fetch_add(1, Ordering::Relaxed);
+        conn
+    }
+
+    /// Waits for a slot to connect to `ip` with: the host's first, then the
+    /// scan's, so that waiters never hold one while blocking the other.
+    pub async fn reserve(&self, ip: IpAddr) -> Slot {
+        let host = self.host(ip);
+        let per_host = match &host.slots {
+            Some(slots) => Some(
+                slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        let all = match &self.inner.all {
+            Some(slots) => Some(
+                slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        Slot {
+            limit: self.clone(),
+            host,
+            permits: (per_host, all),
+        }
+    }
+
+    /// Connections closed at once because a limit was reached.
+    pub fn deferred(&self) -> u64 {
+        self.inner.deferred.load(Ordering::Relaxed)
+    }
+
+    /// The limits and the most connections held so far.
+    pub fn report(&self) -> EstablishedReport {
+        let hosts = self.inner.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        EstablishedReport {
+            max_per_host: self.inner.per_host,
+            max_total: self.inner.total,
+            peak: self.inner.count.peak(),
+            hosts: hosts
+                .iter()
+                .filter(|(_, host)| host.count.peak() > 0)
+                .map(|(ip, host)| (*ip, host.count.peak()))
+                .collect(),
+            deferred: self.deferred(),
+        }
+    }
+}
+
+/// An established connection counted against the limits.
+struct Held {
+    conn: Connection,
+    limit: EstablishedLimit,
+    host: Arc<Host>,
+    _permits: (Option<OwnedSemaphorePermit>, Option<OwnedSemaphorePermit>),
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.host.count.down();
+        self.limit.inner.count.down();
+    }
+}
+
+impl AsyncRead for Held {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Held {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.conn).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn conn() -> Connection {
+        Box::new(tokio::io::duplex(64).0)
+    }
+
+    #[tokio::test]
+    async fn connections_over_the_limit_are_handed_back_until_one_drops() {
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let limit = EstablishedLimit::new(Some(2), Some(3));
+
+        let first = limit.try_hold(a, conn()).ok().unwrap();
+        let _second = limit.try_hold(a, conn()).ok().unwrap();
+        assert!(limit.try_hold(a, conn()).is_err());
+        let _third = limit.try_hold(b, conn()).ok().unwrap();
+        // b is under its own limit, but the scan is at its.
+        assert!(limit.try_hold(b, conn()).is_err());
+
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            async move { limit.reserve(a).await.hold(conn()) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        let _fourth = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let report = limit.report();
+        assert_eq!(report.peak, 3);
+        assert_eq!(report.hosts, BTreeMap::from([(a, 2), (b, 1)]));
+        assert_eq!(report.deferred, 2);
+        assert_eq!(report.for_host(b).peak, 1);
+    }
+}
@@ -0,0 +1,120 @@
+//! How a port came to be classified: the steps its probe went through, in
+//! order, each with what came of it, for answering "how do you know it's
+//! open?".
+//!
+//! Evidence is only recorded when asked for
+//! ([`ScannerBuilder::evidence`](crate::scanner::ScannerBuilder::evidence),
+//! `--evidence` or `-v verbose`), and plays no part in telling scans apart:
+//! the `--write-on-change` fingerprint leaves it out like any other detail.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A step of the probe of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// The host had been taken for unreachable, so the port wasn't probed.
+    ReachCache,
+    /// The TCP handshake.
+    TcpConnect,
+    /// Waiting for the service to speak first.
+    BannerRead,
+    /// Telling the service from the banner or the port number.
+    ServiceMatch,
+    /// Asking the port to relay as a SOCKS5 or HTTP proxy, with
+    /// `--proxy-check`.
+    ProxyCheck,
+    /// A follow-up probe such as `--http-probe`, on the connection the
+    /// detail names.
+    Probe,
+}
+
+/// What came of a [`Step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// It worked: the handshake completed or the service sent something.
+    Success,
+    /// The port refused the connection.
+    Refused,
+    /// The connection was reset.
+    Reset,
+    /// Nothing came before the timeout.
+    Timeout,
+    /// It failed otherwise; the detail says how.
+    Error,
+    /// The service closed the connection without a word.
+    Empty,
+    /// A rule recognized what answered.
+    Matched,
+    /// No rule did.
+    NoMatch,
+    /// The step was left out.
+    Skipped,
+}
+
+/// One step of how a port was classified, such as
+/// `{"step": "tcp_connect", "outcome": "success", "elapsed_ms": 12}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evidence {
+    /// What was done.
+    pub step: Step,
+    /// What came of it.
+    pub outcome: Outcome,
+    /// How long it took, for steps that wait on the network.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    /// Anything more to say, such as the error or the rule that matched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Evidence {
+    /// A step and its outcome, with neither time nor detail.
+    pub fn new(step: Step, outcome: Outcome) -> Self {
+        Evidence {
+            step,
+            outcome,
+            elapsed_ms: None,
+            detail: None,
+        }
+    }
+
+    /// Records how long the step took.
+    pub fn elapsed(mut self, elapsed: Duration) -> Self {
+        self.elapsed_ms = Some(elapsed.as_millis() as u64);
+        self
+    }
+
+    /// Adds the detail.
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_serialize_as_small_records() {
+        let steps = vec![
+            Evidence::new(Step::TcpConnect, Outcome::Success).elapsed(Duration::from_millis(12)),
+            Evidence::new(Step::BannerRead, Outcome::Timeout),
+            Evidence::new(Step::ServiceMatch, Outcome::Matched).detail("HTTP response"),
+        ];
+        let json = serde_json::to_value(&steps).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"step": "tcp_connect", "outcome": "success", "elapsed_ms": 12},
+                {"step": "banner_read", "outcome": "timeout"},
+                {"step": "service_match", "outcome": "matched", "detail": "HTTP response"},
+            ])
+        );
+        let back: Vec<Evidence> = serde_json::from_value(json).unwrap();
+        assert_eq!(back, steps);
+    }
+}
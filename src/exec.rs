@@ -0,0 +1,211 @@
+//! Follow-up commands run for every open port (`--exec`).
+
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::scanner::PortResult;
+
+/// Captured stdout is cut off after this many bytes.
+const MAX_STDOUT: usize = 64 * 1024;
+
+/// The outcome of a follow-up command.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExecResult {
+    /// The command as run, after placeholder substitution.
+    pub command: String,
+    /// Exit status, if the command ran to completion.
+    pub exit_code: Option<i32>,
+    /// Trimmed standard output.
+    pub stdout: String,
+    /// True when the command was killed at the timeout.
+    pub timed_out: bool,
+    /// Why the command could not be started or waited for.
+    pub error: Option<String>,
+}
+
+impl ExecResult {
+    /// True when the command exited with status 0.
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// A command template such as `nuclei -u http://{target}:{port}`, run for
+/// open ports with a bounded number of commands at a time.
+///
+/// `{target}`, `{port}`, `{service}` and `{banner}` are substituted. The
+/// template is split into arguments like a shell would, and each value is
+/// substituted into a single argument, so a banner can't inject extra ones.
+/// In shell mode the template is run by `sh -c` with the values quoted.
+#[derive(Debug, Clone)]
+pub struct ExecHook {
+    template: String,
+    argv: Vec<String>,
+    shell: bool,
+    timeout: Duration,
+    slots: Arc<Semaphore>,
+}
+
+impl ExecHook {
+    /// Checks that `template` splits into a command and prepares the hook.
+    pub fn new(
+        template: &str,
+        shell: bool,
+        concurrency: usize,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let argv = shell_words::split(template)
+            .map_err(|e| format!("invalid --exec command '{}': {}", template, e))?;
+        if argv.is_empty() {
+            return Err("--exec command is empty".to_string());
+        }
+        if concurrency == 0 {
+            return Err("--exec-concurrency must be at least 1".to_string());
+        }
+        Ok(ExecHook {
+            template: template.to_string(),
+            argv,
+            shell,
+            timeout,
+            slots: Arc::new(Semaphore::new(concurrency)),
+        })
+    }
+
+    /// The argument vector for `r`: the split template with placeholders
+    /// filled in, or `sh -c` and the quoted command line in shell mode.
+    pub fn command_for(&self, r: &PortResult) -> Vec<String> {
+        if self.shell {
+            let line = substitute(&self.template, r, |v| shell_words::quote(v).into_owned());
+            return vec!["sh".to_string(), "-c".to_string(), line];
+        }
+        self.argv
+            .iter()
+            .map(|arg| substitute(arg, r, str::to_string))
+            .collect()
+    }
+
+    /// Runs the command for `r`, waiting for a free slot first.
+    pub async fn run(&self, r: &PortResult) -> ExecResult {
+        let argv = self.command_for(r);
+        let command = shell_words::join(&argv);
+        let _slot = self
+            .slots
+            .acquire()
+            .await
+            .expect("exec semaphore is never closed");
+        debug!(port = r.port, command = %command, "running follow-up command");
+
+        let child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+        let mut result = ExecResult {
+            command,
+            exit_code: None,
+            stdout: String::new(),
+            timed_out: false,
+            error: None,
+        };
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+        match timeout(self.timeout, child.wait_with_output()).await {
+            Ok(Ok(output)) => {
+                let stdout = &output.stdout[..output.stdout.len().min(MAX_STDOUT)];
+                result.exit_code = output.status.code();
+                result.stdout = String::from_utf8_lossy(stdout).trim().to_string();
+            }
+            Ok(Err(e)) => result.error = Some(e.to_string()),
+            Err(_) => result.timed_out = true,
+        }
+        result
+    }
+}
+
+fn substitute(s: &str, r: &PortResult, quote: impl Fn(&str) -> String) -> String {
+    s.replace("{target}", &quote(&r.target.to_string()))
+        .replace("{port}", &quote(&r.port.to_string()))
+        .replace("{service}", &quote(r.service.as_deref().unwrap_or("")))
+        .replace("{banner}", &quote(r.banner_str().as_deref().unwrap_or("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+
+    fn open(banner: &str) -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), 8080, PortStatus::Open);
+        r.banner = Some(banner.into());
+        r.service = Some("HTTP".to_string());
+        r.duration_ms = 1;
+        r
+    }
+
+    fn hook(template: &str, shell: bool) -> ExecHook {
+        ExecHook::new(template, shell, 2, Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn placeholders_stay_within_one_argument() {
+        let argv = hook("probe -u 'http://{target}:{port}' --note {banner}", false)
+            .command_for(&open("x; rm -rf /"));
+        assert_eq!(
+            argv,
+            [
+                "probe",
+                "-u",
+                "http://10.0.0.1:8080",
+                "--note",
+                "x; rm -rf /"
+            ]
+        );
+    }
+
+    #[test]
+    fn shell_mode_quotes_values() {
+        let argv = hook("echo {banner} | wc -c", true).command_for(&open("a'b"));
+        assert_eq!(argv[..2], ["sh", "-c"]);
+        assert_eq!(argv[2], r#"echo 'a'\''b' | wc -c"#);
+    }
+
+    #[test]
+    fn rejects_unbalanced_quotes() {
+        assert!(ExecHook::new("echo 'oops", false, 1, Duration::from_secs(1)).is_err());
+        assert!(ExecHook::new("  ", false, 1, Duration::from_secs(1)).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn captures_status_and_output() {
+        let ok = hook("echo {service} {port}", false).run(&open("")).await;
+        assert_eq!(ok.exit_code, Some(0));
+        assert_eq!(ok.stdout, "HTTP 8080");
+
+        let failed = hook("exit 3", true).run(&open("")).await;
+        assert_eq!(failed.exit_code, Some(3));
+        assert!(!failed.succeeded());
+
+        let slow = ExecHook::new("sleep 5", false, 1, Duration::from_millis(100))
+            .unwrap()
+            .run(&open(""))
+            .await;
+        assert!(slow.timed_out);
+
+        let missing = hook("/nonexistent/tool", false).run(&open("")).await;
+        assert!(missing.error.is_some());
+    }
+}
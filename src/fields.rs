@@ -0,0 +1,238 @@
+//! Report columns chosen with `--fields`, for the CSV and TXT reports and
+//! the verbose console line.
+//!
+//! Every column is read from a [`PortResult`] by [`Field::value`]; a new
+//! variant listed in [`Field::ALL`] is selectable everywhere at once.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::scanner::PortResult;
+
+/// One column of a result row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The scanned host.
+    Target,
+    /// The port number.
+    Port,
+    /// `tcp` or `udp`.
+    Protocol,
+    /// `open`, `closed` or `filtered`.
+    Status,
+    /// Detected protocol, such as `SSH`.
+    Service,
+    /// Detected software, such as `OpenSSH`.
+    Product,
+    /// Version of the product.
+    Version,
+    /// Anything else the banner said about the product.
+    ExtraInfo,
+    /// CPE 2.3 name of the product.
+    Cpe,
+    /// What the service sent first. Only shown when asked for, as it
+    /// dominates the width of a row.
+    Banner,
+    /// Milliseconds from the start of the scan until the port was classified.
+    Latency,
+    /// When the connect attempt started, with `--audit`.
+    StartedAt,
+    /// When the connect attempt completed, with `--audit`.
+    FinishedAt,
+    /// The local port the attempt came from, with `--audit`.
+    SourcePort,
+    /// Which attempt at the port the result is from, with `--audit`.
+    Attempt,
+    /// Why the port is filtered or its banner couldn't be read.
+    Error,
+    /// Ids of the `--vuln-db` and `--proxy-check` findings.
+    Findings,
+    /// Number of the group of ports sharing the banner.
+    Cluster,
+    /// Whether `--proxy-check` found an open proxy.
+    OpenProxy,
+    /// Where `--save-responses` wrote the raw response.
+    ResponseFile,
+    /// Notes a `--script` added.
+    Notes,
+    /// What the `--annotations` inventory says the port is.
+    Note,
+    /// The status the `--annotations` inventory expects.
+    ExpectedStatus,
+}
+
+impl Field {
+    /// Every field, in the order they are listed in errors and help.
+    pub const ALL: [Field; 23] = [
+        Field::Target,
+        Field::Port,
+        Field::Protocol,
+        Field::Status,
+        Field::Service,
+        Field::Product,
+        Field::Version,
+        Field::ExtraInfo,
+        Field::Cpe,
+        Field::Banner,
+        Field::Latency,
+        Field::StartedAt,
+        Field::FinishedAt,
+        Field::SourcePort,
+        Field::Attempt,
+        Field::Error,
+        Field::Findings,
+        Field::Cluster,
+        Field::OpenProxy,
+        Field::ResponseFile,
+        Field::Notes,
+        Field::Note,
+        Field::ExpectedStatus,
+    ];
+
+    /// The name `--fields` knows it by, also used as its column header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Target => "target",
+            Field::Port => "port",
+            Field::Protocol => "protocol",
+            Field::Status => "status",
+            Field::Service => "service",
+            Field::Product => "product",
+            Field::Version => "version",
+            Field::ExtraInfo => "extra_info",
+            Field::Cpe => "cpe",
+            Field::Banner => "banner",
+            Field::Latency => "latency",
+            Field::StartedAt => "started_at",
+            Field::FinishedAt => "finished_at",
+            Field::SourcePort => "source_port",
+            Field::Attempt => "attempt",
+            Field::Error => "error",
+            Field::Findings => "findings",
+            Field::Cluster => "cluster",
+            Field::OpenProxy => "open_proxy",
+            Field::ResponseFile => "response_file",
+            Field::Notes => "notes",
+            Field::Note => "note",
+            Field::ExpectedStatus => "expected_status",
+        }
+    }
+
+    /// The field of `r` as uncolored text; none when the result has no
+    /// such value. Lists are joined with `", "`.
+    pub fn value(self, r: &PortResult) -> Option<String> {
+        match self {
+            Field::Target => Some(r.target.to_string()),
+            Field::Port => Some(r.port.to_string()),
+            Field::Protocol => Some(r.protocol.to_string()),
+            Field::Status => Some(format!("{:?}", r.status).to_lowercase()),
+            Field::Service => r.service.clone(),
+            Field::Product => r.product.clone(),
+            Field::Version => r.version.clone(),
+            Field::ExtraInfo => r.extra_info.clone(),
+            Field::Cpe => r.cpe.clone(),
+            Field::Banner => r.banner_str().map(|b| b.into_owned()),
+            Field::Latency => Some(r.duration_ms.to_string()),
+            Field::StartedAt => r.audit.as_ref().map(|a| timestamp(a.started_at)),
+            Field::FinishedAt => r.audit.as_ref().map(|a| timestamp(a.finished_at)),
+            Field::SourcePort => r.audit.as_ref()?.source_port.map(|p| p.to_string()),
+            Field::Attempt => r.audit.as_ref().map(|a| a.attempt.to_string()),
+            Field::Error => r.error.as_ref().map(|e| e.to_string()),
+            Field::Findings => join(r.findings.iter().map(|f| f.id.as_str())),
+            Field::Cluster => r.cluster.map(|c| c.to_string()),
+            Field::OpenProxy => r.open_proxy.map(|open| open.to_string()),
+            Field::ResponseFile => r.response_file.as_ref().map(|p| p.display().to_string()),
+            Field::Notes => join(r.notes.iter().map(String::as_str)),
+            Field::Note => r.note.clone(),
+            Field::ExpectedStatus => r
+                .expected_status
+                .as_ref()
+                .map(|s| format!("{:?}", s).to_lowercase()),
+        }
+    }
+}
+
+/// A timestamp to the microsecond, such as `2024-05-01T12:00:00.123456Z`.
+pub fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn join<'a>(items: impl Iterator<Item = &'a str>) -> Option<String> {
+    let items: Vec<&str> = items.collect();
+    (!items.is_empty()).then(|| items.join(", "))
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Field::ALL
+            .into_iter()
+            .find(|f| f.name() == name)
+            .ok_or_else(|| {
+                let valid: Vec<&str> = Field::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "unknown field '{}' (valid fields: {})",
+                    s.trim(),
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+
+    fn ssh() -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), 22, PortStatus::Open);
+        r.banner = Some("SSH-2.0-OpenSSH_8.9p1".into());
+        r.service = Some("SSH".into());
+        r.product = Some("OpenSSH".into());
+        r.version = Some("8.9p1".into());
+        r.duration_ms = 12;
+        r
+    }
+
+    #[test]
+    fn fields_are_read_in_the_order_given() {
+        let fields: Vec<Field> = "version,port,status,service,latency,cpe"
+            .split(',')
+            .map(|f| f.parse().unwrap())
+            .collect();
+        let row: Vec<Option<String>> = fields.iter().map(|f| f.value(&ssh())).collect();
+        assert_eq!(
+            row,
+            [
+                Some("8.9p1".to_string()),
+                Some("22".to_string()),
+                Some("open".to_string()),
+                Some("SSH".to_string()),
+                Some("12".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn every_field_parses_from_its_name_and_unknown_ones_list_the_valid() {
+        for field in Field::ALL {
+            assert_eq!(field.name().parse::<Field>(), Ok(field));
+        }
+        assert_eq!(" Banner".parse::<Field>(), Ok(Field::Banner));
+        let e = "latncy".parse::<Field>().unwrap_err();
+        assert!(
+            e.starts_with("unknown field 'latncy' (valid fields: target, port, protocol, status,")
+        );
+        assert!(e.ends_with("notes, note, expected_status)"));
+    }
+}
@@ -0,0 +1,487 @@
+//! Result filters for `--filter`, such as
+//! `status == "open" && port < 10000 && service != "http"`: which results
+//! the console and the reports show. The summary counts every result
+//! whatever the filter says.
+//!
+//! A filter compares the fields of [`Field`] with literals or each other:
+//!
+//! - literals are numbers (`22`, `1.5`), strings in double or single quotes,
+//!   `true`, `false` and `null`;
+//! - `==`, `!=`, `<`, `<=`, `>` and `>=` compare, `contains` looks for a
+//!   substring; strings compare without regard to case, so
+//!   `service == "http"` matches `HTTP`;
+//! - `&&`, `||`, `!` and parentheses combine comparisons, `&&` binding
+//!   tighter than `||`;
+//! - a field on its own is true when it has a value other than `false`, `0`
+//!   or the empty string.
+//!
+//! A field a result doesn't have, such as the service of a silent port, is
+//! `null`: equal only to `null`, unequal to anything else, and neither less
+//! nor greater than anything, so `service != "http"` keeps ports without a
+//! service and `latency < 100 || cluster > 2` never fails on a missing one.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::fields::Field;
+use crate::scanner::PortResult;
+
+/// A parsed `--filter` expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    text: String,
+    expr: Expr,
+}
+
+impl Filter {
+    /// Whether `r` passes the filter.
+    pub fn matches(&self, r: &PortResult) -> bool {
+        self.expr.eval(r)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Parses a filter; an error names what was expected and points at the
+    /// position where it wasn't found.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s).map_err(|(at, e)| pointed(s, at, &e))?,
+            next: 0,
+        };
+        let expr = parser.or().map_err(|(at, e)| pointed(s, at, &e))?;
+        match parser.peek() {
+            (_, Token::End) => Ok(Filter {
+                text: s.to_string(),
+                expr,
+            }),
+            (at, token) => Err(pointed(
+                s,
+                at,
+                &format!("expected && or || instead of {}", token),
+            )),
+        }
+    }
+}
+
+/// `message` with `text` below it and a caret under position `at`.
+fn pointed(text: &str, at: usize, message: &str) -> String {
+    let column = text[..at.min(text.len())].chars().count();
+    format!(
+        "{} at position {}\n  {}\n  {}^",
+        message,
+        at,
+        text,
+        " ".repeat(column)
+    )
+}
+
+/// A value a field or literal stands for.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    /// The value of `field` in `r`, typed by what the field holds.
+    fn of(field: Field, r: &PortResult) -> Value {
+        let Some(text) = field.value(r) else {
+            return Value::Null;
+        };
+        match field {
+            Field::Port | Field::Latency | Field::SourcePort | Field::Attempt | Field::Cluster => {
+                text.parse().map_or(Value::Text(text), Value::Number)
+            }
+            Field::OpenProxy => Value::Bool(text == "true"),
+            _ => Value::Text(text),
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0,
+            Value::Text(s) => !s.is_empty(),
+        }
+    }
+
+    /// The value as text, for comparing it with a string.
+    fn text(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Text(s) => Some(s.to_lowercase()),
+        }
+    }
+
+    fn compare(&self, op: Op, other: &Value) -> bool {
+        use std::cmp::Ordering;
+        let ordering = match (self, other) {
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) if matches!(op, Op::Eq | Op::Ne) => Some(a.cmp(b)),
+            (a, b) => match (a.text(), b.text()) {
+                (Some(a), Some(b)) if op == Op::Contains => return a.contains(&b),
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => None,
+            },
+        };
+        match op {
+            Op::Eq => ordering == Some(Ordering::Equal),
+            Op::Ne => ordering != Some(Ordering::Equal),
+            Op::Lt => ordering == Some(Ordering::Less),
+            Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Op::Gt => ordering == Some(Ordering::Greater),
+            Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            // Reached for null and numbers compared with each other.
+            Op::Contains => false,
+        }
+    }
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Field(Field),
+    Literal(Value),
+}
+
+impl Operand {
+    fn value(&self, r: &PortResult) -> Value {
+        match self {
+            Operand::Field(field) => Value::of(*field, r),
+            Operand::Literal(value) => value.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    Truthy(Operand),
+}
+
+impl Expr {
+    fn eval(&self, r: &PortResult) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(r) || b.eval(r),
+            Expr::And(a, b) => a.eval(r) && b.eval(r),
+            Expr::Not(e) => !e.eval(r),
+            Expr::Compare(a, op, b) => a.value(r).compare(*op, &b.value(r)),
+            Expr::Truthy(operand) => operand.value(r).truthy(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Text(String),
+    Number(f64),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Token::End => f.write_str("the end"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 11] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")"];
+
+/// Splits `s` into tokens, each with its byte position.
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => return Err((s.len(), "unterminated string".to_string())),
+                    },
+                    Some((_, q)) if q == c => break,
+                    Some((_, other)) => text.push(other),
+                    None => return Err((at, "unterminated string".to_string())),
+                }
+            }
+            tokens.push((at, Token::Text(text)));
+        } else if c.is_ascii_digit()
+            || (c == '-' && s[at + 1..].starts_with(|d: char| d.is_ascii_digit()))
+        {
+            chars.next();
+            let mut end = at + c.len_utf8();
+            while let Some(&(i, d)) = chars.peek() {
+                if !(d.is_ascii_digit() || d == '.') {
+                    break;
+                }
+                chars.next();
+                end = i + 1;
+            }
+            let number = s[at..end]
+                .parse()
+                .map_err(|_| (at, format!("'{}' is not a number", &s[at..end])))?;
+            tokens.push((at, Token::Number(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = at;
+            while let Some(&(i, d)) = chars.peek() {
+                if !(d.is_alphanumeric() || d == '_') {
+                    break;
+                }
+                chars.next();
+                end = i + d.len_utf8();
+            }
+            tokens.push((at, Token::Name(s[at..end].to_string())));
+        } else {
+            let symbol = SYMBOLS
+                .into_iter()
+                .find(|symbol| s[at..].starts_with(symbol))
+                .ok_or_else(|| (at, format!("unexpected '{}'", c)))?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((at, Token::Symbol(symbol)));
+        }
+    }
+    tokens.push((s.len(), Token::End));
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+type Parsed<T> = Result<T, (usize, String)>;
+
+impl Parser {
+    fn peek(&self) -> (usize, &Token) {
+        let (at, token) = &self.tokens[self.next];
+        (*at, token)
+    }
+
+    fn advance(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.next].clone();
+        if token.1 != Token::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek().1, Token::Symbol(s) if *s == symbol);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Parsed<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Parsed<Expr> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Parsed<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            return match self.advance() {
+                (_, Token::Symbol(")")) => Ok(expr),
+                (at, token) => Err((at, format!("expected ')' instead of {}", token))),
+            };
+        }
+        let left = self.operand()?;
+        let op = match self.peek().1 {
+            Token::Symbol("==") => Op::Eq,
+            Token::Symbol("!=") => Op::Ne,
+            Token::Symbol("<") => Op::Lt,
+            Token::Symbol("<=") => Op::Le,
+            Token::Symbol(">") => Op::Gt,
+            Token::Symbol(">=") => Op::Ge,
+            Token::Name(name) if name == "contains" => Op::Contains,
+            _ => return Ok(Expr::Truthy(left)),
+        };
+        self.advance();
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Parsed<Operand> {
+        let (at, token) = self.advance();
+        Ok(Operand::Literal(match token {
+            Token::Text(text) => Value::Text(text),
+            Token::Number(n) => Value::Number(n),
+            Token::Name(name) => match name.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                _ => {
+                    return name
+                        .parse()
+                        .map(Operand::Field)
+                        .map_err(|e: String| (at, e))
+                }
+            },
+            token => {
+                return Err((
+                    at,
+                    format!("expected a field or value instead of {}", token),
+                ))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+
+    fn result(port: u16, status: PortStatus, service: Option<&str>) -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), port, status);
+        r.service = service.map(str::to_string);
+        r.duration_ms = 40;
+        r
+    }
+
+    fn matches(filter: &str, r: &PortResult) -> bool {
+        filter.parse::<Filter>().unwrap().matches(r)
+    }
+
+    #[test]
+    fn strings_compare_without_regard_to_case() {
+        let web = result(8080, PortStatus::Open, Some("HTTP"));
+        let ssh = result(22, PortStatus::Open, Some("SSH"));
+        let filter = r#"status == "open" && port < 10000 && service != "http""#;
+        assert!(!matches(filter, &web));
+        assert!(matches(filter, &ssh));
+        assert!(matches("service == 'ssh'", &ssh));
+        assert!(!matches(
+            r#"service contains "s" && !(service contains "h")"#,
+            &ssh
+        ));
+        assert!(matches(r#"target contains "10.0.""#, &ssh));
+        assert!(matches(r#"status != "closed""#, &ssh));
+    }
+
+    #[test]
+    fn numbers_compare_as_numbers() {
+        let r = result(8080, PortStatus::Open, None);
+        assert!(matches("port >= 8000 && port <= 8100", &r));
+        assert!(!matches("port > 8080 || port < 1024", &r));
+        assert!(matches("latency < 100.5", &r));
+        assert!(matches("port == \"8080\"", &r));
+        assert!(matches("(port < 1024 || port >= 8000) && latency", &r));
+        assert!(!matches("port < -1", &r));
+    }
+
+    #[test]
+    fn missing_fields_are_null() {
+        let silent = result(9000, PortStatus::Open, None);
+        assert!(matches(r#"service != "http""#, &silent));
+        assert!(!matches(r#"service == "http""#, &silent));
+        assert!(matches("service == null", &silent));
+        assert!(!matches("service", &silent));
+        assert!(matches("!service", &silent));
+        assert!(!matches("cluster > 0 || cluster <= 0", &silent));
+        assert!(!matches(r#"banner contains """#, &silent));
+        assert!(!matches("open_proxy", &silent));
+
+        let mut proxy = silent.clone();
+        proxy.open_proxy = Some(true);
+        assert!(matches("open_proxy && open_proxy == true", &proxy));
+        assert!(matches("service != null || open_proxy", &proxy));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        for (filter, error) in [
+            (
+                "status == && port < 10",
+                "expected a field or value instead of '&&' at position 10\n  \
+                 status == && port < 10\n            ^",
+            ),
+            (
+                "prot == 22",
+                "unknown field 'prot' (valid fields: target, port,",
+            ),
+            (
+                "port < 10 port",
+                "expected && or || instead of 'port' at position 10",
+            ),
+            (
+                "(port < 10",
+                "expected ')' instead of the end at position 10",
+            ),
+            ("service == \"ssh", "unterminated string at position 11"),
+            ("port = 22", "unexpected '=' at position 5"),
+            (
+                "",
+                "expected a field or value instead of the end at position 0",
+            ),
+        ] {
+            let err = filter.parse::<Filter>().unwrap_err();
+            assert!(err.starts_with(error), "{}: {}", filter, err);
+        }
+        assert_eq!(
+            "port < 10 || x"
+                .parse::<Filter>()
+                .unwrap_err()
+                .lines()
+                .last(),
+            Some("               ^")
+        );
+    }
+}
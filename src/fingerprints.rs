@@ -0,0 +1,297 @@
+//! Service fingerprints of one's own (`reanalyze --fingerprints`), tried
+//! on a banner before the built-in detection.
+//!
+//! The file is TOML with one `[[fingerprint]]` table per rule:
+//!
+//! ```toml
+//! [[fingerprint]]
+//! match = "Dropbear"
+//! service = "SSH"
+//! cpe = "dropbear_ssh_project:dropbear_ssh"
+//!
+//! [[fingerprint]]
+//! match = "lighttpd"
+//! service = "HTTP"
+//! product = "lighttpd"
+//! ```
+//!
+//! `match` is looked for case-insensitively in the banner. The product is
+//! `product`, or the matched text as the banner spells it; its version is
+//! what follows the match after a `/`, `_`, `-` or space, when that starts
+//! with a digit. `cpe` is the `vendor:product` of the CPE dictionary, for
+//! `--vuln-db` matching. The first rule matching a banner applies; a banner
+//! no rule matches goes through the built-in detection.
+
+use std::path::Path;
+use thiserror::Error;
+use toml_edit::{Document, Item, Table};
+
+use crate::service::{cpe_escape, detect_service, ServiceInfo};
+
+/// Why a fingerprints file could not be loaded.
+#[derive(Error, Debug)]
+pub enum FingerprintsError {
+    /// The file could not be read.
+    #[error("Cannot read fingerprints {path}: {source}")]
+    Read {
+        /// The fingerprints path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is not TOML.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The fingerprints path.
+        path: String,
+        /// The underlying error.
+        source: toml_edit::TomlError,
+    },
+    /// The file has something other than `[[fingerprint]]` tables.
+    #[error("{path}: line {line}: unexpected '{key}', rules are [[fingerprint]] tables")]
+    Layout {
+        /// The fingerprints path.
+        path: String,
+        /// The unexpected top-level key.
+        key: String,
+        /// Line it is on, from 1.
+        line: usize,
+    },
+    /// A rule is not a valid fingerprint.
+    #[error("{path}: fingerprint {number} (line {line}): {reason}")]
+    Rule {
+        /// The fingerprints path.
+        path: String,
+        /// Position of the rule in the file, from 1.
+        number: usize,
+        /// Line the rule starts on, from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+/// One rule of the file.
+#[derive(Debug)]
+struct Fingerprint {
+    token: String,
+    service: String,
+    product: Option<String>,
+    cpe: Option<String>,
+}
+
+impl Fingerprint {
+    fn from_table(table: &Table) -> Result<Self, String> {
+        let mut token = None;
+        let mut service = None;
+        let mut product = None;
+        let mut cpe = None;
+        for (key, item) in table.iter() {
+            let value = string(key, item)?.trim();
+            if value.is_empty() {
+                return Err(format!("'{}' is empty", key));
+            }
+            match key {
+                "match" => token = Some(value.to_string()),
+                "service" => service = Some(value.to_string()),
+                "product" => product = Some(value.to_string()),
+                "cpe" => {
+                    if value.split(':').count() != 2 || value.split(':').any(str::is_empty) {
+                        return Err(format!("cpe '{}' is not vendor:product", value));
+                    }
+                    cpe = Some(value.to_string());
+                }
+                other => {
+                    return Err(format!(
+                        "unknown key '{}' (expected: match, service, product, cpe)",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(Fingerprint {
+            token: token.ok_or("needs a 'match'")?,
+            service: service.ok_or("needs a 'service'")?,
+            product,
+            cpe,
+        })
+    }
+
+    /// What the rule makes of `banner`, if it matches.
+    fn detect(&self, banner: &str) -> Option<ServiceInfo> {
+        let at = banner
+            .to_ascii_lowercase()
+            .find(&self.token.to_ascii_lowercase())?;
+        let end = at + self.token.len();
+        let rest = &banner[end..];
+        let version = rest
+            .strip_prefix(['/', '_', '-', ' '])
+            .map(|v| {
+                let len = v
+                    .find(|c: char| c.is_whitespace() || "()[]{};,\"'".contains(c))
+                    .unwrap_or(v.len());
+                &v[..len]
+            })
+            .filter(|v| v.starts_with(|c: char| c.is_ascii_digit()));
+        Some(ServiceInfo {
+            service: Some(self.service.clone()),
+            product: Some(
+                self.product
+                    .clone()
+                    .unwrap_or_else(|| banner[at..end].to_string()),
+            ),
+            version: version.map(str::to_string),
+            extra_info: None,
+            cpe: self.cpe.as_ref().map(|cpe| {
+                format!(
+                    "cpe:2.3:a:{}:{}:*:*:*:*:*:*:*",
+                    cpe,
+                    version.map_or("*".to_string(), cpe_escape)
+                )
+            }),
+        })
+    }
+}
+
+fn string<'a>(key: &str, item: &'a Item) -> Result<&'a str, String> {
+    item.as_str()
+        .ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+/// The rules of a fingerprints file; without any, detection is the
+/// built-in one alone.
+#[derive(Debug, Default)]
+pub struct Fingerprints {
+    rules: Vec<Fingerprint>,
+}
+
+impl Fingerprints {
+    /// Reads and parses a fingerprints file.
+    pub fn load(path: &Path) -> Result<Self, FingerprintsError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| FingerprintsError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses fingerprints from their TOML text; `name` appears in errors.
+    pub fn parse(name: &str, text: &str) -> Result<Self, FingerprintsError> {
+        let doc = Document::parse(text).map_err(|source| FingerprintsError::Parse {
+            path: name.to_string(),
+            source,
+        })?;
+        let line = |span: Option<std::ops::Range<usize>>| {
+            let offset = span.map_or(0, |s| s.start);
+            text[..offset].matches('\n').count() + 1
+        };
+        let mut rules = Vec::new();
+        for (key, item) in doc.iter() {
+            let Some(tables) = item.as_array_of_tables().filter(|_| key == "fingerprint") else {
+                return Err(FingerprintsError::Layout {
+                    path: name.to_string(),
+                    key: key.to_string(),
+                    line: line(item.span()),
+                });
+            };
+            for (i, table) in tables.iter().enumerate() {
+                let rule =
+                    Fingerprint::from_table(table).map_err(|reason| FingerprintsError::Rule {
+                        path: name.to_string(),
+                        number: i + 1,
+                        line: line(table.span()),
+                        reason,
+                    })?;
+                rules.push(rule);
+            }
+        }
+        Ok(Fingerprints { rules })
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// True when the file had no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Works out the service on `port` from its banner: the first rule
+    /// matching it, otherwise [`detect_service`].
+    pub fn detect(&self, port: u16, banner: Option<&str>) -> ServiceInfo {
+        banner
+            .and_then(|banner| self.rules.iter().find_map(|rule| rule.detect(banner)))
+            .unwrap_or_else(|| detect_service(port, banner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+[[fingerprint]]
+match = "dropbear"
+service = "SSH"
+cpe = "dropbear_ssh_project:dropbear_ssh"
+
+[[fingerprint]]
+match = "lighttpd"
+service = "HTTP"
+product = "lighttpd"
+"#;
+
+    #[test]
+    fn rules_come_before_the_built_in_detection() {
+        let rules = Fingerprints::parse("rules.toml", RULES).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            rules.detect(22, Some("SSH-2.0-dropbear_2022.83\r\n")),
+            ServiceInfo {
+                service: Some("SSH".into()),
+                product: Some("dropbear".into()),
+                version: Some("2022.83".into()),
+                extra_info: None,
+                cpe: Some(
+                    "cpe:2.3:a:dropbear_ssh_project:dropbear_ssh:2022.83:*:*:*:*:*:*:*".into()
+                ),
+            }
+        );
+        let http = rules.detect(80, Some("HTTP/1.0 200 OK\r\nServer: LightTPD (beta)\r\n"));
+        assert_eq!(http.product.as_deref(), Some("lighttpd"));
+        assert_eq!(http.version, None);
+        assert_eq!(http.cpe, None);
+        // Banners no rule matches, and ports without one, are left to the
+        // built-in detection.
+        assert_eq!(
+            rules.detect(22, Some("SSH-2.0-OpenSSH_9.6")),
+            detect_service(22, Some("SSH-2.0-OpenSSH_9.6"))
+        );
+        assert_eq!(rules.detect(3306, None).service.as_deref(), Some("MySQL"));
+    }
+
+    #[test]
+    fn bad_rules_are_reported_with_their_line() {
+        let err = Fingerprints::parse("rules.toml", "[[fingerprint]]\nmatch = \"x\"\n")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "rules.toml: fingerprint 1 (line 1): needs a 'service'");
+        let err = Fingerprints::parse(
+            "rules.toml",
+            "[[fingerprint]]\nmatch = \"x\"\nservice = \"X\"\ncpe = \"x\"\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            err,
+            "rules.toml: fingerprint 1 (line 1): cpe 'x' is not vendor:product"
+        );
+        assert!(matches!(
+            Fingerprints::parse("rules.toml", "rule = 1\n"),
+            Err(FingerprintsError::Layout { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+//! Country and autonomous system of scanned hosts, from local MaxMind
+//! databases (`--geoip-db`, `--asn-db`). Reading the databases needs the
+//! `geoip` cargo feature; without it, or without the files, hosts are simply
+//! left unannotated.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::scope;
+
+/// Where a host is and who routes it. Every field is optional: each
+/// database only knows some of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeoInfo {
+    /// `private` or `reserved` for addresses that are not on the internet
+    /// and were therefore not looked up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// ISO 3166-1 alpha-2 country code, such as `US`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
+    /// English country name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// English city name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// Autonomous system number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// Organization the autonomous system is registered to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_name: Option<String>,
+}
+
+impl GeoInfo {
+    /// True when nothing is known.
+    pub fn is_empty(&self) -> bool {
+        *self == GeoInfo::default()
+    }
+
+    /// The country code, or the scope of an address that wasn't looked up.
+    pub fn country_or_scope(&self) -> Option<&str> {
+        self.country_code.as_deref().or(self.scope.as_deref())
+    }
+
+    fn labeled(scope: &str) -> Self {
+        GeoInfo {
+            scope: Some(scope.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+impl fmt::Display for GeoInfo {
+    /// `US, Mountain View, AS15169 GOOGLE`, or `private`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        parts.extend(self.scope.clone());
+        parts.extend(self.country_code.clone().or(self.country.clone()));
+        parts.extend(self.city.clone());
+        match (self.asn, &self.as_name) {
+            (Some(asn), Some(name)) => parts.push(format!("AS{} {}", asn, name)),
+            (Some(asn), None) => parts.push(format!("AS{}", asn)),
+            (None, Some(name)) => parts.push(name.clone()),
+            (None, None) => {}
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// Why a database could not be used.
+#[derive(Error, Debug)]
+pub enum GeoIpError {
+    /// The file could not be read or is not a MaxMind database.
+    #[error("Cannot open {path}: {message}")]
+    Open {
+        /// The database path.
+        path: String,
+        /// What went wrong.
+        message: String,
+    },
+    /// This build leaves out database support.
+    #[error("Cannot open {0}: built without the geoip feature")]
+    Unsupported(String),
+}
+
+#[cfg(feature = "geoip")]
+type Reader = maxminddb::Reader<Vec<u8>>;
+#[cfg(not(feature = "geoip"))]
+type Reader = ();
+
+/// The databases of a scan, with the answers cached per address.
+#[derive(Default)]
+pub struct GeoDb {
+    city: Option<Reader>,
+    asn: Option<Reader>,
+    cache: Mutex<HashMap<IpAddr, GeoInfo>>,
+}
+
+impl GeoDb {
+    /// No databases: public hosts come back empty.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The City or Country database at `city` and the ASN database at
+    /// `asn`, or `None` when neither is given. One that can't be opened is
+    /// skipped with a warning: the scan goes on without its fields.
+    pub fn open(city: Option<&Path>, asn: Option<&Path>) -> Option<Arc<Self>> {
+        if city.is_none() && asn.is_none() {
+            return None;
+        }
+        let mut db = GeoDb::new();
+        if let Some(path) = city {
+            if let Err(e) = db.add_city_db(path) {
+                warn!("{}; hosts get no country", e);
+            }
+        }
+        if let Some(path) = asn {
+            if let Err(e) = db.add_asn_db(path) {
+                warn!("{}; hosts get no AS", e);
+            }
+        }
+        Some(Arc::new(db))
+    }
+
+    /// Adds a GeoLite2/GeoIP2 City or Country database.
+    pub fn add_city_db(&mut self, path: &Path) -> Result<(), GeoIpError> {
+        self.city = Some(open(path)?);
+        Ok(())
+    }
+
+    /// Adds a GeoLite2/GeoIP2 ASN database.
+    pub fn add_asn_db(&mut self, path: &Path) -> Result<(), GeoIpError> {
+        self.asn = Some(open(path)?);
+        Ok(())
+    }
+
+    /// True when neither database was added.
+    pub fn is_empty(&self) -> bool {
+        self.city.is_none() && self.asn.is_none()
+    }
+
+    /// What the databases know about `ip`. Private and reserved addresses
+    /// are labeled instead of looked up; anything a database cannot answer
+    /// is left out.
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .entry(ip)
+            .or_insert_with(|| {
+                if scope::is_private(ip) {
+                    GeoInfo::labeled("private")
+                } else if scope::is_reserved(ip) {
+                    GeoInfo::labeled("reserved")
+                } else {
+                    self.look_up(ip)
+                }
+            })
+            .clone()
+    }
+
+    #[cfg(feature = "geoip")]
+    fn look_up(&self, ip: IpAddr) -> GeoInfo {
+        use maxminddb::geoip2;
+
+        let mut info = GeoInfo::default();
+        let city = self.city.as_ref().and_then(|db| {
+            db.lookup(ip)
+                .and_then(|found| found.decode::<geoip2::City>())
+                .map_err(|e| tracing::debug!("City lookup of {} failed: {}", ip, e))
+                .ok()
+                .flatten()
+        });
+        if let Some(city) = city {
+            info.country_code = city.country.iso_code.map(str::to_string);
+            info.country = city.country.names.english.map(str::to_string);
+            info.city = city.city.names.english.map(str::to_string);
+        }
+        let asn = self.asn.as_ref().and_then(|db| {
+            db.lookup(ip)
+                .and_then(|found| found.decode::<geoip2::Asn>())
+                .map_err(|e| tracing::debug!("ASN lookup of {} failed: {}", ip, e))
+                .ok()
+                .flatten()
+        });
+        if let Some(asn) = asn {
+            info.asn = asn.autonomous_system_number;
+            info.as_name = asn.autonomous_system_organization.map(str::to_string);
+        }
+        info
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn look_up(&self, _ip: IpAddr) -> GeoInfo {
+        GeoInfo::default()
+    }
+}
+
+#[cfg(feature = "geoip")]
+fn open(path: &Path) -> Result<Reader, GeoIpError> {
+    maxminddb::Reader::open_readfile(path).map_err(|e| GeoIpError::Open {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })
+}
+
+#[cfg(not(feature = "geoip"))]
+fn open(path: &Path) -> Result<Reader, GeoIpError> {
+    Err(GeoIpError::Unsupported(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn non_public_addresses_are_labeled_not_looked_up() {
+        let db = GeoDb::new();
+        assert_eq!(db.lookup(ip("10.0.0.1")).to_string(), "private");
+        assert_eq!(db.lookup(ip("fe80::1")).scope.as_deref(), Some("private"));
+        assert_eq!(db.lookup(ip("192.0.2.1")).to_string(), "reserved");
+        assert!(db.lookup(ip("8.8.8.8")).is_empty());
+    }
+
+    #[test]
+    fn missing_databases_are_errors_for_the_caller_to_skip() {
+        let mut db = GeoDb::new();
+        let err = db
+            .add_city_db(Path::new("/nonexistent/GeoLite2-City.mmdb"))
+            .unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/GeoLite2-City.mmdb"));
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn geo_info_reads_as_one_line() {
+        let info = GeoInfo {
+            country_code: Some("US".into()),
+            country: Some("United States".into()),
+            city: Some("Mountain View".into()),
+            asn: Some(15169),
+            as_name: Some("GOOGLE".into()),
+            ..GeoInfo::default()
+        };
+        assert_eq!(info.to_string(), "US, Mountain View, AS15169 GOOGLE");
+        let asn_only = GeoInfo {
+            asn: Some(13335),
+            ..GeoInfo::default()
+        };
+        assert_eq!(asn_only.to_string(), "AS13335");
+        assert_eq!(
+            serde_json::to_value(&asn_only).unwrap(),
+            serde_json::json!({"asn": 13335})
+        );
+    }
+}
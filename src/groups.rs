@@ -0,0 +1,305 @@
+//! Named groups of ports, used in port specifications as `@name`, e.g.
+//! `--ports @web,@db,8443`.
+//!
+//! A few groups are built in; more are defined with `--port-group
+//! NAME=PORTS` or a daemon config's `[port_groups]` table. A group's ports
+//! may name other groups, so long as none ends up including itself.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::spec::{self, SpecError};
+
+/// The built-in groups: name, ports and what they cover.
+pub const BUILTIN: [(&str, &str, &str); 6] = [
+    ("web", "80,443,8000,8080,8443,8888", "HTTP and HTTPS"),
+    (
+        "db",
+        "1433,1521,3306,5432,5984,6379,9042,9200,11211,27017",
+        "SQL, key-value and document databases",
+    ),
+    ("mail", "25,110,143,465,587,993,995", "SMTP, POP3 and IMAP"),
+    (
+        "remote",
+        "22,23,3389,5900,5985,5986",
+        "remote shells and desktops",
+    ),
+    (
+        "files",
+        "21,111,139,445,873,2049",
+        "file transfer and sharing",
+    ),
+    (
+        "directory",
+        "53,88,389,464,636,3268,3269",
+        "DNS, Kerberos and LDAP",
+    ),
+];
+
+/// Why a group could not be defined.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GroupError {
+    /// Not made of lowercase letters, digits, `-` and `_`.
+    #[error("Invalid port group name '{0}': use lowercase letters, digits, '-' and '_'")]
+    InvalidName(String),
+    /// The name of a built-in group.
+    #[error("@{0} is a built-in port group")]
+    Builtin(String),
+    /// Defined more than once.
+    #[error("Port group @{0} is defined twice")]
+    Duplicate(String),
+    /// The group's ports don't parse, name an unknown group or include the
+    /// group itself.
+    #[error("Port group @{name}: {source}")]
+    Ports {
+        /// The group.
+        name: String,
+        /// What is wrong with its ports.
+        source: SpecError,
+    },
+}
+
+/// A `--port-group` definition: `NAME=PORTS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortGroup {
+    /// The name, without the `@`.
+    pub name: String,
+    /// Its port specification, which may name other groups.
+    pub ports: String,
+}
+
+impl FromStr for PortGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, ports) = s
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' is not NAME=PORTS, e.g. admin=8080-8090,9000", s))?;
+        Ok(PortGroup {
+            name: name.trim().trim_start_matches('@').to_string(),
+            ports: ports.trim().to_string(),
+        })
+    }
+}
+
+/// The groups a port specification can name: the built-in ones and any
+/// user-defined.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortGroups {
+    user: BTreeMap<String, String>,
+}
+
+impl PortGroups {
+    /// The built-in groups only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines the groups in order, then checks that each expands.
+    pub fn with(groups: &[PortGroup]) -> Result<Self, GroupError> {
+        let mut all = PortGroups::new();
+        for group in groups {
+            all.define(&group.name, &group.ports)?;
+        }
+        all.check()?;
+        Ok(all)
+    }
+
+    /// Adds a user group. Its ports are only checked by
+    /// [`PortGroups::check`], so groups can name ones defined after them.
+    pub fn define(&mut self, name: &str, ports: &str) -> Result<(), GroupError> {
+        let valid = !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(GroupError::InvalidName(name.to_string()));
+        }
+        if BUILTIN.iter().any(|(builtin, _, _)| *builtin == name) {
+            return Err(GroupError::Builtin(name.to_string()));
+        }
+        if self
+            .user
+            .insert(name.to_string(), ports.to_string())
+            .is_some()
+        {
+            return Err(GroupError::Duplicate(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks that every user group expands to ports.
+    pub fn check(&self) -> Result<(), GroupError> {
+        for (name, ports) in &self.user {
+            spec::protocol_port_ranges_with(ports, self).map_err(|source| GroupError::Ports {
+                name: name.clone(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The ports of the group called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        BUILTIN
+            .iter()
+            .find(|(builtin, _, _)| *builtin == name)
+            .map(|(_, ports, _)| *ports)
+            .or_else(|| self.user.get(name).map(String::as_str))
+    }
+
+    /// Every group name, built-in ones first.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        BUILTIN
+            .iter()
+            .map(|(name, _, _)| *name)
+            .chain(self.user.keys().map(String::as_str))
+    }
+
+    /// What to tell someone who asked for `name`, which doesn't exist: the
+    /// groups it is probably a typo of, or else all of them.
+    pub fn suggest(&self, name: &str) -> String {
+        let nearest = self.names().map(|known| distance(known, name)).min();
+        let close: Vec<String> = self
+            .names()
+            .filter(|known| {
+                known.starts_with(name)
+                    || Some(distance(known, name)) == nearest.filter(|&d| d <= 2)
+            })
+            .map(|known| format!("@{}", known))
+            .collect();
+        if close.is_empty() {
+            let all: Vec<String> = self.names().map(|known| format!("@{}", known)).collect();
+            format!("known groups: {}", all.join(", "))
+        } else {
+            format!("did you mean {}?", close.join(" or "))
+        }
+    }
+}
+
+impl fmt::Display for PortGroups {
+    /// The `--list-port-groups` table.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = BUILTIN.iter().copied().chain(
+            self.user
+                .iter()
+                .map(|(name, ports)| (name.as_str(), ports.as_str(), "user-defined")),
+        );
+        let name_width = self.names().map(str::len).max().unwrap_or(0) + 1;
+        let ports_width = BUILTIN
+            .iter()
+            .map(|(_, ports, _)| *ports)
+            .chain(self.user.values().map(String::as_str))
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        for (name, ports, about) in rows {
+            writeln!(
+                f,
+                "{:<name_width$}  {:<ports_width$}  {}",
+                format!("@{}", name),
+                ports,
+                about
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The edit distance between `a` and `b`, counting insertions, deletions,
+/// substitutions and swaps of adjacent characters.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::PortSet;
+    use crate::spec::SpecErrorKind;
+
+    fn group(s: &str) -> PortGroup {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn groups_compose_and_exclusions_still_apply() {
+        let groups = PortGroups::with(&[
+            group("admin=@internal,9000"),
+            group("internal=8080-8090,@db"),
+        ])
+        .unwrap();
+        let mut ports = PortSet::parse_with("@admin,@web,22", &groups).unwrap();
+        ports.remove_all(&PortSet::parse_with("@db,8080", &groups).unwrap());
+        assert_eq!(ports.to_string(), "22,80,443,8000,8081-8090,8443,8888,9000");
+        assert_eq!(
+            PortSet::parse("@web,80,443").unwrap().to_string(),
+            "80,443,8000,8080,8443,8888"
+        );
+    }
+
+    #[test]
+    fn cycles_unknown_names_and_clashes_are_rejected() {
+        let cycle = PortGroups::with(&[group("a=1,@b"), group("b=2,@c"), group("c=@a")]);
+        assert!(matches!(
+            cycle,
+            Err(GroupError::Ports { source, .. }) if source.kind == SpecErrorKind::GroupCycle
+        ));
+        assert!(matches!(
+            PortGroups::with(&[group("self=@self")]),
+            Err(GroupError::Ports { source, .. }) if source.kind == SpecErrorKind::GroupCycle
+        ));
+
+        let e = PortSet::parse("22,@wbe").unwrap_err();
+        assert_eq!(e.kind, SpecErrorKind::UnknownGroup);
+        assert_eq!(e.position, 3);
+        assert_eq!(
+            e.to_string(),
+            "Unknown port group in \"@wbe\" at position 3; did you mean @web?"
+        );
+        let e = PortSet::parse("@nothing-like-it").unwrap_err();
+        assert!(e
+            .to_string()
+            .ends_with("known groups: @web, @db, @mail, @remote, @files, @directory"));
+
+        assert_eq!(
+            PortGroups::with(&[group("web=80")]),
+            Err(GroupError::Builtin("web".to_string()))
+        );
+        assert_eq!(
+            PortGroups::with(&[group("x=1"), group("x=2")]),
+            Err(GroupError::Duplicate("x".to_string()))
+        );
+        assert_eq!(
+            PortGroups::with(&[group("Web Ports=80")]),
+            Err(GroupError::InvalidName("Web Ports".to_string()))
+        );
+        assert!(matches!(
+            PortGroups::with(&[group("bad=80-70")]),
+            Err(GroupError::Ports { source, .. }) if source.kind == SpecErrorKind::StartAfterEnd
+        ));
+        assert!("no-equals".parse::<PortGroup>().is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! Latency heat maps of a scan (`--heatmap`).
+//!
+//! With [`ScannerBuilder::record_timings`](crate::scanner::ScannerBuilder::record_timings)
+//! every port that answered keeps its connect time, and an open port that
+//! sent a banner the time the banner took, as its [`Timing`]. [`heatmap`]
+//! counts them into latency bins for each host and range of ports, connect
+//! and banner times apart: one [`HeatRow`] per cell row of the map.
+//!
+//! The map has at most [`HeatmapOptions::max_cells`] counts. A scan that
+//! would have more gets wider port ranges, doubling until they fit, and
+//! then one row per range for all hosts together; the map says it was
+//! [`coarsened`](Heatmap::coarsened). Counts add up, so a coarser map is
+//! exactly what the wider ranges would have counted.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::scanner::PortResult;
+
+/// The default upper edges of the latency bins, in milliseconds.
+pub const DEFAULT_BINS_MS: [f64; 7] = [1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0];
+
+/// How many ports a row covers by default.
+pub const DEFAULT_PORT_WIDTH: u32 = 1024;
+
+/// The most counts a map has by default: about a megabyte of JSON.
+pub const DEFAULT_MAX_CELLS: usize = 100_000;
+
+/// Ports are 16-bit, so no row is wider than this.
+const ALL_PORTS: u32 = 65536;
+
+/// How long a port took to answer, in milliseconds, as the JSON report
+/// keeps it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Timing {
+    /// From the start of the connect until the port answered, open or
+    /// closed.
+    pub connect_ms: f64,
+    /// From the connection until the banner arrived; none when no banner
+    /// was read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_ms: Option<f64>,
+}
+
+/// The upper edges of the latency bins, in milliseconds, in increasing
+/// order, such as `1,5,10,50`; a last bin counts everything slower.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bins(pub Vec<f64>);
+
+impl Default for Bins {
+    fn default() -> Self {
+        Bins(DEFAULT_BINS_MS.to_vec())
+    }
+}
+
+impl FromStr for Bins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut edges = Vec::new();
+        for edge in s.split(',') {
+            let edge = edge.trim();
+            let ms: f64 = edge
+                .strip_suffix("ms")
+                .unwrap_or(edge)
+                .parse()
+                .map_err(|_| format!("'{}' is not a number of milliseconds", edge))?;
+            if !ms.is_finite() || ms <= 0.0 {
+                return Err(format!(
+                    "'{}' is not a positive number of milliseconds",
+                    edge
+                ));
+            }
+            if edges.last().is_some_and(|&last| ms <= last) {
+                return Err(format!("bins must be increasing, but {} is not", edge));
+            }
+            edges.push(ms);
+        }
+        Ok(Bins(edges))
+    }
+}
+
+/// How finely [`heatmap`] bins the times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapOptions {
+    /// The latency bins.
+    pub bins: Bins,
+    /// How many ports a row covers, before any coarsening.
+    pub port_width: u32,
+    /// The most counts the map may have.
+    pub max_cells: usize,
+}
+
+impl Default for HeatmapOptions {
+    fn default() -> Self {
+        HeatmapOptions {
+            bins: Bins::default(),
+            port_width: DEFAULT_PORT_WIDTH,
+            max_cells: DEFAULT_MAX_CELLS,
+        }
+    }
+}
+
+/// Connect and banner times binned by host and range of ports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Heatmap {
+    /// The upper edges of the bins, in milliseconds; each row has one count
+    /// more, for the times above the last edge.
+    pub bins_ms: Vec<f64>,
+    /// How many ports each row covers.
+    pub port_width: u32,
+    /// Whether the rows were widened, or the hosts merged, to stay within
+    /// the cap on counts.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub coarsened: bool,
+    /// The rows with at least one time, by host and then port.
+    pub rows: Vec<HeatRow>,
+}
+
+/// The counts of one host and range of ports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeatRow {
+    /// The host; none when the row counts all hosts together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<IpAddr>,
+    /// The first port of the range.
+    pub first_port: u16,
+    /// The last port of the range.
+    pub last_port: u16,
+    /// How many connect times fell into each bin.
+    pub connect: Vec<u64>,
+    /// How many banner times fell into each bin.
+    pub banner: Vec<u64>,
+}
+
+/// The counts of a row while they are added up.
+#[derive(Default, Clone)]
+struct Counts {
+    connect: Vec<u64>,
+    banner: Vec<u64>,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        for (a, b) in self.connect.iter_mut().zip(&other.connect) {
+            *a += b;
+        }
+        for (a, b) in self.banner.iter_mut().zip(&other.banner) {
+            *a += b;
+        }
+    }
+}
+
+/// Bins the times of `results` as `options` say; results without a
+/// [`Timing`] are left out.
+pub fn heatmap<R: Borrow<PortResult>>(
+    results: impl IntoIterator<Item = R>,
+    options: &HeatmapOptions,
+) -> Heatmap {
+    let edges = &options.bins.0;
+    let bin = |ms: f64| {
+        edges
+            .iter()
+            .position(|&edge| ms <= edge)
+            .unwrap_or(edges.len())
+    };
+    let empty = Counts {
+        connect: vec![0; edges.len() + 1],
+        banner: vec![0; edges.len() + 1],
+    };
+    let mut width = options.port_width.clamp(1, ALL_PORTS);
+    let mut rows: BTreeMap<(Option<IpAddr>, u32), Counts> = BTreeMap::new();
+    for r in results {
+        let r = r.borrow();
+        let Some(timing) = r.timing else {
+            continue;
+        };
+        let first = u32::from(r.port) / width * width;
+        let counts = rows
+            .entry((Some(r.target), first))
+            .or_insert_with(|| empty.clone());
+        counts.connect[bin(timing.connect_ms)] += 1;
+        if let Some(ms) = timing.banner_ms {
+            counts.banner[bin(ms)] += 1;
+        }
+    }
+
+    let cells = |rows: &BTreeMap<_, _>| rows.len() * 2 * (edges.len() + 1);
+    let mut coarsened = false;
+    while cells(&rows) > options.max_cells && width < ALL_PORTS {
+        width = (width * 2).min(ALL_PORTS);
+        rows = merge(rows, |(host, first)| (host, first / width * width));
+        coarsened = true;
+    }
+    if cells(&rows) > options.max_cells {
+        rows = merge(rows, |(_, first)| (None, first));
+        coarsened = true;
+    }
+
+    Heatmap {
+        bins_ms: edges.clone(),
+        port_width: width,
+        coarsened,
+        rows: rows
+            .into_iter()
+            .map(|((host, first), counts)| HeatRow {
+                host,
+                first_port: first as u16,
+                last_port: (first + width - 1).min(ALL_PORTS - 1) as u16,
+                connect: counts.connect,
+                banner: counts.banner,
+            })
+            .collect(),
+    }
+}
+
+type Key = (Option<IpAddr>, u32);
+
+/// Adds up the rows that `key` puts together.
+fn merge(rows: BTreeMap<Key, Counts>, key: impl Fn(Key) -> Key) -> BTreeMap<Key, Counts> {
+    let mut merged: BTreeMap<Key, Counts> = BTreeMap::new();
+    for (k, counts) in rows {
+        match merged.get_mut(&key(k)) {
+            Some(total) => total.add(&counts),
+            None => {
+                merged.insert(key(k), counts);
+            }
+        }
+    }
+    merged
+}
+
+impl Heatmap {
+    /// The map narrowed to the rows of `host`.
+    pub fn for_host(&self, host: IpAddr) -> Self {
+        Heatmap {
+            rows: self
+                .rows
+                .iter()
+                .filter(|row| row.host == Some(host))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Heatmap {
+    /// The map as a table of the text report: a line of connect counts and
+    /// one of banner counts for each row, with a column per bin.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut labels: Vec<String> = self.bins_ms.iter().map(|ms| format!("<={}", ms)).collect();
+        if let Some(last) = self.bins_ms.last() {
+            labels.push(format!(">{}", last));
+        }
+        let column = labels.iter().map(String::len).max().unwrap_or(0).max(5);
+        let hosts: Vec<String> = self
+            .rows
+            .iter()
+            .map(|row| row.host.map_or("all".to_string(), |h| h.to_string()))
+            .collect();
+        let host_width = hosts.iter().map(String::len).max().unwrap_or(0).max(4);
+        write!(f, "{:<host_width$} | {:<11} | {:<7}", "Host", "Ports", "ms")?;
+        for label in &labels {
+            write!(f, " {:>column$}", label)?;
+        }
+        writeln!(f)?;
+        for (row, host) in self.rows.iter().zip(&hosts) {
+            let ports = format!("{}-{}", row.first_port, row.last_port);
+            for (kind, counts) in [("connect", &row.connect), ("banner", &row.banner)] {
+                write!(f, "{:<host_width$} | {:<11} | {:<7}", host, ports, kind)?;
+                for n in counts {
+                    write!(f, " {:>column$}", n)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        if self.coarsened {
+            writeln!(
+                f,
+                "(coarsened to {} ports a row to stay small)",
+                self.port_width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+
+    fn timed(host: &str, port: u16, connect_ms: f64, banner_ms: Option<f64>) -> PortResult {
+        let mut r = PortResult::new(host.parse().unwrap(), port, PortStatus::Open);
+        r.timing = Some(Timing {
+            connect_ms,
+            banner_ms,
+        });
+        r
+    }
+
+    fn options(bins: &str, port_width: u32, max_cells: usize) -> HeatmapOptions {
+        HeatmapOptions {
+            bins: bins.parse().unwrap(),
+            port_width,
+            max_cells,
+        }
+    }
+
+    #[test]
+    fn times_are_counted_by_host_port_range_and_bin() {
+        let results = vec![
+            timed("10.0.0.1", 22, 0.5, Some(3.0)),
+            timed("10.0.0.1", 80, 1.0, None),
+            timed("10.0.0.1", 8080, 20.0, Some(200.0)),
+            timed("10.0.0.2", 443, 7.0, None),
+            PortResult::new("10.0.0.2".parse().unwrap(), 444, PortStatus::Filtered),
+        ];
+        let map = heatmap(&results, &options("1,10,100", 1024, 1000));
+
+        assert_eq!(map.bins_ms, [1.0, 10.0, 100.0]);
+        assert!(!map.coarsened);
+        let rows: Vec<_> = map
+            .rows
+            .iter()
+            .map(|r| {
+                (
+                    r.host.unwrap().to_string(),
+                    r.first_port,
+                    r.last_port,
+                    r.connect.clone(),
+                    r.banner.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    "10.0.0.1".into(),
+                    0,
+                    1023,
+                    vec![2, 0, 0, 0],
+                    vec![0, 1, 0, 0]
+                ),
+                (
+                    "10.0.0.1".into(),
+                    7168,
+                    8191,
+                    vec![0, 0, 1, 0],
+                    vec![0, 0, 0, 1]
+                ),
+                (
+                    "10.0.0.2".into(),
+                    0,
+                    1023,
+                    vec![0, 1, 0, 0],
+                    vec![0, 0, 0, 0]
+                ),
+            ]
+        );
+        assert_eq!(map.for_host("10.0.0.2".parse().unwrap()).rows.len(), 1);
+    }
+
+    #[test]
+    fn large_maps_are_coarsened_without_losing_counts() {
+        let results: Vec<PortResult> = (0..4)
+            .flat_map(|host| {
+                (0..8).map(move |i| timed(&format!("10.0.0.{}", host), i * 8192, 2.0, None))
+            })
+            .collect();
+        // 4 hosts of 8 rows, 2 kinds of 2 bins: 128 counts.
+        let map = heatmap(&results, &options("1", 8192, 128));
+        assert!(!map.coarsened);
+        assert_eq!(map.rows.len(), 32);
+
+        let map = heatmap(&results, &options("1", 8192, 40));
+        assert!(map.coarsened);
+        assert_eq!(map.port_width, 32768);
+        assert_eq!(map.rows.len(), 8);
+        assert_eq!(map.rows[0].connect, [0, 4]);
+
+        let map = heatmap(&results, &options("1", 8192, 4));
+        assert_eq!(map.port_width, 65536);
+        assert_eq!(map.rows.len(), 1);
+        assert_eq!(map.rows[0].host, None);
+        assert_eq!((map.rows[0].first_port, map.rows[0].last_port), (0, 65535));
+        assert_eq!(map.rows[0].connect, [0, 32]);
+    }
+
+    #[test]
+    fn bins_must_be_increasing_positive_milliseconds() {
+        assert_eq!("0.5, 2ms,10".parse(), Ok(Bins(vec![0.5, 2.0, 10.0])));
+        assert!("5,1".parse::<Bins>().is_err());
+        assert!("0".parse::<Bins>().is_err());
+        assert!("fast".parse::<Bins>().is_err());
+    }
+}
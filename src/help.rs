@@ -0,0 +1,408 @@
+//! Help for the mistakes new users make most: the annotated invocations of
+//! `port-scanner examples`, and the hint printed under an error, pointing
+//! at the part of the command line to change and how.
+
+use std::error::Error;
+
+use crate::groups::PortGroups;
+use crate::plan::{PortSet, ProtocolPorts};
+use crate::resolve::ResolveError;
+use crate::scanner::ScanError;
+use crate::spec::{SpecError, SpecErrorKind};
+use crate::targets::TargetError;
+
+/// What `port-scanner examples` prints.
+pub const EXAMPLES: &str = "\
+Scan the first 1024 ports of one host:
+  port-scanner -t 192.168.1.10
+
+Scan a few ports and ranges; a range runs from the lower port to the
+higher, and port 0 is never scanned:
+  port-scanner -t 192.168.1.10 -p 22,80,443,8000-8100
+
+Scan a CIDR block and an address range, leaving the gateway out:
+  port-scanner -t 10.0.0.0/24,10.0.1.5-10.0.1.20 --exclude 10.0.0.1
+
+Scan a host name, asking a DNS server of your choice rather than the
+system resolver:
+  port-scanner -t scanme.example.com --resolver 1.1.1.1:53
+
+Name ports by group; --list-port-groups shows the groups:
+  port-scanner -t 192.168.1.10 -p @web,@mail
+
+Leave some ports of a range out:
+  port-scanner -t 192.168.1.10 -p 1-1024 --exclude-ports 135-139
+
+Tell the services on every port by their first bytes:
+  port-scanner -t 192.168.1.10 -p 1-65535 --sniff
+
+Write a JSON report, then check the next scan against it:
+  port-scanner -t 10.0.0.0/24 --json scan.json
+  port-scanner -t 10.0.0.0/24 --baseline scan.json
+
+See what a scan would do without scanning:
+  port-scanner -t 10.0.0.0/16 -p @web --dry-run
+";
+
+/// The parts of the command line a hint may point at.
+#[derive(Debug, Clone, Default)]
+pub struct Given {
+    /// The targets, as given.
+    pub targets: Vec<String>,
+    /// The `--ports` specification.
+    pub ports: String,
+    /// The `--exclude-ports` specification.
+    pub exclude_ports: Option<String>,
+    /// The groups the specifications may name.
+    pub groups: PortGroups,
+    /// Whether `--resolver` or `--doh` named the servers to ask.
+    pub resolvers: bool,
+}
+
+/// The hint for `error`, when it is a mistake with a known remedy.
+pub fn hint(error: &(dyn Error + 'static), given: &Given) -> Option<String> {
+    match error.downcast_ref::<TargetError>() {
+        Some(TargetError::Unresolvable(e)) => return hint(e, given),
+        Some(TargetError::Invalid(e)) => return hint(e, given),
+        Some(_) => return None,
+        None => {}
+    }
+    if let Some(e) = error.downcast_ref::<ScanError>() {
+        return match e {
+            ScanError::InvalidPorts(e) => ports_hint(e, given),
+            ScanError::InvalidTarget(e) => target_hint(e, given),
+            ScanError::Resolution(_) => Some(resolution_hint(given)),
+            ScanError::NoTargets => {
+                Some("hint: name the hosts with --target, as in --target 192.168.1.0/24".into())
+            }
+            _ => None,
+        };
+    }
+    error
+        .downcast_ref::<ResolveError>()
+        .map(|_| resolution_hint(given))
+}
+
+fn ports_hint(e: &SpecError, given: &Given) -> Option<String> {
+    let exclude_ports = given.exclude_ports.as_deref().unwrap_or("");
+    let (flag, spec) = if points_into(e, &given.ports) {
+        ("--ports", given.ports.as_str())
+    } else if points_into(e, exclude_ports) {
+        ("--exclude-ports", exclude_ports)
+    } else {
+        ("", "")
+    };
+    let advice = match e.kind {
+        SpecErrorKind::StartAfterEnd => reversed(&e.fragment),
+        SpecErrorKind::InvalidPort if e.fragment.bytes().any(|b| b.is_ascii_alphabetic()) => {
+            "hint: ports are numbers from 1 to 65535; name a group of them with @, \
+             as in @web (--list-port-groups shows them)"
+                .to_string()
+        }
+        SpecErrorKind::InvalidPort => "hint: ports are numbers from 1 to 65535".to_string(),
+        SpecErrorKind::InvalidRange => {
+            "hint: a range is two ports joined by '-', as in 8000-8100".to_string()
+        }
+        SpecErrorKind::NoPorts => return Some(no_ports(given)),
+        _ => return None,
+    };
+    Some(match flag {
+        "" => advice,
+        flag => format!("{}\n{}", caret(flag, spec, e), advice),
+    })
+}
+
+fn target_hint(e: &SpecError, given: &Given) -> Option<String> {
+    let target = given.targets.iter().find(|t| points_into(e, t));
+    let advice = match e.kind {
+        SpecErrorKind::StartAfterEnd => reversed(&e.fragment),
+        SpecErrorKind::InvalidAddress => {
+            let whole = target.map_or(e.fragment.as_str(), String::as_str);
+            match host_and_port(whole) {
+                Some((host, Some(port))) => format!(
+                    "hint: give the host and the port apart: --target {} --ports {}",
+                    host, port
+                ),
+                Some((host, None)) => format!("hint: give the host alone: --target {}", host),
+                None if whole.bytes().all(|b| b.is_ascii_digit() || b == b'.') => {
+                    "hint: an IPv4 address is four numbers from 0 to 255, as in 192.168.1.10"
+                        .to_string()
+                }
+                None => "hint: a target is a host name, an address, a CIDR block \
+                         (10.0.0.0/24) or a range (10.0.0.5-10.0.0.20)"
+                    .to_string(),
+            }
+        }
+        SpecErrorKind::InvalidPrefix => {
+            "hint: a prefix length is at most 32 for IPv4 and 128 for IPv6".to_string()
+        }
+        SpecErrorKind::MixedFamilies => {
+            "hint: both ends of a range are IPv4, or both IPv6".to_string()
+        }
+        _ => return None,
+    };
+    Some(match target {
+        Some(target) => format!("{}\n{}", caret("--target", target, e), advice),
+        None => advice,
+    })
+}
+
+fn resolution_hint(given: &Given) -> String {
+    if given.resolvers {
+        "hint: check the spelling of the name, and that the servers of --resolver or \
+         --doh answer; --resolver-timeout gives slow ones longer"
+            .to_string()
+    } else {
+        "hint: check the spelling of the name and the DNS settings of this machine, \
+         or ask a server of your choice with --resolver, as in --resolver 1.1.1.1:53"
+            .to_string()
+    }
+}
+
+/// Why no port was left: only port 0 was asked for, or the exclusions
+/// took every port, naming those that took some.
+fn no_ports(given: &Given) -> String {
+    let asked = match ProtocolPorts::parse_with(&given.ports, &given.groups) {
+        Ok(ports) if !ports.tcp.is_empty() => ports.tcp,
+        _ => {
+            return "hint: port 0 is never scanned; ports go from 1 to 65535, \
+                    as in --ports 1-1024"
+                .to_string()
+        }
+    };
+    let exclude_ports = given.exclude_ports.as_deref().unwrap_or("");
+    let mut lines = vec![format!(
+        "hint: --exclude-ports {:?} leaves none of --ports {:?}:",
+        exclude_ports, given.ports
+    )];
+    for (item, ports) in exclusions(exclude_ports, &given.groups) {
+        let taken: PortSet = asked.iter().filter(|&p| ports.contains(p)).collect();
+        if !taken.is_empty() {
+            lines.push(format!("  {} excludes {}", item, taken));
+        }
+    }
+    lines.push("drop those exclusions, or scan more ports".to_string());
+    lines.join("\n")
+}
+
+/// Each item of an `--exclude-ports` specification and the TCP ports it
+/// names, reading each under the protocol prefix before it.
+fn exclusions<'a>(spec: &'a str, groups: &PortGroups) -> Vec<(&'a str, PortSet)> {
+    let mut prefix = "";
+    let mut items = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let ports = match item.split_once(':') {
+            Some((p, _)) => {
+                prefix = p;
+                ProtocolPorts::parse_with(item, groups)
+            }
+            None if prefix.is_empty() => ProtocolPorts::parse_with(item, groups),
+            None => ProtocolPorts::parse_with(&format!("{}:{}", prefix, item), groups),
+        };
+        if let Ok(ports) = ports {
+            items.push((item, ports.tcp));
+        }
+    }
+    items
+}
+
+/// `start-end` the right way round.
+fn reversed(fragment: &str) -> String {
+    match fragment.split_once('-') {
+        Some((start, end)) => format!(
+            "hint: a range runs from the lower end to the higher: {}-{}",
+            end.trim(),
+            start.trim()
+        ),
+        None => "hint: a range runs from the lower end to the higher".to_string(),
+    }
+}
+
+/// The host, and the port if any, of a target given as a URL or as
+/// `host:port`; `None` when it is neither.
+fn host_and_port(target: &str) -> Option<(&str, Option<&str>)> {
+    let (rest, url) = match target.split_once("://") {
+        Some((_, rest)) => (rest, true),
+        None => (target, false),
+    };
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            (host, Some(port))
+        }
+        _ => (authority, None),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    (url || port.is_some()).then_some((host, port))
+}
+
+/// Whether the fragment of `e` is found in `spec` where `e` says.
+fn points_into(e: &SpecError, spec: &str) -> bool {
+    !e.fragment.is_empty()
+        && spec.get(e.position..e.position + e.fragment.len()) == Some(e.fragment.as_str())
+}
+
+/// `flag spec`, and under it carets marking the fragment of `e`.
+fn caret(flag: &str, spec: &str, e: &SpecError) -> String {
+    let indent = flag.chars().count() + 1 + spec[..e.position].chars().count();
+    format!(
+        "  {} {}\n  {}{}",
+        flag,
+        spec,
+        " ".repeat(indent),
+        "^".repeat(e.fragment.chars().count())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::TargetSet;
+
+    fn given(ports: &str, exclude_ports: Option<&str>) -> Given {
+        Given {
+            targets: vec!["127.0.0.1".to_string()],
+            ports: ports.to_string(),
+            exclude_ports: exclude_ports.map(str::to_string),
+            ..Given::default()
+        }
+    }
+
+    fn ports_error(spec: &str) -> ScanError {
+        ScanError::InvalidPorts(PortSet::parse(spec).unwrap_err())
+    }
+
+    fn target_error(given: &mut Given, target: &str) -> ScanError {
+        given.targets = vec![target.to_string()];
+        ScanError::InvalidTarget(TargetSet::new().insert(target).unwrap_err())
+    }
+
+    fn no_ports() -> ScanError {
+        ScanError::InvalidPorts(SpecError::new(SpecErrorKind::NoPorts, "", 0))
+    }
+
+    #[test]
+    fn a_reversed_range_is_pointed_at_and_turned_round() {
+        assert_eq!(
+            hint(&ports_error("22,90-80"), &given("22,90-80", None)).unwrap(),
+            "  --ports 22,90-80\n\
+             \x20            ^^^^^\n\
+             hint: a range runs from the lower end to the higher: 80-90"
+        );
+
+        assert_eq!(
+            hint(&ports_error("200-100"), &given("1-1024", Some("200-100"))).unwrap(),
+            "  --exclude-ports 200-100\n\
+             \x20                 ^^^^^^^\n\
+             hint: a range runs from the lower end to the higher: 100-200"
+        );
+    }
+
+    #[test]
+    fn bad_ports_say_what_a_port_is() {
+        assert_eq!(
+            hint(&ports_error("80,http"), &given("80,http", None)).unwrap(),
+            "  --ports 80,http\n\
+             \x20            ^^^^\n\
+             hint: ports are numbers from 1 to 65535; name a group of them with @, \
+             as in @web (--list-port-groups shows them)"
+        );
+        assert_eq!(
+            hint(&ports_error("70000"), &given("70000", None)).unwrap(),
+            "  --ports 70000\n\
+             \x20         ^^^^^\n\
+             hint: ports are numbers from 1 to 65535"
+        );
+        assert_eq!(
+            hint(&ports_error("1-2-3"), &given("1-2-3", None)).unwrap(),
+            "  --ports 1-2-3\n\
+             \x20         ^^^^^\n\
+             hint: a range is two ports joined by '-', as in 8000-8100"
+        );
+    }
+
+    #[test]
+    fn port_zero_alone_is_explained() {
+        assert_eq!(
+            hint(&no_ports(), &given("0", None)).unwrap(),
+            "hint: port 0 is never scanned; ports go from 1 to 65535, as in --ports 1-1024"
+        );
+    }
+
+    #[test]
+    fn the_exclusions_that_took_every_port_are_named() {
+        let given = given("22,80,443", Some("T:22,U:80,T:1-100,443,8000"));
+        assert_eq!(
+            hint(&no_ports(), &given).unwrap(),
+            "hint: --exclude-ports \"T:22,U:80,T:1-100,443,8000\" leaves none of --ports \"22,80,443\":\n\
+             \x20 T:22 excludes 22\n\
+             \x20 T:1-100 excludes 22,80\n\
+             \x20 443 excludes 443\n\
+             drop those exclusions, or scan more ports"
+        );
+    }
+
+    #[test]
+    fn bad_targets_are_pointed_at_with_a_remedy() {
+        let mut given = given("80", None);
+        assert_eq!(
+            hint(&target_error(&mut given, "10.0.0.9-10.0.0.1"), &given).unwrap(),
+            "  --target 10.0.0.9-10.0.0.1\n\
+             \x20          ^^^^^^^^^^^^^^^^^\n\
+             hint: a range runs from the lower end to the higher: 10.0.0.1-10.0.0.9"
+        );
+        assert_eq!(
+            hint(&target_error(&mut given, "10.0.0.1:8080"), &given).unwrap(),
+            "  --target 10.0.0.1:8080\n\
+             \x20          ^^^^^^^^^^^^^\n\
+             hint: give the host and the port apart: --target 10.0.0.1 --ports 8080"
+        );
+        assert_eq!(
+            hint(&target_error(&mut given, "http://example.com/"), &given).unwrap(),
+            "  --target http://example.com/\n\
+             \x20          ^^^^^\n\
+             hint: give the host alone: --target example.com"
+        );
+        assert_eq!(
+            hint(&target_error(&mut given, "10.0.0.256"), &given).unwrap(),
+            "  --target 10.0.0.256\n\
+             \x20          ^^^^^^^^^^\n\
+             hint: an IPv4 address is four numbers from 0 to 255, as in 192.168.1.10"
+        );
+        assert_eq!(
+            hint(&target_error(&mut given, "10.0.0.0/33"), &given).unwrap(),
+            "  --target 10.0.0.0/33\n\
+             \x20                   ^^\n\
+             hint: a prefix length is at most 32 for IPv4 and 128 for IPv6"
+        );
+    }
+
+    #[test]
+    fn unresolved_names_point_at_the_resolvers() {
+        let error = ResolveError {
+            name: "scanme.invalid".to_string(),
+            tried: vec!["system".to_string()],
+            message: "no record found".to_string(),
+        };
+        let mut given = given("80", None);
+        assert_eq!(
+            hint(&error, &given).unwrap(),
+            "hint: check the spelling of the name and the DNS settings of this machine, \
+             or ask a server of your choice with --resolver, as in --resolver 1.1.1.1:53"
+        );
+        given.resolvers = true;
+        assert_eq!(
+            hint(&error, &given).unwrap(),
+            "hint: check the spelling of the name, and that the servers of --resolver or \
+             --doh answer; --resolver-timeout gives slow ones longer"
+        );
+    }
+
+    #[test]
+    fn other_errors_have_no_hint() {
+        assert_eq!(hint(&ScanError::ZeroTimeout, &given("80", None)), None);
+    }
+}
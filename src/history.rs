@@ -0,0 +1,607 @@
+//! `port-scanner history stability`: how steadily each port of a host has
+//! been open over the reports of earlier scans of it.
+//!
+//! The history is the reports the scans wrote: `--json` reports, or nmap
+//! and masscan output read as for `--baseline`. Each scan is dated by the
+//! start time in its scan id, or failing that by the modification time of
+//! its file. A scan only tells about the ports it has a result for: a
+//! port missing from a report, because the scan covered other ports, was
+//! interrupted, or filtered or spilled its results, is unobserved in that
+//! scan rather than closed. Scans without any result for a host don't
+//! count for its ports at all.
+//!
+//! A host is followed by its key (see [`identity`](crate::identity)): by
+//! default its address, or with `--identity-by name` the name it was
+//! scanned by, so that its ports stay one history when its address
+//! changes. A report without names for a host, as when it was scanned by
+//! address, takes the names the latest report that has them recorded for
+//! that address.
+//!
+//! `--tag KEY=VALUE` keeps only the scans whose report carries every tag
+//! given, as recorded by the scan's own `--tag`s; reports of other
+//! scanners have none.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::identity::{HostKey, Identities, IdentityMode};
+use crate::import::{self, ImportError};
+use crate::metadata::Tag;
+use crate::output::csv_field;
+use crate::plan::TargetSet;
+use crate::scanner::{PortStatus, ScanSummary};
+use crate::spec::Protocol;
+
+/// The number of changes between open and not open at or above which a
+/// port is flapping, unless `--flapping` says otherwise.
+pub const DEFAULT_FLAPPING: usize = 3;
+
+/// Why the history could not be read.
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    /// A directory of reports could not be listed.
+    #[error("Cannot read {path}: {source}")]
+    Read {
+        /// The directory.
+        path: String,
+        /// What went wrong.
+        #[source]
+        source: io::Error,
+    },
+
+    /// A report could not be read or understood.
+    #[error(transparent)]
+    Report(#[from] ImportError),
+}
+
+/// One scan of the history: when it started and the status of each port
+/// it has a result for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+    /// When the scan started.
+    pub at: DateTime<Utc>,
+    /// Host, protocol, port and status of each result.
+    pub results: Vec<(IpAddr, Protocol, u16, PortStatus)>,
+    /// What the report identified hosts by.
+    pub identity: IdentityMode,
+    /// The names of its hosts.
+    pub names: Identities,
+    /// The `--tag`s it was run with.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Scan {
+    /// The scan of `summary`, dated by its scan id or else by `fallback`.
+    pub fn from_summary(summary: &ScanSummary, fallback: DateTime<Utc>) -> Self {
+        Scan {
+            at: scan_time(&summary.scan_id).unwrap_or(fallback),
+            results: summary
+                .results
+                .iter()
+                .map(|r| (r.target, r.protocol, r.port, r.status.clone()))
+                .collect(),
+            identity: summary.identity,
+            names: Identities::of(summary, summary.identity),
+            tags: summary.metadata.tags.clone(),
+        }
+    }
+
+    /// Whether the scan was run with every one of `tags`.
+    pub fn has_tags(&self, tags: &[Tag]) -> bool {
+        tags.iter()
+            .all(|tag| self.tags.get(&tag.key) == Some(&tag.value))
+    }
+}
+
+/// The start of a scan from its id: the milliseconds since the epoch in
+/// hex, before the process id when there is one.
+pub fn scan_time(scan_id: &str) -> Option<DateTime<Utc>> {
+    let millis = scan_id.split('-').next()?;
+    let millis = i64::from_str_radix(millis, 16).ok()?;
+    Utc.timestamp_millis_opt(millis).single()
+}
+
+/// Reads the reports at `paths` into scans. A directory stands for every
+/// report in it; files in it that aren't reports, such as text or CSV
+/// reports written alongside, are skipped.
+pub fn load(paths: &[PathBuf]) -> Result<Vec<Scan>, HistoryError> {
+    let mut scans = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            scans.push(load_one(path)?);
+            continue;
+        }
+        let read = |source| HistoryError::Read {
+            path: path.display().to_string(),
+            source,
+        };
+        let mut files: Vec<PathBuf> = fs::read_dir(path)
+            .map_err(read)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()
+            .map_err(read)?;
+        files.sort();
+        for file in files.iter().filter(|f| f.is_file()) {
+            match load_one(file) {
+                Ok(scan) => scans.push(scan),
+                Err(HistoryError::Report(ImportError::Unrecognized { .. })) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(scans)
+}
+
+fn load_one(path: &Path) -> Result<Scan, HistoryError> {
+    let (summary, at) = load_dated(path)?;
+    Ok(Scan::from_summary(&summary, at))
+}
+
+/// Reads the report at `path`, with when its scan started: by its scan id,
+/// or failing that by the modification time of the file.
+pub fn load_dated(path: &Path) -> Result<(ScanSummary, DateTime<Utc>), ImportError> {
+    let summary = import::load(path, None)?;
+    let at = scan_time(&summary.scan_id).unwrap_or_else(|| {
+        fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_or(DateTime::UNIX_EPOCH, DateTime::<Utc>::from)
+    });
+    Ok((summary, at))
+}
+
+/// How steadily one port has been open.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stability {
+    /// Host the port belongs to, as last observed.
+    pub target: IpAddr,
+    /// The name the host is followed by, with `--identity-by name`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The port's protocol.
+    pub protocol: Protocol,
+    /// The port.
+    pub port: u16,
+    /// Scans with a result for the host.
+    pub scans: usize,
+    /// Of those, the scans with a result for the port.
+    pub observed: usize,
+    /// Of those, the scans that found it open.
+    pub open: usize,
+    /// `open` as a percentage of `observed`.
+    pub open_percent: f64,
+    /// Start of the first scan that found it open.
+    pub first_seen: DateTime<Utc>,
+    /// Start of the last scan that found it open.
+    pub last_seen: DateTime<Utc>,
+    /// What the last scan that observed it found.
+    pub last_status: PortStatus,
+    /// Times it went from open to not open or back, between one scan that
+    /// observed it and the next.
+    pub changes: usize,
+    /// Whether `changes` reached the flapping threshold.
+    pub flapping: bool,
+}
+
+/// The stability of every port found open at least once in the history,
+/// by host, protocol and port.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StabilityReport {
+    /// Scans in the history, of any host.
+    pub scans: usize,
+    /// The changes at or above which a port counts as flapping.
+    pub flapping_threshold: usize,
+    /// One entry per port.
+    pub ports: Vec<Stability>,
+}
+
+/// Works out the stability of the ports of the hosts in `hosts`, or of
+/// every host, over `scans` in the order they started, following each
+/// host by its key in `mode`. A port is flapping once it changed between
+/// open and not open `flapping` times.
+pub fn stability(
+    scans: &[Scan],
+    hosts: Option<&TargetSet>,
+    flapping: usize,
+    mode: IdentityMode,
+) -> StabilityReport {
+    let mut scans: Vec<&Scan> = scans.iter().collect();
+    scans.sort_by_key(|s| s.at);
+    let wanted = |host: IpAddr| hosts.is_none_or(|h| h.contains(host));
+    let hosts_of = |scan: &Scan| -> BTreeSet<IpAddr> { scan.results.iter().map(|r| r.0).collect() };
+
+    // The latest names of each address, for reports without any.
+    let mut latest = Identities::new(mode);
+    for scan in scans.iter().rev() {
+        latest.adopt(&scan.names, &hosts_of(scan));
+    }
+
+    let mut scans_of: BTreeMap<HostKey, usize> = BTreeMap::new();
+    // Where and when each port was observed and what was found.
+    type Observations = Vec<(IpAddr, DateTime<Utc>, PortStatus)>;
+    let mut seen: BTreeMap<(HostKey, Protocol, u16), Observations> = BTreeMap::new();
+    for scan in &scans {
+        let hosts = hosts_of(scan);
+        let mut names = Identities::new(mode);
+        names.adopt(&scan.names, &hosts);
+        names.adopt(&latest, &hosts);
+        let keys: BTreeSet<HostKey> = hosts
+            .iter()
+            .filter(|&&h| wanted(h))
+            .map(|&h| names.key(h))
+            .collect();
+        for key in keys {
+            *scans_of.entry(key).or_default() += 1;
+        }
+        for (host, protocol, port, status) in &scan.results {
+            if wanted(*host) {
+                seen.entry((names.key(*host), *protocol, *port))
+                    .or_default()
+                    .push((*host, scan.at, status.clone()));
+            }
+        }
+    }
+
+    let ports = seen
+        .into_iter()
+        .filter_map(|((key, protocol, port), observations)| {
+            let open_at: Vec<DateTime<Utc>> = observations
+                .iter()
+                .filter(|(_, _, status)| *status == PortStatus::Open)
+                .map(|(_, at, _)| *at)
+                .collect();
+            let changes = observations
+                .windows(2)
+                .filter(|w| (w[0].2 == PortStatus::Open) != (w[1].2 == PortStatus::Open))
+                .count();
+            Some(Stability {
+                target: observations.last()?.0,
+                name: key.name().map(str::to_string),
+                protocol,
+                port,
+                scans: scans_of[&key],
+                observed: observations.len(),
+                open: open_at.len(),
+                open_percent: 100.0 * open_at.len() as f64 / observations.len() as f64,
+                first_seen: *open_at.first()?,
+                last_seen: *open_at.last()?,
+                last_status: observations.last()?.2.clone(),
+                changes,
+                flapping: changes >= flapping,
+            })
+        })
+        .collect();
+    StabilityReport {
+        scans: scans.len(),
+        flapping_threshold: flapping,
+        ports,
+    }
+}
+
+impl StabilityReport {
+    /// One line per port, with a header.
+    pub fn csv(&self) -> String {
+        let mut out = String::from(
+            "target,protocol,port,scans,observed,open,open_percent,first_seen,last_seen,last_status,changes,flapping\n",
+        );
+        for s in &self.ports {
+            let row = [
+                csv_field(&s.name.clone().unwrap_or_else(|| s.target.to_string())),
+                s.protocol.to_string(),
+                s.port.to_string(),
+                s.scans.to_string(),
+                s.observed.to_string(),
+                s.open.to_string(),
+                format!("{:.1}", s.open_percent),
+                s.first_seen.to_rfc3339(),
+                s.last_seen.to_rfc3339(),
+                status_name(&s.last_status).to_string(),
+                s.changes.to_string(),
+                s.flapping.to_string(),
+            ];
+            out.push_str(&row.join(","));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn status_name(status: &PortStatus) -> &'static str {
+    match status {
+        PortStatus::Open => "open",
+        PortStatus::Closed => "closed",
+        PortStatus::Filtered => "filtered",
+    }
+}
+
+/// A table of the ports, the flapping ones marked, under a line counting
+/// the scans.
+impl fmt::Display for StabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flapping = self.ports.iter().filter(|s| s.flapping).count();
+        writeln!(
+            f,
+            "{} scans, {} ports open at least once, {} flapping ({}+ changes)",
+            self.scans,
+            self.ports.len(),
+            flapping,
+            self.flapping_threshold
+        )?;
+        if self.ports.is_empty() {
+            return Ok(());
+        }
+        let ports: Vec<String> = self
+            .ports
+            .iter()
+            .map(|s| match (&s.name, s.target) {
+                (Some(name), _) => format!("{}:{}/{}", name, s.port, s.protocol),
+                (None, IpAddr::V4(ip)) => format!("{}:{}/{}", ip, s.port, s.protocol),
+                (None, IpAddr::V6(ip)) => format!("[{}]:{}/{}", ip, s.port, s.protocol),
+            })
+            .collect();
+        let width = ports.iter().map(String::len).max().unwrap_or(0).max(4);
+        writeln!(
+            f,
+            "{:<width$}  {:>13}  {:<16}  {:<16}  {:<8}  CHANGES",
+            "PORT", "OPEN", "FIRST SEEN", "LAST SEEN", "LAST"
+        )?;
+        for (s, port) in self.ports.iter().zip(&ports) {
+            let open = format!("{:.0}% ({}/{})", s.open_percent, s.open, s.observed);
+            let unobserved = s.scans - s.observed;
+            writeln!(
+                f,
+                "{:<width$}  {:>13}  {:<16}  {:<16}  {:<8}  {}{}{}",
+                port,
+                open,
+                s.first_seen.format("%Y-%m-%d %H:%M"),
+                s.last_seen.format("%Y-%m-%d %H:%M"),
+                status_name(&s.last_status),
+                s.changes,
+                if s.flapping { ", flapping" } else { "" },
+                match unobserved {
+                    0 => String::new(),
+                    n => format!(", unobserved in {} of {} scans", n, s.scans),
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "10.0.0.5";
+    const OTHER: &str = "10.0.0.6";
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, day, 12, 0, 0).unwrap()
+    }
+
+    fn scan(day: u32, results: &[(&str, u16, PortStatus)]) -> Scan {
+        Scan {
+            at: at(day),
+            results: results
+                .iter()
+                .map(|(host, port, status)| {
+                    (host.parse().unwrap(), Protocol::Tcp, *port, status.clone())
+                })
+                .collect(),
+            identity: IdentityMode::Address,
+            names: Identities::default(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Five scans of HOST, listed out of order, and one of OTHER only: 22
+    /// always open, 80 open then closed, 8080 toggling every scan, and 443
+    /// open in the two scans that covered it.
+    fn history() -> Vec<Scan> {
+        use PortStatus::*;
+        vec![
+            scan(
+                3,
+                &[(HOST, 22, Open), (HOST, 80, Closed), (HOST, 8080, Open)],
+            ),
+            scan(
+                1,
+                &[
+                    (HOST, 22, Open),
+                    (HOST, 80, Open),
+                    (HOST, 8080, Open),
+                    (HOST, 443, Open),
+                ],
+            ),
+            scan(
+                2,
+                &[(HOST, 22, Open), (HOST, 80, Open), (HOST, 8080, Filtered)],
+            ),
+            scan(
+                4,
+                &[(HOST, 22, Open), (HOST, 80, Closed), (HOST, 8080, Closed)],
+            ),
+            scan(
+                5,
+                &[(HOST, 22, Open), (HOST, 8080, Open), (HOST, 443, Open)],
+            ),
+            scan(6, &[(OTHER, 22, Open)]),
+        ]
+    }
+
+    fn port(report: &StabilityReport, port: u16) -> &Stability {
+        report.ports.iter().find(|s| s.port == port).unwrap()
+    }
+
+    #[test]
+    fn ports_are_measured_over_the_scans_that_observed_them() {
+        let mut hosts = TargetSet::new();
+        hosts.insert(HOST).unwrap();
+        let report = stability(
+            &history(),
+            Some(&hosts),
+            DEFAULT_FLAPPING,
+            IdentityMode::Address,
+        );
+
+        assert_eq!(report.scans, 6);
+        let ports: Vec<u16> = report.ports.iter().map(|s| s.port).collect();
+        assert_eq!(ports, [22, 80, 443, 8080]);
+
+        let ssh = port(&report, 22);
+        assert_eq!((ssh.scans, ssh.observed, ssh.open), (5, 5, 5));
+        assert_eq!(ssh.open_percent, 100.0);
+        assert_eq!((ssh.first_seen, ssh.last_seen), (at(1), at(5)));
+        assert_eq!((ssh.changes, ssh.flapping), (0, false));
+
+        let http = port(&report, 80);
+        assert_eq!((http.observed, http.open), (4, 2));
+        assert_eq!(http.open_percent, 50.0);
+        assert_eq!((http.first_seen, http.last_seen), (at(1), at(2)));
+        assert_eq!(http.last_status, PortStatus::Closed);
+        assert_eq!((http.changes, http.flapping), (1, false));
+
+        // Unobserved in three scans, which count neither way.
+        let https = port(&report, 443);
+        assert_eq!((https.scans, https.observed, https.open), (5, 2, 2));
+        assert_eq!(https.open_percent, 100.0);
+        assert_eq!(https.changes, 0);
+
+        // Filtered and closed both count as not open.
+        let alt = port(&report, 8080);
+        assert_eq!((alt.observed, alt.open, alt.changes), (5, 3, 4));
+        assert_eq!(alt.open_percent, 60.0);
+        assert!(alt.flapping);
+    }
+
+    #[test]
+    fn the_flapping_threshold_is_configurable() {
+        let report = stability(&history(), None, 1, IdentityMode::Address);
+        let flapping: Vec<(IpAddr, u16)> = report
+            .ports
+            .iter()
+            .filter(|s| s.flapping)
+            .map(|s| (s.target, s.port))
+            .collect();
+        let host: IpAddr = HOST.parse().unwrap();
+        assert_eq!(flapping, [(host, 80), (host, 8080)]);
+        assert_eq!(report.ports.len(), 5);
+        assert_eq!(port(&report, 22).scans, 5);
+    }
+
+    #[test]
+    fn ports_never_open_are_left_out() {
+        let report = stability(
+            &[
+                scan(1, &[(HOST, 23, PortStatus::Closed)]),
+                scan(2, &[(HOST, 23, PortStatus::Filtered)]),
+            ],
+            None,
+            DEFAULT_FLAPPING,
+            IdentityMode::Address,
+        );
+        assert_eq!(report.ports, []);
+        assert_eq!(
+            report.to_string(),
+            "2 scans, 0 ports open at least once, 0 flapping (3+ changes)\n"
+        );
+    }
+
+    #[test]
+    fn the_report_reads_as_a_table_and_as_csv() {
+        let mut hosts = TargetSet::new();
+        hosts.insert(HOST).unwrap();
+        let report = stability(
+            &history(),
+            Some(&hosts),
+            DEFAULT_FLAPPING,
+            IdentityMode::Address,
+        );
+
+        assert_eq!(
+            report.to_string(),
+            "6 scans, 4 ports open at least once, 1 flapping (3+ changes)\n\
+             PORT                        OPEN  FIRST SEEN        LAST SEEN         LAST      CHANGES\n\
+             10.0.0.5:22/tcp       100% (5/5)  2026-03-01 12:00  2026-03-05 12:00  open      0\n\
+             10.0.0.5:80/tcp        50% (2/4)  2026-03-01 12:00  2026-03-02 12:00  closed    1, unobserved in 1 of 5 scans\n\
+             10.0.0.5:443/tcp      100% (2/2)  2026-03-01 12:00  2026-03-05 12:00  open      0, unobserved in 3 of 5 scans\n\
+             10.0.0.5:8080/tcp      60% (3/5)  2026-03-01 12:00  2026-03-05 12:00  open      4, flapping\n"
+        );
+        let csv = report.csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[2],
+            "10.0.0.5,tcp,80,5,4,2,50.0,2026-03-01T12:00:00+00:00,2026-03-02T12:00:00+00:00,closed,1,false"
+        );
+    }
+
+    #[test]
+    fn hosts_are_followed_by_name_from_ip_to_hostname() {
+        use PortStatus::*;
+        let named = |day, host: &str, status| {
+            let mut scan = scan(day, &[(host, 22, status)]);
+            scan.identity = IdentityMode::Name;
+            scan.names = Identities::new(IdentityMode::Name);
+            scan.names.add(host.parse().unwrap(), "db01.corp");
+            scan
+        };
+        // By IP, then by name, then by name at its new address.
+        let scans = [
+            scan(1, &[(HOST, 22, Open)]),
+            named(2, HOST, Closed),
+            named(3, "10.0.0.9", Open),
+        ];
+
+        let report = stability(&scans, None, 1, IdentityMode::Name);
+        assert_eq!(report.ports.len(), 1);
+        let ssh = &report.ports[0];
+        assert_eq!(ssh.name.as_deref(), Some("db01.corp"));
+        assert_eq!(ssh.target, "10.0.0.9".parse::<IpAddr>().unwrap());
+        assert_eq!((ssh.scans, ssh.open, ssh.changes), (3, 2, 2));
+        assert!(report.to_string().contains("\ndb01.corp:22/tcp "));
+        assert!(report.csv().contains("\ndb01.corp,tcp,22,3,3,2,"));
+
+        let report = stability(&scans, None, 1, IdentityMode::Address);
+        let hosts: Vec<(IpAddr, usize)> =
+            report.ports.iter().map(|s| (s.target, s.scans)).collect();
+        assert_eq!(
+            hosts,
+            [(HOST.parse().unwrap(), 2), ("10.0.0.9".parse().unwrap(), 1)]
+        );
+    }
+
+    #[test]
+    fn only_scans_with_every_tag_asked_for_are_kept() {
+        let tag = |s: &str| s.parse::<Tag>().unwrap();
+        let mut prod = scan(1, &[(HOST, 22, PortStatus::Open)]);
+        prod.tags = BTreeMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("site".to_string(), "fra".to_string()),
+        ]);
+        let untagged = scan(2, &[(HOST, 22, PortStatus::Closed)]);
+
+        assert!(prod.has_tags(&[]));
+        assert!(untagged.has_tags(&[]));
+        assert!(prod.has_tags(&[tag("env=prod"), tag("site=fra")]));
+        assert!(!prod.has_tags(&[tag("env=prod"), tag("site=ams")]));
+        assert!(!prod.has_tags(&[tag("env=Prod")]));
+        assert!(!untagged.has_tags(&[tag("env=prod")]));
+    }
+
+    #[test]
+    fn scans_are_dated_by_their_id() {
+        assert_eq!(
+            scan_time("19c8a1b2c00-3039"),
+            Utc.timestamp_millis_opt(0x19c8a1b2c00).single()
+        );
+        assert_eq!(scan_time("0"), Some(DateTime::UNIX_EPOCH));
+        assert_eq!(scan_time("nmap"), None);
+    }
+}
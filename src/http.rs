@@ -0,0 +1,254 @@
+//! The small HTTP/1.1 client of the requests the scanner makes itself:
+//! `--upload-s3` and `--pushgateway`.
+//!
+//! Each request goes over a connection of its own, closed after the reply,
+//! and over TLS to `https://` endpoints, which needs the `tls` feature.
+//! Connecting and each read of the reply are bounded by [`REPLY_TIMEOUT`].
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::connector::Connection;
+use crate::tls;
+
+/// Longest wait for a server to connect or send the next bytes of a reply.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Why a request got no reply.
+#[derive(Error, Debug)]
+pub enum HttpError {
+    /// Connecting, the TLS handshake, sending or reading failed.
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    /// The server took longer than [`REPLY_TIMEOUT`] to connect or answer.
+    #[error("no answer within {}s", REPLY_TIMEOUT.as_secs())]
+    Timeout,
+
+    /// The reply was not HTTP.
+    #[error("malformed reply: {0:?}")]
+    Malformed(String),
+}
+
+/// A server to send requests to, such as `http://127.0.0.1:9000`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub(crate) tls: bool,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tls, rest) = if let Some(rest) = s.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("'{}' is not an http:// or https:// URL", s));
+        };
+        let authority = rest.trim_end_matches('/');
+        if authority.is_empty() || authority.contains('/') {
+            return Err(format!("'{}' must be a scheme, host and port only", s));
+        }
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !host.ends_with(':') && !port.contains(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid port '{}' in '{}'", port, s))?,
+            ),
+            _ => (authority, default_port),
+        };
+        Ok(Endpoint {
+            tls,
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{}://{}", scheme, self.authority())
+    }
+}
+
+impl Endpoint {
+    /// The `Host` header: the host, and the port unless it is the default.
+    pub(crate) fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == if self.tls { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    /// Sends one request for `path` with `headers` and `body`, adding the
+    /// `content-length` and `connection` headers, and `host` unless
+    /// `headers` have it, and reads the reply to the end.
+    pub(crate) async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Result<Reply, HttpError> {
+        let mut request = format!("{} {} HTTP/1.1\r\n", method, path);
+        if !headers.iter().any(|(name, _)| name == "host") {
+            request.push_str(&format!("host: {}\r\n", self.authority()));
+        }
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str(&format!(
+            "content-length: {}\r\nconnection: close\r\n\r\n",
+            body.len()
+        ));
+
+        let mut stream = self.connect().await?;
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await?;
+        let mut reply = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = tokio::time::timeout(REPLY_TIMEOUT, stream.read(&mut chunk))
+                .await
+                .map_err(|_| HttpError::Timeout)??;
+            if n == 0 {
+                break;
+            }
+            reply.extend_from_slice(&chunk[..n]);
+        }
+        let reply = String::from_utf8_lossy(&reply);
+        let status = reply
+            .split_whitespace()
+            .nth(1)
+            .filter(|_| reply.starts_with("HTTP/"))
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| HttpError::Malformed(reply.chars().take(80).collect()))?;
+        let body = reply
+            .split_once("\r\n\r\n")
+            .map_or("", |(_, body)| body)
+            .to_string();
+        Ok(Reply { status, body })
+    }
+
+    async fn connect(&self) -> Result<Connection, HttpError> {
+        let address = (self.host.as_str(), self.port);
+        let stream = tokio::time::timeout(REPLY_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| HttpError::Timeout)??;
+        let stream: Connection = Box::new(stream);
+        if self.tls {
+            Ok(tls::connect(stream, &self.host).await?)
+        } else {
+            Ok(stream)
+        }
+    }
+}
+
+/// What the server answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// The status code.
+    pub status: u16,
+    /// Everything after the head, as text.
+    pub body: String,
+}
+
+impl Reply {
+    /// Whether the status is 2xx.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Whether a request that got `status` may go better another time:
+/// timeouts, throttling and server errors.
+pub fn is_transient(status: u16) -> bool {
+    status >= 500 || matches!(status, 408 | 429)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn endpoints_parse() {
+        let minio: Endpoint = "http://127.0.0.1:9000/".parse().unwrap();
+        assert_eq!(minio.authority(), "127.0.0.1:9000");
+        let aws: Endpoint = "https://s3.example.com".parse().unwrap();
+        assert_eq!(
+            (aws.port, aws.authority().as_str()),
+            (443, "s3.example.com")
+        );
+        assert_eq!(aws.to_string(), "https://s3.example.com");
+        let v6: Endpoint = "http://[::1]:9000".parse().unwrap();
+        assert_eq!(
+            (v6.host.as_str(), v6.authority().as_str()),
+            ("::1", "[::1]:9000")
+        );
+        assert!("minio:9000".parse::<Endpoint>().is_err());
+        assert!("http://minio:9000/bucket".parse::<Endpoint>().is_err());
+    }
+
+    #[tokio::test]
+    async fn a_request_gets_the_status_and_body_of_the_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 4096];
+            while !request.ends_with(b"\r\n\r\nhi") {
+                let n = stream.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 4\r\n\r\nnope")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+        let endpoint: Endpoint = format!("http://127.0.0.1:{}", port).parse().unwrap();
+        let headers = [("content-type".to_string(), "text/plain".to_string())];
+
+        let reply = endpoint.send("PUT", "/a/b", &headers, b"hi").await.unwrap();
+
+        assert_eq!(
+            reply,
+            Reply {
+                status: 400,
+                body: "nope".to_string()
+            }
+        );
+        assert!(!reply.is_success());
+        assert_eq!(
+            server.await.unwrap(),
+            format!(
+                "PUT /a/b HTTP/1.1\r\nhost: 127.0.0.1:{}\r\ncontent-type: text/plain\r\n\
+                 content-length: 2\r\nconnection: close\r\n\r\nhi",
+                port
+            )
+        );
+    }
+}
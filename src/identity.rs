@@ -0,0 +1,323 @@
+//! Which host a result belongs to, across scans that named it differently
+//! (`--identity-by`).
+//!
+//! Results are recorded by address, but the same machine may be scanned as
+//! `10.0.0.5` one day and as `db01.corp` the next, and its address may
+//! change under its name. Each host of a report carries the names it was
+//! scanned by ([`HostInfo::names`]) and its canonical key
+//! ([`HostInfo::key`]): its address by default, or with `--identity-by
+//! name` its first name, for a host that has one no other host shares.
+//! Re-scans (`--from`), report baselines and `history stability` match
+//! hosts on that key.
+//!
+//! As every report keeps the names, an earlier report is keyed again in
+//! the mode of the scan it is matched against, so changing the mode
+//! between runs doesn't make every host look new; it is still warned about
+//! ([`mismatch`]).
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::resolve::Resolution;
+use crate::scanner::ScanSummary;
+
+#[cfg(doc)]
+use crate::scanner::HostInfo;
+
+/// What a host is known by from one scan to the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentityMode {
+    /// Its address.
+    #[default]
+    Address,
+    /// The first name it was scanned by, or its address without one.
+    Name,
+}
+
+impl IdentityMode {
+    /// Whether this is the default mode, which reports leave unsaid.
+    pub fn is_default(&self) -> bool {
+        *self == IdentityMode::default()
+    }
+}
+
+impl fmt::Display for IdentityMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            IdentityMode::Address => "address",
+            IdentityMode::Name => "name",
+        })
+    }
+}
+
+/// The canonical key of a host: what results are matched on across scans.
+/// Addresses sort before names, and in address order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HostKey {
+    /// The host's address.
+    Address(IpAddr),
+    /// A name of the host's own, normalized.
+    Name(String),
+}
+
+impl HostKey {
+    /// The key of a host given as `text`: an address or a host name.
+    pub fn parse(text: &str) -> Self {
+        match text.trim().parse() {
+            Ok(address) => HostKey::Address(address),
+            Err(_) => HostKey::Name(normalize(text)),
+        }
+    }
+
+    /// The name, for a key that is one.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            HostKey::Name(name) => Some(name),
+            HostKey::Address(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostKey::Address(address) => write!(f, "{}", address),
+            HostKey::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// A host name as it is compared: lowercase, without a trailing dot.
+pub fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The names of the hosts of one scan, and the key of each in a mode.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identities {
+    mode: IdentityMode,
+    names: HashMap<IpAddr, Vec<String>>,
+    /// How many hosts each name names.
+    named: HashMap<String, usize>,
+}
+
+impl Identities {
+    /// No names yet, keyed in `mode`.
+    pub fn new(mode: IdentityMode) -> Self {
+        Identities {
+            mode,
+            ..Identities::default()
+        }
+    }
+
+    /// The hosts the names of `dns` resolved to, keyed in `mode`.
+    pub fn of_resolutions<'a>(
+        mode: IdentityMode,
+        dns: impl IntoIterator<Item = &'a Resolution>,
+    ) -> Self {
+        let mut identities = Identities::new(mode);
+        for d in dns {
+            for address in d.addresses() {
+                identities.add(address, &d.name);
+            }
+        }
+        identities
+    }
+
+    /// The hosts of a report, by the names it recorded for them, keyed in
+    /// `mode` whatever mode the report was written in.
+    pub fn of(summary: &ScanSummary, mode: IdentityMode) -> Self {
+        let mut identities = Identities::new(mode);
+        for host in &summary.hosts {
+            let dns = host.dns.iter().map(|d| &d.name);
+            for name in host.names.iter().chain(dns) {
+                identities.add(host.address, name);
+            }
+        }
+        identities
+    }
+
+    /// Adds `name` to the names of `address`.
+    pub fn add(&mut self, address: IpAddr, name: &str) {
+        let name = normalize(name);
+        if name.is_empty() || name.parse::<IpAddr>().is_ok() {
+            return;
+        }
+        let names = self.names.entry(address).or_default();
+        if !names.contains(&name) {
+            *self.named.entry(name.clone()).or_default() += 1;
+            names.push(name);
+        }
+    }
+
+    /// Gives each of `addresses` this has no name for the names `other`
+    /// has for it.
+    pub fn adopt(&mut self, other: &Identities, addresses: &BTreeSet<IpAddr>) {
+        for &address in addresses {
+            if self.names(address).is_empty() {
+                for name in other.names(address) {
+                    self.add(address, name);
+                }
+            }
+        }
+    }
+
+    /// The mode the hosts are keyed in.
+    pub fn mode(&self) -> IdentityMode {
+        self.mode
+    }
+
+    /// The names of `address`, in the order they were added.
+    pub fn names(&self, address: IpAddr) -> &[String] {
+        self.names.get(&address).map_or(&[], Vec::as_slice)
+    }
+
+    /// The canonical key of `address`. A name that names several hosts,
+    /// as a round-robin name does, tells none of them apart and isn't one.
+    pub fn key(&self, address: IpAddr) -> HostKey {
+        let unique = |name: &&String| self.named.get(*name) == Some(&1);
+        match self.mode {
+            IdentityMode::Name => self
+                .names(address)
+                .iter()
+                .find(unique)
+                .map_or(HostKey::Address(address), |name| {
+                    HostKey::Name(name.clone())
+                }),
+            IdentityMode::Address => HostKey::Address(address),
+        }
+    }
+}
+
+/// The identities of the hosts of a scan and of an earlier one, to match
+/// them on their keys in the mode of `current`. Each side gives the hosts
+/// of the other it has no name for the names it has for them, so that a
+/// host scanned by name in one scan and by address in the other has one
+/// key; the names a side has of its own decide its keys, so that a name
+/// whose address changed between the scans keeps its key.
+pub fn exchange(
+    current: &Identities,
+    hosts: &BTreeSet<IpAddr>,
+    earlier: &Identities,
+    earlier_hosts: &BTreeSet<IpAddr>,
+) -> (Identities, Identities) {
+    let mut now = current.clone();
+    now.adopt(earlier, hosts);
+    let mut then = Identities::new(current.mode);
+    then.adopt(earlier, earlier_hosts);
+    then.adopt(current, earlier_hosts);
+    (now, then)
+}
+
+/// A warning when `what` was written with hosts identified in `written`,
+/// not in `mode`; its hosts are matched in `mode` all the same.
+pub fn mismatch(what: &str, written: IdentityMode, mode: IdentityMode) -> Option<String> {
+    (written != mode).then(|| {
+        format!(
+            "{} identified hosts by {}, this run by {}; hosts are matched by {} from the names recorded",
+            what, written, mode, mode
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn hosts(addresses: &[&str]) -> BTreeSet<IpAddr> {
+        addresses.iter().map(|a| ip(a)).collect()
+    }
+
+    fn name(text: &str) -> HostKey {
+        HostKey::Name(text.to_string())
+    }
+
+    #[test]
+    fn hosts_are_keyed_by_address_or_by_a_name_of_their_own() {
+        let mut identities = Identities::new(IdentityMode::Name);
+        identities.add(ip("10.0.0.5"), "DB01.corp.");
+        identities.add(ip("10.0.0.5"), "db01.corp");
+        identities.add(ip("10.0.0.5"), "10.0.0.5");
+        identities.add(ip("10.0.0.7"), "www.corp");
+        identities.add(ip("10.0.0.8"), "www.corp");
+        identities.add(ip("10.0.0.8"), "web2.corp");
+
+        assert_eq!(identities.names(ip("10.0.0.5")), ["db01.corp"]);
+        assert_eq!(identities.key(ip("10.0.0.5")), name("db01.corp"));
+        assert_eq!(
+            identities.key(ip("10.0.0.6")),
+            HostKey::Address(ip("10.0.0.6"))
+        );
+        // www.corp names two hosts, so it is the key of neither.
+        assert_eq!(
+            identities.key(ip("10.0.0.7")),
+            HostKey::Address(ip("10.0.0.7"))
+        );
+        assert_eq!(identities.key(ip("10.0.0.8")), name("web2.corp"));
+
+        let by_address = Identities {
+            mode: IdentityMode::Address,
+            ..identities
+        };
+        assert_eq!(
+            by_address.key(ip("10.0.0.5")),
+            HostKey::Address(ip("10.0.0.5"))
+        );
+        assert_eq!(HostKey::parse(" Mail.Corp. "), name("mail.corp"));
+        assert_eq!(name("mail.corp").to_string(), "mail.corp");
+    }
+
+    #[test]
+    fn a_host_keeps_its_key_from_hostname_to_ip_and_back() {
+        let mut by_name = Identities::new(IdentityMode::Name);
+        by_name.add(ip("10.0.0.5"), "db01.corp");
+        let by_ip = Identities::new(IdentityMode::Name);
+        let db = hosts(&["10.0.0.5"]);
+
+        // Scanned by name, then by IP.
+        let (now, then) = exchange(&by_ip, &db, &by_name, &db);
+        assert_eq!(now.key(ip("10.0.0.5")), name("db01.corp"));
+        assert_eq!(then.key(ip("10.0.0.5")), name("db01.corp"));
+
+        // Scanned by IP, then by name.
+        let (now, then) = exchange(&by_name, &db, &by_ip, &db);
+        assert_eq!(now.key(ip("10.0.0.5")), name("db01.corp"));
+        assert_eq!(then.key(ip("10.0.0.5")), name("db01.corp"));
+
+        // The name moved to another address.
+        let mut moved = Identities::new(IdentityMode::Name);
+        moved.add(ip("10.0.0.9"), "db01.corp");
+        let (now, then) = exchange(&moved, &hosts(&["10.0.0.9"]), &by_name, &db);
+        assert_eq!(now.key(ip("10.0.0.9")), then.key(ip("10.0.0.5")));
+
+        // By address, names change nothing.
+        let by_address = Identities::new(IdentityMode::Address);
+        let (now, then) = exchange(&by_address, &db, &by_name, &db);
+        assert_eq!(now.key(ip("10.0.0.5")), HostKey::Address(ip("10.0.0.5")));
+        assert_eq!(then.key(ip("10.0.0.5")), HostKey::Address(ip("10.0.0.5")));
+        assert_eq!(then.names(ip("10.0.0.5")), ["db01.corp"]);
+    }
+
+    #[test]
+    fn a_change_of_mode_is_warned_about() {
+        assert_eq!(
+            mismatch("before.json", IdentityMode::Address, IdentityMode::Name).as_deref(),
+            Some(
+                "before.json identified hosts by address, this run by name; \
+                 hosts are matched by name from the names recorded"
+            )
+        );
+        assert_eq!(
+            mismatch("before.json", IdentityMode::Name, IdentityMode::Name),
+            None
+        );
+    }
+}
@@ -0,0 +1,856 @@
+//! Reports of other scanners read into a [`ScanSummary`], so historical
+//! nmap and masscan output can stand in for one of our JSON reports.
+//!
+//! Understood are our own JSON report, nmap's XML (`-oX`, also written by
+//! `masscan -oX`), and masscan's JSON (`-oJ`) and list (`-oL`) formats.
+//! TCP and UDP ports are read, each with its protocol; SCTP and the
+//! like are skipped. What the other tools don't record, such as latencies
+//! or connection counts, is left empty or zero; nmap's `<extraports>`
+//! totals, which name no ports, are left out. The output of nmap's
+//! `ssl-enum-ciphers`, `ssl-cert` and `tls-alpn` scripts becomes each
+//! port's [`TlsDetails`].
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::metrics::ScanMetrics;
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+use crate::spec::Protocol;
+use crate::tlsreport::{TlsDetails, TlsVersion};
+
+/// The format of an imported report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Our own JSON report (`--json`).
+    Json,
+    /// nmap's XML, as written by `nmap -oX` or `masscan -oX`.
+    NmapXml,
+    /// masscan's JSON, as written by `masscan -oJ`.
+    MasscanJson,
+    /// masscan's list, as written by `masscan -oL`.
+    MasscanList,
+}
+
+impl InputFormat {
+    /// Every format, by its name.
+    pub const ALL: [InputFormat; 4] = [
+        InputFormat::Json,
+        InputFormat::NmapXml,
+        InputFormat::MasscanJson,
+        InputFormat::MasscanList,
+    ];
+
+    /// The name the format is selected by.
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Json => "json",
+            InputFormat::NmapXml => "nmap-xml",
+            InputFormat::MasscanJson => "masscan-json",
+            InputFormat::MasscanList => "masscan-list",
+        }
+    }
+
+    /// Guesses the format from the start of the file: XML is nmap's, a
+    /// JSON array masscan's, a JSON object ours, and lines such as
+    /// `open tcp 80 10.0.0.1 1709993107` masscan's list.
+    pub fn sniff(text: &str) -> Option<InputFormat> {
+        let text = text.trim_start_matches('\u{feff}').trim_start();
+        if text.starts_with('<') {
+            Some(InputFormat::NmapXml)
+        } else if text.starts_with('[') {
+            Some(InputFormat::MasscanJson)
+        } else if text.starts_with('{') {
+            Some(InputFormat::Json)
+        } else if text.starts_with("#masscan")
+            || text.lines().next().is_some_and(|l| {
+                let mut words = l.split_whitespace();
+                matches!(words.next(), Some("open" | "closed" | "banner"))
+                    && matches!(words.next(), Some("tcp" | "udp"))
+            })
+        {
+            Some(InputFormat::MasscanList)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        InputFormat::ALL
+            .into_iter()
+            .find(|f| f.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let valid: Vec<&str> = InputFormat::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "unknown input format '{}' (valid formats: {})",
+                    s.trim(),
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+/// Why a report could not be imported.
+#[derive(Error, Debug)]
+pub enum ImportError {
+    /// The file could not be read.
+    #[error("Cannot read {path}: {source}")]
+    Read {
+        /// The report path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file looks like none of the known formats.
+    #[error("{path}: not a JSON report, nmap XML or masscan output")]
+    Unrecognized {
+        /// The report path.
+        path: String,
+    },
+    /// The file is not well-formed JSON or XML.
+    #[error("{path}: {reason}")]
+    Syntax {
+        /// The report path.
+        path: String,
+        /// What the parser said, with the position.
+        reason: String,
+    },
+    /// An element of the report doesn't hold what the format says it
+    /// should.
+    #[error("{path}: {element}: {reason}")]
+    Element {
+        /// The report path.
+        path: String,
+        /// The first bad element and where it is, e.g. `<port> on line 12`.
+        element: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+/// Reads the report at `path` in `format`, or the format sniffed from its
+/// contents when none is given.
+pub fn load(path: &Path, format: Option<InputFormat>) -> Result<ScanSummary, ImportError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ImportError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    parse(&text, format, &path.display().to_string())
+}
+
+/// Reads a report from `text`; `path` only names it in errors.
+pub fn parse(
+    text: &str,
+    format: Option<InputFormat>,
+    path: &str,
+) -> Result<ScanSummary, ImportError> {
+    let format =
+        format
+            .or_else(|| InputFormat::sniff(text))
+            .ok_or_else(|| ImportError::Unrecognized {
+                path: path.to_string(),
+            })?;
+    let syntax = |reason: String| ImportError::Syntax {
+        path: path.to_string(),
+        reason,
+    };
+    let element = |element: String, reason: String| ImportError::Element {
+        path: path.to_string(),
+        element,
+        reason,
+    };
+    match format {
+        InputFormat::Json => {
+            let mut summary: ScanSummary =
+                serde_json::from_str(text).map_err(|e| syntax(e.to_string()))?;
+            summary.fill_result_targets();
+            summary.expand_shared_responses();
+            Ok(summary)
+        }
+        InputFormat::NmapXml => nmap_xml(text, syntax, element),
+        InputFormat::MasscanJson => masscan_json(text, syntax, element),
+        InputFormat::MasscanList => masscan_list(text, element),
+    }
+}
+
+fn nmap_xml(
+    text: &str,
+    syntax: impl Fn(String) -> ImportError,
+    element: impl Fn(String, String) -> ImportError,
+) -> Result<ScanSummary, ImportError> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let doc = roxmltree::Document::parse_with_options(text, options)
+        .map_err(|e| syntax(e.to_string()))?;
+    let root = doc.root_element();
+    let at = |node: roxmltree::Node| {
+        format!(
+            "<{}> on line {}",
+            node.tag_name().name(),
+            doc.text_pos_at(node.range().start).row
+        )
+    };
+    if root.tag_name().name() != "nmaprun" {
+        return Err(element(at(root), "expected <nmaprun>".to_string()));
+    }
+    let scanner = root.attribute("scanner").unwrap_or("nmap");
+
+    let mut hosts: Vec<IpAddr> = Vec::new();
+    let mut results = Vec::new();
+    for host in children(root, "host") {
+        let address = children(host, "address")
+            .find(|a| matches!(a.attribute("addrtype"), Some("ipv4" | "ipv6") | None))
+            .ok_or_else(|| element(at(host), "no IP <address>".to_string()))?;
+        let addr = address.attribute("addr").unwrap_or("");
+        let target: IpAddr = addr
+            .parse()
+            .map_err(|_| element(at(address), format!("'{}' is not an IP address", addr)))?;
+        if !hosts.contains(&target) {
+            hosts.push(target);
+        }
+        for port in children(host, "ports").flat_map(|p| children(p, "port")) {
+            let Some(protocol) = protocol(port.attribute("protocol").unwrap_or("tcp")) else {
+                continue;
+            };
+            let id = port.attribute("portid").unwrap_or("");
+            let number: u16 = id
+                .parse()
+                .map_err(|_| element(at(port), format!("'{}' is not a port number", id)))?;
+            let state = children(port, "state")
+                .next()
+                .ok_or_else(|| element(at(port), "no <state>".to_string()))?;
+            let status = match state.attribute("state") {
+                Some("open") => PortStatus::Open,
+                Some("closed") => PortStatus::Closed,
+                Some(s) if s.contains("filtered") => PortStatus::Filtered,
+                other => {
+                    return Err(element(
+                        at(state),
+                        format!("unknown state '{}'", other.unwrap_or("")),
+                    ))
+                }
+            };
+            let mut r = PortResult::new(target, number, status);
+            r.protocol = protocol;
+            if let Some(service) = children(port, "service").next() {
+                let text = |name| service.attribute(name).map(str::to_string);
+                r.service = service.attribute("name").map(str::to_uppercase);
+                r.product = text("product");
+                r.version = text("version");
+                r.extra_info = text("extrainfo");
+                r.cpe = children(service, "cpe")
+                    .filter_map(|c| c.text())
+                    .find_map(cpe_23);
+            }
+            r.banner = children(port, "script")
+                .find(|s| s.attribute("id") == Some("banner"))
+                .and_then(|s| s.attribute("output"))
+                .map(Into::into);
+            r.tls = tls(port);
+            results.push(r);
+        }
+    }
+
+    let start = root.attribute("start").unwrap_or("0");
+    let elapsed_ms = children(root, "runstats")
+        .flat_map(|r| children(r, "finished"))
+        .find_map(|f| f.attribute("elapsed")?.parse::<f64>().ok())
+        .map_or(0, |secs| (secs * 1000.0) as u128);
+    Ok(summary(
+        format!("{}-{}", scanner, start),
+        &hosts,
+        results,
+        elapsed_ms,
+    ))
+}
+
+fn children<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children()
+        .filter(move |c| c.is_element() && c.tag_name().name() == name)
+}
+
+/// What the TLS scripts found out about `port`; none when none of them ran.
+fn tls(port: roxmltree::Node) -> Option<TlsDetails> {
+    let script = |id: &str| children(port, "script").find(|s| s.attribute("id") == Some(id));
+    let (ciphers, cert, alpn) = (
+        script("ssl-enum-ciphers"),
+        script("ssl-cert"),
+        script("tls-alpn"),
+    );
+    if ciphers.is_none() && cert.is_none() && alpn.is_none() {
+        return None;
+    }
+    let mut tls = TlsDetails::default();
+    if let Some(ciphers) = ciphers {
+        tls.versions = children(ciphers, "table")
+            .filter_map(|t| TlsVersion::from_name(t.attribute("key")?))
+            .collect();
+        tls.versions.sort();
+        tls.versions.dedup();
+    }
+    if let Some(cert) = cert {
+        let issuer = keyed(cert, "table", "issuer");
+        tls.issuer = issuer
+            .and_then(|i| elem(i, "commonName").or_else(|| elem(i, "organizationName")))
+            .map(str::to_string);
+        tls.not_after = keyed(cert, "table", "validity")
+            .and_then(|v| elem(v, "notAfter"))
+            .and_then(timestamp);
+        let key = keyed(cert, "table", "pubkey");
+        tls.key_type = key.and_then(|k| elem(k, "type")).map(str::to_string);
+        tls.key_bits = key
+            .and_then(|k| elem(k, "bits"))
+            .and_then(|bits| bits.parse().ok());
+    }
+    if let Some(alpn) = alpn {
+        tls.alpn = children(alpn, "elem")
+            .filter_map(|e| e.text())
+            .map(str::to_string)
+            .collect();
+    }
+    Some(tls)
+}
+
+/// The `<table>` or `<elem>` of a script's output under `key`.
+fn keyed<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'static str,
+    key: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    children(node, name).find(|c| c.attribute("key") == Some(key))
+}
+
+/// The text of the `<elem>` under `key`.
+fn elem<'a>(node: roxmltree::Node<'a, '_>, key: &str) -> Option<&'a str> {
+    keyed(node, "elem", key)?.text()
+}
+
+/// A time as nmap writes it, `2026-06-01T00:00:00` in UTC, or in RFC 3339.
+fn timestamp(text: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S")
+        .map(|t| t.and_utc())
+        .or_else(|_| DateTime::parse_from_rfc3339(text).map(|t| t.to_utc()))
+        .ok()
+}
+
+/// A CPE 2.2 URI such as nmap's `cpe:/a:openbsd:openssh:8.9p1` as the CPE
+/// 2.3 name our reports use.
+fn cpe_23(uri: &str) -> Option<String> {
+    let parts: Vec<&str> = uri.strip_prefix("cpe:/")?.split(':').collect();
+    let fields: Vec<&str> = (0..11)
+        .map(|i| {
+            parts
+                .get(i)
+                .copied()
+                .filter(|p| !p.is_empty())
+                .unwrap_or("*")
+        })
+        .collect();
+    Some(format!("cpe:2.3:{}", fields.join(":")))
+}
+
+#[derive(Deserialize)]
+struct MasscanRecord {
+    ip: IpAddr,
+    #[serde(default)]
+    timestamp: Option<String>,
+    ports: Vec<MasscanPort>,
+}
+
+#[derive(Deserialize)]
+struct MasscanPort {
+    port: u16,
+    #[serde(default)]
+    proto: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    service: Option<MasscanService>,
+}
+
+#[derive(Deserialize)]
+struct MasscanService {
+    name: String,
+    #[serde(default)]
+    banner: String,
+}
+
+fn masscan_json(
+    text: &str,
+    syntax: impl Fn(String) -> ImportError,
+    element: impl Fn(String, String) -> ImportError,
+) -> Result<ScanSummary, ImportError> {
+    // masscan before 1.3 left a comma after the last record.
+    let trimmed = text.trim_end();
+    let fixed;
+    let text = match trimmed
+        .strip_suffix(']')
+        .map(str::trim_end)
+        .and_then(|t| t.strip_suffix(','))
+    {
+        Some(body) => {
+            fixed = format!("{}]", body);
+            fixed.as_str()
+        }
+        None => text,
+    };
+    let records: Vec<serde_json::Value> =
+        serde_json::from_str(text).map_err(|e| syntax(e.to_string()))?;
+    let mut import = MasscanImport::default();
+    for (i, record) in records.into_iter().enumerate() {
+        let record: MasscanRecord = serde_json::from_value(record)
+            .map_err(|e| element(format!("record {}", i + 1), e.to_string()))?;
+        for port in record.ports {
+            let Some(protocol) = protocol(port.proto.as_deref().unwrap_or("tcp")) else {
+                continue;
+            };
+            let at = (record.ip, protocol, port.port);
+            match (port.status.as_deref(), port.service) {
+                (_, Some(service)) => import.banner(at, &service.name, &service.banner),
+                (Some(status), None) => {
+                    let status = masscan_status(status).ok_or_else(|| {
+                        element(
+                            format!("record {}", i + 1),
+                            format!("unknown status '{}'", status),
+                        )
+                    })?;
+                    import.port(at, status, record.timestamp.as_deref());
+                }
+                (None, None) => {
+                    return Err(element(
+                        format!("record {}", i + 1),
+                        "port without a status or service".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(import.finish())
+}
+
+fn masscan_list(
+    text: &str,
+    element: impl Fn(String, String) -> ImportError,
+) -> Result<ScanSummary, ImportError> {
+    let mut import = MasscanImport::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |reason: String| element(format!("line {}", i + 1), reason);
+        let mut words = line.splitn(7, ' ');
+        let (Some(kind), Some(proto), Some(port), Some(ip), Some(timestamp)) = (
+            words.next(),
+            words.next(),
+            words.next(),
+            words.next(),
+            words.next(),
+        ) else {
+            return Err(bad(format!(
+                "expected '<status> tcp <port> <ip> <time>', got '{}'",
+                line
+            )));
+        };
+        let port: u16 = port
+            .parse()
+            .map_err(|_| bad(format!("'{}' is not a port number", port)))?;
+        let ip: IpAddr = ip
+            .parse()
+            .map_err(|_| bad(format!("'{}' is not an IP address", ip)))?;
+        let Some(protocol) = protocol(proto) else {
+            continue;
+        };
+        let at = (ip, protocol, port);
+        if kind == "banner" {
+            let service = words.next().unwrap_or("");
+            let banner = unescape(words.next().unwrap_or(""));
+            import.banner(at, service, &banner);
+        } else {
+            let status =
+                masscan_status(kind).ok_or_else(|| bad(format!("unknown status '{}'", kind)))?;
+            import.port(at, status, Some(timestamp));
+        }
+    }
+    Ok(import.finish())
+}
+
+/// The protocols we keep, by the name nmap and masscan give them.
+fn protocol(name: &str) -> Option<Protocol> {
+    match name {
+        "tcp" => Some(Protocol::Tcp),
+        "udp" => Some(Protocol::Udp),
+        _ => None,
+    }
+}
+
+fn masscan_status(status: &str) -> Option<PortStatus> {
+    match status {
+        "open" => Some(PortStatus::Open),
+        "closed" => Some(PortStatus::Closed),
+        _ => None,
+    }
+}
+
+/// Undoes masscan's `\xNN` escapes of unprintable banner bytes.
+fn unescape(banner: &str) -> String {
+    let mut bytes = Vec::with_capacity(banner.len());
+    let mut rest = banner.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if let (b'\\', Some(hex)) = (b, tail.strip_prefix(b"x").and_then(|t| t.get(..2))) {
+            if let Ok(value) = u8::from_str_radix(&String::from_utf8_lossy(hex), 16) {
+                bytes.push(value);
+                rest = &tail[3..];
+                continue;
+            }
+        }
+        bytes.push(b);
+        rest = tail;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// A port of a host, as masscan records name it.
+type PortKey = (IpAddr, Protocol, u16);
+
+/// masscan reports ports and their banners as separate records.
+#[derive(Default)]
+struct MasscanImport {
+    hosts: Vec<IpAddr>,
+    results: Vec<PortResult>,
+    index: HashMap<PortKey, usize>,
+    first: Option<String>,
+}
+
+impl MasscanImport {
+    fn result(&mut self, at: PortKey, status: PortStatus) -> &mut PortResult {
+        let (ip, protocol, port) = at;
+        if !self.hosts.contains(&ip) {
+            self.hosts.push(ip);
+        }
+        let i = *self.index.entry(at).or_insert_with(|| {
+            let mut r = PortResult::new(ip, port, status);
+            r.protocol = protocol;
+            self.results.push(r);
+            self.results.len() - 1
+        });
+        &mut self.results[i]
+    }
+
+    fn port(&mut self, at: PortKey, status: PortStatus, timestamp: Option<&str>) {
+        if self.first.is_none() {
+            self.first = timestamp.map(str::to_string);
+        }
+        let r = self.result(at, status.clone());
+        r.status = status;
+    }
+
+    /// A banner also means the port is open. Of several, the first is
+    /// kept; titles and certificates are not the service's greeting.
+    fn banner(&mut self, at: PortKey, service: &str, banner: &str) {
+        let r = self.result(at, PortStatus::Open);
+        let greeting = !(service.contains('.')
+            || service.eq_ignore_ascii_case("title")
+            || service.eq_ignore_ascii_case("x509"));
+        if greeting && r.service.is_none() && !service.is_empty() {
+            r.service = Some(service.to_uppercase());
+        }
+        if greeting && r.banner.is_none() && !banner.is_empty() {
+            r.banner = Some(banner.into());
+        }
+    }
+
+    fn finish(self) -> ScanSummary {
+        let id = format!("masscan-{}", self.first.as_deref().unwrap_or("0"));
+        summary(id, &self.hosts, self.results, 0)
+    }
+}
+
+fn summary(
+    scan_id: String,
+    hosts: &[IpAddr],
+    results: Vec<PortResult>,
+    total_time_ms: u128,
+) -> ScanSummary {
+    let target: Vec<String> = hosts.iter().map(IpAddr::to_string).collect();
+    let target = target.join(",");
+    let mut summary = ScanSummary::from_results(
+        scan_id,
+        target.clone(),
+        results,
+        total_time_ms,
+        &ScanMetrics::new(&target),
+        false,
+    );
+    summary.ports_per_second = if total_time_ms > 0 {
+        summary.scanned_ports as f64 * 1000.0 / total_time_ms as f64
+    } else {
+        0.0
+    };
+    summary.concurrency = 0;
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn ports(summary: &ScanSummary) -> Vec<(String, String, PortStatus)> {
+        summary
+            .results
+            .iter()
+            .map(|r| (r.target.to_string(), r.port_label(), r.status.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn reports_of_the_first_release_load_and_round_trip() {
+        let summary = load(&fixture("old-scan.json"), None).unwrap();
+        assert_eq!(summary.scan_id, "");
+        assert_eq!(summary.target, "127.0.0.1");
+        assert_eq!(
+            ports(&summary),
+            [
+                (
+                    "127.0.0.1".to_string(),
+                    "2222".to_string(),
+                    PortStatus::Open
+                ),
+                (
+                    "127.0.0.1".to_string(),
+                    "2323".to_string(),
+                    PortStatus::Closed
+                ),
+                (
+                    "127.0.0.1".to_string(),
+                    "8080".to_string(),
+                    PortStatus::Open
+                ),
+            ]
+        );
+        assert_eq!(
+            summary.results[0].banner_str().as_deref(),
+            Some("SSH-2.0-dropbear_2020.81")
+        );
+        assert_eq!(summary.connection_attempts, 0);
+        assert!(!summary.interrupted);
+        assert!(summary.policy.is_none());
+
+        let json = serde_json::to_string(&summary).unwrap();
+        let again = parse(&json, Some(InputFormat::Json), "round trip").unwrap();
+        assert_eq!(serde_json::to_string(&again).unwrap(), json);
+    }
+
+    #[test]
+    fn nmap_xml_maps_ports_and_services() {
+        let summary = load(&fixture("nmap.xml"), None).unwrap();
+        assert_eq!(summary.scan_id, "nmap-1709993107");
+        assert_eq!(summary.target, "192.168.1.10,192.168.1.20");
+        assert_eq!(summary.total_time_ms, 12340);
+        assert_eq!(
+            ports(&summary),
+            [
+                (
+                    "192.168.1.10".to_string(),
+                    "22".to_string(),
+                    PortStatus::Open
+                ),
+                (
+                    "192.168.1.10".to_string(),
+                    "25".to_string(),
+                    PortStatus::Filtered
+                ),
+                (
+                    "192.168.1.10".to_string(),
+                    "53/udp".to_string(),
+                    PortStatus::Open
+                ),
+                (
+                    "192.168.1.10".to_string(),
+                    "80".to_string(),
+                    PortStatus::Open
+                ),
+                (
+                    "192.168.1.20".to_string(),
+                    "443".to_string(),
+                    PortStatus::Closed
+                ),
+            ]
+        );
+        assert_eq!(
+            (
+                summary.open_ports,
+                summary.closed_ports,
+                summary.filtered_ports
+            ),
+            (3, 1, 1)
+        );
+        assert_eq!(
+            summary
+                .protocols
+                .iter()
+                .map(|c| (c.protocol, c.scanned, c.open))
+                .collect::<Vec<_>>(),
+            [(Protocol::Tcp, 4, 2), (Protocol::Udp, 1, 1)]
+        );
+        let dns = &summary.results[2];
+        assert_eq!(dns.protocol, Protocol::Udp);
+        assert_eq!(dns.product_version().as_deref(), Some("dnsmasq 2.86"));
+        let ssh = &summary.results[0];
+        assert_eq!(ssh.service.as_deref(), Some("SSH"));
+        assert_eq!(ssh.product_version().as_deref(), Some("OpenSSH 8.9p1"));
+        assert_eq!(
+            ssh.extra_info.as_deref(),
+            Some("Ubuntu Linux; protocol 2.0")
+        );
+        assert_eq!(
+            ssh.cpe.as_deref(),
+            Some("cpe:2.3:a:openbsd:openssh:8.9p1:*:*:*:*:*:*:*")
+        );
+        assert_eq!(
+            ssh.banner_str().as_deref(),
+            Some("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6")
+        );
+        let web = &summary.results[3];
+        assert_eq!(web.service.as_deref(), Some("HTTP"));
+        assert_eq!((web.banner.is_none(), web.cpe.is_none()), (true, true));
+        assert_eq!(web.duration_ms, 0);
+    }
+
+    #[test]
+    fn nmap_tls_scripts_become_tls_details() {
+        let summary = load(&fixture("nmap-tls.xml"), None).unwrap();
+        let tls: Vec<(String, Option<&TlsDetails>)> = summary
+            .results
+            .iter()
+            .map(|r| (format!("{}:{}", r.target, r.port), r.tls.as_ref()))
+            .collect();
+        assert_eq!(tls.len(), 6);
+        assert_eq!(tls[0], ("10.0.0.5:22".to_string(), None));
+        assert_eq!(
+            tls[1].1,
+            Some(&TlsDetails {
+                versions: vec![TlsVersion::Tls12, TlsVersion::Tls13],
+                issuer: Some("R3".to_string()),
+                not_after: timestamp("2026-06-01T00:00:00Z"),
+                key_type: Some("rsa".to_string()),
+                key_bits: Some(2048),
+                alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+            })
+        );
+        let legacy = tls[3].1.unwrap();
+        assert_eq!(legacy.versions[0], TlsVersion::Tls10);
+        assert!(legacy.alpn.is_empty());
+        // Without a common name the issuer is its organization.
+        let internal = tls[5].1.unwrap();
+        assert_eq!(internal.issuer.as_deref(), Some("Lab Internal"));
+        assert_eq!(
+            (internal.key_type.as_deref(), internal.key_bits),
+            (Some("ec"), Some(256))
+        );
+    }
+
+    #[test]
+    fn masscan_json_and_list_merge_banners_into_their_ports() {
+        for name in ["masscan.json", "masscan.list"] {
+            let summary = load(&fixture(name), None).unwrap();
+            assert_eq!(summary.scan_id, "masscan-1709993107", "{}", name);
+            assert_eq!(summary.target, "10.0.0.5,10.0.0.7", "{}", name);
+            assert_eq!(
+                ports(&summary),
+                [
+                    ("10.0.0.5".to_string(), "22".to_string(), PortStatus::Open),
+                    ("10.0.0.7".to_string(), "80".to_string(), PortStatus::Open),
+                    ("10.0.0.5".to_string(), "3389".to_string(), PortStatus::Open),
+                    (
+                        "10.0.0.5".to_string(),
+                        "53/udp".to_string(),
+                        PortStatus::Open
+                    ),
+                ],
+                "{}",
+                name
+            );
+            let ssh = &summary.results[0];
+            assert_eq!(ssh.service.as_deref(), Some("SSH"));
+            assert_eq!(ssh.banner_str().as_deref(), Some("SSH-2.0-OpenSSH_7.4"));
+            let web = &summary.results[1];
+            assert_eq!(web.service.as_deref(), Some("HTTP"));
+            assert_eq!(
+                web.banner_str().as_deref(),
+                Some("HTTP/1.1 200 OK\r\nServer: nginx/1.18.0")
+            );
+            assert_eq!(summary.results[2].banner, None);
+            assert_eq!(summary.results[3].protocol, Protocol::Udp, "{}", name);
+        }
+    }
+
+    #[test]
+    fn our_json_round_trips_and_formats_can_be_forced() {
+        let json = r#"{"scan_id": "abc", "target": "10.0.0.1", "scanned_ports": 0,
+            "open_ports": 0, "closed_ports": 0, "filtered_ports": 0, "total_time_ms": 5,
+            "ports_per_second": 0.0, "connection_attempts": 0, "retries": 0,
+            "banner_probes": 0, "banner_bytes": 0, "interrupted": false, "policy": null,
+            "results": []}"#;
+        assert_eq!(parse(json, None, "old.json").unwrap().scan_id, "abc");
+        let e = parse(json, Some(InputFormat::NmapXml), "old.json").unwrap_err();
+        assert!(matches!(e, ImportError::Syntax { .. }), "{}", e);
+        assert_eq!(
+            "Masscan-List".parse::<InputFormat>(),
+            Ok(InputFormat::MasscanList)
+        );
+        assert!("gnmap"
+            .parse::<InputFormat>()
+            .unwrap_err()
+            .contains("valid formats: json, nmap-xml, masscan-json, masscan-list"));
+        let e = parse("Nmap 7.94 scan initiated", None, "scan.gnmap").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "scan.gnmap: not a JSON report, nmap XML or masscan output"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_first_bad_element() {
+        let xml = "<?xml version=\"1.0\"?>\n<nmaprun scanner=\"nmap\">\n\
+                   <host><address addr=\"10.0.0.1\" addrtype=\"ipv4\"/>\n<ports>\n\
+                   <port protocol=\"tcp\" portid=\"22\"><state state=\"open\"/></port>\n\
+                   <port protocol=\"tcp\" portid=\"http\"><state state=\"open\"/></port>\n\
+                   </ports></host></nmaprun>";
+        assert_eq!(
+            parse(xml, None, "scan.xml").unwrap_err().to_string(),
+            "scan.xml: <port> on line 6: 'http' is not a port number"
+        );
+
+        let json = r#"[{"ip": "10.0.0.1", "ports": [{"port": 22, "status": "open"}]},
+            {"ip": "10.0.0.300", "ports": []}]"#;
+        let e = parse(json, None, "scan.json").unwrap_err().to_string();
+        assert!(e.starts_with("scan.json: record 2: "), "{}", e);
+
+        let list = "#masscan\nopen tcp 22 10.0.0.1 1709993107\nopen tcp 22\n";
+        assert_eq!(
+            parse(list, None, "scan.list").unwrap_err().to_string(),
+            "scan.list: line 3: expected '<status> tcp <port> <ip> <time>', got 'open tcp 22'"
+        );
+    }
+}
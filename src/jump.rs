@@ -0,0 +1,989 @@
+//! Scanning through an SSH jump host (`--ssh-jump user@bastion[:port]`),
+//! built with the `ssh-jump` feature, on Unix.
+//!
+//! The scan logs in to the bastion once: the system's OpenSSH client runs
+//! as a control master (`ssh -M -S socket -N bastion`), so keys, the agent,
+//! `~/.ssh/config` and `known_hosts` work as they do for the user's own
+//! logins, and nothing ever prompts. The scanner then puts the master's
+//! control socket in proxy mode, where it carries the connection protocol
+//! of RFC 4254 in the clear and the master does the encryption: every
+//! connect of the scan is a `direct-tcpip` channel of that one session, and
+//! the channel's data is the connection, so banners and probes flow through
+//! it like through a socket.
+//!
+//! The bastion answers each channel open: a confirmation is an open port,
+//! and a failure carries a reason code with the bastion's own connect
+//! error, which [`open_failure`] turns into the error a direct connect would
+//! have had, so a refused port is closed and an unreachable one filtered.
+//!
+//! `SshConnector::start` waits for the login, so that a host key or
+//! authentication problem stops the scan with the client's own message, and
+//! opens one channel to the bastion itself to check that it forwards before
+//! any port is scanned.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[cfg(all(feature = "ssh-jump", unix))]
+pub use imp::SshConnector;
+
+/// Channels open at once by default.
+pub const DEFAULT_CHANNELS: usize = 8;
+
+/// Where the scan jumps from: `[user@]host[:port]`, with IPv6 addresses in
+/// brackets when a port follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpHost {
+    /// The login, when not the one `~/.ssh/config` or the local user gives.
+    pub user: Option<String>,
+    /// The bastion's name or address.
+    pub host: String,
+    /// Its SSH port.
+    pub port: u16,
+}
+
+impl FromStr for JumpHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid jump host '{}' (e.g. alice@bastion:2222)", s);
+        let (user, rest) = match s.rsplit_once('@') {
+            Some((user, rest)) if !user.is_empty() => (Some(user.to_string()), rest),
+            Some(_) => return Err(invalid()),
+            None => (None, s),
+        };
+        let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            let (host, after) = bracketed.split_once(']').ok_or_else(invalid)?;
+            match after {
+                "" => (host, None),
+                _ => (host, Some(after.strip_prefix(':').ok_or_else(invalid)?)),
+            }
+        } else {
+            match rest.split_once(':') {
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                // A bare IPv6 address.
+                Some(_) => (rest, None),
+                None => (rest, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(invalid)?,
+            None => 22,
+        };
+        Ok(JumpHost {
+            user,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for JumpHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// How the OpenSSH client is run.
+#[derive(Debug, Clone)]
+pub struct SshOptions {
+    /// The client, `ssh` from the `PATH` by default.
+    pub program: PathBuf,
+    /// A private key to authenticate with besides the agent's and the
+    /// configured ones.
+    pub identity: Option<PathBuf>,
+    /// Channels open at once over the login.
+    pub channels: usize,
+}
+
+impl Default for SshOptions {
+    fn default() -> Self {
+        SshOptions {
+            program: PathBuf::from("ssh"),
+            identity: None,
+            channels: DEFAULT_CHANNELS,
+        }
+    }
+}
+
+/// Why scanning through the jump host could not start.
+#[derive(Error, Debug)]
+pub enum JumpError {
+    /// Built without the `ssh-jump` feature, or not for Unix.
+    #[error("--ssh-jump needs a Unix build with the ssh-jump feature")]
+    Unsupported,
+    /// The client could not be run.
+    #[error("Cannot run {program}: {source}")]
+    Spawn {
+        /// The client.
+        program: String,
+        /// Why it could not be run.
+        source: io::Error,
+    },
+    /// The connection or the authentication failed.
+    #[error("SSH connection to {host} failed: {message}")]
+    Handshake {
+        /// The jump host.
+        host: String,
+        /// What the client said.
+        message: String,
+    },
+    /// The bastion doesn't allow TCP forwarding.
+    #[error("{host} refuses to forward connections: {message}")]
+    Forwarding {
+        /// The jump host.
+        host: String,
+        /// Why the bastion turned the channel down.
+        message: String,
+    },
+}
+
+/// The error a direct connect would have failed with, from the reason code
+/// and description of a channel open failure (RFC 4254, section 5.1), such
+/// as 2 and `Connection refused` when the bastion's own connect was refused.
+pub fn open_failure(reason: u32, description: &str) -> io::Error {
+    let (kind, name) = match reason {
+        1 => (
+            io::ErrorKind::PermissionDenied,
+            "administratively prohibited",
+        ),
+        2 => (connect_error(description), "connect failed"),
+        3 => (io::ErrorKind::Unsupported, "unknown channel type"),
+        4 => (io::ErrorKind::OutOfMemory, "resource shortage"),
+        _ => (io::ErrorKind::Other, "open failed"),
+    };
+    let message = match description {
+        "" => format!("via jump host: {}", name),
+        _ => format!("via jump host: {}: {}", name, description),
+    };
+    io::Error::new(kind, message)
+}
+
+/// The kind of the bastion's connect error, from its `strerror` text.
+fn connect_error(description: &str) -> io::ErrorKind {
+    if description.contains("Connection refused") {
+        io::ErrorKind::ConnectionRefused
+    } else if description.contains("timed out") {
+        io::ErrorKind::TimedOut
+    } else if description.contains("No route to host") {
+        io::ErrorKind::HostUnreachable
+    } else if description.contains("Network is unreachable") {
+        io::ErrorKind::NetworkUnreachable
+    } else {
+        io::ErrorKind::Other
+    }
+}
+
+#[cfg(all(feature = "ssh-jump", unix))]
+mod imp {
+    use bytes::BytesMut;
+    use futures::future::BoxFuture;
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use std::pin::Pin;
+    use std::process::Stdio;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use std::time::Duration;
+    use tokio::io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+    };
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::UnixStream;
+    use tokio::process::{Child, Command};
+    use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+    use tracing::{debug, trace};
+
+    use super::{open_failure, JumpError, JumpHost, SshOptions};
+    use crate::connector::{Connection, Connector};
+
+    // The control socket's own messages (OpenSSH's PROTOCOL.mux).
+    const MUX_MSG_HELLO: u32 = 0x0000_0001;
+    const MUX_VERSION: u32 = 4;
+    const MUX_C_PROXY: u32 = 0x1000_000f;
+    const MUX_S_PROXY: u32 = 0x8000_000f;
+
+    // The connection protocol's channel messages (RFC 4254).
+    const CHANNEL_OPEN: u8 = 90;
+    const CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+    const CHANNEL_OPEN_FAILURE: u8 = 92;
+    const CHANNEL_WINDOW_ADJUST: u8 = 93;
+    const CHANNEL_DATA: u8 = 94;
+    const CHANNEL_EOF: u8 = 96;
+    const CHANNEL_CLOSE: u8 = 97;
+    const CHANNEL_REQUEST: u8 = 98;
+    const CHANNEL_FAILURE: u8 = 100;
+
+    /// Bytes a channel may have on the way to the scanner; topped up as
+    /// they are read.
+    const WINDOW: u32 = 256 * 1024;
+    /// The largest data message a channel takes.
+    const MAX_PACKET: u32 = 32 * 1024;
+    /// The largest message read from the master, OpenSSH's own limit.
+    const MAX_MESSAGE: usize = 256 * 1024;
+    /// How often the control socket is looked for while the master logs in.
+    const LOGIN_POLL: Duration = Duration::from_millis(50);
+
+    /// Connects through a jump host, a channel of one login per connection.
+    pub struct SshConnector {
+        jump: JumpHost,
+        mux: Arc<Mux>,
+        channels: Arc<Semaphore>,
+        control: PathBuf,
+        /// Killed with the connector.
+        _master: Child,
+    }
+
+    /// What became of a channel the bastion was asked for.
+    enum Opened {
+        Channel(Box<Channel>),
+        /// The bastion answered, but could not connect.
+        Failed(io::Error),
+    }
+
+    impl SshConnector {
+        /// Logs in to `jump` and checks that it can be scanned through.
+        pub async fn start(jump: JumpHost, options: SshOptions) -> Result<Self, JumpError> {
+            let handshake = |message: String| JumpError::Handshake {
+                host: jump.to_string(),
+                message,
+            };
+            let control =
+                std::env::temp_dir().join(format!("port-scanner-ssh-{}", std::process::id()));
+            let _ = std::fs::remove_file(&control);
+            let mut master =
+                master_command(&jump, &options, &control)
+                    .spawn()
+                    .map_err(|source| JumpError::Spawn {
+                        program: options.program.display().to_string(),
+                        source,
+                    })?;
+            // Read so the master never blocks on it; the last line says why
+            // a login failed.
+            let mut log = BufReader::new(master.stderr.take().expect("stderr is piped")).lines();
+            let said = tokio::spawn(async move {
+                let mut said = None;
+                while let Ok(Some(line)) = log.next_line().await {
+                    trace!(%line, "ssh");
+                    if !line.trim().is_empty() {
+                        said = Some(line);
+                    }
+                }
+                said
+            });
+            // The master listens on its control socket once logged in.
+            let mut stream = loop {
+                if let Ok(stream) = UnixStream::connect(&control).await {
+                    break stream;
+                }
+                if master
+                    .try_wait()
+                    .map_err(|e| handshake(e.to_string()))?
+                    .is_some()
+                {
+                    let said = said.await.ok().flatten();
+                    return Err(handshake(
+                        said.unwrap_or_else(|| "ssh exited without a reason".to_string()),
+                    ));
+                }
+                tokio::time::sleep(LOGIN_POLL).await;
+            };
+            proxy_mode(&mut stream)
+                .await
+                .map_err(|e| handshake(e.to_string()))?;
+
+            let (from, to) = stream.into_split();
+            let (outgoing, queued) = mpsc::unbounded_channel();
+            let mux = Arc::new(Mux {
+                outgoing,
+                state: Mutex::new(MuxState {
+                    next_id: 0,
+                    channels: HashMap::new(),
+                    ended: None,
+                }),
+            });
+            tokio::spawn(write_master(to, queued));
+            tokio::spawn(read_master(mux.clone(), from, jump.to_string()));
+            let connector = SshConnector {
+                channels: Arc::new(Semaphore::new(options.channels.max(1))),
+                mux,
+                control,
+                jump,
+                _master: master,
+            };
+            // A channel to the bastion's own SSH port: any answer from the
+            // bastion but a prohibition shows forwarding works.
+            match connector.open("localhost", connector.jump.port).await {
+                Ok(Opened::Failed(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    Err(JumpError::Forwarding {
+                        host: connector.jump.to_string(),
+                        message: e.to_string(),
+                    })
+                }
+                Ok(_) => {
+                    debug!(jump = %connector.jump, "jump host ready");
+                    Ok(connector)
+                }
+                Err(e) => Err(JumpError::Handshake {
+                    host: connector.jump.to_string(),
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        /// Asks the bastion for a channel to `host:port`, waiting for a free
+        /// one first; fails when the session ended without an answer.
+        async fn open(&self, host: &str, port: u16) -> io::Result<Opened> {
+            let permit = self
+                .channels
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("the channel semaphore is never closed");
+            let (answer, answered) = oneshot::channel();
+            let id = {
+                let mut state = self.mux.state.lock().expect("mux state");
+                if let Some(why) = &state.ended {
+                    return Err(io::Error::other(why.clone()));
+                }
+                let id = state.next_id;
+                state.next_id = id.wrapping_add(1);
+                state.channels.insert(id, Slot::Opening(answer));
+                id
+            };
+            let mut open = Message::new(CHANNEL_OPEN);
+            open.string(b"direct-tcpip");
+            open.u32(id);
+            open.u32(WINDOW);
+            open.u32(MAX_PACKET);
+            open.string(host.as_bytes());
+            open.u32(port.into());
+            open.string(b"127.0.0.1");
+            open.u32(0);
+            self.mux.send(open);
+            match answered.await {
+                Ok(Ok(confirmed)) => Ok(Opened::Channel(Box::new(Channel {
+                    id,
+                    max_packet: confirmed.max_packet.max(1),
+                    flow: confirmed.flow,
+                    mux: self.mux.clone(),
+                    unacknowledged: 0,
+                    eof_sent: false,
+                    _permit: permit,
+                }))),
+                Ok(Err(e)) => Ok(Opened::Failed(e)),
+                Err(_) => Err(self.mux.ended()),
+            }
+        }
+    }
+
+    impl Drop for SshConnector {
+        fn drop(&mut self) {
+            // The master is killed, so it can't remove its socket itself.
+            let _ = std::fs::remove_file(&self.control);
+        }
+    }
+
+    impl Connector for SshConnector {
+        fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, io::Result<Connection>> {
+            Box::pin(async move {
+                match self.open(&addr.ip().to_string(), addr.port()).await? {
+                    Opened::Channel(channel) => Ok(channel as Connection),
+                    Opened::Failed(e) => Err(e),
+                }
+            })
+        }
+    }
+
+    /// `ssh -M -S control -N jump`: logs in and serves the control socket
+    /// until killed, even when `~/.ssh/config` asks for a master that
+    /// persists in the background.
+    fn master_command(jump: &JumpHost, options: &SshOptions, control: &Path) -> Command {
+        let mut command = Command::new(&options.program);
+        command
+            .args(["-o", "BatchMode=yes", "-o", "ControlPersist=no"])
+            .args(["-p", &jump.port.to_string()]);
+        if let Some(identity) = &options.identity {
+            command.arg("-i").arg(identity);
+        }
+        if let Some(user) = &jump.user {
+            command.args(["-l", user]);
+        }
+        command
+            .args(["-M", "-S"])
+            .arg(control)
+            .args(["-N", &jump.host])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        command
+    }
+
+    /// Greets the master on its control socket and asks for proxy mode.
+    async fn proxy_mode(stream: &mut UnixStream) -> io::Result<()> {
+        stream
+            .write_all(&control_message(&[MUX_MSG_HELLO, MUX_VERSION]))
+            .await?;
+        if Reader::new(&read_control(stream).await?).u32()? != MUX_MSG_HELLO {
+            return Err(io::Error::other(
+                "unexpected greeting on the control socket",
+            ));
+        }
+        // The request id is 0, the only request.
+        stream
+            .write_all(&control_message(&[MUX_C_PROXY, 0]))
+            .await?;
+        if Reader::new(&read_control(stream).await?).u32()? != MUX_S_PROXY {
+            return Err(io::Error::other("the SSH master refused proxy mode"));
+        }
+        Ok(())
+    }
+
+    /// A message of the control socket: its length, then its words.
+    fn control_message(words: &[u32]) -> Vec<u8> {
+        let mut message = ((words.len() * 4) as u32).to_be_bytes().to_vec();
+        for word in words {
+            message.extend_from_slice(&word.to_be_bytes());
+        }
+        message
+    }
+
+    async fn read_control(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+        let len = stream.read_u32().await? as usize;
+        if len > MAX_MESSAGE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "oversized message on the control socket",
+            ));
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await?;
+        Ok(body)
+    }
+
+    /// Writes the queued messages to the master, in order.
+    async fn write_master(mut to: OwnedWriteHalf, mut queued: mpsc::UnboundedReceiver<Message>) {
+        while let Some(message) = queued.recv().await {
+            if to.write_all(&message.finish()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Hands the master's messages to the channels until the session ends.
+    async fn read_master(mux: Arc<Mux>, mut from: OwnedReadHalf, jump: String) {
+        let why = loop {
+            let message = match read_message(&mut from).await {
+                Ok(message) => message,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    break format!("the SSH session to {} ended", jump);
+                }
+                Err(e) => break format!("the SSH session to {} failed: {}", jump, e),
+            };
+            if let Err(e) = mux.dispatch(&message) {
+                break format!("the SSH session to {} failed: {}", jump, e);
+            }
+        };
+        debug!(%why, "jump host gone");
+        mux.end(why);
+    }
+
+    /// A message of the connection protocol as proxy mode frames it: the
+    /// length, the padding length, the message and the padding, with no
+    /// MAC.
+    async fn read_message(from: &mut OwnedReadHalf) -> io::Result<Vec<u8>> {
+        let len = from.read_u32().await? as usize;
+        if !(2..=MAX_MESSAGE).contains(&len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad message length {}", len),
+            ));
+        }
+        let mut packet = vec![0; len];
+        from.read_exact(&mut packet).await?;
+        let padding = packet[0] as usize;
+        if padding >= len - 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad padding length {}", padding),
+            ));
+        }
+        packet.truncate(len - padding);
+        packet.remove(0);
+        Ok(packet)
+    }
+
+    /// The master's control socket in proxy mode, shared by the channels.
+    struct Mux {
+        /// Messages for the master, written in order by one task.
+        outgoing: mpsc::UnboundedSender<Message>,
+        state: Mutex<MuxState>,
+    }
+
+    struct MuxState {
+        next_id: u32,
+        channels: HashMap<u32, Slot>,
+        /// Why the session ended, once it has.
+        ended: Option<String>,
+    }
+
+    enum Slot {
+        /// Asked for, and waiting for the bastion's answer.
+        Opening(oneshot::Sender<io::Result<Confirmed>>),
+        Open(Arc<Mutex<Flow>>),
+    }
+
+    /// The bastion's confirmation of a channel open.
+    struct Confirmed {
+        max_packet: u32,
+        flow: Arc<Mutex<Flow>>,
+    }
+
+    /// What the reading task and a channel share.
+    struct Flow {
+        /// The bastion's number for the channel.
+        remote: u32,
+        /// Data received and not yet read.
+        received: BytesMut,
+        /// The bastion sent EOF, or the channel is closed.
+        eof: bool,
+        /// The bastion closed the channel, or the session ended.
+        closed: bool,
+        close_sent: bool,
+        /// Bytes the bastion still takes.
+        window: u32,
+        reader: Option<Waker>,
+        writer: Option<Waker>,
+    }
+
+    impl Mux {
+        fn send(&self, message: Message) {
+            // Once the session has ended, the channels have been told.
+            let _ = self.outgoing.send(message);
+        }
+
+        fn ended(&self) -> io::Error {
+            let state = self.state.lock().expect("mux state");
+            io::Error::other(
+                state
+                    .ended
+                    .clone()
+                    .unwrap_or_else(|| "SSH session ended".into()),
+            )
+        }
+
+        fn flow(&self, id: u32) -> Option<Arc<Mutex<Flow>>> {
+            match self.state.lock().expect("mux state").channels.get(&id) {
+                Some(Slot::Open(flow)) => Some(flow.clone()),
+                _ => None,
+            }
+        }
+
+        /// Acts on a message from the bastion; messages for channels that
+        /// are gone are dropped.
+        fn dispatch(&self, message: &[u8]) -> io::Result<()> {
+            let mut r = Reader::new(message);
+            let kind = r.u8()?;
+            if !(CHANNEL_OPEN_CONFIRMATION..=CHANNEL_FAILURE).contains(&kind) {
+                trace!(kind, "ignored SSH message");
+                return Ok(());
+            }
+            let id = r.u32()?;
+            match kind {
+                CHANNEL_OPEN_CONFIRMATION => {
+                    let remote = r.u32()?;
+                    let window = r.u32()?;
+                    let max_packet = r.u32()?;
+                    let flow = Arc::new(Mutex::new(Flow {
+                        remote,
+                        received: BytesMut::new(),
+                        eof: false,
+                        closed: false,
+                        close_sent: false,
+                        window,
+                        reader: None,
+                        writer: None,
+                    }));
+                    let mut state = self.state.lock().expect("mux state");
+                    let Some(Slot::Opening(answer)) = state.channels.remove(&id) else {
+                        return Ok(());
+                    };
+                    state.channels.insert(id, Slot::Open(flow.clone()));
+                    drop(state);
+                    let confirmed = Confirmed {
+                        max_packet,
+                        flow: flow.clone(),
+                    };
+                    if answer.send(Ok(confirmed)).is_err() {
+                        // Given up on, as when the connect timed out.
+                        self.state.lock().expect("mux state").channels.remove(&id);
+                        flow.lock().expect("channel flow").close(self);
+                    }
+                }
+                CHANNEL_OPEN_FAILURE => {
+                    let reason = r.u32()?;
+                    let description = r.string()?;
+                    let slot = self.state.lock().expect("mux state").channels.remove(&id);
+                    if let Some(Slot::Opening(answer)) = slot {
+                        let description = String::from_utf8_lossy(description);
+                        let _ = answer.send(Err(open_failure(reason, &description)));
+                    }
+                }
+                CHANNEL_WINDOW_ADJUST => {
+                    if let Some(flow) = self.flow(id) {
+                        let mut flow = flow.lock().expect("channel flow");
+                        flow.window = flow.window.saturating_add(r.u32()?);
+                        flow.wake_writer();
+                    }
+                }
+                CHANNEL_DATA => {
+                    if let Some(flow) = self.flow(id) {
+                        let mut flow = flow.lock().expect("channel flow");
+                        flow.received.extend_from_slice(r.string()?);
+                        flow.wake_reader();
+                    }
+                }
+                CHANNEL_EOF => {
+                    if let Some(flow) = self.flow(id) {
+                        let mut flow = flow.lock().expect("channel flow");
+                        flow.eof = true;
+                        flow.wake_reader();
+                    }
+                }
+                CHANNEL_CLOSE => {
+                    if let Some(flow) = self.flow(id) {
+                        self.state.lock().expect("mux state").channels.remove(&id);
+                        let mut flow = flow.lock().expect("channel flow");
+                        flow.eof = true;
+                        flow.closed = true;
+                        flow.close(self);
+                        flow.wake();
+                    }
+                }
+                CHANNEL_REQUEST => {
+                    // Nothing is asked of a direct-tcpip channel; a request
+                    // that wants a reply is turned down.
+                    let _name = r.string()?;
+                    if r.u8()? != 0 {
+                        if let Some(flow) = self.flow(id) {
+                            let mut failure = Message::new(CHANNEL_FAILURE);
+                            failure.u32(flow.lock().expect("channel flow").remote);
+                            self.send(failure);
+                        }
+                    }
+                }
+                _ => trace!(kind, "ignored SSH channel message"),
+            }
+            Ok(())
+        }
+
+        /// Fails the channels still waiting for an answer and ends the open
+        /// ones.
+        fn end(&self, why: String) {
+            let channels = {
+                let mut state = self.state.lock().expect("mux state");
+                state.ended = Some(why);
+                std::mem::take(&mut state.channels)
+            };
+            for slot in channels.into_values() {
+                if let Slot::Open(flow) = slot {
+                    let mut flow = flow.lock().expect("channel flow");
+                    flow.eof = true;
+                    flow.closed = true;
+                    flow.close_sent = true;
+                    flow.wake();
+                }
+            }
+        }
+    }
+
+    impl Flow {
+        fn wake_reader(&mut self) {
+            if let Some(waker) = self.reader.take() {
+                waker.wake();
+            }
+        }
+
+        fn wake_writer(&mut self) {
+            if let Some(waker) = self.writer.take() {
+                waker.wake();
+            }
+        }
+
+        fn wake(&mut self) {
+            self.wake_reader();
+            self.wake_writer();
+        }
+
+        /// Closes the channel on the bastion's side, once.
+        fn close(&mut self, mux: &Mux) {
+            if !self.close_sent {
+                self.close_sent = true;
+                let mut close = Message::new(CHANNEL_CLOSE);
+                close.u32(self.remote);
+                mux.send(close);
+            }
+        }
+    }
+
+    /// A channel: its data is the connection. Dropping it closes the
+    /// channel.
+    struct Channel {
+        id: u32,
+        max_packet: u32,
+        flow: Arc<Mutex<Flow>>,
+        mux: Arc<Mux>,
+        /// Bytes read since the bastion's window was last topped up.
+        unacknowledged: u32,
+        eof_sent: bool,
+        _permit: OwnedSemaphorePermit,
+    }
+
+    impl AsyncRead for Channel {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let this = &mut *self;
+            let mut flow = this.flow.lock().expect("channel flow");
+            if flow.received.is_empty() {
+                if !flow.eof {
+                    flow.reader = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            let n = buf.remaining().min(flow.received.len());
+            buf.put_slice(&flow.received.split_to(n));
+            this.unacknowledged += n as u32;
+            if this.unacknowledged >= WINDOW / 2 && !flow.closed {
+                let mut adjust = Message::new(CHANNEL_WINDOW_ADJUST);
+                adjust.u32(flow.remote);
+                adjust.u32(this.unacknowledged);
+                this.mux.send(adjust);
+                this.unacknowledged = 0;
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Channel {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let mut flow = self.flow.lock().expect("channel flow");
+            if flow.closed || self.eof_sent {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            if flow.window == 0 {
+                flow.writer = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let n = buf
+                .len()
+                .min(flow.window as usize)
+                .min(self.max_packet as usize);
+            flow.window -= n as u32;
+            let mut data = Message::new(CHANNEL_DATA);
+            data.u32(flow.remote);
+            data.string(&buf[..n]);
+            self.mux.send(data);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = &mut *self;
+            let flow = this.flow.lock().expect("channel flow");
+            if !this.eof_sent && !flow.closed {
+                let mut eof = Message::new(CHANNEL_EOF);
+                eof.u32(flow.remote);
+                this.mux.send(eof);
+            }
+            this.eof_sent = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl Drop for Channel {
+        fn drop(&mut self) {
+            self.flow.lock().expect("channel flow").close(&self.mux);
+            let mut state = self.mux.state.lock().expect("mux state");
+            if matches!(state.channels.get(&self.id), Some(Slot::Open(_))) {
+                state.channels.remove(&self.id);
+            }
+        }
+    }
+
+    /// Builds a message of the connection protocol, framed for proxy mode:
+    /// the length, a padding length of zero and the message.
+    struct Message(Vec<u8>);
+
+    impl Message {
+        fn new(kind: u8) -> Self {
+            Message(vec![0, 0, 0, 0, 0, kind])
+        }
+
+        fn u32(&mut self, v: u32) {
+            self.0.extend_from_slice(&v.to_be_bytes());
+        }
+
+        fn string(&mut self, v: &[u8]) {
+            self.u32(v.len() as u32);
+            self.0.extend_from_slice(v);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            let len = (self.0.len() - 4) as u32;
+            self.0[..4].copy_from_slice(&len.to_be_bytes());
+            self.0
+        }
+    }
+
+    /// Reads the SSH wire encoding.
+    struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        fn new(data: &'a [u8]) -> Self {
+            Reader { data }
+        }
+
+        fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+            if self.data.len() < n {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated message from the SSH master",
+                ));
+            }
+            let (head, rest) = self.data.split_at(n);
+            self.data = rest;
+            Ok(head)
+        }
+
+        fn u8(&mut self) -> io::Result<u8> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn u32(&mut self) -> io::Result<u32> {
+            Ok(u32::from_be_bytes(
+                self.take(4)?.try_into().expect("4 bytes"),
+            ))
+        }
+
+        fn string(&mut self) -> io::Result<&'a [u8]> {
+            let len = self.u32()? as usize;
+            self.take(len)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn messages_are_framed_as_the_master_reads_them_in_proxy_mode() {
+            // The session open of OpenSSH 9.2's own `ssh -O proxy`.
+            let mut open = Message::new(CHANNEL_OPEN);
+            open.string(b"session");
+            open.u32(0);
+            open.u32(2 * 1024 * 1024);
+            open.u32(32 * 1024);
+            let bytes = open.finish();
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(
+                hex,
+                "00000019005a0000000773657373696f6e000000000020000000008000"
+            );
+            assert_eq!(
+                control_message(&[MUX_C_PROXY, 0]),
+                [0, 0, 0, 8, 0x10, 0, 0, 0x0f, 0, 0, 0, 0]
+            );
+
+            let mut r = Reader::new(&bytes[5..]);
+            assert_eq!(r.u8().unwrap(), CHANNEL_OPEN);
+            assert_eq!(r.string().unwrap(), b"session");
+            assert_eq!(r.u32().unwrap(), 0);
+            assert!(Reader::new(&[0, 0, 0, 9, b'x']).string().is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_hosts_parse_with_or_without_user_and_port() {
+        let parse = |s: &str| s.parse::<JumpHost>();
+        assert_eq!(
+            parse("alice@bastion.example.com:2222").unwrap(),
+            JumpHost {
+                user: Some("alice".to_string()),
+                host: "bastion.example.com".to_string(),
+                port: 2222,
+            }
+        );
+        assert_eq!(parse("bastion").unwrap().port, 22);
+        assert_eq!(parse("bastion").unwrap().user, None);
+        assert_eq!(parse("ops@[2001:db8::1]:22").unwrap().host, "2001:db8::1");
+        assert_eq!(parse("2001:db8::1").unwrap().port, 22);
+        assert_eq!(
+            parse("ops@[2001:db8::1]:2200").unwrap().to_string(),
+            "ops@[2001:db8::1]:2200"
+        );
+        for bad in ["", "@bastion", "alice@", "bastion:0", "bastion:ssh", "[::1"] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn channel_open_failures_read_as_connect_errors() {
+        let kind = |reason: u32, description: &str| open_failure(reason, description).kind();
+        assert_eq!(
+            kind(2, "Connection refused"),
+            io::ErrorKind::ConnectionRefused
+        );
+        assert_eq!(kind(2, "No route to host"), io::ErrorKind::HostUnreachable);
+        assert_eq!(kind(1, "open failed"), io::ErrorKind::PermissionDenied);
+        assert_eq!(kind(4, ""), io::ErrorKind::OutOfMemory);
+        assert_eq!(
+            open_failure(2, "Connection refused").to_string(),
+            "via jump host: connect failed: Connection refused"
+        );
+        assert_eq!(
+            open_failure(1, "").to_string(),
+            "via jump host: administratively prohibited"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! JUnit XML reports for CI systems.
+
+use std::io;
+use std::net::IpAddr;
+
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+
+struct TestCase {
+    classname: String,
+    name: String,
+    time: f64,
+    failure: Option<String>,
+}
+
+/// Renders the summary as a JUnit XML report.
+///
+/// When a `--fail-on`/`--allow-open` policy was evaluated, every assertion
+/// becomes a test case; otherwise every scanned port does and all of them pass.
+pub fn render(summary: &ScanSummary) -> String {
+    let mut out = Vec::new();
+    write(summary, &mut out).expect("writing to a Vec cannot fail");
+    String::from_utf8(out).expect("the report is UTF-8")
+}
+
+/// Writes the report of [`render`] to `out`, one test case at a time, so a
+/// spilled scan is never loaded back into memory.
+pub fn write(summary: &ScanSummary, out: &mut impl io::Write) -> io::Result<()> {
+    let suite_name = format!("port-scanner.{}", summary.target);
+    let (tests, failures, mut cases): (
+        usize,
+        usize,
+        Box<dyn Iterator<Item = io::Result<TestCase>>>,
+    ) = match &summary.policy {
+        Some(policy) => (
+            policy.assertions.len(),
+            policy.assertions.iter().filter(|a| !a.passed).count(),
+            Box::new(policy.assertions.iter().map(|a| {
+                Ok(TestCase {
+                    classname: format!("port-scanner.{}", a.target),
+                    name: format!("{} port {}", a.rule, a.port),
+                    time: port_time(&summary.results, a.target, a.port),
+                    failure: (!a.passed).then(|| a.message.clone()),
+                })
+            })),
+        ),
+        None => (
+            summary.scanned_ports,
+            0,
+            Box::new(summary.reported_results()?.map(|r| {
+                r.map(|r| TestCase {
+                    classname: format!("port-scanner.{}", r.target),
+                    name: format!("port {} {}", r.port_label(), status_name(&r.status)),
+                    time: r.duration_ms as f64 / 1000.0,
+                    failure: None,
+                })
+            })),
+        ),
+    };
+    let time = summary.total_time_ms as f64 / 1000.0;
+
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<testsuites name=\"port-scanner\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">",
+        tests,
+        failures,
+        time
+    )?;
+    writeln!(
+        out,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"0\" time=\"{:.3}\">",
+        escape(&suite_name),
+        tests,
+        failures,
+        time
+    )?;
+    writeln!(out, "    <properties>")?;
+    let properties = [
+        ("target", summary.target.clone()),
+        ("scanned_ports", summary.scanned_ports.to_string()),
+        ("open_ports", summary.open_ports.to_string()),
+        ("total_time_ms", summary.total_time_ms.to_string()),
+        ("interrupted", summary.interrupted.to_string()),
+    ];
+    let metadata = summary.metadata.pairs();
+    let properties = properties
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.as_str()))
+        .chain(metadata);
+    for (name, value) in properties {
+        writeln!(
+            out,
+            "      <property name=\"{}\" value=\"{}\"/>",
+            escape(&name),
+            escape(value)
+        )?;
+    }
+    writeln!(out, "    </properties>")?;
+
+    cases.try_for_each(|case| {
+        let case = case?;
+        write!(
+            out,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&case.name),
+            escape(&case.classname),
+            case.time
+        )?;
+        match &case.failure {
+            Some(message) => writeln!(
+                out,
+                ">\n      <failure message=\"{}\" type=\"PolicyViolation\">{}</failure>\n    </testcase>",
+                escape(message),
+                escape(message)
+            ),
+            None => writeln!(out, "/>"),
+        }
+    })?;
+
+    writeln!(out, "  </testsuite>\n</testsuites>")
+}
+
+fn port_time(results: &[PortResult], target: IpAddr, port: u16) -> f64 {
+    results
+        .iter()
+        .find(|r| r.target == target && r.port == port)
+        .map_or(0.0, |r| r.duration_ms as f64 / 1000.0)
+}
+
+fn status_name(status: &PortStatus) -> &'static str {
+    match status {
+        PortStatus::Open => "open",
+        PortStatus::Closed => "closed",
+        PortStatus::Filtered => "filtered",
+    }
+}
+
+/// Escapes text for use in XML attributes and content, dropping characters
+/// XML 1.0 cannot represent at all.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            '\t' => out.push_str("&#9;"),
+            c if (c as u32) < 0x20 || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banner::Banner;
+    use crate::metadata::Metadata;
+    use crate::policy::{AllowOpen, Policy};
+    use std::path::Path;
+    use std::process::Command;
+
+    fn result(port: u16, status: PortStatus, banner: Option<&str>) -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), port, status);
+        r.banner = banner.map(Banner::from);
+        r.service = banner.map(|_| "SSH".to_string());
+        r.duration_ms = 12;
+        r
+    }
+
+    fn summary(policy: Option<Policy>) -> ScanSummary {
+        let results = vec![
+            result(22, PortStatus::Open, Some("SSH-2.0-OpenSSH <\"&\">\x01")),
+            result(80, PortStatus::Open, None),
+            result(81, PortStatus::Closed, None),
+        ];
+        ScanSummary {
+            scan_id: "test".to_string(),
+            target: "10.0.0.1".to_string(),
+            metadata: Default::default(),
+            preset: None,
+            scanned_ports: 3,
+            open_ports: 2,
+            closed_ports: 1,
+            filtered_ports: 0,
+            unscanned_ports: 0,
+            protocols: Vec::new(),
+            reset_on_read_ports: 0,
+            capture_truncated_ports: 0,
+            panicked_ports: 0,
+            blocked_hosts: 0,
+            total_time_ms: 1234,
+            ports_per_second: 2.4,
+            concurrency: 512,
+            connection_attempts: 3,
+            retries: 0,
+            banner_probes: 2,
+            banner_bytes: 30,
+            stats: None,
+            established: None,
+            retry_budget: None,
+            heatmap: None,
+            interrupted: false,
+            completion: None,
+            findings: Default::default(),
+            deviations: None,
+            policy: policy.map(|p| p.evaluate(&results)),
+            compliance: None,
+            script_summary: None,
+            reanalysis: None,
+            tls_report: None,
+            rescan: None,
+            identity: Default::default(),
+            targets: Vec::new(),
+            hosts: Vec::new(),
+            clusters: Vec::new(),
+            shared_responses: Default::default(),
+            results,
+            spill: None,
+            filter: None,
+        }
+    }
+
+    /// Validates against the reference XSD with xmllint.
+    fn assert_schema_valid(xml: &str) {
+        let xsd = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/junit.xsd");
+        let file = std::env::temp_dir().join(format!("scanner-junit-{}.xml", std::process::id()));
+        std::fs::write(&file, xml).unwrap();
+        let output = Command::new("xmllint")
+            .arg("--noout")
+            .arg("--schema")
+            .arg(&xsd)
+            .arg(&file)
+            .output();
+        let _ = std::fs::remove_file(&file);
+        let out = output.expect("xmllint (libxml2) must be on PATH for this test");
+        assert!(
+            out.status.success(),
+            "schema validation failed: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+
+    fn with_metadata() -> ScanSummary {
+        let mut summary = summary(None);
+        summary.metadata = Metadata::new(
+            Some("jsmith".to_string()),
+            None,
+            ["owner=<ops & sec>".parse().unwrap()],
+        )
+        .unwrap();
+        summary
+    }
+
+    fn allowing_80() -> Policy {
+        Policy {
+            fail_on: vec![],
+            allow_open: Some(AllowOpen(vec![80])),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs xmllint; run with cargo test -- --ignored"]
+    fn reports_validate_against_the_reference_xsd() {
+        for summary in [summary(None), with_metadata(), summary(Some(allowing_80()))] {
+            assert_schema_valid(&render(&summary));
+        }
+    }
+
+    #[test]
+    fn ports_become_passing_cases_without_policy() {
+        let xml = render(&summary(None));
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+
+        let suite = doc
+            .descendants()
+            .find(|n| n.has_tag_name("testsuite"))
+            .unwrap();
+        assert_eq!(suite.attribute("tests"), Some("3"));
+        assert_eq!(suite.attribute("failures"), Some("0"));
+        let target = suite
+            .descendants()
+            .find(|n| n.attribute("name") == Some("target"))
+            .unwrap();
+        assert_eq!(target.attribute("value"), Some("10.0.0.1"));
+        assert!(!doc.descendants().any(|n| n.has_tag_name("failure")));
+    }
+
+    #[test]
+    fn metadata_becomes_suite_properties() {
+        let xml = render(&with_metadata());
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+
+        let value = |name| {
+            doc.descendants()
+                .find(|n| n.has_tag_name("property") && n.attribute("name") == Some(name))
+                .and_then(|n| n.attribute("value"))
+        };
+        assert_eq!(value("operator"), Some("jsmith"));
+        assert_eq!(value("tag:owner"), Some("<ops & sec>"));
+        assert_eq!(value("reason"), None);
+    }
+
+    #[test]
+    fn disallowed_open_ports_are_failures() {
+        let xml = render(&summary(Some(allowing_80())));
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+
+        let failures: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("failure"))
+            .collect();
+        assert_eq!(failures.len(), 1);
+        let message = failures[0].attribute("message").unwrap();
+        assert!(message.contains("port 22 is open but not allowed"));
+        assert!(message.contains("SSH-2.0-OpenSSH <\"&\">"));
+        assert_eq!(
+            failures[0].parent().unwrap().attribute("name"),
+            Some("allow-open port 22")
+        );
+    }
+}
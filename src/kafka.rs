@@ -0,0 +1,694 @@
+//! A Kafka producer for `--publish kafka://…`, built with the `kafka`
+//! feature.
+//!
+//! It asks the bootstrap brokers for the topic's partition leaders
+//! (Metadata v4, creating the topic if the cluster allows it), picks each
+//! message's partition the way the Java client does — murmur2 of the key,
+//! or round robin without one — and sends every leader its share as
+//! uncompressed v2 record batches (Produce v3, `acks=all`). Any error drops
+//! the connections and the metadata, and the next batch starts afresh.
+//! SASL, TLS and idempotent delivery aren't supported.
+
+use futures::future::BoxFuture;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::publish::{Message, Transport, ACK_TIMEOUT, CONNECT_TIMEOUT};
+
+const PRODUCE: i16 = 0;
+const METADATA: i16 = 3;
+const CLIENT_ID: &str = "port-scanner";
+
+/// The broker has no leader for the partition yet, as right after the
+/// topic was created.
+const LEADER_NOT_AVAILABLE: i16 = 5;
+
+/// How often metadata is asked for while a new topic elects its leaders.
+const METADATA_ATTEMPTS: u32 = 5;
+
+/// Where a topic's partitions live.
+#[derive(Debug, Clone, PartialEq)]
+struct Metadata {
+    /// `host:port` of each broker by node id.
+    brokers: HashMap<i32, (String, u16)>,
+    /// The leader node of each partition, by partition index.
+    leaders: Vec<i32>,
+}
+
+/// Produces to one topic.
+pub struct KafkaTransport {
+    bootstrap: Vec<String>,
+    topic: String,
+    metadata: Option<Metadata>,
+    connections: HashMap<i32, TcpStream>,
+    correlation: i32,
+    next_partition: usize,
+}
+
+impl KafkaTransport {
+    /// A transport for `topic`; nothing connects until the first batch.
+    pub fn new(bootstrap: Vec<String>, topic: String) -> Self {
+        KafkaTransport {
+            bootstrap,
+            topic,
+            metadata: None,
+            connections: HashMap::new(),
+            correlation: 0,
+            next_partition: 0,
+        }
+    }
+
+    async fn produce(&mut self, batch: &[Message]) -> io::Result<()> {
+        if self.metadata.is_none() {
+            self.metadata = Some(self.fetch_metadata().await?);
+        }
+        let metadata = self.metadata.clone().expect("metadata was just fetched");
+
+        // leader -> partition -> messages
+        let mut plan: BTreeMap<i32, BTreeMap<i32, Vec<&Message>>> = BTreeMap::new();
+        for message in batch {
+            let partition = match &message.key {
+                Some(key) => partition_for(key.as_bytes(), metadata.leaders.len()),
+                None => {
+                    self.next_partition = self.next_partition.wrapping_add(1);
+                    self.next_partition % metadata.leaders.len()
+                }
+            };
+            plan.entry(metadata.leaders[partition])
+                .or_default()
+                .entry(partition as i32)
+                .or_default()
+                .push(message);
+        }
+
+        let timestamp = chrono::Utc::now().timestamp_millis();
+        for (leader, partitions) in plan {
+            let mut body = Encoder::default();
+            body.i16(-1); // transactional_id: none
+            body.i16(-1); // acks: all in-sync replicas
+            body.i32(ACK_TIMEOUT.as_millis() as i32);
+            body.i32(1);
+            body.string(&self.topic);
+            body.i32(partitions.len() as i32);
+            for (partition, messages) in &partitions {
+                body.i32(*partition);
+                body.bytes(&record_batch(messages, timestamp));
+            }
+            let &(ref host, port) = metadata.brokers.get(&leader).ok_or_else(|| {
+                io::Error::other(format!("Kafka metadata names no broker {}", leader))
+            })?;
+            let correlation = self.next_correlation();
+            let stream = match self.connections.entry(leader) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(connect(host, port).await?),
+            };
+            let response = exchange(stream, PRODUCE, 3, correlation, &body.0).await?;
+            check_produce(&response)?;
+            debug!(
+                "Produced {} messages to {} on broker {}",
+                partitions.values().map(Vec::len).sum::<usize>(),
+                self.topic,
+                leader
+            );
+        }
+        Ok(())
+    }
+
+    async fn fetch_metadata(&mut self) -> io::Result<Metadata> {
+        let mut last = io::Error::other("no Kafka broker to connect to");
+        for attempt in 1..=METADATA_ATTEMPTS {
+            for server in self.bootstrap.clone() {
+                let Some((host, port)) = server
+                    .rsplit_once(':')
+                    .and_then(|(h, p)| Some((h.trim_matches(['[', ']']), p.parse().ok()?)))
+                else {
+                    return Err(io::Error::other(format!("bad Kafka broker '{}'", server)));
+                };
+                let mut body = Encoder::default();
+                body.i32(1);
+                body.string(&self.topic);
+                body.i8(1); // allow_auto_topic_creation
+                let correlation = self.next_correlation();
+                let response = match connect(host, port).await {
+                    Ok(mut stream) => {
+                        exchange(&mut stream, METADATA, 4, correlation, &body.0).await
+                    }
+                    Err(e) => Err(e),
+                };
+                match response.and_then(|r| parse_metadata(&r, &self.topic)) {
+                    Ok(Ok(metadata)) => return Ok(metadata),
+                    Ok(Err(code)) => {
+                        last = io::Error::other(format!(
+                            "Kafka has no leaders for topic {} (error {})",
+                            self.topic, code
+                        ));
+                        if code != LEADER_NOT_AVAILABLE {
+                            return Err(last);
+                        }
+                        break;
+                    }
+                    Err(e) => last = e,
+                }
+            }
+            if attempt < METADATA_ATTEMPTS {
+                tokio::time::sleep(Duration::from_millis(250 * u64::from(attempt))).await;
+            }
+        }
+        Err(last)
+    }
+
+    fn next_correlation(&mut self) -> i32 {
+        self.correlation = self.correlation.wrapping_add(1);
+        self.correlation
+    }
+}
+
+impl Transport for KafkaTransport {
+    fn send<'a>(&'a mut self, batch: &'a [Message]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let sent = self.produce(batch).await;
+            if sent.is_err() {
+                // Leaders may have moved: look them up again next time.
+                self.metadata = None;
+                self.connections.clear();
+            }
+            sent
+        })
+    }
+}
+
+async fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Kafka connect timed out"))??;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Sends one request and returns the body of its response.
+async fn exchange(
+    stream: &mut TcpStream,
+    api_key: i16,
+    version: i16,
+    correlation: i32,
+    body: &[u8],
+) -> io::Result<Vec<u8>> {
+    let mut request = Encoder::default();
+    request.i32(0); // size, filled in below
+    request.i16(api_key);
+    request.i16(version);
+    request.i32(correlation);
+    request.string(CLIENT_ID);
+    request.0.extend_from_slice(body);
+    let size = (request.0.len() - 4) as i32;
+    request.0[..4].copy_from_slice(&size.to_be_bytes());
+    stream.write_all(&request.0).await?;
+
+    tokio::time::timeout(ACK_TIMEOUT, async {
+        let size = stream.read_i32().await?;
+        let mut response = vec![0; usize::try_from(size).unwrap_or(0)];
+        stream.read_exact(&mut response).await?;
+        let mut decoder = Decoder::new(&response);
+        if decoder.i32()? != correlation {
+            return Err(io::Error::other("Kafka response out of order"));
+        }
+        Ok(response[4..].to_vec())
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Kafka broker did not answer"))?
+}
+
+/// The topic's leaders from a Metadata v4 response, or the error code of a
+/// topic or partition without one.
+fn parse_metadata(response: &[u8], topic: &str) -> io::Result<Result<Metadata, i16>> {
+    let mut d = Decoder::new(response);
+    d.i32()?; // throttle_time_ms
+    let mut brokers = HashMap::new();
+    for _ in 0..d.array()? {
+        let node = d.i32()?;
+        let host = d.string()?;
+        let port = d.i32()?;
+        d.nullable_string()?; // rack
+        brokers.insert(node, (host, port as u16));
+    }
+    d.nullable_string()?; // cluster_id
+    d.i32()?; // controller_id
+    for _ in 0..d.array()? {
+        let error = d.i16()?;
+        let name = d.string()?;
+        d.i8()?; // is_internal
+        let mut leaders = BTreeMap::new();
+        let mut partition_error = 0;
+        for _ in 0..d.array()? {
+            let error = d.i16()?;
+            let index = d.i32()?;
+            let leader = d.i32()?;
+            for _ in 0..2 {
+                // replica and in-sync replica nodes
+                for _ in 0..d.array()? {
+                    d.i32()?;
+                }
+            }
+            if error != 0 || leader < 0 {
+                partition_error = if error != 0 {
+                    error
+                } else {
+                    LEADER_NOT_AVAILABLE
+                };
+            }
+            leaders.insert(index, leader);
+        }
+        if name != topic {
+            continue;
+        }
+        if error != 0 {
+            return Ok(Err(error));
+        }
+        if partition_error != 0 || leaders.is_empty() {
+            return Ok(Err(if leaders.is_empty() {
+                LEADER_NOT_AVAILABLE
+            } else {
+                partition_error
+            }));
+        }
+        let leaders: Vec<i32> = leaders.into_values().collect();
+        return Ok(Ok(Metadata { brokers, leaders }));
+    }
+    Err(io::Error::other(format!(
+        "Kafka metadata lacks topic {}",
+        topic
+    )))
+}
+
+/// Fails on the first partition a Produce v3 response reports an error
+/// for.
+fn check_produce(response: &[u8]) -> io::Result<()> {
+    let mut d = Decoder::new(response);
+    for _ in 0..d.array()? {
+        let topic = d.string()?;
+        for _ in 0..d.array()? {
+            let partition = d.i32()?;
+            let error = d.i16()?;
+            d.i64()?; // base_offset
+            d.i64()?; // log_append_time
+            if error != 0 {
+                return Err(io::Error::other(format!(
+                    "Kafka rejected the messages for {}/{} (error {})",
+                    topic, partition, error
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The partition the Java client's default partitioner picks for `key`.
+fn partition_for(key: &[u8], partitions: usize) -> usize {
+    (murmur2(key) & 0x7fff_ffff) as usize % partitions
+}
+
+/// Kafka's variant of MurmurHash2, as in `org.apache.kafka.common.utils`.
+fn murmur2(data: &[u8]) -> i32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, &b) in rest.iter().enumerate().rev() {
+            h ^= u32::from(b) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+/// `messages` as one uncompressed v2 record batch.
+fn record_batch(messages: &[&Message], timestamp: i64) -> Vec<u8> {
+    let mut records = Encoder::default();
+    for (offset, message) in messages.iter().enumerate() {
+        let mut record = Encoder::default();
+        record.i8(0); // attributes
+        record.varint(0); // timestamp delta
+        record.varint(offset as i64);
+        match &message.key {
+            Some(key) => {
+                record.varint(key.len() as i64);
+                record.0.extend_from_slice(key.as_bytes());
+            }
+            None => record.varint(-1),
+        }
+        record.varint(message.payload.len() as i64);
+        record.0.extend_from_slice(&message.payload);
+        record.varint(0); // headers
+        records.varint(record.0.len() as i64);
+        records.0.extend_from_slice(&record.0);
+    }
+
+    // Everything the CRC covers: attributes through the records.
+    let mut tail = Encoder::default();
+    tail.i16(0); // attributes: no compression, create time
+    tail.i32(messages.len() as i32 - 1); // last offset delta
+    tail.i64(timestamp);
+    tail.i64(timestamp);
+    tail.i64(-1); // producer id
+    tail.i16(-1); // producer epoch
+    tail.i32(-1); // base sequence
+    tail.i32(messages.len() as i32);
+    tail.0.extend_from_slice(&records.0);
+
+    let mut batch = Encoder::default();
+    batch.i64(0); // base offset
+    batch.i32((4 + 1 + 4 + tail.0.len()) as i32);
+    batch.i32(-1); // partition leader epoch
+    batch.i8(2); // magic
+    batch.0.extend_from_slice(&crc32c(&tail.0).to_be_bytes());
+    batch.0.extend_from_slice(&tail.0);
+    batch.0
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32C (Castagnoli), the checksum of record batches.
+fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| {
+        CRC32C_TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Builds Kafka's big-endian wire encoding.
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn i8(&mut self, v: i8) {
+        self.0.push(v as u8);
+    }
+
+    fn i16(&mut self, v: i16) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i64(&mut self, v: i64) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn string(&mut self, v: &str) {
+        self.i16(v.len() as i16);
+        self.0.extend_from_slice(v.as_bytes());
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.i32(v.len() as i32);
+        self.0.extend_from_slice(v);
+    }
+
+    /// A zigzag varint, as record fields use.
+    fn varint(&mut self, v: i64) {
+        let mut z = ((v << 1) ^ (v >> 63)) as u64;
+        while z >= 0x80 {
+            self.0.push((z as u8) | 0x80);
+            z >>= 7;
+        }
+        self.0.push(z as u8);
+    }
+}
+
+/// Reads Kafka's big-endian wire encoding.
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Decoder { data }
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Kafka response is truncated",
+            ));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn i8(&mut self) -> io::Result<i8> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(
+            self.take(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_be_bytes(
+            self.take(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+
+    fn nullable_string(&mut self) -> io::Result<Option<String>> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let bytes = self.take(len as usize)?;
+        Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        Ok(self.nullable_string()?.unwrap_or_default())
+    }
+
+    /// An array's length; null arrays are empty.
+    fn array(&mut self) -> io::Result<i32> {
+        Ok(self.i32()?.max(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn checksums_and_partitions_match_the_java_client() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(murmur2(b"21"), -973_932_308);
+        assert_eq!(murmur2(b"foobar"), -790_332_482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985_981_536);
+        assert_eq!(murmur2(b"abc"), 479_470_107);
+        assert_eq!(
+            partition_for(b"foobar", 3),
+            (-790_332_482i32 & 0x7fff_ffff) as usize % 3
+        );
+
+        let mut e = Encoder::default();
+        for v in [0, -1, 1, 150] {
+            e.varint(v);
+        }
+        assert_eq!(e.0, [0x00, 0x01, 0x02, 0xac, 0x02]);
+    }
+
+    /// Reads one request, returning its API key, correlation id and body.
+    async fn request(stream: &mut TcpStream) -> Option<(i16, i32, Vec<u8>)> {
+        let size = stream.read_i32().await.ok()?;
+        let mut request = vec![0; size as usize];
+        stream.read_exact(&mut request).await.ok()?;
+        let mut d = Decoder::new(&request);
+        let api_key = d.i16().unwrap();
+        d.i16().unwrap();
+        let correlation = d.i32().unwrap();
+        d.string().unwrap();
+        Some((api_key, correlation, d.data.to_vec()))
+    }
+
+    async fn respond(stream: &mut TcpStream, correlation: i32, body: Encoder) {
+        let mut response = Encoder::default();
+        response.i32(4 + body.0.len() as i32);
+        response.i32(correlation);
+        response.0.extend_from_slice(&body.0);
+        stream.write_all(&response.0).await.unwrap();
+    }
+
+    /// The keys and values of a record batch, after checking its CRC.
+    fn records(batch: &[u8]) -> Vec<(Option<String>, String)> {
+        fn varint(data: &mut &[u8]) -> i64 {
+            let (mut z, mut shift) = (0u64, 0);
+            loop {
+                let b = data[0];
+                *data = &data[1..];
+                z |= u64::from(b & 0x7f) << shift;
+                shift += 7;
+                if b < 0x80 {
+                    return (z >> 1) as i64 ^ -((z & 1) as i64);
+                }
+            }
+        }
+        assert_eq!(batch[16], 2, "magic");
+        let crc = u32::from_be_bytes(batch[17..21].try_into().unwrap());
+        assert_eq!(crc, crc32c(&batch[21..]));
+        let count = i32::from_be_bytes(batch[57..61].try_into().unwrap());
+        let mut data = &batch[61..];
+        (0..count)
+            .map(|_| {
+                varint(&mut data); // length
+                data = &data[1..]; // attributes
+                varint(&mut data);
+                varint(&mut data);
+                let key = match varint(&mut data) {
+                    -1 => None,
+                    n => {
+                        let (key, rest) = data.split_at(n as usize);
+                        data = rest;
+                        Some(String::from_utf8(key.to_vec()).unwrap())
+                    }
+                };
+                let n = varint(&mut data) as usize;
+                let (value, rest) = data.split_at(n);
+                data = rest;
+                assert_eq!(varint(&mut data), 0, "headers");
+                (key, String::from_utf8(value.to_vec()).unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn messages_reach_the_partition_leaders() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = tokio::spawn(async move {
+            let mut produced = Vec::new();
+            let mut creating = true;
+            while produced.is_empty() {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                while let Some((api_key, correlation, body)) = request(&mut stream).await {
+                    let mut r = Encoder::default();
+                    if api_key == METADATA {
+                        r.i32(0);
+                        r.i32(1);
+                        r.i32(7);
+                        r.string("127.0.0.1");
+                        r.i32(port as i32);
+                        r.i16(-1);
+                        r.i16(-1);
+                        r.i32(7);
+                        r.i32(1);
+                        // The first answer comes while the topic is created.
+                        r.i16(if creating { LEADER_NOT_AVAILABLE } else { 0 });
+                        r.string("scans");
+                        r.i8(0);
+                        r.i32(if creating { 0 } else { 2 });
+                        for partition in 0..if creating { 0 } else { 2 } {
+                            r.i16(0);
+                            r.i32(partition);
+                            r.i32(7);
+                            r.i32(0);
+                            r.i32(0);
+                        }
+                        creating = false;
+                    } else {
+                        assert_eq!(api_key, PRODUCE);
+                        let mut d = Decoder::new(&body);
+                        assert_eq!(d.i16().unwrap(), -1);
+                        assert_eq!(d.i16().unwrap(), -1, "acks=all");
+                        d.i32().unwrap();
+                        assert_eq!(d.array().unwrap(), 1);
+                        assert_eq!(d.string().unwrap(), "scans");
+                        let partitions = d.array().unwrap();
+                        r.i32(1);
+                        r.string("scans");
+                        r.i32(partitions);
+                        for _ in 0..partitions {
+                            let partition = d.i32().unwrap();
+                            let len = d.i32().unwrap() as usize;
+                            let batch = d.take(len).unwrap();
+                            for record in records(batch) {
+                                produced.push((partition, record));
+                            }
+                            r.i32(partition);
+                            r.i16(0);
+                            r.i64(0);
+                            r.i64(-1);
+                        }
+                        r.i32(0);
+                    }
+                    respond(&mut stream, correlation, r).await;
+                }
+            }
+            produced
+        });
+
+        let mut transport =
+            KafkaTransport::new(vec![format!("127.0.0.1:{}", port)], "scans".into());
+        let batch = vec![
+            Message {
+                key: Some("foobar".into()),
+                payload: b"{\"port\":22}".to_vec(),
+            },
+            Message {
+                key: None,
+                payload: b"{\"port\":80}".to_vec(),
+            },
+        ];
+        transport.send(&batch).await.unwrap();
+        drop(transport);
+
+        let mut produced = broker.await.unwrap();
+        produced.sort();
+        let foobar = partition_for(b"foobar", 2) as i32;
+        assert!(produced.contains(&(foobar, (Some("foobar".into()), "{\"port\":22}".into()))));
+        assert!(produced
+            .iter()
+            .any(|(_, r)| r == &(None, "{\"port\":80}".to_string())));
+        assert_eq!(produced.len(), 2);
+    }
+}
@@ -0,0 +1,153 @@
+//! Spotting connect times that are outliers for their host, for
+//! `--retime-outliers`.
+//!
+//! A scheduling hiccup on the scanning machine can make one connect take
+//! far longer than the host ever needs. [`HostLatencies`] keeps the last
+//! [`WINDOW`] definitive connect times of each host (a port answering open
+//! or closed; a timeout says nothing about the host's speed) and calls a
+//! new one an outlier when it is more than [`FACTOR`] times the host's
+//! rolling p95 and at least [`MIN_OUTLIER`]. The scanner measures such a
+//! port again at the end of the scan. Outliers stay out of the window, so
+//! one doesn't raise the bar for the next.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Connect times kept per host.
+pub const WINDOW: usize = 100;
+
+/// Connect times a host needs before any is called an outlier.
+pub const MIN_SAMPLES: usize = 10;
+
+/// How many times the host's p95 an outlier takes.
+pub const FACTOR: u32 = 5;
+
+/// The shortest connect time called an outlier, below which a re-measure
+/// could not tell a hiccup from noise.
+pub const MIN_OUTLIER: Duration = Duration::from_millis(10);
+
+/// The rolling connect times of every host of a scan.
+#[derive(Debug, Default)]
+pub struct HostLatencies {
+    hosts: Mutex<HashMap<IpAddr, VecDeque<Duration>>>,
+}
+
+impl HostLatencies {
+    /// Creates an empty record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a definitive connect time of `host`, returning whether it
+    /// is an outlier; an outlier is not recorded.
+    pub fn observe(&self, host: IpAddr, took: Duration) -> bool {
+        let mut hosts = self.hosts.lock().expect("latency window");
+        let window = hosts.entry(host).or_default();
+        let outlier =
+            took >= MIN_OUTLIER && p95(window).is_some_and(|p95| took > p95.saturating_mul(FACTOR));
+        if !outlier {
+            if window.len() == WINDOW {
+                window.pop_front();
+            }
+            window.push_back(took);
+        }
+        outlier
+    }
+
+    /// The rolling p95 of `host`, once it has [`MIN_SAMPLES`] connect
+    /// times.
+    pub fn p95(&self, host: IpAddr) -> Option<Duration> {
+        p95(self.hosts.lock().expect("latency window").get(&host)?)
+    }
+}
+
+/// Both connect times of a port whose first was an outlier, in
+/// milliseconds, as the JSON report keeps them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Retime {
+    /// The outlier.
+    pub first_ms: f64,
+    /// The connect time measured at the end of the scan; none when that
+    /// connect got no open or closed answer.
+    pub second_ms: Option<f64>,
+}
+
+impl Retime {
+    /// An outlier not measured again yet.
+    pub fn first(took: Duration) -> Self {
+        Retime {
+            first_ms: millis(took),
+            second_ms: None,
+        }
+    }
+
+    /// Records the second measurement.
+    pub fn second(&mut self, took: Duration) {
+        self.second_ms = Some(millis(took));
+    }
+
+    /// The faster of the two measurements, the one statistics use.
+    pub fn best(&self) -> Duration {
+        let best = self
+            .second_ms
+            .map_or(self.first_ms, |s| s.min(self.first_ms));
+        Duration::from_secs_f64(best / 1000.0)
+    }
+}
+
+/// A duration in milliseconds, to the microsecond.
+fn millis(d: Duration) -> f64 {
+    d.as_micros() as f64 / 1000.0
+}
+
+fn p95(window: &VecDeque<Duration>) -> Option<Duration> {
+    if window.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut sorted: Vec<Duration> = window.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (sorted.len() * 95).div_ceil(100);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn outliers_need_a_history_and_a_large_factor() {
+        let latencies = HostLatencies::new();
+        // Too early to tell, so kept.
+        assert!(!latencies.observe(HOST, ms(500)));
+        for n in 1..=19 {
+            assert!(!latencies.observe(HOST, ms(n % 4 + 2)));
+        }
+        assert_eq!(latencies.p95(HOST), Some(ms(5)));
+        assert!(latencies.observe(HOST, ms(26)));
+        // The outlier is not kept, so the p95 doesn't move.
+        assert_eq!(latencies.p95(HOST), Some(ms(5)));
+        assert!(!latencies.observe(HOST, ms(25)));
+        // Another host has its own window.
+        let other = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+        assert!(!latencies.observe(other, ms(400)));
+    }
+
+    #[test]
+    fn fast_hosts_need_a_noticeable_outlier() {
+        let latencies = HostLatencies::new();
+        for _ in 0..WINDOW + 5 {
+            latencies.observe(HOST, Duration::from_micros(100));
+        }
+        assert!(!latencies.observe(HOST, ms(9)));
+        assert!(latencies.observe(HOST, ms(10)));
+    }
+}
@@ -0,0 +1,105 @@
+//! Fast asynchronous TCP port scanning.
+//!
+//! The [`Scanner`] runs connect scans described by a [`ScanConfig`] and hands
+//! back [`PortResult`]s; [`ScanSummary`] aggregates them. Report formats and
+//! policy checks used by the `port-scanner` binary live in the submodules.
+
+#![warn(missing_docs)]
+
+pub mod annotations;
+pub mod arp;
+pub mod audit;
+pub mod banner;
+pub mod baseline;
+pub mod batch;
+pub mod blocklist;
+pub mod capture;
+pub mod change;
+pub mod charset;
+pub mod clock;
+pub mod cluster;
+pub mod completion;
+pub mod connector;
+pub mod console;
+pub mod cron;
+pub mod daemon;
+pub mod discover;
+pub mod duration;
+pub mod email;
+pub mod established;
+pub mod evidence;
+pub mod exec;
+pub mod fields;
+pub mod filter;
+pub mod fingerprints;
+pub mod geoip;
+pub mod groups;
+pub mod hash;
+pub mod heatmap;
+pub mod help;
+pub mod history;
+pub mod http;
+pub mod identity;
+pub mod import;
+pub mod jump;
+pub mod junit;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod latency;
+pub mod limits;
+pub mod live;
+pub mod mdns;
+pub mod metadata;
+pub mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod ndp;
+pub mod osguess;
+pub mod oui;
+pub mod output;
+pub mod plan;
+pub mod policy;
+pub mod preset;
+pub mod probe;
+pub mod proxy;
+pub mod ptr;
+pub mod publish;
+pub mod pushgateway;
+pub mod reach;
+pub mod reanalysis;
+pub mod redact;
+pub mod rescan;
+pub mod resolve;
+pub mod responses;
+pub mod retry;
+pub mod s3;
+pub mod scanner;
+pub mod schema;
+pub mod scope;
+pub mod script;
+pub mod selftest;
+pub mod server;
+pub mod service;
+pub mod shared;
+pub mod sniff;
+pub mod spec;
+pub mod spill;
+pub mod ssdp;
+pub mod stats;
+pub mod targets;
+pub mod template;
+pub mod timeouts;
+pub mod tls;
+pub mod tlsreport;
+pub mod traceroute;
+pub mod verdict;
+pub mod vuln;
+pub mod window;
+
+pub use banner::Banner;
+pub use scanner::{
+    new_scan_id, parse_ports, HostInfo, IntoPorts, PortError, PortResult, PortStatus, ScanConfig,
+    ScanError, ScanStream, ScanSummary, Scanner, ScannerBuilder, TargetReport, TargetStatus,
+    Throttle,
+};
+pub use service::{detect_service, ServiceInfo};
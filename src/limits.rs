@@ -0,0 +1,194 @@
+//! Limits of the host that cap how many connections a scan can hold open at
+//! once. Above them, connects fail locally with `EMFILE` or
+//! `EADDRNOTAVAIL` and a scan fills up with ports that were never probed.
+
+use tracing::{debug, info, warn};
+
+/// File descriptors left for everything but probes: stdio, report files,
+/// the metrics listener and the runtime's own.
+pub const RESERVED_FDS: u64 = 64;
+
+/// What the host allows, as far as it could be read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemLimits {
+    /// Soft limit on open file descriptors (`RLIMIT_NOFILE`), unless
+    /// unlimited or unknown.
+    pub open_files: Option<u64>,
+    /// Size of the local port range for outgoing connections, known on Linux
+    /// only.
+    pub ephemeral_ports: Option<u64>,
+}
+
+/// The highest concurrency the host can sustain and how it was worked out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafeConcurrency {
+    /// Highest safe number of connection attempts in flight.
+    pub max: usize,
+    /// The arithmetic behind `max`, for the log.
+    pub explanation: String,
+}
+
+impl SystemLimits {
+    /// Reads the limits of the current process and host.
+    pub fn detect() -> Self {
+        SystemLimits {
+            open_files: open_files(),
+            ephemeral_ports: ephemeral_ports(),
+        }
+    }
+
+    /// The lower of the limits: every probe holds a file descriptor, less
+    /// [`RESERVED_FDS`], and a local port, of which half are left for
+    /// sockets in `TIME_WAIT` and other programs. `None` when neither limit
+    /// is known.
+    pub fn safe_concurrency(&self) -> Option<SafeConcurrency> {
+        let by_fds = self.open_files.map(|n| {
+            let max = n.saturating_sub(RESERVED_FDS).max(1);
+            let why = format!("{} open files - {} reserved = {}", n, RESERVED_FDS, max);
+            (max, why)
+        });
+        let by_ports = self.ephemeral_ports.map(|n| {
+            let max = (n / 2).max(1);
+            (max, format!("{} ephemeral ports / 2 = {}", n, max))
+        });
+        let limits: Vec<(u64, String)> = by_fds.into_iter().chain(by_ports).collect();
+        let max = limits.iter().map(|(max, _)| *max).min()?;
+        let explanation = limits
+            .into_iter()
+            .map(|(_, why)| why)
+            .collect::<Vec<_>>()
+            .join("; ");
+        Some(SafeConcurrency {
+            max: usize::try_from(max).unwrap_or(usize::MAX),
+            explanation,
+        })
+    }
+
+    /// The concurrency to scan with when `requested`: lowered to the safe
+    /// maximum with `auto_tune`, else kept with a warning when above it.
+    pub fn tune(&self, requested: usize, auto_tune: bool) -> usize {
+        let Some(safe) = self.safe_concurrency() else {
+            debug!("System connection limits unknown, not checking --concurrency");
+            return requested;
+        };
+        if requested <= safe.max {
+            debug!(
+                "Concurrency {} is within the safe maximum of {} ({})",
+                requested, safe.max, safe.explanation
+            );
+            return requested;
+        }
+        if auto_tune {
+            info!(
+                "Lowering concurrency from {} to {} ({})",
+                requested, safe.max, safe.explanation
+            );
+            safe.max
+        } else {
+            warn!(
+                "Concurrency {} is above the safe maximum of {} ({}); ports may be \
+                 left unscanned, use --auto-tune to lower it",
+                requested, safe.max, safe.explanation
+            );
+            requested
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_files() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct it is given.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    // rlim_t is narrower than u64 on some targets.
+    #[allow(clippy::unnecessary_cast)]
+    let current = limit.rlim_cur as u64;
+    (limit.rlim_cur != libc::RLIM_INFINITY).then_some(current)
+}
+
+#[cfg(not(unix))]
+fn open_files() -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn ephemeral_ports() -> Option<u64> {
+    let range = std::fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range").ok()?;
+    port_range_size(&range)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn ephemeral_ports() -> Option<u64> {
+    None
+}
+
+/// Number of ports in an `ip_local_port_range` such as `32768\t60999`.
+fn port_range_size(range: &str) -> Option<u64> {
+    let mut ends = range.split_whitespace().map(str::parse::<u64>);
+    let (low, high) = (ends.next()?.ok()?, ends.next()?.ok()?);
+    (low <= high).then(|| high - low + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_lowest_limit_wins() {
+        let limits = SystemLimits {
+            open_files: Some(1024),
+            ephemeral_ports: Some(28232),
+        };
+        let safe = limits.safe_concurrency().unwrap();
+        assert_eq!(safe.max, 960);
+        assert_eq!(
+            safe.explanation,
+            "1024 open files - 64 reserved = 960; 28232 ephemeral ports / 2 = 14116"
+        );
+
+        let limits = SystemLimits {
+            open_files: Some(1_048_576),
+            ephemeral_ports: Some(1000),
+        };
+        assert_eq!(limits.safe_concurrency().unwrap().max, 500);
+
+        let fds_only = SystemLimits {
+            open_files: Some(256),
+            ephemeral_ports: None,
+        };
+        assert_eq!(fds_only.safe_concurrency().unwrap().max, 192);
+
+        let tiny = SystemLimits {
+            open_files: Some(10),
+            ephemeral_ports: Some(1),
+        };
+        assert_eq!(tiny.safe_concurrency().unwrap().max, 1);
+
+        assert_eq!(SystemLimits::default().safe_concurrency(), None);
+    }
+
+    #[test]
+    fn only_auto_tune_lowers_the_concurrency() {
+        let limits = SystemLimits {
+            open_files: Some(1024),
+            ephemeral_ports: None,
+        };
+        assert_eq!(limits.tune(500, false), 500);
+        assert_eq!(limits.tune(5000, false), 5000);
+        assert_eq!(limits.tune(5000, true), 960);
+        assert_eq!(SystemLimits::default().tune(5000, true), 5000);
+    }
+
+    #[test]
+    fn port_ranges_are_read_like_procfs_writes_them() {
+        assert_eq!(port_range_size("32768\t60999\n"), Some(28232));
+        assert_eq!(port_range_size("1024 1024"), Some(1));
+        assert_eq!(port_range_size("61000 32768"), None);
+        assert_eq!(port_range_size("garbage"), None);
+    }
+}
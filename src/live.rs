@@ -0,0 +1,524 @@
+//! Streaming events to a consumer on the same box with `--stream-to`:
+//! NDJSON over a UNIX domain socket or TCP, one `result` event per result as
+//! it completes, a `progress` event now and then and a last `summary`
+//! event with the totals.
+//!
+//! The scanner connects to the consumer, or with `--stream-listen` listens
+//! and takes one consumer at a time. Like the [`Publisher`](crate::publish::Publisher),
+//! the [`StreamWriter`] is an [`OutputWriter`] that never waits on the
+//! consumer: events queue for a writer thread, which holds them while no
+//! consumer is connected (reconnecting, or waiting for the next one) and
+//! resends the one a disconnect interrupted. Once `--stream-buffer` events
+//! are waiting, further ones are dropped and counted; the `progress` and
+//! `summary` events of the end of the scan are always queued.
+
+use serde_json::json;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::output::OutputWriter;
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+
+/// Default for `--stream-buffer`: events held for the consumer at most.
+pub const DEFAULT_BUFFER: usize = 10_000;
+
+/// How often a `progress` event is sent while results come in.
+const PROGRESS_EVERY: Duration = Duration::from_secs(1);
+
+/// How often the writer tries again to reach a consumer.
+const RETRY_EVERY: Duration = Duration::from_millis(100);
+
+/// How long the writer waits for a consumer to come back once the scan is
+/// over, before the events still held are lost.
+const LINGER: Duration = Duration::from_secs(2);
+
+/// Longest a write may block on a consumer that stopped reading.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where `--stream-to` events go: `unix:///path/to.sock` or
+/// `tcp://host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamUrl {
+    /// A UNIX domain socket.
+    Unix(PathBuf),
+    /// A TCP address, such as `127.0.0.1:9000`.
+    Tcp(String),
+}
+
+impl FromStr for StreamUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err(format!("'{}' names no socket", s));
+            }
+            return Ok(StreamUrl::Unix(PathBuf::from(path)));
+        }
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            let port = addr
+                .rsplit_once(':')
+                .and_then(|(host, port)| port.parse::<u16>().ok().filter(|_| !host.is_empty()));
+            return match port {
+                Some(_) => Ok(StreamUrl::Tcp(addr.to_string())),
+                None => Err(format!("'{}' needs a host and a port", s)),
+            };
+        }
+        Err(format!(
+            "'{}' is not a unix:///path or tcp://host:port URL",
+            s
+        ))
+    }
+}
+
+impl fmt::Display for StreamUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamUrl::Unix(path) => write!(f, "unix://{}", path.display()),
+            StreamUrl::Tcp(addr) => write!(f, "tcp://{}", addr),
+        }
+    }
+}
+
+/// A connection to the consumer.
+type Consumer = Box<dyn Write + Send>;
+
+/// How the writer thread finds its consumer.
+enum Endpoint {
+    Connect(StreamUrl),
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener, PathBuf),
+}
+
+impl Endpoint {
+    fn listen(url: &StreamUrl) -> io::Result<Self> {
+        match url {
+            StreamUrl::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(Endpoint::Tcp(listener))
+            }
+            #[cfg(unix)]
+            StreamUrl::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                // A socket left behind by an earlier run would fail the
+                // bind; any other file is left alone.
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = std::os::unix::net::UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                Ok(Endpoint::Unix(listener, path.clone()))
+            }
+            #[cfg(not(unix))]
+            StreamUrl::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    /// A consumer, if one can be had right now.
+    fn consumer(&self) -> io::Result<Option<Consumer>> {
+        let consumer: Consumer = match self {
+            Endpoint::Connect(url) => connect(url)?,
+            Endpoint::Tcp(listener) => match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    Box::new(stream)
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            },
+            #[cfg(unix)]
+            Endpoint::Unix(listener, _) => match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    Box::new(stream)
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            },
+        };
+        Ok(Some(consumer))
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Endpoint::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn connect(url: &StreamUrl) -> io::Result<Consumer> {
+    match url {
+        StreamUrl::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(unix)]
+        StreamUrl::Unix(path) => {
+            let stream = std::os::unix::net::UnixStream::connect(path)?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(not(unix))]
+        StreamUrl::Unix(_) => Err(unix_unsupported()),
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "UNIX domain sockets are only supported on Unix; use tcp://",
+    )
+}
+
+/// How the events of a scan fared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delivery {
+    /// Written to a consumer.
+    pub written: u64,
+    /// Held for a consumer that never came back.
+    pub lost: u64,
+    /// Never queued because the buffer was full.
+    pub dropped: u64,
+}
+
+/// Streams events to a consumer; see the module documentation.
+pub struct StreamWriter {
+    url: StreamUrl,
+    scan_id: String,
+    buffer: usize,
+    queue: Option<Sender<Vec<u8>>>,
+    queued: Arc<AtomicUsize>,
+    finished: Arc<AtomicBool>,
+    worker: Option<JoinHandle<Delivery>>,
+    dropped: u64,
+    started: Instant,
+    last_progress: Instant,
+    counts: [u64; 3],
+}
+
+impl StreamWriter {
+    /// Connects to the consumer at `url`, or with `listen` binds it and
+    /// lets consumers connect, and holds up to `buffer` events for them.
+    pub fn start(url: &StreamUrl, listen: bool, buffer: usize, scan_id: &str) -> io::Result<Self> {
+        let endpoint = if listen {
+            Endpoint::listen(url)?
+        } else {
+            // The first connect is made here, so that a consumer that
+            // isn't there stops the scan before it starts.
+            let first = connect(url)?;
+            drop(first);
+            Endpoint::Connect(url.clone())
+        };
+        let (queue, events) = mpsc::channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let finished = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let (queued, finished) = (queued.clone(), finished.clone());
+            std::thread::Builder::new()
+                .name("stream".to_string())
+                .spawn(move || deliver(endpoint, events, queued, finished))?
+        };
+        let now = Instant::now();
+        Ok(StreamWriter {
+            url: url.clone(),
+            scan_id: scan_id.to_string(),
+            buffer: buffer.max(1),
+            queue: Some(queue),
+            queued,
+            finished,
+            worker: Some(worker),
+            dropped: 0,
+            started: now,
+            last_progress: now,
+            counts: [0; 3],
+        })
+    }
+
+    /// Queues `event`, unless the buffer is full and it may be dropped.
+    fn queue(&mut self, event: serde_json::Value, droppable: bool) -> io::Result<()> {
+        let Some(queue) = &self.queue else {
+            return Ok(());
+        };
+        if droppable && self.queued.load(Ordering::Relaxed) >= self.buffer {
+            if self.dropped == 0 {
+                warn!("Stream to {} is not keeping up, dropping events", self.url);
+            }
+            self.dropped += 1;
+            return Ok(());
+        }
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if queue.send(line).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn progress(&self) -> serde_json::Value {
+        let [open, closed, filtered] = self.counts;
+        json!({
+            "event": "progress",
+            "scan_id": self.scan_id,
+            "elapsed_ms": self.started.elapsed().as_millis() as u64,
+            "completed": open + closed + filtered,
+            "open": open,
+            "closed": closed,
+            "filtered": filtered,
+        })
+    }
+}
+
+impl OutputWriter for StreamWriter {
+    fn write_result(&mut self, r: &PortResult) -> io::Result<()> {
+        self.counts[match r.status {
+            PortStatus::Open => 0,
+            PortStatus::Closed => 1,
+            PortStatus::Filtered => 2,
+        }] += 1;
+        let event = json!({
+            "event": "result",
+            "scan_id": self.scan_id,
+            "result": r,
+        });
+        self.queue(event, true)?;
+        if self.last_progress.elapsed() >= PROGRESS_EVERY {
+            self.last_progress = Instant::now();
+            self.queue(self.progress(), true)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()> {
+        self.queue(self.progress(), false)?;
+        // The results went out one by one; the summary event carries the
+        // totals only.
+        let mut totals = serde_json::to_value(summary)?;
+        if let Some(fields) = totals.as_object_mut() {
+            fields.remove("results");
+        }
+        self.queue(
+            json!({
+                "event": "summary",
+                "scan_id": self.scan_id,
+                "summary": totals,
+                "dropped": self.dropped,
+            }),
+            false,
+        )?;
+
+        self.finished.store(true, Ordering::Relaxed);
+        self.queue = None;
+        let worker = self.worker.take().expect("a stream finishes once");
+        let mut delivery = worker
+            .join()
+            .map_err(|_| io::Error::other("stream worker panicked"))?;
+        delivery.dropped = self.dropped;
+        if delivery.lost + delivery.dropped > 0 {
+            warn!(
+                "{} events never reached the consumer of {} ({} dropped on a full buffer, {} held when it went away)",
+                delivery.lost + delivery.dropped,
+                self.url,
+                delivery.dropped,
+                delivery.lost
+            );
+        }
+        info!("Streamed {} events to {}", delivery.written, self.url);
+        Ok(())
+    }
+}
+
+impl Drop for StreamWriter {
+    fn drop(&mut self) {
+        // A writer dropped without finishing lets its worker give up too.
+        self.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The worker: writes events as they come while a consumer is connected,
+/// and holds them while none is.
+fn deliver(
+    endpoint: Endpoint,
+    events: Receiver<Vec<u8>>,
+    queued: Arc<AtomicUsize>,
+    finished: Arc<AtomicBool>,
+) -> Delivery {
+    let mut delivery = Delivery::default();
+    let mut consumer: Option<Consumer> = None;
+    // An event a disconnect interrupted, for the next consumer.
+    let mut pending: Option<Vec<u8>> = None;
+    let mut given_up_at: Option<Instant> = None;
+    loop {
+        let Some(out) = &mut consumer else {
+            match endpoint.consumer() {
+                Ok(Some(c)) => {
+                    debug!("stream consumer connected");
+                    consumer = Some(c);
+                }
+                Ok(None) => {}
+                Err(e) => debug!(error = %e, "no stream consumer"),
+            }
+            if consumer.is_none() {
+                if finished.load(Ordering::Relaxed) {
+                    let since = *given_up_at.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= LINGER {
+                        break;
+                    }
+                }
+                std::thread::sleep(RETRY_EVERY);
+            }
+            continue;
+        };
+        let event = match pending.take() {
+            Some(event) => event,
+            None => match events.recv() {
+                Ok(event) => {
+                    queued.fetch_sub(1, Ordering::Relaxed);
+                    event
+                }
+                Err(_) => break,
+            },
+        };
+        match out.write_all(&event).and_then(|()| out.flush()) {
+            Ok(()) => delivery.written += 1,
+            Err(e) => {
+                warn!("Stream consumer went away ({}), holding events", e);
+                consumer = None;
+                pending = Some(event);
+            }
+        }
+    }
+    delivery.lost = u64::from(pending.is_some()) + events.try_iter().count() as u64;
+    delivery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn summary(results: Vec<PortResult>) -> ScanSummary {
+        let metrics = crate::metrics::ScanMetrics::new("127.0.0.1");
+        ScanSummary::from_results("id".into(), "127.0.0.1".into(), results, 5, &metrics, false)
+    }
+
+    fn results() -> Vec<PortResult> {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        (1..=5)
+            .map(|port| {
+                let status = if port == 3 {
+                    PortStatus::Open
+                } else {
+                    PortStatus::Closed
+                };
+                PortResult::new(ip, port, status)
+            })
+            .collect()
+    }
+
+    fn events(reader: impl io::Read) -> Vec<serde_json::Value> {
+        BufReader::new(reader)
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn a_consumer_gets_results_in_order_then_the_summary() {
+        let consumer = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = StreamUrl::Tcp(consumer.local_addr().unwrap().to_string());
+        let reader = std::thread::spawn(move || {
+            // The connect that checks the consumer is there comes first.
+            let _ = consumer.accept().unwrap();
+            events(consumer.accept().unwrap().0)
+        });
+
+        let mut writer = StreamWriter::start(&url, false, 100, "id").unwrap();
+        let results = results();
+        for r in &results {
+            writer.write_result(r).unwrap();
+        }
+        writer.finish(&summary(results)).unwrap();
+
+        let events = reader.join().unwrap();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            ["result", "result", "result", "result", "result", "progress", "summary"]
+        );
+        let ports: Vec<u64> = events[..5]
+            .iter()
+            .map(|e| e["result"]["port"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ports, [1, 2, 3, 4, 5]);
+        assert_eq!(events[5]["completed"], 5);
+        assert_eq!(events[5]["open"], 1);
+        let last = &events[6];
+        assert_eq!(last["summary"]["open_ports"], 1);
+        assert_eq!(last["summary"]["scanned_ports"], 5);
+        assert!(last["summary"].get("results").is_none());
+        assert_eq!(last["dropped"], 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn events_past_the_buffer_are_dropped_until_a_consumer_listens() {
+        let path = std::env::temp_dir().join(format!("live-{}.sock", std::process::id()));
+        let url = StreamUrl::Unix(path.clone());
+        let mut writer = StreamWriter::start(&url, true, 2, "id").unwrap();
+        let results = results();
+        for r in &results {
+            writer.write_result(r).unwrap();
+        }
+
+        let consumer = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        writer.finish(&summary(results)).unwrap();
+        let events = events(consumer);
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["result", "result", "progress", "summary"]);
+        assert_eq!(events[1]["result"]["port"], 2);
+        assert_eq!(events[3]["dropped"], 3);
+        drop(writer);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn urls_name_a_socket_or_an_address() {
+        assert_eq!(
+            "unix:///tmp/scan.sock".parse(),
+            Ok(StreamUrl::Unix(PathBuf::from("/tmp/scan.sock")))
+        );
+        assert_eq!(
+            "tcp://127.0.0.1:9000".parse(),
+            Ok(StreamUrl::Tcp("127.0.0.1:9000".to_string()))
+        );
+        for bad in ["unix://", "tcp://127.0.0.1", "tcp://:9000", "http://x:1"] {
+            assert!(bad.parse::<StreamUrl>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -1,359 +1,560 @@
-use clap::{Parser, ValueEnum};
-use colored::*;
-use futures::stream::{self, StreamExt};
-use indicatif::{ProgressBar, ProgressStyle};
-use log::{error, info, LevelFilter};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fmt;
-use std::net::{IpAddr, SocketAddr};
-use std::path::PathBuf;
-use std::str::FromStr;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use thiserror::Error;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
-use tokio::sync::Semaphore;
-use tokio::time::timeout;
-
-#[derive(Parser, Debug)]
-#[command(name = "port-scanner", version = "0.1.0", about = "Fast async TCP port scanner")]
-struct Args {
-#[arg(short, long)]
-target: String,
-
-#[arg(short, long, default_value = "1-1024")]  
-ports: String,  
-
-#[arg(short = 'c', long, default_value_t = 512)]  
-concurrency: usize,  
-
-#[arg(short = 't', long, default_value_t = 800)]  
-timeout_ms: u64,  
-
-#[arg(short, long, value_enum, default_value_t = Verbosity::Normal)]  
-verbose: Verbosity,  
-
-#[arg(long)]  
-json: Option<PathBuf>,  
-
-#[arg(long)]  
-output: Option<PathBuf>,  
-
-#[arg(short = 'q', long)]  
-quiet: bool,
+use clap::{CommandFactory, Parser};
+use indicatif::MultiProgress;
+use port_scanner::audit::{self, RotatingFile};
+use port_scanner::batch::{self, Rollup};
+use port_scanner::daemon;
+use port_scanner::discover;
+use port_scanner::fingerprints::Fingerprints;
+use port_scanner::groups::PortGroups;
+use port_scanner::help;
+use port_scanner::history;
+use port_scanner::identity::{self, IdentityMode};
+use port_scanner::import;
+use port_scanner::plan::TargetSet;
+use port_scanner::ptr;
+use port_scanner::reanalysis;
+use port_scanner::resolve::{self, Resolver};
+use port_scanner::schema;
+use port_scanner::selftest;
+use port_scanner::server::{self, ServerConfig};
+use port_scanner::tlsreport::TlsReport;
+use port_scanner::verdict::Verdict;
+use port_scanner::vuln::VulnDb;
+use port_scanner::{metrics, ScanError, ScanSummary};
+use std::io::{IsTerminal, Write};
+use std::process::ExitCode;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{Layer, Registry};
+
+mod cli;
+mod printer;
+mod progress;
+mod scan;
+mod tui;
+
+use cli::{
+    BatchArgs, Cli, Command, DaemonArgs, DiscoverArgs, HistoryCommand, ListFormat, LogFormat,
+    PtrArgs, ServeArgs, StabilityArgs, TlsReportArgs, TlsReportFormat, Verbosity,
+};
+use progress::LogWriter;
+use tui::LogPane;
+
+const EXIT_OPEN_FOUND: u8 = 0;
+const EXIT_NONE_OPEN: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_INTERRUPTED: u8 = 3;
+const EXIT_POLICY_FAILED: u8 = 4;
+const EXIT_EXEC_FAILED: u8 = 5;
+const EXIT_TARGETS_FAILED: u8 = 6;
+const EXIT_PROBE_PANICKED: u8 = 7;
+const EXIT_SCAN_FAILED: u8 = 8;
 
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse_with_preset();
+    let args = match cli.command {
+        Some(Command::Scan(args)) => *args,
+        Some(Command::Examples) => return write_stdout(help::EXAMPLES.as_bytes()),
+        Some(Command::History {
+            command: HistoryCommand::Stability(args),
+        }) => {
+            return match stability(&args) {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::Ptr(args)) => {
+            return match ptr_sweep(&args).await {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::TlsReport(args)) => {
+            return match tls_report(&args) {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::Completions { shell }) => {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut Cli::command(), "port-scanner", &mut script);
+            return write_stdout(&script);
+        }
+        Some(Command::Manpage) => {
+            let mut page = Vec::new();
+            if let Err(e) = clap_mangen::Man::new(Cli::command()).render(&mut page) {
+                eprintln!("{}", e);
+                return ExitCode::from(EXIT_USAGE);
+            }
+            return write_stdout(&page);
+        }
+        Some(Command::Schema) => {
+            let mut schema = serde_json::to_vec_pretty(&schema::scan_summary())
+                .expect("the schema is plain JSON");
+            schema.push(b'\n');
+            return write_stdout(&schema);
+        }
+        Some(Command::SelfTest { timeout }) => {
+            let report = match selftest::run(timeout).await {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::from(EXIT_USAGE);
+                }
+            };
+            let code = write_stdout(report.to_string().as_bytes());
+            return if report.passed() {
+                code
+            } else {
+                ExitCode::from(EXIT_POLICY_FAILED)
+            };
+        }
+        Some(Command::Reanalyze {
+            input,
+            fingerprints,
+            vuln_db,
+            output,
+        }) => {
+            return match reanalyze(&input, fingerprints, vuln_db, output) {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::Serve(args)) => {
+            init_logging(
+                &args.verbose,
+                args.log_format,
+                LogWriter::new(MultiProgress::new()),
+                false,
+                None,
+            );
+            return match serve(args).await {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::Daemon(args)) => {
+            init_logging(
+                &args.verbose,
+                args.log_format,
+                LogWriter::new(MultiProgress::new()),
+                false,
+                None,
+            );
+            return match daemon(args).await {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::Batch(args)) => {
+            init_logging(
+                &args.verbose,
+                args.log_format,
+                LogWriter::new(MultiProgress::new()),
+                false,
+                None,
+            );
+            return match run_batch(&args).await {
+                Ok(rollup) => {
+                    if let Some(path) = &args.json {
+                        if let Err(e) = rollup.write(path) {
+                            error!("Cannot write {}: {}", path.display(), e);
+                            return ExitCode::from(EXIT_USAGE);
+                        }
+                    }
+                    let code = write_stdout(format!("{}\n", rollup).as_bytes());
+                    if rollup.passed() {
+                        code
+                    } else {
+                        ExitCode::from(EXIT_POLICY_FAILED)
+                    }
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        Some(Command::Discover(args)) => {
+            init_logging(
+                &args.verbose,
+                args.log_format,
+                LogWriter::new(MultiProgress::new()),
+                false,
+                None,
+            );
+            return match list_devices(args).await {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("{}", e);
+                    ExitCode::from(EXIT_USAGE)
+                }
+            };
+        }
+        None => cli.scan,
+    };
+
+    let log_file = match &args.log_file {
+        Some(path) => match RotatingFile::open(path, args.log_max_size) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Cannot open the log file {}: {}", path.display(), e);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        },
+        None => None,
+    };
+    let multi = MultiProgress::new();
+    let pane = LogPane::default();
+    if args.tui {
+        // The log pane shows plain text.
+        colored::control::set_override(false);
+        init_logging(&args.verbose, args.log_format, pane.clone(), true, log_file);
+    } else {
+        init_logging(
+            &args.verbose,
+            args.log_format,
+            LogWriter::new(multi.clone()),
+            false,
+            log_file,
+        );
+    }
+
+    let given = help::Given {
+        targets: args.target.clone(),
+        ports: args.ports.clone(),
+        exclude_ports: args.exclude_ports.clone(),
+        groups: PortGroups::with(&args.port_group).unwrap_or_default(),
+        resolvers: !args.resolver.is_empty() || !args.doh.is_empty(),
+    };
+    match scan::run(args, multi, pane).await {
+        Ok(verdict) => ExitCode::from(verdict.map_or(EXIT_OPEN_FOUND, exit_code)),
+        Err(e) => {
+            error!("{}", e);
+            if let Some(hint) = help::hint(e.as_ref(), &given) {
+                eprintln!("{}", hint);
+            }
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
 }
 
-#[derive(ValueEnum, Clone, Debug, PartialEq)]
-enum Verbosity {
-Quiet,
-Normal,
-Verbose,
-Debug,
+/// Sets up logging to `writer`, and to `log_file` if given; `plain`
+/// leaves out colors.
+///
+/// The log file gets every event at info level or above whatever the
+/// console shows, audit events included; the console shows audit events
+/// only from `-v verbose` on.
+fn init_logging<W>(
+    verbosity: &Verbosity,
+    format: LogFormat,
+    writer: W,
+    plain: bool,
+    log_file: Option<RotatingFile>,
+) where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let log_level = match verbosity {
+        Verbosity::Quiet => LevelFilter::ERROR,
+        Verbosity::Normal => LevelFilter::INFO,
+        Verbosity::Verbose => LevelFilter::DEBUG,
+        Verbosity::Debug => LevelFilter::TRACE,
+    };
+    let audit_level = if log_level >= LevelFilter::DEBUG {
+        log_level
+    } else {
+        LevelFilter::OFF
+    };
+    let mut layers = vec![layer(
+        format,
+        writer,
+        !plain && std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    )
+    .with_filter(
+        Targets::new()
+            .with_default(log_level)
+            .with_target(audit::TARGET, audit_level),
+    )
+    .boxed()];
+    if let Some(file) = log_file {
+        layers.push(
+            layer(format, file, false)
+                .with_filter(LevelFilter::INFO.max(log_level))
+                .boxed(),
+        );
+    }
+    tracing_subscriber::registry().with(layers).init();
 }
 
-#[derive(Error, Debug)]
-#[error("Scan error")]
-enum ScanError {
-#[error("Invalid target address")]
-InvalidTarget,
-
-#[error("Network error: {0}")]  
-Io(#[from] std::io::Error),
-
+/// A formatting layer in `format`, writing to `writer`.
+fn layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(ansi)
+        .with_writer(writer);
+    match format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Json => layer.json().with_span_list(true).boxed(),
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct PortResult {
-port: u16,
-status: PortStatus,
-banner: Option<String>,
-service: Option<String>,
-duration_ms: u128,
+/// Runs the API server until Ctrl-C.
+async fn serve(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if args.max_jobs == 0 {
+        return Err("--max-jobs must be at least 1".into());
+    }
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+    if args.token.is_none() && !listener.local_addr()?.ip().is_loopback() {
+        warn!("Serving without --token on a non-loopback address");
+    }
+    let config = ServerConfig {
+        max_jobs: args.max_jobs,
+        retain: args.retain,
+        token: args.token,
+        ..ServerConfig::default()
+    };
+    let shutdown = tokio_util::sync::CancellationToken::new();
+    let stop = shutdown.clone();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Shutting down");
+        stop.cancel();
+    });
+    server::serve(listener, config, shutdown).await?;
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
-enum PortStatus {
-Open,
-Closed,
-Filtered,
+/// Runs the config's scan on the schedule until SIGTERM or Ctrl-C, each
+/// scan a child process of this executable.
+async fn daemon(args: DaemonArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let options = daemon::Options {
+        config: args.config,
+        schedule: args.schedule,
+        timezone: args.timezone,
+        overlap: args.overlap,
+        status_file: args.status_file,
+        max_runs: args.max_runs,
+        require_blocklist: args.require_blocklist,
+    };
+    daemon::run(options, &std::env::current_exe()?, &check_scan_args).await?;
+    Ok(())
 }
 
-impl fmt::Display for PortStatus {
-fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-match self {
-PortStatus::Open => write!(f, "{}", "open".bright_green()),
-PortStatus::Closed => write!(f, "{}", "closed".bright_red()),
-PortStatus::Filtered => write!(f, "{}", "filtered".yellow()),
-}
-}
+/// Runs the jobs of a `batch`, each as a scan of its own.
+async fn run_batch(args: &BatchArgs) -> Result<Rollup, Box<dyn std::error::Error>> {
+    let options = batch::Options {
+        parallel_jobs: args.parallel_jobs,
+        fail_fast: args.fail_fast,
+        report_dir: args.report_dir.clone(),
+    };
+    let exe = std::env::current_exe()?;
+    Ok(batch::run(&args.jobs, &options, &exe, &check_scan_args).await?)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ScanSummary {
-target: String,
-scanned_ports: usize,
-open_ports: usize,
-closed_ports: usize,
-filtered_ports: usize,
-total_time_ms: u128,
-results: Vec<PortResult>,
+/// Checks that `scan` takes `args`, giving the first line of the parser's
+/// complaint if not.
+fn check_scan_args(args: Vec<String>) -> Result<(), String> {
+    let argv = ["port-scanner".to_string(), "scan".to_string()]
+        .into_iter()
+        .chain(args);
+    match Cli::try_parse_from(argv) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let e = e.to_string();
+            let first = e.lines().next().unwrap_or_default();
+            Err(first.strip_prefix("error: ").unwrap_or(first).to_string())
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-let args = Args::parse();
-
-let log_level = match args.verbose {  
-    Verbosity::Quiet => LevelFilter::Error,  
-    Verbosity::Normal => LevelFilter::Info,  
-    Verbosity::Verbose => LevelFilter::Debug,  
-    Verbosity::Debug => LevelFilter::Trace,  
-};  
-env_logger::builder().filter_level(log_level).init();  
-
-info!("Starting scan on {}", args.target.bold());  
-
-let ip_addr: IpAddr = IpAddr::from_str(&args.target)  
-    .map_err(|_| ScanError::InvalidTarget)?;  
-
-let ports = parse_ports(&args.ports)?;  
-info!("Scanning {} ports", ports.len());  
-
-let start_time = Instant::now();  
-let semaphore = Arc::new(Semaphore::new(args.concurrency));  
-
-let pb = if !args.quiet {  
-    let style = ProgressStyle::default_bar()  
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")  
-        .unwrap()  
-        .progress_chars("#>-");  
-    Some(ProgressBar::new(ports.len() as u64).with_style(style))  
-} else {  
-    None  
-};  
-
-let mut tasks = vec![];  
-
-for port in ports {  
-    let permit = semaphore.clone().acquire_owned().await?;  
-    let target_ip = ip_addr;  
-    let conn_timeout = Duration::from_millis(args.timeout_ms);  
-
-    let task = tokio::spawn(async move {  
-        let _permit = permit;  
-        let addr = SocketAddr::new(target_ip, port);  
-
-        let connect_res = timeout(conn_timeout, TcpStream::connect(addr)).await;  
-
-        let duration = Instant::now() - start_time;  
-
-        match connect_res {  
-            Ok(Ok(mut stream)) => {  
-                let banner = grab_banner(&mut stream, Duration::from_millis(1200)).await.ok();  
-                let service = detect_service(port, banner.as_deref());  
-                PortResult {  
-                    port,  
-                    status: PortStatus::Open,  
-                    banner,  
-                    service,  
-                    duration_ms: duration.as_millis(),  
-                }  
-            }  
-            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => PortResult {  
-                port,  
-                status: PortStatus::Closed,  
-                banner: None,  
-                service: None,  
-                duration_ms: duration.as_millis(),  
-            },  
-            _ => PortResult {  
-                port,  
-                status: PortStatus::Filtered,  
-                banner: None,  
-                service: None,  
-                duration_ms: duration.as_millis(),  
-            },  
-        }  
-    });  
-    tasks.push(task);  
-}  
-
-let mut results = vec![];  
-
-let mut stream = stream::iter(tasks).buffer_unordered(args.concurrency * 2);  
-
-while let Some(res) = stream.next().await {  
-    match res {  
-        Ok(r) => {  
-            if !args.quiet || r.status == PortStatus::Open {  
-                print_result(&r, args.verbose == Verbosity::Verbose);  
-            }  
-            results.push(r);  
-        }  
-        Err(e) => error!("Task failed: {}", e),  
-    }  
-    if let Some(pb) = &pb {  
-        pb.inc(1);  
-    }  
-}  
-
-if let Some(pb) = &pb {  
-    pb.finish_with_message("Scan completed");  
-}  
-
-let total_time = start_time.elapsed().as_millis();  
-let open_count = results.iter().filter(|r| r.status == PortStatus::Open).count();  
-let closed_count = results.iter().filter(|r| r.status == PortStatus::Closed).count();  
-let filtered_count = results.iter().filter(|r| r.status == PortStatus::Filtered).count();  
-
-let summary = ScanSummary {  
-    target: args.target.clone(),  
-    scanned_ports: results.len(),  
-    open_ports: open_count,  
-    closed_ports: closed_count,  
-    filtered_ports: filtered_count,  
-    total_time_ms: total_time,  
-    results,  
-};  
-
-info!(  
-    "Done. Open: {}, Closed: {}, Filtered: {}, Time: {} ms",  
-    open_count.to_string().bright_green(),  
-    closed_count,  
-    filtered_count.to_string().yellow(),  
-    total_time  
-);  
-
-if let Some(ref path) = args.json {  
-    let json = serde_json::to_string_pretty(&summary)?;  
-    std::fs::write(path, json)?;  
-    info!("Saved JSON: {}", path.display());  
-}  
-
-if let Some(ref path) = args.output {  
-    let mut txt = format!(  
-        "Scan of {} | Ports: {} | Time: {}ms\n\n",  
-        args.target, summary.scanned_ports, total_time  
-    );  
-    for r in &summary.results {  
-        txt.push_str(&format!(  
-            "Port {:>5} | {} | Service: {:<12} | Banner: {}\n",  
-            r.port,  
-            r.status,  
-            r.service.as_deref().unwrap_or("-"),  
-            r.banner.as_deref().unwrap_or("-").replace('\n', " ")  
-        ));  
-    }  
-    std::fs::write(path, txt)?;  
-    info!("Saved TXT: {}", path.display());  
-}  
-
-Ok(())
-
+/// Runs the banner analysis of the report at `input` again, printing what
+/// changed to stderr and the updated report to `output` or stdout.
+fn reanalyze(
+    input: &std::path::Path,
+    fingerprints: Option<std::path::PathBuf>,
+    vuln_db: Option<std::path::PathBuf>,
+    output: Option<std::path::PathBuf>,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let fingerprints = fingerprints
+        .as_deref()
+        .map(Fingerprints::load)
+        .transpose()?
+        .unwrap_or_default();
+    let vuln_db = vuln_db.as_deref().map(VulnDb::load).transpose()?;
+    let text = std::fs::read_to_string(input)
+        .map_err(|e| format!("Cannot read {}: {}", input.display(), e))?;
+    let mut summary: ScanSummary = serde_json::from_str(&text)
+        .map_err(|e| format!("{} is not a --json report: {}", input.display(), e))?;
+    summary.fill_result_targets();
+    summary.expand_shared_responses();
+    let changes = reanalysis::reanalyze(&mut summary, &fingerprints, vuln_db.as_ref());
+    eprint!("{}", changes);
+    let mut json = serde_json::to_vec_pretty(&summary)?;
+    json.push(b'\n');
+    match output {
+        Some(path) => {
+            std::fs::write(&path, json)
+                .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+            Ok(ExitCode::SUCCESS)
+        }
+        None => Ok(write_stdout(&json)),
+    }
 }
 
-async fn grab_banner(stream: &mut TcpStream, dur: Duration) -> Result<String, ScanError> {
-let mut buffer = vec![0u8; 4096];
-let read_res = timeout(dur, async {
-stream.readable().await?;
-stream.read(&mut buffer).await
-})
-.await;
-
-match read_res {  
-    Ok(Ok(n)) if n > 0 => Ok(String::from_utf8_lossy(&buffer[..n]).trim_end().to_string()),  
-    _ => Err(ScanError::Io(std::io::Error::new(  
-        std::io::ErrorKind::Other,  
-        "Banner read failed",  
-    ))),  
+/// Prints the stability of the ports over the reports of `args`.
+fn stability(args: &StabilityArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut hosts = TargetSet::new();
+    for t in &args.target {
+        hosts.insert(t).map_err(ScanError::InvalidTarget)?;
+    }
+    let mut scans = history::load(&args.reports)?;
+    scans.retain(|s| s.has_tags(&args.tag));
+    let others: Vec<IdentityMode> = scans
+        .iter()
+        .map(|s| s.identity)
+        .filter(|&m| m != args.identity_by)
+        .collect();
+    if let Some(&written) = others.first() {
+        let what = format!("{} of the {} reports", others.len(), scans.len());
+        if let Some(warning) = identity::mismatch(&what, written, args.identity_by) {
+            warn!("{}", warning);
+        }
+    }
+    let report = history::stability(
+        &scans,
+        (!args.target.is_empty()).then_some(&hosts),
+        args.flapping,
+        args.identity_by,
+    );
+    let text = match args.format {
+        ListFormat::Table => report.to_string(),
+        ListFormat::Json => serde_json::to_string_pretty(&report)? + "\n",
+        ListFormat::Csv => report.csv(),
+    };
+    Ok(write_stdout(text.as_bytes()))
 }
 
+/// Prints the names of the addresses of `args` from their PTR records.
+async fn ptr_sweep(args: &PtrArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if args.concurrency == 0 {
+        return Err(ScanError::ZeroConcurrency.into());
+    }
+    let mut targets = TargetSet::new();
+    for t in &args.target {
+        targets.insert(t).map_err(ScanError::InvalidTarget)?;
+    }
+    for t in &args.exclude {
+        targets.remove(t).map_err(ScanError::InvalidTarget)?;
+    }
+    if targets.len() > ptr::MAX_ADDRESSES {
+        return Err(format!(
+            "{} addresses to look up; at most {} are looked up at a time",
+            targets.len(),
+            ptr::MAX_ADDRESSES
+        )
+        .into());
+    }
+    let servers = match args.resolver.as_slice() {
+        [] => resolve::system_name_servers(),
+        servers => servers.to_vec(),
+    };
+    if servers.is_empty() && args.doh.is_empty() {
+        return Err("No DNS server to ask: /etc/resolv.conf names none; give --resolver".into());
+    }
+    let mut resolver = Resolver::servers(&servers, args.timeout);
+    for url in &args.doh {
+        resolver.add_doh(url).await?;
+    }
+    let sweep = ptr::sweep(&resolver, &targets, args.concurrency).await;
+    let text = match args.format {
+        ListFormat::Table => sweep.to_string(),
+        ListFormat::Json => serde_json::to_string_pretty(&sweep)? + "\n",
+        ListFormat::Csv => sweep.csv(),
+    };
+    Ok(write_stdout(text.as_bytes()))
 }
 
-fn detect_service(port: u16, banner: Option<&str>) -> Option<String> {
-let mut m: HashMap<u16, &str> = HashMap::new();
-m.insert(22, "SSH");
-m.insert(80, "HTTP");
-m.insert(443, "HTTPS");
-m.insert(21, "FTP");
-m.insert(25, "SMTP");
-m.insert(3306, "MySQL");
-m.insert(5432, "PostgreSQL");
-m.insert(3389, "RDP");
-m.insert(5900, "VNC");
-
-if let Some(b) = banner {  
-    if b.contains("SSH-") {  
-        return Some("SSH".to_string());  
-    }  
-    if b.contains("HTTP/") || b.contains("Server:") {  
-        return Some("HTTP".to_string());  
-    }  
-    if b.starts_with("220 ") {  
-        return Some("SMTP/FTP".to_string());  
-    }  
-}  
-m.get(&port).map(|s| s.to_string())
-
-}
-
-fn print_result(r: &PortResult, verbose: bool) {
-let p = format!("{:>5}", r.port).bright_blue();
-let s = match r.status {
-PortStatus::Open => "open".bright_green(),
-PortStatus::Closed => "closed".bright_red(),
-PortStatus::Filtered => "filtered".yellow(),
-};
-let serv = r.service.as_deref().unwrap_or("-").bright_cyan();
-let ban = r.banner.as_deref().map_or("-".to_string(), |b| {
-let preview: String = b.chars().take(60).collect();
-if b.len() > 60 {
-format!("{}...", preview)
-} else {
-preview
+/// Prints the TLS of the ports of the report of `args`, compared.
+fn tls_report(args: &TlsReportArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut summary = import::load(&args.report, None)?;
+    let at = args.at.unwrap_or_else(chrono::Utc::now);
+    let report = TlsReport::of(&summary.results, at, args.expiring_within);
+    let text = match args.format {
+        TlsReportFormat::Table => report.to_string(),
+        TlsReportFormat::Markdown => report.markdown(),
+        TlsReportFormat::Json => {
+            summary.tls_report = Some(report);
+            serde_json::to_string_pretty(&summary)? + "\n"
+        }
+    };
+    Ok(write_stdout(text.as_bytes()))
 }
-});
 
-if verbose {  
-    println!("{} | {} | Service: {} | Banner: {}", p, s, serv, ban);  
-} else if r.status == PortStatus::Open {  
-    println!("{} open   {}", p, serv);  
+/// Prints the devices local discovery finds, one per line or as JSON.
+async fn list_devices(args: DiscoverArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let devices = discover::discover(args.window).await?;
+    let text = if args.json {
+        serde_json::to_string_pretty(&devices)? + "\n"
+    } else {
+        devices
+            .iter()
+            .map(|(ip, device)| format!("{:<15} {}\n", ip, device))
+            .collect()
+    };
+    info!("Found {} devices", devices.len());
+    match std::io::stdout().lock().write_all(text.as_bytes()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
 }
 
+/// Writes generated output, treating a closed pipe (e.g. `| head`) as done.
+fn write_stdout(bytes: &[u8]) -> ExitCode {
+    match std::io::stdout().lock().write_all(bytes) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(EXIT_USAGE)
+        }
+    }
 }
 
-fn parse_ports(s: &str) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
-let mut v = Vec::new();
-for part in s.split(',') {
-let part = part.trim();
-if part.is_empty() {
-continue;
-}
-if part.contains('-') {
-let nums: Vec<&str> = part.split('-').collect();
-if nums.len() != 2 {
-return Err("Invalid range format".into());
-}
-let start: u16 = nums[0].parse()?;
-let end: u16 = nums[1].parse()?;
-if start > end {
-return Err("Start > end".into());
-}
-for i in start..=end {
-v.push(i);
-}
-} else {
-v.push(part.parse()?);
-}
-}
-v.sort();
-v.dedup();
-v.retain(|&p| p != 0);
-if v.is_empty() {
-return Err("No valid ports".into());
-}
-Ok(v)
+/// The exit code of a scan with `verdict`.
+fn exit_code(verdict: Verdict) -> u8 {
+    match verdict {
+        Verdict::Failed => EXIT_SCAN_FAILED,
+        Verdict::Interrupted => EXIT_INTERRUPTED,
+        Verdict::PolicyFailed => EXIT_POLICY_FAILED,
+        Verdict::ExecFailed => EXIT_EXEC_FAILED,
+        Verdict::ProbePanicked => EXIT_PROBE_PANICKED,
+        Verdict::TargetsFailed => EXIT_TARGETS_FAILED,
+        Verdict::OpenFound => EXIT_OPEN_FOUND,
+        Verdict::NoneOpen => EXIT_NONE_OPEN,
+    }
 }
@@ -0,0 +1,139 @@
+//! Multicast DNS service discovery (RFC 6762, 6763): the queries that ask
+//! the local network what it offers, and what the answers say about the
+//! host that sent them.
+
+use hickory_resolver::proto::op::{Message, MessageType, OpCode, Query};
+use hickory_resolver::proto::rr::{Name, RData, RecordType};
+use std::net::{Ipv4Addr, SocketAddr};
+
+/// Where mDNS queries go and announcements arrive.
+pub const GROUP: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+/// The name under which every responder lists the service types it offers.
+pub const SERVICES: &str = "_services._dns-sd._udp.local.";
+
+/// A PTR query for each of `names`, such as [`SERVICES`] or
+/// `_googlecast._tcp.local.`. Sent from any port other than 5353, it is a
+/// "legacy unicast" query: the answers come back to the sender directly.
+pub fn query(names: &[&str]) -> Vec<u8> {
+    let mut message = Message::new(0, MessageType::Query, OpCode::Query);
+    for name in names {
+        if let Ok(name) = Name::from_ascii(name) {
+            message.add_query(Query::query(name, RecordType::PTR));
+        }
+    }
+    message.to_vec().unwrap_or_default()
+}
+
+/// What one mDNS response says about its sender.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Announcement {
+    /// Service types offered, such as `_googlecast._tcp`.
+    pub services: Vec<String>,
+    /// Service instance names, such as `Living Room TV`.
+    pub instances: Vec<String>,
+    /// Friendly name from a TXT record (`fn=`), when given.
+    pub name: Option<String>,
+    /// Device model from a TXT record (`md=` or `model=`), when given.
+    pub model: Option<String>,
+}
+
+/// Reads an mDNS response; `None` for queries and anything undecodable.
+pub fn parse(datagram: &[u8]) -> Option<Announcement> {
+    let message = Message::from_vec(datagram).ok()?;
+    if message.metadata.message_type != MessageType::Response {
+        return None;
+    }
+    let services_name = Name::from_ascii(SERVICES).ok()?;
+    let mut found = Announcement::default();
+    for record in message.answers.iter().chain(&message.additionals) {
+        match &record.data {
+            RData::PTR(ptr) if record.name.eq_ignore_root_case(&services_name) => {
+                push_new(&mut found.services, service_type(&ptr.0));
+            }
+            RData::PTR(ptr) => {
+                push_new(&mut found.services, service_type(&record.name));
+                if let Some(instance) = ptr.0.iter().next() {
+                    push_new(
+                        &mut found.instances,
+                        String::from_utf8_lossy(instance).into_owned(),
+                    );
+                }
+            }
+            RData::TXT(txt) => {
+                for entry in txt.txt_data.iter() {
+                    let entry = String::from_utf8_lossy(entry);
+                    let Some((key, value)) = entry.split_once('=') else {
+                        continue;
+                    };
+                    let slot = match key.to_ascii_lowercase().as_str() {
+                        "fn" => &mut found.name,
+                        "md" | "model" => &mut found.model,
+                        _ => continue,
+                    };
+                    if slot.is_none() && !value.is_empty() {
+                        *slot = Some(value.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(found)
+}
+
+/// `_ipp._tcp.local.` as `_ipp._tcp`.
+fn service_type(name: &Name) -> String {
+    let name = name.to_utf8();
+    let name = name.trim_end_matches('.');
+    name.strip_suffix(".local").unwrap_or(name).to_string()
+}
+
+fn push_new(list: &mut Vec<String>, value: String) {
+    if !value.is_empty() && !list.contains(&value) {
+        list.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Chromecast's answers to the service meta-query and to the query for
+    /// its own service type, with the name compression it uses on the wire.
+    const CHROMECAST: &[u8] = &[
+        0x00, 0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, // header
+        // _services._dns-sd._udp.local PTR _googlecast._tcp.local
+        0x09, b'_', b's', b'e', b'r', b'v', b'i', b'c', b'e', b's', 0x07, b'_', b'd', b'n', b's',
+        b'-', b's', b'd', 0x04, b'_', b'u', b'd', b'p', 0x05, b'l', b'o', b'c', b'a', b'l', 0x00,
+        0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x13, 0x0b, b'_', b'g', b'o', b'o',
+        b'g', b'l', b'e', b'c', b'a', b's', b't', 0x04, b'_', b't', b'c', b'p', 0xc0, 0x23,
+        // _googlecast._tcp.local PTR Living Room TV._googlecast._tcp.local
+        0xc0, 0x34, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x11, 0x0e, b'L', b'i',
+        b'v', b'i', b'n', b'g', b' ', b'R', b'o', b'o', b'm', b' ', b'T', b'V', 0xc0, 0x34,
+        // Living Room TV._googlecast._tcp.local TXT "md=Chromecast" "fn=Living Room TV"
+        0xc0, 0x53, 0x00, 0x10, 0x80, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x20, 0x0d, b'm', b'd',
+        b'=', b'C', b'h', b'r', b'o', b'm', b'e', b'c', b'a', b's', b't', 0x11, b'f', b'n', b'=',
+        b'L', b'i', b'v', b'i', b'n', b'g', b' ', b'R', b'o', b'o', b'm', b' ', b'T', b'V',
+    ];
+
+    #[test]
+    fn responses_name_services_instances_and_models() {
+        let found = parse(CHROMECAST).unwrap();
+        assert_eq!(found.services, ["_googlecast._tcp"]);
+        assert_eq!(found.instances, ["Living Room TV"]);
+        assert_eq!(found.name.as_deref(), Some("Living Room TV"));
+        assert_eq!(found.model.as_deref(), Some("Chromecast"));
+    }
+
+    #[test]
+    fn queries_are_not_announcements() {
+        let query = query(&[SERVICES, "_ipp._tcp.local."]);
+        let message = Message::from_vec(&query).unwrap();
+        assert_eq!(message.queries.len(), 2);
+        assert_eq!(message.queries[1].query_type(), RecordType::PTR);
+        assert_eq!(parse(&query), None);
+        assert_eq!(parse(b"\x00\x01garbage"), None);
+    }
+}
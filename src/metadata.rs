@@ -0,0 +1,180 @@
+//! Who ran a scan and why, from `--operator`, `--reason` and repeated
+//! `--tag KEY=VALUE`, kept verbatim in every report of the scan.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A `--tag KEY=VALUE`. The key is neither empty nor holds control
+/// characters; the value is taken as given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    /// What the tag says, such as `env`.
+    pub key: String,
+    /// What it says it is, such as `prod`.
+    pub value: String,
+}
+
+impl FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' is not KEY=VALUE, e.g. env=prod", s))?;
+        if key.is_empty() {
+            return Err(format!("'{}' has no key before the =", s));
+        }
+        if key.chars().any(char::is_control) {
+            return Err(format!("the key {:?} has control characters", key));
+        }
+        Ok(Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// The operator metadata of a scan; empty when none was given.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Who ran the scan, from `--operator`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// Why, from `--reason`, such as a change ticket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The `--tag`s, by key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Collects the metadata of a scan, refusing a tag key given twice.
+    pub fn new(
+        operator: Option<String>,
+        reason: Option<String>,
+        tags: impl IntoIterator<Item = Tag>,
+    ) -> Result<Self, String> {
+        let mut metadata = Metadata {
+            operator,
+            reason,
+            tags: BTreeMap::new(),
+        };
+        for tag in tags {
+            if metadata.tags.contains_key(&tag.key) {
+                return Err(format!("--tag {} is given more than once", tag.key));
+            }
+            metadata.tags.insert(tag.key, tag.value);
+        }
+        Ok(metadata)
+    }
+
+    /// Whether nothing was given.
+    pub fn is_empty(&self) -> bool {
+        self.operator.is_none() && self.reason.is_none() && self.tags.is_empty()
+    }
+
+    /// The metadata as named values for flat formats such as CSV columns:
+    /// `operator` and `reason` when given, then `tag:KEY` for each tag.
+    pub fn pairs(&self) -> Vec<(String, &str)> {
+        let fixed = [("operator", &self.operator), ("reason", &self.reason)];
+        fixed
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.as_deref()?)))
+            .chain(
+                self.tags
+                    .iter()
+                    .map(|(key, value)| (format!("tag:{}", key), value.as_str())),
+            )
+            .collect()
+    }
+}
+
+/// Reads as `Operator: jsmith | Reason: change CHG-1234 | Tags: env=prod`,
+/// leaving out what wasn't given.
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(operator) = &self.operator {
+            parts.push(format!("Operator: {}", operator));
+        }
+        if let Some(reason) = &self.reason {
+            parts.push(format!("Reason: {}", reason));
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            parts.push(format!("Tags: {}", tags.join(", ")));
+        }
+        f.write_str(&parts.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(s: &str) -> Tag {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn tags_need_a_clean_key() {
+        assert_eq!(
+            tag("ticket=CHG-1234=a"),
+            Tag {
+                key: "ticket".to_string(),
+                value: "CHG-1234=a".to_string()
+            }
+        );
+        assert_eq!(tag("empty=").value, "");
+        for (s, error) in [
+            ("env", "is not KEY=VALUE"),
+            ("=prod", "has no key"),
+            ("e\tnv=prod", "control characters"),
+        ] {
+            let err = s.parse::<Tag>().unwrap_err();
+            assert!(err.contains(error), "{}: {}", s, err);
+        }
+    }
+
+    #[test]
+    fn metadata_keeps_every_tag_once() {
+        let metadata = Metadata::new(
+            Some("jsmith".to_string()),
+            Some("change CHG-1234".to_string()),
+            [tag("team=net"), tag("env=prod")],
+        )
+        .unwrap();
+        assert_eq!(
+            metadata.to_string(),
+            "Operator: jsmith | Reason: change CHG-1234 | Tags: env=prod, team=net"
+        );
+        assert_eq!(
+            metadata.pairs(),
+            [
+                ("operator".to_string(), "jsmith"),
+                ("reason".to_string(), "change CHG-1234"),
+                ("tag:env".to_string(), "prod"),
+                ("tag:team".to_string(), "net"),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&metadata).unwrap(),
+            serde_json::json!({
+                "operator": "jsmith",
+                "reason": "change CHG-1234",
+                "tags": {"env": "prod", "team": "net"},
+            })
+        );
+
+        let twice = Metadata::new(None, None, [tag("env=prod"), tag("env=dev")]);
+        assert_eq!(twice, Err("--tag env is given more than once".to_string()));
+        assert!(Metadata::new(None, None, []).unwrap().is_empty());
+    }
+}
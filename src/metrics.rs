@@ -0,0 +1,462 @@
+//! Live scan counters and their Prometheus text exposition.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::clock::{Clock, SystemClock};
+use crate::scanner::PortStatus;
+use crate::stats::ScanStats;
+
+/// `finished_ms` before [`ScanMetrics::finish`] is called.
+const UNFINISHED: u64 = u64::MAX;
+
+/// Live scan counters exported in Prometheus text format.
+#[derive(Debug)]
+pub struct ScanMetrics {
+    target: String,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    connected: AtomicU64,
+    scanned: AtomicU64,
+    open: AtomicU64,
+    closed: AtomicU64,
+    filtered: AtomicU64,
+    attempts: AtomicU64,
+    retries: AtomicU64,
+    banner_probes: AtomicU64,
+    banner_bytes: AtomicU64,
+    concurrency: AtomicU64,
+    finished_ms: AtomicU64,
+    host_times: Mutex<BTreeMap<IpAddr, HostTime>>,
+    stats: Option<ScanStats>,
+}
+
+/// When one host was scanned, in milliseconds from the start of the scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostTime {
+    /// When its first probe started.
+    pub started_ms: u64,
+    /// From then until its last probe finished.
+    pub duration_ms: u64,
+}
+
+impl ScanMetrics {
+    /// Creates zeroed counters labelled with `target`.
+    pub fn new(target: &str) -> Self {
+        Self::with_clock(target, Arc::new(SystemClock))
+    }
+
+    /// Like [`ScanMetrics::new`], timing the scan with `clock`.
+    pub fn with_clock(target: &str, clock: Arc<dyn Clock>) -> Self {
+        ScanMetrics {
+            target: target.to_string(),
+            clock,
+            started: Instant::now(),
+            connected: AtomicU64::new(0),
+            scanned: AtomicU64::new(0),
+            open: AtomicU64::new(0),
+            closed: AtomicU64::new(0),
+            filtered: AtomicU64::new(0),
+            attempts: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            banner_probes: AtomicU64::new(0),
+            banner_bytes: AtomicU64::new(0),
+            concurrency: AtomicU64::new(0),
+            finished_ms: AtomicU64::new(UNFINISHED),
+            host_times: Mutex::new(BTreeMap::new()),
+            stats: None,
+        }
+    }
+
+    /// Keeps the detailed `--stats` counters as well.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(ScanStats::new());
+        self
+    }
+
+    /// The detailed counters, when the scan keeps them.
+    pub fn stats(&self) -> Option<&ScanStats> {
+        self.stats.as_ref()
+    }
+
+    /// Notes that the scan of `host` started.
+    pub fn host_started(&self, host: IpAddr) {
+        let started_ms = self.clock.elapsed(self.started).as_millis() as u64;
+        self.host_times.lock().unwrap().insert(
+            host,
+            HostTime {
+                started_ms,
+                duration_ms: 0,
+            },
+        );
+    }
+
+    /// Notes that the last probe of `host` finished.
+    pub fn host_finished(&self, host: IpAddr) {
+        let now_ms = self.clock.elapsed(self.started).as_millis() as u64;
+        if let Some(time) = self.host_times.lock().unwrap().get_mut(&host) {
+            time.duration_ms = now_ms.saturating_sub(time.started_ms);
+        }
+    }
+
+    /// When each host was scanned. Only scans that limit the hosts or the
+    /// connections per host keep track.
+    pub fn host_times(&self) -> BTreeMap<IpAddr, HostTime> {
+        self.host_times.lock().unwrap().clone()
+    }
+
+    /// Counts a classified port.
+    pub fn record(&self, status: &PortStatus) {
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+        let counter = match status {
+            PortStatus::Open => &self.open,
+            PortStatus::Closed => &self.closed,
+            PortStatus::Filtered => &self.filtered,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a port through its connect stage; open ports go on to the
+    /// probe stage before they are [recorded](ScanMetrics::record).
+    pub fn record_connected(&self) {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection attempt as it is made, whatever its outcome.
+    pub fn record_attempt(&self) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection attempt that retries an earlier one; it is
+    /// [an attempt](ScanMetrics::record_attempt) too.
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+        self.record_attempt();
+    }
+
+    /// Counts a banner read on an open port and the bytes it returned.
+    pub fn record_banner(&self, bytes: usize) {
+        self.banner_probes.fetch_add(1, Ordering::Relaxed);
+        self.banner_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Ports through the connect stage so far.
+    pub fn connected(&self) -> u64 {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Open ports through the connect stage still in the probe stage.
+    pub fn probing(&self) -> u64 {
+        self.connected().saturating_sub(self.scanned())
+    }
+
+    /// Ports classified so far, their probes done.
+    pub fn scanned(&self) -> u64 {
+        self.scanned.load(Ordering::Relaxed)
+    }
+
+    /// Ports found open so far.
+    pub fn open(&self) -> u64 {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Ports found closed so far.
+    pub fn closed(&self) -> u64 {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Ports found filtered so far.
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
+    /// Connection attempts made so far, including retries.
+    pub fn connection_attempts(&self) -> u64 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    /// Connection attempts that were retries.
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Banner reads attempted on open ports.
+    pub fn banner_probes(&self) -> u64 {
+        self.banner_probes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of banner data read.
+    pub fn banner_bytes(&self) -> u64 {
+        self.banner_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Records the current limit on connection attempts in flight.
+    pub fn set_concurrency(&self, concurrency: usize) {
+        self.concurrency
+            .store(concurrency as u64, Ordering::Relaxed);
+    }
+
+    /// The limit on connection attempts in flight, 0 before a scan starts.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.load(Ordering::Relaxed) as usize
+    }
+
+    /// Average scan rate since the counters were created.
+    pub fn ports_per_second(&self) -> f64 {
+        let secs = self.duration_seconds();
+        if secs > 0.0 {
+            self.scanned.load(Ordering::Relaxed) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Freezes the duration gauge at the final scan time.
+    pub fn finish(&self, total_time_ms: u128) {
+        self.finished_ms
+            .store(total_time_ms as u64, Ordering::Relaxed);
+    }
+
+    fn duration_seconds(&self) -> f64 {
+        match self.finished_ms.load(Ordering::Relaxed) {
+            UNFINISHED => self.clock.elapsed(self.started).as_secs_f64(),
+            ms => ms as f64 / 1000.0,
+        }
+    }
+
+    /// Renders the current values in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let label = format!("target=\"{}\"", escape_label_value(&self.target));
+        let gauges = [
+            (
+                "scanner_scanned_ports",
+                "Number of ports scanned so far.",
+                self.scanned.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "scanner_connected_ports",
+                "Number of ports through the connect stage so far.",
+                self.connected() as f64,
+            ),
+            (
+                "scanner_open_ports",
+                "Number of ports found open.",
+                self.open.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "scanner_closed_ports",
+                "Number of ports found closed.",
+                self.closed.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "scanner_filtered_ports",
+                "Number of ports found filtered.",
+                self.filtered.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "scanner_scan_duration_seconds",
+                "Wall time spent scanning.",
+                self.duration_seconds(),
+            ),
+            (
+                "scanner_ports_per_second",
+                "Average scan rate in ports per second.",
+                self.ports_per_second(),
+            ),
+            (
+                "scanner_concurrency",
+                "Limit on connection attempts in flight.",
+                self.concurrency() as f64,
+            ),
+            (
+                "scanner_connection_attempts",
+                "Connection attempts made, including retries.",
+                self.connection_attempts() as f64,
+            ),
+            (
+                "scanner_retries",
+                "Connection attempts that were retries.",
+                self.retries() as f64,
+            ),
+            (
+                "scanner_banner_probes",
+                "Banner reads attempted on open ports.",
+                self.banner_probes() as f64,
+            ),
+            (
+                "scanner_banner_bytes",
+                "Bytes of banner data read.",
+                self.banner_bytes() as f64,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{}{{{}}} {}", name, label, value);
+        }
+        out
+    }
+}
+
+/// Escapes a label value as required by the text exposition format.
+fn escape_label_value(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes the metrics for the node_exporter textfile collector.
+///
+/// The file is written next to its destination and renamed into place so the
+/// collector never reads a partially written file.
+pub fn write_textfile(path: &Path, metrics: &ScanMetrics) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, metrics.render())?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Serves `GET /metrics` on `addr` until the returned task is aborted.
+pub async fn serve(addr: SocketAddr, metrics: Arc<ScanMetrics>) -> std::io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(addr).await?;
+    Ok(tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("Metrics listener accept failed: {}", e);
+                    continue;
+                }
+            };
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_request(stream, &metrics).await {
+                    debug!("Metrics request from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }))
+}
+
+async fn handle_request(mut stream: TcpStream, metrics: &ScanMetrics) -> std::io::Result<()> {
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let response = if request.starts_with("GET ") && path == "/metrics" {
+        let body = metrics.render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(target: &str) -> ScanMetrics {
+        let m = ScanMetrics::new(target);
+        m.record(&PortStatus::Open);
+        m.record(&PortStatus::Open);
+        m.record(&PortStatus::Closed);
+        m.record(&PortStatus::Filtered);
+        for _ in 0..4 {
+            m.record_attempt();
+        }
+        for _ in 0..5 {
+            m.record_connected();
+        }
+        m.record_banner(100);
+        m.record_banner(0);
+        m.set_concurrency(8);
+        m.finish(1500);
+        m
+    }
+
+    fn parse(text: &str) -> prometheus_parse::Scrape {
+        let lines = text.lines().map(|l| Ok(l.to_string()));
+        prometheus_parse::Scrape::parse(lines).expect("valid exposition format")
+    }
+
+    fn gauge(scrape: &prometheus_parse::Scrape, name: &str) -> f64 {
+        let sample = scrape
+            .samples
+            .iter()
+            .find(|s| s.metric == name)
+            .unwrap_or_else(|| panic!("missing {}", name));
+        match sample.value {
+            prometheus_parse::Value::Gauge(v) => v,
+            ref other => panic!("{} is not a gauge: {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn render_parses_as_prometheus_text() {
+        let scrape = parse(&sample("10.0.0.1").render());
+
+        assert_eq!(gauge(&scrape, "scanner_open_ports"), 2.0);
+        assert_eq!(gauge(&scrape, "scanner_closed_ports"), 1.0);
+        assert_eq!(gauge(&scrape, "scanner_filtered_ports"), 1.0);
+        assert_eq!(gauge(&scrape, "scanner_scanned_ports"), 4.0);
+        assert_eq!(gauge(&scrape, "scanner_connected_ports"), 5.0);
+        assert_eq!(gauge(&scrape, "scanner_scan_duration_seconds"), 1.5);
+        assert_eq!(gauge(&scrape, "scanner_connection_attempts"), 4.0);
+        assert_eq!(gauge(&scrape, "scanner_banner_probes"), 2.0);
+        assert_eq!(gauge(&scrape, "scanner_banner_bytes"), 100.0);
+        assert_eq!(gauge(&scrape, "scanner_concurrency"), 8.0);
+        assert!((gauge(&scrape, "scanner_ports_per_second") - 4.0 / 1.5).abs() < 1e-9);
+        for s in &scrape.samples {
+            assert_eq!(s.labels.get("target"), Some("10.0.0.1"));
+        }
+        assert_eq!(scrape.docs.len(), 12);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let text = sample("bad\"host\\\nname").render();
+        assert!(text.contains(r#"target="bad\"host\\\nname""#));
+        assert_eq!(parse(&text).samples.len(), 12);
+    }
+
+    #[test]
+    fn textfile_is_written_atomically() {
+        let dir = std::env::temp_dir().join(format!("scanner-metrics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scan.prom");
+
+        write_textfile(&path, &sample("::1")).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(gauge(&parse(&text), "scanner_open_ports"), 2.0);
+        assert!(!dir.join("scan.prom.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! A NATS client for `--publish nats://…`, built with the `nats` feature.
+//!
+//! It speaks the core text protocol: read the server's INFO, send CONNECT,
+//! then PUB each message of a batch and flush with PING. The PONG confirms
+//! the server has processed everything before it, so an error between the
+//! two fails the batch. Servers that require TLS or credentials aren't
+//! supported.
+
+use futures::future::BoxFuture;
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::publish::{Message, Transport, ACK_TIMEOUT, CONNECT_TIMEOUT};
+
+/// Publishes to one subject, connecting to the first server that answers.
+pub struct NatsTransport {
+    servers: Vec<String>,
+    subject: String,
+    connection: Option<BufReader<TcpStream>>,
+}
+
+impl NatsTransport {
+    /// A transport for `subject`; nothing connects until the first batch.
+    pub fn new(servers: Vec<String>, subject: String) -> Self {
+        NatsTransport {
+            servers,
+            subject,
+            connection: None,
+        }
+    }
+
+    async fn connect(&self) -> io::Result<BufReader<TcpStream>> {
+        let mut last = io::Error::other("no NATS server to connect to");
+        for server in &self.servers {
+            match tokio::time::timeout(CONNECT_TIMEOUT, handshake(server)).await {
+                Ok(Ok(connection)) => {
+                    debug!("Connected to NATS server {}", server);
+                    return Ok(connection);
+                }
+                Ok(Err(e)) => last = e,
+                Err(_) => last = io::Error::new(io::ErrorKind::TimedOut, "NATS connect timed out"),
+            }
+        }
+        Err(last)
+    }
+
+    async fn publish(&mut self, batch: &[Message]) -> io::Result<()> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(self.connect().await?),
+        };
+        let mut frames = Vec::new();
+        for message in batch {
+            frames.extend_from_slice(
+                format!("PUB {} {}\r\n", self.subject, message.payload.len()).as_bytes(),
+            );
+            frames.extend_from_slice(&message.payload);
+            frames.extend_from_slice(b"\r\n");
+        }
+        frames.extend_from_slice(b"PING\r\n");
+        connection.get_mut().write_all(&frames).await?;
+        tokio::time::timeout(ACK_TIMEOUT, pong(connection))
+            .await
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, "NATS server did not answer PING")
+            })?
+    }
+}
+
+impl Transport for NatsTransport {
+    fn send<'a>(&'a mut self, batch: &'a [Message]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let sent = self.publish(batch).await;
+            if sent.is_err() {
+                // Start over on a fresh connection with the next batch.
+                self.connection = None;
+            }
+            sent
+        })
+    }
+}
+
+/// Connects to `server` and introduces the client.
+async fn handshake(server: &str) -> io::Result<BufReader<TcpStream>> {
+    let mut connection = BufReader::new(TcpStream::connect(server).await?);
+    let info = read_line(&mut connection).await?;
+    if !info.starts_with("INFO ") {
+        return Err(io::Error::other(format!(
+            "expected INFO from NATS, got {:?}",
+            info
+        )));
+    }
+    let connect = format!(
+        "CONNECT {{\"verbose\":false,\"pedantic\":false,\"lang\":\"rust\",\"name\":\"port-scanner\",\"version\":\"{}\"}}\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    connection.get_mut().write_all(connect.as_bytes()).await?;
+    Ok(connection)
+}
+
+/// Waits for the PONG to our PING, answering the server's own PINGs.
+async fn pong(connection: &mut BufReader<TcpStream>) -> io::Result<()> {
+    loop {
+        let line = read_line(connection).await?;
+        if line == "PONG" {
+            return Ok(());
+        } else if line == "PING" {
+            connection.get_mut().write_all(b"PONG\r\n").await?;
+        } else if let Some(reason) = line.strip_prefix("-ERR") {
+            return Err(io::Error::other(format!("NATS server error:{}", reason)));
+        }
+    }
+}
+
+async fn read_line(connection: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line = String::new();
+    if connection.read_line(&mut line).await? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "NATS server closed the connection",
+        ));
+    }
+    Ok(line.trim_end().to_string())
+}
@@ -0,0 +1,534 @@
+//! IPv6 neighbor discovery on the local segment (`--ipv6-local-discovery`):
+//! an ICMPv6 echo request to the all-nodes group `ff02::1` on each chosen
+//! interface, with every link-local address that answers becoming a
+//! target, and its MAC address read from the kernel's neighbor cache.
+//!
+//! A /64 can't be swept address by address, so this is the only way to
+//! find IPv6 hosts that nothing names. Needs Linux, a raw ICMPv6 socket
+//! (root or `CAP_NET_RAW`) and builds with the `ndp` feature.
+
+use std::net::Ipv6Addr;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::oui::MacAddr;
+
+/// A host that answered on the local segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbor {
+    /// Its link-local address.
+    pub address: Ipv6Addr,
+    /// The interface it answered on.
+    pub interface: String,
+    /// That interface's index, the scope id `address` is reached through.
+    pub index: u32,
+    /// Its MAC address, when the neighbor cache has it.
+    pub mac: Option<MacAddr>,
+}
+
+/// Why IPv6 neighbor discovery could not run.
+#[derive(Error, Debug)]
+pub enum NdpError {
+    /// This build or platform has no raw ICMPv6 support.
+    #[error("IPv6 neighbor discovery unavailable: {0}")]
+    Unsupported(String),
+    /// The raw socket was refused, usually for lack of privileges.
+    #[error("IPv6 neighbor discovery needs root or CAP_NET_RAW: {0}")]
+    Permission(std::io::Error),
+    /// The interface asked for is missing, down or has no link-local
+    /// address; `None` when no interface has one.
+    #[error("{}", match .0 {
+        Some(name) => format!("{} is not an up interface with an IPv6 link-local address", name),
+        None => "No up interface has an IPv6 link-local address".to_string(),
+    })]
+    NoInterface(Option<String>),
+    /// Sending, receiving or reading the neighbor cache failed.
+    #[error("IPv6 neighbor discovery failed: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Pings `ff02::1` on `interface`, or on every up interface with a
+/// link-local address, twice, waiting `wait` after each round, and returns
+/// the hosts that answered ordered by interface and address.
+pub async fn discover(interface: Option<&str>, wait: Duration) -> Result<Vec<Neighbor>, NdpError> {
+    imp::discover(interface, wait).await
+}
+
+/// The ICMPv6 and netlink messages, built and read on every platform so
+/// they can be tested anywhere.
+#[cfg_attr(not(all(feature = "ndp", target_os = "linux")), allow(dead_code))]
+mod packet {
+    use crate::oui::MacAddr;
+    use std::net::Ipv6Addr;
+
+    const ECHO_REQUEST: u8 = 128;
+    const ECHO_REPLY: u8 = 129;
+
+    const NLMSG_ERROR: u16 = 2;
+    const NLMSG_DONE: u16 = 3;
+    const RTM_NEWNEIGH: u16 = 28;
+    const RTM_GETNEIGH: u16 = 30;
+    const NLM_F_REQUEST: u16 = 0x1;
+    const NLM_F_DUMP: u16 = 0x300;
+    const AF_INET6: u8 = 10;
+    const NDA_DST: u16 = 1;
+    const NDA_LLADDR: u16 = 2;
+    const NUD_INCOMPLETE: u16 = 0x01;
+    const NUD_FAILED: u16 = 0x20;
+
+    /// An echo request; the kernel fills in the checksum.
+    pub fn echo_request(id: u16, seq: u16) -> [u8; 8] {
+        let mut packet = [0u8; 8];
+        packet[0] = ECHO_REQUEST;
+        packet[4..6].copy_from_slice(&id.to_be_bytes());
+        packet[6..8].copy_from_slice(&seq.to_be_bytes());
+        packet
+    }
+
+    /// True for an echo reply to one of our requests.
+    pub fn is_echo_reply(packet: &[u8], id: u16) -> bool {
+        packet.len() >= 8
+            && packet[0] == ECHO_REPLY
+            && packet[1] == 0
+            && packet[4..6] == id.to_be_bytes()
+    }
+
+    /// A netlink request for every IPv6 entry of the neighbor cache.
+    pub fn neighbor_dump(seq: u32) -> [u8; 28] {
+        let mut message = [0u8; 28];
+        message[0..4].copy_from_slice(&28u32.to_ne_bytes());
+        message[4..6].copy_from_slice(&RTM_GETNEIGH.to_ne_bytes());
+        message[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+        message[8..12].copy_from_slice(&seq.to_ne_bytes());
+        message[16] = AF_INET6;
+        message
+    }
+
+    /// What a netlink reply to [`neighbor_dump`] holds.
+    #[derive(Debug, Default, PartialEq, Eq)]
+    pub struct Entries {
+        /// Interface index, address and MAC of each resolved neighbor.
+        pub neighbors: Vec<(u32, Ipv6Addr, MacAddr)>,
+        /// Whether the dump is over.
+        pub done: bool,
+        /// The errno the kernel answered with, if it refused.
+        pub error: Option<i32>,
+    }
+
+    fn u16_at(buf: &[u8], at: usize) -> Option<u16> {
+        Some(u16::from_ne_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> Option<u32> {
+        Some(u32::from_ne_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+    }
+
+    /// Reads the messages of one netlink datagram.
+    pub fn parse_neighbors(buf: &[u8]) -> Entries {
+        let mut entries = Entries::default();
+        let mut at = 0;
+        while let (Some(len), Some(kind)) = (u32_at(buf, at), u16_at(buf, at + 4)) {
+            let len = len as usize;
+            let Some(message) = buf.get(at..at + len).filter(|_| len >= 16) else {
+                break;
+            };
+            match kind {
+                NLMSG_DONE => entries.done = true,
+                NLMSG_ERROR => {
+                    let errno = u32_at(message, 16).map_or(0, |e| e as i32);
+                    if errno != 0 {
+                        entries.error = Some(-errno);
+                    }
+                    entries.done = true;
+                }
+                RTM_NEWNEIGH => entries.neighbors.extend(neighbor(&message[16..])),
+                _ => {}
+            }
+            at += (len + 3) & !3;
+        }
+        entries
+    }
+
+    /// One `ndmsg` and its attributes.
+    fn neighbor(body: &[u8]) -> Option<(u32, Ipv6Addr, MacAddr)> {
+        if *body.first()? != AF_INET6 {
+            return None;
+        }
+        let index = u32_at(body, 4)?;
+        let state = u16_at(body, 8)?;
+        if state & (NUD_INCOMPLETE | NUD_FAILED) != 0 {
+            return None;
+        }
+        let (mut address, mut mac) = (None, None);
+        let mut at = 12;
+        while let (Some(len), Some(kind)) = (u16_at(body, at), u16_at(body, at + 2)) {
+            let len = usize::from(len);
+            let value = body.get(at + 4..at + len.max(4))?;
+            match kind {
+                NDA_DST => address = <[u8; 16]>::try_from(value).ok().map(Ipv6Addr::from),
+                NDA_LLADDR => mac = <[u8; 6]>::try_from(value).ok().map(MacAddr),
+                _ => {}
+            }
+            at += (len.max(4) + 3) & !3;
+        }
+        Some((index, address?, mac?))
+    }
+}
+
+#[cfg(all(feature = "ndp", target_os = "linux"))]
+mod imp {
+    use super::packet::{echo_request, is_echo_reply, neighbor_dump, parse_neighbors};
+    use super::{NdpError, Neighbor};
+    use crate::oui::MacAddr;
+    use std::collections::{BTreeSet, HashMap};
+    use std::ffi::CStr;
+    use std::io;
+    use std::mem;
+    use std::net::Ipv6Addr;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+    use tokio::io::unix::AsyncFd;
+    use tokio::time::Instant;
+
+    /// How many echo requests each interface gets.
+    const ROUNDS: u16 = 2;
+
+    /// An up, multicast, non-loopback interface with a link-local address.
+    #[derive(Debug, Clone)]
+    struct Interface {
+        name: String,
+        index: u32,
+        mac: Option<MacAddr>,
+        address: Ipv6Addr,
+    }
+
+    pub async fn discover(
+        interface: Option<&str>,
+        wait: Duration,
+    ) -> Result<Vec<Neighbor>, NdpError> {
+        let mut interfaces = interfaces()?;
+        if let Some(name) = interface {
+            interfaces.retain(|i| i.name == name);
+        }
+        if interfaces.is_empty() {
+            return Err(NdpError::NoInterface(interface.map(str::to_string)));
+        }
+        let socket = AsyncFd::new(open()?)?;
+        let id = std::process::id() as u16;
+        let mut found: BTreeSet<(u32, Ipv6Addr)> = BTreeSet::new();
+        for seq in 0..ROUNDS {
+            for interface in &interfaces {
+                tracing::debug!("NDP: pinging ff02::1 on {}", interface.name);
+                let packet = echo_request(id, seq);
+                loop {
+                    let mut guard = socket.writable().await?;
+                    match guard.try_io(|fd| send(fd.get_ref(), interface.index, &packet)) {
+                        Ok(sent) => break sent?,
+                        Err(_would_block) => continue,
+                    }
+                }
+            }
+            let deadline = Instant::now() + wait;
+            let mut buf = [0u8; 1280];
+            loop {
+                let Ok(readable) = tokio::time::timeout_at(deadline, socket.readable()).await
+                else {
+                    break;
+                };
+                let mut guard = readable?;
+                let (n, from, scope) = match guard.try_io(|fd| recv(fd.get_ref(), &mut buf)) {
+                    Ok(received) => received?,
+                    Err(_would_block) => continue,
+                };
+                let link_local = from.segments()[0] & 0xffc0 == 0xfe80;
+                if link_local
+                    && is_echo_reply(&buf[..n], id)
+                    && interfaces.iter().any(|i| i.index == scope)
+                {
+                    found.insert((scope, from));
+                }
+            }
+        }
+        let macs = neighbors()?;
+        let mut neighbors: Vec<Neighbor> = found
+            .into_iter()
+            .filter_map(|(index, address)| {
+                let interface = interfaces.iter().find(|i| i.index == index)?;
+                // This machine answers too, and knows its own MAC.
+                let mac = if address == interface.address {
+                    interface.mac
+                } else {
+                    macs.get(&(index, address)).copied()
+                };
+                Some(Neighbor {
+                    address,
+                    interface: interface.name.clone(),
+                    index,
+                    mac,
+                })
+            })
+            .collect();
+        neighbors.sort_by(|a, b| (&a.interface, a.address).cmp(&(&b.interface, b.address)));
+        Ok(neighbors)
+    }
+
+    fn permission_or_io(e: io::Error) -> NdpError {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => NdpError::Permission(e),
+            _ => NdpError::Io(e),
+        }
+    }
+
+    fn open() -> Result<OwnedFd, NdpError> {
+        // SAFETY: socket returns a new descriptor we own, or -1.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_INET6,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::IPPROTO_ICMPV6,
+            )
+        };
+        if fd < 0 {
+            return Err(permission_or_io(io::Error::last_os_error()));
+        }
+        // SAFETY: fd is a fresh, valid descriptor nothing else owns.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    fn send(fd: &OwnedFd, index: u32, packet: &[u8]) -> io::Result<()> {
+        // SAFETY: sockaddr_in6 is plain data; zeroed is a valid value.
+        let mut to: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+        to.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        to.sin6_addr.s6_addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1).octets();
+        to.sin6_scope_id = index;
+        // SAFETY: packet and to outlive the call, with the sizes given.
+        let n = unsafe {
+            libc::sendto(
+                fd.as_raw_fd(),
+                packet.as_ptr().cast(),
+                packet.len(),
+                0,
+                &to as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// One ICMPv6 message, with its sender and the scope id it came in on.
+    fn recv(fd: &OwnedFd, buf: &mut [u8]) -> io::Result<(usize, Ipv6Addr, u32)> {
+        // SAFETY: sockaddr_in6 is plain data; zeroed is a valid value.
+        let mut from: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+        // SAFETY: buf and from outlive the call, with the sizes given.
+        let n = unsafe {
+            libc::recvfrom(
+                fd.as_raw_fd(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                0,
+                &mut from as *mut libc::sockaddr_in6 as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok((
+                n as usize,
+                Ipv6Addr::from(from.sin6_addr.s6_addr),
+                from.sin6_scope_id,
+            ))
+        }
+    }
+
+    /// The resolved IPv6 entries of the neighbor cache, read over netlink.
+    fn neighbors() -> io::Result<HashMap<(u32, Ipv6Addr), MacAddr>> {
+        // SAFETY: socket returns a new descriptor we own, or -1.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a fresh, valid descriptor nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let request = neighbor_dump(1);
+        // SAFETY: request outlives the call, with the size given.
+        let n = unsafe { libc::send(fd.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut macs = HashMap::new();
+        let mut buf = vec![0u8; 32 * 1024];
+        loop {
+            // SAFETY: buf outlives the call, with the size given.
+            let n = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let entries = parse_neighbors(&buf[..n as usize]);
+            if let Some(errno) = entries.error {
+                return Err(io::Error::from_raw_os_error(errno));
+            }
+            for (index, address, mac) in entries.neighbors {
+                macs.insert((index, address), mac);
+            }
+            if entries.done || n == 0 {
+                return Ok(macs);
+            }
+        }
+    }
+
+    /// The up, multicast, non-loopback interfaces with a link-local address.
+    fn interfaces() -> io::Result<Vec<Interface>> {
+        let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+        // SAFETY: getifaddrs fills in head, freed below.
+        if unsafe { libc::getifaddrs(&mut head) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut macs: HashMap<String, MacAddr> = HashMap::new();
+        let mut addresses: Vec<(String, u32, Ipv6Addr)> = Vec::new();
+        let mut entry = head;
+        // SAFETY: the list and its addresses stay valid until freeifaddrs;
+        // each address is read as the family it names.
+        unsafe {
+            while !entry.is_null() {
+                let ifa = &*entry;
+                entry = ifa.ifa_next;
+                let flags = ifa.ifa_flags as libc::c_int;
+                if ifa.ifa_addr.is_null()
+                    || flags & libc::IFF_UP == 0
+                    || flags & libc::IFF_MULTICAST == 0
+                    || flags & libc::IFF_LOOPBACK != 0
+                {
+                    continue;
+                }
+                let name = CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+                match (*ifa.ifa_addr).sa_family as libc::c_int {
+                    libc::AF_PACKET => {
+                        let ll = &*(ifa.ifa_addr as *const libc::sockaddr_ll);
+                        if ll.sll_halen == 6 {
+                            let mut mac = [0u8; 6];
+                            mac.copy_from_slice(&ll.sll_addr[..6]);
+                            macs.insert(name, MacAddr(mac));
+                        }
+                    }
+                    libc::AF_INET6 => {
+                        let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                        let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                        if ip.segments()[0] & 0xffc0 == 0xfe80 {
+                            addresses.push((name, addr.sin6_scope_id, ip));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            libc::freeifaddrs(head);
+        }
+        let mut interfaces: Vec<Interface> = Vec::new();
+        for (name, index, address) in addresses {
+            if interfaces.iter().any(|i| i.index == index) {
+                continue;
+            }
+            interfaces.push(Interface {
+                mac: macs.get(&name).copied(),
+                name,
+                index,
+                address,
+            });
+        }
+        Ok(interfaces)
+    }
+}
+
+#[cfg(not(all(feature = "ndp", target_os = "linux")))]
+mod imp {
+    use super::{NdpError, Neighbor};
+    use std::time::Duration;
+
+    pub async fn discover(_: Option<&str>, _: Duration) -> Result<Vec<Neighbor>, NdpError> {
+        Err(NdpError::Unsupported(
+            if cfg!(target_os = "linux") {
+                "built without the ndp feature"
+            } else {
+                "only supported on Linux"
+            }
+            .to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::packet::{echo_request, is_echo_reply, neighbor_dump, parse_neighbors};
+    use super::*;
+
+    #[test]
+    fn echo_requests_and_their_replies() {
+        let request = echo_request(0x1234, 1);
+        assert_eq!(request, [128, 0, 0, 0, 0x12, 0x34, 0, 1]);
+        assert!(!is_echo_reply(&request, 0x1234), "a request is not a reply");
+        let mut reply = request;
+        reply[0] = 129;
+        assert!(is_echo_reply(&reply, 0x1234));
+        assert!(!is_echo_reply(&reply, 0x4321), "someone else's ping");
+        assert!(!is_echo_reply(&reply[..6], 0x1234));
+    }
+
+    /// An RTM_NEWNEIGH message for `address` at `mac` on interface 2.
+    fn new_neighbor(address: Ipv6Addr, state: u16, mac: Option<[u8; 6]>) -> Vec<u8> {
+        let mut body = vec![10, 0, 0, 0];
+        body.extend(2u32.to_ne_bytes());
+        body.extend(state.to_ne_bytes());
+        body.extend([0, 0]);
+        body.extend(20u16.to_ne_bytes());
+        body.extend(1u16.to_ne_bytes());
+        body.extend(address.octets());
+        if let Some(mac) = mac {
+            body.extend(10u16.to_ne_bytes());
+            body.extend(2u16.to_ne_bytes());
+            body.extend(mac);
+            body.extend([0, 0]);
+        }
+        let mut message = Vec::new();
+        message.extend((16 + body.len() as u32).to_ne_bytes());
+        message.extend(28u16.to_ne_bytes());
+        message.extend([0; 10]);
+        message.extend(body);
+        message
+    }
+
+    #[test]
+    fn neighbor_dumps_give_resolved_entries() {
+        let request = neighbor_dump(7);
+        assert_eq!(&request[0..4], &28u32.to_ne_bytes());
+        assert_eq!(&request[4..6], &30u16.to_ne_bytes());
+        assert_eq!(request[16], 10);
+
+        let router: Ipv6Addr = "fe80::1".parse().unwrap();
+        let mut datagram = new_neighbor(router, 0x02, Some([0x00, 0x50, 0x56, 1, 2, 3]));
+        datagram.extend(new_neighbor("fe80::2".parse().unwrap(), 0x01, None));
+        datagram.extend(new_neighbor("fe80::3".parse().unwrap(), 0x04, None));
+        let entries = parse_neighbors(&datagram);
+        assert_eq!(
+            entries.neighbors,
+            [(2, router, "00:50:56:01:02:03".parse().unwrap())]
+        );
+        assert!(!entries.done);
+
+        let mut done = 16u32.to_ne_bytes().to_vec();
+        done.extend(3u16.to_ne_bytes());
+        done.extend([0; 10]);
+        assert!(parse_neighbors(&done).done);
+        assert_eq!(parse_neighbors(&datagram[..20]), Default::default());
+    }
+}
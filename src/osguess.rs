@@ -0,0 +1,557 @@
+//! Operating system guesses (`--os-guess`) from what a TCP scan can see
+//! without raw sockets: which ports are open, what their banners say and,
+//! on Linux, the window the host offered when it accepted a connection.
+//!
+//! Every piece of evidence that matches a rule adds the rule's weight to its
+//! operating system; the highest total wins. The bundled rules can be
+//! replaced with `--os-rules`, a JSON array of rules with exactly one
+//! condition each:
+//!
+//! ```json
+//! [
+//!   { "os": "Windows", "port": 3389, "weight": 3 },
+//!   { "os": "Linux", "banner": "Ubuntu", "weight": 5 },
+//!   { "os": "Linux", "window_scale": 7, "weight": 2 },
+//!   { "os": "Windows", "window": 8192 }
+//! ]
+//! ```
+//!
+//! `banner` is looked for case-insensitively in the banner, product and
+//! extra information of every open port. `weight` defaults to 1. The
+//! received TTL would be a good hint too, but no platform reports it on a
+//! connected TCP socket, so it is not used.
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::scanner::{PortResult, PortStatus};
+
+/// The rules used without `--os-rules`.
+const BUILTIN_RULES: &str = include_str!("os_rules.json");
+
+/// How much a guess can be relied on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// Little evidence, or evidence for other systems almost as strong.
+    Low,
+    /// Some evidence, more of it for this system than for any other.
+    Medium,
+    /// Strong evidence, at least twice that for any other system.
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        })
+    }
+}
+
+/// The operating system a host probably runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OsGuess {
+    /// Such as `Linux`, `Windows` or `Embedded`.
+    pub os: String,
+    /// How much the guess can be relied on.
+    pub confidence: Confidence,
+    /// The evidence that matched, such as `"Ubuntu" in the banner on port 22`.
+    pub reasons: Vec<String>,
+}
+
+impl fmt::Display for OsGuess {
+    /// `Linux (guess, medium confidence: port 22 open, ...)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (guess, {} confidence: {})",
+            self.os,
+            self.confidence,
+            self.reasons.join(", ")
+        )
+    }
+}
+
+/// What the host's TCP stack showed when it accepted a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpTraits {
+    /// The receive window it offered.
+    pub window: Option<u32>,
+    /// Its window scale option, if it sent one.
+    pub window_scale: Option<u8>,
+}
+
+/// Everything known about one host that a rule can look at.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Evidence {
+    /// Open ports.
+    pub open_ports: Vec<u16>,
+    /// The banner, product and extra information of each open port that had
+    /// any, by port.
+    pub banners: Vec<(u16, String)>,
+    /// The host's TCP behaviour, when it could be read.
+    pub tcp: TcpTraits,
+}
+
+impl Evidence {
+    /// Collects the evidence in the results of one host.
+    pub fn of<'a>(results: impl IntoIterator<Item = &'a PortResult>) -> Self {
+        let mut evidence = Evidence::default();
+        for r in results.into_iter().filter(|r| r.status == PortStatus::Open) {
+            evidence.open_ports.push(r.port);
+            let text: Vec<String> = [
+                r.banner_str().map(|b| b.into_owned()),
+                r.product.clone(),
+                r.extra_info.clone(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            if !text.is_empty() {
+                evidence.banners.push((r.port, text.join("\n")));
+            }
+        }
+        evidence.open_ports.sort_unstable();
+        evidence.banners.sort();
+        evidence
+    }
+}
+
+/// Why a rules file could not be loaded.
+#[derive(Error, Debug)]
+pub enum OsRulesError {
+    /// The file could not be read.
+    #[error("Cannot read OS rules {path}: {source}")]
+    Read {
+        /// The rules path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is not an array of rules.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The rules path.
+        path: String,
+        /// The underlying error.
+        source: serde_json::Error,
+    },
+    /// A rule has no condition, or more than one.
+    #[error(
+        "{path}: rule {index} for {os} needs exactly one of port, banner, window, window_scale"
+    )]
+    Rule {
+        /// The rules path.
+        path: String,
+        /// Position of the rule in the file, from 0.
+        index: usize,
+        /// The rule's operating system.
+        os: String,
+    },
+}
+
+/// A rule as written in the rules file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    os: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    banner: Option<String>,
+    #[serde(default)]
+    window: Option<u32>,
+    #[serde(default)]
+    window_scale: Option<u8>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug)]
+enum Condition {
+    Port(u16),
+    Banner(String),
+    Window(u32),
+    WindowScale(u8),
+}
+
+#[derive(Debug)]
+struct Rule {
+    os: String,
+    weight: u32,
+    condition: Condition,
+}
+
+impl Rule {
+    /// Describes the evidence that matches, if any.
+    fn matches(&self, evidence: &Evidence) -> Option<String> {
+        match &self.condition {
+            Condition::Port(port) => evidence
+                .open_ports
+                .contains(port)
+                .then(|| format!("port {} open", port)),
+            Condition::Banner(needle) => evidence
+                .banners
+                .iter()
+                .find(|(_, text)| text.to_lowercase().contains(&needle.to_lowercase()))
+                .map(|(port, _)| format!("{:?} in the banner on port {}", needle, port)),
+            Condition::Window(window) => {
+                (evidence.tcp.window == Some(*window)).then(|| format!("TCP window {}", window))
+            }
+            Condition::WindowScale(scale) => (evidence.tcp.window_scale == Some(*scale))
+                .then(|| format!("TCP window scale {}", scale)),
+        }
+    }
+}
+
+/// The rules guesses are made with.
+#[derive(Debug)]
+pub struct OsRules {
+    rules: Vec<Rule>,
+}
+
+impl OsRules {
+    /// The bundled rules.
+    pub fn builtin() -> Self {
+        Self::parse("built-in OS rules", BUILTIN_RULES).expect("the bundled OS rules are valid")
+    }
+
+    /// Reads the rules at `path`.
+    pub fn load(path: &Path) -> Result<Self, OsRulesError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| OsRulesError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses rules from their JSON text; `name` appears in errors.
+    pub fn parse(name: &str, text: &str) -> Result<Self, OsRulesError> {
+        let raw: Vec<RawRule> =
+            serde_json::from_str(text).map_err(|source| OsRulesError::Parse {
+                path: name.to_string(),
+                source,
+            })?;
+        let rules = raw
+            .into_iter()
+            .enumerate()
+            .map(|(index, r)| {
+                let conditions = [
+                    r.port.map(Condition::Port),
+                    r.banner.map(Condition::Banner),
+                    r.window.map(Condition::Window),
+                    r.window_scale.map(Condition::WindowScale),
+                ];
+                let mut conditions = conditions.into_iter().flatten();
+                match (conditions.next(), conditions.next()) {
+                    (Some(condition), None) => Ok(Rule {
+                        os: r.os,
+                        weight: r.weight,
+                        condition,
+                    }),
+                    _ => Err(OsRulesError::Rule {
+                        path: name.to_string(),
+                        index,
+                        os: r.os,
+                    }),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(OsRules { rules })
+    }
+
+    /// Guesses the operating system behind `evidence`; `None` when no rule
+    /// matched.
+    pub fn guess(&self, evidence: &Evidence) -> Option<OsGuess> {
+        let mut scores: BTreeMap<&str, (u32, Vec<String>)> = BTreeMap::new();
+        for rule in &self.rules {
+            if let Some(reason) = rule.matches(evidence) {
+                let score = scores.entry(&rule.os).or_default();
+                score.0 += rule.weight;
+                score.1.push(reason);
+            }
+        }
+        // Ties go to the name that sorts first, so reports are reproducible.
+        let (&os, (best, reasons)) = scores.iter().rev().max_by_key(|(_, (score, _))| *score)?;
+        let runner_up = scores
+            .iter()
+            .filter(|(other, _)| **other != os)
+            .map(|(_, (score, _))| *score)
+            .max()
+            .unwrap_or(0);
+        let confidence = if *best >= 6 && *best >= 2 * runner_up {
+            Confidence::High
+        } else if *best >= 3 && *best > runner_up {
+            Confidence::Medium
+        } else {
+            Confidence::Low
+        };
+        Some(OsGuess {
+            os: os.to_string(),
+            confidence,
+            reasons: reasons.clone(),
+        })
+    }
+}
+
+/// Connects to `addr` once more and reads what the host's TCP stack offered
+/// in its answer. Only Linux reports it; elsewhere, and when the connection
+/// fails, nothing is known.
+pub async fn tcp_traits(addr: SocketAddr, timeout: Duration) -> TcpTraits {
+    let connect = tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr));
+    match connect.await {
+        Ok(Ok(stream)) => imp::tcp_traits(&stream),
+        _ => TcpTraits::default(),
+    }
+}
+
+/// Guesses the operating system of every host with an open port, after
+/// reading its TCP window on one of them.
+pub async fn guess_hosts(
+    results: &[PortResult],
+    rules: &OsRules,
+    timeout: Duration,
+) -> HashMap<IpAddr, OsGuess> {
+    let mut hosts: HashMap<IpAddr, Vec<&PortResult>> = HashMap::new();
+    for r in results.iter().filter(|r| r.status == PortStatus::Open) {
+        hosts.entry(r.target).or_default().push(r);
+    }
+    futures::stream::iter(hosts)
+        .map(|(host, open)| async move {
+            let mut evidence = Evidence::of(open.iter().copied());
+            let addr = SocketAddr::new(host, evidence.open_ports[0]);
+            evidence.tcp = tcp_traits(addr, timeout).await;
+            debug!("OS evidence for {}: {:?}", host, evidence);
+            let guess = rules.guess(&evidence)?;
+            info!("OS of {}: {}", host, guess);
+            Some((host, guess))
+        })
+        .buffer_unordered(16)
+        .filter_map(|guess| async move { guess })
+        .collect()
+        .await
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::TcpTraits;
+    use std::os::fd::AsRawFd;
+
+    /// `TCPI_OPT_WSCALE` from `linux/tcp.h`.
+    const TCPI_OPT_WSCALE: u8 = 4;
+
+    pub fn tcp_traits(stream: &tokio::net::TcpStream) -> TcpTraits {
+        // SAFETY: tcp_info is plain data, and the kernel writes at most `len`
+        // bytes of it.
+        let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                &mut info as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if rc != 0 {
+            return TcpTraits::default();
+        }
+        // The peer's window (tcpi_snd_wnd) is only reported since Linux 6.2.
+        let window_end = std::mem::offset_of!(libc::tcp_info, tcpi_snd_wnd) + 4;
+        let window = (len as usize >= window_end).then_some(info.tcpi_snd_wnd);
+        // tcpi_snd_wscale is the first 4-bit field of the byte.
+        let scale = if cfg!(target_endian = "little") {
+            info.tcpi_snd_rcv_wscale & 0x0f
+        } else {
+            info.tcpi_snd_rcv_wscale >> 4
+        };
+        TcpTraits {
+            window,
+            window_scale: (info.tcpi_options & TCPI_OPT_WSCALE != 0).then_some(scale),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::TcpTraits;
+
+    pub fn tcp_traits(_: &tokio::net::TcpStream) -> TcpTraits {
+        TcpTraits::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evidence(ports: &[u16], banners: &[(u16, &str)], tcp: TcpTraits) -> Evidence {
+        Evidence {
+            open_ports: ports.to_vec(),
+            banners: banners.iter().map(|&(p, b)| (p, b.to_string())).collect(),
+            tcp,
+        }
+    }
+
+    fn scale(window: u32, scale: u8) -> TcpTraits {
+        TcpTraits {
+            window: Some(window),
+            window_scale: Some(scale),
+        }
+    }
+
+    #[test]
+    fn the_bundled_rules_load() {
+        assert!(!OsRules::builtin().rules.is_empty());
+    }
+
+    #[test]
+    fn an_ubuntu_web_server_is_linux() {
+        let host = evidence(
+            &[22, 80],
+            &[
+                (22, "SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\nOpenSSH"),
+                (
+                    80,
+                    "HTTP/1.1 200 OK\r\nServer: nginx/1.18.0 (Ubuntu)\nnginx",
+                ),
+            ],
+            scale(65160, 7),
+        );
+        let guess = OsRules::builtin().guess(&host).unwrap();
+        assert_eq!(guess.os, "Linux");
+        assert_eq!(guess.confidence, Confidence::High);
+        assert_eq!(
+            guess.reasons,
+            [
+                "\"Ubuntu\" in the banner on port 22",
+                "TCP window scale 7",
+                "TCP window 65160"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_windows_server_is_windows() {
+        let host = evidence(
+            &[80, 135, 445, 3389],
+            &[(80, "HTTP/1.1 200 OK\r\nServer: Microsoft-IIS/10.0")],
+            scale(8192, 8),
+        );
+        let guess = OsRules::builtin().guess(&host).unwrap();
+        assert_eq!(guess.os, "Windows");
+        assert_eq!(guess.confidence, Confidence::High);
+        assert_eq!(guess.reasons.len(), 6);
+    }
+
+    #[test]
+    fn a_router_is_embedded() {
+        let host = evidence(
+            &[22, 23, 80, 7547],
+            &[
+                (22, "SSH-2.0-dropbear_2019.78"),
+                (
+                    80,
+                    "HTTP/1.0 401 Unauthorized\r\nServer: RomPager/4.07 UPnP/1.0",
+                ),
+            ],
+            TcpTraits {
+                window: Some(5840),
+                window_scale: None,
+            },
+        );
+        let guess = OsRules::builtin().guess(&host).unwrap();
+        assert_eq!(guess.os, "Embedded");
+        assert_eq!(guess.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn thin_or_conflicting_evidence_is_low_confidence() {
+        let rules = OsRules::builtin();
+        assert_eq!(rules.guess(&Evidence::default()), None);
+        assert_eq!(
+            rules.guess(&evidence(&[8080], &[], TcpTraits::default())),
+            None
+        );
+
+        let telnet = rules
+            .guess(&evidence(&[23], &[], TcpTraits::default()))
+            .unwrap();
+        assert_eq!(
+            (telnet.os.as_str(), telnet.confidence),
+            ("Embedded", Confidence::Low)
+        );
+
+        // Linux by its banner, Windows by its ports, equally.
+        let mixed = evidence(
+            &[22, 445, 3389],
+            &[(22, "SSH-2.0-OpenSSH_9.2p1 Debian-2")],
+            TcpTraits::default(),
+        );
+        let guess = rules.guess(&mixed).unwrap();
+        assert_eq!(
+            (guess.os.as_str(), guess.confidence),
+            ("Linux", Confidence::Low)
+        );
+    }
+
+    #[test]
+    fn rules_files_are_checked() {
+        let rules = OsRules::parse(
+            "rules.json",
+            r#"[{"os": "Plan 9", "banner": "plan9"}, {"os": "Plan 9", "port": 564, "weight": 2}]"#,
+        )
+        .unwrap();
+        let guess = rules
+            .guess(&evidence(
+                &[564],
+                &[(564, "Plan9 fossil")],
+                TcpTraits::default(),
+            ))
+            .unwrap();
+        assert_eq!(guess.os, "Plan 9");
+        assert_eq!(guess.confidence, Confidence::Medium);
+        assert_eq!(
+            guess.to_string(),
+            "Plan 9 (guess, medium confidence: \"plan9\" in the banner on port 564, port 564 open)"
+        );
+
+        let both = OsRules::parse("r.json", r#"[{"os": "X", "port": 1, "window": 2}]"#);
+        assert_eq!(
+            both.unwrap_err().to_string(),
+            "r.json: rule 0 for X needs exactly one of port, banner, window, window_scale"
+        );
+        assert!(OsRules::parse("r.json", r#"[{"os": "X"}]"#).is_err());
+        assert!(matches!(
+            OsRules::parse("r.json", r#"[{"os": "X", "ttl": 64}]"#),
+            Err(OsRulesError::Parse { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn tcp_traits_are_read_from_a_live_connection() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let traits = tcp_traits(addr, Duration::from_secs(2)).await;
+        if cfg!(target_os = "linux") {
+            // Loopback connections negotiate window scaling on Linux.
+            assert!(traits.window_scale.is_some(), "{:?}", traits);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! MAC addresses and the hardware vendors their first three bytes (the OUI)
+//! are registered to, from a bundled excerpt of the IEEE registry.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Vendors by OUI, sorted for binary search.
+const VENDORS: &[([u8; 3], &str)] = &[
+    ([0x00, 0x00, 0x0c], "Cisco Systems"),
+    ([0x00, 0x00, 0x5e], "IANA"),
+    ([0x00, 0x03, 0x93], "Apple"),
+    ([0x00, 0x04, 0x4b], "NVIDIA"),
+    ([0x00, 0x05, 0x69], "VMware"),
+    ([0x00, 0x0c, 0x29], "VMware"),
+    ([0x00, 0x0d, 0xb9], "PC Engines"),
+    ([0x00, 0x11, 0x32], "Synology"),
+    ([0x00, 0x14, 0x22], "Dell"),
+    ([0x00, 0x15, 0x5d], "Microsoft"),
+    ([0x00, 0x16, 0x3e], "Xensource"),
+    ([0x00, 0x17, 0x88], "Philips Lighting"),
+    ([0x00, 0x1a, 0x11], "Google"),
+    ([0x00, 0x1b, 0x21], "Intel"),
+    ([0x00, 0x1b, 0x63], "Apple"),
+    ([0x00, 0x1c, 0x42], "Parallels"),
+    ([0x00, 0x25, 0x90], "Super Micro Computer"),
+    ([0x00, 0x50, 0x56], "VMware"),
+    ([0x00, 0xe0, 0x4c], "Realtek"),
+    ([0x08, 0x00, 0x27], "PCS Systemtechnik (VirtualBox)"),
+    ([0x18, 0xb4, 0x30], "Nest Labs"),
+    ([0x24, 0xa4, 0x3c], "Ubiquiti"),
+    ([0x3c, 0x5a, 0xb4], "Google"),
+    ([0x44, 0xd9, 0xe7], "Ubiquiti"),
+    ([0x50, 0xc7, 0xbf], "TP-Link"),
+    ([0x80, 0x2a, 0xa8], "Ubiquiti"),
+    ([0xb4, 0xfb, 0xe4], "Ubiquiti"),
+    ([0xb8, 0x27, 0xeb], "Raspberry Pi Foundation"),
+    ([0xdc, 0xa6, 0x32], "Raspberry Pi Trading"),
+    ([0xe4, 0x5f, 0x01], "Raspberry Pi Trading"),
+    ([0xf4, 0xf2, 0x6d], "TP-Link"),
+    ([0xf4, 0xf5, 0xd8], "Google"),
+    ([0xfc, 0xec, 0xda], "Ubiquiti"),
+];
+
+/// A 48-bit Ethernet address, written `aa:bb:cc:dd:ee:ff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// True for addresses assigned by software rather than the manufacturer,
+    /// such as randomized Wi-Fi addresses and most virtual interfaces.
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+
+    /// The vendor the address is registered to, if the bundled table knows
+    /// it. Locally administered addresses belong to no vendor.
+    pub fn vendor(&self) -> Option<&'static str> {
+        if self.is_local() {
+            return None;
+        }
+        let oui = [self.0[0], self.0[1], self.0[2]];
+        VENDORS
+            .binary_search_by_key(&oui, |(prefix, _)| *prefix)
+            .ok()
+            .map(|i| VENDORS[i].1)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = String;
+
+    /// Accepts `:` or `-` between the bytes, in either case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid MAC address '{}'", s);
+        let mut bytes = [0u8; 6];
+        let mut parts = s.split([':', '-']);
+        for byte in &mut bytes {
+            let part = parts.next().filter(|p| p.len() == 2).ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(MacAddr(bytes))
+    }
+}
+
+impl Serialize for MacAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(s: &str) -> MacAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn the_table_is_sorted_for_binary_search() {
+        assert!(VENDORS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn vendors_are_found_by_their_oui() {
+        assert_eq!(mac("00:50:56:12:34:56").vendor(), Some("VMware"));
+        assert_eq!(
+            mac("B8-27-EB-00-00-01").vendor(),
+            Some("Raspberry Pi Foundation")
+        );
+        assert_eq!(mac("fc:ec:da:ff:ff:ff").vendor(), Some("Ubiquiti"));
+        assert_eq!(mac("00:00:01:00:00:00").vendor(), None);
+    }
+
+    #[test]
+    fn locally_administered_addresses_have_no_vendor() {
+        let docker = mac("02:42:ac:11:00:02");
+        assert!(docker.is_local());
+        assert_eq!(docker.vendor(), None);
+        assert!(!mac("00:0c:29:00:00:00").is_local());
+    }
+
+    #[test]
+    fn mac_addresses_round_trip_as_text() {
+        let m = mac("AA:bb:0C:dd:ee:0F");
+        assert_eq!(m.to_string(), "aa:bb:0c:dd:ee:0f");
+        assert_eq!(
+            serde_json::to_value(m).unwrap(),
+            serde_json::json!("aa:bb:0c:dd:ee:0f")
+        );
+        for bad in [
+            "aa:bb:cc:dd:ee",
+            "aa:bb:cc:dd:ee:ff:00",
+            "aa:bb:cc:dd:ee:gg",
+            "a:b:c:d:e:f",
+        ] {
+            assert!(bad.parse::<MacAddr>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,942 @@
+//! Report writers: streaming formats see every result as it arrives,
+//! end-of-scan formats render the finished [`ScanSummary`].
+
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::annotations::Deviation;
+use crate::discover::Device;
+use crate::fields::Field;
+use crate::geoip::{GeoDb, GeoInfo};
+use crate::junit;
+use crate::live::{StreamUrl, StreamWriter};
+use crate::metadata::Metadata;
+use crate::plan::TargetSet;
+use crate::publish::{PublishKey, PublishUrl, Publisher};
+use crate::scanner::{PortResult, ScanSummary, TargetStatus};
+use crate::template::{self, FilenameTemplate};
+
+/// A report format fed by the scan.
+///
+/// `write_result` is called for every port in completion order and
+/// `finish` once with the final summary, including any policy outcome.
+pub trait OutputWriter {
+    /// Handles one result as it completes; end-of-scan formats ignore it.
+    fn write_result(&mut self, _result: &PortResult) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes or completes the report.
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()>;
+
+    /// The files the writer saved, asked once `finish` succeeded.
+    fn files(&self) -> Vec<OutputFile> {
+        Vec::new()
+    }
+}
+
+/// A report file on disk and the media type of its contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFile {
+    /// Where the report was saved.
+    pub path: PathBuf,
+    /// Its `Content-Type`, such as `application/json`.
+    pub content_type: &'static str,
+}
+
+impl OutputFile {
+    fn new(path: &Path, content_type: &'static str) -> Vec<Self> {
+        vec![OutputFile {
+            path: path.to_path_buf(),
+            content_type,
+        }]
+    }
+}
+
+/// What [`Outputs::finish`] left behind.
+#[derive(Debug, Default)]
+pub struct Finished {
+    /// Every file of the writers that succeeded, in registration order.
+    pub files: Vec<OutputFile>,
+    /// The writers that failed.
+    pub failures: Vec<OutputFailure>,
+}
+
+/// A writer that stopped receiving results after its first error.
+#[derive(Debug)]
+pub struct OutputFailure {
+    /// The name the writer was registered under.
+    pub name: String,
+    /// The error it returned.
+    pub error: io::Error,
+}
+
+struct Registered {
+    name: String,
+    writer: Box<dyn OutputWriter + Send>,
+    error: Option<io::Error>,
+}
+
+/// The writers registered for a scan, driven together.
+///
+/// A writer that fails is skipped from then on while the others carry on;
+/// the failures are returned by [`Outputs::finish`].
+#[derive(Default)]
+pub struct Outputs {
+    writers: Vec<Registered>,
+}
+
+impl Outputs {
+    /// Creates an empty set of writers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a writer; `name` identifies it in log lines and failures.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        writer: impl OutputWriter + Send + 'static,
+    ) {
+        self.writers.push(Registered {
+            name: name.into(),
+            writer: Box::new(writer),
+            error: None,
+        });
+    }
+
+    /// Number of registered writers.
+    pub fn len(&self) -> usize {
+        self.writers.len()
+    }
+
+    /// True when no writer is registered.
+    pub fn is_empty(&self) -> bool {
+        self.writers.is_empty()
+    }
+
+    /// Feeds a result to every writer that hasn't failed yet.
+    pub fn write_result(&mut self, result: &PortResult) {
+        for w in self.writers.iter_mut().filter(|w| w.error.is_none()) {
+            if let Err(e) = w.writer.write_result(result) {
+                warn!(
+                    "{} failed, skipping it for the rest of the scan: {}",
+                    w.name, e
+                );
+                w.error = Some(e);
+            }
+        }
+    }
+
+    /// Finishes every writer that hasn't failed and returns the files
+    /// saved and the failures.
+    pub fn finish(self, summary: &ScanSummary) -> Finished {
+        let mut finished = Finished::default();
+        for mut w in self.writers {
+            let result = match w.error.take() {
+                Some(e) => Err(e),
+                None => w.writer.finish(summary),
+            };
+            match result {
+                Ok(()) => {
+                    info!("Saved {}", w.name);
+                    finished.files.extend(w.writer.files());
+                }
+                Err(error) => finished.failures.push(OutputFailure {
+                    name: w.name,
+                    error,
+                }),
+            }
+        }
+        finished
+    }
+}
+
+/// The reports a scan writes and the sinks its results are sent to.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// The JSON report.
+    pub json: Option<PathBuf>,
+    /// The text report.
+    pub text: Option<PathBuf>,
+    /// The CSV report.
+    pub csv: Option<PathBuf>,
+    /// The NDJSON stream of results.
+    pub ndjson: Option<PathBuf>,
+    /// The directory of per-host JSON reports.
+    pub host_dir: Option<PathBuf>,
+    /// How the per-host reports are named.
+    pub filename_template: FilenameTemplate,
+    /// The JUnit XML report.
+    pub junit: Option<PathBuf>,
+    /// The columns of the text and CSV reports, instead of the usual ones.
+    pub fields: Option<Vec<Field>>,
+    /// Whether the CSV report has the audit columns.
+    pub audit: bool,
+    /// The broker results are published to.
+    pub publish: Option<PublishUrl>,
+    /// What published messages are keyed by.
+    pub publish_key: PublishKey,
+    /// Results held while the broker can't take them.
+    pub publish_buffer: usize,
+    /// The consumer results are streamed to.
+    pub stream_to: Option<StreamUrl>,
+    /// Whether the stream waits for the consumer to connect.
+    pub stream_listen: bool,
+    /// Results held while the consumer can't take them.
+    pub stream_buffer: usize,
+}
+
+impl OutputOptions {
+    /// Registers a writer for every report and sink asked for, for the scan
+    /// `scan_id` of `targets` started at `started_at`.
+    pub fn open(
+        &self,
+        targets: &TargetSet,
+        started_at: DateTime<Utc>,
+        scan_id: &str,
+        metadata: &Metadata,
+        geo: Option<Arc<GeoDb>>,
+    ) -> io::Result<Outputs> {
+        let mut outputs = Outputs::new();
+        if let Some(ref path) = self.json {
+            outputs.register(format!("JSON: {}", path.display()), JsonWriter::new(path));
+        }
+        if let Some(ref path) = self.text {
+            let mut txt = TextWriter::new(path);
+            if let Some(fields) = &self.fields {
+                txt = txt.with_fields(fields.clone());
+            }
+            outputs.register(format!("TXT: {}", path.display()), txt);
+        }
+        if let Some(ref path) = self.csv {
+            let mut csv = match &self.fields {
+                Some(fields) => CsvWriter::create_with_fields(path, fields.clone())?,
+                None => CsvWriter::create_with_metadata(path, self.audit, metadata)?,
+            };
+            if let Some(geo) = geo {
+                csv = csv.with_geo(geo);
+            }
+            outputs.register(format!("CSV: {}", path.display()), csv);
+        }
+        if let Some(ref path) = self.ndjson {
+            outputs.register(
+                format!("NDJSON: {}", path.display()),
+                NdjsonWriter::create(path)?,
+            );
+        }
+        if let Some(ref dir) = self.host_dir {
+            outputs.register(
+                format!("per-host JSON: {}", dir.display()),
+                HostFilesWriter::new(
+                    dir,
+                    self.filename_template.clone(),
+                    targets.clone(),
+                    started_at,
+                ),
+            );
+        }
+        if let Some(ref path) = self.junit {
+            outputs.register(
+                format!("JUnit XML: {}", path.display()),
+                JunitWriter::new(path),
+            );
+        }
+        if let Some(ref url) = self.publish {
+            let publisher = Publisher::start(url, self.publish_key, self.publish_buffer, scan_id)
+                .map_err(|e| io::Error::new(e.kind(), format!("--publish: {}", e)))?;
+            outputs.register(format!("publish: {}", url), publisher);
+        }
+        if let Some(ref url) = self.stream_to {
+            let stream = StreamWriter::start(url, self.stream_listen, self.stream_buffer, scan_id)
+                .map_err(|e| io::Error::new(e.kind(), format!("--stream-to: {}", e)))?;
+            outputs.register(format!("stream: {}", url), stream);
+        }
+        Ok(outputs)
+    }
+}
+
+/// The full summary as pretty-printed JSON.
+pub struct JsonWriter {
+    path: PathBuf,
+}
+
+impl JsonWriter {
+    /// Writes to `path` when the scan finishes.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonWriter { path: path.into() }
+    }
+}
+
+impl OutputWriter for JsonWriter {
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        serde_json::to_writer_pretty(&mut out, summary)?;
+        out.flush()
+    }
+
+    fn files(&self) -> Vec<OutputFile> {
+        OutputFile::new(&self.path, "application/json")
+    }
+}
+
+/// The human-readable report: a header line and one row per port.
+pub struct TextWriter {
+    path: PathBuf,
+    fields: Option<Vec<Field>>,
+}
+
+impl TextWriter {
+    /// Writes to `path` when the scan finishes.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TextWriter {
+            path: path.into(),
+            fields: None,
+        }
+    }
+
+    /// Writes a table of these columns instead of the usual rows: a line
+    /// naming them, then one line per port with `" | "` between values.
+    pub fn with_fields(mut self, fields: Vec<Field>) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+}
+
+impl OutputWriter for TextWriter {
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        render_text(summary, self.fields.as_deref(), &mut out)?;
+        out.flush()
+    }
+
+    fn files(&self) -> Vec<OutputFile> {
+        OutputFile::new(&self.path, "text/plain; charset=utf-8")
+    }
+}
+
+/// Renders the report [`TextWriter`] writes, with `fields` as its table
+/// columns, into `out`.
+pub fn render_text(
+    summary: &ScanSummary,
+    fields: Option<&[Field]>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let multi_target = summary.target.parse::<IpAddr>().is_err();
+    writeln!(
+        out,
+        "Scan of {} | Ports: {} | Time: {}ms",
+        summary.target, summary.scanned_ports, summary.total_time_ms
+    )?;
+    if !summary.metadata.is_empty() {
+        writeln!(out, "{}", summary.metadata)?;
+    }
+    for h in &summary.hosts {
+        let Some(guess) = &h.os_guess else {
+            continue;
+        };
+        if multi_target {
+            writeln!(out, "OS of {}: {}", h.address, guess)?;
+        } else {
+            writeln!(out, "OS: {}", guess)?;
+        }
+    }
+    if let Some(deviations) = &summary.deviations {
+        writeln!(out, "Annotations: {}", deviations)?;
+    }
+    for t in summary
+        .targets
+        .iter()
+        .filter(|t| t.status != TargetStatus::Scanned)
+    {
+        write!(out, "Target {}: {}", t.target, t.status)?;
+        match &t.error {
+            Some(e) => writeln!(out, " ({})", e)?,
+            None => writeln!(out)?,
+        }
+    }
+    writeln!(out)?;
+    if let Some(fields) = fields {
+        let names: Vec<&str> = fields.iter().map(|f| f.name()).collect();
+        writeln!(out, "{}", names.join(" | "))?;
+        for r in summary.reported_results()? {
+            let r = r?;
+            let values: Vec<String> = fields
+                .iter()
+                .map(|f| {
+                    f.value(&r)
+                        .map_or("-".to_string(), |v| v.replace('\n', " "))
+                })
+                .collect();
+            writeln!(out, "{}", values.join(" | "))?;
+        }
+    } else {
+        let mut clusters_shown = HashSet::new();
+        for r in summary.reported_results()? {
+            let r = r?;
+            // A group of ports with the same service takes one line, where
+            // its first port would be; ports with findings or an --exec
+            // outcome keep their own line too.
+            let cluster = r
+                .cluster
+                .and_then(|id| summary.clusters.iter().find(|c| c.id == id));
+            if let Some(c) = cluster {
+                if clusters_shown.insert(c.id) {
+                    writeln!(out, "Cluster {} | {}", c.id, c)?;
+                }
+                if r.exec.is_none() && r.findings.is_empty() {
+                    continue;
+                }
+            }
+            if multi_target {
+                write!(out, "Host {} | ", r.target)?;
+            }
+            write!(
+                out,
+                "Port {:>5} | {} | Service: {:<12} | Banner: {}",
+                r.port_label(),
+                r.status,
+                r.service.as_deref().unwrap_or("-"),
+                r.banner_str().as_deref().unwrap_or("-").replace('\n', " ")
+            )?;
+            if let Some(product) = r.product_version() {
+                write!(out, " | Product: {}", product)?;
+            }
+            if let Some(e) = &r.error {
+                write!(out, " | Error: {}", e)?;
+            }
+            if let Some(previous) = r.previous_status.as_ref().filter(|p| **p != r.status) {
+                write!(out, " | Was: {}", previous)?;
+            }
+            if let Some(at) = r.stale {
+                write!(
+                    out,
+                    " | Stale since: {}",
+                    at.format("%Y-%m-%d %H:%M:%S UTC")
+                )?;
+            }
+            if let Some(note) = &r.note {
+                write!(out, " | Note: {}", note)?;
+            }
+            if let Some(expected) = &r.expected_status {
+                write!(out, " | Expected: {}", expected)?;
+                if Deviation::of(&r).is_some() {
+                    write!(out, " (DEVIATION)")?;
+                }
+            }
+            if !r.findings.is_empty() {
+                let findings: Vec<String> = r
+                    .findings
+                    .iter()
+                    .map(|f| format!("{} ({})", f.id, f.severity))
+                    .collect();
+                write!(out, " | Findings: {}", findings.join(", "))?;
+            }
+            if let Some(exec) = &r.exec {
+                let status = match exec.exit_code {
+                    Some(code) => format!("exit {}", code),
+                    None if exec.timed_out => "timed out".to_string(),
+                    None => "failed".to_string(),
+                };
+                let first_line = exec.stdout.lines().next().unwrap_or("");
+                write!(out, " | Exec: {}: {}", status, first_line)?;
+            }
+            writeln!(out)?;
+        }
+    }
+    if !summary.hosts.is_empty() {
+        writeln!(out, "\nHosts:")?;
+        for h in &summary.hosts {
+            let mut label: Vec<String> = Vec::new();
+            if !h.dns.is_empty() {
+                let names: Vec<&str> = h.dns.iter().map(|d| d.name.as_str()).collect();
+                label.push(names.join(", "));
+            }
+            match (h.mac, &h.vendor) {
+                (Some(mac), Some(vendor)) => label.push(format!("{} ({})", mac, vendor)),
+                (Some(mac), None) => label.push(mac.to_string()),
+                (None, _) => {}
+            }
+            if !h.geo.is_empty() {
+                label.push(h.geo.to_string());
+            }
+            label.extend(
+                h.device
+                    .as_ref()
+                    .map(Device::to_string)
+                    .filter(|d| !d.is_empty()),
+            );
+            if let Some(timing) = h.timing {
+                label.push(format!(
+                    "Time: {}ms from +{}ms",
+                    timing.duration_ms, timing.started_ms
+                ));
+            }
+            let address = match &h.interface {
+                Some(interface) => format!("{}%{}", h.address, interface),
+                None => h.address.to_string(),
+            };
+            let line = format!("{:<15} {}", address, label.join(" | "));
+            writeln!(out, "{}", line.trim_end())?;
+            for hop in &h.route {
+                writeln!(out, "    {}", hop)?;
+            }
+        }
+    }
+    if let Some(heatmap) = summary.heatmap.as_ref().filter(|h| !h.rows.is_empty()) {
+        writeln!(out, "\nLatency:")?;
+        write!(out, "{}", heatmap)?;
+    }
+    Ok(())
+}
+
+/// One CSV row per port, written as results arrive.
+pub struct CsvWriter {
+    out: BufWriter<File>,
+    path: PathBuf,
+    geo: Option<Arc<GeoDb>>,
+    fields: Option<Vec<Field>>,
+    audit: bool,
+    /// The values of the metadata columns, the same on every row.
+    metadata: Vec<String>,
+}
+
+/// The columns `--audit` adds to the default CSV report.
+const AUDIT_FIELDS: [Field; 4] = [
+    Field::StartedAt,
+    Field::FinishedAt,
+    Field::SourcePort,
+    Field::Attempt,
+];
+
+impl CsvWriter {
+    /// Creates the file and writes the header row.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::create_with_metadata(path, false, &Metadata::default())
+    }
+
+    /// Creates the file with the [`ProbeAudit`](crate::scanner::ProbeAudit)
+    /// columns after the usual ones.
+    pub fn create_audited(path: &Path) -> io::Result<Self> {
+        Self::create_with_metadata(path, true, &Metadata::default())
+    }
+
+    /// Creates the file with a column for each of the scan's
+    /// [`Metadata::pairs`] last, named `operator`, `reason` and `tag:KEY`,
+    /// after the audit ones when `audit` is set.
+    pub fn create_with_metadata(path: &Path, audit: bool, metadata: &Metadata) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(
+            out,
+            "target,port,status,service,banner,duration_ms,error,product,version,cpe,\
+             country,asn,as_name,protocol"
+        )?;
+        if audit {
+            AUDIT_FIELDS
+                .iter()
+                .try_for_each(|f| write!(out, ",{}", f.name()))?;
+        }
+        let pairs = metadata.pairs();
+        pairs
+            .iter()
+            .try_for_each(|(name, _)| write!(out, ",{}", csv_field(name)))?;
+        writeln!(out)?;
+        Ok(CsvWriter {
+            out,
+            path: path.to_path_buf(),
+            geo: None,
+            fields: None,
+            audit,
+            metadata: pairs.iter().map(|(_, value)| csv_field(value)).collect(),
+        })
+    }
+
+    /// Creates the file with only these columns, in this order, named in
+    /// the header row. The country and AS columns are not among them.
+    pub fn create_with_fields(path: &Path, fields: Vec<Field>) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let names: Vec<&str> = fields.iter().map(|f| f.name()).collect();
+        writeln!(out, "{}", names.join(","))?;
+        Ok(CsvWriter {
+            out,
+            path: path.to_path_buf(),
+            geo: None,
+            fields: Some(fields),
+            audit: false,
+            metadata: Vec::new(),
+        })
+    }
+
+    /// Fills the country and AS columns from `geo`.
+    pub fn with_geo(mut self, geo: Arc<GeoDb>) -> Self {
+        self.geo = Some(geo);
+        self
+    }
+}
+
+impl OutputWriter for CsvWriter {
+    fn write_result(&mut self, r: &PortResult) -> io::Result<()> {
+        if let Some(fields) = &self.fields {
+            let values: Vec<String> = fields
+                .iter()
+                .map(|f| csv_field(&f.value(r).unwrap_or_default()))
+                .collect();
+            return writeln!(self.out, "{}", values.join(","));
+        }
+        let geo = self
+            .geo
+            .as_ref()
+            .map_or_else(GeoInfo::default, |db| db.lookup(r.target));
+        write!(
+            self.out,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            r.target,
+            r.port,
+            csv_field(&format!("{:?}", r.status).to_lowercase()),
+            csv_field(r.service.as_deref().unwrap_or("")),
+            csv_field(r.banner_str().as_deref().unwrap_or("")),
+            r.duration_ms,
+            csv_field(&r.error.as_ref().map_or(String::new(), |e| e.to_string())),
+            csv_field(r.product.as_deref().unwrap_or("")),
+            csv_field(r.version.as_deref().unwrap_or("")),
+            csv_field(r.cpe.as_deref().unwrap_or("")),
+            csv_field(geo.country_or_scope().unwrap_or("")),
+            geo.asn.map_or(String::new(), |asn| asn.to_string()),
+            csv_field(geo.as_name.as_deref().unwrap_or("")),
+            r.protocol
+        )?;
+        if self.audit {
+            for f in AUDIT_FIELDS {
+                write!(self.out, ",{}", f.value(r).unwrap_or_default())?;
+            }
+        }
+        for value in &self.metadata {
+            write!(self.out, ",{}", value)?;
+        }
+        writeln!(self.out)
+    }
+
+    fn finish(&mut self, _summary: &ScanSummary) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn files(&self) -> Vec<OutputFile> {
+        OutputFile::new(&self.path, "text/csv")
+    }
+}
+
+/// Quotes a field when it contains a separator, quote or line break.
+pub(crate) fn csv_field(v: &str) -> String {
+    if v.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", v.replace('"', "\"\""))
+    } else {
+        v.to_string()
+    }
+}
+
+/// One JSON object per line and per port, flushed as results arrive so the
+/// file can be tailed during the scan.
+pub struct NdjsonWriter {
+    out: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl NdjsonWriter {
+    /// Creates (or truncates) the file.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(NdjsonWriter {
+            out: BufWriter::new(File::create(path)?),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl OutputWriter for NdjsonWriter {
+    fn write_result(&mut self, r: &PortResult) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, r)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()
+    }
+
+    fn finish(&mut self, _summary: &ScanSummary) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn files(&self) -> Vec<OutputFile> {
+        OutputFile::new(&self.path, "application/x-ndjson")
+    }
+}
+
+/// The JUnit XML report (see [`junit::write`]).
+pub struct JunitWriter {
+    path: PathBuf,
+}
+
+impl JunitWriter {
+    /// Writes to `path` when the scan finishes.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JunitWriter { path: path.into() }
+    }
+}
+
+impl OutputWriter for JunitWriter {
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        junit::write(summary, &mut out)?;
+        out.flush()
+    }
+
+    fn files(&self) -> Vec<OutputFile> {
+        OutputFile::new(&self.path, "application/xml")
+    }
+}
+
+/// One JSON summary per host, named from a [`FilenameTemplate`].
+pub struct HostFilesWriter {
+    dir: PathBuf,
+    template: FilenameTemplate,
+    targets: TargetSet,
+    started: DateTime<Utc>,
+    written: Vec<PathBuf>,
+}
+
+impl HostFilesWriter {
+    /// Writes a file per entry of `targets` into `dir` when the scan finishes.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        template: FilenameTemplate,
+        targets: TargetSet,
+        started: DateTime<Utc>,
+    ) -> Self {
+        HostFilesWriter {
+            dir: dir.into(),
+            template,
+            targets,
+            started,
+            written: Vec::new(),
+        }
+    }
+}
+
+impl OutputWriter for HostFilesWriter {
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let targets: Vec<IpAddr> = self.targets.iter().collect();
+        let names: Vec<String> = targets
+            .iter()
+            .map(|t| {
+                self.template
+                    .render(&t.to_string(), &summary.scan_id, self.started)
+            })
+            .collect();
+        let paths = template::unique_paths(&self.dir, &names, Path::exists);
+        for ((target, name), path) in targets.iter().zip(&names).zip(&paths) {
+            if path.file_name().and_then(|f| f.to_str()) != Some(name.as_str()) {
+                warn!(
+                    "File name {} for {} is already taken, writing {} instead",
+                    name,
+                    target,
+                    path.display()
+                );
+            }
+            let json = serde_json::to_string_pretty(&summary.for_host(*target)?)?;
+            std::fs::write(path, json)?;
+            info!("Saved JSON for {}: {}", target, path.display());
+            self.written.push(path.clone());
+        }
+        Ok(())
+    }
+
+    fn files(&self) -> Vec<OutputFile> {
+        self.written
+            .iter()
+            .flat_map(|path| OutputFile::new(path, "application/json"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banner::Banner;
+    use crate::metrics::ScanMetrics;
+    use crate::scanner::PortStatus;
+
+    fn result(port: u16, status: PortStatus, banner: Option<&str>) -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), port, status);
+        r.banner = banner.map(Banner::from);
+        r.duration_ms = 5;
+        r
+    }
+
+    fn summary(results: Vec<PortResult>) -> ScanSummary {
+        ScanSummary::from_results(
+            "id".into(),
+            "10.0.0.1".into(),
+            results,
+            10,
+            &ScanMetrics::new("10.0.0.1"),
+            false,
+        )
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("scanner-output-{}-{}", std::process::id(), name))
+    }
+
+    struct Broken;
+
+    impl OutputWriter for Broken {
+        fn write_result(&mut self, _result: &PortResult) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn finish(&mut self, _summary: &ScanSummary) -> io::Result<()> {
+            unreachable!("failed writers are not finished")
+        }
+    }
+
+    #[test]
+    fn csv_quotes_awkward_banners() {
+        let path = temp_path("rows.csv");
+        let mut w = CsvWriter::create(&path).unwrap();
+        let r = result(21, PortStatus::Open, Some("220 \"hi\", there"));
+        w.write_result(&r).unwrap();
+        w.finish(&summary(vec![r])).unwrap();
+        let mut text = std::fs::read_to_string(&path).unwrap();
+
+        let mut w = CsvWriter::create(&path)
+            .unwrap()
+            .with_geo(Arc::new(GeoDb::new()));
+        let r = result(22, PortStatus::Closed, None);
+        w.write_result(&r).unwrap();
+        w.finish(&summary(vec![r])).unwrap();
+        text += std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .nth(1)
+            .unwrap();
+        text.push('\n');
+
+        assert_eq!(
+            text,
+            "target,port,status,service,banner,duration_ms,error,product,version,cpe,\
+             country,asn,as_name,protocol\n\
+             10.0.0.1,21,open,,\"220 \"\"hi\"\", there\",5,,,,,,,,tcp\n\
+             10.0.0.1,22,closed,,,5,,,,,private,,,tcp\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn csv_repeats_the_metadata_on_every_row() {
+        let path = temp_path("tagged.csv");
+        let metadata = Metadata::new(
+            Some("jsmith".to_string()),
+            None,
+            ["env=prod, eu".parse().unwrap()],
+        )
+        .unwrap();
+        let mut w = CsvWriter::create_with_metadata(&path, false, &metadata).unwrap();
+        let results = vec![
+            result(21, PortStatus::Open, None),
+            result(22, PortStatus::Closed, None),
+        ];
+        for r in &results {
+            w.write_result(r).unwrap();
+        }
+        w.finish(&summary(results)).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "target,port,status,service,banner,duration_ms,error,product,version,cpe,\
+             country,asn,as_name,protocol,operator,tag:env\n\
+             10.0.0.1,21,open,,,5,,,,,,,,tcp,jsmith,\"prod, eu\"\n\
+             10.0.0.1,22,closed,,,5,,,,,,,,tcp,jsmith,\"prod, eu\"\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn selected_fields_are_the_columns_in_order() {
+        let fields = vec![Field::Status, Field::Port, Field::Latency, Field::Banner];
+        let mut open = result(22, PortStatus::Open, Some("SSH-2.0-Test\r\nmore"));
+        open.duration_ms = 12;
+        let results = vec![open, result(23, PortStatus::Closed, None)];
+
+        let csv = temp_path("fields.csv");
+        let mut w = CsvWriter::create_with_fields(&csv, fields.clone()).unwrap();
+        for r in &results {
+            w.write_result(r).unwrap();
+        }
+        w.finish(&summary(results.clone())).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&csv).unwrap(),
+            "status,port,latency,banner\n\
+             open,22,12,\"SSH-2.0-Test\r\nmore\"\n\
+             closed,23,5,\n"
+        );
+
+        let txt = temp_path("fields.txt");
+        TextWriter::new(&txt)
+            .with_fields(fields)
+            .finish(&summary(results))
+            .unwrap();
+        let text = std::fs::read_to_string(&txt).unwrap();
+        let rows: Vec<&str> = text.lines().skip(2).collect();
+        assert_eq!(
+            rows,
+            [
+                "status | port | latency | banner",
+                "open | 22 | 12 | SSH-2.0-Test\r more",
+                "closed | 23 | 5 | -"
+            ]
+        );
+        std::fs::remove_file(csv).unwrap();
+        std::fs::remove_file(txt).unwrap();
+    }
+
+    #[test]
+    fn failing_writer_does_not_stop_the_others() {
+        let path = temp_path("lines.ndjson");
+        let mut outputs = Outputs::new();
+        outputs.register("broken", Broken);
+        outputs.register("NDJSON", NdjsonWriter::create(&path).unwrap());
+
+        let results = vec![
+            result(22, PortStatus::Open, None),
+            result(23, PortStatus::Closed, None),
+        ];
+        for r in &results {
+            outputs.write_result(r);
+        }
+        let finished = outputs.finish(&summary(results));
+
+        assert_eq!(finished.failures.len(), 1);
+        assert_eq!(finished.failures[0].name, "broken");
+        assert_eq!(
+            finished.files,
+            [OutputFile {
+                path: path.clone(),
+                content_type: "application/x-ndjson"
+            }]
+        );
+        let text = std::fs::read_to_string(&path).unwrap();
+        let ports: Vec<u64> = text
+            .lines()
+            .map(|l| {
+                serde_json::from_str::<serde_json::Value>(l).unwrap()["port"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(ports, [22, 23]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,927 @@
+//! What a scan will probe: targets and ports kept as sets of ranges and
+//! expanded lazily, so `10.0.0.0/16` × `1-65535` costs a few ranges of
+//! memory rather than billions of pairs.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::groups::PortGroups;
+use crate::preset::AppliedPreset;
+use crate::scope;
+use crate::spec::{self, Protocol, SpecError, TargetRange};
+
+/// An integer a [`RangeSet`] can hold.
+trait Point: Copy + Ord + fmt::Debug {
+    fn next(self) -> Option<Self>;
+    fn prev(self) -> Option<Self>;
+    /// Number of points in `start..=end`; `None` when that is more than a
+    /// `u128` holds.
+    fn span(start: Self, end: Self) -> Option<u128>;
+}
+
+macro_rules! point {
+    ($($t:ty),*) => {$(
+        impl Point for $t {
+            fn next(self) -> Option<Self> {
+                self.checked_add(1)
+            }
+            fn prev(self) -> Option<Self> {
+                self.checked_sub(1)
+            }
+            fn span(start: Self, end: Self) -> Option<u128> {
+                ((end - start) as u128).checked_add(1)
+            }
+        }
+    )*};
+}
+
+point!(u16, u32, u128);
+
+/// Sorted, disjoint, non-adjacent inclusive ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RangeSet<T> {
+    ranges: Vec<(T, T)>,
+}
+
+impl<T> Default for RangeSet<T> {
+    fn default() -> Self {
+        RangeSet { ranges: Vec::new() }
+    }
+}
+
+impl<T: Point> RangeSet<T> {
+    fn insert(&mut self, start: T, end: T) {
+        let mut merged = (start, end);
+        let mut placed = false;
+        let mut out = Vec::with_capacity(self.ranges.len() + 1);
+        for &(a, b) in &self.ranges {
+            if b.next().is_some_and(|n| n < merged.0) {
+                out.push((a, b));
+            } else if merged.1.next().is_some_and(|n| n < a) {
+                if !placed {
+                    out.push(merged);
+                    placed = true;
+                }
+                out.push((a, b));
+            } else {
+                merged = (merged.0.min(a), merged.1.max(b));
+            }
+        }
+        if !placed {
+            out.push(merged);
+        }
+        self.ranges = out;
+    }
+
+    fn remove(&mut self, start: T, end: T) {
+        let mut out = Vec::with_capacity(self.ranges.len() + 1);
+        for &(a, b) in &self.ranges {
+            if b < start || a > end {
+                out.push((a, b));
+                continue;
+            }
+            if let Some(before) = start.prev().filter(|_| a < start) {
+                out.push((a, before));
+            }
+            if let Some(after) = end.next().filter(|_| b > end) {
+                out.push((after, b));
+            }
+        }
+        self.ranges = out;
+    }
+
+    fn checked_len(&self) -> Option<u128> {
+        self.ranges
+            .iter()
+            .try_fold(0u128, |n, &(a, b)| n.checked_add(T::span(a, b)?))
+    }
+
+    fn len(&self) -> u128 {
+        self.checked_len().unwrap_or(u128::MAX)
+    }
+
+    fn contains(&self, point: T) -> bool {
+        self.ranges.iter().any(|&(a, b)| a <= point && point <= b)
+    }
+
+    fn iter(&self) -> Points<'_, T> {
+        Points {
+            ranges: self.ranges.iter(),
+            current: None,
+        }
+    }
+}
+
+/// The points of a [`RangeSet`] in order.
+#[derive(Debug, Clone)]
+struct Points<'a, T> {
+    ranges: std::slice::Iter<'a, (T, T)>,
+    current: Option<(T, T)>,
+}
+
+impl<T: Point> Iterator for Points<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some((a, b)) = self.current {
+                self.current = if a == b {
+                    None
+                } else {
+                    a.next().map(|n| (n, b))
+                };
+                return Some(a);
+            }
+            self.current = Some(*self.ranges.next()?);
+        }
+    }
+}
+
+/// A set of ports, such as the CLI's `--ports 22,80,8000-8100`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortSet(RangeSet<u16>);
+
+impl PortSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a specification such as `22,80,8000-8100,@web`. Port 0 is
+    /// dropped.
+    pub fn parse(spec: &str) -> Result<Self, SpecError> {
+        Self::parse_with(spec, &PortGroups::new())
+    }
+
+    /// Like [`PortSet::parse`], expanding the `@group`s of `groups`.
+    pub fn parse_with(spec: &str, groups: &PortGroups) -> Result<Self, SpecError> {
+        let mut set: PortSet = PortSet::new();
+        for range in spec::port_ranges_with(spec, groups)? {
+            set.insert(range);
+        }
+        set.remove(0..=0);
+        Ok(set)
+    }
+
+    /// Adds the ports in `range`.
+    pub fn insert(&mut self, range: RangeInclusive<u16>) {
+        if !range.is_empty() {
+            self.0.insert(*range.start(), *range.end());
+        }
+    }
+
+    /// Removes the ports in `range`.
+    pub fn remove(&mut self, range: RangeInclusive<u16>) {
+        if !range.is_empty() {
+            self.0.remove(*range.start(), *range.end());
+        }
+    }
+
+    /// Removes every port of `other`.
+    pub fn remove_all(&mut self, other: &PortSet) {
+        for &(a, b) in &other.0.ranges {
+            self.0.remove(a, b);
+        }
+    }
+
+    /// Number of ports.
+    pub fn len(&self) -> usize {
+        self.0.len() as usize
+    }
+
+    /// True when there is no port.
+    pub fn is_empty(&self) -> bool {
+        self.0.ranges.is_empty()
+    }
+
+    /// True when `port` is in the set.
+    pub fn contains(&self, port: u16) -> bool {
+        self.0.contains(port)
+    }
+
+    /// The ports in ascending order.
+    pub fn iter(&self) -> Ports<'_> {
+        Ports(self.0.iter())
+    }
+}
+
+impl FromIterator<u16> for PortSet {
+    fn from_iter<I: IntoIterator<Item = u16>>(iter: I) -> Self {
+        let mut set = PortSet::new();
+        for port in iter {
+            set.insert(port..=port);
+        }
+        set
+    }
+}
+
+impl fmt::Display for PortSet {
+    /// The set in `--ports` syntax.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, &(a, b)) in self.0.ranges.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if a == b {
+                write!(f, "{}", a)?;
+            } else {
+                write!(f, "{}-{}", a, b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The ports of each protocol, such as the CLI's `--ports T:22,80,U:53`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolPorts {
+    /// The TCP ports.
+    pub tcp: PortSet,
+    /// The UDP ports.
+    pub udp: PortSet,
+}
+
+impl ProtocolPorts {
+    /// Parses a specification with `T:` and `U:` prefixes, expanding the
+    /// `@group`s of `groups`. Port 0 is dropped.
+    pub fn parse_with(spec: &str, groups: &PortGroups) -> Result<Self, SpecError> {
+        let mut ports = ProtocolPorts::default();
+        for (protocol, range) in spec::protocol_port_ranges_with(spec, groups)? {
+            ports.of_mut(protocol).insert(range);
+        }
+        ports.tcp.remove(0..=0);
+        ports.udp.remove(0..=0);
+        Ok(ports)
+    }
+
+    /// The ports of `protocol`.
+    pub fn of(&self, protocol: Protocol) -> &PortSet {
+        match protocol {
+            Protocol::Tcp => &self.tcp,
+            Protocol::Udp => &self.udp,
+        }
+    }
+
+    fn of_mut(&mut self, protocol: Protocol) -> &mut PortSet {
+        match protocol {
+            Protocol::Tcp => &mut self.tcp,
+            Protocol::Udp => &mut self.udp,
+        }
+    }
+
+    /// Removes every port of `other`, each from its own protocol.
+    pub fn remove_all(&mut self, other: &ProtocolPorts) {
+        self.tcp.remove_all(&other.tcp);
+        self.udp.remove_all(&other.udp);
+    }
+
+    /// Number of ports, over both protocols.
+    pub fn len(&self) -> usize {
+        self.tcp.len() + self.udp.len()
+    }
+
+    /// True when there is no port of either protocol.
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_empty() && self.udp.is_empty()
+    }
+}
+
+impl fmt::Display for ProtocolPorts {
+    /// The ports in `--ports` syntax, prefixed only when there are UDP ones.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.tcp.is_empty(), self.udp.is_empty()) {
+            (_, true) => write!(f, "{}", self.tcp),
+            (true, false) => write!(f, "U:{}", self.udp),
+            (false, false) => write!(f, "T:{},U:{}", self.tcp, self.udp),
+        }
+    }
+}
+
+/// The ports of a [`PortSet`] in ascending order.
+#[derive(Debug, Clone)]
+pub struct Ports<'a>(Points<'a, u16>);
+
+impl Iterator for Ports<'_> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        self.0.next()
+    }
+}
+
+/// A set of hosts built from addresses, CIDR blocks (`10.0.0.0/16`) and
+/// ranges (`10.0.0.5-10.0.0.20`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetSet {
+    v4: RangeSet<u32>,
+    v6: RangeSet<u128>,
+}
+
+impl TargetSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the hosts named by `spec`.
+    pub fn insert(&mut self, spec: &str) -> Result<(), SpecError> {
+        match spec::target_range(spec)? {
+            TargetRange::V4(a, b) => self.v4.insert(a.into(), b.into()),
+            TargetRange::V6(a, b) => self.v6.insert(a.into(), b.into()),
+        }
+        Ok(())
+    }
+
+    /// Adds a single host.
+    pub fn insert_addr(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(ip) => self.v4.insert(ip.into(), ip.into()),
+            IpAddr::V6(ip) => self.v6.insert(ip.into(), ip.into()),
+        }
+    }
+
+    /// Removes the hosts named by `spec`.
+    pub fn remove(&mut self, spec: &str) -> Result<(), SpecError> {
+        match spec::target_range(spec)? {
+            TargetRange::V4(a, b) => self.v4.remove(a.into(), b.into()),
+            TargetRange::V6(a, b) => self.v6.remove(a.into(), b.into()),
+        }
+        Ok(())
+    }
+
+    /// Adds every host of `other`.
+    pub fn insert_all(&mut self, other: &TargetSet) {
+        for &(a, b) in &other.v4.ranges {
+            self.v4.insert(a, b);
+        }
+        for &(a, b) in &other.v6.ranges {
+            self.v6.insert(a, b);
+        }
+    }
+
+    /// Removes every host of `other`.
+    pub fn remove_all(&mut self, other: &TargetSet) {
+        for &(a, b) in &other.v4.ranges {
+            self.v4.remove(a, b);
+        }
+        for &(a, b) in &other.v6.ranges {
+            self.v6.remove(a, b);
+        }
+    }
+
+    /// The hosts that are in both sets.
+    pub fn intersection(&self, other: &TargetSet) -> TargetSet {
+        let mut only = self.clone();
+        only.remove_all(other);
+        let mut both = self.clone();
+        both.remove_all(&only);
+        both
+    }
+
+    /// Number of hosts, saturating for enormous IPv6 ranges.
+    pub fn len(&self) -> u128 {
+        self.checked_len().unwrap_or(u128::MAX)
+    }
+
+    /// Number of hosts; `None` when there are more than a `u128` holds, as
+    /// in `::/0`.
+    pub fn checked_len(&self) -> Option<u128> {
+        self.v4.checked_len()?.checked_add(self.v6.checked_len()?)
+    }
+
+    /// True when there is no host.
+    pub fn is_empty(&self) -> bool {
+        self.v4.ranges.is_empty() && self.v6.ranges.is_empty()
+    }
+
+    /// True when `addr` is in the set.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(ip) => self.v4.contains(ip.into()),
+            IpAddr::V6(ip) => self.v6.contains(ip.into()),
+        }
+    }
+
+    /// The hosts in ascending order, IPv4 first.
+    pub fn iter(&self) -> Targets<'_> {
+        Targets {
+            v4: self.v4.iter(),
+            v6: self.v6.iter(),
+        }
+    }
+}
+
+impl fmt::Display for TargetSet {
+    /// The set as comma-separated addresses, CIDR blocks and ranges.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v4 = self.v4.ranges.iter().map(|&(a, b)| {
+            let block = cidr_len(u128::from(a), u128::from(b), 32);
+            (
+                IpAddr::from(Ipv4Addr::from(a)),
+                IpAddr::from(Ipv4Addr::from(b)),
+                block,
+            )
+        });
+        let v6 = self.v6.ranges.iter().map(|&(a, b)| {
+            let block = cidr_len(a, b, 128);
+            (
+                IpAddr::from(Ipv6Addr::from(a)),
+                IpAddr::from(Ipv6Addr::from(b)),
+                block,
+            )
+        });
+        for (i, (a, b, block)) in v4.chain(v6).enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match block {
+                _ if a == b => write!(f, "{}", a)?,
+                Some(len) => write!(f, "{}/{}", a, len)?,
+                None => write!(f, "{}-{}", a, b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The prefix length when `start..=end` is exactly one CIDR block.
+fn cidr_len(start: u128, end: u128, bits: u32) -> Option<u32> {
+    let size = (end - start).checked_add(1);
+    match size {
+        None => Some(0),
+        Some(size) if size.is_power_of_two() && start.is_multiple_of(size) => {
+            Some(bits - size.trailing_zeros())
+        }
+        Some(_) => None,
+    }
+}
+
+/// The hosts of a [`TargetSet`] in ascending order.
+#[derive(Debug, Clone)]
+pub struct Targets<'a> {
+    v4: Points<'a, u32>,
+    v6: Points<'a, u128>,
+}
+
+impl Iterator for Targets<'_> {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<IpAddr> {
+        if let Some(ip) = self.v4.next() {
+            return Some(Ipv4Addr::from(ip).into());
+        }
+        self.v6.next().map(|ip| Ipv6Addr::from(ip).into())
+    }
+}
+
+/// Every target/port pair of a scan, target by target, generated on demand.
+#[derive(Debug, Clone)]
+pub struct Plan<'a> {
+    targets: Targets<'a>,
+    ports: &'a PortSet,
+    current: Option<(IpAddr, Ports<'a>)>,
+}
+
+impl<'a> Plan<'a> {
+    /// Pairs every host of `targets` with every port of `ports`.
+    pub fn new(targets: &'a TargetSet, ports: &'a PortSet) -> Self {
+        Plan {
+            targets: targets.iter(),
+            ports,
+            current: None,
+        }
+    }
+}
+
+impl Iterator for Plan<'_> {
+    type Item = SocketAddr;
+
+    fn next(&mut self) -> Option<SocketAddr> {
+        loop {
+            if let Some((ip, ports)) = &mut self.current {
+                if let Some(port) = ports.next() {
+                    return Some(SocketAddr::new(*ip, port));
+                }
+            }
+            self.current = Some((self.targets.next()?, self.ports.iter()));
+        }
+    }
+}
+
+/// How many hosts a [`PlanSummary`] lists by name.
+const TARGET_SAMPLE: usize = 10;
+
+/// What a scan would do, worked out without touching the network: the
+/// report of `--dry-run`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanSummary {
+    /// The targets in `--target` syntax, after exclusions.
+    pub targets: String,
+    /// Number of hosts.
+    pub target_count: u128,
+    /// The first hosts in scan order; every host of small sets.
+    pub target_sample: Vec<IpAddr>,
+    /// Number of hosts on the public internet.
+    pub public_hosts: u128,
+    /// The ports in `--ports` syntax, after exclusions.
+    pub ports: String,
+    /// Number of ports probed on every host.
+    pub port_count: usize,
+    /// Number of target/port pairs, saturating at `u64::MAX`.
+    pub total_pairs: u64,
+    /// How ports are probed.
+    pub scan_type: String,
+    /// Maximum number of connection attempts in flight at once.
+    pub concurrency: usize,
+    /// Connect timeout in milliseconds.
+    pub timeout_ms: u64,
+    /// Banner timeout in milliseconds.
+    pub banner_timeout_ms: u64,
+    /// The scan's duration if every connection ran into the timeout, an
+    /// upper bound for all but the slowest banners.
+    pub estimated_duration_ms: u64,
+    /// What holds the scan back besides `concurrency`.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// The `--preset` the scan runs with and the options it filled in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<AppliedPreset>,
+    /// The counts too large to hold, such as `target_count` for `::/0`:
+    /// each is then the largest value of its type, a lower bound.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overflow: Vec<String>,
+}
+
+impl PlanSummary {
+    /// Describes a scan of `targets` × `ports`.
+    pub fn new(
+        targets: &TargetSet,
+        ports: &PortSet,
+        concurrency: usize,
+        timeout: Duration,
+        banner_timeout: Duration,
+    ) -> Self {
+        let mut overflow = Vec::new();
+        let mut count = |field: &str, n: Option<u128>| {
+            n.unwrap_or_else(|| {
+                overflow.push(field.to_string());
+                u128::MAX
+            })
+        };
+        let target_count = count("target_count", targets.checked_len());
+        let public_hosts = count("public_hosts", scope::public_hosts(targets).checked_len());
+        let total_pairs = targets
+            .checked_len()
+            .and_then(|hosts| hosts.checked_mul(ports.len() as u128))
+            .and_then(|pairs| u64::try_from(pairs).ok());
+        let waves = total_pairs.map(|pairs| pairs.div_ceil(concurrency.max(1) as u64));
+        let timeout_ms = timeout.as_millis() as u64;
+        let estimated_duration_ms = waves.and_then(|waves| waves.checked_mul(timeout_ms));
+        if total_pairs.is_none() {
+            overflow.push("total_pairs".to_string());
+        }
+        if estimated_duration_ms.is_none() {
+            overflow.push("estimated_duration_ms".to_string());
+        }
+        PlanSummary {
+            targets: targets.to_string(),
+            target_count,
+            target_sample: targets.iter().take(TARGET_SAMPLE).collect(),
+            public_hosts,
+            ports: ports.to_string(),
+            port_count: ports.len(),
+            total_pairs: total_pairs.unwrap_or(u64::MAX),
+            scan_type: "tcp-connect".to_string(),
+            concurrency,
+            timeout_ms,
+            banner_timeout_ms: banner_timeout.as_millis() as u64,
+            estimated_duration_ms: estimated_duration_ms.unwrap_or(u64::MAX),
+            rate_limits: RateLimits::default(),
+            preset: None,
+            overflow,
+        }
+    }
+
+    /// `n`, the value of `field`, prefixed with `>= ` when it overflowed.
+    fn count(&self, field: &str, n: impl fmt::Display) -> String {
+        if self.overflow.iter().any(|f| f == field) {
+            format!(">= {}", n)
+        } else {
+            n.to_string()
+        }
+    }
+}
+
+impl fmt::Display for PlanSummary {
+    /// The plan as aligned `Field: value` lines.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(preset) = &self.preset {
+            writeln!(f, "Preset:       {}", preset)?;
+        }
+        writeln!(f, "Targets:      {}", self.targets)?;
+        let sample: Vec<String> = self.target_sample.iter().map(|ip| ip.to_string()).collect();
+        let more = if self.target_count > sample.len() as u128 {
+            ", ..."
+        } else {
+            ""
+        };
+        writeln!(
+            f,
+            "Hosts:        {} ({}{})",
+            self.count("target_count", self.target_count),
+            sample.join(", "),
+            more
+        )?;
+        writeln!(
+            f,
+            "Public hosts: {}",
+            self.count("public_hosts", self.public_hosts)
+        )?;
+        writeln!(
+            f,
+            "Ports:        {} ({} ports)",
+            self.ports, self.port_count
+        )?;
+        writeln!(
+            f,
+            "Pairs:        {}",
+            self.count("total_pairs", self.total_pairs)
+        )?;
+        writeln!(f, "Scan type:    {}", self.scan_type)?;
+        writeln!(f, "Concurrency:  {}", self.concurrency)?;
+        writeln!(
+            f,
+            "Timeouts:     {} ms connect, {} ms banner",
+            self.timeout_ms, self.banner_timeout_ms
+        )?;
+        writeln!(f, "Rate limits:  {}", self.rate_limits)?;
+        if let Some(window) = &self.rate_limits.window {
+            let state = if self.rate_limits.paused {
+                "paused"
+            } else {
+                "open"
+            };
+            match &self.rate_limits.until {
+                Some(until) => writeln!(f, "Window:       {}, {} until {}", window, state, until)?,
+                None => writeln!(f, "Window:       {}, {}", window, state)?,
+            }
+        }
+        if self.overflow.iter().any(|f| f == "estimated_duration_ms") {
+            return write!(f, "Estimate:     too long to count");
+        }
+        write!(
+            f,
+            "Estimate:     at most {:.1} s (every connection timing out)",
+            self.estimated_duration_ms as f64 / 1000.0
+        )
+    }
+}
+
+/// The limits of a [`PlanSummary`] that slow a scan down besides its
+/// concurrency: probe slots, per-host caps and the `--window` it pauses
+/// outside of.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Open ports having their banner read and follow-up probes run at
+    /// once.
+    pub probe_concurrency: usize,
+    /// Hosts scanned at once.
+    pub host_concurrency: Option<usize>,
+    /// Connection attempts in flight to any one host.
+    pub per_host_concurrency: Option<usize>,
+    /// Connections held established at once in all.
+    pub max_established: Option<usize>,
+    /// Connections held established at once to any one host.
+    pub max_established_per_host: Option<usize>,
+    /// The `--window` probes only start in.
+    pub window: Option<String>,
+    /// Whether the scan would start paused, outside its window.
+    pub paused: bool,
+    /// When the window next opens or closes, in its own time zone.
+    pub until: Option<String>,
+}
+
+impl fmt::Display for RateLimits {
+    /// The limits as a comma-separated list, probe slots first.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} probes at once", self.probe_concurrency)?;
+        let limits = [
+            (self.host_concurrency, "hosts at once"),
+            (self.per_host_concurrency, "connects per host"),
+            (self.max_established, "established"),
+            (self.max_established_per_host, "established per host"),
+        ];
+        for (n, what) in limits {
+            if let Some(n) = n {
+                write!(f, ", {} {}", n, what)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_sets_merge_and_exclude_ranges() {
+        let mut ports = PortSet::parse("80,20-25,22,26,0,1000-2000").unwrap();
+        assert_eq!(ports.to_string(), "20-26,80,1000-2000");
+        assert_eq!(ports.len(), 7 + 1 + 1001);
+
+        ports.remove_all(&PortSet::parse("21,1000-1999").unwrap());
+        assert_eq!(ports.to_string(), "20,22-26,80,2000");
+        assert_eq!(
+            ports.iter().collect::<Vec<_>>(),
+            [20, 22, 23, 24, 25, 26, 80, 2000]
+        );
+
+        let mut all = PortSet::parse("1-65535").unwrap();
+        all.remove(65535..=65535);
+        assert_eq!(all.len(), 65534);
+        assert!(PortSet::parse("0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn ports_of_each_protocol_are_kept_apart() {
+        let groups = PortGroups::new();
+        let parse = |spec| ProtocolPorts::parse_with(spec, &groups).unwrap();
+        let mut ports = parse("T:22,80,U:53,161-163,T:443,0");
+        assert_eq!(ports.tcp.to_string(), "22,80,443");
+        assert_eq!(ports.udp.to_string(), "53,161-163");
+        assert_eq!(ports.len(), 7);
+        assert_eq!(ports.to_string(), "T:22,80,443,U:53,161-163");
+
+        // An exclusion takes the protocol it names, TCP by default.
+        ports.remove_all(&parse("22,80-100,U:162"));
+        assert_eq!(ports.to_string(), "T:443,U:53,161,163");
+        ports.remove_all(&parse("U:1-1000"));
+        assert_eq!(ports.to_string(), "443");
+        assert_eq!(ports.of(Protocol::Udp), &PortSet::new());
+        assert_eq!(parse("U:53").to_string(), "U:53");
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn targets_expand_cidr_blocks_and_ranges() {
+        let mut targets = TargetSet::new();
+        targets.insert("10.0.0.7/30").unwrap();
+        targets.insert("10.0.0.2").unwrap();
+        targets.insert("::1").unwrap();
+        targets.insert("10.0.0.3-10.0.0.5").unwrap();
+        assert_eq!(targets.to_string(), "10.0.0.2-10.0.0.7,::1");
+        assert_eq!(targets.len(), 7);
+
+        targets.remove("10.0.0.5").unwrap();
+        let hosts: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            hosts,
+            ["10.0.0.2", "10.0.0.3", "10.0.0.4", "10.0.0.6", "10.0.0.7", "::1"]
+        );
+        assert!(!targets.contains("10.0.0.5".parse().unwrap()));
+
+        let mut block = TargetSet::new();
+        block.insert("192.168.1.77/24").unwrap();
+        assert_eq!(block.to_string(), "192.168.1.0/24");
+        block.insert("2001:db8::/32").unwrap();
+        assert_eq!(block.len(), 256 + (1 << 96));
+
+        for bad in ["10.0.0.1/33", "10.0.0.9-10.0.0.1", "10.0.0.1-::2", "host"] {
+            assert!(TargetSet::new().insert(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn plan_walks_every_pair_in_order() {
+        let mut targets = TargetSet::new();
+        targets.insert("10.0.0.1-10.0.0.2").unwrap();
+        let ports = PortSet::parse("22,80").unwrap();
+        let pairs: Vec<String> = Plan::new(&targets, &ports).map(|a| a.to_string()).collect();
+        assert_eq!(
+            pairs,
+            ["10.0.0.1:22", "10.0.0.1:80", "10.0.0.2:22", "10.0.0.2:80"]
+        );
+    }
+
+    #[test]
+    fn plan_summary_samples_targets_and_bounds_the_duration() {
+        let mut targets = TargetSet::new();
+        targets.insert("10.0.0.0/24").unwrap();
+        targets.insert("8.8.8.8").unwrap();
+        let ports = PortSet::parse("1-100").unwrap();
+        let plan = PlanSummary::new(
+            &targets,
+            &ports,
+            1000,
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+        );
+        assert_eq!(plan.targets, "8.8.8.8,10.0.0.0/24");
+        assert_eq!(plan.target_count, 257);
+        assert_eq!(plan.target_sample.len(), TARGET_SAMPLE);
+        assert_eq!(plan.target_sample[1], "10.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(plan.public_hosts, 1);
+        assert_eq!(plan.total_pairs, 25_700);
+        // 26 waves of 1000 connections, 500 ms each.
+        assert_eq!(plan.estimated_duration_ms, 13_000);
+        assert!(plan.to_string().contains("257 (8.8.8.8, 10.0.0.0,"));
+
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(serde_json::from_str::<PlanSummary>(&json).unwrap(), plan);
+        assert!(plan.overflow.is_empty());
+        assert!(!json.contains("overflow"));
+    }
+
+    #[test]
+    fn plan_summary_says_which_counts_overflowed() {
+        let mut targets = TargetSet::new();
+        targets.insert("::/0").unwrap();
+        assert_eq!(targets.checked_len(), None);
+        assert_eq!(targets.len(), u128::MAX);
+        let ports = PortSet::parse("22").unwrap();
+        let plan = PlanSummary::new(
+            &targets,
+            &ports,
+            1000,
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+        );
+        assert_eq!(
+            plan.overflow,
+            ["target_count", "total_pairs", "estimated_duration_ms"]
+        );
+        assert_eq!(plan.total_pairs, u64::MAX);
+        let text = plan.to_string();
+        assert!(
+            text.contains(&format!("Hosts:        >= {} (", u128::MAX)),
+            "{}",
+            text
+        );
+        assert!(text.contains(&format!("Pairs:        >= {}\n", u64::MAX)));
+        assert!(text.contains("too long to count"));
+
+        // One address short of all of them still counts exactly.
+        targets.remove("::").unwrap();
+        assert_eq!(targets.checked_len(), Some(u128::MAX));
+        let plan = PlanSummary::new(&targets, &ports, 1000, Duration::ZERO, Duration::ZERO);
+        assert_eq!(plan.overflow, ["total_pairs", "estimated_duration_ms"]);
+        assert_eq!(plan.target_count, u128::MAX);
+    }
+
+    #[test]
+    fn plan_summary_lists_the_rate_limits_and_window() {
+        let mut targets = TargetSet::new();
+        targets.insert("10.0.0.0/30").unwrap();
+        let ports = PortSet::parse("1-100").unwrap();
+        let mut plan = PlanSummary::new(
+            &targets,
+            &ports,
+            100,
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+        );
+        plan.rate_limits.probe_concurrency = 16;
+        assert!(plan
+            .to_string()
+            .contains("Rate limits:  16 probes at once\n"));
+        assert!(!plan.to_string().contains("Window:"));
+
+        plan.rate_limits = RateLimits {
+            probe_concurrency: 16,
+            host_concurrency: Some(2),
+            per_host_concurrency: Some(8),
+            max_established: None,
+            max_established_per_host: Some(4),
+            window: Some("01:00-05:00 UTC".to_string()),
+            paused: true,
+            until: Some("Tue 01:00".to_string()),
+        };
+        let text = plan.to_string();
+        assert!(
+            text.contains(
+                "Rate limits:  16 probes at once, 2 hosts at once, 8 connects per host, \
+                 4 established per host\n"
+            ),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("Window:       01:00-05:00 UTC, paused until Tue 01:00\n"),
+            "{}",
+            text
+        );
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["rate_limits"]["per_host_concurrency"], 8);
+        assert_eq!(
+            json["rate_limits"]["max_established"],
+            serde_json::Value::Null
+        );
+        assert_eq!(json["rate_limits"]["paused"], true);
+        assert_eq!(serde_json::from_value::<PlanSummary>(json).unwrap(), plan);
+    }
+}
@@ -0,0 +1,328 @@
+//! Firewall posture assertions (`--fail-on`, `--allow-open`) checked
+//! against scan results.
+
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::scanner::{parse_ports, PortResult, PortStatus, ScanSummary};
+use crate::vuln::Severity;
+
+/// A `--fail-on` assertion such as `open:23,3389` or `severity:high`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailOn {
+    /// None of these ports may be open.
+    Open(Vec<u16>),
+    /// No `--vuln-db` finding may be this severe or worse.
+    Severity(Severity),
+}
+
+impl FailOn {
+    /// Whether the rule checks findings, which only `--vuln-db`,
+    /// `--proxy-check` and `--baseline` report: without them it can never
+    /// fail.
+    pub fn needs_findings(&self) -> bool {
+        matches!(self, FailOn::Severity(_))
+    }
+}
+
+impl FromStr for FailOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <condition>:<value>, got '{}'", s))?;
+        match kind.trim() {
+            "open" => parse_ports(value)
+                .map(FailOn::Open)
+                .map_err(|e| format!("invalid ports in '{}': {}", s, e)),
+            "severity" => value.parse().map(FailOn::Severity),
+            other => Err(format!(
+                "unknown condition '{}' (expected: open, severity)",
+                other
+            )),
+        }
+    }
+}
+
+/// Ports that are allowed to be open, from `--allow-open 80,443`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllowOpen(pub Vec<u16>);
+
+impl FromStr for AllowOpen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ports(s).map(AllowOpen).map_err(|e| e.to_string())
+    }
+}
+
+/// Firewall posture assertions evaluated against the scan results.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Conditions that must not hold.
+    pub fail_on: Vec<FailOn>,
+    /// When set, the only ports allowed to be open.
+    pub allow_open: Option<AllowOpen>,
+}
+
+/// One check of one port on one host.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PolicyAssertion {
+    /// The rule that produced the check, e.g. `allow-open`.
+    pub rule: String,
+    /// Host the check was made against.
+    pub target: IpAddr,
+    /// Port the check was made against.
+    pub port: u16,
+    /// Whether the port satisfied the rule.
+    pub passed: bool,
+    /// Human-readable outcome, including service and banner on failure.
+    pub message: String,
+}
+
+/// Outcome of evaluating a [`Policy`], as recorded in the scan summary.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PolicyResult {
+    /// True when no assertion failed.
+    pub passed: bool,
+    /// Number of failed assertions.
+    pub violations: usize,
+    /// Every assertion made, passed or not.
+    pub assertions: Vec<PolicyAssertion>,
+}
+
+impl Policy {
+    /// True when no rules were given.
+    pub fn is_empty(&self) -> bool {
+        self.fail_on.is_empty() && self.allow_open.is_none()
+    }
+
+    /// Checks the rules against the results of `summary` and records the
+    /// outcome in it, warning of every violation. An empty policy records
+    /// nothing.
+    pub fn apply(&self, summary: &mut ScanSummary) {
+        if self.is_empty() {
+            return;
+        }
+        let outcome = self.evaluate(&summary.results);
+        for a in outcome.assertions.iter().filter(|a| !a.passed) {
+            warn!("Policy violation ({}): {}", a.rule, a.message);
+        }
+        summary.policy = Some(outcome);
+    }
+
+    /// Checks every rule against every scanned host.
+    pub fn evaluate(&self, results: &[PortResult]) -> PolicyResult {
+        let mut targets: Vec<IpAddr> = Vec::new();
+        for r in results {
+            if !targets.contains(&r.target) {
+                targets.push(r.target);
+            }
+        }
+        let open = |target: IpAddr, port: u16| {
+            results
+                .iter()
+                .find(|r| r.target == target && r.port == port && r.status == PortStatus::Open)
+        };
+        let mut assertions = Vec::new();
+
+        for rule in &self.fail_on {
+            match rule {
+                FailOn::Open(ports) => {
+                    for (&target, &port) in targets
+                        .iter()
+                        .flat_map(|t| ports.iter().map(move |p| (t, p)))
+                    {
+                        let found = open(target, port);
+                        assertions.push(PolicyAssertion {
+                            rule: "fail-on open".to_string(),
+                            target,
+                            port,
+                            passed: found.is_none(),
+                            message: match found {
+                                Some(r) => format!("port {} is open{}", port, describe(r)),
+                                None => format!("port {} is not open", port),
+                            },
+                        });
+                    }
+                }
+                FailOn::Severity(min) => {
+                    for r in results {
+                        for f in &r.findings {
+                            let passed = f.severity < *min;
+                            assertions.push(PolicyAssertion {
+                                rule: "fail-on severity".to_string(),
+                                target: r.target,
+                                port: r.port,
+                                passed,
+                                message: format!(
+                                    "{} ({}) on {}: {}",
+                                    f.id,
+                                    f.severity,
+                                    r.product_version().unwrap_or_default(),
+                                    f.summary
+                                ),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(AllowOpen(allowed)) = &self.allow_open {
+            for r in results.iter().filter(|r| r.status == PortStatus::Open) {
+                let passed = allowed.contains(&r.port);
+                assertions.push(PolicyAssertion {
+                    rule: "allow-open".to_string(),
+                    target: r.target,
+                    port: r.port,
+                    passed,
+                    message: if passed {
+                        format!("port {} is open and allowed", r.port)
+                    } else {
+                        format!("port {} is open but not allowed{}", r.port, describe(r))
+                    },
+                });
+            }
+        }
+
+        PolicyResult::from_assertions(assertions)
+    }
+}
+
+impl PolicyResult {
+    fn from_assertions(assertions: Vec<PolicyAssertion>) -> Self {
+        let violations = assertions.iter().filter(|a| !a.passed).count();
+        PolicyResult {
+            passed: violations == 0,
+            violations,
+            assertions,
+        }
+    }
+
+    /// The subset of assertions made about one host.
+    pub fn for_target(&self, target: IpAddr) -> PolicyResult {
+        PolicyResult::from_assertions(
+            self.assertions
+                .iter()
+                .filter(|a| a.target == target)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+fn describe(r: &PortResult) -> String {
+    match (&r.service, r.banner_str()) {
+        (Some(s), Some(b)) => format!(" ({}: {})", s, b.replace('\n', " ")),
+        (Some(s), None) => format!(" ({})", s),
+        (None, Some(b)) => format!(" ({})", b.replace('\n', " ")),
+        (None, None) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vuln::Finding;
+
+    fn result(port: u16, status: PortStatus) -> PortResult {
+        PortResult::new("10.0.0.1".parse().unwrap(), port, status)
+    }
+
+    #[test]
+    fn parses_fail_on_rules() {
+        assert_eq!(
+            "open:23,3389".parse::<FailOn>().unwrap(),
+            FailOn::Open(vec![23, 3389])
+        );
+        assert!("closed:23".parse::<FailOn>().is_err());
+        assert!("23".parse::<FailOn>().is_err());
+        assert!("open:".parse::<FailOn>().is_err());
+        assert_eq!(
+            "severity:high".parse::<FailOn>().unwrap(),
+            FailOn::Severity(Severity::High)
+        );
+        assert!("severity:severe".parse::<FailOn>().is_err());
+    }
+
+    #[test]
+    fn fail_on_flags_listed_open_ports() {
+        let policy = Policy {
+            fail_on: vec![FailOn::Open(vec![23, 3389])],
+            allow_open: None,
+        };
+        let results = [
+            result(22, PortStatus::Open),
+            result(23, PortStatus::Open),
+            result(3389, PortStatus::Filtered),
+        ];
+
+        let outcome = policy.evaluate(&results);
+        assert!(!outcome.passed);
+        assert_eq!(outcome.violations, 1);
+        assert_eq!(outcome.assertions.len(), 2);
+        assert!(!outcome.assertions[0].passed);
+        assert!(outcome.assertions[1].passed);
+    }
+
+    #[test]
+    fn allow_open_flags_everything_else() {
+        let policy = Policy {
+            fail_on: vec![],
+            allow_open: Some("80,443".parse().unwrap()),
+        };
+        let passing = [result(80, PortStatus::Open), result(22, PortStatus::Closed)];
+        assert!(policy.evaluate(&passing).passed);
+
+        let failing = [result(80, PortStatus::Open), result(22, PortStatus::Open)];
+        let outcome = policy.evaluate(&failing);
+        assert_eq!(outcome.violations, 1);
+        assert_eq!(outcome.assertions[1].port, 22);
+    }
+
+    #[test]
+    fn fail_on_is_checked_per_host() {
+        let policy = Policy {
+            fail_on: vec![FailOn::Open(vec![23])],
+            allow_open: None,
+        };
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut results = vec![result(23, PortStatus::Closed), result(23, PortStatus::Open)];
+        results[1].target = other;
+
+        let outcome = policy.evaluate(&results);
+        assert_eq!(outcome.assertions.len(), 2);
+        assert_eq!(outcome.violations, 1);
+        assert!(outcome.for_target("10.0.0.1".parse().unwrap()).passed);
+        assert!(!outcome.for_target(other).passed);
+    }
+
+    #[test]
+    fn fail_on_severity_flags_findings_at_or_above_it() {
+        let policy = Policy {
+            fail_on: vec![FailOn::Severity(Severity::High)],
+            allow_open: None,
+        };
+        let finding = |id: &str, severity| Finding {
+            id: id.into(),
+            severity,
+            summary: "summary".into(),
+        };
+        let mut results = vec![result(22, PortStatus::Open), result(80, PortStatus::Open)];
+        results[0].product = Some("OpenSSH".into());
+        results[0].findings = vec![finding("LOW-1", Severity::Low)];
+        assert!(policy.evaluate(&results).passed);
+
+        results[1].findings = vec![finding("CRIT-1", Severity::Critical)];
+        let outcome = policy.evaluate(&results);
+        assert_eq!((outcome.assertions.len(), outcome.violations), (2, 1));
+        assert_eq!(outcome.assertions[1].port, 80);
+        assert!(outcome.assertions[1]
+            .message
+            .starts_with("CRIT-1 (critical)"));
+    }
+}
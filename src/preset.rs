@@ -0,0 +1,390 @@
+//! Named bundles of scan options (`--preset`).
+//!
+//! Presets are TOML tables of `--long-option` names and values, like a
+//! daemon config, one table per preset:
+//!
+//! ```toml
+//! [web-audit]
+//! description = "Web ports, with HTTP probes naming servers and products"
+//! ports = "@web"
+//! http_probe = true
+//! ```
+//!
+//! The built-in ones are embedded from `presets.toml`; a user presets
+//! file ([`user_file`]) adds more and replaces built-in ones of the same
+//! name. A preset only fills in options that weren't given on the command
+//! line, so explicit flags always win.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use toml_edit::{Document, Item, Value};
+
+/// The built-in presets.
+const BUILT_IN: &str = include_str!("presets.toml");
+
+/// Why presets could not be loaded or applied.
+#[derive(Error, Debug)]
+pub enum PresetError {
+    /// The user presets file could not be read.
+    #[error("Cannot read presets {path}: {source}")]
+    Read {
+        /// The presets path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is not TOML.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The presets path.
+        path: String,
+        /// The underlying error.
+        source: toml_edit::TomlError,
+    },
+    /// A preset, or one of its options, can't be passed on the command
+    /// line.
+    #[error("{path}: [{preset}] {key}: {reason}")]
+    Option {
+        /// The presets path.
+        path: String,
+        /// The preset.
+        preset: String,
+        /// The option, or the preset's name for a preset that isn't a
+        /// table.
+        key: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+    /// No preset has this name.
+    #[error("Unknown preset '{name}' (known: {known})")]
+    Unknown {
+        /// The name asked for.
+        name: String,
+        /// The names there are, comma-separated.
+        known: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Setting {
+    Flag,
+    Values(Vec<String>),
+}
+
+/// One named bundle of options.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    /// The name `--preset` takes.
+    pub name: String,
+    /// What the preset is for.
+    pub description: String,
+    /// Where it was defined: `built-in` or the user presets file.
+    pub source: String,
+    options: Vec<(String, Setting)>,
+}
+
+impl Preset {
+    /// The long names of the options the preset sets, such as `ports`.
+    pub fn options(&self) -> impl Iterator<Item = &str> {
+        self.options.iter().map(|(option, _)| option.as_str())
+    }
+
+    /// The preset applied to a command line: `given` tells whether an
+    /// option, by its long name, was given there, and then the preset
+    /// leaves it alone.
+    pub fn apply(&self, given: impl Fn(&str) -> bool) -> AppliedPreset {
+        let mut applied = AppliedPreset {
+            name: self.name.clone(),
+            args: Vec::new(),
+            overridden: Vec::new(),
+        };
+        for (option, setting) in &self.options {
+            let flag = format!("--{}", option);
+            if given(option) {
+                applied.overridden.push(flag);
+                continue;
+            }
+            match setting {
+                Setting::Flag => applied.args.push(flag),
+                Setting::Values(values) => {
+                    for value in values {
+                        applied.args.push(flag.clone());
+                        applied.args.push(value.clone());
+                    }
+                }
+            }
+        }
+        applied
+    }
+}
+
+/// What a preset did to a scan, as recorded in the plan and the report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedPreset {
+    /// The preset's name.
+    pub name: String,
+    /// The options it set, as command-line arguments.
+    pub args: Vec<String>,
+    /// Its options that were given on the command line too, which won.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overridden: Vec<String>,
+}
+
+impl fmt::Display for AppliedPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, shell_words::join(&self.args))?;
+        if !self.overridden.is_empty() {
+            write!(f, ", given instead: {}", self.overridden.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The built-in presets and the user's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Presets {
+    presets: Vec<Preset>,
+}
+
+impl Presets {
+    /// The built-in presets alone.
+    pub fn built_in() -> Self {
+        let presets = parse("built-in", BUILT_IN).expect("the built-in presets are valid");
+        Presets { presets }
+    }
+
+    /// The built-in presets and those of the file at `user`, if there is
+    /// one; a missing file is no error.
+    pub fn load(user: Option<&Path>) -> Result<Self, PresetError> {
+        let mut presets = Self::built_in();
+        let Some(path) = user else {
+            return Ok(presets);
+        };
+        let name = path.display().to_string();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(presets),
+            Err(source) => return Err(PresetError::Read { path: name, source }),
+        };
+        presets.extend(parse(&name, &text)?);
+        Ok(presets)
+    }
+
+    /// Adds `more`, replacing presets of the same name.
+    pub fn extend(&mut self, more: Vec<Preset>) {
+        for preset in more {
+            match self.presets.iter_mut().find(|p| p.name == preset.name) {
+                Some(known) => *known = preset,
+                None => self.presets.push(preset),
+            }
+        }
+    }
+
+    /// The preset called `name`.
+    pub fn get(&self, name: &str) -> Result<&Preset, PresetError> {
+        self.presets
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| PresetError::Unknown {
+                name: name.to_string(),
+                known: self
+                    .presets
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+    }
+
+    /// Every preset, built-in ones first.
+    pub fn iter(&self) -> impl Iterator<Item = &Preset> {
+        self.presets.iter()
+    }
+}
+
+impl fmt::Display for Presets {
+    /// The `--list-presets` table: each preset with its description and
+    /// options.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.presets.iter().map(|p| p.name.len()).max().unwrap_or(0);
+        for p in &self.presets {
+            let source = if p.source == "built-in" {
+                String::new()
+            } else {
+                format!(" (from {})", p.source)
+            };
+            writeln!(f, "{:<width$}  {}{}", p.name, p.description, source)?;
+            let args = p.apply(|_| false).args;
+            writeln!(f, "{:<width$}  {}", "", shell_words::join(&args))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the user's presets are kept:
+/// `$XDG_CONFIG_HOME/port-scanner/presets.toml`, or under `~/.config`.
+pub fn user_file() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("port-scanner").join("presets.toml"))
+}
+
+/// Parses the presets of a TOML file; `path` appears in errors.
+fn parse(path: &str, text: &str) -> Result<Vec<Preset>, PresetError> {
+    let doc = Document::parse(text).map_err(|source| PresetError::Parse {
+        path: path.to_string(),
+        source,
+    })?;
+    let mut presets = Vec::new();
+    for (name, table) in doc.iter() {
+        let error = |key: &str, reason: &str| PresetError::Option {
+            path: path.to_string(),
+            preset: name.to_string(),
+            key: key.to_string(),
+            reason: reason.to_string(),
+        };
+        let table = table
+            .as_table_like()
+            .ok_or_else(|| error(name, "expected a table of options"))?;
+        let mut preset = Preset {
+            name: name.to_string(),
+            description: String::new(),
+            source: path.to_string(),
+            options: Vec::new(),
+        };
+        for (key, item) in table.iter() {
+            let option = key.replace('_', "-");
+            if option == "preset" {
+                return Err(error(key, "presets can't name other presets"));
+            }
+            let values = match item {
+                Item::Value(Value::Array(array)) => {
+                    array.iter().map(scalar).collect::<Option<Vec<_>>>()
+                }
+                Item::Value(value) => scalar(value).map(|v| vec![v]),
+                _ => None,
+            }
+            .ok_or_else(|| error(key, "expected a string, number, boolean or list of them"))?;
+            if option == "description" {
+                preset.description = values.join(" ");
+                continue;
+            }
+            let setting = match item.as_bool() {
+                Some(true) => Setting::Flag,
+                Some(false) => continue,
+                None => Setting::Values(values),
+            };
+            preset.options.push((option, setting));
+        }
+        presets.push(preset);
+    }
+    Ok(presets)
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        Value::Boolean(b) => Some(b.value().to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(name: &str) -> Vec<String> {
+        Presets::built_in().get(name).unwrap().apply(|_| false).args
+    }
+
+    #[test]
+    fn built_in_presets_resolve_to_their_documented_options() {
+        assert_eq!(
+            args("web-audit"),
+            [
+                "--ports",
+                "@web",
+                "--http-probe",
+                "--banner-timeout",
+                "2s",
+                "--probe-budget",
+                "5s"
+            ]
+        );
+        assert_eq!(
+            args("exposure-check"),
+            [
+                "--ports",
+                "1-1024,@remote,@db,@web",
+                "--fail-on",
+                "open:21,23,445,3389,5900"
+            ]
+        );
+        assert_eq!(
+            args("db-discovery"),
+            [
+                "--ports",
+                "@db",
+                "--http-probe",
+                "--banner-timeout",
+                "3s",
+                "--probe-budget",
+                "6s"
+            ]
+        );
+    }
+
+    #[test]
+    fn options_given_on_the_command_line_win() {
+        let presets = Presets::built_in();
+        let applied = presets
+            .get("web-audit")
+            .unwrap()
+            .apply(|option| option == "ports");
+        assert_eq!(
+            applied.args,
+            [
+                "--http-probe",
+                "--banner-timeout",
+                "2s",
+                "--probe-budget",
+                "5s"
+            ]
+        );
+        assert_eq!(applied.overridden, ["--ports"]);
+        assert_eq!(
+            applied.to_string(),
+            "web-audit (--http-probe --banner-timeout 2s --probe-budget 5s), given instead: --ports"
+        );
+    }
+
+    #[test]
+    fn user_presets_add_to_and_replace_built_in_ones() {
+        let mut presets = Presets::built_in();
+        let user = "[web-audit]\nports = \"80\"\n\n[nightly]\nfail_on = [\"open:23\", \"open:3389\"]\nquiet = true\nstats = false\n";
+        presets.extend(parse("user.toml", user).unwrap());
+
+        assert_eq!(
+            presets.get("web-audit").unwrap().apply(|_| false).args,
+            ["--ports", "80"]
+        );
+        let nightly = presets.get("nightly").unwrap();
+        assert_eq!(nightly.source, "user.toml");
+        assert_eq!(
+            nightly.apply(|_| false).args,
+            ["--fail-on", "open:23", "--fail-on", "open:3389", "--quiet"]
+        );
+        assert!(matches!(
+            presets.get("nope"),
+            Err(PresetError::Unknown { known, .. }) if known == "web-audit, exposure-check, db-discovery, nightly"
+        ));
+        assert!(parse("user.toml", "ports = \"80\"").is_err());
+        assert!(parse("user.toml", "[a]\npreset = \"b\"").is_err());
+    }
+}
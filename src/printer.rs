@@ -0,0 +1,217 @@
+use colored::*;
+use indicatif::MultiProgress;
+use port_scanner::console::{ConsoleMode, Layout};
+use port_scanner::{PortError, PortResult, PortStatus};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// How long a printed line may wait in the buffer.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Buffered lines that are written without waiting for the interval.
+const FLUSH_LINES: usize = 1024;
+
+/// Results on the console, written by a task of their own in batches so a
+/// scan printing every port isn't held up by the terminal, and with the
+/// progress bars hidden around each batch so lines never tear them.
+pub struct Printer {
+    mode: ConsoleMode,
+    layout: Layout,
+    collapse_runs: bool,
+    sender: mpsc::UnboundedSender<Message>,
+    task: JoinHandle<()>,
+}
+
+enum Message {
+    Line(String),
+    /// A port printed on its own, which ends any run around it.
+    Listed(IpAddr, u16),
+    /// A port with nothing to show but how it failed and how long that
+    /// took, left for its run.
+    Plain(IpAddr, u16, Class, u128),
+}
+
+/// What a port in a collapsed run shows: its status and connect error.
+#[derive(Clone, PartialEq)]
+struct Class(PortStatus, Option<PortError>);
+
+impl Printer {
+    /// Starts the printing task. With `collapse_runs`, detailed output holds
+    /// back closed and filtered ports with nothing else to show and prints
+    /// each run of consecutive ones that failed alike as a single line once
+    /// [`Printer::finish`] is called.
+    pub fn start(
+        multi: MultiProgress,
+        mode: ConsoleMode,
+        layout: Layout,
+        collapse_runs: bool,
+    ) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(print(receiver, multi, layout.clone()));
+        Printer {
+            mode,
+            layout,
+            collapse_runs: collapse_runs && mode == ConsoleMode::Detailed,
+            sender,
+            task,
+        }
+    }
+
+    /// Queues the console lines of `r`, if any.
+    pub fn print(&self, r: &PortResult) {
+        if self.collapse_runs {
+            if let Some(class) = plain(r) {
+                let _ = self
+                    .sender
+                    .send(Message::Plain(r.target, r.port, class, r.duration_ms));
+                return;
+            }
+            let _ = self.sender.send(Message::Listed(r.target, r.port));
+        }
+        let text = self.mode.print_result(r, &self.layout);
+        if !text.is_empty() {
+            let _ = self.sender.send(Message::Line(text));
+        }
+    }
+
+    /// Prints the collapsed runs and whatever is still buffered.
+    pub async fn finish(self) {
+        drop(self.sender);
+        let _ = self.task.await;
+    }
+}
+
+/// The class of a port that shows nothing but its status and error.
+fn plain(r: &PortResult) -> Option<Class> {
+    let bare = r.status != PortStatus::Open
+        && r.service.is_none()
+        && r.banner.is_none()
+        && r.product.is_none()
+        && r.findings.is_empty()
+        && !r.reset_on_read;
+    bare.then(|| Class(r.status.clone(), r.error.clone()))
+}
+
+async fn print(
+    mut receiver: mpsc::UnboundedReceiver<Message>,
+    multi: MultiProgress,
+    layout: Layout,
+) {
+    let mut console = Console::new(multi);
+    // Every port seen, by host, so runs end at listed or missing ports.
+    let mut ports: BTreeMap<IpAddr, BTreeMap<u16, Option<(Class, u128)>>> = BTreeMap::new();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Line(text)) => console.push(&text),
+                Some(Message::Listed(target, port)) => {
+                    ports.entry(target).or_default().insert(port, None);
+                }
+                Some(Message::Plain(target, port, class, duration_ms)) => {
+                    ports.entry(target).or_default().insert(port, Some((class, duration_ms)));
+                }
+                None => break,
+            },
+            _ = flush.tick() => console.flush(),
+        }
+    }
+    for (target, ports) in ports {
+        let mut run: Option<(u16, u16, Class, u128)> = None;
+        for (port, class) in ports {
+            if let (Some((_, end, current, _)), Some((class, _))) = (&mut run, &class) {
+                if *end + 1 == port && current == class {
+                    *end = port;
+                    continue;
+                }
+            }
+            if let Some(run) = run.take() {
+                console.push(&run_line(target, run, &layout));
+            }
+            run = class.map(|(class, duration_ms)| (port, port, class, duration_ms));
+        }
+        if let Some(run) = run {
+            console.push(&run_line(target, run, &layout));
+        }
+    }
+    console.flush();
+}
+
+/// The line of a collapsed run; a run of one port gets the usual line.
+fn run_line(
+    target: IpAddr,
+    (start, end, class, duration_ms): (u16, u16, Class, u128),
+    layout: &Layout,
+) -> String {
+    let Class(status, error) = class;
+    if start == end {
+        let mut r = PortResult::new(target, start, status);
+        r.error = error;
+        r.duration_ms = duration_ms;
+        return ConsoleMode::Detailed.print_result(&r, layout);
+    }
+    let ports = if layout.show_target {
+        format!("{}-{}", SocketAddr::new(target, start), end)
+    } else {
+        format!("{:>5}-{}", start, end)
+    };
+    let error = error.map_or(String::new(), |e| format!(" | Error: {}", e));
+    format!(
+        "{} | {} | {} ports{}\n",
+        ports.bright_blue(),
+        status,
+        u32::from(end - start) + 1,
+        error
+    )
+}
+
+/// Batched writes to stdout.
+struct Console {
+    multi: MultiProgress,
+    buffer: String,
+    lines: usize,
+    failed: bool,
+}
+
+impl Console {
+    fn new(multi: MultiProgress) -> Self {
+        Console {
+            multi,
+            buffer: String::new(),
+            lines: 0,
+            failed: false,
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        self.buffer.push_str(text);
+        self.lines += text.matches('\n').count();
+        if self.lines >= FLUSH_LINES {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        if !self.failed {
+            let written = self
+                .multi
+                .suspend(|| std::io::stdout().lock().write_all(self.buffer.as_bytes()));
+            if let Err(e) = written {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    warn!("Could not print the results: {}", e);
+                }
+                self.failed = true;
+            }
+        }
+        self.buffer.clear();
+        self.lines = 0;
+    }
+}
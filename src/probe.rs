@@ -0,0 +1,361 @@
+//! Follow-up probes of open ports, run by the scanner after the banner read
+//! (`--http-probe`).
+//!
+//! A port's probes take turns on as few connections as they can: the one
+//! the banner read leaves open goes to the first probe that applies, and on
+//! to the next for as long as it stays open. What a probe needs of the
+//! connection is its [`Transport`]: one that only needs an open stream
+//! shares it, while one that must be the first to speak, such as a TLS
+//! handshake, gets a new connection once anything has gone over the old
+//! one. All of a port's probes share one time budget
+//! ([`ScannerBuilder::probe_budget`](crate::scanner::ScannerBuilder::probe_budget)),
+//! and the connections they take are counted in the evidence and the
+//! stats.
+//!
+//! Within the budget each probe has a timeout of its own
+//! ([`ProbeTimeouts`], `--probe-timeout`), so that a slow or stuck probe
+//! can't spend the budget of the probes after it. A probe that times out
+//! or fails is recorded in the evidence and the stats, whatever it wrote
+//! on the result is undone, and the next probe runs: the port keeps what
+//! its connect and banner read found. Which types of probe run at all is
+//! a [`ProbeSelection`] (`--only-probe`, `--disable-probe`).
+
+use futures::future::BoxFuture;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::banner::{Banner, BANNER_CAP};
+use crate::connector::Connection;
+use crate::duration::parse_millis;
+use crate::scanner::{PortResult, PortStatus};
+use crate::service::detect_service_by;
+
+/// How long a probe may take without a `--probe-timeout` for it: a third
+/// of the default probe budget, so that the default ladder fits.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The types of probe there are, as `--probe-timeout`, `--only-probe` and
+/// `--disable-probe` name them.
+pub const KINDS: [&str; 3] = ["http", "tls", "crlf"];
+
+/// What a [`Probe`] needs of the connection it is handed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Any open connection, whatever the service or earlier probes sent
+    /// over it.
+    Shared,
+    /// A connection nothing has gone over yet in either direction.
+    Fresh,
+}
+
+/// What came of a [`Probe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probed {
+    /// What the probe found, for the evidence; none when the service didn't
+    /// answer the way the probe hoped.
+    pub found: Option<String>,
+    /// Whether the connection can carry another probe.
+    pub keep: bool,
+}
+
+/// A follow-up probe of open ports.
+pub trait Probe: Send + Sync {
+    /// A short name for logs and the evidence, such as `http`.
+    fn name(&self) -> &'static str;
+
+    /// The type of the probe, by which it is given a timeout and selected,
+    /// such as `tls` for both a TLS probe and the ladder's TLS rung; its
+    /// name unless it says otherwise.
+    fn kind(&self) -> &'static str {
+        self.name()
+    }
+
+    /// What the probe needs of its connection.
+    fn transport(&self) -> Transport;
+
+    /// Whether the probe has anything to learn about `r`, as the banner
+    /// read and the probes before it left it.
+    fn applies(&self, r: &PortResult) -> bool;
+
+    /// Probes the port of `r` over `stream` and records what it learns on
+    /// `r`. The scanner bounds the call by the port's probe budget.
+    fn run<'a>(
+        &'a self,
+        stream: &'a mut Connection,
+        r: &'a mut PortResult,
+    ) -> BoxFuture<'a, io::Result<Probed>>;
+}
+
+/// Checks that `name` is one of the [`KINDS`] of probe.
+pub fn parse_kind(name: &str) -> Result<String, String> {
+    let kind = name.trim().to_ascii_lowercase();
+    if KINDS.contains(&kind.as_str()) {
+        Ok(kind)
+    } else {
+        Err(format!(
+            "unknown probe '{}' (valid probes: {})",
+            name.trim(),
+            KINDS.join(", ")
+        ))
+    }
+}
+
+/// Timeouts for each type of probe, from `--probe-timeout 2s,tls:5s`: a
+/// bare duration for every probe, `KIND:DURATION` for one type.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeTimeouts {
+    default: Option<Duration>,
+    overrides: Vec<(String, Duration)>,
+}
+
+impl ProbeTimeouts {
+    /// No timeouts: every probe gets [`DEFAULT_PROBE_TIMEOUT`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timeout of every probe without one of its own.
+    pub fn set_default(&mut self, timeout: Duration) {
+        self.default = Some(timeout);
+    }
+
+    /// Sets the timeout of the probes of type `kind`; the last one set
+    /// wins.
+    pub fn insert(&mut self, kind: &str, timeout: Duration) {
+        self.overrides.push((kind.to_string(), timeout));
+    }
+
+    /// The timeout of a probe of type `kind`.
+    pub fn resolve(&self, kind: &str) -> Duration {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|(k, _)| k == kind)
+            .map(|&(_, timeout)| timeout)
+            .or(self.default)
+            .unwrap_or(DEFAULT_PROBE_TIMEOUT)
+    }
+
+    /// Whether any timeout is zero.
+    pub fn has_zero(&self) -> bool {
+        self.default.is_some_and(|t| t.is_zero()) || self.overrides.iter().any(|(_, t)| t.is_zero())
+    }
+}
+
+impl FromStr for ProbeTimeouts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut timeouts = ProbeTimeouts::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once(':') {
+                Some((kind, timeout)) => {
+                    let kind = parse_kind(kind)?;
+                    let timeout = parse_millis(timeout).map_err(|e| e.to_string())?;
+                    timeouts.insert(&kind, timeout);
+                }
+                None => {
+                    let timeout = parse_millis(entry).map_err(|e| e.to_string())?;
+                    timeouts.set_default(timeout);
+                }
+            }
+        }
+        Ok(timeouts)
+    }
+}
+
+impl fmt::Display for ProbeTimeouts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let default = self.default.map(|t| format!("{}ms", t.as_millis()));
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|(kind, t)| format!("{}:{}ms", kind, t.as_millis()));
+        let entries: Vec<String> = default.into_iter().chain(overrides).collect();
+        f.write_str(&entries.join(","))
+    }
+}
+
+/// Which types of probe a scan runs: those of `--only-probe`, when given,
+/// less those of `--disable-probe`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeSelection {
+    /// The only types to run; every type when empty.
+    pub only: Vec<String>,
+    /// The types never to run.
+    pub disabled: Vec<String>,
+}
+
+impl ProbeSelection {
+    /// Whether probes of type `kind` run.
+    pub fn allows(&self, kind: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|k| k == kind))
+            && !self.disabled.iter().any(|k| k == kind)
+    }
+}
+
+/// Asks open ports that stayed silent for `/` over HTTP, so that web
+/// servers, which never speak first, get their service and product from the
+/// response's status line and headers. The response head becomes the
+/// port's banner.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HttpProbe;
+
+impl Probe for HttpProbe {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn transport(&self) -> Transport {
+        Transport::Shared
+    }
+
+    fn applies(&self, r: &PortResult) -> bool {
+        r.status == PortStatus::Open && r.banner.is_none() && !r.reset_on_read
+    }
+
+    fn run<'a>(
+        &'a self,
+        stream: &'a mut Connection,
+        r: &'a mut PortResult,
+    ) -> BoxFuture<'a, io::Result<Probed>> {
+        Box::pin(async move {
+            stream.write_all(http_get(r).as_bytes()).await?;
+            let head = read_head(stream).await?;
+            // The body is left unread, so the connection goes no further.
+            let done = Probed {
+                found: None,
+                keep: false,
+            };
+            let text = String::from_utf8_lossy(&head);
+            if !text.starts_with("HTTP/") {
+                return Ok(done);
+            }
+            let (info, _) = detect_service_by(r.port, Some(text.as_ref()));
+            r.service = info.service.or(Some("HTTP".to_string()));
+            r.product = info.product;
+            r.version = info.version;
+            r.extra_info = info.extra_info;
+            r.cpe = info.cpe;
+            let status = text.lines().next().unwrap_or_default().to_string();
+            r.banner = Some(Banner::from(text.trim_end()));
+            Ok(Probed {
+                found: Some(status),
+                ..done
+            })
+        })
+    }
+}
+
+/// The request for `/` the HTTP probes send to the port of `r`.
+pub(crate) fn http_get(r: &PortResult) -> String {
+    format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nUser-Agent: port-scanner\r\nAccept: */*\r\n\r\n",
+        SocketAddr::new(r.target, r.port)
+    )
+}
+
+/// Reads a response head: up to the blank line that ends it, the end of the
+/// stream or [`BANNER_CAP`] bytes, whichever comes first.
+pub(crate) async fn read_head(stream: &mut Connection) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while head.len() < BANNER_CAP {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            head.truncate(end);
+            break;
+        }
+    }
+    head.truncate(BANNER_CAP);
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_http_probe_reads_the_response_head() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut stream: Connection = Box::new(client);
+        let serve = tokio::spawn(async move {
+            let mut request = vec![0; 1024];
+            let n = server.read(&mut request).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 200 OK\r\nServer: nginx/1.18.0\r\n\r\n<html>")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), 8080, PortStatus::Open);
+        assert!(HttpProbe.applies(&r));
+
+        let probed = HttpProbe.run(&mut stream, &mut r).await.unwrap();
+        assert_eq!(
+            probed,
+            Probed {
+                found: Some("HTTP/1.1 200 OK".to_string()),
+                keep: false
+            }
+        );
+        assert!(serve
+            .await
+            .unwrap()
+            .starts_with("GET / HTTP/1.1\r\nHost: 10.0.0.1:8080\r\n"));
+        assert_eq!(r.service.as_deref(), Some("HTTP"));
+        assert_eq!(r.product_version().as_deref(), Some("nginx 1.18.0"));
+        assert_eq!(
+            r.banner_str().as_deref(),
+            Some("HTTP/1.1 200 OK\r\nServer: nginx/1.18.0")
+        );
+        assert!(!HttpProbe.applies(&r));
+    }
+
+    #[test]
+    fn probes_get_the_timeout_of_their_kind() {
+        let ms = Duration::from_millis;
+        let timeouts: ProbeTimeouts = "tls:5s, 2s, http:250, tls:4s".parse().unwrap();
+        assert_eq!(timeouts.resolve("tls"), ms(4000));
+        assert_eq!(timeouts.resolve("http"), ms(250));
+        assert_eq!(timeouts.resolve("crlf"), ms(2000));
+        assert_eq!(ProbeTimeouts::new().resolve("tls"), DEFAULT_PROBE_TIMEOUT);
+        assert_eq!(
+            timeouts.to_string(),
+            "2000ms,tls:5000ms,http:250ms,tls:4000ms"
+        );
+        assert!(!timeouts.has_zero());
+        assert!("tls:0".parse::<ProbeTimeouts>().unwrap().has_zero());
+        assert_eq!(
+            "jarm:5s".parse::<ProbeTimeouts>(),
+            Err("unknown probe 'jarm' (valid probes: http, tls, crlf)".to_string())
+        );
+    }
+
+    #[test]
+    fn only_and_disabled_probes_narrow_the_selection() {
+        let all = ProbeSelection::default();
+        assert!(KINDS.iter().all(|k| all.allows(k)));
+        let only_tls = ProbeSelection {
+            only: vec!["tls".to_string()],
+            disabled: Vec::new(),
+        };
+        assert!(only_tls.allows("tls"));
+        assert!(!only_tls.allows("http"));
+        let no_http = ProbeSelection {
+            only: Vec::new(),
+            disabled: vec!["http".to_string(), "crlf".to_string()],
+        };
+        assert!(no_http.allows("tls"));
+        assert!(!no_http.allows("http"));
+        assert_eq!(parse_kind(" TLS ").as_deref(), Ok("tls"));
+    }
+}
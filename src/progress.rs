@@ -0,0 +1,544 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::metrics::ScanMetrics;
+use port_scanner::plan::TargetSet;
+
+/// Hosts beyond this many share a single aggregate bar.
+const MAX_HOST_BARS: usize = 8;
+
+const TICK: Duration = Duration::from_millis(250);
+
+/// Console progress reporting: bars on a terminal, periodic log lines otherwise.
+pub struct Progress {
+    bars: Option<Bars>,
+    ticker: JoinHandle<()>,
+    /// Why the scan is paused, shown in place of the rate while it is.
+    paused: Arc<Mutex<Option<String>>>,
+}
+
+struct Bars {
+    total: ProgressBar,
+    hosts: HashMap<IpAddr, ProgressBar>,
+    others: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Draws on `multi`, which the log writer suspends around every line.
+    pub fn new(
+        multi: MultiProgress,
+        targets: &TargetSet,
+        ports_per_host: u64,
+        metrics: Arc<ScanMetrics>,
+        status_interval: Duration,
+    ) -> Self {
+        let host_count = u64::try_from(targets.len()).unwrap_or(u64::MAX);
+        let total_ports = ports_per_host.saturating_mul(host_count);
+        let paused = Arc::new(Mutex::new(None));
+        if !std::io::stderr().is_terminal() {
+            let ticker = tokio::spawn(log_status(
+                metrics,
+                total_ports,
+                status_interval,
+                paused.clone(),
+            ));
+            return Progress {
+                bars: None,
+                ticker,
+                paused,
+            };
+        }
+
+        let total = multi.add(ProgressBar::new(total_ports).with_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+                .unwrap()
+                .progress_chars("#>-"),
+        ));
+
+        let mut hosts = HashMap::new();
+        let mut others = None;
+        if host_count > 1 {
+            let host_style = ProgressStyle::default_bar()
+                .template("  {prefix:>39} [{bar:40.cyan/blue}] {pos}/{len}")
+                .unwrap()
+                .progress_chars("#>-");
+            for target in targets.iter().take(MAX_HOST_BARS) {
+                let bar = multi.add(
+                    ProgressBar::new(ports_per_host)
+                        .with_style(host_style.clone())
+                        .with_prefix(target.to_string()),
+                );
+                hosts.insert(target, bar);
+            }
+            let rest = host_count.saturating_sub(MAX_HOST_BARS as u64);
+            if rest > 0 {
+                others = Some(
+                    multi.add(
+                        ProgressBar::new(ports_per_host.saturating_mul(rest))
+                            .with_style(host_style)
+                            .with_prefix(format!("{} more hosts", rest)),
+                    ),
+                );
+            }
+        }
+
+        let ticker = tokio::spawn(update_message(total.clone(), metrics, paused.clone()));
+        Progress {
+            bars: Some(Bars {
+                total,
+                hosts,
+                others,
+            }),
+            ticker,
+            paused,
+        }
+    }
+
+    /// Shows the scan as paused for `reason`, or running again with none.
+    pub fn set_paused(&self, reason: Option<String>) {
+        *self.paused.lock().unwrap() = reason;
+    }
+
+    /// Advances the total bar and, when known, the bar of the result's host.
+    pub fn inc(&self, target: Option<IpAddr>) {
+        if let Some(bars) = &self.bars {
+            bars.total.inc(1);
+            let Some(target) = target else {
+                return;
+            };
+            match bars.hosts.get(&target) {
+                Some(bar) => bar.inc(1),
+                None => {
+                    if let Some(bar) = &bars.others {
+                        bar.inc(1);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn finish(self, interrupted: bool) {
+        self.ticker.abort();
+        if let Some(bars) = self.bars {
+            let host_bars = bars.hosts.values().chain(bars.others.iter());
+            for bar in host_bars {
+                if interrupted {
+                    bar.abandon();
+                } else {
+                    bar.finish();
+                }
+            }
+            if interrupted {
+                bars.total.abandon_with_message("Scan interrupted");
+            } else {
+                bars.total.finish_with_message("Scan completed");
+            }
+        }
+    }
+}
+
+/// Log output that hides the progress bars while a line is written, so
+/// events from concurrent probes never tear a bar.
+#[derive(Clone)]
+pub struct LogWriter {
+    multi: MultiProgress,
+}
+
+impl LogWriter {
+    pub fn new(multi: MultiProgress) -> Self {
+        LogWriter { multi }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LogLine;
+
+    fn make_writer(&'a self) -> LogLine {
+        LogLine {
+            multi: self.multi.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// One formatted event, written to stderr in a single piece when dropped.
+pub struct LogLine {
+    multi: MultiProgress,
+    buf: Vec<u8>,
+}
+
+impl Write for LogLine {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogLine {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.multi
+                .suspend(|| std::io::stderr().lock().write_all(&self.buf))
+                .ok();
+        }
+    }
+}
+
+/// Where `--progress-json` events go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressSink {
+    /// A file descriptor inherited from the parent, e.g. `3`.
+    Fd(i32),
+    /// A file, created or truncated, or a FIFO.
+    Path(PathBuf),
+}
+
+impl FromStr for ProgressSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("expected a file descriptor or a path".to_string());
+        }
+        if !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(ProgressSink::Path(PathBuf::from(s)));
+        }
+        match s.parse::<i32>() {
+            Ok(fd) if fd > 2 => Ok(ProgressSink::Fd(fd)),
+            _ => Err(format!(
+                "'{}' is not a usable file descriptor; pass one above 2, or ./{} for a file",
+                s, s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ProgressSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgressSink::Fd(fd) => write!(f, "file descriptor {}", fd),
+            ProgressSink::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Progress as JSON lines for orchestrators, written every interval from
+/// the same counters as the bars whether or not anything is shown on the
+/// console, and a last event once the reports are saved.
+pub struct JsonProgress {
+    hosts: Arc<Mutex<HostProgress>>,
+    done: oneshot::Sender<Finish>,
+    task: JoinHandle<()>,
+}
+
+/// Ports done per host, for the hosts still being scanned.
+struct HostProgress {
+    ports_per_host: u64,
+    total: u64,
+    completed: u64,
+    scanning: BTreeMap<IpAddr, u64>,
+}
+
+struct Finish {
+    interrupted: bool,
+    reports: Vec<PathBuf>,
+    summary: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Progress(Snapshot),
+    Finished {
+        #[serde(flatten)]
+        snapshot: Snapshot,
+        interrupted: bool,
+        /// The JSON report, if one was written.
+        summary: Option<PathBuf>,
+        reports: Vec<PathBuf>,
+    },
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    elapsed_ms: u64,
+    /// Ports through the connect stage, some of them still being probed.
+    connected: u64,
+    completed: u64,
+    total: u64,
+    open: u64,
+    closed: u64,
+    filtered: u64,
+    ports_per_second: f64,
+    eta_ms: Option<u64>,
+    hosts_completed: u64,
+    hosts_total: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hosts: Vec<HostSnapshot>,
+}
+
+#[derive(Serialize)]
+struct HostSnapshot {
+    target: IpAddr,
+    completed: u64,
+    total: u64,
+}
+
+impl JsonProgress {
+    /// Opens `sink`, waiting for a reader if it is a FIFO, and starts
+    /// writing an event every `every`.
+    pub async fn start(
+        sink: &ProgressSink,
+        targets: &TargetSet,
+        ports_per_host: u64,
+        metrics: Arc<ScanMetrics>,
+        every: Duration,
+    ) -> std::io::Result<Self> {
+        let file = match sink {
+            ProgressSink::Path(path) => tokio::fs::File::create(path).await?,
+            ProgressSink::Fd(fd) => tokio::fs::File::from_std(inherited_fd(*fd)?),
+        };
+        let hosts = Arc::new(Mutex::new(HostProgress {
+            ports_per_host,
+            total: u64::try_from(targets.len()).unwrap_or(u64::MAX),
+            completed: 0,
+            scanning: BTreeMap::new(),
+        }));
+        let (done, finish) = oneshot::channel();
+        let task = tokio::spawn(write_events(
+            file,
+            metrics,
+            hosts.clone(),
+            targets.len() > 1,
+            every,
+            finish,
+        ));
+        Ok(JsonProgress { hosts, done, task })
+    }
+
+    /// Counts a result of `target`.
+    pub fn inc(&self, target: IpAddr) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let per_host = hosts.ports_per_host;
+        let done = hosts.scanning.entry(target).or_default();
+        *done += 1;
+        if *done >= per_host {
+            hosts.scanning.remove(&target);
+            hosts.completed += 1;
+        }
+    }
+
+    /// Writes the last event, pointing at `summary` and the other `reports`.
+    pub async fn finish(self, interrupted: bool, summary: Option<PathBuf>, reports: Vec<PathBuf>) {
+        let finish = Finish {
+            interrupted,
+            reports,
+            summary,
+        };
+        if self.done.send(finish).is_ok() {
+            let _ = self.task.await;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inherited_fd(fd: i32) -> std::io::Result<std::fs::File> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: fcntl only checks that the descriptor is open; the parent
+    // handed it over for these events, so nothing else here owns it.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn inherited_fd(_fd: i32) -> std::io::Result<std::fs::File> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "file descriptors are only supported on Unix; pass a path",
+    ))
+}
+
+async fn write_events(
+    mut file: tokio::fs::File,
+    metrics: Arc<ScanMetrics>,
+    hosts: Arc<Mutex<HostProgress>>,
+    multi_target: bool,
+    every: Duration,
+    mut finish: oneshot::Receiver<Finish>,
+) {
+    let started = Instant::now();
+    let total = {
+        let hosts = hosts.lock().unwrap_or_else(|e| e.into_inner());
+        hosts.ports_per_host.saturating_mul(hosts.total)
+    };
+    let snapshot = |rate: f64| {
+        let hosts = hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let completed = metrics.scanned();
+        let left = total.saturating_sub(completed);
+        Snapshot {
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            connected: metrics.connected(),
+            completed,
+            total,
+            open: metrics.open(),
+            closed: metrics.closed(),
+            filtered: metrics.filtered(),
+            ports_per_second: rate,
+            eta_ms: (rate > 0.0).then(|| (left as f64 / rate * 1000.0) as u64),
+            hosts_completed: hosts.completed,
+            hosts_total: hosts.total,
+            hosts: if multi_target {
+                hosts
+                    .scanning
+                    .iter()
+                    .map(|(&target, &completed)| HostSnapshot {
+                        target,
+                        completed,
+                        total: hosts.ports_per_host,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    };
+    let mut rate = RateMeter::new(metrics.scanned());
+    let mut interval = tokio::time::interval(every);
+    loop {
+        let (event, last) = tokio::select! {
+            _ = interval.tick() => (Event::Progress(snapshot(rate.update(metrics.scanned()))), false),
+            f = &mut finish => {
+                // Dropped without finishing: the scan failed.
+                let Ok(f) = f else {
+                    return;
+                };
+                let mut snapshot = snapshot(metrics.ports_per_second());
+                snapshot.eta_ms = None;
+                let event = Event::Finished {
+                    snapshot,
+                    interrupted: f.interrupted,
+                    summary: f.summary,
+                    reports: f.reports,
+                };
+                (event, true)
+            }
+        };
+        let mut line = serde_json::to_vec(&event).unwrap_or_default();
+        line.push(b'\n');
+        let written = async {
+            file.write_all(&line).await?;
+            file.flush().await
+        };
+        if let Err(e) = written.await {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                warn!("Stopped writing --progress-json: {}", e);
+            }
+            return;
+        }
+        if last {
+            return;
+        }
+    }
+}
+
+/// Keeps the bar message showing how many ports are connected, the open
+/// count and the current rate, or why the scan is paused. The bar itself
+/// counts the ports done, probes and all.
+async fn update_message(
+    bar: ProgressBar,
+    metrics: Arc<ScanMetrics>,
+    paused: Arc<Mutex<Option<String>>>,
+) {
+    let mut rate = RateMeter::new(metrics.scanned());
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let rate = rate.update(metrics.scanned());
+        let counts = format!(
+            "connected: {}, open: {}",
+            metrics.connected(),
+            metrics.open()
+        );
+        match paused.lock().unwrap().as_deref() {
+            Some(reason) => bar.set_message(format!("{}, {}", counts, reason)),
+            None => bar.set_message(format!("{}, {:.0} ports/s", counts, rate)),
+        }
+    }
+}
+
+/// Logs a one-line status every `every` when there is no terminal to draw on.
+async fn log_status(
+    metrics: Arc<ScanMetrics>,
+    total: u64,
+    every: Duration,
+    paused: Arc<Mutex<Option<String>>>,
+) {
+    let mut rate = RateMeter::new(metrics.scanned());
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let scanned = metrics.scanned();
+        let rate = rate.update(scanned);
+        let pace = match paused.lock().unwrap().as_deref() {
+            Some(reason) => reason.to_string(),
+            None => format!("{:.0} ports/s", rate),
+        };
+        info!(
+            "Progress: {}/{} ports connected, {} probed ({:.1}%), {} open, {}",
+            metrics.connected(),
+            total,
+            scanned,
+            scanned as f64 * 100.0 / total.max(1) as f64,
+            metrics.open(),
+            pace
+        );
+    }
+}
+
+/// Instantaneous rate from successive counter samples.
+struct RateMeter {
+    last: u64,
+    at: Instant,
+}
+
+impl RateMeter {
+    fn new(start: u64) -> Self {
+        RateMeter {
+            last: start,
+            at: Instant::now(),
+        }
+    }
+
+    fn update(&mut self, now: u64) -> f64 {
+        let elapsed = self.at.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            now.saturating_sub(self.last) as f64 / elapsed
+        } else {
+            0.0
+        };
+        self.last = now;
+        self.at = Instant::now();
+        rate
+    }
+}
@@ -0,0 +1,574 @@
+//! Open proxy checks (`--proxy-check`): whether an HTTP or SOCKS5 service on
+//! a proxy port relays connections for anyone.
+//!
+//! The check asks the proxy to reach back to a listener of the scanner's own,
+//! bound to an ephemeral port for the length of the scan, at the address the
+//! proxy sees the scanner connect from. A proxy that delivers the request,
+//! recognized by a token unique to each check, is open. With a probe URL
+//! instead, a proxy that fetches it successfully counts as open.
+
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::evidence::{self, Evidence, Step};
+use crate::scanner::{PortResult, PortStatus};
+use crate::vuln::{Finding, Severity};
+
+/// Ports checked unless told otherwise.
+pub const DEFAULT_PORTS: &[u16] = &[1080, 3128, 8080, 8888];
+
+/// Id of the finding an open proxy gets.
+pub const FINDING_ID: &str = "OPEN-PROXY";
+
+/// What a proxy is asked to fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl ProbeUrl {
+    /// Parses a plain `http://host[:port]/path` URL.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid = || format!("'{}' is not an http:// URL", url);
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        // An IPv6 address is bracketed: [2001:db8::1]:8080.
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => bracketed.split_once(']').ok_or_else(invalid)?,
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port),
+                None => (authority, ""),
+            },
+        };
+        let port = match port.strip_prefix(':').unwrap_or(port) {
+            "" => 80,
+            port => port.parse().map_err(|_| invalid())?,
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ProbeUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    fn to_url(&self) -> String {
+        match self.host.parse::<Ipv6Addr>() {
+            Ok(_) => format!("http://[{}]:{}{}", self.host, self.port, self.path),
+            Err(_) => format!("http://{}:{}{}", self.host, self.port, self.path),
+        }
+    }
+}
+
+/// The scanner's own listener that proxies are asked to reach. It stops
+/// when dropped.
+struct Callback {
+    port: u16,
+    seen: Arc<Mutex<HashSet<String>>>,
+    arrived: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl Callback {
+    /// Listens on an ephemeral port of every address, IPv6 too where the
+    /// system allows.
+    async fn start() -> io::Result<Self> {
+        let listener = match dual_stack() {
+            Ok(listener) => listener,
+            Err(e) => {
+                debug!("No IPv6 callback listener: {}", e);
+                TcpListener::bind((IpAddr::from([0, 0, 0, 0]), 0)).await?
+            }
+        };
+        let port = listener.local_addr()?.port();
+        let seen: Arc<Mutex<HashSet<String>>> = Arc::default();
+        let arrived: Arc<Notify> = Arc::default();
+        let (record, notify) = (seen.clone(), arrived.clone());
+        let task = tokio::spawn(async move {
+            while let Ok((mut stream, from)) = listener.accept().await {
+                let (record, notify) = (record.clone(), notify.clone());
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 2048];
+                    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf));
+                    let Ok(Ok(n)) = read.await else {
+                        return;
+                    };
+                    let text = String::from_utf8_lossy(&buf[..n]);
+                    let Some(token) = find_token(&text) else {
+                        return;
+                    };
+                    debug!("Proxy check callback from {}: {}", from, token);
+                    record.lock().unwrap().insert(token.to_string());
+                    notify.notify_waiters();
+                    let _ = stream
+                        .write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                        .await;
+                });
+            }
+        });
+        Ok(Callback {
+            port,
+            seen,
+            arrived,
+            task,
+        })
+    }
+
+    /// Waits up to `timeout` for a connection carrying `token`.
+    async fn wait_for(&self, token: &str, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                let arrived = self.arrived.notified();
+                if self.seen.lock().unwrap().remove(token) {
+                    return;
+                }
+                arrived.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+}
+
+impl Drop for Callback {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn dual_stack() -> io::Result<TcpListener> {
+    use socket2::{Domain, Socket, Type};
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+    socket.listen(128)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+const TOKEN_PREFIX: &str = "port-scanner-proxy-check-";
+
+fn find_token(text: &str) -> Option<&str> {
+    let start = text.find(TOKEN_PREFIX)?;
+    let token = &text[start..];
+    let end = token
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(token.len());
+    Some(&token[..end])
+}
+
+/// What a check found out about a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// It speaks the protocol and relayed the request.
+    Open,
+    /// It speaks the protocol but refused, or asked for credentials.
+    Closed,
+    /// It doesn't speak the protocol.
+    NotProxy,
+}
+
+/// Runs the checks of a scan.
+pub struct ProxyChecker {
+    ports: Vec<u16>,
+    url: Option<ProbeUrl>,
+    timeout: Duration,
+    callback: Option<Callback>,
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl ProxyChecker {
+    /// Checks open `ports`, waiting up to `timeout` for each step. Without a
+    /// `url`, starts the callback listener.
+    pub async fn start(
+        ports: Vec<u16>,
+        url: Option<ProbeUrl>,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let callback = match url {
+            Some(_) => None,
+            None => Some(Callback::start().await?),
+        };
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        Ok(ProxyChecker {
+            ports,
+            url,
+            timeout,
+            callback,
+            prefix: format!("{}{:x}-{:x}", TOKEN_PREFIX, millis, std::process::id()),
+            next: AtomicU64::new(0),
+        })
+    }
+
+    /// The port of the callback listener, if one is running.
+    pub fn callback_port(&self) -> Option<u16> {
+        self.callback.as_ref().map(|c| c.port)
+    }
+
+    /// True for open ports that should be checked.
+    pub fn applies(&self, r: &PortResult) -> bool {
+        r.status == PortStatus::Open && self.ports.contains(&r.port)
+    }
+
+    /// Checks the port of `r` for SOCKS5, then HTTP, and records what it
+    /// found: `open_proxy`, the service, and a finding for an open proxy;
+    /// also a step of its evidence, when the scan recorded any.
+    pub async fn check(&self, r: &mut PortResult) {
+        let addr = SocketAddr::new(r.target, r.port);
+        let started = std::time::Instant::now();
+        let recorded = !r.evidence.is_empty();
+        for (service, summary) in [
+            ("SOCKS5", "SOCKS5 proxy relays connections for anyone"),
+            ("HTTP", "HTTP proxy relays requests for anyone"),
+        ] {
+            let token = format!(
+                "{}-{}",
+                self.prefix,
+                self.next.fetch_add(1, Ordering::Relaxed)
+            );
+            let check = async {
+                let mut stream = TcpStream::connect(addr).await?;
+                match service {
+                    "SOCKS5" => self.socks5(&mut stream, &token).await,
+                    _ => self.http(&mut stream, &token).await,
+                }
+            };
+            let outcome = match tokio::time::timeout(self.timeout * 2, check).await {
+                Ok(Ok(outcome)) => outcome,
+                Ok(Err(e)) => {
+                    debug!("{} check of {}: {}", service, addr, e);
+                    Outcome::NotProxy
+                }
+                Err(_) => Outcome::NotProxy,
+            };
+            if outcome == Outcome::NotProxy {
+                continue;
+            }
+            if recorded {
+                let detail = if outcome == Outcome::Open {
+                    format!("{} proxy relays for anyone", service)
+                } else {
+                    format!("{} proxy, refuses to relay", service)
+                };
+                r.evidence.push(
+                    Evidence::new(Step::ProxyCheck, evidence::Outcome::Matched)
+                        .elapsed(started.elapsed())
+                        .detail(detail),
+                );
+            }
+            r.service = Some(service.to_string());
+            r.open_proxy = Some(outcome == Outcome::Open);
+            if outcome == Outcome::Open {
+                warn!("Open proxy: {} ({})", addr, service);
+                r.findings.push(Finding {
+                    id: FINDING_ID.to_string(),
+                    severity: Severity::High,
+                    summary: summary.to_string(),
+                });
+            }
+            return;
+        }
+        if recorded {
+            r.evidence.push(
+                Evidence::new(Step::ProxyCheck, evidence::Outcome::NoMatch)
+                    .elapsed(started.elapsed())
+                    .detail("neither a SOCKS5 nor an HTTP proxy"),
+            );
+        }
+    }
+
+    /// Where the proxy should connect: the probe URL, or the callback
+    /// listener at the address the proxy sees us at.
+    fn destination(&self, stream: &TcpStream, token: &str) -> io::Result<ProbeUrl> {
+        match (&self.url, &self.callback) {
+            (Some(url), _) => Ok(url.clone()),
+            (None, Some(callback)) => Ok(ProbeUrl {
+                host: stream.local_addr()?.ip().to_canonical().to_string(),
+                port: callback.port,
+                path: format!("/{}", token),
+            }),
+            (None, None) => unreachable!("a checker without a URL has a callback"),
+        }
+    }
+
+    /// Whether the request went through: the callback saw the token, or the
+    /// probe URL answered with a success `status`.
+    async fn relayed(&self, token: &str, status: Option<u16>) -> Outcome {
+        let success = status.is_some_and(|s| (200..300).contains(&s));
+        let relayed = match &self.callback {
+            // A proxy that refused has nothing more to deliver.
+            Some(callback) => {
+                let wait = if success {
+                    self.timeout
+                } else {
+                    Duration::ZERO
+                };
+                callback.wait_for(token, wait).await
+            }
+            None => success,
+        };
+        if relayed {
+            Outcome::Open
+        } else {
+            Outcome::Closed
+        }
+    }
+
+    async fn http(&self, stream: &mut TcpStream, token: &str) -> io::Result<Outcome> {
+        let dest = self.destination(stream, token)?;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: port-scanner proxy check\r\n\
+             X-Proxy-Check: {}\r\nConnection: close\r\n\r\n",
+            dest.to_url(),
+            dest.host,
+            dest.port,
+            token
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut buf = [0u8; 512];
+        let n = tokio::time::timeout(self.timeout, stream.read(&mut buf))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let head = String::from_utf8_lossy(&buf[..n]);
+        let Some(status) = http_status(&head) else {
+            return Ok(Outcome::NotProxy);
+        };
+        Ok(self.relayed(token, Some(status)).await)
+    }
+
+    async fn socks5(&self, stream: &mut TcpStream, token: &str) -> io::Result<Outcome> {
+        // Version 5, one method: no authentication.
+        stream.write_all(&[5, 1, 0]).await?;
+        let mut choice = [0u8; 2];
+        tokio::time::timeout(self.timeout, stream.read_exact(&mut choice))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        match choice {
+            [5, 0] => {}
+            // Wants credentials: a proxy, but not an open one.
+            [5, 0xff] => return Ok(Outcome::Closed),
+            _ => return Ok(Outcome::NotProxy),
+        }
+        let dest = self.destination(stream, token)?;
+        stream.write_all(&socks5_connect(&dest)).await?;
+        let mut reply = [0u8; 4];
+        tokio::time::timeout(self.timeout, stream.read_exact(&mut reply))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if reply[1] != 0 {
+            return Ok(Outcome::Closed);
+        }
+        let bound = match reply[3] {
+            1 => 4 + 2,
+            4 => 16 + 2,
+            _ => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize + 2
+            }
+        };
+        stream.read_exact(&mut vec![0u8; bound]).await?;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nX-Proxy-Check: {}\r\nConnection: close\r\n\r\n",
+            dest.path, dest.host, dest.port, token
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut buf = [0u8; 512];
+        let status = match tokio::time::timeout(self.timeout, stream.read(&mut buf)).await {
+            Ok(Ok(n)) => http_status(&String::from_utf8_lossy(&buf[..n])),
+            _ => None,
+        };
+        Ok(self.relayed(token, status).await)
+    }
+}
+
+/// A SOCKS5 CONNECT request for `dest`.
+fn socks5_connect(dest: &ProbeUrl) -> Vec<u8> {
+    let mut request = vec![5, 1, 0];
+    match dest.host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend(ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend(ip.octets());
+        }
+        Err(_) => {
+            request.push(3);
+            request.push(dest.host.len().min(255) as u8);
+            request.extend(dest.host.bytes().take(255));
+        }
+    }
+    request.extend(dest.port.to_be_bytes());
+    request
+}
+
+/// The status code of an HTTP response head.
+fn http_status(head: &str) -> Option<u16> {
+    let line = head.lines().next()?;
+    let rest = line.strip_prefix("HTTP/")?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(port: u16) -> PortResult {
+        PortResult::new("127.0.0.1".parse().unwrap(), port, PortStatus::Open)
+    }
+
+    /// A forwarding HTTP proxy for absolute-form requests, or one that
+    /// answers 403 to everything.
+    async fn http_proxy(forward: bool) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 2048];
+                    let n = client.read(&mut buf).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let Some(target) = request.strip_prefix("GET http://") else {
+                        return;
+                    };
+                    if !forward {
+                        let _ = client.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await;
+                        return;
+                    }
+                    let authority = target.split('/').next().unwrap();
+                    let mut upstream = TcpStream::connect(authority).await.unwrap();
+                    upstream.write_all(request.as_bytes()).await.unwrap();
+                    let mut answer = Vec::new();
+                    let _ = upstream.read_to_end(&mut answer).await;
+                    let _ = client.write_all(&answer).await;
+                });
+            }
+        });
+        port
+    }
+
+    /// A SOCKS5 server without authentication that tunnels to IPv4 hosts.
+    async fn socks5_proxy() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut hello = [0u8; 3];
+                    client.read_exact(&mut hello).await.unwrap();
+                    client.write_all(&[5, 0]).await.unwrap();
+                    let mut request = [0u8; 10];
+                    client.read_exact(&mut request).await.unwrap();
+                    assert_eq!(request[3], 1);
+                    let ip = IpAddr::from([request[4], request[5], request[6], request[7]]);
+                    let port = u16::from_be_bytes([request[8], request[9]]);
+                    let mut upstream = TcpStream::connect((ip, port)).await.unwrap();
+                    client
+                        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                        .await
+                        .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        port
+    }
+
+    async fn checker(port: u16) -> ProxyChecker {
+        ProxyChecker::start(vec![port], None, Duration::from_secs(2))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_forwarding_http_proxy_is_open() {
+        let port = http_proxy(true).await;
+        let mut r = open(port);
+        checker(port).await.check(&mut r).await;
+        assert_eq!(r.open_proxy, Some(true));
+        assert_eq!(r.service.as_deref(), Some("HTTP"));
+        assert_eq!(r.findings[0].id, FINDING_ID);
+        assert_eq!(r.findings[0].severity, Severity::High);
+    }
+
+    #[tokio::test]
+    async fn a_refusing_http_proxy_is_not_open() {
+        let port = http_proxy(false).await;
+        let mut r = open(port);
+        r.evidence
+            .push(Evidence::new(Step::TcpConnect, evidence::Outcome::Success));
+        checker(port).await.check(&mut r).await;
+        assert_eq!(r.open_proxy, Some(false));
+        assert!(r.findings.is_empty());
+        let step = r.evidence.last().unwrap();
+        assert_eq!(step.step, Step::ProxyCheck);
+        assert_eq!(step.detail.as_deref(), Some("HTTP proxy, refuses to relay"));
+    }
+
+    #[tokio::test]
+    async fn a_socks5_proxy_without_credentials_is_open() {
+        let port = socks5_proxy().await;
+        let mut r = open(port);
+        checker(port).await.check(&mut r).await;
+        assert_eq!(r.open_proxy, Some(true));
+        assert_eq!(r.service.as_deref(), Some("SOCKS5"));
+        assert_eq!(
+            r.findings[0].summary,
+            "SOCKS5 proxy relays connections for anyone"
+        );
+    }
+
+    #[tokio::test]
+    async fn other_services_are_left_alone() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut s, _)) = listener.accept().await {
+                let _ = s.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await;
+            }
+        });
+        let checker = checker(port).await;
+        let mut r = open(port);
+        assert!(checker.applies(&r));
+        checker.check(&mut r).await;
+        assert_eq!((r.open_proxy, r.service), (None, None));
+        assert!(!checker.applies(&open(port + 1)));
+    }
+
+    #[test]
+    fn probe_urls_are_plain_http() {
+        assert_eq!(
+            ProbeUrl::parse("http://example.com/ip").unwrap().to_url(),
+            "http://example.com:80/ip"
+        );
+        let v6 = ProbeUrl::parse("http://[2001:db8::1]:8000/").unwrap();
+        assert_eq!((v6.host.as_str(), v6.port), ("2001:db8::1", 8000));
+        assert_eq!(v6.to_url(), "http://[2001:db8::1]:8000/");
+        assert!(ProbeUrl::parse("https://example.com/").is_err());
+        assert!(ProbeUrl::parse("http://:80/").is_err());
+        assert_eq!(
+            socks5_connect(&ProbeUrl::parse("http://10.0.0.1:80/").unwrap()),
+            [5, 1, 0, 1, 10, 0, 0, 1, 0, 80]
+        );
+    }
+}
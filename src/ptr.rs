@@ -0,0 +1,253 @@
+//! `port-scanner ptr`: the names of the addresses of a range, from their
+//! PTR records, without touching the hosts themselves. Only the DNS
+//! servers are sent anything.
+//!
+//! Lookups run concurrently, up to a limit, and are listed in address
+//! order. Each address gets what came of its lookup, so that an address
+//! with no name (NXDOMAIN, or no PTR record) is told apart from one the
+//! servers failed on (SERVFAIL, REFUSED) or never answered for.
+
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::fmt;
+
+use crate::output::csv_field;
+use crate::plan::TargetSet;
+use crate::resolve::{Ptr, PtrStatus, Resolver};
+
+/// The most addresses one sweep looks up.
+pub const MAX_ADDRESSES: u128 = 1 << 20;
+
+/// The lookups of a sweep, in address order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Sweep {
+    /// One lookup per address.
+    pub lookups: Vec<Ptr>,
+}
+
+/// Looks up every address of `targets` with `resolver`, `concurrency` at
+/// a time.
+pub async fn sweep(resolver: &Resolver, targets: &TargetSet, concurrency: usize) -> Sweep {
+    let lookups = stream::iter(targets.iter())
+        .map(|address| resolver.reverse(address))
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+    Sweep { lookups }
+}
+
+impl Sweep {
+    /// How many lookups came to `status`.
+    pub fn count(&self, status: PtrStatus) -> usize {
+        self.lookups.iter().filter(|p| p.status == status).count()
+    }
+
+    /// One line per address and name, with a header; an address without a
+    /// name gets one line with the name empty.
+    pub fn csv(&self) -> String {
+        let mut out = String::from("address,name,status,server,message\n");
+        for p in &self.lookups {
+            let names = match p.names.as_slice() {
+                [] => vec![""],
+                names => names.iter().map(String::as_str).collect(),
+            };
+            for name in names {
+                out.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    p.address,
+                    csv_field(name),
+                    p.status,
+                    csv_field(&p.server),
+                    csv_field(p.message.as_deref().unwrap_or(""))
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// An aligned line per address, then the count of each outcome.
+impl fmt::Display for Sweep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .lookups
+            .iter()
+            .map(|p| p.address.to_string().len())
+            .max()
+            .unwrap_or(0);
+        for p in &self.lookups {
+            let what = match (p.status, &p.message) {
+                (PtrStatus::Found, _) => p.names.join(", "),
+                (status, Some(message)) => format!("({}: {})", status, message),
+                (status, None) => format!("({})", status),
+            };
+            writeln!(f, "{:<width$}  {}", p.address, what)?;
+        }
+        let counts: Vec<String> = [
+            PtrStatus::Found,
+            PtrStatus::NxDomain,
+            PtrStatus::NoRecord,
+            PtrStatus::ServFail,
+            PtrStatus::Refused,
+            PtrStatus::Timeout,
+            PtrStatus::Error,
+        ]
+        .into_iter()
+        .map(|status| (status, self.count(status)))
+        .filter(|&(_, n)| n > 0)
+        .map(|(status, n)| format!("{} {}", n, status))
+        .collect();
+        writeln!(
+            f,
+            "{} addresses: {}",
+            self.lookups.len(),
+            match counts.is_empty() {
+                true => "none".to_string(),
+                false => counts.join(", "),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::op::{Message, OpCode, ResponseCode};
+    use hickory_resolver::proto::rr::rdata::{PTR, SOA};
+    use hickory_resolver::proto::rr::{Name, RData, Record};
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    /// A DNS server answering the PTR queries of 10.9.0.1 to 10.9.0.6 each
+    /// its own way: a name, two names, NXDOMAIN, SERVFAIL, an empty answer
+    /// and no answer at all.
+    async fn stub_dns() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                let query = Message::from_vec(&buf[..n]).unwrap();
+                let mut reply = Message::response(query.metadata.id, OpCode::Query);
+                reply.add_queries(query.queries.clone());
+                let name = query.queries[0].name().clone();
+                let ptr = |host: &str| {
+                    let host = Name::from_ascii(host).unwrap();
+                    Record::from_rdata(name.clone(), 300, RData::PTR(PTR(host)))
+                };
+                let soa = || {
+                    let zone = Name::from_ascii("9.10.in-addr.arpa.").unwrap();
+                    let mname = Name::from_ascii("ns.lab.").unwrap();
+                    let rname = Name::from_ascii("admin.lab.").unwrap();
+                    let soa = SOA::new(mname, rname, 1, 60, 60, 60, 60);
+                    Record::from_rdata(zone, 60, RData::SOA(soa))
+                };
+                match name.to_ascii().as_str() {
+                    "1.0.9.10.in-addr.arpa." => {
+                        reply.add_answer(ptr("gw.lab."));
+                    }
+                    "2.0.9.10.in-addr.arpa." => {
+                        reply.add_answer(ptr("nas.lab."));
+                        reply.add_answer(ptr("files.lab."));
+                    }
+                    "3.0.9.10.in-addr.arpa." => {
+                        reply.metadata.response_code = ResponseCode::NXDomain;
+                        reply.add_authority(soa());
+                    }
+                    "4.0.9.10.in-addr.arpa." => {
+                        reply.metadata.response_code = ResponseCode::ServFail;
+                    }
+                    "5.0.9.10.in-addr.arpa." => {
+                        reply.add_authority(soa());
+                    }
+                    _ => continue,
+                }
+                let _ = socket.send_to(&reply.to_vec().unwrap(), from).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn every_kind_of_answer_is_told_apart() {
+        let server = stub_dns().await;
+        let resolver = Resolver::servers(&[server], Duration::from_millis(300));
+        let mut targets = TargetSet::new();
+        targets.insert("10.9.0.1-10.9.0.6").unwrap();
+
+        let sweep = sweep(&resolver, &targets, 4).await;
+
+        let found: Vec<(String, PtrStatus, Vec<String>)> = sweep
+            .lookups
+            .iter()
+            .map(|p| (p.address.to_string(), p.status, p.names.clone()))
+            .collect();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            found,
+            [
+                ("10.9.0.1".to_string(), PtrStatus::Found, names(&["gw.lab"])),
+                (
+                    "10.9.0.2".to_string(),
+                    PtrStatus::Found,
+                    names(&["nas.lab", "files.lab"])
+                ),
+                ("10.9.0.3".to_string(), PtrStatus::NxDomain, names(&[])),
+                ("10.9.0.4".to_string(), PtrStatus::ServFail, names(&[])),
+                ("10.9.0.5".to_string(), PtrStatus::NoRecord, names(&[])),
+                ("10.9.0.6".to_string(), PtrStatus::Timeout, names(&[])),
+            ]
+        );
+        assert!(sweep.lookups.iter().all(|p| p.server == server.to_string()));
+
+        assert_eq!(
+            sweep.to_string(),
+            "10.9.0.1  gw.lab\n\
+             10.9.0.2  nas.lab, files.lab\n\
+             10.9.0.3  (nxdomain)\n\
+             10.9.0.4  (servfail)\n\
+             10.9.0.5  (no_record)\n\
+             10.9.0.6  (timeout)\n\
+             6 addresses: 2 found, 1 nxdomain, 1 no_record, 1 servfail, 1 timeout\n"
+        );
+        let csv = sweep.csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "address,name,status,server,message");
+        assert_eq!(lines[2], format!("10.9.0.2,nas.lab,found,{},", server));
+        assert_eq!(lines[3], format!("10.9.0.2,files.lab,found,{},", server));
+        assert_eq!(lines[5], format!("10.9.0.4,,servfail,{},", server));
+    }
+
+    #[tokio::test]
+    async fn failures_go_to_the_next_server_and_answers_stop_there() {
+        let dead = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dead = dead.local_addr().unwrap();
+        let stub = stub_dns().await;
+        let resolver = Resolver::servers(&[dead, stub], Duration::from_millis(200));
+
+        let gateway = resolver.reverse(IpAddr::from([10, 9, 0, 1])).await;
+        assert_eq!(
+            (gateway.status, gateway.server),
+            (PtrStatus::Found, stub.to_string())
+        );
+
+        let resolver = Resolver::servers(&[stub, dead], Duration::from_millis(200));
+        let missing = resolver.reverse(IpAddr::from([10, 9, 0, 3])).await;
+        assert_eq!(
+            (missing.status, missing.server),
+            (PtrStatus::NxDomain, stub.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn the_system_resolver_does_no_reverse_lookups() {
+        let resolver = Resolver::system(Duration::from_millis(100));
+        let p = resolver.reverse(IpAddr::from([127, 0, 0, 1])).await;
+        assert_eq!((p.status, p.server.as_str()), (PtrStatus::Error, "system"));
+        assert_eq!(
+            p.message.as_deref(),
+            Some("the system resolver does not do reverse lookups")
+        );
+    }
+}
@@ -0,0 +1,462 @@
+//! Publishing results to a message broker with `--publish`: one JSON
+//! message per open port as it is found, and one with the summary when the
+//! scan ends.
+//!
+//! The [`Publisher`] is an [`OutputWriter`] like the file formats. It
+//! hands messages to a worker thread through a bounded queue and never
+//! waits on the broker: a message that finds the queue full is dropped.
+//! Dropped and undelivered messages are counted, and make the output fail
+//! at the end of the scan. The Kafka and NATS clients need the `kafka` and
+//! `nats` features.
+
+use futures::future::BoxFuture;
+use serde_json::json;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::output::OutputWriter;
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+
+/// Default for `--publish-buffer`: messages queued for the broker at most.
+pub const DEFAULT_BUFFER: usize = 1000;
+
+/// Most messages sent to the broker in one go.
+const BATCH: usize = 500;
+
+/// Longest wait for a broker to accept a connection.
+#[cfg_attr(not(any(feature = "kafka", feature = "nats")), allow(dead_code))]
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest wait for a broker to acknowledge a batch.
+#[cfg_attr(not(any(feature = "kafka", feature = "nats")), allow(dead_code))]
+pub(crate) const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The broker protocol of a `--publish` URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Kafka, or a Kafka-compatible broker such as Redpanda.
+    Kafka,
+    /// NATS core publish/subscribe.
+    Nats,
+}
+
+/// A `--publish` destination such as `kafka://broker:9092/scans` or
+/// `nats://nats:4222/scans.results`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishUrl {
+    /// The protocol.
+    pub scheme: Scheme,
+    /// `host:port` of the brokers to connect to first.
+    pub brokers: Vec<String>,
+    /// The Kafka topic or NATS subject.
+    pub topic: String,
+}
+
+impl FromStr for PublishUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest, port) = if let Some(rest) = s.strip_prefix("kafka://") {
+            (Scheme::Kafka, rest, 9092)
+        } else if let Some(rest) = s.strip_prefix("nats://") {
+            (Scheme::Nats, rest, 4222)
+        } else {
+            return Err(format!(
+                "'{}' is not a kafka://broker/topic or nats://server/subject URL",
+                s
+            ));
+        };
+        let (hosts, topic) = rest
+            .split_once('/')
+            .filter(|(_, topic)| !topic.is_empty() && !topic.contains('/'))
+            .ok_or_else(|| format!("'{}' needs a topic or subject after the broker", s))?;
+        let brokers: Vec<String> = hosts
+            .split(',')
+            .filter(|h| !h.is_empty())
+            .map(|h| {
+                let bracketed_v6 = h.starts_with('[') && h.ends_with(']');
+                if h.contains(':') && !bracketed_v6 {
+                    h.to_string()
+                } else {
+                    format!("{}:{}", h, port)
+                }
+            })
+            .collect();
+        if brokers.is_empty() {
+            return Err(format!("'{}' names no broker", s));
+        }
+        Ok(PublishUrl {
+            scheme,
+            brokers,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for PublishUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = match self.scheme {
+            Scheme::Kafka => "kafka",
+            Scheme::Nats => "nats",
+        };
+        write!(f, "{}://{}/{}", scheme, self.brokers.join(","), self.topic)
+    }
+}
+
+/// What a message is keyed by, which picks its Kafka partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishKey {
+    /// The host, so each host's messages stay in order.
+    Target,
+    /// The host and port, as `10.0.0.1:22`.
+    TargetPort,
+    /// No key: messages are spread over the partitions.
+    None,
+}
+
+impl FromStr for PublishKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "target" => Ok(PublishKey::Target),
+            "target-port" => Ok(PublishKey::TargetPort),
+            "none" => Ok(PublishKey::None),
+            _ => Err(format!(
+                "unknown key '{}' (expected target, target-port or none)",
+                s.trim()
+            )),
+        }
+    }
+}
+
+/// One message for the broker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// The partitioning key, if any.
+    pub key: Option<String>,
+    /// The JSON document.
+    pub payload: Vec<u8>,
+}
+
+/// Delivers batches of messages to a broker.
+pub trait Transport: Send {
+    /// Sends `batch` and waits for the broker to take it. On an error none
+    /// of the batch counts as delivered.
+    fn send<'a>(&'a mut self, batch: &'a [Message]) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// How the messages of a scan fared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delivery {
+    /// Acknowledged by the broker.
+    pub delivered: u64,
+    /// Sent but not acknowledged, or not sent for lack of a connection.
+    pub failed: u64,
+    /// Never queued because the queue was full.
+    pub dropped: u64,
+}
+
+/// Publishes results and the summary to a broker; see the module
+/// documentation.
+pub struct Publisher {
+    name: String,
+    scan_id: String,
+    key: PublishKey,
+    queue: Option<SyncSender<Message>>,
+    worker: Option<JoinHandle<Delivery>>,
+    dropped: u64,
+}
+
+impl Publisher {
+    /// Connects lazily to `url` and queues up to `buffer` messages.
+    pub fn start(
+        url: &PublishUrl,
+        key: PublishKey,
+        buffer: usize,
+        scan_id: &str,
+    ) -> io::Result<Self> {
+        Self::with_transport(url.to_string(), transport(url)?, key, buffer, scan_id)
+    }
+
+    /// Publishes through `transport`; `name` identifies the destination in
+    /// log lines.
+    pub fn with_transport(
+        name: String,
+        transport: Box<dyn Transport>,
+        key: PublishKey,
+        buffer: usize,
+        scan_id: &str,
+    ) -> io::Result<Self> {
+        let (queue, messages) = mpsc::sync_channel(buffer.max(1));
+        let worker = std::thread::Builder::new()
+            .name("publish".to_string())
+            .spawn(move || deliver(transport, messages))?;
+        Ok(Publisher {
+            name,
+            scan_id: scan_id.to_string(),
+            key,
+            queue: Some(queue),
+            worker: Some(worker),
+            dropped: 0,
+        })
+    }
+
+    fn queue(&mut self, message: Message) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        match queue.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                if self.dropped == 0 {
+                    warn!("Publish queue for {} is full, dropping messages", self.name);
+                }
+                self.dropped += 1;
+            }
+        }
+    }
+}
+
+impl OutputWriter for Publisher {
+    fn write_result(&mut self, r: &PortResult) -> io::Result<()> {
+        if r.status != PortStatus::Open {
+            return Ok(());
+        }
+        let key = match self.key {
+            PublishKey::Target => Some(r.target.to_string()),
+            PublishKey::TargetPort => Some(format!("{}:{}", r.target, r.port)),
+            PublishKey::None => None,
+        };
+        let payload = serde_json::to_vec(&json!({
+            "kind": "port",
+            "scan_id": self.scan_id,
+            "result": r,
+        }))?;
+        self.queue(Message { key, payload });
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &ScanSummary) -> io::Result<()> {
+        // The results went out one by one; the summary message carries the
+        // totals only.
+        let mut totals = serde_json::to_value(summary)?;
+        if let Some(fields) = totals.as_object_mut() {
+            fields.remove("results");
+        }
+        let key = match self.key {
+            PublishKey::None => None,
+            PublishKey::Target | PublishKey::TargetPort => Some(summary.target.clone()),
+        };
+        let payload = serde_json::to_vec(&json!({
+            "kind": "summary",
+            "scan_id": self.scan_id,
+            "summary": totals,
+        }))?;
+        self.queue(Message { key, payload });
+
+        self.queue = None;
+        let worker = self.worker.take().expect("a publisher finishes once");
+        let mut delivery = worker
+            .join()
+            .map_err(|_| io::Error::other("publish worker panicked"))?;
+        delivery.dropped = self.dropped;
+        let lost = delivery.failed + delivery.dropped;
+        if lost > 0 {
+            return Err(io::Error::other(format!(
+                "{} of {} messages were not delivered ({} failed, {} dropped on a full queue)",
+                lost,
+                lost + delivery.delivered,
+                delivery.failed,
+                delivery.dropped
+            )));
+        }
+        info!("Published {} messages to {}", delivery.delivered, self.name);
+        Ok(())
+    }
+}
+
+/// The client for the URL's protocol, if it was built in.
+fn transport(url: &PublishUrl) -> io::Result<Box<dyn Transport>> {
+    match url.scheme {
+        #[cfg(feature = "kafka")]
+        Scheme::Kafka => Ok(Box::new(crate::kafka::KafkaTransport::new(
+            url.brokers.clone(),
+            url.topic.clone(),
+        ))),
+        #[cfg(not(feature = "kafka"))]
+        Scheme::Kafka => Err(unsupported("kafka")),
+        #[cfg(feature = "nats")]
+        Scheme::Nats => Ok(Box::new(crate::nats::NatsTransport::new(
+            url.brokers.clone(),
+            url.topic.clone(),
+        ))),
+        #[cfg(not(feature = "nats"))]
+        Scheme::Nats => Err(unsupported("nats")),
+    }
+}
+
+#[cfg_attr(all(feature = "kafka", feature = "nats"), allow(dead_code))]
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("built without the {} feature", feature),
+    )
+}
+
+/// The worker: sends whatever is queued in batches until the queue closes.
+fn deliver(mut transport: Box<dyn Transport>, messages: Receiver<Message>) -> Delivery {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            warn!("Cannot start the publish worker: {}", e);
+            return Delivery {
+                failed: messages.iter().count() as u64,
+                ..Delivery::default()
+            };
+        }
+    };
+    let mut delivery = Delivery::default();
+    let mut batch = Vec::with_capacity(BATCH);
+    while let Ok(first) = messages.recv() {
+        batch.push(first);
+        batch.extend(messages.try_iter().take(BATCH - 1));
+        match runtime.block_on(transport.send(&batch)) {
+            Ok(()) => {
+                debug!("Published {} messages", batch.len());
+                delivery.delivered += batch.len() as u64;
+            }
+            Err(e) => {
+                warn!("Failed to publish {} messages: {}", batch.len(), e);
+                delivery.failed += batch.len() as u64;
+            }
+        }
+        batch.clear();
+    }
+    delivery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ScanMetrics;
+    use std::sync::{Arc, Mutex};
+
+    /// Records what it is sent, failing the batches after the first
+    /// `accept`.
+    struct Recorder {
+        sent: Arc<Mutex<Vec<Message>>>,
+        accept: usize,
+    }
+
+    impl Transport for Recorder {
+        fn send<'a>(&'a mut self, batch: &'a [Message]) -> BoxFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                if self.accept == 0 {
+                    return Err(io::Error::other("broker down"));
+                }
+                self.accept -= 1;
+                self.sent.lock().unwrap().extend_from_slice(batch);
+                Ok(())
+            })
+        }
+    }
+
+    fn result(port: u16, status: PortStatus) -> PortResult {
+        PortResult::new("10.0.0.1".parse().unwrap(), port, status)
+    }
+
+    fn summary(results: Vec<PortResult>) -> ScanSummary {
+        ScanSummary::from_results(
+            "abc".into(),
+            "10.0.0.1".into(),
+            results,
+            5,
+            &ScanMetrics::new("10.0.0.1"),
+            false,
+        )
+    }
+
+    #[test]
+    fn urls_name_the_brokers_and_topic() {
+        let url: PublishUrl = "kafka://k1,k2:9093/scans".parse().unwrap();
+        assert_eq!(url.scheme, Scheme::Kafka);
+        assert_eq!(url.brokers, ["k1:9092", "k2:9093"]);
+        assert_eq!(url.topic, "scans");
+        assert_eq!(url.to_string(), "kafka://k1:9092,k2:9093/scans");
+        let url: PublishUrl = "nats://[::1]/scans.results".parse().unwrap();
+        assert_eq!(url.brokers, ["[::1]:4222"]);
+        assert!("kafka://broker:9092".parse::<PublishUrl>().is_err());
+        assert!("kafka://broker/a/b".parse::<PublishUrl>().is_err());
+        assert!("amqp://broker/scans".parse::<PublishUrl>().is_err());
+        assert_eq!("Target-Port".parse(), Ok(PublishKey::TargetPort));
+    }
+
+    #[test]
+    fn open_ports_and_the_summary_are_published_with_their_keys() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let transport = Recorder {
+            sent: sent.clone(),
+            accept: usize::MAX,
+        };
+        let mut publisher = Publisher::with_transport(
+            "test".into(),
+            Box::new(transport),
+            PublishKey::TargetPort,
+            10,
+            "abc",
+        )
+        .unwrap();
+        let results = vec![result(22, PortStatus::Open), result(23, PortStatus::Closed)];
+        for r in &results {
+            publisher.write_result(r).unwrap();
+        }
+        publisher.finish(&summary(results)).unwrap();
+
+        let sent = sent.lock().unwrap();
+        let keys: Vec<Option<&str>> = sent.iter().map(|m| m.key.as_deref()).collect();
+        assert_eq!(keys, [Some("10.0.0.1:22"), Some("10.0.0.1")]);
+        let port: serde_json::Value = serde_json::from_slice(&sent[0].payload).unwrap();
+        assert_eq!(port["kind"], "port");
+        assert_eq!(port["scan_id"], "abc");
+        assert_eq!(port["result"]["port"], 22);
+        let summary: serde_json::Value = serde_json::from_slice(&sent[1].payload).unwrap();
+        assert_eq!(summary["kind"], "summary");
+        assert_eq!(summary["summary"]["open_ports"], 1);
+        assert!(summary["summary"].get("results").is_none());
+    }
+
+    #[test]
+    fn undelivered_and_dropped_messages_fail_the_output() {
+        let transport = Recorder {
+            sent: Arc::new(Mutex::new(Vec::new())),
+            accept: 0,
+        };
+        let mut publisher =
+            Publisher::with_transport("test".into(), Box::new(transport), PublishKey::None, 1, "x")
+                .unwrap();
+        let results: Vec<PortResult> = (1..=50).map(|p| result(p, PortStatus::Open)).collect();
+        for r in &results {
+            publisher.write_result(r).unwrap();
+        }
+        let e = publisher.finish(&summary(results)).unwrap_err().to_string();
+        assert!(
+            e.starts_with("51 of 51 messages were not delivered ("),
+            "{}",
+            e
+        );
+        assert!(
+            !e.contains(" 0 dropped"),
+            "a queue of 1 can't take 50 at once: {}",
+            e
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! Pushing the scan's gauges to a Prometheus Pushgateway with
+//! `--pushgateway`, for scanners Prometheus can't reach to scrape.
+//!
+//! The URL names the group the gauges go to, such as
+//! `http://push.internal:9091/metrics/job/portscan/instance/{target}`: a job
+//! and pairs of grouping labels, whose values may use the placeholders of
+//! [`GroupUrl`] to take them from the scan. Each push `PUT`s the
+//! [`ScanMetrics::render`](crate::metrics::ScanMetrics::render) exposition,
+//! replacing what the group held, and with `--pushgateway-delete` the group
+//! is deleted once the scan is done. Failed pushes are retried with a
+//! growing delay and then only warned about: they never change the outcome
+//! of the scan.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::engine::general_purpose::URL_SAFE as BASE64_URL;
+use base64::Engine;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::http::{self, Endpoint, HttpError};
+use crate::metadata::Metadata;
+use crate::metrics::ScanMetrics;
+
+/// How often a push or delete is tried before giving up.
+pub const ATTEMPTS: u32 = 3;
+
+/// The content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Why a push failed.
+#[derive(Error, Debug)]
+pub enum PushError {
+    /// The gateway couldn't be reached or sent no HTTP reply.
+    #[error("{0}")]
+    Http(#[from] HttpError),
+
+    /// The gateway refused the push.
+    #[error("HTTP {status}{}", if body.is_empty() { String::new() } else { format!(": {}", body) })]
+    Status {
+        /// The HTTP status code.
+        status: u16,
+        /// What the gateway said, such as a clash of labels.
+        body: String,
+    },
+}
+
+impl PushError {
+    /// Whether another attempt may go better.
+    fn is_transient(&self) -> bool {
+        match self {
+            PushError::Http(_) => true,
+            PushError::Status { status, .. } => http::is_transient(*status),
+        }
+    }
+}
+
+/// What a grouping label value is made of.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// `{target}`: the targets of the scan as given.
+    Target,
+    /// `{scan_id}`.
+    ScanId,
+    /// `{operator}`, from `--operator`.
+    Operator,
+    /// `{tag:KEY}`, the value of `--tag KEY=VALUE`.
+    Tag(String),
+}
+
+/// A `--pushgateway` URL: the gateway, the job and the grouping labels, whose
+/// values may hold `{target}`, `{scan_id}`, `{operator}` and `{tag:KEY}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupUrl {
+    endpoint: Endpoint,
+    /// The job first, then the other labels, by name.
+    labels: Vec<(String, Vec<Part>)>,
+}
+
+impl FromStr for GroupUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let scheme_end = s.find("://").map_or(0, |i| i + 3);
+        let (base, path) = match s[scheme_end..].find('/') {
+            Some(slash) => s.split_at(scheme_end + slash),
+            None => (s, ""),
+        };
+        let endpoint: Endpoint = base.parse()?;
+        let rest = path.strip_prefix("/metrics/").ok_or_else(|| {
+            format!(
+                "'{}' has no /metrics/job/JOB path, e.g. http://push:9091/metrics/job/portscan",
+                s
+            )
+        })?;
+        let segments: Vec<&str> = rest.trim_end_matches('/').split('/').collect();
+        if !segments.len().is_multiple_of(2) || segments.first() != Some(&"job") {
+            return Err(format!(
+                "'{}' must go on as /job/JOB and then /LABEL/VALUE pairs",
+                s
+            ));
+        }
+        let mut labels = Vec::new();
+        for pair in segments.chunks(2) {
+            let (name, value) = (pair[0], pair[1]);
+            if !valid_label_name(name) {
+                return Err(format!("'{}' is not a label name", name));
+            }
+            if labels.iter().any(|(n, _)| n == name) {
+                return Err(format!("the label '{}' is given twice", name));
+            }
+            labels.push((name.to_string(), parts(value)?));
+        }
+        Ok(GroupUrl { endpoint, labels })
+    }
+}
+
+impl fmt::Display for GroupUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/metrics", self.endpoint)?;
+        for (name, parts) in &self.labels {
+            write!(f, "/{}/", name)?;
+            for part in parts {
+                match part {
+                    Part::Literal(s) => f.write_str(s)?,
+                    Part::Target => f.write_str("{target}")?,
+                    Part::ScanId => f.write_str("{scan_id}")?,
+                    Part::Operator => f.write_str("{operator}")?,
+                    Part::Tag(key) => write!(f, "{{tag:{}}}", key)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prometheus label names: `[a-zA-Z_][a-zA-Z0-9_]*`.
+fn valid_label_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits a label value into literals and placeholders.
+fn parts(value: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = value;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(Part::Literal(rest[..open].to_string()));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in '{}'", value))?;
+        let name = &rest[open + 1..open + close];
+        parts.push(match name {
+            "target" => Part::Target,
+            "scan_id" => Part::ScanId,
+            "operator" => Part::Operator,
+            _ => match name.strip_prefix("tag:") {
+                Some(key) if !key.is_empty() => Part::Tag(key.to_string()),
+                _ => {
+                    return Err(format!(
+                        "unknown placeholder '{{{}}}' (expected one of: {{target}}, {{scan_id}}, {{operator}}, {{tag:KEY}})",
+                        name
+                    ))
+                }
+            },
+        });
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+impl GroupUrl {
+    /// The gateway's path of the group of a scan: the placeholders filled
+    /// in, and values that are empty or hold a `/` base64-encoded as the
+    /// gateway wants them (`LABEL@base64/VALUE`). A tag the scan doesn't
+    /// have, or a missing operator, is empty.
+    pub fn path(&self, target: &str, scan_id: &str, metadata: &Metadata) -> String {
+        let mut path = "/metrics".to_string();
+        for (name, parts) in &self.labels {
+            let value: String = parts
+                .iter()
+                .map(|part| match part {
+                    Part::Literal(s) => s.as_str(),
+                    Part::Target => target,
+                    Part::ScanId => scan_id,
+                    Part::Operator => metadata.operator.as_deref().unwrap_or_default(),
+                    Part::Tag(key) => metadata.tags.get(key).map_or("", String::as_str),
+                })
+                .collect();
+            if value.is_empty() {
+                path.push_str(&format!("/{}@base64/=", name));
+            } else if value.contains('/') {
+                path.push_str(&format!("/{}@base64/{}", name, BASE64_URL.encode(&value)));
+            } else {
+                path.push_str(&format!("/{}/{}", name, escape(&value)));
+            }
+        }
+        path
+    }
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Pushes to, and deletes, one group of a gateway.
+#[derive(Debug, Clone)]
+pub struct Pusher {
+    endpoint: Endpoint,
+    path: String,
+    authorization: Option<String>,
+    retry_delay: Duration,
+}
+
+impl Pusher {
+    /// Pushes to the group of `url` for the scan of `target` with the id
+    /// `scan_id` and `metadata`.
+    pub fn new(url: &GroupUrl, target: &str, scan_id: &str, metadata: &Metadata) -> Self {
+        Pusher {
+            endpoint: url.endpoint.clone(),
+            path: url.path(target, scan_id, metadata),
+            authorization: None,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+
+    /// Logs in to the gateway with HTTP basic authentication.
+    pub fn basic_auth(mut self, user: &str, password: &str) -> Self {
+        let credentials = BASE64.encode(format!("{}:{}", user, password));
+        self.authorization = Some(format!("Basic {}", credentials));
+        self
+    }
+
+    /// Wait before the second attempt; each further one waits twice as long.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// The group's path on the gateway.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Replaces the group's metrics with `exposition`, in the text format.
+    pub async fn push(&self, exposition: &str) -> Result<(), PushError> {
+        self.send("PUT", exposition).await
+    }
+
+    /// Deletes the group and its metrics.
+    pub async fn delete(&self) -> Result<(), PushError> {
+        self.send("DELETE", "").await
+    }
+
+    /// The last word at the end of the scan: pushes the final `metrics`, or
+    /// with `delete` deletes the group. A failure is only warned about.
+    pub async fn finish(&self, metrics: &ScanMetrics, delete: bool) {
+        let (pushed, done) = if delete {
+            (self.delete().await, "Deleted")
+        } else {
+            (self.push(&metrics.render()).await, "Pushed")
+        };
+        match pushed {
+            Ok(()) => info!("{} metrics group {}", done, self.path),
+            Err(e) => warn!("Pushing metrics to the gateway failed: {}", e),
+        }
+    }
+
+    /// Pushes `metrics` every `every` until the returned task is aborted,
+    /// the first time after one interval; failed pushes are warned about
+    /// and the next one goes ahead as planned.
+    pub fn push_every(&self, metrics: Arc<ScanMetrics>, every: Duration) -> JoinHandle<()> {
+        let pusher = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                match pusher.push(&metrics.render()).await {
+                    Ok(()) => debug!(path = %pusher.path, "pushed metrics"),
+                    Err(e) => warn!("Pushing metrics to the gateway failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Sends one request up to [`ATTEMPTS`] times while the failures look
+    /// transient, warning about each failed attempt.
+    async fn send(&self, method: &str, body: &str) -> Result<(), PushError> {
+        let mut headers = Vec::new();
+        if !body.is_empty() {
+            headers.push(("content-type".to_string(), CONTENT_TYPE.to_string()));
+        }
+        if let Some(authorization) = &self.authorization {
+            headers.push(("authorization".to_string(), authorization.clone()));
+        }
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+        loop {
+            let result = match self
+                .endpoint
+                .send(method, &self.path, &headers, body.as_bytes())
+                .await
+            {
+                Ok(reply) if reply.is_success() => Ok(()),
+                Ok(reply) => Err(PushError::Status {
+                    status: reply.status,
+                    body: reply.body.trim().to_string(),
+                }),
+                Err(e) => Err(PushError::Http(e)),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt == ATTEMPTS || !e.is_transient() => return Err(e),
+                Err(e) => {
+                    warn!(
+                        "Pushgateway {} attempt {} of {} failed: {}",
+                        method, attempt, ATTEMPTS, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            operator: Some("alice".to_string()),
+            reason: None,
+            tags: BTreeMap::from([("env".to_string(), "prod".to_string())]),
+        }
+    }
+
+    #[test]
+    fn urls_name_the_job_and_grouping_labels() {
+        let url: GroupUrl = "http://push.internal:9091/metrics/job/portscan/instance/{target}"
+            .parse()
+            .unwrap();
+        assert_eq!(url.endpoint, "http://push.internal:9091".parse().unwrap());
+        assert_eq!(
+            url.to_string(),
+            "http://push.internal:9091/metrics/job/portscan/instance/{target}"
+        );
+        assert_eq!(
+            url.path("10.0.0.1", "abc", &metadata()),
+            "/metrics/job/portscan/instance/10.0.0.1"
+        );
+
+        let url: GroupUrl =
+            "https://push/metrics/job/scan-{tag:env}/operator/{operator}/run/{scan_id}/"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            url.path("x", "abc", &metadata()),
+            "/metrics/job/scan-prod/operator/alice/run/abc"
+        );
+    }
+
+    #[test]
+    fn values_the_path_cant_hold_are_encoded() {
+        let url: GroupUrl = "http://push:9091/metrics/job/scan/instance/{target}/team/{tag:team}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            url.path("10.0.0.0/24", "abc", &metadata()),
+            "/metrics/job/scan/instance@base64/MTAuMC4wLjAvMjQ=/team@base64/="
+        );
+        assert_eq!(
+            url.path("fe80::1,host a", "abc", &metadata()),
+            "/metrics/job/scan/instance/fe80%3A%3A1%2Chost%20a/team@base64/="
+        );
+    }
+
+    #[test]
+    fn bad_urls_are_refused() {
+        for bad in [
+            "push:9091/metrics/job/scan",
+            "http://push:9091",
+            "http://push:9091/metrics/instance/x",
+            "http://push:9091/metrics/job/scan/instance",
+            "http://push:9091/metrics/job/scan/9lives/x",
+            "http://push:9091/metrics/job/scan/a/1/a/2",
+            "http://push:9091/metrics/job/{host}",
+            "http://push:9091/metrics/job/{target",
+        ] {
+            assert!(bad.parse::<GroupUrl>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn basic_auth_is_base64_of_user_and_password() {
+        let url: GroupUrl = "http://push/metrics/job/scan".parse().unwrap();
+        let pusher =
+            Pusher::new(&url, "x", "abc", &Metadata::default()).basic_auth("ops", "s3cret");
+        assert_eq!(
+            pusher.authorization.as_deref(),
+            Some("Basic b3BzOnMzY3JldA==")
+        );
+    }
+}
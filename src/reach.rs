@@ -0,0 +1,134 @@
+//! Hosts the network can't reach, remembered so that a down host costs a
+//! few probes instead of a timeout per port.
+//!
+//! `EHOSTUNREACH` and `ENETUNREACH` come from an ICMP error or a failed
+//! neighbor lookup: the path to the host is dead, whatever the port. After
+//! a number of them in a row from one host, [`ReachCache::skip`] has the
+//! scan leave the host's remaining ports unprobed, except every
+//! [`REPROBE_EVERY`]th, in case the host comes back. Any answer from the
+//! host, open or refused, makes it reachable again; timeouts and other
+//! errors say nothing either way and leave its state alone.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Of the ports of a host taken for unreachable, every this many is still
+/// probed.
+pub const REPROBE_EVERY: u32 = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Reach {
+    /// This many unreachable errors in a row, not yet enough.
+    Suspect(u32),
+    /// Taken for unreachable, with the error that did it and the ports
+    /// skipped since.
+    Unreachable { error: String, skipped: u32 },
+}
+
+/// The unreachable state of every host of a scan; hosts it doesn't know
+/// are reachable.
+#[derive(Debug)]
+pub struct ReachCache {
+    threshold: u32,
+    hosts: Mutex<HashMap<IpAddr, Reach>>,
+}
+
+impl ReachCache {
+    /// A cache that takes a host for unreachable after `threshold`
+    /// unreachable errors in a row.
+    pub fn new(threshold: u32) -> Self {
+        ReachCache {
+            threshold: threshold.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The error to report instead of probing the next port of `host`, when
+    /// the host is taken for unreachable and the port isn't due for a
+    /// re-probe.
+    pub fn skip(&self, host: IpAddr) -> Option<String> {
+        match self.hosts.lock().unwrap().get_mut(&host) {
+            Some(Reach::Unreachable { error, skipped }) => {
+                *skipped += 1;
+                (*skipped % REPROBE_EVERY != 0).then(|| error.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Notes that a probe of `host` failed with the unreachable `error`.
+    pub fn unreachable(&self, host: IpAddr, error: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let count = match hosts.get(&host) {
+            Some(Reach::Unreachable { .. }) => return,
+            Some(Reach::Suspect(count)) => count + 1,
+            None => 1,
+        };
+        if count >= self.threshold {
+            info!(
+                host = %host,
+                "{} is unreachable ({}), skipping its remaining ports",
+                host,
+                error
+            );
+            hosts.insert(
+                host,
+                Reach::Unreachable {
+                    error: error.to_string(),
+                    skipped: 0,
+                },
+            );
+        } else {
+            hosts.insert(host, Reach::Suspect(count));
+        }
+    }
+
+    /// Notes that `host` answered a probe, open or refused.
+    pub fn answered(&self, host: IpAddr) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(Reach::Unreachable { .. }) = hosts.remove(&host) {
+            info!(host = %host, "{} answered again, probing its ports", host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 7));
+
+    #[test]
+    fn a_host_is_skipped_after_enough_errors_in_a_row() {
+        let cache = ReachCache::new(3);
+        cache.unreachable(HOST, "No route to host");
+        cache.unreachable(HOST, "No route to host");
+        assert_eq!(cache.skip(HOST), None);
+        cache.answered(HOST);
+        cache.unreachable(HOST, "No route to host");
+        cache.unreachable(HOST, "No route to host");
+        assert_eq!(cache.skip(HOST), None, "the answer started the count over");
+
+        cache.unreachable(HOST, "No route to host");
+        let skipped = (1..=2 * REPROBE_EVERY)
+            .filter(|_| cache.skip(HOST).is_some())
+            .count() as u32;
+        assert_eq!(skipped, 2 * REPROBE_EVERY - 2, "every 32nd is re-probed");
+        assert_eq!(
+            cache.skip("192.0.2.8".parse().unwrap()),
+            None,
+            "other hosts are probed"
+        );
+    }
+
+    #[test]
+    fn a_host_that_answers_again_is_probed_again() {
+        let cache = ReachCache::new(1);
+        cache.unreachable(HOST, "Network is unreachable");
+        assert_eq!(cache.skip(HOST).as_deref(), Some("Network is unreachable"));
+        cache.answered(HOST);
+        assert_eq!(cache.skip(HOST), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! `port-scanner reanalyze`: runs the banner analysis of a scan again over
+//! a JSON report it wrote, without touching the network, so that newer
+//! detection, `--fingerprints` rules or a `--vuln-db` reach old scans.
+//!
+//! Every open port with a banner, or on a well-known port, is detected
+//! again; the advisories of a vulnerability database replace the port's
+//! findings; and ports are clustered by banner again. What changed is kept
+//! in the report as [`Reanalysis::changes`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::cluster;
+use crate::fingerprints::Fingerprints;
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+use crate::vuln::{FindingCounts, VulnDb};
+
+/// A classification of a port that came out differently.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// Host the port belongs to.
+    pub target: IpAddr,
+    /// The port.
+    pub port: u16,
+    /// The result field: `service`, `product`, `version`, `extra_info`,
+    /// `cpe` or `findings`.
+    pub field: String,
+    /// What the report said; none when it said nothing.
+    pub before: Option<String>,
+    /// What it says now.
+    pub after: Option<String>,
+}
+
+/// What a reanalysis changed, as recorded in the report.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reanalysis {
+    /// The classifications that changed, in the order of the results.
+    pub changes: Vec<Change>,
+}
+
+/// How many classifications changed, then one line per change.
+impl fmt::Display for Reanalysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ports: Vec<(IpAddr, u16)> =
+            self.changes.iter().map(|c| (c.target, c.port)).collect();
+        ports.dedup();
+        writeln!(
+            f,
+            "{} classifications changed on {} ports",
+            self.changes.len(),
+            ports.len()
+        )?;
+        for c in &self.changes {
+            writeln!(
+                f,
+                "{}:{} {}: {} -> {}",
+                c.target,
+                c.port,
+                c.field,
+                c.before.as_deref().unwrap_or("-"),
+                c.after.as_deref().unwrap_or("-")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Detects the service of every open port of `summary` again with
+/// `fingerprints`, tags them with `vuln_db` when there is one, clusters
+/// them again and records what changed in the summary.
+pub fn reanalyze<'a>(
+    summary: &'a mut ScanSummary,
+    fingerprints: &Fingerprints,
+    vuln_db: Option<&VulnDb>,
+) -> &'a Reanalysis {
+    let mut changes = Vec::new();
+    for r in summary
+        .results
+        .iter_mut()
+        .filter(|r| r.status == PortStatus::Open)
+    {
+        let before = classification(r);
+        let banner = r.banner_str().map(|b| b.into_owned());
+        let info = fingerprints.detect(r.port, banner.as_deref());
+        r.service = info.service;
+        r.product = info.product;
+        r.version = info.version;
+        r.extra_info = info.extra_info;
+        r.cpe = info.cpe;
+        if let Some(db) = vuln_db {
+            db.tag(r);
+        }
+        for ((field, before), (_, after)) in before.into_iter().zip(classification(r)) {
+            if before != after {
+                changes.push(Change {
+                    target: r.target,
+                    port: r.port,
+                    field: field.to_string(),
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+    if vuln_db.is_some() {
+        summary.findings = FindingCounts::of(&summary.results);
+    }
+    summary.clusters = cluster::assign(&mut summary.results);
+    summary.reanalysis.insert(Reanalysis { changes })
+}
+
+/// The fields of `r` a reanalysis may change, as text.
+fn classification(r: &PortResult) -> [(&'static str, Option<String>); 6] {
+    let findings = (!r.findings.is_empty()).then(|| {
+        r.findings
+            .iter()
+            .map(|f| f.id.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    });
+    [
+        ("service", r.service.clone()),
+        ("product", r.product.clone()),
+        ("version", r.version.clone()),
+        ("extra_info", r.extra_info.clone()),
+        ("cpe", r.cpe.clone()),
+        ("findings", findings),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ScanMetrics;
+    use std::net::Ipv4Addr;
+
+    const HOST: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn summary() -> ScanSummary {
+        // As an old scanner reported them: the service alone.
+        let mut ssh = PortResult::new(HOST, 22, PortStatus::Open);
+        ssh.banner = Some("SSH-2.0-dropbear_2020.81".into());
+        ssh.service = Some("SSH".to_string());
+        let mut web = PortResult::new(HOST, 8080, PortStatus::Open);
+        web.banner = Some("HTTP/1.1 200 OK\r\nServer: nginx/1.18.0\r\n".into());
+        let closed = PortResult::new(HOST, 23, PortStatus::Closed);
+        ScanSummary::from_results(
+            "scan".to_string(),
+            HOST.to_string(),
+            vec![ssh, web, closed],
+            100,
+            &ScanMetrics::new("test"),
+            false,
+        )
+    }
+
+    #[test]
+    fn changed_classifications_are_recorded() {
+        let rules = Fingerprints::parse(
+            "rules.toml",
+            "[[fingerprint]]\nmatch = \"dropbear\"\nservice = \"SSH\"\nproduct = \"Dropbear\"\n",
+        )
+        .unwrap();
+        let db = VulnDb::parse(
+            "db.json",
+            r#"[{"id": "CVE-2021-0001", "product": "nginx", "versions": "<1.20", "severity": "high", "summary": "x"}]"#,
+        )
+        .unwrap();
+        let mut summary = summary();
+        let reanalysis = reanalyze(&mut summary, &rules, Some(&db)).clone();
+        let fields: Vec<(u16, &str, Option<&str>)> = reanalysis
+            .changes
+            .iter()
+            .map(|c| (c.port, c.field.as_str(), c.after.as_deref()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (22, "product", Some("Dropbear")),
+                (22, "version", Some("2020.81")),
+                (8080, "service", Some("HTTP")),
+                (8080, "product", Some("nginx")),
+                (8080, "version", Some("1.18.0")),
+                (8080, "cpe", Some("cpe:2.3:a:f5:nginx:1.18.0:*:*:*:*:*:*:*")),
+                (8080, "findings", Some("CVE-2021-0001")),
+            ]
+        );
+        assert_eq!(summary.findings.high, 1);
+        assert_eq!(summary.reanalysis.as_ref(), Some(&reanalysis));
+        assert!(reanalysis.to_string().starts_with(
+            "7 classifications changed on 2 ports\n10.0.0.1:22 product: - -> Dropbear\n"
+        ));
+
+        // A second pass changes nothing.
+        let again = reanalyze(&mut summary, &rules, Some(&db));
+        assert!(again.changes.is_empty());
+    }
+}
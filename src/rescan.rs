@@ -0,0 +1,368 @@
+//! `--from`: a re-scan of some of the ports of an earlier scan, whose
+//! report still covers every port the earlier scan had.
+//!
+//! The earlier scan is a report in any format [`import`] reads, dated by
+//! its scan id or else by the modification time of its file, as in
+//! [`history`](crate::history). Each port this scan probes is either
+//! scanned for the first time or re-verified, when the earlier scan has a
+//! result for it; each port of the earlier scan that this one leaves out,
+//! or didn't get to, is skipped. Results are merged by host, protocol and
+//! port, the host by its key in the mode of this scan (see
+//! [`identity`](crate::identity)):
+//!
+//! - a result of this scan wins over the earlier one, and keeps its status
+//!   as [`PortResult::previous_status`];
+//! - an earlier result without one is carried forward, with
+//!   [`PortResult::stale`] the time the earlier scan started, unless that
+//!   report had carried it forward from an older scan already, whose time
+//!   it keeps.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::history;
+use crate::identity::{self, HostKey, Identities, IdentityMode};
+use crate::import::ImportError;
+use crate::scanner::{PortResult, ScanSummary};
+use crate::spec::Protocol;
+
+/// The results of an earlier scan, to re-scan some of its ports.
+#[derive(Debug, Clone)]
+pub struct Previous {
+    from: String,
+    at: DateTime<Utc>,
+    results: Vec<PortResult>,
+    /// What the earlier scan identified hosts by, and their names.
+    identity: IdentityMode,
+    names: Identities,
+}
+
+/// How a re-scan relates to the scan it started from, as recorded in the
+/// report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rescan {
+    /// The report of the earlier scan.
+    pub from: String,
+    /// When the earlier scan started.
+    pub from_started_at: DateTime<Utc>,
+    /// Ports probed that the earlier scan has no result for.
+    pub first_time: usize,
+    /// Ports of the earlier scan probed again.
+    pub reverified: usize,
+    /// Re-verified ports whose status is not the earlier one.
+    pub changed: usize,
+    /// Ports of the earlier scan not probed again, carried forward stale.
+    pub skipped: usize,
+}
+
+impl Previous {
+    /// Reads the report of the earlier scan at `path`.
+    pub fn load(path: &Path) -> Result<Self, ImportError> {
+        let (summary, at) = history::load_dated(path)?;
+        Ok(Self::of(path.display().to_string(), at, summary))
+    }
+
+    /// The earlier scan `from`, started `at`, with its results.
+    pub fn new(from: impl Into<String>, at: DateTime<Utc>, results: Vec<PortResult>) -> Self {
+        Previous {
+            from: from.into(),
+            at,
+            results,
+            identity: IdentityMode::default(),
+            names: Identities::default(),
+        }
+    }
+
+    /// The earlier scan `from`, started `at`, with the results and the
+    /// names of the hosts of its report.
+    pub fn of(from: impl Into<String>, at: DateTime<Utc>, summary: ScanSummary) -> Self {
+        let names = Identities::of(&summary, summary.identity);
+        Previous {
+            identity: summary.identity,
+            names,
+            ..Self::new(from, at, summary.results)
+        }
+    }
+
+    /// A warning when the earlier scan identified hosts otherwise than
+    /// `mode`.
+    pub fn mismatch(&self, mode: IdentityMode) -> Option<String> {
+        identity::mismatch(&self.from, self.identity, mode)
+    }
+
+    /// How many results the earlier scan has.
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Whether the earlier scan has no result at all.
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Merges the earlier results into `summary`, matching hosts on their
+    /// keys in `identities`, those of this scan: its own results keep the
+    /// earlier status, and the earlier results it has none for are added
+    /// after them, stale. The counts of `summary` are brought up to date.
+    pub fn merge(self, summary: &mut ScanSummary, identities: &Identities) -> Rescan {
+        let hosts: BTreeSet<IpAddr> = summary.results.iter().map(|r| r.target).collect();
+        let earlier_hosts: BTreeSet<IpAddr> = self.results.iter().map(|r| r.target).collect();
+        let (now, then) = identity::exchange(identities, &hosts, &self.names, &earlier_hosts);
+        let fresh: HashMap<(HostKey, Protocol, u16), usize> = summary
+            .results
+            .iter()
+            .enumerate()
+            .map(|(i, r)| ((now.key(r.target), r.protocol, r.port), i))
+            .collect();
+        for mut earlier in self.results {
+            match fresh.get(&(then.key(earlier.target), earlier.protocol, earlier.port)) {
+                Some(&i) => summary.results[i].previous_status = Some(earlier.status),
+                None => {
+                    earlier.stale = earlier.stale.or(Some(self.at));
+                    earlier.previous_status = None;
+                    summary.results.push(earlier);
+                }
+            }
+        }
+        summary.recount();
+        let rescan = Rescan {
+            from: self.from,
+            from_started_at: self.at,
+            first_time: 0,
+            reverified: 0,
+            changed: 0,
+            skipped: 0,
+        };
+        rescan.recount(&summary.results)
+    }
+}
+
+impl Rescan {
+    /// The same re-scan with its ports counted from `results`, such as
+    /// those of a single host.
+    pub fn recount(&self, results: &[PortResult]) -> Rescan {
+        let mut rescan = Rescan {
+            first_time: 0,
+            reverified: 0,
+            changed: 0,
+            skipped: 0,
+            ..self.clone()
+        };
+        for r in results {
+            match (&r.stale, &r.previous_status) {
+                (Some(_), _) => rescan.skipped += 1,
+                (None, Some(previous)) => {
+                    rescan.reverified += 1;
+                    if *previous != r.status {
+                        rescan.changed += 1;
+                    }
+                }
+                (None, None) => rescan.first_time += 1,
+            }
+        }
+        rescan
+    }
+}
+
+/// One line: where the re-scan started from and its count of ports of
+/// each kind.
+impl fmt::Display for Rescan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Re-scan of {} (started {}): {} new, {} re-verified ({} changed), \
+             {} skipped and carried forward",
+            self.from,
+            self.from_started_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.first_time,
+            self.reverified,
+            self.changed,
+            self.skipped
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ScanMetrics;
+    use crate::scanner::PortStatus;
+
+    fn result(target: &str, port: u16, status: PortStatus) -> PortResult {
+        PortResult::new(target.parse().unwrap(), port, status)
+    }
+
+    fn summary(results: Vec<PortResult>) -> ScanSummary {
+        ScanSummary::from_results(
+            "rescan-test".to_string(),
+            "10.0.0.5".to_string(),
+            results,
+            0,
+            &ScanMetrics::new("10.0.0.5"),
+            false,
+        )
+    }
+
+    fn at(day: u32) -> DateTime<Utc> {
+        format!("2026-03-{:02}T08:00:00Z", day).parse().unwrap()
+    }
+
+    #[test]
+    fn ports_are_new_reverified_or_carried_forward() {
+        let previous = Previous::new(
+            "before.json",
+            at(1),
+            vec![
+                result("10.0.0.5", 22, PortStatus::Open),
+                result("10.0.0.5", 80, PortStatus::Open),
+                result("10.0.0.5", 443, PortStatus::Open),
+                result("10.0.0.6", 22, PortStatus::Closed),
+            ],
+        );
+        let mut summary = summary(vec![
+            result("10.0.0.5", 22, PortStatus::Open),
+            result("10.0.0.5", 80, PortStatus::Closed),
+            result("10.0.0.5", 8080, PortStatus::Open),
+        ]);
+
+        let rescan = previous.merge(&mut summary, &Identities::default());
+
+        assert_eq!(
+            rescan,
+            Rescan {
+                from: "before.json".to_string(),
+                from_started_at: at(1),
+                first_time: 1,
+                reverified: 2,
+                changed: 1,
+                skipped: 2,
+            }
+        );
+        let found: Vec<String> = summary
+            .results
+            .iter()
+            .map(|r| {
+                let previous = r.previous_status.as_ref().map(|p| format!(" was {}", p));
+                let stale = r
+                    .stale
+                    .map(|at| format!(" stale since {}", at.format("%d")));
+                format!(
+                    "{}:{} {}{}{}",
+                    r.target,
+                    r.port,
+                    r.status,
+                    previous.unwrap_or_default(),
+                    stale.unwrap_or_default()
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "10.0.0.5:22 open was open",
+                "10.0.0.5:80 closed was open",
+                "10.0.0.5:8080 open",
+                "10.0.0.5:443 open stale since 01",
+                "10.0.0.6:22 closed stale since 01",
+            ]
+        );
+        assert_eq!(summary.scanned_ports, 5);
+        assert_eq!(
+            (
+                summary.open_ports,
+                summary.closed_ports,
+                summary.filtered_ports
+            ),
+            (3, 2, 0)
+        );
+        assert_eq!(
+            rescan.to_string(),
+            "Re-scan of before.json (started 2026-03-01 08:00:00 UTC): 1 new, \
+             2 re-verified (1 changed), 2 skipped and carried forward"
+        );
+    }
+
+    #[test]
+    fn results_already_stale_keep_their_age_and_fresh_ones_win() {
+        let mut stale = result("10.0.0.5", 443, PortStatus::Open);
+        stale.stale = Some(at(1));
+        let mut reverified = result("10.0.0.5", 22, PortStatus::Open);
+        reverified.stale = Some(at(1));
+        let mut skipped = result("10.0.0.5", 25, PortStatus::Closed);
+        skipped.previous_status = Some(PortStatus::Open);
+        let previous = Previous::new("second.json", at(8), vec![stale, reverified, skipped]);
+        let mut summary = summary(vec![result("10.0.0.5", 22, PortStatus::Filtered)]);
+
+        let rescan = previous.merge(&mut summary, &Identities::default());
+
+        let ssh = &summary.results[0];
+        assert_eq!(
+            (ssh.status.clone(), ssh.previous_status.clone(), ssh.stale),
+            (PortStatus::Filtered, Some(PortStatus::Open), None)
+        );
+        assert_eq!(summary.results[1].stale, Some(at(1)));
+        assert_eq!(summary.results[2].stale, Some(at(8)));
+        assert_eq!(summary.results[2].previous_status, None);
+        assert_eq!(
+            (rescan.reverified, rescan.changed, rescan.skipped),
+            (1, 1, 2)
+        );
+
+        let host = summary.for_host("10.0.0.5".parse().unwrap()).unwrap();
+        assert_eq!(host.rescan, None);
+        summary.rescan = Some(rescan.clone());
+        let host = summary.for_host("10.0.0.5".parse().unwrap()).unwrap();
+        assert_eq!(host.rescan, Some(rescan));
+    }
+
+    fn named(address: &str, name: &str) -> Identities {
+        let mut identities = Identities::new(IdentityMode::Name);
+        identities.add(address.parse().unwrap(), name);
+        identities
+    }
+
+    #[test]
+    fn hosts_are_matched_by_name_from_hostname_to_ip_and_back() {
+        // Scanned as db01.corp at 10.0.0.5, then by IP.
+        let mut before = summary(vec![result("10.0.0.5", 22, PortStatus::Open)]);
+        before.annotate_hosts(None, &[]);
+        before.identify(&named("10.0.0.5", "db01.corp"));
+        let previous = Previous::of("before.json", at(1), before);
+        assert_eq!(previous.mismatch(IdentityMode::Name), None);
+        let mut now = summary(vec![result("10.0.0.5", 22, PortStatus::Closed)]);
+        let rescan = previous.merge(&mut now, &Identities::new(IdentityMode::Name));
+        assert_eq!(
+            (rescan.reverified, rescan.changed, rescan.skipped),
+            (1, 1, 0)
+        );
+
+        // Scanned by IP, then as db01.corp.
+        let mut before = summary(vec![result("10.0.0.5", 22, PortStatus::Open)]);
+        before.annotate_hosts(None, &[]);
+        let previous = Previous::of("before.json", at(1), before);
+        assert!(previous.mismatch(IdentityMode::Name).is_some());
+        let mut now = summary(vec![result("10.0.0.5", 22, PortStatus::Open)]);
+        let rescan = previous.merge(&mut now, &named("10.0.0.5", "db01.corp"));
+        assert_eq!((rescan.reverified, rescan.skipped), (1, 0));
+
+        // db01.corp moved to 10.0.0.9: only its name says it is the host.
+        let moved = || {
+            let mut before = summary(vec![result("10.0.0.5", 22, PortStatus::Open)]);
+            before.annotate_hosts(None, &[]);
+            before.identify(&named("10.0.0.5", "db01.corp"));
+            Previous::of("before.json", at(1), before)
+        };
+        let mut now = summary(vec![result("10.0.0.9", 22, PortStatus::Open)]);
+        let rescan = moved().merge(&mut now, &named("10.0.0.9", "db01.corp"));
+        assert_eq!((rescan.reverified, rescan.skipped), (1, 0));
+        let mut by_address = Identities::new(IdentityMode::Address);
+        by_address.add("10.0.0.9".parse().unwrap(), "db01.corp");
+        let mut now = summary(vec![result("10.0.0.9", 22, PortStatus::Open)]);
+        let rescan = moved().merge(&mut now, &by_address);
+        assert_eq!((rescan.first_time, rescan.skipped), (1, 1));
+    }
+}
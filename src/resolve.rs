@@ -0,0 +1,592 @@
+//! Host names among the targets, resolved before the scan starts: by the
+//! system resolver, or by the servers given with `--resolver` and `--doh`.
+//! Each name is resolved once per scan.
+//!
+//! The same servers answer the reverse lookups of `port-scanner ptr`. The
+//! system resolver does not do those; the name servers of
+//! `/etc/resolv.conf` stand in for it.
+
+use hickory_resolver::config::{NameServerConfig, ResolveHosts, ResolverConfig, ResolverOpts};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::net::{DnsError, NetError};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::proto::rr::{Name, RData};
+use hickory_resolver::TokioResolver;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::debug;
+
+/// One address record of an answer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsRecord {
+    /// The address.
+    pub address: IpAddr,
+    /// Seconds the answer may be cached, unknown from the system resolver.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+}
+
+/// How a host name was resolved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolution {
+    /// The name as given.
+    pub name: String,
+    /// The server that answered: `1.1.1.1:53`, a DoH URL, or `system`.
+    pub server: String,
+    /// Every address returned, in the server's order.
+    pub records: Vec<DnsRecord>,
+}
+
+impl Resolution {
+    /// The addresses the name resolved to.
+    pub fn addresses(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.records.iter().map(|r| r.address)
+    }
+}
+
+/// A name no server could resolve.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Could not resolve {name} (tried {}): {message}", .tried.join(", "))]
+pub struct ResolveError {
+    /// The name.
+    pub name: String,
+    /// Every server asked, in order.
+    pub tried: Vec<String>,
+    /// What the last of them said.
+    pub message: String,
+}
+
+/// What the reverse lookup of an address came to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PtrStatus {
+    /// The address has a name.
+    Found,
+    /// NXDOMAIN: the server knows of no name for the address.
+    NxDomain,
+    /// The server has the address's entry, but no PTR record in it.
+    NoRecord,
+    /// SERVFAIL: the server could not find out.
+    ServFail,
+    /// REFUSED: the server won't answer for the address.
+    Refused,
+    /// No server answered in time.
+    Timeout,
+    /// Anything else, such as a malformed answer; see the message.
+    Error,
+}
+
+impl PtrStatus {
+    /// The name, as reports write it.
+    pub fn name(self) -> &'static str {
+        match self {
+            PtrStatus::Found => "found",
+            PtrStatus::NxDomain => "nxdomain",
+            PtrStatus::NoRecord => "no_record",
+            PtrStatus::ServFail => "servfail",
+            PtrStatus::Refused => "refused",
+            PtrStatus::Timeout => "timeout",
+            PtrStatus::Error => "error",
+        }
+    }
+
+    /// Whether the server had the last word: a name, or none to be had.
+    /// Other outcomes are worth asking the next server about.
+    pub fn is_answer(self) -> bool {
+        matches!(
+            self,
+            PtrStatus::Found | PtrStatus::NxDomain | PtrStatus::NoRecord
+        )
+    }
+}
+
+impl fmt::Display for PtrStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The reverse lookup of one address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ptr {
+    /// The address looked up.
+    pub address: IpAddr,
+    /// What came of it.
+    pub status: PtrStatus,
+    /// The names found, without the trailing dot, in the server's order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    /// The server that gave the last word, or the last one asked.
+    pub server: String,
+    /// What went wrong, for [`PtrStatus::Error`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+enum Upstream {
+    /// `getaddrinfo`, honoring `/etc/hosts` and the system configuration.
+    System,
+    /// A configured server, queried on its own so that failover and the
+    /// answering server are ours to see.
+    Server {
+        label: String,
+        resolver: Box<TokioResolver>,
+    },
+}
+
+impl Upstream {
+    fn label(&self) -> &str {
+        match self {
+            Upstream::System => "system",
+            Upstream::Server { label, .. } => label,
+        }
+    }
+}
+
+/// Resolves target names, rotating through its servers and failing over to
+/// the next when one does not answer.
+pub struct Resolver {
+    upstreams: Vec<Upstream>,
+    next: usize,
+    timeout: Duration,
+    cache: HashMap<String, Resolution>,
+}
+
+impl Resolver {
+    /// Asks the system resolver, giving up after `timeout`.
+    pub fn system(timeout: Duration) -> Self {
+        Resolver {
+            upstreams: vec![Upstream::System],
+            next: 0,
+            timeout,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Asks `servers` over UDP (TCP for truncated answers), starting with the
+    /// next one in turn for every name. Without servers, asks the system.
+    pub fn servers(servers: &[SocketAddr], timeout: Duration) -> Self {
+        let mut resolver = Self::system(timeout);
+        if !servers.is_empty() {
+            resolver.upstreams = servers
+                .iter()
+                .map(|&addr| {
+                    let mut server = NameServerConfig::udp_and_tcp(addr.ip());
+                    for connection in &mut server.connections {
+                        connection.port = addr.port();
+                    }
+                    Upstream::Server {
+                        label: addr.to_string(),
+                        resolver: Box::new(build(server, timeout)),
+                    }
+                })
+                .collect();
+        }
+        resolver
+    }
+
+    /// Adds a DNS-over-HTTPS server such as
+    /// `https://cloudflare-dns.com/dns-query`, replacing the system resolver
+    /// if that was all there was. Its host name is looked up with the system
+    /// resolver.
+    pub async fn add_doh(&mut self, url: &str) -> Result<(), ResolveError> {
+        let fail = |message: String| ResolveError {
+            name: url.to_string(),
+            tried: vec!["system".to_string()],
+            message,
+        };
+        let (host, port, path) = parse_doh_url(url).map_err(fail)?;
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => {
+                let mut system = Resolver::system(self.timeout);
+                let found = system.resolve(host).await?;
+                let first = found.addresses().next();
+                first.ok_or_else(|| fail("no addresses".into()))?
+            }
+        };
+        let server = doh_server(ip, port, host, path).map_err(fail)?;
+        if matches!(self.upstreams.as_slice(), [Upstream::System]) {
+            self.upstreams.clear();
+        }
+        self.upstreams.push(Upstream::Server {
+            label: url.to_string(),
+            resolver: Box::new(build(server, self.timeout)),
+        });
+        Ok(())
+    }
+
+    /// The addresses of `name`, from the cache when it was resolved before.
+    pub async fn resolve(&mut self, name: &str) -> Result<Resolution, ResolveError> {
+        let key = name.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(found) = self.cache.get(&key) {
+            return Ok(found.clone());
+        }
+        let start = self.next;
+        self.next = (self.next + 1) % self.upstreams.len();
+        let mut tried = Vec::new();
+        let mut message = String::new();
+        for i in 0..self.upstreams.len() {
+            let upstream = &self.upstreams[(start + i) % self.upstreams.len()];
+            tried.push(upstream.label().to_string());
+            match self.ask(upstream, name).await {
+                Ok(records) if !records.is_empty() => {
+                    let found = Resolution {
+                        name: name.to_string(),
+                        server: upstream.label().to_string(),
+                        records,
+                    };
+                    debug!(
+                        "{} resolved by {}: {}",
+                        name,
+                        found.server,
+                        found
+                            .records
+                            .iter()
+                            .map(|r| match r.ttl {
+                                Some(ttl) => format!("{} (TTL {}s)", r.address, ttl),
+                                None => r.address.to_string(),
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    self.cache.insert(key, found.clone());
+                    return Ok(found);
+                }
+                Ok(_) => message = "no addresses".to_string(),
+                Err(e) => message = e,
+            }
+            debug!("{} did not resolve {}: {}", upstream.label(), name, message);
+        }
+        Err(ResolveError {
+            name: name.to_string(),
+            tried,
+            message,
+        })
+    }
+
+    /// The names of `address`, from its PTR records. Servers are asked in
+    /// order until one has the last word; the answer is not cached.
+    pub async fn reverse(&self, address: IpAddr) -> Ptr {
+        let mut ptr = Ptr {
+            address,
+            status: PtrStatus::Error,
+            names: Vec::new(),
+            server: String::new(),
+            message: None,
+        };
+        for upstream in &self.upstreams {
+            ptr.server = upstream.label().to_string();
+            (ptr.status, ptr.names, ptr.message) = match upstream {
+                Upstream::System => (
+                    PtrStatus::Error,
+                    Vec::new(),
+                    Some("the system resolver does not do reverse lookups".to_string()),
+                ),
+                Upstream::Server { resolver, .. } => {
+                    let lookup = resolver.reverse_lookup(Name::from(address));
+                    match lookup.await {
+                        Ok(found) => {
+                            let names: Vec<String> = found
+                                .answers()
+                                .iter()
+                                .filter_map(|r| match &r.data {
+                                    RData::PTR(name) => {
+                                        Some(name.to_string().trim_end_matches('.').to_string())
+                                    }
+                                    _ => None,
+                                })
+                                .collect();
+                            match names.is_empty() {
+                                true => (PtrStatus::NoRecord, names, None),
+                                false => (PtrStatus::Found, names, None),
+                            }
+                        }
+                        Err(e) => {
+                            let status = ptr_status(&e);
+                            let message = (status == PtrStatus::Error).then(|| e.to_string());
+                            (status, Vec::new(), message)
+                        }
+                    }
+                }
+            };
+            debug!(
+                "{} reverse lookup by {}: {}",
+                address, ptr.server, ptr.status
+            );
+            if ptr.status.is_answer() {
+                break;
+            }
+        }
+        ptr
+    }
+
+    async fn ask(&self, upstream: &Upstream, name: &str) -> Result<Vec<DnsRecord>, String> {
+        match upstream {
+            Upstream::System => {
+                let lookup = tokio::net::lookup_host((name, 0));
+                let found = tokio::time::timeout(self.timeout, lookup)
+                    .await
+                    .map_err(|_| "timed out".to_string())?
+                    .map_err(|e| e.to_string())?;
+                let mut records: Vec<DnsRecord> = Vec::new();
+                for addr in found {
+                    if !records.iter().any(|r| r.address == addr.ip()) {
+                        records.push(DnsRecord {
+                            address: addr.ip(),
+                            ttl: None,
+                        });
+                    }
+                }
+                Ok(records)
+            }
+            Upstream::Server { resolver, .. } => {
+                let found = resolver.lookup_ip(name).await.map_err(|e| e.to_string())?;
+                Ok(found
+                    .as_lookup()
+                    .answers()
+                    .iter()
+                    .filter_map(|r| {
+                        Some(DnsRecord {
+                            address: r.data.ip_addr()?,
+                            ttl: Some(r.ttl),
+                        })
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+/// The outcome a failed lookup stands for.
+fn ptr_status(e: &NetError) -> PtrStatus {
+    match e {
+        NetError::Timeout => PtrStatus::Timeout,
+        NetError::Dns(DnsError::NoRecordsFound(none)) => match none.response_code {
+            ResponseCode::NXDomain => PtrStatus::NxDomain,
+            ResponseCode::ServFail => PtrStatus::ServFail,
+            ResponseCode::Refused => PtrStatus::Refused,
+            _ => PtrStatus::NoRecord,
+        },
+        NetError::Dns(DnsError::ResponseCode(ResponseCode::NXDomain)) => PtrStatus::NxDomain,
+        NetError::Dns(DnsError::ResponseCode(ResponseCode::ServFail)) => PtrStatus::ServFail,
+        NetError::Dns(DnsError::ResponseCode(ResponseCode::Refused)) => PtrStatus::Refused,
+        _ => PtrStatus::Error,
+    }
+}
+
+/// The name servers of `/etc/resolv.conf`, on port 53; none where there
+/// is no such file.
+pub fn system_name_servers() -> Vec<SocketAddr> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    name_servers(&conf)
+}
+
+fn name_servers(conf: &str) -> Vec<SocketAddr> {
+    conf.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(ip)) => {
+                    // A scoped IPv6 server such as fe80::1%eth0 can't be asked.
+                    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn build(server: NameServerConfig, timeout: Duration) -> TokioResolver {
+    let config = ResolverConfig::from_parts(None, Vec::new(), vec![server]);
+    let mut options = ResolverOpts::default();
+    options.timeout = timeout;
+    options.attempts = 1;
+    // Answers are cached per scan by the Resolver itself.
+    options.cache_size = 0;
+    options.use_hosts_file = ResolveHosts::Never;
+    TokioResolver::builder_with_config(config, TokioRuntimeProvider::default())
+        .with_options(options)
+        .build()
+        .expect("a resolver with one name server builds")
+}
+
+/// Host, port and path of a DoH URL.
+fn parse_doh_url(url: &str) -> Result<(&str, u16, &str), String> {
+    let rest = url
+        .strip_prefix("https://")
+        .ok_or_else(|| "a DoH URL must start with https://".to_string())?;
+    let (authority, path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, "/dns-query"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(']') || authority.starts_with('[') => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in {}", url))?,
+        ),
+        _ => (authority, 443),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(format!("no host in {}", url));
+    }
+    Ok((host, port, path))
+}
+
+#[cfg(feature = "doh")]
+fn doh_server(ip: IpAddr, port: u16, host: &str, path: &str) -> Result<NameServerConfig, String> {
+    let mut server = NameServerConfig::https(ip, host.into(), Some(path.into()));
+    for connection in &mut server.connections {
+        connection.port = port;
+    }
+    Ok(server)
+}
+
+#[cfg(not(feature = "doh"))]
+fn doh_server(_: IpAddr, _: u16, _: &str, _: &str) -> Result<NameServerConfig, String> {
+    Err("built without the doh feature".to_string())
+}
+
+/// True for strings that can only be a host name: dot-separated labels of
+/// letters, digits, `-` and `_`, with a letter somewhere.
+pub fn is_host_name(s: &str) -> bool {
+    let s = s.strip_suffix('.').unwrap_or(s);
+    !s.is_empty()
+        && s.len() <= 253
+        && s.bytes().any(|b| b.is_ascii_alphabetic())
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::op::{Message, OpCode};
+    use hickory_resolver::proto::rr::{rdata::A, RData, Record, RecordType};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+
+    /// A DNS server answering every A query with 10.1.2.3, counting queries.
+    async fn fake_dns() -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counted = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                let query = Message::from_vec(&buf[..n]).unwrap();
+                counted.fetch_add(1, Ordering::SeqCst);
+                let mut reply = Message::response(query.metadata.id, OpCode::Query);
+                reply.add_queries(query.queries.clone());
+                for q in &query.queries {
+                    if q.query_type() == RecordType::A {
+                        let a = RData::A(A::new(10, 1, 2, 3));
+                        reply.add_answer(Record::from_rdata(q.name().clone(), 300, a));
+                    }
+                }
+                let _ = socket.send_to(&reply.to_vec().unwrap(), from).await;
+            }
+        });
+        (addr, queries)
+    }
+
+    /// An address nothing answers on.
+    async fn dead_dns() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn failover_finds_the_answering_server_and_caches_it() {
+        let (fake, queries) = fake_dns().await;
+        let dead = dead_dns().await;
+        let mut resolver = Resolver::servers(&[dead, fake], Duration::from_millis(200));
+
+        let found = resolver.resolve("scan.example").await.unwrap();
+        assert_eq!(found.server, fake.to_string());
+        assert_eq!(
+            found.records,
+            [DnsRecord {
+                address: "10.1.2.3".parse().unwrap(),
+                ttl: Some(300)
+            }]
+        );
+        let asked = queries.load(Ordering::SeqCst);
+        assert!(asked > 0);
+
+        let again = resolver.resolve("SCAN.example.").await.unwrap();
+        assert_eq!(again, found);
+        assert_eq!(queries.load(Ordering::SeqCst), asked);
+    }
+
+    #[tokio::test]
+    async fn failures_name_every_server_tried() {
+        let dead = [dead_dns().await, dead_dns().await];
+        let mut resolver = Resolver::servers(&dead, Duration::from_millis(100));
+        let err = resolver.resolve("scan.example").await.unwrap_err();
+        assert_eq!(err.tried, [dead[0].to_string(), dead[1].to_string()]);
+        assert!(err.to_string().starts_with(&format!(
+            "Could not resolve scan.example (tried {}, {})",
+            dead[0], dead[1]
+        )));
+    }
+
+    #[test]
+    fn name_servers_are_read_from_resolv_conf() {
+        let conf = "# generated\nsearch lab\nnameserver 10.0.0.1\nnameserver ::1\n\
+                    nameserver fe80::1%eth0\noptions ndots:1\n";
+        assert_eq!(
+            name_servers(conf),
+            [
+                "10.0.0.1:53".parse::<SocketAddr>().unwrap(),
+                "[::1]:53".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn host_names_are_told_apart_from_targets() {
+        for name in ["example.com", "localhost", "db-1.internal.", "_srv.x", "a1"] {
+            assert!(is_host_name(name), "{}", name);
+        }
+        for target in [
+            "10.0.0.1",
+            "10.0.0.0/24",
+            "::1",
+            "1.2.3.4-5",
+            "",
+            "a..b",
+            "-x.com",
+        ] {
+            assert!(!is_host_name(target), "{}", target);
+        }
+    }
+
+    #[test]
+    fn doh_urls_give_host_port_and_path() {
+        assert_eq!(
+            parse_doh_url("https://cloudflare-dns.com/dns-query"),
+            Ok(("cloudflare-dns.com", 443, "/dns-query"))
+        );
+        assert_eq!(
+            parse_doh_url("https://[2606:4700::1111]:8443"),
+            Ok(("2606:4700::1111", 8443, "/dns-query"))
+        );
+        assert!(parse_doh_url("http://dns.example/dns-query").is_err());
+        assert!(parse_doh_url("https://dns.example:x/").is_err());
+    }
+}
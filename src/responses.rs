@@ -0,0 +1,125 @@
+//! Raw responses saved for offline analysis with `--save-responses`.
+//!
+//! Each open port's response goes to `<dir>/<target>/<port>.bin`. Files are
+//! written under a name no other writer uses and renamed into place, so a
+//! reader never sees half a file and concurrent scans into the same
+//! directory can't interleave their bytes.
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::scanner::PortResult;
+use crate::template::sanitize;
+
+/// Default for `--response-cap`: most bytes kept per port.
+pub const DEFAULT_CAP: usize = 64 * 1024;
+
+/// A directory of saved responses.
+#[derive(Debug)]
+pub struct ResponseStore {
+    dir: PathBuf,
+    next: AtomicU64,
+}
+
+impl ResponseStore {
+    /// Saves into `dir`, which is created with the first file.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ResponseStore {
+            dir: dir.into(),
+            next: AtomicU64::new(0),
+        }
+    }
+
+    /// Where the response of `target`'s `port` goes.
+    pub fn path(&self, target: IpAddr, port: u16) -> PathBuf {
+        self.dir
+            .join(sanitize(&target.to_string()))
+            .join(format!("{}.bin", port))
+    }
+
+    /// Writes `bytes` as the response of `target`'s `port`, replacing an
+    /// earlier one, and returns the path.
+    pub fn write(&self, target: IpAddr, port: u16, bytes: &[u8]) -> io::Result<PathBuf> {
+        let path = self.path(target, port);
+        let parent = path.parent().expect("responses live in a host directory");
+        fs::create_dir_all(parent)?;
+        let tmp = parent.join(format!(
+            ".{}.{}.{}.tmp",
+            port,
+            std::process::id(),
+            self.next.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = fs::write(&tmp, bytes).and_then(|()| fs::rename(&tmp, &path)) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        Ok(path)
+    }
+
+    /// Moves `r.response` into its file and records the path as
+    /// `r.response_file`. Results without a response are left alone.
+    pub fn save(&self, r: &mut PortResult) -> io::Result<()> {
+        let Some(bytes) = r.response.take() else {
+            return Ok(());
+        };
+        r.response_file = Some(self.write(r.target, r.port, &bytes)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("responses-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn ipv6_targets_get_a_portable_directory() {
+        let store = ResponseStore::new("out");
+        assert_eq!(
+            store.path("fe80::1".parse().unwrap(), 443),
+            Path::new("out").join("fe80__1").join("443.bin")
+        );
+        assert_eq!(
+            store.path("10.0.0.1".parse().unwrap(), 22),
+            Path::new("out").join("10.0.0.1").join("22.bin")
+        );
+    }
+
+    #[test]
+    fn concurrent_writers_leave_one_whole_file() {
+        let dir = temp_dir("concurrent");
+        let store = Arc::new(ResponseStore::new(&dir));
+        let target: IpAddr = "127.0.0.1".parse().unwrap();
+        let writers: Vec<_> = (0..8u8)
+            .map(|n| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        store.write(target, 80, &vec![n; 100_000]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+
+        let bytes = fs::read(store.path(target, 80)).unwrap();
+        assert_eq!(bytes.len(), 100_000);
+        assert!(bytes.iter().all(|&b| b == bytes[0]));
+        let names: Vec<_> = fs::read_dir(dir.join("127.0.0.1"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["80.bin"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! A budget of retries for the whole scan (`--max-total-retries`).
+//!
+//! A port is connected to again when no local port was free to connect
+//! from, and with `--banner-retry` when its banner read failed. Each retry
+//! waits and connects once more, so against a host that keeps failing they
+//! can take longer than the scan itself. A [`RetryBudget`] counts them
+//! across every port and host; once it is spent, a port that would be
+//! retried keeps what its last attempt gave and is flagged
+//! [`retries_exhausted`](PortResult::retries_exhausted). A port stops at
+//! the first retry it is refused, so each refusal is one port.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::scanner::PortResult;
+
+/// The retries a scan may make in all, shared by every port.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    limit: u64,
+    counts: Arc<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    used: AtomicU64,
+    refused: AtomicU64,
+}
+
+/// How much of its retry budget a scan used, as recorded in the report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryBudgetReport {
+    /// The retries the scan was allowed.
+    pub limit: u64,
+    /// The retries it made.
+    pub used: u64,
+    /// Ports that would have been retried once the budget was spent.
+    pub exhausted_ports: u64,
+}
+
+impl RetryBudget {
+    /// A budget of `limit` retries.
+    pub fn new(limit: u64) -> Self {
+        RetryBudget {
+            limit,
+            counts: Arc::default(),
+        }
+    }
+
+    /// The limit, in retries.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The retries taken so far.
+    pub fn used(&self) -> u64 {
+        self.counts.used.load(Ordering::Relaxed)
+    }
+
+    /// Takes one retry from the budget; false, counting a refusal, once it
+    /// is spent.
+    pub fn take(&self) -> bool {
+        let taken = self
+            .counts
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < self.limit).then_some(used + 1)
+            })
+            .is_ok();
+        if !taken {
+            self.counts.refused.fetch_add(1, Ordering::Relaxed);
+        }
+        taken
+    }
+
+    /// What the scan used of the budget.
+    pub fn report(&self) -> RetryBudgetReport {
+        RetryBudgetReport {
+            limit: self.limit,
+            used: self.used(),
+            exhausted_ports: self.counts.refused.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl RetryBudgetReport {
+    /// The report with the exhausted ports counted from `results`, such as
+    /// those of a single host.
+    pub fn recount(&self, results: &[PortResult]) -> Self {
+        RetryBudgetReport {
+            exhausted_ports: results.iter().filter(|r| r.retries_exhausted).count() as u64,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_are_refused_once_the_budget_is_spent() {
+        let budget = RetryBudget::new(3);
+        let shared = budget.clone();
+        let taken = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| (0..5).filter(|_| shared.take()).count()))
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().unwrap())
+                .sum::<usize>()
+        });
+        assert_eq!(taken, 3);
+        assert_eq!(
+            budget.report(),
+            RetryBudgetReport {
+                limit: 3,
+                used: 3,
+                exhausted_ports: 17,
+            }
+        );
+        assert!(!RetryBudget::new(0).take());
+    }
+}
@@ -0,0 +1,1222 @@
+use colored::*;
+use futures::StreamExt;
+use indicatif::MultiProgress;
+use port_scanner::annotations::{Annotations, Deviation};
+use port_scanner::audit;
+use port_scanner::baseline::Baseline;
+use port_scanner::blocklist::Blocklist;
+use port_scanner::capture::CaptureBudget;
+use port_scanner::change::{self, ChangeState};
+use port_scanner::clock::{Clock, FixedClock, SystemClock};
+use port_scanner::cluster;
+use port_scanner::completion::{Completion, Shutdown};
+use port_scanner::console::{ConsoleMode, Layout};
+use port_scanner::email::Mailer;
+use port_scanner::exec::ExecHook;
+use port_scanner::geoip::GeoDb;
+use port_scanner::groups::PortGroups;
+use port_scanner::heatmap::{heatmap, HeatmapOptions};
+use port_scanner::identity::Identities;
+use port_scanner::jump::{JumpError, JumpHost};
+#[cfg(all(feature = "ssh-jump", unix))]
+use port_scanner::jump::{SshConnector, SshOptions};
+use port_scanner::limits::SystemLimits;
+use port_scanner::metadata::Metadata;
+use port_scanner::metrics::{self, ScanMetrics};
+use port_scanner::osguess::{self, OsRules};
+use port_scanner::output::{Finished, Outputs};
+use port_scanner::plan::TargetSet;
+use port_scanner::policy::{FailOn, Policy};
+use port_scanner::preset::{self, Presets};
+use port_scanner::probe::{HttpProbe, ProbeSelection};
+use port_scanner::proxy::ProxyChecker;
+use port_scanner::pushgateway::Pusher;
+use port_scanner::redact::{self, Redactor};
+use port_scanner::rescan::Previous;
+use port_scanner::responses::ResponseStore;
+use port_scanner::s3::Uploader;
+use port_scanner::scope;
+use port_scanner::script::Script;
+use port_scanner::shared;
+use port_scanner::spill::ResultLog;
+use port_scanner::stats::Phases;
+use port_scanner::targets;
+use port_scanner::traceroute;
+use port_scanner::verdict::Verdict;
+use port_scanner::vuln::VulnDb;
+use port_scanner::{
+    new_scan_id, PortError, PortResult, PortStatus, ScanError, ScanSummary, Scanner, ScannerBuilder,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::{self, JoinError, JoinSet};
+use tracing::{debug, error, info, warn};
+
+use crate::cli::{ScanArgs, Verbosity};
+use crate::printer::Printer;
+use crate::progress::{JsonProgress, Progress};
+use crate::tui::{self, Key, LogPane, Tui};
+
+/// Target/port pairs above which results are spilled to disk by default.
+const LOW_MEMORY_THRESHOLD: u64 = 1_000_000;
+
+/// Asks before scanning public internet addresses. Without a terminal to ask
+/// on, refuses: an unattended scan of the internet needs --allow-public.
+fn confirm_public(public: &TargetSet, ports: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let listed = public.to_string();
+    let listed = match listed.match_indices(',').nth(4) {
+        Some((at, _)) => format!("{}, ...", &listed[..at]),
+        None => listed,
+    };
+    let what = format!(
+        "{} public hosts ({}) on {} ports",
+        public.len(),
+        listed,
+        ports
+    );
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Err(format!(
+            "Refusing to scan {} without --allow-public (or --private-only to leave them out)",
+            what
+        )
+        .into());
+    }
+    eprint!("About to scan {}. Continue? [y/N] ", what);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    match answer.trim() {
+        "y" | "Y" | "yes" => Ok(()),
+        _ => Err("Not scanning public hosts".into()),
+    }
+}
+
+/// Runs a scan with `args`, showing progress in `multi` or, with --tui,
+/// the log in `pane`. `None` when the scan only listed something and
+/// scanned nothing.
+pub async fn run(
+    args: ScanArgs,
+    multi: MultiProgress,
+    pane: LogPane,
+) -> Result<Option<Verdict>, Box<dyn std::error::Error>> {
+    if args.tui && !std::io::stdout().is_terminal() {
+        return Err("--tui needs a terminal on stdout".into());
+    }
+    let run_started = Instant::now();
+    let clock: Arc<dyn Clock> = if args.deterministic {
+        Arc::new(args.fake_time.map_or_else(FixedClock::default, FixedClock))
+    } else {
+        Arc::new(SystemClock)
+    };
+    let metadata = Metadata::new(
+        args.operator.clone(),
+        args.reason.clone(),
+        args.tag.iter().cloned(),
+    )?;
+    let finds = args.vuln_db.is_some() || args.proxy_check || args.baseline.is_some();
+    if !finds && args.fail_on.iter().any(FailOn::needs_findings) {
+        return Err(
+            "--fail-on severity needs --vuln-db, --proxy-check or --baseline to report findings"
+                .into(),
+        );
+    }
+    let groups = PortGroups::with(&args.port_group)?;
+    let blocklist = args.blocklist.as_deref().map(Blocklist::load).transpose()?;
+    if args.list_presets {
+        let presets = Presets::load(preset::user_file().as_deref())?;
+        match std::io::stdout()
+            .lock()
+            .write_all(presets.to_string().as_bytes())
+        {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => return Ok(None),
+        }
+    }
+    if args.list_port_groups {
+        match std::io::stdout()
+            .lock()
+            .write_all(groups.to_string().as_bytes())
+        {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => return Ok(None),
+        }
+    }
+    let blocklist = blocklist.as_ref().zip(args.blocklist.as_deref());
+    let (mut targets, scanner) =
+        targets::Targets::resolve(&args.target_options(), blocklist, Scanner::builder()).await?;
+    let ports = targets::tcp_ports(&args.ports, args.exclude_ports.as_deref(), &groups)?;
+    let mut builder = configure(scanner.ports(ports), &args, clock.clone()).await?;
+    let arp = if args.arp_discovery && !args.dry_run {
+        targets::discover_hosts(&builder.clone().build()?.config().targets, args.arp_timeout).await
+    } else {
+        None
+    };
+    if let Some(arp) = arp.as_ref().filter(|_| !args.no_ping) {
+        builder = targets::without_silent(builder, arp);
+    }
+    let mut scanner = builder.clone().build()?;
+    let public = scope::public_hosts(&scanner.config().targets);
+    if !public.is_empty() {
+        if args.private_only {
+            info!("Leaving out {} public hosts (--private-only)", public.len());
+            scanner = builder.exclude_hosts(&public).build()?;
+        } else if !args.allow_public && !args.dry_run {
+            confirm_public(&public, scanner.config().ports.len())?;
+        }
+    }
+    let console = ConsoleMode::from_flags(
+        args.console,
+        args.verbose == Verbosity::Verbose,
+        args.quiet,
+        args.tui,
+    );
+    if args.collapse_runs && console != ConsoleMode::Detailed {
+        warn!("--collapse-runs only applies to the detailed console output");
+    }
+    let config = scanner.config();
+    for ports in config.port_timeouts.unused(&config.ports) {
+        if ports.start() == ports.end() {
+            warn!(
+                "--port-timeout for port {} which is not scanned",
+                ports.start()
+            );
+        } else {
+            warn!(
+                "--port-timeout for ports {}-{} none of which are scanned",
+                ports.start(),
+                ports.end()
+            );
+        }
+    }
+
+    if args.dry_run {
+        let mut plan = scanner.config().describe();
+        plan.preset = args.applied_preset.clone();
+        if let Some(window) = &args.window {
+            plan.rate_limits.window = Some(window.to_string());
+            plan.rate_limits.until = window
+                .next_change(clock.now())
+                .map(|t| window.clock_time(t));
+        }
+        let text = match args.json.as_deref() {
+            Some(path) if path.as_os_str() == "-" => serde_json::to_string_pretty(&plan)? + "\n",
+            Some(path) => {
+                std::fs::write(path, serde_json::to_string_pretty(&plan)?)?;
+                info!("Saved plan: {}", path.display());
+                String::new()
+            }
+            None => format!("{}\n", plan),
+        };
+        match std::io::stdout().lock().write_all(text.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+        return Ok(None);
+    }
+    let hosts = scanner.config().targets.clone();
+    let multi_target = hosts.len() > 1;
+    let target_label = hosts.to_string();
+    info!("Starting scan on {}", target_label.bold());
+
+    let port_count = scanner.config().ports.len();
+    if multi_target {
+        info!("Scanning {} ports on {} hosts", port_count, hosts.len());
+    } else {
+        info!("Scanning {} ports", port_count);
+    }
+
+    let metrics = scanner.metrics();
+
+    let started_at = clock.now();
+    // The usual id includes the process id; a reproducible one can only
+    // come from the fake time.
+    let scan_id = if args.deterministic {
+        format!("{:x}", started_at.timestamp_millis())
+    } else {
+        new_scan_id()
+    };
+    info!(
+        target: audit::TARGET,
+        scan_id = %scan_id,
+        command = %audit::command_line(std::env::args()),
+        hosts = hosts.len() as u64,
+        ports = port_count,
+        concurrency = scanner.config().concurrency,
+        timeout_ms = scanner.config().timeout.as_millis() as u64,
+        operator = metadata.operator.as_deref(),
+        reason = metadata.reason.as_deref(),
+        tags = (!metadata.tags.is_empty()).then(|| {
+            let tags: Vec<String> = metadata.tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            tags.join(",")
+        }),
+        "scan started"
+    );
+    let metrics_server = match args.metrics_listen {
+        Some(addr) => {
+            let handle = metrics::serve(addr, metrics.clone()).await?;
+            info!("Serving metrics on http://{}/metrics", addr);
+            Some(handle)
+        }
+        None => None,
+    };
+
+    let pusher = args.pushgateway.as_ref().map(|url| {
+        let pusher = Pusher::new(url, &target_label, &scan_id, &metadata);
+        match &args.pushgateway_username {
+            Some(user) => {
+                pusher.basic_auth(user, args.pushgateway_password.as_deref().unwrap_or(""))
+            }
+            None => pusher,
+        }
+    });
+    let push_task = pusher
+        .as_ref()
+        .zip(args.pushgateway_interval)
+        .map(|(pusher, every)| pusher.push_every(metrics.clone(), every));
+
+    let planning_ms = clock.elapsed(run_started).as_millis() as u64;
+    let start_time = Instant::now();
+
+    let printer = (console != ConsoleMode::Silent).then(|| {
+        let layout = Layout {
+            show_target: multi_target,
+            fields: args.fields.clone(),
+        };
+        Printer::start(multi.clone(), console, layout, args.collapse_runs)
+    });
+    let progress = (!args.quiet && !args.tui).then(|| {
+        Progress::new(
+            multi,
+            &hosts,
+            scanner.config().ports.len() as u64,
+            metrics.clone(),
+            args.status_interval.max(Duration::from_secs(1)),
+        )
+    });
+    let progress_json = match &args.progress_json {
+        Some(sink) => Some(
+            JsonProgress::start(
+                sink,
+                &hosts,
+                scanner.config().ports.len() as u64,
+                metrics.clone(),
+                args.progress_json_interval.max(Duration::from_millis(100)),
+            )
+            .await
+            .map_err(|e| format!("--progress-json {}: {}", sink, e))?,
+        ),
+        None => None,
+    };
+
+    let mut shutdown = Shutdown::new(scanner.cancel_token());
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let deadline = tokio::time::sleep(args.max_scan_time.unwrap_or(Duration::MAX));
+    tokio::pin!(deadline);
+
+    let vuln_db = args.vuln_db.as_deref().map(VulnDb::load).transpose()?;
+    if let Some(db) = &vuln_db {
+        info!("Loaded {} advisories", db.len());
+    }
+    let annotations = args
+        .annotations
+        .as_deref()
+        .map(Annotations::load)
+        .transpose()?;
+    if let Some(a) = &annotations {
+        info!("Loaded {} annotations", a.len());
+    }
+    let previous = args.from.as_deref().map(Previous::load).transpose()?;
+    if let (Some(p), Some(path)) = (&previous, &args.from) {
+        info!(
+            "Loaded {} results of an earlier scan from {}",
+            p.len(),
+            path.display()
+        );
+        if let Some(warning) = p.mismatch(args.identity_by) {
+            warn!("{}", warning);
+        }
+    }
+    let baseline = args.baseline.as_deref().map(Baseline::load).transpose()?;
+    if let (Some(b), Some(path)) = (&baseline, &args.baseline) {
+        info!(
+            "Loaded a baseline of {} expected open ports",
+            b.expected_open()
+        );
+        if let Some(warning) = b.mismatch(&path.display().to_string(), args.identity_by) {
+            warn!("{}", warning);
+        }
+    }
+    let mut script = args.script.as_deref().map(Script::load).transpose()?;
+    let exec_hook = args
+        .exec
+        .as_deref()
+        .map(|template| {
+            ExecHook::new(
+                template,
+                args.exec_shell,
+                args.exec_concurrency,
+                args.exec_timeout,
+            )
+        })
+        .transpose()?;
+
+    let os_rules = match (&args.os_rules, args.os_guess) {
+        (Some(path), _) => Some(OsRules::load(path)?),
+        (None, true) => Some(OsRules::builtin()),
+        (None, false) => None,
+    };
+
+    let mailer = match &args.smtp_server {
+        Some(server) => Some(
+            Mailer::new(server, &args.email_from, args.email_to.clone()).secured(
+                args.smtp_starttls,
+                args.smtp_username.clone(),
+                args.smtp_password.clone(),
+            )?,
+        ),
+        None => None,
+    };
+    let uploader = args
+        .upload_s3
+        .as_ref()
+        .map(|url| Uploader::for_url(url, args.s3_endpoint.clone()))
+        .transpose()
+        .map_err(|e| format!("--upload-s3: {}", e))?;
+
+    let geo = GeoDb::open(args.geoip_db.as_deref(), args.asn_db.as_deref());
+    let output_options = args.output_options();
+    // With --write-on-change the reports wait for the end of the scan, when
+    // it is known whether they are wanted at all.
+    let change_state = match &args.write_on_change {
+        Some(path) => ChangeState::load(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => None,
+    };
+    let mut outputs = if args.write_on_change.is_some() {
+        Outputs::new()
+    } else {
+        output_options.open(&hosts, started_at, &scan_id, &metadata, geo.clone())?
+    };
+
+    let responses = args.save_responses.as_ref().map(ResponseStore::new);
+    let proxy_checker = if args.proxy_check {
+        let checker = ProxyChecker::start(
+            args.proxy_ports.clone(),
+            args.proxy_check_url.clone(),
+            args.timeout,
+        )
+        .await?;
+        if let Some(port) = checker.callback_port() {
+            info!("Proxy check callback listening on port {}", port);
+        }
+        Some(Arc::new(checker))
+    } else {
+        None
+    };
+
+    // Results failing the --filter are counted but neither printed nor
+    // written.
+    let shown = |r: &PortResult| args.filter.as_ref().is_none_or(|f| f.matches(r));
+
+    // Open ports with an --exec hook or a --proxy-check are held back until
+    // the follow-up finishes, so every writer sees its result.
+    let mut follow_ups: JoinSet<PortResult> = JoinSet::new();
+    let mut pending_follow_ups: HashMap<task::Id, PortResult> = HashMap::new();
+
+    // A re-scan merges the earlier results into those in memory.
+    let low_memory = args.low_memory
+        || (!args.deterministic
+            && previous.is_none()
+            && scanner.total_ports() > LOW_MEMORY_THRESHOLD);
+    let results = if low_memory {
+        if !args.low_memory {
+            info!(
+                "Scanning {} ports, spilling results to disk (--low-memory)",
+                scanner.total_ports()
+            );
+        }
+        ResultLog::spill_in(&std::env::temp_dir())?
+    } else {
+        ResultLog::in_memory()
+    };
+    let mut results = match &scanner.config().capture_budget {
+        Some(budget) => results.release_to(budget.clone()),
+        None => results,
+    };
+    let mut stream = scanner.scan();
+    let (mut tui, mut keys) = if args.tui {
+        let (tui, keys) = Tui::start(
+            stream.throttle(),
+            metrics.clone(),
+            scanner.total_ports(),
+            pane,
+        )?;
+        (Some(tui), Some(keys))
+    } else {
+        (None, None)
+    };
+    let mut redraw = tokio::time::interval(Duration::from_millis(100));
+    // The window is looked at again when it should open or close, and at
+    // least every minute in case the clock jumps.
+    let window_check = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(window_check);
+    let mut window_open = None;
+    let mut paused_since: Option<tokio::time::Instant> = None;
+    let mut quit = false;
+    let mut scanning = true;
+    while scanning || !follow_ups.is_empty() {
+        tokio::select! {
+            r = stream.next(), if scanning => {
+                let Some(mut r) = r else {
+                    scanning = false;
+                    continue;
+                };
+                if let Some(p) = &progress {
+                    p.inc(Some(r.target));
+                }
+                if let Some(events) = &progress_json {
+                    events.inc(r.target);
+                }
+                if let Some(db) = &vuln_db {
+                    db.tag(&mut r);
+                }
+                if let Some(a) = &annotations {
+                    a.apply(&mut r);
+                    // Closed where filtered was expected is only counted;
+                    // open where it shouldn't be, or the reverse, is news.
+                    let deviation = Deviation::of(&r).filter(|d| *d != Deviation::Other);
+                    if let (Some(_), Some(expected)) = (deviation, &r.expected_status) {
+                        warn!(
+                            "{} is {}, expected {}{}",
+                            SocketAddr::new(r.target, r.port),
+                            r.status,
+                            expected,
+                            r.note.as_ref().map_or(String::new(), |n| format!(" ({})", n))
+                        );
+                    }
+                }
+                if let Some(script) = &mut script {
+                    script.on_open(&mut r);
+                    if !script.keep(&r) {
+                        continue;
+                    }
+                }
+                if let Some(store) = &responses {
+                    if let Err(e) = store.save(&mut r) {
+                        warn!(
+                            "Could not save the response of {}: {}",
+                            SocketAddr::new(r.target, r.port),
+                            e
+                        );
+                    }
+                }
+                if let Some(tui) = &mut tui {
+                    tui.record(&r);
+                } else if let Some(printer) = printer.as_ref().filter(|_| shown(&r)) {
+                    printer.print(&r);
+                }
+                let hook = exec_hook.clone().filter(|_| r.status == PortStatus::Open);
+                let checker = proxy_checker.clone().filter(|c| c.applies(&r));
+                if (hook.is_some() || checker.is_some()) && !shutdown.is_stopping() {
+                    let held = r.clone();
+                    let handle = follow_ups.spawn(async move {
+                        let mut r = r;
+                        if let Some(checker) = checker {
+                            checker.check(&mut r).await;
+                        }
+                        if let Some(hook) = hook {
+                            r.exec = Some(hook.run(&r).await);
+                        }
+                        r
+                    });
+                    pending_follow_ups.insert(handle.id(), held);
+                } else {
+                    if !args.deterministic && shown(&r) {
+                        outputs.write_result(&r);
+                    }
+                    if let Err(e) = results.push(r) {
+                        let what = format!("Cannot spill the results: {}", e);
+                        let why = Completion::error(&what);
+                        stop_scan(&mut shutdown, &mut follow_ups, &what, why);
+                    }
+                }
+            }
+            Some(done) = follow_ups.join_next_with_id() => {
+                let r = follow_up_finished(done, &mut pending_follow_ups);
+                if !args.deterministic && shown(&r) {
+                    outputs.write_result(&r);
+                }
+                if let Err(e) = results.push(r) {
+                    let what = format!("Cannot spill the results: {}", e);
+                    let why = Completion::error(&what);
+                    stop_scan(&mut shutdown, &mut follow_ups, &what, why);
+                }
+            }
+            _ = &mut ctrl_c, if !shutdown.is_stopping() => {
+                let why = Completion::cancelled("Ctrl-C");
+                stop_scan(&mut shutdown, &mut follow_ups, "Interrupted", why);
+            }
+            _ = &mut deadline, if !shutdown.is_stopping() && paused_since.is_none() => {
+                let limit = args.max_scan_time.expect("a deadline needs --max-scan-time");
+                let why = Completion::deadline(limit);
+                stop_scan(&mut shutdown, &mut follow_ups, "Reached --max-scan-time", why);
+            }
+            Some(key) = tui::next_key(&mut keys) => {
+                if let Some(Key::Quit) = tui.as_mut().map(|t| t.on_key(key)) {
+                    let why = Completion::cancelled("quit from the TUI");
+                    stop_scan(&mut shutdown, &mut follow_ups, "Quit from the TUI", why);
+                    quit = true;
+                }
+            }
+            _ = &mut window_check, if scanning && args.window.is_some() => {
+                let window = args.window.as_ref().expect("checked with a --window");
+                let now = clock.now();
+                let open = window.contains(now);
+                let next = window.next_change(now);
+                let until = next.map_or("further notice".to_string(), |t| window.clock_time(t));
+                if window_open != Some(open) {
+                    window_open = Some(open);
+                    if open {
+                        stream.throttle().resume();
+                        // --max-scan-time only counts the time spent scanning.
+                        if let Some(since) = paused_since.take() {
+                            if args.max_scan_time.is_some() {
+                                let extended = deadline.deadline() + since.elapsed();
+                                deadline.as_mut().reset(extended);
+                            }
+                        }
+                        info!("Scan window {} is open, scanning until {}", window, until);
+                        if let Some(p) = &progress {
+                            p.set_paused(None);
+                        }
+                    } else {
+                        stream.throttle().pause();
+                        paused_since = Some(tokio::time::Instant::now());
+                        info!("Outside the scan window {}, pausing until {}", window, until);
+                        if let Some(p) = &progress {
+                            p.set_paused(Some(format!("paused until {}", until)));
+                        }
+                    }
+                }
+                let wait = next
+                    .and_then(|t| (t - now).to_std().ok())
+                    .map_or(Duration::from_secs(60), |d| d.min(Duration::from_secs(60)));
+                window_check.as_mut().reset(tokio::time::Instant::now() + wait);
+            }
+            _ = redraw.tick(), if tui.is_some() => {
+                if let Some(tui) = &mut tui {
+                    tui.draw()?;
+                }
+            }
+        }
+    }
+    // The finished scan stays on screen until the user is done with it.
+    if let Some(tui) = &mut tui {
+        tui.finish();
+        while !quit {
+            tokio::select! {
+                key = tui::next_key(&mut keys) => match key {
+                    Some(key) => quit = matches!(tui.on_key(key), Key::Quit),
+                    None => quit = true,
+                },
+                _ = redraw.tick() => tui.draw()?,
+            }
+        }
+    }
+    drop(tui);
+    drop(keys);
+    let interrupted = stream.is_cancelled();
+    let completion = shutdown.completion();
+
+    if let Some(printer) = printer {
+        printer.finish().await;
+    }
+    if let Some(p) = progress {
+        p.finish(interrupted);
+    }
+    if interrupted {
+        warn!("{} results are partial", results.len());
+    }
+
+    // Streaming writers get the results only now, in canonical order.
+    if args.deterministic {
+        let results = results.kept_mut();
+        results.sort_by_key(|r| (r.target, r.port, r.protocol));
+        for r in results.iter().filter(|r| shown(r)) {
+            outputs.write_result(r);
+        }
+    }
+
+    let total_time = clock.elapsed(start_time).as_millis();
+    metrics.finish(total_time);
+    let scan_finished = Instant::now();
+
+    let mut summary = results.into_summary(
+        scan_id,
+        target_label.clone(),
+        total_time,
+        &metrics,
+        interrupted,
+    )?;
+    summary.completion = Some(completion.clone());
+    summary.blocked_hosts = targets.blocked_hosts;
+    summary.filter = args.filter.clone();
+    summary.metadata = metadata;
+    summary.preset = args.applied_preset.clone();
+    summary.established = scanner.config().established.as_ref().map(|e| e.report());
+    summary.retry_budget = scanner.config().retry_budget.as_ref().map(|b| b.report());
+    if let Some(b) = summary
+        .retry_budget
+        .as_ref()
+        .filter(|b| b.exhausted_ports > 0)
+    {
+        warn!(
+            "Retry budget of {} spent: {} ports were not retried",
+            b.limit, b.exhausted_ports
+        );
+    }
+    if args.heatmap {
+        let options = HeatmapOptions {
+            bins: args.heatmap_bins.clone(),
+            port_width: args.heatmap_port_width,
+            ..HeatmapOptions::default()
+        };
+        let map = heatmap(summary.all_results()?.flatten(), &options);
+        summary.heatmap = Some(map);
+    }
+    summary.report_targets(std::mem::take(&mut targets.reports), &hosts);
+    info!(
+        target: audit::TARGET,
+        scan_id = %summary.scan_id,
+        open = summary.open_ports,
+        closed = summary.closed_ports,
+        filtered = summary.filtered_ports,
+        unscanned = summary.unscanned_ports,
+        interrupted,
+        duration_ms = total_time as u64,
+        "scan finished"
+    );
+
+    let named = targets.named(&args.target);
+    let identities = Identities::of_resolutions(args.identity_by, &named);
+    summary.identity = args.identity_by;
+
+    // The earlier results carried forward are checked like the others.
+    if let Some(previous) = previous {
+        let rescan = previous.merge(&mut summary, &identities);
+        info!("{}", rescan);
+        for r in summary.results.iter().filter(|r| r.stale.is_some()) {
+            if shown(r) {
+                outputs.write_result(r);
+            }
+        }
+        summary.rescan = Some(rescan);
+    }
+
+    // Before the policy, so that --fail-on severity: sees drift findings.
+    if let (Some(b), Some(path)) = (&baseline, &args.baseline) {
+        let severities = args.drift_severity.unwrap_or_default();
+        summary.compliance = Some(b.check(
+            &path.display().to_string(),
+            &mut summary,
+            &severities,
+            &identities,
+        )?);
+    }
+
+    Policy {
+        fail_on: args.fail_on.clone(),
+        allow_open: args.allow_open.clone(),
+    }
+    .apply(&mut summary);
+
+    let shared = shared::share(summary.all_results()?.flatten(), args.shared_responses);
+    summary.shared_responses = shared;
+    for shared in summary.shared_responses.values() {
+        for host in &shared.hosts {
+            warn!(
+                "{}: more than {} open ports answered with the same {}-byte response, probably a middlebox (transparent proxy or captive portal)",
+                host,
+                args.shared_responses,
+                shared.banner.len()
+            );
+        }
+    }
+
+    let routes = if args.traceroute && !summary.interrupted {
+        traceroute::trace_hosts(&summary.results, args.max_hops, args.timeout).await
+    } else {
+        HashMap::new()
+    };
+    let host_times = metrics.host_times();
+    let os_guesses = match &os_rules {
+        Some(rules) if !summary.interrupted => {
+            osguess::guess_hosts(&summary.results, rules, args.timeout).await
+        }
+        _ => HashMap::new(),
+    };
+    if geo.is_some()
+        || !named.is_empty()
+        || !routes.is_empty()
+        || arp.is_some()
+        || !targets.devices.is_empty()
+        || !targets.neighbors.is_empty()
+        || !os_guesses.is_empty()
+        || !host_times.is_empty()
+        || !targets.overlaps.is_empty()
+        || !summary.shared_responses.is_empty()
+    {
+        summary.annotate_hosts(geo.as_deref(), &named);
+        summary.identify(&identities);
+        for host in &mut summary.hosts {
+            host.route = routes.get(&host.address).cloned().unwrap_or_default();
+            let neighbor = targets
+                .neighbors
+                .iter()
+                .find(|n| IpAddr::V6(n.address) == host.address);
+            let mac = match (host.address, &arp) {
+                (IpAddr::V4(ip), Some(arp)) => arp.found.get(&ip).copied(),
+                _ => neighbor.and_then(|n| n.mac),
+            };
+            host.vendor = mac.and_then(|m| m.vendor()).map(str::to_string);
+            host.mac = mac;
+            host.interface = neighbor.map(|n| n.interface.clone());
+            host.device = targets.devices.get(&host.address).cloned();
+            host.os_guess = os_guesses.get(&host.address).cloned();
+            host.timing = host_times.get(&host.address).copied();
+        }
+    }
+
+    summary.clusters = cluster::assign(&mut summary.results);
+    for c in &summary.clusters {
+        info!("Cluster {}: {}", c.id, c);
+    }
+
+    if let Some(script) = &mut script {
+        summary.script_summary = script.summarize(&summary);
+        if let Some(text) = &summary.script_summary {
+            println!("{}", text);
+        }
+    }
+
+    log_outcome(&summary, &metrics, total_time, &completion)?;
+
+    if let Some(stats) = &mut summary.stats {
+        stats.phases_ms = Some(Phases {
+            planning: planning_ms,
+            scanning: total_time as u64,
+            verification: clock.elapsed(scan_finished).as_millis() as u64,
+            output: None,
+        });
+    }
+    let output_started = Instant::now();
+
+    let mut unchanged = false;
+    if let Some(path) = &args.write_on_change {
+        let fingerprint =
+            change::fingerprint(summary.all_results()?.collect::<Result<Vec<_>, _>>()?);
+        unchanged = !interrupted && ChangeState::unchanged(change_state.as_ref(), &fingerprint);
+        if unchanged {
+            let state = change_state.as_ref().expect("unchanged from a saved state");
+            info!(
+                "Results unchanged since scan {} ({}), not writing reports",
+                state.changed_scan_id, fingerprint
+            );
+        } else {
+            outputs = output_options.open(
+                &hosts,
+                started_at,
+                &summary.scan_id,
+                &summary.metadata,
+                geo.clone(),
+            )?;
+            for r in summary.reported_results()? {
+                outputs.write_result(r?.as_ref());
+            }
+        }
+        // A partial scan is no baseline for the next one.
+        if !interrupted {
+            ChangeState::next(change_state, &fingerprint, &summary.scan_id, clock.now())
+                .save(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+    }
+    let output_count = outputs.len();
+    let Finished { files, failures } = outputs.finish(&summary);
+    for f in &failures {
+        error!("Failed to write {}: {}", f.name, f.error);
+    }
+    if let Some(events) = progress_json {
+        let reports: Vec<_> = files.iter().map(|f| f.path.clone()).collect();
+        let summary = args.json.clone().filter(|path| reports.contains(path));
+        events.finish(interrupted, summary, reports).await;
+    }
+    let mut upload_failures = 0;
+    if let (Some(uploader), Some(url), false) = (&uploader, &args.upload_s3, unchanged) {
+        upload_failures = uploader
+            .upload_reports(url, &args.s3_key_template, &files, &summary, started_at)
+            .await;
+    }
+    // A report that couldn't be mailed is logged, not an exit code: the
+    // scan itself went fine.
+    if let Some(mailer) = mailer.as_ref().filter(|_| !unchanged) {
+        if summary.open_ports > 0 || args.email_always {
+            mailer.mail_report(&summary, args.fields.as_deref()).await;
+        } else {
+            info!("No open ports, not mailing the report");
+        }
+    }
+
+    if let Some(stats) = &mut summary.stats {
+        if let Some(phases) = &mut stats.phases_ms {
+            phases.output = Some(clock.elapsed(output_started).as_millis() as u64);
+        }
+        eprintln!("Stats:\n{}", stats);
+    }
+
+    if let Some(ref path) = args.metrics_textfile {
+        metrics::write_textfile(path, &metrics)?;
+        info!("Saved metrics: {}", path.display());
+    }
+
+    if let Some(pusher) = &pusher {
+        if let Some(task) = push_task {
+            task.abort();
+        }
+        pusher.finish(&metrics, args.pushgateway_delete).await;
+    }
+
+    if let Some(handle) = metrics_server {
+        handle.abort();
+    }
+    if !failures.is_empty() {
+        let names: Vec<&str> = failures.iter().map(|f| f.name.as_str()).collect();
+        return Err(ScanError::OutputWrite(format!(
+            "{} of {} outputs ({})",
+            failures.len(),
+            output_count,
+            names.join(", ")
+        ))
+        .into());
+    }
+    if upload_failures > 0 && args.upload_required {
+        return Err(ScanError::OutputWrite(format!(
+            "{} of {} reports to {}",
+            upload_failures,
+            files.len(),
+            args.upload_s3.as_ref().expect("uploads need --upload-s3")
+        ))
+        .into());
+    }
+
+    Ok(Some(Verdict::of(
+        &summary,
+        args.fail_on_drift,
+        args.exec_fail_fatal,
+    )))
+}
+
+/// Sets the scan options of `args` on `builder`, beyond its targets and
+/// ports.
+async fn configure(
+    builder: ScannerBuilder,
+    args: &ScanArgs,
+    clock: Arc<dyn Clock>,
+) -> Result<ScannerBuilder, Box<dyn std::error::Error>> {
+    let mut builder = builder
+        .concurrency(SystemLimits::detect().tune(args.concurrency, args.auto_tune))
+        .probe_concurrency(args.probe_concurrency)
+        .timeout(args.timeout)
+        .port_timeouts(args.port_timeout.clone().unwrap_or_default())
+        .banner_timeout(args.banner_timeout)
+        .probe_budget(args.probe_budget)
+        .probe_timeouts(args.probe_timeout.clone().unwrap_or_default())
+        .probe_selection(ProbeSelection {
+            only: args.only_probe.clone(),
+            disabled: args.disable_probe.clone(),
+        })
+        .clock(clock.clone());
+    #[cfg(windows)]
+    if args.timeout < port_scanner::scanner::WINDOWS_REFUSED_DELAY {
+        warn!(
+            "Windows takes about {}ms to report a closed port; with --timeout {}ms closed ports are reported filtered",
+            port_scanner::scanner::WINDOWS_REFUSED_DELAY.as_millis(),
+            args.timeout.as_millis()
+        );
+    }
+    if args.banner_retry {
+        builder = builder.banner_retry();
+    }
+    if let Some(retries) = args.max_total_retries {
+        builder = builder.max_total_retries(retries);
+    }
+    if args.http_probe {
+        builder = builder.probe(HttpProbe);
+    }
+    if args.sniff {
+        builder = builder.sniff();
+        for &rung in &args.probe_ladder {
+            builder = builder.probe(rung);
+        }
+    }
+    if args.save_responses.is_some() {
+        builder = builder.response_cap(args.response_cap);
+    }
+    if args.audit {
+        builder = builder.audit();
+    }
+    if args.evidence || matches!(args.verbose, Verbosity::Verbose | Verbosity::Debug) {
+        builder = builder.evidence();
+    }
+    if args.stats {
+        builder = builder.stats();
+    }
+    if args.retime_outliers {
+        builder = builder.retime_outliers();
+    }
+    if args.heatmap {
+        builder = builder.record_timings();
+    }
+    if args.linger_zero {
+        builder = builder.zero_linger();
+    }
+    if let Some(limit) = args.max_capture_mem {
+        builder = builder.capture_budget(CaptureBudget::new(limit));
+    }
+    if let Some(jump) = args.ssh_jump.clone().filter(|_| !args.dry_run) {
+        builder = builder.connector(jump_connector(jump, args).await?);
+    }
+    if let Some(values) = &args.redact {
+        let patterns: Vec<String> = values
+            .iter()
+            .flat_map(|v| redact::split_patterns(v))
+            .collect();
+        let mut redactor = Redactor::new(&patterns)?;
+        if args.redact_raw {
+            redactor = redactor.raw();
+        }
+        builder = builder.redact(redactor);
+    }
+    if args.no_unreachable_cache {
+        builder = builder.no_unreachable_cache();
+    }
+    if let Some(hosts) = args.host_concurrency {
+        builder = builder.host_concurrency(hosts);
+    }
+    if let Some(connections) = args.per_host_concurrency {
+        builder = builder.per_host_concurrency(connections);
+    }
+    if let Some(connections) = args.max_established_per_host {
+        builder = builder.max_established_per_host(connections.get());
+    }
+    if let Some(connections) = args.max_established {
+        builder = builder.max_established(connections.get());
+    }
+    if let Some(window) = &args.window {
+        builder = builder.start_paused(!window.is_open(clock.as_ref()));
+    }
+    Ok(builder)
+}
+
+/// Logs what came of the scan: what went wrong, what it found and how long
+/// it took.
+fn log_outcome(
+    summary: &ScanSummary,
+    metrics: &ScanMetrics,
+    total_time: u128,
+    completion: &Completion,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if summary.unscanned_ports > 0 {
+        let reasons: Vec<String> = summary
+            .unscanned_reasons()
+            .iter()
+            .map(|(reason, n)| format!("{} x {}", n, reason))
+            .collect();
+        warn!(
+            "{} ports could not be scanned: {}",
+            summary.unscanned_ports,
+            reasons.join(", ")
+        );
+    }
+
+    let mut unreachable = BTreeSet::new();
+    let mut cached = 0;
+    for r in summary.all_results()?.flatten() {
+        if let Some(PortError::UnreachableCached(_)) = r.error {
+            unreachable.insert(r.target);
+            cached += 1;
+        }
+    }
+    if cached > 0 {
+        info!(
+            "Skipped {} ports on {} unreachable hosts (--no-unreachable-cache probes them all)",
+            cached,
+            unreachable.len()
+        );
+    }
+
+    let failed_targets: Vec<&str> = summary
+        .failed_targets()
+        .map(|t| t.target.as_str())
+        .collect();
+    if !failed_targets.is_empty() {
+        warn!(
+            "{} of {} targets could not be scanned: {}",
+            failed_targets.len(),
+            summary.targets.len(),
+            failed_targets.join(", ")
+        );
+    }
+    if summary.reset_on_read_ports > 0 {
+        warn!(
+            "{} open ports reset the connection as soon as it was read; an inline IPS may be interfering",
+            summary.reset_on_read_ports
+        );
+    }
+    if summary.capture_truncated_ports > 0 {
+        warn!(
+            "{} results were cut to previews once --max-capture-mem was spent",
+            summary.capture_truncated_ports
+        );
+    }
+    if let Some(established) = &summary.established {
+        info!(
+            "At most {} connections were established at once, {} to any one host",
+            established.peak,
+            established.hosts.values().max().unwrap_or(&0)
+        );
+    }
+
+    info!(
+        "Done. Open: {}, Closed: {}, Filtered: {}, Time: {} ms",
+        summary.open_ports.to_string().bright_green(),
+        summary.closed_ports,
+        summary.filtered_ports.to_string().yellow(),
+        total_time
+    );
+    if summary.findings.total() > 0 {
+        warn!("Findings: {}", summary.findings);
+    }
+    if let Some(deviations) = &summary.deviations {
+        if deviations.total() > 0 {
+            warn!("{}", deviations);
+        } else {
+            info!("{}", deviations);
+        }
+    }
+    if let Some(compliance) = &summary.compliance {
+        if compliance.passed() {
+            info!("Compliance: {}", compliance);
+        } else {
+            warn!("Compliance: {}", compliance);
+        }
+    }
+    info!(
+        "Scanned {} ports in {:.1}s ({:.0} ports/s, {} open-port probes, {} banners), {}",
+        metrics.scanned(),
+        total_time as f64 / 1000.0,
+        summary.ports_per_second,
+        summary.banner_probes,
+        format_bytes(summary.banner_bytes),
+        completion
+    );
+    Ok(())
+}
+
+/// Stops the scan for `why`, saying `what` happened, unless it is
+/// stopping already; the follow-ups of its results stop with it.
+fn stop_scan(
+    shutdown: &mut Shutdown,
+    follow_ups: &mut JoinSet<PortResult>,
+    what: &str,
+    why: Completion,
+) {
+    if shutdown.stop(why) {
+        warn!("{}, stopping the scan", what);
+        follow_ups.abort_all();
+    }
+}
+
+/// Takes a finished follow-up task's result (--proxy-check, --exec), logging
+/// the command's outcome. An aborted task yields the held-back result as it
+/// was before the follow-ups ran.
+fn follow_up_finished(
+    done: Result<(task::Id, PortResult), JoinError>,
+    pending: &mut HashMap<task::Id, PortResult>,
+) -> PortResult {
+    let (id, r) = match done {
+        Ok(done) => done,
+        Err(e) => {
+            let r = pending
+                .remove(&e.id())
+                .expect("every follow-up task is tracked");
+            warn!(
+                "Follow-up of {} did not finish: {}",
+                SocketAddr::new(r.target, r.port),
+                e
+            );
+            return r;
+        }
+    };
+    pending.remove(&id);
+    let Some(exec) = &r.exec else {
+        return r;
+    };
+    let addr = SocketAddr::new(r.target, r.port);
+    if exec.succeeded() {
+        info!("{}: `{}` succeeded", addr, exec.command);
+    } else if exec.timed_out {
+        warn!("{}: `{}` timed out", addr, exec.command);
+    } else if let Some(e) = &exec.error {
+        warn!("{}: `{}` could not run: {}", addr, exec.command, e);
+    } else {
+        warn!(
+            "{}: `{}` exited with {}",
+            addr,
+            exec.command,
+            exec.exit_code
+                .map_or("a signal".to_string(), |c| format!("status {}", c))
+        );
+    }
+    for line in exec.stdout.lines() {
+        debug!("{}: {}", addr, line);
+    }
+    r
+}
+
+/// Checks the --ssh-jump host, before anything is scanned through it.
+#[cfg(all(feature = "ssh-jump", unix))]
+async fn jump_connector(jump: JumpHost, args: &ScanArgs) -> Result<SshConnector, JumpError> {
+    info!("Connecting to jump host {}", jump);
+    let options = SshOptions {
+        program: args.ssh_command.clone(),
+        identity: args.ssh_identity.clone(),
+        channels: args.ssh_channels,
+    };
+    SshConnector::start(jump, options).await
+}
+
+#[cfg(not(all(feature = "ssh-jump", unix)))]
+async fn jump_connector(
+    _: JumpHost,
+    _: &ScanArgs,
+) -> Result<port_scanner::connector::TcpConnector, JumpError> {
+    Err(JumpError::Unsupported)
+}
+
+/// Formats a byte count with binary units, e.g. `1.2 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
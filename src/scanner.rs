@@ -0,0 +1,4723 @@
+//! The scanning engine: configuration, the [`Scanner`] itself and the result
+//! types it produces.
+
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use colored::*;
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinError, JoinHandle, JoinSet};
+use tokio::time::{timeout, timeout_at};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, debug_span, error, info, info_span, trace, warn, Instrument};
+
+use crate::annotations::DeviationCounts;
+use crate::audit;
+use crate::banner::{read_banner, read_rest, Banner, BannerBuffers};
+use crate::baseline::Compliance;
+use crate::capture::CaptureBudget;
+use crate::charset::BannerEncoding;
+use crate::clock::{Clock, SystemClock};
+use crate::cluster::Cluster;
+use crate::completion::Completion;
+use crate::connector::{Connection, Connector, TcpConnector};
+use crate::discover::Device;
+use crate::established::{EstablishedLimit, EstablishedReport};
+use crate::evidence::{Evidence, Outcome, Step};
+use crate::exec::ExecResult;
+use crate::filter::Filter;
+use crate::geoip::{GeoDb, GeoInfo};
+use crate::heatmap::{Heatmap, Timing};
+use crate::identity::{Identities, IdentityMode};
+use crate::latency::{HostLatencies, Retime};
+use crate::metadata::Metadata;
+use crate::metrics::{HostTime, ScanMetrics};
+use crate::osguess::OsGuess;
+use crate::oui::MacAddr;
+use crate::plan::{Plan, PlanSummary, PortSet, Ports, RateLimits, TargetSet};
+use crate::policy::PolicyResult;
+use crate::preset::AppliedPreset;
+use crate::probe::{Probe, ProbeSelection, ProbeTimeouts, Transport};
+use crate::reach::ReachCache;
+use crate::reanalysis::Reanalysis;
+use crate::redact::Redactor;
+use crate::rescan::Rescan;
+use crate::resolve::Resolution;
+use crate::retry::{RetryBudget, RetryBudgetReport};
+use crate::service::detect_service_by;
+use crate::shared::{self, SharedResponse};
+use crate::sniff;
+use crate::spec::{Protocol, SpecError, SpecErrorKind};
+use crate::spill::Spill;
+use crate::stats::{BannerOutcome, ConnectOutcome, Stats};
+use crate::timeouts::PortTimeouts;
+use crate::tlsreport::{TlsDetails, TlsReport};
+use crate::traceroute::Hop;
+use crate::vuln::{Finding, FindingCounts};
+
+/// Errors returned by the scanning engine and its helpers.
+#[derive(Error, Debug)]
+#[error("Scan error")]
+pub enum ScanError {
+    /// A target is not an address, CIDR block or address range.
+    #[error("Invalid target: {0}")]
+    InvalidTarget(SpecError),
+
+    /// The port specification could not be parsed, or left no port to scan.
+    #[error("Invalid ports: {0}")]
+    InvalidPorts(SpecError),
+
+    /// No target was given.
+    #[error("No targets to scan")]
+    NoTargets,
+
+    /// Concurrency must allow at least one connection in flight.
+    #[error("Concurrency must be at least 1")]
+    ZeroConcurrency,
+
+    /// A zero timeout would classify every port as filtered.
+    #[error("Timeout must be greater than zero")]
+    ZeroTimeout,
+
+    /// A host name could not be resolved to an address.
+    #[error("Could not resolve {0}")]
+    Resolution(String),
+
+    /// The scanning machine ran out of sockets or file descriptors.
+    #[error("Local resources exhausted: {0}")]
+    ResourceExhausted(String),
+
+    /// One or more reports could not be written.
+    #[error("Failed to write {0}")]
+    OutputWrite(String),
+
+    /// A socket or file operation failed.
+    #[error("Network error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// The host of a result read from a report whose results didn't name it,
+/// until [`ScanSummary::fill_result_targets`] puts in the summary's.
+fn unnamed_target() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+/// The outcome of probing a single port on a single host.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PortResult {
+    /// Host the port belongs to.
+    #[serde(default = "unnamed_target")]
+    pub target: IpAddr,
+    /// The probed port.
+    pub port: u16,
+    /// The protocol the port was probed over; TCP in reports from before
+    /// there was a choice.
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// How the port responded.
+    pub status: PortStatus,
+    /// First bytes the service sent after the connection was accepted.
+    pub banner: Option<Banner>,
+    /// The charset the banner was decoded from, when it wasn't UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_encoding: Option<BannerEncoding>,
+    /// Matches of the scan's [`Redactor`] replaced in the banner and the
+    /// extra information ([`ScannerBuilder::redact`]).
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub redactions: u32,
+    /// Whether the banner, extra information or raw response was cut to a
+    /// preview because the scan's [`CaptureBudget`] was spent
+    /// ([`ScannerBuilder::capture_budget`]).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub capture_truncated: bool,
+    /// Protocol name read from the banner or guessed from the well-known
+    /// port, such as `SSH`.
+    pub service: Option<String>,
+    /// Software answering on the port, such as `OpenSSH`.
+    #[serde(default)]
+    pub product: Option<String>,
+    /// Version of `product`, such as `8.9p1`.
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Anything else the banner said about the product.
+    #[serde(default)]
+    pub extra_info: Option<String>,
+    /// CPE 2.3 name of a recognized product.
+    #[serde(default)]
+    pub cpe: Option<String>,
+    /// Milliseconds from the start of the scan until this port was classified.
+    pub duration_ms: u128,
+    /// The connect timeout the probe used, which `--port-timeout` may have
+    /// changed for this port; none when the port wasn't probed by this scan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Both measurements of a connect time that was an outlier for its
+    /// host and was measured again at the end of the scan
+    /// ([`ScannerBuilder::retime_outliers`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retime: Option<Retime>,
+    /// How long the port took to answer and to send its banner, when the
+    /// scan records timings ([`ScannerBuilder::record_timings`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+    /// The port accepted the connection, then reset it when the banner was
+    /// read, as inline IPS devices do; without this an open port that is
+    /// silent looks the same.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reset_on_read: bool,
+    /// The banner read was reset, or the port closed without a word, and
+    /// the port was connected to again for its banner
+    /// ([`ScannerBuilder::banner_retry`]); the result is that of the
+    /// second connection.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub banner_retried: bool,
+    /// The port would have been connected to again, but the scan's
+    /// [`RetryBudget`] was spent ([`ScannerBuilder::max_total_retries`]);
+    /// the result is that of its last attempt.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retries_exhausted: bool,
+    /// When and from where the port was probed, with `--audit`
+    /// ([`ScannerBuilder::audit`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<ProbeAudit>,
+    /// How the port came to be classified, step by step, when the scan
+    /// records evidence ([`ScannerBuilder::evidence`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub evidence: Vec<Evidence>,
+    /// Why the port is filtered, or why its banner couldn't be read.
+    #[serde(default)]
+    pub error: Option<PortError>,
+    /// Outcome of the `--exec` follow-up command, when one ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<ExecResult>,
+    /// Annotations added by a `--script`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Advisories from `--vuln-db` that apply to the product and version.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub findings: Vec<Finding>,
+    /// The group of ports answering with the same or nearly the same
+    /// banner, numbered from 1; none when the banner is unlike any other.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<u32>,
+    /// Whether the proxy on the port relays for anyone, from
+    /// `--proxy-check`; none when no proxy was found or none was checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_proxy: Option<bool>,
+    /// The TLS the port speaks, when a report carried it; see
+    /// [`tlsreport`](crate::tlsreport).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsDetails>,
+    /// The status the port had in the `--from` scan, when this scan probed
+    /// it again; see [`rescan`](crate::rescan).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_status: Option<PortStatus>,
+    /// When the scan this result comes from started, for a port of the
+    /// `--from` scan that this one didn't probe again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale: Option<DateTime<Utc>>,
+    /// The key in the report's `shared_responses` of the banner the port
+    /// sent, which it doesn't carry itself; see [`shared`](crate::shared).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_response: Option<String>,
+    /// Everything the port sent, untrimmed, when the scan keeps raw
+    /// responses ([`ScannerBuilder::response_cap`]).
+    #[serde(skip)]
+    pub response: Option<Bytes>,
+    /// Where `--save-responses` wrote `response`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_file: Option<PathBuf>,
+    /// What the `--annotations` inventory says the port is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// The status the `--annotations` inventory expects; see
+    /// [`Deviation`](crate::annotations::Deviation).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_status: Option<PortStatus>,
+}
+
+impl PortResult {
+    /// A result with nothing but its status, such as one read from another
+    /// scanner's report.
+    pub fn new(target: IpAddr, port: u16, status: PortStatus) -> Self {
+        PortResult {
+            target,
+            port,
+            protocol: Protocol::Tcp,
+            status,
+            banner: None,
+            banner_encoding: None,
+            redactions: 0,
+            capture_truncated: false,
+            service: None,
+            product: None,
+            version: None,
+            extra_info: None,
+            cpe: None,
+            duration_ms: 0,
+            timeout_ms: None,
+            retime: None,
+            timing: None,
+            reset_on_read: false,
+            banner_retried: false,
+            retries_exhausted: false,
+            audit: None,
+            evidence: Vec::new(),
+            error: None,
+            exec: None,
+            notes: Vec::new(),
+            findings: Vec::new(),
+            cluster: None,
+            open_proxy: None,
+            tls: None,
+            previous_status: None,
+            stale: None,
+            shared_response: None,
+            response: None,
+            response_file: None,
+            note: None,
+            expected_status: None,
+        }
+    }
+
+    /// The port as reports show it: the number alone for TCP, and with the
+    /// protocol otherwise, such as `53/udp`.
+    pub fn port_label(&self) -> String {
+        match self.protocol {
+            Protocol::Tcp => self.port.to_string(),
+            protocol => format!("{}/{}", self.port, protocol),
+        }
+    }
+
+    /// The banner as text, if there was one.
+    pub fn banner_str(&self) -> Option<Cow<'_, str>> {
+        self.banner.as_ref().map(Banner::to_str)
+    }
+
+    /// Product and version for display, such as `OpenSSH 8.9p1`.
+    pub fn product_version(&self) -> Option<String> {
+        let product = self.product.as_deref()?;
+        Some(match &self.version {
+            Some(version) => format!("{} {}", product, version),
+            None => product.to_string(),
+        })
+    }
+}
+
+/// When and from where a port was probed, for proving exactly what the
+/// scan sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeAudit {
+    /// When the connect attempt started, in UTC.
+    pub started_at: DateTime<Utc>,
+    /// When the attempt completed, in UTC: accepted, refused, failed or
+    /// timed out. Reading the banner comes after.
+    pub finished_at: DateTime<Utc>,
+    /// The local port the attempt was made from; none when the connector
+    /// can't tell.
+    pub source_port: Option<u16>,
+    /// Which attempt at the port this was, counting from 1. The scanner
+    /// makes one per port, and more only while it is out of local ports.
+    pub attempt: u32,
+}
+
+/// What went wrong while probing a port.
+///
+/// Serialized as `{"kind": "...", "detail": "..."}` so consumers can tell a
+/// silent remote host apart from a local failure that left the port unprobed.
+#[derive(Error, Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum PortError {
+    /// No answer before the connect timeout.
+    #[error("timed out")]
+    Timeout,
+    /// The host or network is unreachable from here.
+    #[error("unreachable: {0}")]
+    Unreachable(String),
+    /// Not probed: the host's earlier ports were unreachable, with this
+    /// error.
+    #[error("unreachable (cached): {0}")]
+    UnreachableCached(String),
+    /// The scanning machine ran out of file descriptors, ephemeral ports or
+    /// buffers, so the port was never really probed.
+    #[error("local resources exhausted: {0}")]
+    LocalResource(String),
+    /// Any other connect failure.
+    #[error("connect failed: {0}")]
+    Connect(String),
+    /// The connection was accepted but reading the banner failed.
+    #[error("banner read failed: {0}")]
+    Banner(String),
+    /// The probe task was aborted.
+    #[error("probe task failed: {0}")]
+    Task(String),
+    /// The probe panicked, with this message: a bug, not the port.
+    #[error("probe panicked: {0}")]
+    Panicked(String),
+}
+
+impl PortError {
+    /// Classifies a connect error.
+    pub fn from_io(e: &std::io::Error) -> Self {
+        match Failure::of(e) {
+            Failure::LocalResource | Failure::PortsExhausted => {
+                PortError::LocalResource(e.to_string())
+            }
+            Failure::TimedOut => PortError::Timeout,
+            Failure::Unreachable => PortError::Unreachable(e.to_string()),
+            Failure::Refused | Failure::Reset | Failure::Other => PortError::Connect(e.to_string()),
+        }
+    }
+
+    /// True when the failure was on this side, so the port's status says
+    /// nothing about the target.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            PortError::LocalResource(_) | PortError::Task(_) | PortError::Panicked(_)
+        )
+    }
+}
+
+/// How a connect attempt failed, as far as the port's classification
+/// goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Refused: the port is closed.
+    Refused,
+    /// Reset after the handshake: the port is open.
+    Reset,
+    /// The operating system gave up waiting for an answer.
+    TimedOut,
+    /// No route to the host or its network.
+    Unreachable,
+    /// Out of descriptors or buffers on this side.
+    LocalResource,
+    /// No local port to connect from: the ephemeral range, or the
+    /// connection table, is full of connections still in `TIME_WAIT`.
+    /// Worth retrying once some of them expire.
+    PortsExhausted,
+    /// Anything else.
+    Other,
+}
+
+impl Failure {
+    /// Classifies `e` by the raw OS error where the platform's codes say
+    /// more than the [`std::io::ErrorKind`] std maps them to, and by the kind
+    /// otherwise.
+    fn of(e: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        if let Some(failure) = e.raw_os_error().and_then(Failure::from_os) {
+            return failure;
+        }
+        match e.kind() {
+            ErrorKind::ConnectionRefused => Failure::Refused,
+            ErrorKind::ConnectionReset => Failure::Reset,
+            ErrorKind::TimedOut => Failure::TimedOut,
+            ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => Failure::Unreachable,
+            ErrorKind::AddrNotAvailable | ErrorKind::AddrInUse => Failure::PortsExhausted,
+            ErrorKind::OutOfMemory => Failure::LocalResource,
+            _ => Failure::Other,
+        }
+    }
+
+    /// Running out of descriptors or buffers has no kind of its own.
+    #[cfg(unix)]
+    fn from_os(code: i32) -> Option<Self> {
+        matches!(code, libc::EMFILE | libc::ENFILE | libc::ENOBUFS)
+            .then_some(Failure::LocalResource)
+    }
+
+    #[cfg(windows)]
+    fn from_os(code: i32) -> Option<Self> {
+        winsock::failure(code)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn from_os(_code: i32) -> Option<Self> {
+        None
+    }
+}
+
+/// Winsock's connect errors. std maps the common ones to the same kinds
+/// as on Unix, but not all of them (`WSAEHOSTDOWN`, `WSAEMFILE` and
+/// `WSAENOBUFS` come back uncategorized, which would make a port filtered
+/// for the wrong reason), and which kind a code gets has changed between
+/// Rust releases; the codes themselves don't.
+///
+/// What is left different on Windows, and handled by neither:
+///
+/// - A refused SYN is retried, twice, half a second apart, before connect
+///   fails with `WSAECONNREFUSED`, so a closed port takes about a second
+///   to classify, on loopback too; with a shorter connect timeout it is
+///   reported filtered. [`WINDOWS_REFUSED_DELAY`] is that second.
+/// - ICMP unreachables don't end a connect, so an unreachable host times
+///   out (filtered, `Timeout`) where Linux fails fast (filtered,
+///   `Unreachable`), and the unreachable cache never trips.
+/// - Windows Firewall blocking the scanner's outbound connects fails them
+///   with `WSAEACCES`: filtered, `Connect`, as on Linux with an `iptables`
+///   `REJECT --reject-with icmp-admin-prohibited` rule.
+#[cfg(any(windows, test))]
+mod winsock {
+    use super::Failure;
+
+    pub const WSAEMFILE: i32 = 10024;
+    pub const WSAEADDRINUSE: i32 = 10048;
+    pub const WSAEADDRNOTAVAIL: i32 = 10049;
+    pub const WSAENETUNREACH: i32 = 10051;
+    pub const WSAECONNRESET: i32 = 10054;
+    pub const WSAENOBUFS: i32 = 10055;
+    pub const WSAETIMEDOUT: i32 = 10060;
+    pub const WSAECONNREFUSED: i32 = 10061;
+    pub const WSAEHOSTDOWN: i32 = 10064;
+    pub const WSAEHOSTUNREACH: i32 = 10065;
+
+    pub fn failure(code: i32) -> Option<Failure> {
+        Some(match code {
+            WSAECONNREFUSED => Failure::Refused,
+            WSAECONNRESET => Failure::Reset,
+            // The system's own SYN retries ran out before the scan's
+            // timeout: dropped, as by a firewall.
+            WSAETIMEDOUT => Failure::TimedOut,
+            WSAEHOSTUNREACH | WSAENETUNREACH | WSAEHOSTDOWN => Failure::Unreachable,
+            WSAEMFILE | WSAENOBUFS => Failure::LocalResource,
+            WSAEADDRINUSE | WSAEADDRNOTAVAIL => Failure::PortsExhausted,
+            _ => return None,
+        })
+    }
+}
+
+/// How long Windows takes to report a refused connect, retrying the SYN
+/// first; a shorter connect timeout makes a closed port filtered there.
+pub const WINDOWS_REFUSED_DELAY: Duration = Duration::from_secs(1);
+
+/// How a port responded to a connection attempt.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum PortStatus {
+    /// The connection was accepted.
+    Open,
+    /// The connection was actively refused.
+    Closed,
+    /// No answer within the timeout, or any other connection error.
+    Filtered,
+}
+
+impl fmt::Display for PortStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortStatus::Open => write!(f, "{}", "open".bright_green()),
+            PortStatus::Closed => write!(f, "{}", "closed".bright_red()),
+            PortStatus::Filtered => write!(f, "{}", "filtered".yellow()),
+        }
+    }
+}
+
+/// What is known about a scanned host beyond its ports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostInfo {
+    /// The host.
+    pub address: IpAddr,
+    /// The targets that named the host, when more than one did, e.g. a
+    /// host name and its address; it was scanned once.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Country and autonomous system, from `--geoip-db` and `--asn-db`.
+    #[serde(flatten)]
+    pub geo: GeoInfo,
+    /// The target names that resolved to the host, with the server that
+    /// answered and every record it returned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<Resolution>,
+    /// Every name the host is known by, normalized.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    /// What the host is matched on across scans, when it isn't its
+    /// address: its name, with `--identity-by name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The host's MAC address, from `--arp-discovery` or
+    /// `--ipv6-local-discovery`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<MacAddr>,
+    /// The vendor `mac` is registered to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    /// The interface a link-local host was found on, from
+    /// `--ipv6-local-discovery`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// The network path to the host, from `--traceroute`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub route: Vec<Hop>,
+    /// What the host advertised over mDNS and SSDP, from
+    /// `--local-discovery`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<Device>,
+    /// The operating system the host probably runs, from `--os-guess`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_guess: Option<OsGuess>,
+    /// When the host was scanned, with `--host-concurrency` or
+    /// `--per-host-concurrency`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<HostTime>,
+    /// Whether many open ports of the host answered with one response, as
+    /// a transparent proxy or captive portal in the path does.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub uniform_responses: bool,
+}
+
+/// Targets that name some of the same hosts, such as a host name and its
+/// address or overlapping CIDR blocks: pairs of indices into `targets`, in
+/// order, with the hosts they share. Those hosts are scanned once.
+pub fn overlapping_targets(targets: &[TargetReport]) -> Vec<(usize, usize, TargetSet)> {
+    let mut overlaps = Vec::new();
+    let mut seen = TargetSet::new();
+    for (j, t) in targets.iter().enumerate() {
+        if !t.hosts.intersection(&seen).is_empty() {
+            for (i, earlier) in targets[..j].iter().enumerate() {
+                let shared = t.hosts.intersection(&earlier.hosts);
+                if !shared.is_empty() {
+                    overlaps.push((i, j, shared));
+                }
+            }
+        }
+        seen.insert_all(&t.hosts);
+    }
+    overlaps
+}
+
+/// What became of one `--target` as it was given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetReport {
+    /// The target as given: a host name, address, CIDR block or range.
+    pub target: String,
+    /// Whether it was scanned, and if not, why.
+    pub status: TargetStatus,
+    /// The addresses a host name resolved to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<IpAddr>,
+    /// Why the target couldn't be scanned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The hosts the target stands for.
+    #[serde(skip)]
+    pub hosts: TargetSet,
+}
+
+impl TargetReport {
+    /// A valid target standing for `hosts`, not scanned yet.
+    pub fn new(target: &str, hosts: TargetSet, addresses: Vec<IpAddr>) -> Self {
+        TargetReport {
+            target: target.to_string(),
+            status: TargetStatus::Resolved,
+            addresses,
+            error: None,
+            hosts,
+        }
+    }
+
+    /// A target that can't be scanned at all, and why.
+    pub fn failed(target: &str, status: TargetStatus, error: String) -> Self {
+        TargetReport {
+            target: target.to_string(),
+            status,
+            addresses: Vec::new(),
+            error: Some(error),
+            hosts: TargetSet::new(),
+        }
+    }
+}
+
+/// How far a target got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetStatus {
+    /// At least one of its ports answered, open or closed.
+    Scanned,
+    /// Its ports were probed and every one of them was filtered.
+    Unresponsive,
+    /// Valid, but none of its ports was probed before the scan stopped.
+    Resolved,
+    /// Every one of its hosts was left out, by `--exclude`,
+    /// `--private-only` or ARP discovery.
+    Excluded,
+    /// Every one of its hosts is on the `--blocklist`, and `--skip-blocked`
+    /// let the scan go on without it.
+    Blocked,
+    /// A host name no server could resolve.
+    Unresolvable,
+    /// Not an address, CIDR block, range or host name.
+    Invalid,
+}
+
+impl TargetStatus {
+    /// True when the target was given but couldn't be scanned at all.
+    pub fn is_failure(self) -> bool {
+        matches!(self, TargetStatus::Unresolvable | TargetStatus::Invalid)
+    }
+}
+
+impl fmt::Display for TargetStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TargetStatus::Scanned => "scanned",
+            TargetStatus::Unresponsive => "unresponsive, every port filtered",
+            TargetStatus::Resolved => "not scanned before the scan stopped",
+            TargetStatus::Excluded => "not scanned, excluded",
+            TargetStatus::Blocked => "not scanned, blocklisted",
+            TargetStatus::Unresolvable => "not scanned, unresolvable",
+            TargetStatus::Invalid => "not scanned, invalid",
+        })
+    }
+}
+
+/// The port counts of one protocol, as the scan summary breaks them down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolCounts {
+    /// The protocol counted.
+    pub protocol: Protocol,
+    /// Ports of the protocol with a result.
+    pub scanned: usize,
+    /// Of those, the open ports.
+    pub open: usize,
+    /// The closed ports.
+    pub closed: usize,
+    /// The filtered ports.
+    pub filtered: usize,
+}
+
+impl ProtocolCounts {
+    /// The counts of each protocol among `results`, TCP first.
+    pub fn of<'a>(results: impl IntoIterator<Item = &'a PortResult>) -> Vec<Self> {
+        let mut counts = Vec::new();
+        for r in results {
+            Self::record(&mut counts, r);
+        }
+        counts
+    }
+
+    /// Adds `r` to the counts of its protocol.
+    pub fn record(counts: &mut Vec<Self>, r: &PortResult) {
+        let i = match counts.binary_search_by_key(&r.protocol, |c| c.protocol) {
+            Ok(i) => i,
+            Err(i) => {
+                counts.insert(
+                    i,
+                    ProtocolCounts {
+                        protocol: r.protocol,
+                        scanned: 0,
+                        open: 0,
+                        closed: 0,
+                        filtered: 0,
+                    },
+                );
+                i
+            }
+        };
+        let c = &mut counts[i];
+        c.scanned += 1;
+        match r.status {
+            PortStatus::Open => c.open += 1,
+            PortStatus::Closed => c.closed += 1,
+            PortStatus::Filtered => c.filtered += 1,
+        }
+    }
+}
+
+/// Totals and per-port results for a finished (or interrupted) scan.
+#[derive(Debug, Deserialize)]
+pub struct ScanSummary {
+    /// Unique identifier of the run; empty in reports from before runs had
+    /// one.
+    #[serde(default)]
+    pub scan_id: String,
+    /// Comma-separated list of the scanned hosts.
+    pub target: String,
+    /// Who ran the scan and why (`--operator`, `--reason`, `--tag`).
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// The `--preset` the scan ran with and the options it filled in.
+    #[serde(default)]
+    pub preset: Option<AppliedPreset>,
+    /// Number of ports with a result.
+    pub scanned_ports: usize,
+    /// Number of ports found open.
+    pub open_ports: usize,
+    /// Number of ports found closed.
+    pub closed_ports: usize,
+    /// Number of ports found filtered.
+    pub filtered_ports: usize,
+    /// Ports left unprobed by a local failure (counted as filtered too).
+    #[serde(default)]
+    pub unscanned_ports: usize,
+    /// The counts above for each protocol scanned.
+    #[serde(default)]
+    pub protocols: Vec<ProtocolCounts>,
+    /// Open ports that reset the connection when read (counted as open too).
+    #[serde(default)]
+    pub reset_on_read_ports: usize,
+    /// Results whose captures were cut to previews by the
+    /// `--max-capture-mem` budget.
+    #[serde(default)]
+    pub capture_truncated_ports: usize,
+    /// Ports whose probe panicked (counted as unscanned too).
+    #[serde(default)]
+    pub panicked_ports: usize,
+    /// Hosts of the targets left out because they are on the `--blocklist`.
+    #[serde(default)]
+    pub blocked_hosts: u128,
+    /// Wall time of the whole scan.
+    pub total_time_ms: u128,
+    /// Average scan rate.
+    #[serde(default)]
+    pub ports_per_second: f64,
+    /// Limit on connection attempts in flight by the end of the scan, after
+    /// any tuning.
+    #[serde(default)]
+    pub concurrency: usize,
+    /// Connection attempts made, including retries.
+    #[serde(default)]
+    pub connection_attempts: u64,
+    /// Connection attempts that were retries.
+    #[serde(default)]
+    pub retries: u64,
+    /// Banner reads attempted on open ports.
+    #[serde(default)]
+    pub banner_probes: u64,
+    /// Bytes of banner data read.
+    #[serde(default)]
+    pub banner_bytes: u64,
+    /// The detailed counters, with `--stats`.
+    #[serde(default)]
+    pub stats: Option<Stats>,
+    /// The most connections held established at once, overall and per
+    /// host, with `--max-established-per-host` or `--max-established`.
+    #[serde(default)]
+    pub established: Option<EstablishedReport>,
+    /// How much of its retry budget the scan used, with
+    /// `--max-total-retries`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_budget: Option<RetryBudgetReport>,
+    /// Connect and banner times binned by host and range of ports, with
+    /// `--heatmap`.
+    #[serde(default)]
+    pub heatmap: Option<Heatmap>,
+    /// True when the scan was stopped before every port was probed.
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Why the scan ended, for a scan this run made.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion: Option<Completion>,
+    /// Number of `--vuln-db` findings by severity.
+    #[serde(default)]
+    pub findings: FindingCounts,
+    /// Number of ports that deviate from their `--annotations` expected
+    /// status; none when no port had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deviations: Option<DeviationCounts>,
+    /// Outcome of the `--fail-on`/`--allow-open` assertions, if any were made.
+    #[serde(default)]
+    pub policy: Option<PolicyResult>,
+    /// How the scan drifted from its `--baseline`, when it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compliance: Option<Compliance>,
+    /// Text returned by a `--script`'s `summarize` function.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_summary: Option<String>,
+    /// What `port-scanner reanalyze` changed, when the report went through
+    /// it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reanalysis: Option<Reanalysis>,
+    /// The TLS of the ports compared, when `port-scanner tls-report`
+    /// wrote the report.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_report: Option<TlsReport>,
+    /// How the scan relates to the `--from` scan it re-scanned, when it
+    /// had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rescan: Option<Rescan>,
+    /// What hosts are identified by across scans (`--identity-by`); the
+    /// report leaves out the default.
+    #[serde(default, skip_serializing_if = "IdentityMode::is_default")]
+    pub identity: IdentityMode,
+    /// What became of each `--target`, when they were reported on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TargetReport>,
+    /// Annotations of the hosts with results, when any were made.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<HostInfo>,
+    /// Groups of ports answering with the same service; the JSON report
+    /// only has each result's `cluster`.
+    #[serde(skip)]
+    pub clusters: Vec<Cluster>,
+    /// The banners many open ports of a host answered with, by key, which
+    /// the JSON report has once instead of in each result; see
+    /// [`shared`](crate::shared).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shared_responses: BTreeMap<String, SharedResponse>,
+    /// Per-port results in completion order.
+    pub results: Vec<PortResult>,
+    /// Where every result went when the scan was spilled to disk; `results`
+    /// then only holds the open ports.
+    #[serde(skip)]
+    pub spill: Option<Spill>,
+    /// Which results the reports show (`--filter`); the counts cover them
+    /// all.
+    #[serde(skip)]
+    pub filter: Option<Filter>,
+}
+
+impl Serialize for ScanSummary {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("ScanSummary", 40)?;
+        s.serialize_field("scan_id", &self.scan_id)?;
+        s.serialize_field("target", &self.target)?;
+        if self.metadata.is_empty() {
+            s.skip_field("metadata")?;
+        } else {
+            s.serialize_field("metadata", &self.metadata)?;
+        }
+        if self.preset.is_none() {
+            s.skip_field("preset")?;
+        } else {
+            s.serialize_field("preset", &self.preset)?;
+        }
+        s.serialize_field("scanned_ports", &self.scanned_ports)?;
+        s.serialize_field("open_ports", &self.open_ports)?;
+        s.serialize_field("closed_ports", &self.closed_ports)?;
+        s.serialize_field("filtered_ports", &self.filtered_ports)?;
+        s.serialize_field("unscanned_ports", &self.unscanned_ports)?;
+        if self.protocols.is_empty() {
+            s.skip_field("protocols")?;
+        } else {
+            s.serialize_field("protocols", &self.protocols)?;
+        }
+        s.serialize_field("reset_on_read_ports", &self.reset_on_read_ports)?;
+        if self.capture_truncated_ports == 0 {
+            s.skip_field("capture_truncated_ports")?;
+        } else {
+            s.serialize_field("capture_truncated_ports", &self.capture_truncated_ports)?;
+        }
+        if self.panicked_ports == 0 {
+            s.skip_field("panicked_ports")?;
+        } else {
+            s.serialize_field("panicked_ports", &self.panicked_ports)?;
+        }
+        s.serialize_field("blocked_hosts", &self.blocked_hosts)?;
+        s.serialize_field("total_time_ms", &self.total_time_ms)?;
+        s.serialize_field("ports_per_second", &self.ports_per_second)?;
+        s.serialize_field("concurrency", &self.concurrency)?;
+        s.serialize_field("connection_attempts", &self.connection_attempts)?;
+        s.serialize_field("retries", &self.retries)?;
+        s.serialize_field("banner_probes", &self.banner_probes)?;
+        s.serialize_field("banner_bytes", &self.banner_bytes)?;
+        match &self.stats {
+            Some(stats) => s.serialize_field("stats", stats)?,
+            None => s.skip_field("stats")?,
+        }
+        match &self.established {
+            Some(established) => s.serialize_field("established", established)?,
+            None => s.skip_field("established")?,
+        }
+        match &self.retry_budget {
+            Some(budget) => s.serialize_field("retry_budget", budget)?,
+            None => s.skip_field("retry_budget")?,
+        }
+        match &self.heatmap {
+            Some(heatmap) => s.serialize_field("heatmap", heatmap)?,
+            None => s.skip_field("heatmap")?,
+        }
+        s.serialize_field("interrupted", &self.interrupted)?;
+        match &self.completion {
+            Some(completion) => s.serialize_field("completion", completion)?,
+            None => s.skip_field("completion")?,
+        }
+        s.serialize_field("findings", &self.findings)?;
+        match &self.deviations {
+            Some(counts) => s.serialize_field("deviations", counts)?,
+            None => s.skip_field("deviations")?,
+        }
+        s.serialize_field("policy", &self.policy)?;
+        match &self.compliance {
+            Some(compliance) => s.serialize_field("compliance", compliance)?,
+            None => s.skip_field("compliance")?,
+        }
+        match &self.script_summary {
+            Some(text) => s.serialize_field("script_summary", text)?,
+            None => s.skip_field("script_summary")?,
+        }
+        match &self.reanalysis {
+            Some(reanalysis) => s.serialize_field("reanalysis", reanalysis)?,
+            None => s.skip_field("reanalysis")?,
+        }
+        match &self.tls_report {
+            Some(report) => s.serialize_field("tls_report", report)?,
+            None => s.skip_field("tls_report")?,
+        }
+        match &self.rescan {
+            Some(rescan) => s.serialize_field("rescan", rescan)?,
+            None => s.skip_field("rescan")?,
+        }
+        if self.identity.is_default() {
+            s.skip_field("identity")?;
+        } else {
+            s.serialize_field("identity", &self.identity)?;
+        }
+        if self.targets.is_empty() {
+            s.skip_field("targets")?;
+        } else {
+            s.serialize_field("targets", &self.targets)?;
+        }
+        if self.hosts.is_empty() {
+            s.skip_field("hosts")?;
+        } else {
+            s.serialize_field("hosts", &self.hosts)?;
+        }
+        if self.shared_responses.is_empty() {
+            s.skip_field("shared_responses")?;
+        } else {
+            s.serialize_field("shared_responses", &self.shared_responses)?;
+        }
+        s.serialize_field("results", &ReportedResults(self))?;
+        s.end()
+    }
+}
+
+/// Serializes a summary's reported results, streaming them back from a
+/// spill file, without the banners it has in `shared_responses`.
+struct ReportedResults<'a>(&'a ScanSummary);
+
+impl Serialize for ReportedResults<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeSeq};
+        let len = self
+            .0
+            .spill
+            .as_ref()
+            .map_or(self.0.results.len(), Spill::len);
+        // How many results pass a filter isn't known until they are read.
+        let len = self.0.filter.is_none().then_some(len);
+        let mut seq = serializer.serialize_seq(len)?;
+        for r in self.0.reported_results().map_err(S::Error::custom)? {
+            let r = r.map_err(S::Error::custom)?;
+            seq.serialize_element(&*self.0.shared_view(&r))?;
+        }
+        seq.end()
+    }
+}
+
+impl ScanSummary {
+    /// Gives the results of a report that didn't name their host the one
+    /// host of the summary's `target`, as the reports of single-host scans
+    /// had.
+    pub fn fill_result_targets(&mut self) {
+        let Ok(target) = self.target.parse::<IpAddr>() else {
+            return;
+        };
+        for r in &mut self.results {
+            if r.target == unnamed_target() {
+                r.target = target;
+            }
+        }
+    }
+
+    /// Builds a summary from collected results and the scanner's counters.
+    pub fn from_results(
+        scan_id: String,
+        target: String,
+        results: Vec<PortResult>,
+        total_time_ms: u128,
+        metrics: &ScanMetrics,
+        interrupted: bool,
+    ) -> Self {
+        let count = |status: PortStatus| results.iter().filter(|r| r.status == status).count();
+        ScanSummary {
+            scan_id,
+            target,
+            metadata: Metadata::default(),
+            preset: None,
+            scanned_ports: results.len(),
+            open_ports: count(PortStatus::Open),
+            closed_ports: count(PortStatus::Closed),
+            filtered_ports: count(PortStatus::Filtered),
+            unscanned_ports: count_unscanned(&results),
+            protocols: ProtocolCounts::of(&results),
+            reset_on_read_ports: results.iter().filter(|r| r.reset_on_read).count(),
+            capture_truncated_ports: results.iter().filter(|r| r.capture_truncated).count(),
+            panicked_ports: count_panicked(&results),
+            blocked_hosts: 0,
+            total_time_ms,
+            ports_per_second: metrics.ports_per_second(),
+            concurrency: metrics.concurrency(),
+            connection_attempts: metrics.connection_attempts(),
+            retries: metrics.retries(),
+            banner_probes: metrics.banner_probes(),
+            banner_bytes: metrics.banner_bytes(),
+            stats: metrics.stats().map(|s| s.snapshot(metrics.retries())),
+            established: None,
+            retry_budget: None,
+            heatmap: None,
+            interrupted,
+            completion: None,
+            findings: FindingCounts::of(&results),
+            deviations: DeviationCounts::of(&results),
+            policy: None,
+            compliance: None,
+            script_summary: None,
+            reanalysis: None,
+            tls_report: None,
+            rescan: None,
+            identity: IdentityMode::default(),
+            targets: Vec::new(),
+            hosts: Vec::new(),
+            clusters: Vec::new(),
+            shared_responses: BTreeMap::new(),
+            results,
+            spill: None,
+            filter: None,
+        }
+    }
+
+    /// Counts the results again, after some were added, such as those a
+    /// re-scan carries forward from its `--from` scan.
+    pub fn recount(&mut self) {
+        let results = &self.results;
+        let count = |status: PortStatus| results.iter().filter(|r| r.status == status).count();
+        self.scanned_ports = results.len();
+        self.open_ports = count(PortStatus::Open);
+        self.closed_ports = count(PortStatus::Closed);
+        self.filtered_ports = count(PortStatus::Filtered);
+        self.unscanned_ports = count_unscanned(results);
+        self.protocols = ProtocolCounts::of(results);
+        self.reset_on_read_ports = results.iter().filter(|r| r.reset_on_read).count();
+        self.capture_truncated_ports = results.iter().filter(|r| r.capture_truncated).count();
+        self.panicked_ports = count_panicked(results);
+        self.findings = FindingCounts::of(results);
+        self.deviations = DeviationCounts::of(results);
+    }
+
+    /// Records what became of each given target: those still
+    /// [`TargetStatus::Resolved`] are settled against the hosts the scan
+    /// covered (`scanned`) and how their ports answered.
+    pub fn report_targets(&mut self, mut targets: Vec<TargetReport>, scanned: &TargetSet) {
+        let pending: Vec<usize> = (0..targets.len())
+            .filter(|&i| targets[i].status == TargetStatus::Resolved)
+            .collect();
+        for &i in &pending {
+            let mut left = targets[i].hosts.clone();
+            left.remove_all(scanned);
+            if left.len() == targets[i].hosts.len() {
+                targets[i].status = TargetStatus::Excluded;
+            }
+        }
+        if let Ok(results) = self.all_results() {
+            for r in results.flatten() {
+                for &i in &pending {
+                    let t = &mut targets[i];
+                    if t.status == TargetStatus::Scanned || !t.hosts.contains(r.target) {
+                        continue;
+                    }
+                    t.status = if r.status == PortStatus::Filtered {
+                        TargetStatus::Unresponsive
+                    } else {
+                        TargetStatus::Scanned
+                    };
+                }
+            }
+        }
+        self.targets = targets;
+    }
+
+    /// The targets that were given but couldn't be scanned at all.
+    pub fn failed_targets(&self) -> impl Iterator<Item = &TargetReport> {
+        self.targets.iter().filter(|t| t.status.is_failure())
+    }
+
+    /// Every result, read back from disk when the scan was spilled.
+    pub fn all_results(
+        &self,
+    ) -> std::io::Result<Box<dyn Iterator<Item = std::io::Result<Cow<'_, PortResult>>> + '_>> {
+        Ok(match &self.spill {
+            Some(spill) => Box::new(spill.iter()?.map(|r| r.map(Cow::Owned))),
+            None => Box::new(self.results.iter().map(|r| Ok(Cow::Borrowed(r)))),
+        })
+    }
+
+    /// The results the reports show: those passing the `--filter`, or all
+    /// of them.
+    pub fn reported_results(
+        &self,
+    ) -> std::io::Result<Box<dyn Iterator<Item = std::io::Result<Cow<'_, PortResult>>> + '_>> {
+        let results = self.all_results()?;
+        Ok(match &self.filter {
+            Some(filter) => {
+                Box::new(results.filter(|r| r.as_ref().map_or(true, |r| filter.matches(r))))
+            }
+            None => results,
+        })
+    }
+
+    /// Annotates every host with a result, in address order: with the
+    /// targets that named it, what `geo` knows about it and the `dns`
+    /// answers that named it.
+    pub fn annotate_hosts(&mut self, geo: Option<&GeoDb>, dns: &[Resolution]) {
+        let uniform = self.uniform_hosts();
+        let mut addresses: Vec<IpAddr> = self.results.iter().map(|r| r.target).collect();
+        addresses.sort();
+        addresses.dedup();
+        let overlaps = overlapping_targets(&self.targets);
+        let aliases = |address: IpAddr| {
+            let mut named: Vec<usize> = overlaps
+                .iter()
+                .filter(|(_, _, shared)| shared.contains(address))
+                .flat_map(|&(i, j, _)| [i, j])
+                .collect();
+            named.sort();
+            named.dedup();
+            named
+                .into_iter()
+                .map(|i| self.targets[i].target.clone())
+                .collect()
+        };
+        self.hosts = addresses
+            .into_iter()
+            .map(|address| HostInfo {
+                address,
+                aliases: aliases(address),
+                geo: geo.map(|db| db.lookup(address)).unwrap_or_default(),
+                dns: dns
+                    .iter()
+                    .filter(|d| d.addresses().any(|a| a == address))
+                    .cloned()
+                    .collect(),
+                names: Vec::new(),
+                key: None,
+                mac: None,
+                vendor: None,
+                interface: None,
+                route: Vec::new(),
+                device: None,
+                os_guess: None,
+                timing: None,
+                uniform_responses: uniform.contains(&address),
+            })
+            .collect();
+    }
+
+    /// Records what the hosts are identified by: the mode of `identities`,
+    /// and the names and key of each host.
+    pub fn identify(&mut self, identities: &Identities) {
+        self.identity = identities.mode();
+        for host in &mut self.hosts {
+            host.names = identities.names(host.address).to_vec();
+            host.key = identities.key(host.address).name().map(str::to_string);
+        }
+    }
+
+    /// Why ports could not be scanned, most frequent reason first.
+    pub fn unscanned_reasons(&self) -> Vec<(String, usize)> {
+        let mut reasons: Vec<(String, usize)> = Vec::new();
+        let Ok(results) = self.all_results() else {
+            return reasons;
+        };
+        for r in results.flatten() {
+            let Some(e) = r.error.as_ref().filter(|e| e.is_local()) else {
+                continue;
+            };
+            let reason = e.to_string();
+            match reasons.iter_mut().find(|(r, _)| *r == reason) {
+                Some((_, n)) => *n += 1,
+                None => reasons.push((reason, 1)),
+            }
+        }
+        reasons.sort_by_key(|r| std::cmp::Reverse(r.1));
+        reasons
+    }
+
+    /// Narrows the summary down to the results for a single host.
+    pub fn for_host(&self, target: IpAddr) -> std::io::Result<ScanSummary> {
+        let mut results = Vec::new();
+        for r in self.all_results()? {
+            let r = r?;
+            if r.target == target {
+                results.push(r.into_owned());
+            }
+        }
+        let count = |status: PortStatus| results.iter().filter(|r| r.status == status).count();
+        Ok(ScanSummary {
+            scan_id: self.scan_id.clone(),
+            target: target.to_string(),
+            metadata: self.metadata.clone(),
+            preset: self.preset.clone(),
+            scanned_ports: results.len(),
+            open_ports: count(PortStatus::Open),
+            closed_ports: count(PortStatus::Closed),
+            filtered_ports: count(PortStatus::Filtered),
+            unscanned_ports: count_unscanned(&results),
+            protocols: ProtocolCounts::of(&results),
+            reset_on_read_ports: results.iter().filter(|r| r.reset_on_read).count(),
+            capture_truncated_ports: results.iter().filter(|r| r.capture_truncated).count(),
+            panicked_ports: count_panicked(&results),
+            blocked_hosts: 0,
+            total_time_ms: self.total_time_ms,
+            ports_per_second: self.ports_per_second,
+            concurrency: self.concurrency,
+            connection_attempts: self.connection_attempts,
+            retries: self.retries,
+            banner_probes: self.banner_probes,
+            banner_bytes: self.banner_bytes,
+            stats: self.stats.clone(),
+            established: self.established.as_ref().map(|e| e.for_host(target)),
+            retry_budget: self.retry_budget.as_ref().map(|b| b.recount(&results)),
+            heatmap: self.heatmap.as_ref().map(|h| h.for_host(target)),
+            interrupted: self.interrupted,
+            completion: self.completion.clone(),
+            findings: FindingCounts::of(&results),
+            deviations: DeviationCounts::of(&results),
+            policy: self.policy.as_ref().map(|p| p.for_target(target)),
+            compliance: self.compliance.as_ref().map(|c| c.for_target(target)),
+            script_summary: self.script_summary.clone(),
+            reanalysis: self.reanalysis.clone(),
+            tls_report: self.tls_report.as_ref().map(|r| r.for_target(target)),
+            rescan: self.rescan.as_ref().map(|r| r.recount(&results)),
+            identity: self.identity,
+            // The per-host files are JSON, where each result keeps its id.
+            clusters: Vec::new(),
+            shared_responses: shared::for_host(&self.shared_responses, target, &results),
+            targets: self
+                .targets
+                .iter()
+                .filter(|t| t.hosts.contains(target))
+                .cloned()
+                .collect(),
+            hosts: self
+                .hosts
+                .iter()
+                .filter(|h| h.address == target)
+                .cloned()
+                .collect(),
+            results,
+            spill: None,
+            filter: self.filter.clone(),
+        })
+    }
+}
+
+fn count_unscanned(results: &[PortResult]) -> usize {
+    results
+        .iter()
+        .filter(|r| r.error.as_ref().is_some_and(PortError::is_local))
+        .count()
+}
+
+fn count_panicked(results: &[PortResult]) -> usize {
+    results
+        .iter()
+        .filter(|r| matches!(r.error, Some(PortError::Panicked(_))))
+        .count()
+}
+
+/// What to scan and how aggressively.
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// Hosts to scan, each against every port in `ports`.
+    pub targets: TargetSet,
+    /// Ports to probe on every target.
+    pub ports: PortSet,
+    /// Maximum number of connection attempts in flight at once.
+    pub concurrency: usize,
+    /// How many hosts are scanned at once; `None` takes them one after
+    /// another, each starting as the last ports of the one before are
+    /// probed, unless `per_host_concurrency` is set.
+    pub host_concurrency: Option<usize>,
+    /// Maximum number of connection attempts in flight to any one host.
+    pub per_host_concurrency: Option<usize>,
+    /// Maximum number of open ports having their banner read and follow-up
+    /// probes run at once, apart from the connection attempts.
+    pub probe_concurrency: usize,
+    /// How long to wait for a connection to be accepted or refused.
+    pub timeout: Duration,
+    /// Connect timeouts for particular ports, replacing `timeout` there.
+    pub port_timeouts: PortTimeouts,
+    /// How long to wait for an open port to send a banner.
+    pub banner_timeout: Duration,
+    /// Most bytes of each open port's raw response to keep, reading on after
+    /// the banner until the banner timeout; none keeps no response.
+    pub response_cap: Option<usize>,
+    /// How long the banner retry and the follow-up probes of an open port
+    /// may take in all, reconnects included.
+    pub probe_budget: Duration,
+    /// How long each follow-up probe may take, within the probe budget.
+    pub probe_timeouts: ProbeTimeouts,
+    /// Whether every result records a [`ProbeAudit`].
+    pub audit: bool,
+    /// Whether every result records the [`Evidence`] of how it was
+    /// classified.
+    pub evidence: bool,
+    /// Whether the scan keeps the detailed counters of
+    /// [`ScanMetrics::stats`].
+    pub stats: bool,
+    /// Whether the scan starts paused, waiting for [`Throttle::resume`].
+    pub start_paused: bool,
+    /// Whether connect times that are outliers for their host are measured
+    /// again at the end of the scan; see [`crate::latency`].
+    pub retime_outliers: bool,
+    /// Whether each port that answered keeps its connect and banner times
+    /// as its [`PortResult::timing`], for a [`crate::heatmap`].
+    pub timings: bool,
+    /// Whether banners are told by their first bytes before anything else;
+    /// see [`crate::sniff`].
+    pub sniff: bool,
+    /// Whether an open port whose banner read was reset, or that closed
+    /// without a word, is connected to once more for its banner, within
+    /// the probe budget.
+    pub banner_retry: bool,
+    /// What is redacted from each result before the stream hands it out;
+    /// none redacts nothing.
+    pub redactor: Option<Redactor>,
+    /// The memory all results may keep of what services sent; none keeps
+    /// everything.
+    pub capture_budget: Option<CaptureBudget>,
+    /// Caps on the connections held established at once; none counts
+    /// nothing.
+    pub established: Option<EstablishedLimit>,
+    /// The retries all ports may make in all; none lets each port make
+    /// its own.
+    pub retry_budget: Option<RetryBudget>,
+    /// Unreachable errors in a row after which a host's remaining ports are
+    /// left unprobed (see [`ReachCache`]); `None` probes them all.
+    pub unreachable_after: Option<u32>,
+    /// The interface index each link-local IPv6 target is reached through.
+    pub scope_ids: HashMap<Ipv6Addr, u32>,
+    /// Stops the scan when cancelled: no new connections are attempted and
+    /// in-flight ones are abandoned.
+    pub cancel: CancellationToken,
+}
+
+impl ScanConfig {
+    /// Checks that the configuration describes a scan that can run.
+    pub fn validate(&self) -> Result<(), ScanError> {
+        if self.targets.is_empty() {
+            return Err(ScanError::NoTargets);
+        }
+        if self.ports.is_empty() {
+            return Err(ScanError::InvalidPorts(SpecError::new(
+                SpecErrorKind::NoPorts,
+                "",
+                0,
+            )));
+        }
+        if self.concurrency == 0
+            || self.probe_concurrency == 0
+            || self.host_concurrency == Some(0)
+            || self.per_host_concurrency == Some(0)
+        {
+            return Err(ScanError::ZeroConcurrency);
+        }
+        if self.timeout.is_zero()
+            || self.banner_timeout.is_zero()
+            || self.probe_budget.is_zero()
+            || self.port_timeouts.has_zero()
+            || self.probe_timeouts.has_zero()
+        {
+            return Err(ScanError::ZeroTimeout);
+        }
+        Ok(())
+    }
+
+    /// What the scan would do, worked out without touching the network.
+    pub fn describe(&self) -> PlanSummary {
+        let mut plan = PlanSummary::new(
+            &self.targets,
+            &self.ports,
+            self.concurrency,
+            self.timeout,
+            self.banner_timeout,
+        );
+        let established = self.established.as_ref().map(EstablishedLimit::report);
+        plan.rate_limits = RateLimits {
+            probe_concurrency: self.probe_concurrency,
+            host_concurrency: self.host_concurrency,
+            per_host_concurrency: self.per_host_concurrency,
+            max_established: established.as_ref().and_then(|e| e.max_total),
+            max_established_per_host: established.as_ref().and_then(|e| e.max_per_host),
+            window: None,
+            paused: self.start_paused,
+            until: None,
+        };
+        plan
+    }
+
+    /// Every target/port pair to probe, generated lazily in scan order.
+    pub fn plan(&self) -> Plan<'_> {
+        Plan::new(&self.targets, &self.ports)
+    }
+
+    /// Number of target/port pairs, saturating at `u64::MAX`.
+    pub fn total_ports(&self) -> u64 {
+        self.targets
+            .len()
+            .saturating_mul(self.ports.len() as u128)
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+/// Runs TCP connect scans described by a [`ScanConfig`].
+///
+/// Build one with [`Scanner::builder`] and drive it from any tokio runtime:
+///
+/// ```
+/// use futures::StreamExt;
+/// use port_scanner::{PortStatus, Scanner};
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+///     let port = listener.local_addr()?.port();
+///
+///     let scanner = Scanner::builder()
+///         .target("127.0.0.1")
+///         .ports(port)
+///         .concurrency(16)
+///         .timeout(Duration::from_millis(500))
+///         .banner_timeout(Duration::from_millis(100))
+///         .build()?;
+///
+///     let mut results = scanner.scan();
+///     while let Some(r) = results.next().await {
+///         assert_eq!(r.port, port);
+///         assert_eq!(r.status, PortStatus::Open);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Scanner {
+    config: ScanConfig,
+    connector: Arc<dyn Connector>,
+    probes: Vec<Arc<dyn Probe>>,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<ScanMetrics>,
+    label: String,
+}
+
+impl Scanner {
+    /// Starts a [`ScannerBuilder`] with the CLI's defaults.
+    pub fn builder() -> ScannerBuilder {
+        ScannerBuilder::default()
+    }
+
+    /// Creates a scanner from a validated configuration; nothing is sent
+    /// until [`Scanner::scan`] is called.
+    pub fn new(config: ScanConfig) -> Result<Self, ScanError> {
+        Self::with_connector(config, TcpConnector::default())
+    }
+
+    /// Like [`Scanner::new`], opening connections through `connector`.
+    pub fn with_connector(
+        config: ScanConfig,
+        connector: impl Connector + 'static,
+    ) -> Result<Self, ScanError> {
+        Self::from_parts(
+            config,
+            Arc::new(connector),
+            Vec::new(),
+            Arc::new(SystemClock),
+        )
+    }
+
+    fn from_parts(
+        config: ScanConfig,
+        connector: Arc<dyn Connector>,
+        probes: Vec<Arc<dyn Probe>>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, ScanError> {
+        config.validate()?;
+        let label = config.targets.to_string();
+        let mut metrics = ScanMetrics::with_clock(&label, clock.clone());
+        if config.stats {
+            metrics = metrics.with_stats();
+        }
+        Ok(Scanner {
+            config,
+            connector,
+            probes,
+            metrics: Arc::new(metrics),
+            clock,
+            label,
+        })
+    }
+
+    /// The configuration this scanner was built with.
+    pub fn config(&self) -> &ScanConfig {
+        &self.config
+    }
+
+    /// Live counters, updated as the scan progresses.
+    pub fn metrics(&self) -> Arc<ScanMetrics> {
+        self.metrics.clone()
+    }
+
+    /// The token that stops this scanner's scans when cancelled.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.config.cancel.clone()
+    }
+
+    /// Total number of ports this scan will probe across all targets.
+    pub fn total_ports(&self) -> u64 {
+        self.config.total_ports()
+    }
+
+    /// Starts scanning every target/port pair and returns the results as a
+    /// stream, in completion order.
+    ///
+    /// Probes run on the tokio runtime this is called from, so it must be
+    /// called inside one. Dropping the stream before it ends aborts the
+    /// queued and in-flight probes.
+    pub fn scan(&self) -> ScanStream {
+        let (tx, rx) = mpsc::channel(self.config.concurrency);
+        let throttle = Throttle::new(self.config.concurrency, self.metrics.clone());
+        if self.config.start_paused {
+            throttle.pause();
+        }
+        let driver = tokio::spawn(drive(
+            self.config.clone(),
+            self.connector.clone(),
+            self.probes.clone(),
+            self.clock.clone(),
+            self.metrics.clone(),
+            throttle.clone(),
+            tx,
+        ));
+        ScanStream {
+            rx,
+            driver,
+            cancel: self.config.cancel.clone(),
+            throttle,
+            redactor: self.config.redactor.clone(),
+            capture_budget: self.config.capture_budget.clone(),
+        }
+    }
+
+    /// Runs the whole scan and summarizes it; a cancelled scan is summarized
+    /// as interrupted.
+    pub async fn scan_collect(&self) -> ScanSummary {
+        let start_time = Instant::now();
+        let mut stream = self.scan();
+        let mut results = Vec::new();
+        while let Some(r) = stream.next().await {
+            results.push(r);
+        }
+        let total_time = self.clock.elapsed(start_time).as_millis();
+        self.metrics.finish(total_time);
+        let mut summary = ScanSummary::from_results(
+            new_scan_id(),
+            self.label.clone(),
+            results,
+            total_time,
+            &self.metrics,
+            stream.is_cancelled(),
+        );
+        summary.established = self.config.established.as_ref().map(|e| e.report());
+        summary.retry_budget = self.config.retry_budget.as_ref().map(|b| b.report());
+        summary
+    }
+}
+
+/// Results of [`Scanner::scan`] as they complete.
+///
+/// The stream ends once every port has been probed or the scan's
+/// [`CancellationToken`] is cancelled; dropping it early stops the scan.
+pub struct ScanStream {
+    rx: mpsc::Receiver<PortResult>,
+    driver: JoinHandle<()>,
+    cancel: CancellationToken,
+    throttle: Throttle,
+    redactor: Option<Redactor>,
+    capture_budget: Option<CaptureBudget>,
+}
+
+impl ScanStream {
+    /// True when the scan was cancelled, so the results are partial.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// A handle to pause the scan or change its concurrency while it runs.
+    pub fn throttle(&self) -> Throttle {
+        self.throttle.clone()
+    }
+}
+
+/// Paces a running scan: pausing stops new probes from starting (those in
+/// flight finish), and the concurrency limit can be moved either way.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    semaphore: Arc<Semaphore>,
+    concurrency: Arc<AtomicUsize>,
+    paused: Arc<watch::Sender<bool>>,
+    metrics: Arc<ScanMetrics>,
+}
+
+impl Throttle {
+    fn new(concurrency: usize, metrics: Arc<ScanMetrics>) -> Self {
+        metrics.set_concurrency(concurrency);
+        Throttle {
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+            concurrency: Arc::new(AtomicUsize::new(concurrency)),
+            paused: Arc::new(watch::Sender::new(false)),
+            metrics,
+        }
+    }
+
+    /// Stops starting new probes until [`Throttle::resume`].
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!(target: audit::TARGET, "scan paused");
+        }
+    }
+
+    /// Starts probing again after [`Throttle::pause`].
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!(target: audit::TARGET, "scan resumed");
+        }
+    }
+
+    /// True while paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// The current limit on probes in flight.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.load(Ordering::Relaxed)
+    }
+
+    /// Moves the limit on probes in flight; at least 1. Lowering it takes
+    /// effect as in-flight probes finish, so it must be called inside a
+    /// tokio runtime.
+    pub fn set_concurrency(&self, concurrency: usize) {
+        let concurrency = concurrency.max(1);
+        let old = self.concurrency.swap(concurrency, Ordering::Relaxed);
+        self.metrics.set_concurrency(concurrency);
+        if concurrency != old {
+            info!(target: audit::TARGET, from = old, to = concurrency, "concurrency changed");
+        }
+        if concurrency > old {
+            self.semaphore.add_permits(concurrency - old);
+        } else if concurrency < old {
+            let semaphore = self.semaphore.clone();
+            let surplus = (old - concurrency) as u32;
+            tokio::spawn(async move {
+                if let Ok(permits) = semaphore.acquire_many_owned(surplus).await {
+                    permits.forget();
+                }
+            });
+        }
+    }
+
+    async fn resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+
+    /// Waits for a connection slot, timing the wait when the scan keeps
+    /// stats.
+    async fn acquire(&self, clock: &dyn Clock) -> OwnedSemaphorePermit {
+        let waiting = self.metrics.stats().map(|_| Instant::now());
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("scan semaphore is never closed");
+        if let (Some(stats), Some(waiting)) = (self.metrics.stats(), waiting) {
+            stats.record_permit_wait(clock.elapsed(waiting));
+        }
+        permit
+    }
+}
+
+impl Stream for ScanStream {
+    type Item = PortResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PortResult>> {
+        // Every result leaves the scanner here, so this is where redaction
+        // can't be bypassed, and where what it keeps is first counted.
+        self.rx.poll_recv(cx).map(|r| {
+            r.map(|mut r| {
+                if let Some(redactor) = &self.redactor {
+                    redactor.apply(&mut r);
+                }
+                if let Some(budget) = &self.capture_budget {
+                    budget.retain(&mut r);
+                }
+                r
+            })
+        })
+    }
+}
+
+impl Drop for ScanStream {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+/// Spawns a probe per target/port pair as permits free up and forwards the
+/// results to `tx`.
+///
+/// Probes live in a [`JoinSet`], so aborting this task aborts them all, and
+/// cancellation shuts the set down before returning. A probe that fails to
+/// complete is logged and skipped.
+#[allow(clippy::too_many_arguments)]
+async fn drive(
+    config: ScanConfig,
+    connector: Arc<dyn Connector>,
+    probes: Vec<Arc<dyn Probe>>,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<ScanMetrics>,
+    throttle: Throttle,
+    tx: mpsc::Sender<PortResult>,
+) {
+    let ctx = Arc::new(ProbeContext {
+        connector,
+        probes,
+        clock: clock.clone(),
+        metrics: metrics.clone(),
+        probe_slots: Arc::new(Semaphore::new(config.probe_concurrency)),
+        buffers: BannerBuffers::default(),
+        conn_timeout: config.timeout,
+        port_timeouts: config.port_timeouts.clone(),
+        banner_timeout: config.banner_timeout,
+        response_cap: config.response_cap,
+        probe_budget: config.probe_budget,
+        probe_timeouts: config.probe_timeouts.clone(),
+        audit: config.audit,
+        evidence: config.evidence,
+        reach: config.unreachable_after.map(ReachCache::new),
+        established: config.established.clone(),
+        retry_budget: config.retry_budget.clone(),
+        timings: config.timings,
+        sniff: config.sniff,
+        banner_retry: config.banner_retry,
+        latencies: config.retime_outliers.then(HostLatencies::new),
+        outliers: Default::default(),
+        exhausted_warned: Default::default(),
+        start_time: Instant::now(),
+    });
+    let start_time = ctx.start_time;
+    let mut tasks = JoinSet::new();
+    let mut pending: HashMap<task::Id, (IpAddr, u16)> = HashMap::new();
+    let mut hosts = HostProgress::default();
+
+    let spawner = Spawner {
+        ctx: &ctx,
+        config: &config,
+        tx: &tx,
+    };
+    let spawn_all = async {
+        if config.host_concurrency.is_some() || config.per_host_concurrency.is_some() {
+            let per_host = config
+                .per_host_concurrency
+                .unwrap_or(Semaphore::MAX_PERMITS);
+            let host_limit = config
+                .host_concurrency
+                .unwrap_or_else(|| config.concurrency.div_ceil(per_host));
+            let mut targets = config.targets.iter();
+            let mut active: Vec<ActiveHost> = Vec::new();
+            let mut next = 0;
+            loop {
+                while let Some(res) = tasks.try_join_next_with_id() {
+                    let host =
+                        reap(res, &mut pending, &metrics, &tx, clock.elapsed(start_time)).await;
+                    hosts.reaped(host);
+                }
+                active.retain_mut(|host| {
+                    let done =
+                        host.ports.peek().is_none() && host.permits.available_permits() == per_host;
+                    if done {
+                        metrics.host_finished(host.ip);
+                    }
+                    !done
+                });
+                while active.len() < host_limit {
+                    let Some(target_ip) = targets.next() else {
+                        break;
+                    };
+                    info!(target: audit::TARGET, host = %target_ip, "host started");
+                    metrics.host_started(target_ip);
+                    active.push(ActiveHost {
+                        ip: target_ip,
+                        span: info_span!("target", target = %target_ip),
+                        ports: config.ports.iter().peekable(),
+                        permits: Arc::new(Semaphore::new(per_host)),
+                    });
+                }
+                if active.is_empty() {
+                    break;
+                }
+                // Round robin over the hosts that may take another probe.
+                let n = active.len();
+                let Some(i) = (0..n).map(|k| (next + k) % n).find(|&i| {
+                    active[i].permits.available_permits() > 0 && active[i].ports.peek().is_some()
+                }) else {
+                    // Every host is at its cap or waiting for its last probes.
+                    match tasks.join_next_with_id().await {
+                        Some(res) => {
+                            let host =
+                                reap(res, &mut pending, &metrics, &tx, clock.elapsed(start_time))
+                                    .await;
+                            hosts.reaped(host);
+                            continue;
+                        }
+                        None => break,
+                    }
+                };
+                next = i + 1;
+                let host = &mut active[i];
+                let host_permit = host
+                    .permits
+                    .clone()
+                    .try_acquire_owned()
+                    .expect("the host has a permit free");
+                throttle.resumed().await;
+                let permit = throttle.acquire(clock.as_ref()).await;
+                let port = host.ports.next().expect("the host has ports left");
+                let id = spawner.spawn(
+                    &mut tasks,
+                    &host.span,
+                    host.ip,
+                    port,
+                    (permit, Some(host_permit)),
+                );
+                pending.insert(id, (host.ip, port));
+                hosts.spawned(host.ip);
+                if host.ports.peek().is_none() {
+                    hosts.all_spawned(host.ip);
+                }
+            }
+        } else {
+            for target_ip in config.targets.iter() {
+                let target_span = info_span!("target", target = %target_ip);
+                info!(target: audit::TARGET, host = %target_ip, "host started");
+                for port in config.ports.iter() {
+                    throttle.resumed().await;
+                    let permit = throttle.acquire(clock.as_ref()).await;
+                    let id =
+                        spawner.spawn(&mut tasks, &target_span, target_ip, port, (permit, None));
+                    pending.insert(id, (target_ip, port));
+                    hosts.spawned(target_ip);
+                    while let Some(res) = tasks.try_join_next_with_id() {
+                        let host =
+                            reap(res, &mut pending, &metrics, &tx, clock.elapsed(start_time)).await;
+                        hosts.reaped(host);
+                    }
+                }
+                hosts.all_spawned(target_ip);
+            }
+        }
+        while let Some(res) = tasks.join_next_with_id().await {
+            let host = reap(res, &mut pending, &metrics, &tx, clock.elapsed(start_time)).await;
+            hosts.reaped(host);
+        }
+        // The re-time pass, once every port has had its first connect.
+        let outliers = std::mem::take(&mut *ctx.outliers.lock().expect("outliers"));
+        if !outliers.is_empty() {
+            info!("Re-timing {} outlier connects", outliers.len());
+        }
+        let mut retimed = futures::stream::iter(outliers)
+            .map(|r| {
+                let addr = socket_addr(&config, r.target, r.port);
+                retime(&ctx, addr, r)
+            })
+            .buffer_unordered(config.concurrency);
+        while let Some(r) = retimed.next().await {
+            let _ = tx.send(r).await;
+        }
+    };
+    tokio::select! {
+        _ = spawn_all => {}
+        _ = config.cancel.cancelled() => {
+            info!(target: audit::TARGET, aborted = tasks.len(), "scan cancelled");
+            tasks.shutdown().await;
+            // Held-back results go out with their first time alone.
+            let outliers = std::mem::take(&mut *ctx.outliers.lock().expect("outliers"));
+            for r in outliers {
+                let _ = tx.send(r).await;
+            }
+        }
+    }
+}
+
+/// What starts a probe.
+struct Spawner<'a> {
+    ctx: &'a Arc<ProbeContext>,
+    config: &'a ScanConfig,
+    tx: &'a mpsc::Sender<PortResult>,
+}
+
+impl Spawner<'_> {
+    /// Starts the probe of `port` on `target_ip`. It holds the global permit
+    /// through the connect stage only: an open port then queues for a slot
+    /// of the probe stage with its stream, without holding up the sweep. The
+    /// host's permit, if it has one, is held until the probe is done.
+    fn spawn(
+        &self,
+        tasks: &mut JoinSet<()>,
+        target_span: &tracing::Span,
+        target_ip: IpAddr,
+        port: u16,
+        permits: (OwnedSemaphorePermit, Option<OwnedSemaphorePermit>),
+    ) -> task::Id {
+        let task_ctx = self.ctx.clone();
+        let task_tx = self.tx.clone();
+        let addr = socket_addr(self.config, target_ip, port);
+
+        let span = debug_span!(parent: target_span, "port", port, attempt = 1);
+
+        let handle = tasks.spawn(
+            async move {
+                let (permit, _host_permit) = permits;
+                let connected = connect(&task_ctx, addr).await;
+                drop(permit);
+                task_ctx.metrics.record_connected();
+                let result = match connected {
+                    Connected::Done(r) => *r,
+                    Connected::Open(port, stream, duration) => {
+                        let _slot = task_ctx
+                            .probe_slots
+                            .acquire()
+                            .await
+                            .expect("probe slots are never closed");
+                        probe_open(&task_ctx, port, stream, duration).await
+                    }
+                };
+                debug!(
+                    status = ?result.status,
+                    error = result.error.as_ref().map(tracing::field::display),
+                    "connect finished"
+                );
+                task_ctx.metrics.record(&result.status);
+                if result.retime.is_some() {
+                    task_ctx.outliers.lock().expect("outliers").push(result);
+                    return;
+                }
+                let _ = task_tx.send(result).await;
+            }
+            .instrument(span),
+        );
+        handle.id()
+    }
+}
+
+/// The address to connect to for `port` on `ip`, with the scope of a
+/// link-local IPv6 target.
+fn socket_addr(config: &ScanConfig, ip: IpAddr, port: u16) -> SocketAddr {
+    match ip {
+        IpAddr::V6(v6) => {
+            let scope_id = config.scope_ids.get(&v6).copied().unwrap_or(0);
+            SocketAddr::V6(SocketAddrV6::new(v6, port, 0, scope_id))
+        }
+        IpAddr::V4(_) => SocketAddr::new(ip, port),
+    }
+}
+
+/// A host being scanned with `--host-concurrency` or
+/// `--per-host-concurrency`.
+struct ActiveHost<'a> {
+    ip: IpAddr,
+    span: tracing::Span,
+    ports: std::iter::Peekable<Ports<'a>>,
+    /// Its share of connections; all free once its probes are done.
+    permits: Arc<Semaphore>,
+}
+
+/// Probes in flight per host, to log each host finishing once its last
+/// probe is done.
+#[derive(Default)]
+struct HostProgress {
+    hosts: HashMap<IpAddr, (usize, u64, bool)>,
+}
+
+impl HostProgress {
+    fn spawned(&mut self, host: IpAddr) {
+        let (in_flight, probes, _) = self.hosts.entry(host).or_default();
+        *in_flight += 1;
+        *probes += 1;
+    }
+
+    fn all_spawned(&mut self, host: IpAddr) {
+        if let Some((_, _, done)) = self.hosts.get_mut(&host) {
+            *done = true;
+        }
+        self.check(host);
+    }
+
+    fn reaped(&mut self, host: Option<IpAddr>) {
+        let Some(host) = host else {
+            return;
+        };
+        if let Some((in_flight, _, _)) = self.hosts.get_mut(&host) {
+            *in_flight -= 1;
+        }
+        self.check(host);
+    }
+
+    fn check(&mut self, host: IpAddr) {
+        if let Some(&(0, probes, true)) = self.hosts.get(&host) {
+            self.hosts.remove(&host);
+            info!(target: audit::TARGET, host = %host, probes, "host finished");
+        }
+    }
+}
+
+/// Forgets a finished probe; one that panicked or was aborted still gets a
+/// result, marked with the failure, so its port isn't silently missing from
+/// the report.
+async fn reap(
+    res: Result<(task::Id, ()), JoinError>,
+    pending: &mut HashMap<task::Id, (IpAddr, u16)>,
+    metrics: &ScanMetrics,
+    tx: &mpsc::Sender<PortResult>,
+    elapsed: Duration,
+) -> Option<IpAddr> {
+    let e = match res {
+        Ok((id, ())) => return pending.remove(&id).map(|(target, _)| target),
+        Err(e) => e,
+    };
+    let Some((target, port)) = pending.remove(&e.id()) else {
+        error!("Task failed: {}", e);
+        return None;
+    };
+    let addr = SocketAddr::new(target, port);
+    let error = if e.is_panic() {
+        let message = panic_message(e.into_panic());
+        error!("Probe of {} panicked: {}", addr, message);
+        if let Some(stats) = metrics.stats() {
+            stats.record_panic();
+        }
+        PortError::Panicked(message)
+    } else {
+        error!("Task for {} failed: {}", addr, e);
+        PortError::Task(e.to_string())
+    };
+    metrics.record(&PortStatus::Filtered);
+    let mut r = PortResult::new(target, port, PortStatus::Filtered);
+    r.duration_ms = elapsed.as_millis();
+    r.error = Some(error);
+    let _ = tx.send(r).await;
+    Some(target)
+}
+
+/// The message a panic was raised with, when it is a string.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "(no message)".to_string(),
+        },
+    }
+}
+
+/// A short unique identifier for one run, used in reports and file names.
+pub fn new_scan_id() -> String {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    format!("{:x}-{:x}", millis, std::process::id())
+}
+
+/// Anything [`ScannerBuilder::ports`] accepts: a single port, a range, a
+/// list, a [`PortSet`], or a specification string in the CLI's `--ports`
+/// syntax.
+pub trait IntoPorts {
+    /// Converts into a set of ports without port 0.
+    fn into_ports(self) -> Result<PortSet, SpecError>;
+}
+
+impl IntoPorts for &str {
+    fn into_ports(self) -> Result<PortSet, SpecError> {
+        PortSet::parse(self)
+    }
+}
+
+impl IntoPorts for &String {
+    fn into_ports(self) -> Result<PortSet, SpecError> {
+        PortSet::parse(self)
+    }
+}
+
+impl IntoPorts for u16 {
+    fn into_ports(self) -> Result<PortSet, SpecError> {
+        (self..=self).into_ports()
+    }
+}
+
+impl IntoPorts for RangeInclusive<u16> {
+    fn into_ports(self) -> Result<PortSet, SpecError> {
+        let mut set = PortSet::new();
+        set.insert(self);
+        set.remove(0..=0);
+        Ok(set)
+    }
+}
+
+impl IntoPorts for Vec<u16> {
+    fn into_ports(self) -> Result<PortSet, SpecError> {
+        self.as_slice().into_ports()
+    }
+}
+
+impl IntoPorts for &[u16] {
+    fn into_ports(self) -> Result<PortSet, SpecError> {
+        let mut set: PortSet = self.iter().copied().collect();
+        set.remove(0..=0);
+        Ok(set)
+    }
+}
+
+impl IntoPorts for PortSet {
+    fn into_ports(mut self) -> Result<PortSet, SpecError> {
+        self.remove(0..=0);
+        Ok(self)
+    }
+}
+
+/// Fluent construction of a [`Scanner`]; configuration is validated once, by
+/// [`ScannerBuilder::build`].
+#[derive(Clone)]
+pub struct ScannerBuilder {
+    targets: TargetSet,
+    excluded_targets: TargetSet,
+    ports: Result<PortSet, SpecError>,
+    excluded_ports: Result<PortSet, SpecError>,
+    concurrency: usize,
+    host_concurrency: Option<usize>,
+    per_host_concurrency: Option<usize>,
+    probe_concurrency: usize,
+    timeout: Duration,
+    port_timeouts: PortTimeouts,
+    banner_timeout: Duration,
+    response_cap: Option<usize>,
+    probe_budget: Duration,
+    probe_timeouts: ProbeTimeouts,
+    probe_selection: ProbeSelection,
+    audit: bool,
+    evidence: bool,
+    stats: bool,
+    start_paused: bool,
+    retime_outliers: bool,
+    timings: bool,
+    sniff: bool,
+    banner_retry: bool,
+    redactor: Option<Redactor>,
+    capture_budget: Option<CaptureBudget>,
+    max_established_per_host: Option<usize>,
+    max_established: Option<usize>,
+    max_total_retries: Option<u64>,
+    unreachable_after: Option<u32>,
+    scope_ids: HashMap<Ipv6Addr, u32>,
+    cancel: CancellationToken,
+    connector: Arc<dyn Connector>,
+    probes: Vec<Arc<dyn Probe>>,
+    clock: Arc<dyn Clock>,
+    error: Option<SpecError>,
+}
+
+impl Default for ScannerBuilder {
+    fn default() -> Self {
+        ScannerBuilder {
+            targets: TargetSet::new(),
+            excluded_targets: TargetSet::new(),
+            ports: (1..=1024).into_ports(),
+            excluded_ports: Ok(PortSet::new()),
+            concurrency: 512,
+            host_concurrency: None,
+            per_host_concurrency: None,
+            probe_concurrency: 128,
+            timeout: Duration::from_millis(800),
+            port_timeouts: PortTimeouts::new(),
+            banner_timeout: Duration::from_millis(1200),
+            response_cap: None,
+            probe_budget: Duration::from_secs(3),
+            probe_timeouts: ProbeTimeouts::new(),
+            probe_selection: ProbeSelection::default(),
+            audit: false,
+            evidence: false,
+            stats: false,
+            start_paused: false,
+            retime_outliers: false,
+            timings: false,
+            sniff: false,
+            banner_retry: false,
+            redactor: None,
+            capture_budget: None,
+            max_established_per_host: None,
+            max_established: None,
+            max_total_retries: None,
+            unreachable_after: Some(3),
+            scope_ids: HashMap::new(),
+            cancel: CancellationToken::new(),
+            connector: Arc::new(TcpConnector::default()),
+            probes: Vec::new(),
+            clock: Arc::new(SystemClock),
+            error: None,
+        }
+    }
+}
+
+impl ScannerBuilder {
+    /// Adds targets: an IP address, a CIDR block such as `10.0.0.0/24` or a
+    /// range such as `10.0.0.5-10.0.0.20`. Overlaps are scanned once.
+    pub fn target(mut self, target: impl AsRef<str>) -> Self {
+        if let Err(e) = self.targets.insert(target.as_ref()) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// Adds an already parsed target address; duplicates are ignored.
+    pub fn target_addr(mut self, target: IpAddr) -> Self {
+        self.targets.insert_addr(target);
+        self
+    }
+
+    /// Leaves out hosts, in the same forms [`ScannerBuilder::target`]
+    /// accepts, wherever they were added.
+    pub fn exclude_target(mut self, target: impl AsRef<str>) -> Self {
+        if let Err(e) = self.excluded_targets.insert(target.as_ref()) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// Leaves out every host of an already built set.
+    pub fn exclude_hosts(mut self, hosts: &TargetSet) -> Self {
+        self.excluded_targets.insert_all(hosts);
+        self
+    }
+
+    /// Sets the ports to probe on every target (default `1-1024`).
+    pub fn ports(mut self, ports: impl IntoPorts) -> Self {
+        self.ports = ports.into_ports();
+        self
+    }
+
+    /// Leaves out ports from the set given to [`ScannerBuilder::ports`].
+    pub fn exclude_ports(mut self, ports: impl IntoPorts) -> Self {
+        self.excluded_ports = ports.into_ports();
+        self
+    }
+
+    /// Sets how many connection attempts may be in flight at once (default 512).
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Scans at most `hosts` hosts at once, sharing the permits of
+    /// [`ScannerBuilder::concurrency`] out fairly between them.
+    pub fn host_concurrency(mut self, hosts: usize) -> Self {
+        self.host_concurrency = Some(hosts);
+        self
+    }
+
+    /// Allows at most `connections` attempts in flight to any one host,
+    /// within [`ScannerBuilder::concurrency`]. Without
+    /// [`ScannerBuilder::host_concurrency`], as many hosts are scanned at
+    /// once as it takes to use every permit.
+    pub fn per_host_concurrency(mut self, connections: usize) -> Self {
+        self.per_host_concurrency = Some(connections);
+        self
+    }
+
+    /// Sets how many open ports may have their banner read and follow-up
+    /// probes run at once (default 128). These run apart from the
+    /// connection attempts of [`ScannerBuilder::concurrency`], so slow
+    /// banners don't hold up the connect sweep; a connected port keeps its
+    /// connect permit only while it waits for a probe slot.
+    pub fn probe_concurrency(mut self, probes: usize) -> Self {
+        self.probe_concurrency = probes;
+        self
+    }
+
+    /// Sets the connect timeout (default 800 ms).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gives some ports a connect timeout of their own; see [`PortTimeouts`].
+    pub fn port_timeouts(mut self, timeouts: PortTimeouts) -> Self {
+        self.port_timeouts = timeouts;
+        self
+    }
+
+    /// Sets how long to wait for a banner on open ports (default 1200 ms).
+    pub fn banner_timeout(mut self, timeout: Duration) -> Self {
+        self.banner_timeout = timeout;
+        self
+    }
+
+    /// Keeps up to `cap` bytes of what each open port sends as
+    /// [`PortResult::response`]. Reading goes on after the banner until the
+    /// cap, the end of the stream or the banner timeout, so ports that stay
+    /// open without a word more hold their probe for the whole timeout.
+    pub fn response_cap(mut self, cap: usize) -> Self {
+        self.response_cap = Some(cap);
+        self
+    }
+
+    /// Runs `probe` on every open port it applies to after the banner read,
+    /// in the order probes are added, sharing connections as described in
+    /// [`crate::probe`].
+    pub fn probe(mut self, probe: impl Probe + 'static) -> Self {
+        self.probes.push(Arc::new(probe));
+        self
+    }
+
+    /// Sets how long the banner retry and the follow-up probes of an open
+    /// port may take in all, reconnects included (default 3 s).
+    pub fn probe_budget(mut self, budget: Duration) -> Self {
+        self.probe_budget = budget;
+        self
+    }
+
+    /// Gives each type of follow-up probe a timeout of its own within the
+    /// probe budget; see [`ProbeTimeouts`].
+    pub fn probe_timeouts(mut self, timeouts: ProbeTimeouts) -> Self {
+        self.probe_timeouts = timeouts;
+        self
+    }
+
+    /// Runs only the follow-up probes whose type `selection` allows,
+    /// whatever was added.
+    pub fn probe_selection(mut self, selection: ProbeSelection) -> Self {
+        self.probe_selection = selection;
+        self
+    }
+
+    /// Records on every result when its connect attempt started and
+    /// completed and the local port it came from ([`PortResult::audit`]).
+    /// Plain TCP then binds each socket before connecting, so that refused
+    /// and timed-out attempts have their source port too.
+    pub fn audit(mut self) -> Self {
+        self.audit = true;
+        self
+    }
+
+    /// Records on every result the steps its probe went through and what
+    /// came of each ([`PortResult::evidence`]).
+    pub fn evidence(mut self) -> Self {
+        self.evidence = true;
+        self
+    }
+
+    /// Starts every scan paused: no probe starts until its
+    /// [`ScanStream::throttle`] is resumed.
+    pub fn start_paused(mut self, paused: bool) -> Self {
+        self.start_paused = paused;
+        self
+    }
+
+    /// Keeps the detailed counters of [`ScanMetrics::stats`]: how connects
+    /// and banner reads ended and how long probes waited for a slot.
+    pub fn stats(mut self) -> Self {
+        self.stats = true;
+        self
+    }
+
+    /// Measures a port's connect again at the end of the scan when the
+    /// port answered open or closed, but far slower than its host's
+    /// rolling p95 ([`crate::latency`]). Its result is held back until
+    /// then and keeps both times in [`PortResult::retime`]; the connect
+    /// time statistics take the faster one.
+    pub fn retime_outliers(mut self) -> Self {
+        self.retime_outliers = true;
+        self
+    }
+
+    /// Keeps the connect time of every port that answered, and the banner
+    /// time of every open port that sent one, in [`PortResult::timing`],
+    /// for a latency [`crate::heatmap`].
+    pub fn record_timings(mut self) -> Self {
+        self.timings = true;
+        self
+    }
+
+    /// Tells the service of a port that sends a banner from its first bytes,
+    /// by the table of [`crate::sniff::SIGNATURES`], before falling back to
+    /// the banner rules and the port number. Silent ports are left to
+    /// probes such as the ladder of [`crate::sniff::Rung`]s.
+    pub fn sniff(mut self) -> Self {
+        self.sniff = true;
+        self
+    }
+
+    /// Connects once more to an open port whose banner read was reset or
+    /// that closed without sending anything, [`BANNER_RETRY_DELAY`] later,
+    /// and reads its banner again, as services behind SYN-cookie appliances
+    /// and the like may need. The retry comes out of the probe budget and
+    /// is recorded as [`PortResult::banner_retried`].
+    pub fn banner_retry(mut self) -> Self {
+        self.banner_retry = true;
+        self
+    }
+
+    /// Redacts the matches of `redactor` from every result before the
+    /// [`ScanStream`] hands it out, so nothing downstream sees them; see
+    /// [`crate::redact`].
+    pub fn redact(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Counts what every result keeps of what services sent against
+    /// `budget` as the [`ScanStream`] hands it out, cutting it to previews
+    /// once the budget is spent; see [`crate::capture`].
+    pub fn capture_budget(mut self, budget: CaptureBudget) -> Self {
+        self.capture_budget = Some(budget);
+        self
+    }
+
+    /// Holds at most `connections` established connections to any one host
+    /// at once, from the handshake until the stream is dropped; attempts
+    /// still in flight don't count. See [`crate::established`].
+    pub fn max_established_per_host(mut self, connections: usize) -> Self {
+        self.max_established_per_host = Some(connections);
+        self
+    }
+
+    /// Holds at most `connections` established connections at once across
+    /// all hosts.
+    pub fn max_established(mut self, connections: usize) -> Self {
+        self.max_established = Some(connections);
+        self
+    }
+
+    /// Makes at most `retries` retries in the whole scan, of connects that
+    /// found no free local port and of [`banner_retry`](Self::banner_retry)
+    /// reads; see [`crate::retry`].
+    pub fn max_total_retries(mut self, retries: u64) -> Self {
+        self.max_total_retries = Some(retries);
+        self
+    }
+
+    /// Leaves a host's remaining ports unprobed after `errors` unreachable
+    /// errors in a row from it (default 3), re-probing now and then in case
+    /// it comes back.
+    pub fn unreachable_after(mut self, errors: u32) -> Self {
+        self.unreachable_after = Some(errors);
+        self
+    }
+
+    /// Probes every port of a host, however many came back unreachable.
+    pub fn no_unreachable_cache(mut self) -> Self {
+        self.unreachable_after = None;
+        self
+    }
+
+    /// Reaches the link-local target `addr` through the interface with index
+    /// `scope_id`; without one, connects to it fail.
+    pub fn scope_id(mut self, addr: Ipv6Addr, scope_id: u32) -> Self {
+        self.scope_ids.insert(addr, scope_id);
+        self
+    }
+
+    /// Opens connections through `connector` instead of plain TCP.
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.connector = Arc::new(connector);
+        self
+    }
+
+    /// Connects over plain TCP closing every connection with a reset
+    /// ([`TcpConnector::zero_linger`]), so that a fast scan doesn't run out
+    /// of local ports to connect from; replaces any earlier
+    /// [`ScannerBuilder::connector`].
+    pub fn zero_linger(self) -> Self {
+        self.connector(TcpConnector::default().zero_linger())
+    }
+
+    /// Reads timestamps and durations from `clock` instead of the system
+    /// clock; a [`FixedClock`](crate::clock::FixedClock) makes every
+    /// duration zero.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Uses `token` to stop the scan instead of a fresh one.
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Validates the configuration and creates the scanner.
+    pub fn build(self) -> Result<Scanner, ScanError> {
+        if let Some(e) = self.error {
+            return Err(ScanError::InvalidTarget(e));
+        }
+        let mut targets = self.targets;
+        targets.remove_all(&self.excluded_targets);
+        let mut ports = self.ports.map_err(ScanError::InvalidPorts)?;
+        ports.remove_all(&self.excluded_ports.map_err(ScanError::InvalidPorts)?);
+        let config = ScanConfig {
+            targets,
+            ports,
+            concurrency: self.concurrency,
+            host_concurrency: self.host_concurrency,
+            per_host_concurrency: self.per_host_concurrency,
+            probe_concurrency: self.probe_concurrency,
+            timeout: self.timeout,
+            port_timeouts: self.port_timeouts,
+            banner_timeout: self.banner_timeout,
+            response_cap: self.response_cap,
+            probe_budget: self.probe_budget,
+            probe_timeouts: self.probe_timeouts,
+            audit: self.audit,
+            evidence: self.evidence,
+            stats: self.stats,
+            start_paused: self.start_paused,
+            retime_outliers: self.retime_outliers,
+            timings: self.timings,
+            sniff: self.sniff,
+            banner_retry: self.banner_retry,
+            redactor: self.redactor,
+            capture_budget: self.capture_budget,
+            established: (self.max_established_per_host.is_some()
+                || self.max_established.is_some())
+            .then(|| EstablishedLimit::new(self.max_established_per_host, self.max_established)),
+            retry_budget: self.max_total_retries.map(RetryBudget::new),
+            unreachable_after: self.unreachable_after,
+            scope_ids: self.scope_ids,
+            cancel: self.cancel,
+        };
+        let selection = self.probe_selection;
+        let probes = self
+            .probes
+            .into_iter()
+            .filter(|p| selection.allows(p.kind()))
+            .collect();
+        Scanner::from_parts(config, self.connector, probes, self.clock)
+    }
+}
+
+/// What every probe of a scan shares.
+struct ProbeContext {
+    connector: Arc<dyn Connector>,
+    probes: Vec<Arc<dyn Probe>>,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<ScanMetrics>,
+    /// Slots of the probe stage, one per open port being probed.
+    probe_slots: Arc<Semaphore>,
+    buffers: BannerBuffers,
+    conn_timeout: Duration,
+    port_timeouts: PortTimeouts,
+    banner_timeout: Duration,
+    response_cap: Option<usize>,
+    probe_budget: Duration,
+    probe_timeouts: ProbeTimeouts,
+    audit: bool,
+    evidence: bool,
+    reach: Option<ReachCache>,
+    established: Option<EstablishedLimit>,
+    retry_budget: Option<RetryBudget>,
+    timings: bool,
+    sniff: bool,
+    banner_retry: bool,
+    /// The rolling connect times of each host, when outliers are re-timed.
+    latencies: Option<HostLatencies>,
+    /// Results held back for the re-time pass.
+    outliers: std::sync::Mutex<Vec<PortResult>>,
+    /// When running out of local ports was last warned about.
+    exhausted_warned: std::sync::Mutex<Option<Instant>>,
+    start_time: Instant,
+}
+
+/// How many times a connect that found no free local port is retried.
+const EXHAUSTED_RETRIES: u32 = 4;
+
+/// How long the first retry of such a connect waits; each one after it
+/// waits twice as long, 1.5s in all, for connections in `TIME_WAIT` to
+/// expire.
+const EXHAUSTED_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long a banner retry waits before connecting again.
+pub const BANNER_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// How often running out of local ports is warned about at most.
+const EXHAUSTED_WARN_EVERY: Duration = Duration::from_secs(10);
+
+/// How far off a deadline too late to represent is put: about 30 years,
+/// as tokio does for its own timeouts.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// The instant `after` from now, or [`FAR_FUTURE`] from now when that is
+/// past what an instant can hold, as with a `--probe-budget` of `u64::MAX`
+/// seconds.
+fn deadline_in(after: Duration) -> tokio::time::Instant {
+    let now = tokio::time::Instant::now();
+    now.checked_add(after).unwrap_or_else(|| now + FAR_FUTURE)
+}
+
+/// One port on its way through the probe: what each step needs to know of
+/// it and the evidence the steps leave, until it becomes a [`PortResult`].
+struct PortProbe {
+    addr: SocketAddr,
+    timeout: Duration,
+    audit: Option<ProbeAudit>,
+    retime: Option<Retime>,
+    /// None when the scan records no timings, or before the port answered.
+    timing: Option<Timing>,
+    /// None when the scan records no evidence.
+    evidence: Option<Vec<Evidence>>,
+    retries_exhausted: bool,
+}
+
+impl PortProbe {
+    fn new(ctx: &ProbeContext, addr: SocketAddr) -> Self {
+        PortProbe {
+            addr,
+            timeout: ctx.port_timeouts.resolve(addr.port(), ctx.conn_timeout),
+            audit: None,
+            retime: None,
+            timing: None,
+            evidence: ctx.evidence.then(Vec::new),
+            retries_exhausted: false,
+        }
+    }
+
+    /// Adds a step to the evidence, if it is recorded.
+    fn record(&mut self, evidence: Evidence) {
+        if let Some(steps) = &mut self.evidence {
+            steps.push(evidence);
+        }
+    }
+
+    /// The result of the port, classified `duration` into the scan.
+    fn result(self, status: PortStatus, duration: Duration) -> PortResult {
+        let mut r = PortResult::new(self.addr.ip(), self.addr.port(), status);
+        r.duration_ms = duration.as_millis();
+        r.timeout_ms = Some(self.timeout.as_millis() as u64);
+        r.audit = self.audit;
+        r.retime = self.retime;
+        r.timing = self.timing;
+        r.evidence = self.evidence.unwrap_or_default();
+        r.retries_exhausted = self.retries_exhausted;
+        r
+    }
+}
+
+/// How the connect stage of a port ended.
+enum Connected {
+    /// Classified with nothing left to read.
+    Done(Box<PortResult>),
+    /// Open, with the stream for the probe stage and when it connected.
+    Open(PortProbe, Connection, Duration),
+}
+
+/// Whether a connect attempt got an answer, open or closed, whose time
+/// says how fast the host is.
+fn answered(res: &Result<std::io::Result<Connection>, tokio::time::error::Elapsed>) -> bool {
+    match res {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => matches!(Failure::of(e), Failure::Refused | Failure::Reset),
+        Err(_) => false,
+    }
+}
+
+/// Measures the connect of an outlier again and sends its result with
+/// both times; only an open or closed answer counts as a measurement.
+async fn retime(ctx: &ProbeContext, addr: SocketAddr, mut r: PortResult) -> PortResult {
+    let timeout_for = r.timeout_ms.map_or(ctx.conn_timeout, Duration::from_millis);
+    let slot = match &ctx.established {
+        Some(limit) => Some(limit.reserve(addr.ip()).await),
+        None => None,
+    };
+    let started = Instant::now();
+    let res = timeout(timeout_for, ctx.connector.connect(addr)).await;
+    drop(slot);
+    let took = ctx.clock.elapsed(started);
+    let retime = r.retime.as_mut().expect("an outlier has its first time");
+    if answered(&res) {
+        retime.second(took);
+    }
+    debug!(
+        port = addr.port(),
+        first_ms = retime.first_ms,
+        second_ms = retime.second_ms,
+        "connect re-timed"
+    );
+    if let Some(stats) = ctx.metrics.stats() {
+        stats.record_connect_time(retime.best());
+    }
+    r
+}
+
+/// The connect stage of a port: classifies it by how its connection
+/// attempt ended.
+async fn connect(ctx: &ProbeContext, addr: SocketAddr) -> Connected {
+    let target = addr.ip();
+    let mut port = PortProbe::new(ctx, addr);
+
+    if let Some(error) = ctx.reach.as_ref().and_then(|r| r.skip(target)) {
+        if let Some(stats) = ctx.metrics.stats() {
+            stats.record_unreachable_cached();
+        }
+        port.record(Evidence::new(Step::ReachCache, Outcome::Skipped).detail(error.clone()));
+        let mut r = port.result(PortStatus::Filtered, ctx.clock.elapsed(ctx.start_time));
+        r.timeout_ms = None;
+        r.error = Some(PortError::UnreachableCached(error));
+        return Connected::Done(Box::new(r));
+    }
+    ctx.metrics.record_attempt();
+    debug!(
+        timeout_ms = port.timeout.as_millis() as u64,
+        "connect started"
+    );
+    let started_at = ctx.audit.then(|| ctx.clock.now());
+    let mut attempt = 1;
+    // Out of local ports, the connect fails before a SYN is sent, which
+    // says nothing about the port: it is tried again, still holding its
+    // slot, which slows the scan down until ports free up.
+    let (connect_res, source, took) = loop {
+        let connect_started = Instant::now();
+        let source = OnceLock::new();
+        let connect = if ctx.audit {
+            ctx.connector.connect_from(addr, &source)
+        } else {
+            ctx.connector.connect(addr)
+        };
+        let connect_res = timeout(port.timeout, connect).await;
+        let took = ctx.clock.elapsed(connect_started);
+        match &connect_res {
+            Ok(Err(e))
+                if Failure::of(e) == Failure::PortsExhausted
+                    && attempt <= EXHAUSTED_RETRIES
+                    && may_retry(ctx, &mut port) =>
+            {
+                ports_exhausted(ctx, e);
+                port.record(
+                    Evidence::new(Step::TcpConnect, Outcome::Error)
+                        .elapsed(took)
+                        .detail(e.to_string()),
+                );
+                tokio::time::sleep(EXHAUSTED_BACKOFF * 2u32.pow(attempt - 1)).await;
+                attempt += 1;
+                ctx.metrics.record_retry();
+                debug!(attempt, "connect retried");
+            }
+            _ => break (connect_res, source, took),
+        }
+    };
+    if let Some(stats) = ctx.metrics.stats() {
+        stats.record_connect(match &connect_res {
+            Ok(Ok(_)) => ConnectOutcome::Succeeded,
+            Ok(Err(e)) => match Failure::of(e) {
+                Failure::Reset => ConnectOutcome::Reset,
+                Failure::Refused => ConnectOutcome::Refused,
+                _ => ConnectOutcome::Failed,
+            },
+            Err(_) => ConnectOutcome::TimedOut,
+        });
+    }
+    if answered(&connect_res) {
+        if ctx.timings {
+            port.timing = Some(Timing {
+                connect_ms: took.as_secs_f64() * 1000.0,
+                banner_ms: None,
+            });
+        }
+        match &ctx.latencies {
+            Some(latencies) if latencies.observe(target, took) => {
+                debug!(
+                    connect_ms = took.as_secs_f64() * 1000.0,
+                    "connect time is an outlier, re-timing it at the end"
+                );
+                port.retime = Some(Retime::first(took));
+            }
+            _ => {
+                if let Some(stats) = ctx.metrics.stats() {
+                    stats.record_connect_time(took);
+                }
+            }
+        }
+    }
+    let connected = Evidence::new(
+        Step::TcpConnect,
+        match &connect_res {
+            Ok(Ok(_)) => Outcome::Success,
+            Ok(Err(e)) => match Failure::of(e) {
+                Failure::Reset => Outcome::Reset,
+                Failure::Refused => Outcome::Refused,
+                _ => Outcome::Error,
+            },
+            Err(_) => Outcome::Timeout,
+        },
+    )
+    .elapsed(took);
+    port.record(match &connect_res {
+        Ok(Err(e)) if Failure::of(e) != Failure::Refused => connected.detail(e.to_string()),
+        _ => connected,
+    });
+
+    let duration = ctx.clock.elapsed(ctx.start_time);
+    port.audit = started_at.map(|started_at| ProbeAudit {
+        started_at,
+        finished_at: ctx.clock.now(),
+        source_port: source.get().map(SocketAddr::port),
+        attempt,
+    });
+
+    if let Some(reach) = &ctx.reach {
+        match &connect_res {
+            Ok(Ok(_)) => reach.answered(target),
+            Ok(Err(e)) => match Failure::of(e) {
+                Failure::Unreachable => reach.unreachable(target, &e.to_string()),
+                Failure::Refused | Failure::Reset => reach.answered(target),
+                _ => {}
+            },
+            Err(_) => {}
+        }
+    }
+
+    let (status, error) = match connect_res {
+        Ok(Ok(stream)) => {
+            return match hold(ctx, addr, port.timeout, stream).await {
+                Ok(stream) => Connected::Open(port, stream, duration),
+                Err(e) => Connected::Done(Box::new(open_port(port, duration, Err(e), ctx.sniff))),
+            };
+        }
+        // A reset in answer to the SYN is reported as refused. A reset
+        // reported by the connect came after the handshake: the service
+        // accepted and reset before the connect was polled again, as a busy
+        // scan may be late to, so it is a reset on read come early.
+        Ok(Err(e)) => match Failure::of(&e) {
+            Failure::Reset => {
+                return Connected::Done(Box::new(open_port(port, duration, Err(e), ctx.sniff)));
+            }
+            Failure::Refused => (PortStatus::Closed, None),
+            _ => (PortStatus::Filtered, Some(PortError::from_io(&e))),
+        },
+        Err(_) => (PortStatus::Filtered, Some(PortError::Timeout)),
+    };
+    let mut r = port.result(status, duration);
+    r.error = error;
+    Connected::Done(Box::new(r))
+}
+
+/// Counts a connection just made to `addr` against the
+/// `--max-established` limits. One that finds them reached is closed and
+/// made again once a slot frees up, so that no more than the limit are ever
+/// held; the new connect has `timeout_for` again.
+async fn hold(
+    ctx: &ProbeContext,
+    addr: SocketAddr,
+    timeout_for: Duration,
+    stream: Connection,
+) -> std::io::Result<Connection> {
+    let Some(limit) = &ctx.established else {
+        return Ok(stream);
+    };
+    let stream = match limit.try_hold(addr.ip(), stream) {
+        Ok(held) => return Ok(held),
+        Err(stream) => stream,
+    };
+    drop(stream);
+    debug!("established limit reached, reconnecting once a connection closes");
+    let slot = limit.reserve(addr.ip()).await;
+    match timeout(timeout_for, ctx.connector.connect(addr)).await {
+        Ok(connected) => Ok(slot.hold(connected?)),
+        Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+    }
+}
+
+/// Connects to `addr` holding an established slot, waiting for one first
+/// when the scan has limits.
+async fn connect_held(ctx: &ProbeContext, addr: SocketAddr) -> std::io::Result<Connection> {
+    match &ctx.established {
+        Some(limit) => {
+            let slot = limit.reserve(addr.ip()).await;
+            Ok(slot.hold(ctx.connector.connect(addr).await?))
+        }
+        None => ctx.connector.connect(addr).await,
+    }
+}
+
+/// Counts a connect that found no free local port and warns about it, at
+/// most every [`EXHAUSTED_WARN_EVERY`].
+fn ports_exhausted(ctx: &ProbeContext, e: &std::io::Error) {
+    if let Some(stats) = ctx.metrics.stats() {
+        stats.record_ports_exhausted();
+    }
+    let mut warned = ctx
+        .exhausted_warned
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if warned.is_some_and(|at| at.elapsed() < EXHAUSTED_WARN_EVERY) {
+        return;
+    }
+    *warned = Some(Instant::now());
+    warn!(
+        error = %e,
+        "out of local ports to connect from, retrying; a lower --concurrency or --linger-zero avoids it"
+    );
+}
+
+/// The probe stage of an open port: reads its banner over `stream`, once
+/// more over a new connection with a banner retry, and runs the follow-up
+/// probes. `duration` is when it connected.
+async fn probe_open(
+    ctx: &ProbeContext,
+    mut port: PortProbe,
+    mut stream: Connection,
+    duration: Duration,
+) -> PortResult {
+    let mut buf = ctx.buffers.take();
+    let banner_deadline = deadline_in(ctx.banner_timeout);
+    let (mut grabbed, mut open) =
+        grab_banner(&mut stream, ctx, &mut port, &mut buf, banner_deadline).await;
+    let deadline = deadline_in(ctx.probe_budget);
+    let retried = ctx.banner_retry
+        && worth_retrying(&grabbed, open)
+        && may_retry_banner(ctx, &mut port, deadline);
+    let mut connections = 1;
+    let mut stream = Some(stream);
+    if retried {
+        // Closed first, so that it doesn't hold the established slot the
+        // new connection waits for.
+        stream = None;
+        if let Some(mut fresh) = reconnect(ctx, &mut port, deadline).await {
+            connections += 1;
+            buf.clear();
+            let banner_deadline = deadline_in(ctx.banner_timeout).min(deadline);
+            (grabbed, open) =
+                grab_banner(&mut fresh, ctx, &mut port, &mut buf, banner_deadline).await;
+            stream = Some(fresh);
+        }
+    }
+    let response = ctx
+        .response_cap
+        .filter(|_| !buf.is_empty())
+        .map(|cap| Bytes::copy_from_slice(&buf[..buf.len().min(cap)]));
+    let session = Session {
+        stream: stream.filter(|_| open),
+        used: !buf.is_empty(),
+        connections,
+    };
+    ctx.buffers.give_back(buf);
+    let mut r = open_port(port, duration, grabbed, ctx.sniff);
+    r.response = response;
+    r.banner_retried = retried;
+    if !ctx.probes.is_empty() {
+        follow_up(ctx, &mut r, session, deadline).await;
+    }
+    r
+}
+
+/// Whether a banner read ended in a reset or the end of the stream, as a
+/// banner retry asks again; silence until the timeout doesn't.
+fn worth_retrying(grabbed: &std::io::Result<Option<Banner>>, open: bool) -> bool {
+    match grabbed {
+        Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+        Ok(None) => !open,
+        Ok(Some(_)) => false,
+    }
+}
+
+/// Whether the port may be retried: always without a retry budget, and
+/// while it lasts with one. A port refused a retry is flagged.
+fn may_retry(ctx: &ProbeContext, port: &mut PortProbe) -> bool {
+    match &ctx.retry_budget {
+        Some(budget) if !budget.take() => {
+            debug!("retry budget spent, not retrying");
+            port.retries_exhausted = true;
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Whether the banner retry of the port fits in what is left of its probe
+/// budget, which runs out at `deadline`, and in the scan's retry budget.
+fn may_retry_banner(
+    ctx: &ProbeContext,
+    port: &mut PortProbe,
+    deadline: tokio::time::Instant,
+) -> bool {
+    let skipped = if tokio::time::Instant::now() + BANNER_RETRY_DELAY >= deadline {
+        "banner retry: no probe budget left"
+    } else if !may_retry(ctx, port) {
+        "banner retry: retry budget spent"
+    } else {
+        return true;
+    };
+    port.record(Evidence::new(Step::TcpConnect, Outcome::Skipped).detail(skipped));
+    false
+}
+
+/// Connects to the port again for its banner retry, after
+/// [`BANNER_RETRY_DELAY`], by `deadline` and within the port's connect
+/// timeout, holding an established slot as the first connection did.
+async fn reconnect(
+    ctx: &ProbeContext,
+    port: &mut PortProbe,
+    deadline: tokio::time::Instant,
+) -> Option<Connection> {
+    tokio::time::sleep_until(tokio::time::Instant::now() + BANNER_RETRY_DELAY).await;
+    ctx.metrics.record_retry();
+    debug!("banner read failed, connecting again");
+    let started = Instant::now();
+    let until = deadline_in(port.timeout).min(deadline);
+    let connect_res = timeout_at(until, connect_held(ctx, port.addr)).await;
+    let connect = Evidence::new(
+        Step::TcpConnect,
+        match &connect_res {
+            Ok(Ok(_)) => Outcome::Success,
+            Ok(Err(e)) if Failure::of(e) == Failure::Refused => Outcome::Refused,
+            Ok(Err(_)) => Outcome::Error,
+            Err(_) => Outcome::Timeout,
+        },
+    )
+    .elapsed(ctx.clock.elapsed(started));
+    port.record(match &connect_res {
+        Ok(Err(e)) => connect.detail(format!("banner retry: {}", e)),
+        _ => connect.detail("banner retry"),
+    });
+    match connect_res {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            debug!(error = %e, "banner retry connect failed");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// The result of an open port, with what its banner read gave; with `sniff`
+/// the banner's first bytes tell the service if a signature knows them.
+fn open_port(
+    mut port: PortProbe,
+    duration: Duration,
+    grabbed: std::io::Result<Option<Banner>>,
+    sniff: bool,
+) -> PortResult {
+    let reset_on_read = matches!(
+        &grabbed,
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset
+    );
+    let (banner, error) = match grabbed {
+        Ok(banner) => (banner, None),
+        Err(e) => (None, Some(PortError::Banner(e.to_string()))),
+    };
+    let sniffed = banner
+        .as_ref()
+        .filter(|_| sniff)
+        .and_then(|b| sniff::identify(b.as_bytes()));
+    let (info, basis) = match sniffed {
+        Some((info, signature)) => (info, Some(signature)),
+        None => {
+            let text = banner.as_ref().map(Banner::to_str);
+            detect_service_by(port.addr.port(), text.as_deref())
+        }
+    };
+    port.record(match basis {
+        Some(basis) => Evidence::new(Step::ServiceMatch, Outcome::Matched).detail(basis),
+        None => Evidence::new(Step::ServiceMatch, Outcome::NoMatch),
+    });
+    let banner_encoding = banner.as_ref().and_then(Banner::encoding);
+    let mut r = port.result(PortStatus::Open, duration);
+    r.banner = banner;
+    r.banner_encoding = banner_encoding;
+    r.service = info.service;
+    r.product = info.product;
+    r.version = info.version;
+    r.extra_info = info.extra_info;
+    r.cpe = info.cpe;
+    r.reset_on_read = reset_on_read;
+    r.error = error;
+    r
+}
+
+/// Reads whatever the service sends first into `buf`; silence until
+/// `deadline` or an immediate close is not an error, just no banner. With a
+/// response cap, reading goes on into `buf` until it holds that many bytes,
+/// the stream ends or the deadline is up. Also tells whether the stream is
+/// still open for follow-up probes.
+async fn grab_banner(
+    stream: &mut Connection,
+    ctx: &ProbeContext,
+    port: &mut PortProbe,
+    buf: &mut BytesMut,
+    deadline: tokio::time::Instant,
+) -> (std::io::Result<Option<Banner>>, bool) {
+    let started = Instant::now();
+    let mut read_res = timeout_at(deadline, read_banner(stream, buf)).await;
+    if let (Some(timing), Ok(Ok(Some(_)))) = (&mut port.timing, &read_res) {
+        timing.banner_ms = Some(ctx.clock.elapsed(started).as_secs_f64() * 1000.0);
+    }
+    if let Ok(Ok(None)) = read_res {
+        // After a plain close every read sees the end of the stream again;
+        // one that fails was reset behind the close.
+        if let Ok(Err(e)) = timeout_at(deadline, stream.read(&mut [0; 1])).await {
+            if e.kind() == std::io::ErrorKind::ConnectionReset {
+                read_res = Ok(Err(e));
+            }
+        }
+    }
+    match &read_res {
+        Ok(Ok(_)) => trace!(bytes = buf.len(), "banner read"),
+        Ok(Err(e)) => debug!(error = %e, "banner read failed"),
+        Err(_) => trace!("no banner before timeout"),
+    }
+    let read = Evidence::new(
+        Step::BannerRead,
+        match &read_res {
+            Ok(Ok(Some(_))) => Outcome::Success,
+            Ok(Ok(None)) => Outcome::Empty,
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => Outcome::Reset,
+            Ok(Err(_)) => Outcome::Error,
+            Err(_) => Outcome::Timeout,
+        },
+    )
+    .elapsed(ctx.clock.elapsed(started));
+    port.record(match &read_res {
+        Ok(Ok(Some(_))) => read.detail(format!("{} bytes", buf.len())),
+        Ok(Err(e)) => read.detail(e.to_string()),
+        _ => read,
+    });
+    if let (Some(cap), Ok(Ok(Some(_)))) = (ctx.response_cap, &read_res) {
+        if let Ok(Err(e)) = timeout_at(deadline, read_rest(stream, buf, cap)).await {
+            debug!(error = %e, "response read failed");
+        }
+        trace!(bytes = buf.len(), "response read");
+    }
+    let bytes = match read_res {
+        Ok(Ok(_)) => buf.len(),
+        _ => 0,
+    };
+    ctx.metrics.record_banner(bytes);
+    if let Some(stats) = ctx.metrics.stats() {
+        let outcome = match &read_res {
+            Ok(Ok(Some(_))) => BannerOutcome::Succeeded,
+            Ok(Ok(None)) => BannerOutcome::Empty,
+            Ok(Err(_)) => BannerOutcome::Failed,
+            Err(_) => BannerOutcome::TimedOut,
+        };
+        stats.record_banner(outcome, bytes);
+    }
+
+    let open = matches!(read_res, Ok(Ok(Some(_))) | Err(_));
+    match read_res {
+        Ok(result) => (result, open),
+        Err(_) => (Ok(None), open),
+    }
+}
+
+/// The connection the banner read leaves to the follow-up probes of a port.
+struct Session {
+    /// None once closed.
+    stream: Option<Connection>,
+    /// Whether anything went over `stream` yet.
+    used: bool,
+    /// Connections made to the port so far, the first included.
+    connections: u32,
+}
+
+/// Runs the follow-up probes that apply to the open port `r` in turn, by
+/// `deadline`, the end of the probe budget: each on the session's
+/// connection while it is open and the probe can share it, on a new one
+/// otherwise.
+async fn follow_up(
+    ctx: &ProbeContext,
+    r: &mut PortResult,
+    mut session: Session,
+    deadline: tokio::time::Instant,
+) {
+    let addr = SocketAddr::new(r.target, r.port);
+    for probe in &ctx.probes {
+        if !probe.applies(r) {
+            continue;
+        }
+        let name = probe.name();
+        if session.used && probe.transport() == Transport::Fresh {
+            session.stream = None;
+        }
+        let connected = session.stream.is_none();
+        if connected {
+            let started = Instant::now();
+            let connect_res = timeout_at(deadline, connect_held(ctx, addr)).await;
+            session.connections += 1;
+            let connect = Evidence::new(
+                Step::TcpConnect,
+                match &connect_res {
+                    Ok(Ok(_)) => Outcome::Success,
+                    Ok(Err(_)) => Outcome::Error,
+                    Err(_) => Outcome::Timeout,
+                },
+            )
+            .elapsed(ctx.clock.elapsed(started));
+            let detail = match &connect_res {
+                Ok(Err(e)) => format!("for {}: {}", name, e),
+                _ => format!("for {}", name),
+            };
+            if ctx.evidence {
+                r.evidence.push(connect.detail(detail));
+            }
+            match connect_res {
+                Ok(Ok(stream)) => {
+                    session.stream = Some(stream);
+                    session.used = false;
+                }
+                Ok(Err(e)) => {
+                    debug!(probe = name, error = %e, "probe connect failed");
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        if let Some(stats) = ctx.metrics.stats() {
+            stats.record_probe(connected);
+        }
+        let Some(stream) = session.stream.as_mut() else {
+            break;
+        };
+
+        let started = Instant::now();
+        let timeout = ctx.probe_timeouts.resolve(probe.kind());
+        let until = deadline_in(timeout).min(deadline);
+        // What the probe writes on the result is undone if it doesn't
+        // finish, so that the port keeps its classification.
+        let before = r.clone();
+        let run = timeout_at(until, probe.run(stream, r)).await;
+        session.used = true;
+        let on = format!("{} on connection {}", name, session.connections);
+        let (outcome, detail) = match &run {
+            Ok(Ok(probed)) => match &probed.found {
+                Some(found) => (Outcome::Matched, format!("{}: {}", on, found)),
+                None => (Outcome::NoMatch, on),
+            },
+            Ok(Err(e)) => (Outcome::Error, format!("{}: {}", on, e)),
+            Err(_) if until < deadline => (
+                Outcome::Timeout,
+                format!("{}: probe timeout of {}ms", on, timeout.as_millis()),
+            ),
+            Err(_) => (Outcome::Timeout, on),
+        };
+        trace!(probe = name, ?outcome, "probe finished");
+        if !matches!(run, Ok(Ok(_))) {
+            *r = before;
+            if let Some(stats) = ctx.metrics.stats() {
+                stats.record_probe_failure(name, run.is_err());
+            }
+        }
+        if ctx.evidence {
+            r.evidence.push(
+                Evidence::new(Step::Probe, outcome)
+                    .elapsed(ctx.clock.elapsed(started))
+                    .detail(detail),
+            );
+        }
+        match run {
+            Ok(Ok(probed)) if probed.keep => {}
+            // Only the probe's own timeout: the next one may still fit.
+            Err(_) if until < deadline => session.stream = None,
+            Ok(_) => session.stream = None,
+            Err(_) => break,
+        }
+    }
+}
+
+/// Parses a port specification such as `22,80,8000-8100` into a sorted,
+/// deduplicated list. Port 0 is dropped.
+///
+/// Scans keep ports as a [`PortSet`]; this expands one for callers that
+/// want the list.
+pub fn parse_ports(s: &str) -> Result<Vec<u16>, ScanError> {
+    let ports = PortSet::parse(s).map_err(ScanError::InvalidPorts)?;
+    if ports.is_empty() {
+        return Err(ScanError::InvalidPorts(SpecError::new(
+            SpecErrorKind::NoPorts,
+            s,
+            0,
+        )));
+    }
+    Ok(ports.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{MockBehavior, MockConnector};
+
+    #[test]
+    fn connect_errors_classify_by_kind_and_winsock_code() {
+        use std::io::{Error, ErrorKind};
+        assert_eq!(
+            Failure::of(&ErrorKind::ConnectionRefused.into()),
+            Failure::Refused
+        );
+        assert_eq!(
+            Failure::of(&ErrorKind::NetworkUnreachable.into()),
+            Failure::Unreachable
+        );
+        assert_eq!(
+            Failure::of(&ErrorKind::PermissionDenied.into()),
+            Failure::Other
+        );
+        assert_eq!(
+            Failure::of(&ErrorKind::AddrNotAvailable.into()),
+            Failure::PortsExhausted
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            Failure::of(&Error::from_raw_os_error(libc::EMFILE)),
+            Failure::LocalResource
+        );
+        #[cfg(windows)]
+        assert_eq!(
+            Failure::of(&Error::from_raw_os_error(winsock::WSAEHOSTDOWN)),
+            Failure::Unreachable
+        );
+
+        // The Winsock table, checked everywhere so that a change to it
+        // doesn't wait for a Windows build to be noticed.
+        for (code, failure) in [
+            (winsock::WSAECONNREFUSED, Some(Failure::Refused)),
+            (winsock::WSAECONNRESET, Some(Failure::Reset)),
+            (winsock::WSAETIMEDOUT, Some(Failure::TimedOut)),
+            (winsock::WSAEHOSTUNREACH, Some(Failure::Unreachable)),
+            (winsock::WSAENETUNREACH, Some(Failure::Unreachable)),
+            (winsock::WSAEHOSTDOWN, Some(Failure::Unreachable)),
+            (winsock::WSAEMFILE, Some(Failure::LocalResource)),
+            (winsock::WSAENOBUFS, Some(Failure::LocalResource)),
+            (winsock::WSAEADDRNOTAVAIL, Some(Failure::PortsExhausted)),
+            (winsock::WSAEADDRINUSE, Some(Failure::PortsExhausted)),
+            // WSAEACCES: the firewall blocked the connect.
+            (10013, None),
+        ] {
+            assert_eq!(winsock::failure(code), failure, "{}", code);
+        }
+    }
+
+    #[test]
+    fn builder_accepts_every_port_form() {
+        let from_spec = Scanner::builder()
+            .target("10.0.0.1")
+            .ports("80,443,20-22")
+            .build()
+            .unwrap();
+        assert_eq!(from_spec.config().ports.to_string(), "20-22,80,443");
+
+        let from_range = Scanner::builder()
+            .target("10.0.0.1")
+            .ports(0..=3)
+            .build()
+            .unwrap();
+        assert_eq!(from_range.config().ports.to_string(), "1-3");
+
+        let from_list = Scanner::builder()
+            .target("::1")
+            .target("::1")
+            .ports(vec![443, 80, 443])
+            .build()
+            .unwrap();
+        assert_eq!(from_list.config().ports.to_string(), "80,443");
+        assert_eq!(from_list.config().targets.len(), 1);
+
+        let excluded = Scanner::builder()
+            .target("10.0.0.0/24")
+            .target("10.0.0.128/25")
+            .exclude_target("10.0.0.0-10.0.0.9")
+            .ports("1-1024")
+            .exclude_ports("25,135-139")
+            .build()
+            .unwrap();
+        assert_eq!(
+            excluded.config().targets.to_string(),
+            "10.0.0.10-10.0.0.255"
+        );
+        assert_eq!(excluded.config().ports.to_string(), "1-24,26-134,140-1024");
+        assert_eq!(excluded.total_ports(), 246 * 1018);
+        assert_eq!(
+            excluded.config().plan().next(),
+            Some("10.0.0.10:1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn builder_rejects_unusable_configurations() {
+        let target = || Scanner::builder().target("127.0.0.1");
+        assert!(matches!(
+            Scanner::builder().build(),
+            Err(ScanError::NoTargets)
+        ));
+        assert!(matches!(
+            Scanner::builder().target("not-an-ip").build(),
+            Err(ScanError::InvalidTarget(e)) if e.kind == SpecErrorKind::InvalidAddress
+        ));
+        assert!(matches!(
+            target().ports(0).build(),
+            Err(ScanError::InvalidPorts(e)) if e.kind == SpecErrorKind::NoPorts
+        ));
+        assert!(matches!(
+            target().ports("20-10").build(),
+            Err(ScanError::InvalidPorts(e)) if e.kind == SpecErrorKind::StartAfterEnd
+        ));
+        assert!(matches!(
+            target().concurrency(0).build(),
+            Err(ScanError::ZeroConcurrency)
+        ));
+        assert!(matches!(
+            target().timeout(Duration::ZERO).build(),
+            Err(ScanError::ZeroTimeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_stream_stops_the_scan() {
+        let scanner = Scanner::builder()
+            .target("127.0.0.1")
+            .ports(1..=65535)
+            .concurrency(8)
+            .build()
+            .unwrap();
+        let metrics = scanner.metrics();
+
+        let first: Vec<PortResult> = scanner.scan().take(16).collect().await;
+        assert_eq!(first.len(), 16);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let attempts = metrics.connection_attempts();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(metrics.connection_attempts(), attempts);
+        assert!(attempts < 1000);
+    }
+
+    #[tokio::test]
+    async fn scan_collect_summarizes_every_port() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = {
+            let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap().port()
+        };
+        let scanner = Scanner::builder()
+            .target("127.0.0.1")
+            .ports(vec![open, closed])
+            .concurrency(8)
+            .banner_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        let summary = scanner.scan_collect().await;
+        assert_eq!(summary.target, "127.0.0.1");
+        assert_eq!(summary.concurrency, 8);
+        assert_eq!(summary.scanned_ports, 2);
+        assert_eq!(summary.open_ports, 1);
+        assert_eq!(summary.closed_ports, 1);
+        assert!(!summary.interrupted);
+    }
+
+    #[tokio::test]
+    async fn cancelling_stops_every_probe() {
+        let scanner = Scanner::builder()
+            .target("127.0.0.1")
+            .ports(1..=65535)
+            .concurrency(64)
+            .build()
+            .unwrap();
+        let cancel = scanner.cancel_token();
+        let runtime = tokio::runtime::Handle::current().metrics();
+
+        let mut stream = scanner.scan();
+        let mut seen = 0;
+        while let Some(_r) = stream.next().await {
+            seen += 1;
+            if seen == 100 {
+                cancel.cancel();
+            }
+        }
+        assert!(stream.is_cancelled());
+        assert!(seen < 65535);
+
+        let drained = tokio::time::timeout(Duration::from_secs(2), async {
+            while runtime.num_alive_tasks() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(
+            drained.is_ok(),
+            "{} tasks still alive",
+            runtime.num_alive_tasks()
+        );
+    }
+
+    /// Refuses every connect after a moment, counting the most connects in
+    /// flight at once: in total, to one host, and hosts with any.
+    #[derive(Default)]
+    struct PeakConnector {
+        state: std::sync::Mutex<Peaks>,
+    }
+
+    #[derive(Default)]
+    struct Peaks {
+        in_flight: HashMap<IpAddr, usize>,
+        total: usize,
+        total_peak: usize,
+        host_peak: usize,
+        hosts_peak: usize,
+    }
+
+    impl Connector for PeakConnector {
+        fn connect(
+            &self,
+            addr: SocketAddr,
+        ) -> futures::future::BoxFuture<'_, std::io::Result<Connection>> {
+            Box::pin(async move {
+                {
+                    let mut peaks = self.state.lock().unwrap();
+                    let host = peaks.in_flight.entry(addr.ip()).or_default();
+                    *host += 1;
+                    let host = *host;
+                    peaks.host_peak = peaks.host_peak.max(host);
+                    peaks.total += 1;
+                    peaks.total_peak = peaks.total_peak.max(peaks.total);
+                    peaks.hosts_peak = peaks.hosts_peak.max(peaks.in_flight.len());
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let mut peaks = self.state.lock().unwrap();
+                peaks.total -= 1;
+                let host = peaks.in_flight.get_mut(&addr.ip()).unwrap();
+                *host -= 1;
+                if *host == 0 {
+                    peaks.in_flight.remove(&addr.ip());
+                }
+                Err(std::io::ErrorKind::ConnectionRefused.into())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn host_and_per_host_concurrency_cap_connections() {
+        let connector = Arc::new(PeakConnector::default());
+        let mut builder = Scanner::builder()
+            .ports(1..=20)
+            .concurrency(5)
+            .host_concurrency(2)
+            .per_host_concurrency(3)
+            .timeout(Duration::from_millis(200))
+            .connector(connector.clone());
+        for host in 1..=6 {
+            builder = builder.target_addr(IpAddr::from([127, 0, 0, host]));
+        }
+        let scanner = builder.build().unwrap();
+        let metrics = scanner.metrics();
+        let summary = scanner.scan_collect().await;
+
+        assert_eq!(summary.results.len(), 6 * 20);
+        let peaks = connector.state.lock().unwrap();
+        assert!(
+            peaks.total_peak <= 5,
+            "{} connects at once",
+            peaks.total_peak
+        );
+        assert!(peaks.host_peak <= 3, "{} to one host", peaks.host_peak);
+        assert_eq!(peaks.host_peak, 3);
+        assert_eq!(peaks.hosts_peak, 2, "two hosts at once");
+        let times = metrics.host_times();
+        assert_eq!(times.len(), 6);
+        // The last hosts only start once the first ones are done.
+        let first = times[&IpAddr::from([127, 0, 0, 1])];
+        let last = times[&IpAddr::from([127, 0, 0, 6])];
+        assert!(last.started_ms >= first.started_ms + first.duration_ms);
+    }
+
+    async fn scan_mock(mock: MockConnector, ports: Vec<u16>) -> HashMap<u16, PortResult> {
+        let scanner = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(ports)
+            .timeout(Duration::from_millis(50))
+            .banner_timeout(Duration::from_millis(50))
+            .connector(mock)
+            .build()
+            .unwrap();
+        let summary = scanner.scan_collect().await;
+        summary.results.into_iter().map(|r| (r.port, r)).collect()
+    }
+
+    #[tokio::test]
+    async fn stats_reconcile_with_the_summary() {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(80, MockBehavior::Open(Vec::new()))
+            .port(81, MockBehavior::Hang)
+            .port(
+                82,
+                MockBehavior::Error(std::io::ErrorKind::PermissionDenied),
+            )
+            .port(83, MockBehavior::Error(std::io::ErrorKind::ConnectionReset))
+            .port(84, MockBehavior::Os(libc::EMFILE));
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(20..=90)
+            .concurrency(4)
+            .timeout(Duration::from_millis(50))
+            .banner_timeout(Duration::from_millis(50))
+            .stats()
+            .connector(mock)
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+
+        let stats = summary.stats.expect("stats were asked for");
+        let c = stats.connects;
+        assert_eq!(c.attempted, summary.connection_attempts);
+        assert_eq!(c.attempted as usize, summary.scanned_ports);
+        assert_eq!((c.succeeded + c.reset) as usize, summary.open_ports);
+        assert_eq!(c.refused as usize, summary.closed_ports);
+        assert_eq!((c.timed_out + c.failed) as usize, summary.filtered_ports);
+        assert_eq!((c.succeeded, c.reset, c.timed_out, c.failed), (2, 1, 1, 2));
+        let b = stats.banners;
+        assert_eq!(b.attempted, summary.banner_probes);
+        assert_eq!(b.attempted, c.succeeded);
+        assert_eq!((b.succeeded, b.empty), (1, 1));
+        assert_eq!(b.bytes, summary.banner_bytes);
+        assert_eq!(stats.retries, summary.retries);
+        assert_eq!(stats.permit_wait_us.count, c.attempted);
+        assert_eq!(
+            stats.connect_time_us.count,
+            c.succeeded + c.refused + c.reset
+        );
+
+        let plain = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(1)
+            .connector(MockConnector::new())
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+        assert!(plain.stats.is_none());
+    }
+
+    #[tokio::test]
+    async fn outlier_connects_are_timed_again_at_the_end() {
+        let slow = MockBehavior::After(Duration::from_millis(60), Box::new(MockBehavior::Refused));
+        let scan = |retime: bool| {
+            let mock = MockConnector::new()
+                .sequence(25, vec![slow.clone(), MockBehavior::Refused])
+                .port(26, MockBehavior::Hang);
+            let builder = Scanner::builder()
+                .target("192.0.2.1")
+                .ports(1..=30)
+                .timeout(Duration::from_millis(100))
+                .stats()
+                .connector(mock);
+            let builder = if retime {
+                builder.retime_outliers()
+            } else {
+                builder
+            };
+            builder.build().unwrap()
+        };
+
+        let scanner = scan(true);
+        let results: Vec<PortResult> = scanner.scan().collect().await;
+        // The outlier comes last, once measured again, still closed.
+        let last = results.last().unwrap();
+        assert_eq!((last.port, &last.status), (25, &PortStatus::Closed));
+        let retime = last.retime.expect("port 25 was an outlier");
+        assert!(retime.first_ms >= 60.0, "{:?}", retime);
+        assert!(retime.second_ms.unwrap() < 50.0, "{:?}", retime);
+        // A timeout is no answer, so it is nobody's outlier.
+        assert!(results.iter().all(|r| r.port == 25 || r.retime.is_none()));
+        assert_eq!(results.len(), 30);
+        let json = serde_json::to_value(last).unwrap();
+        assert_eq!(json["retime"]["first_ms"], retime.first_ms);
+        let stats = scanner.metrics().stats().unwrap().snapshot(0);
+        assert_eq!(stats.connect_time_us.count, 29);
+        assert!(stats.connect_time_us.max < 50_000, "{:?}", stats);
+        assert_eq!(stats.connects.attempted, 30);
+
+        let summary = scan(false).scan_collect().await;
+        assert!(summary.results.iter().all(|r| r.retime.is_none()));
+        let stats = summary.stats.unwrap();
+        assert!(stats.connect_time_us.max >= 60_000, "{:?}", stats);
+    }
+
+    #[tokio::test]
+    async fn unreachable_hosts_are_cut_short_unless_told_otherwise() {
+        let down: IpAddr = "192.0.2.9".parse().unwrap();
+        let mock = MockConnector::new().host(
+            down,
+            MockBehavior::Error(std::io::ErrorKind::HostUnreachable),
+        );
+        let builder = Scanner::builder()
+            .target("192.0.2.1")
+            .target_addr(down)
+            .ports(1..=100)
+            .concurrency(1)
+            .stats()
+            .connector(mock);
+
+        let summary = builder.clone().build().unwrap().scan_collect().await;
+        assert_eq!(summary.scanned_ports, 200);
+        assert_eq!(summary.closed_ports, 100);
+        assert_eq!(summary.filtered_ports, 100);
+        let cached = summary
+            .results
+            .iter()
+            .filter(|r| matches!(r.error, Some(PortError::UnreachableCached(_))))
+            .count();
+        // Three errors in a row, then every 32nd port is tried again.
+        assert_eq!(cached, 94);
+        assert_eq!(summary.connection_attempts, 106);
+        let stats = summary.stats.unwrap();
+        assert_eq!(stats.unreachable_cached, 94);
+        assert_eq!(
+            (stats.connects.timed_out + stats.connects.failed + stats.unreachable_cached) as usize,
+            summary.filtered_ports
+        );
+
+        let summary = builder
+            .no_unreachable_cache()
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+        assert_eq!(summary.connection_attempts, 200);
+        assert!(summary
+            .results
+            .iter()
+            .all(|r| !matches!(r.error, Some(PortError::UnreachableCached(_)))));
+    }
+
+    #[tokio::test]
+    async fn connects_out_of_local_ports_are_retried_not_filtered() {
+        use std::io::ErrorKind;
+        let exhausted = MockBehavior::Error(std::io::ErrorKind::AddrNotAvailable);
+        let mock = MockConnector::new()
+            .sequence(
+                22,
+                vec![
+                    exhausted.clone(),
+                    MockBehavior::Error(ErrorKind::AddrInUse),
+                    MockBehavior::Open(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()),
+                ],
+            )
+            .sequence(23, vec![exhausted.clone(), MockBehavior::Refused])
+            .port(24, exhausted);
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(22..=24)
+            .stats()
+            .audit()
+            .connector(mock)
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+
+        let port = |p: u16| summary.results.iter().find(|r| r.port == p).unwrap();
+        assert_eq!(port(22).status, PortStatus::Open);
+        assert_eq!(port(22).audit.as_ref().unwrap().attempt, 3);
+        assert_eq!(port(23).status, PortStatus::Closed);
+        assert!(port(24).error.as_ref().is_some_and(PortError::is_local));
+        assert_eq!(port(24).audit.as_ref().unwrap().attempt, 5);
+        assert_eq!(summary.retries, 2 + 1 + 4);
+        assert_eq!(summary.connection_attempts, 3 + 7);
+        let stats = summary.stats.unwrap();
+        assert_eq!(stats.ports_exhausted, 7);
+        assert_eq!(stats.connects.attempted, 3);
+    }
+
+    /// Unreachable for the first `down_for` connects, refused after.
+    struct ComesBack {
+        down_for: usize,
+        connects: AtomicUsize,
+    }
+
+    impl Connector for ComesBack {
+        fn connect(
+            &self,
+            _addr: SocketAddr,
+        ) -> futures::future::BoxFuture<'_, std::io::Result<Connection>> {
+            let n = self.connects.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                if n < self.down_for {
+                    Err(std::io::ErrorKind::HostUnreachable.into())
+                } else {
+                    Err(std::io::ErrorKind::ConnectionRefused.into())
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn a_host_that_comes_back_is_probed_again() {
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(1..=100)
+            .concurrency(1)
+            .connector(ComesBack {
+                down_for: 4,
+                connects: AtomicUsize::new(0),
+            })
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+        let closed: Vec<u16> = summary
+            .results
+            .iter()
+            .filter(|r| r.status == PortStatus::Closed)
+            .map(|r| r.port)
+            .collect();
+        // Ports 35 and 67 are re-probes; the host answers the second.
+        assert_eq!(closed, (67..=100).collect::<Vec<_>>());
+        assert_eq!(summary.connection_attempts, 38);
+    }
+
+    #[tokio::test]
+    async fn classifies_connect_outcomes() {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(80, MockBehavior::Open(Vec::new()))
+            .port(81, MockBehavior::Hang)
+            .port(
+                82,
+                MockBehavior::Error(std::io::ErrorKind::PermissionDenied),
+            )
+            .port(83, MockBehavior::Error(std::io::ErrorKind::ConnectionReset));
+        let results = scan_mock(mock, vec![22, 23, 80, 81, 82, 83]).await;
+
+        assert_eq!(results[&22].status, PortStatus::Open);
+        assert_eq!(results[&22].banner_str().as_deref(), Some("SSH-2.0-Mock"));
+        assert_eq!(results[&22].service.as_deref(), Some("SSH"));
+        assert_eq!(results[&23].status, PortStatus::Closed);
+        assert_eq!(results[&80].status, PortStatus::Open);
+        assert_eq!(results[&80].banner, None);
+        assert_eq!(results[&80].service.as_deref(), Some("HTTP"));
+        assert_eq!(results[&81].status, PortStatus::Filtered);
+        assert_eq!(results[&81].error, Some(PortError::Timeout));
+        assert_eq!(results[&82].status, PortStatus::Filtered);
+        assert!(matches!(results[&82].error, Some(PortError::Connect(_))));
+        assert_eq!(results[&83].status, PortStatus::Open);
+        assert!(matches!(results[&83].error, Some(PortError::Banner(_))));
+        assert_eq!(results[&23].error, None);
+    }
+
+    /// A follow-up probe that only reports which connection it got.
+    struct Named(&'static str, Transport);
+
+    impl Probe for Named {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn transport(&self) -> Transport {
+            self.1
+        }
+
+        fn applies(&self, r: &PortResult) -> bool {
+            r.status == PortStatus::Open
+        }
+
+        fn run<'a>(
+            &'a self,
+            _stream: &'a mut Connection,
+            _r: &'a mut PortResult,
+        ) -> futures::future::BoxFuture<'a, std::io::Result<crate::probe::Probed>> {
+            Box::pin(async {
+                Ok(crate::probe::Probed {
+                    found: Some("ok".to_string()),
+                    keep: true,
+                })
+            })
+        }
+    }
+
+    /// A follow-up probe with a bug that bites on one port.
+    struct PanicsOn(u16);
+
+    impl Probe for PanicsOn {
+        fn name(&self) -> &'static str {
+            "buggy"
+        }
+
+        fn transport(&self) -> Transport {
+            Transport::Shared
+        }
+
+        fn applies(&self, r: &PortResult) -> bool {
+            r.status == PortStatus::Open
+        }
+
+        fn run<'a>(
+            &'a self,
+            _stream: &'a mut Connection,
+            r: &'a mut PortResult,
+        ) -> futures::future::BoxFuture<'a, std::io::Result<crate::probe::Probed>> {
+            Box::pin(async move {
+                if r.port == self.0 {
+                    panic!("parser bug on port {}", r.port);
+                }
+                Ok(crate::probe::Probed {
+                    found: None,
+                    keep: true,
+                })
+            })
+        }
+    }
+
+    /// A follow-up probe that scribbles on the result and never finishes.
+    struct Hangs;
+
+    impl Probe for Hangs {
+        fn name(&self) -> &'static str {
+            "hangs"
+        }
+
+        fn kind(&self) -> &'static str {
+            "tls"
+        }
+
+        fn transport(&self) -> Transport {
+            Transport::Shared
+        }
+
+        fn applies(&self, r: &PortResult) -> bool {
+            r.status == PortStatus::Open
+        }
+
+        fn run<'a>(
+            &'a self,
+            _stream: &'a mut Connection,
+            r: &'a mut PortResult,
+        ) -> futures::future::BoxFuture<'a, std::io::Result<crate::probe::Probed>> {
+            Box::pin(async move {
+                r.service = Some("half-parsed".to_string());
+                futures::future::pending().await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn a_hanging_probe_times_out_alone_and_the_port_keeps_its_classification() {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(80, MockBehavior::Open(Vec::new()));
+        let mut timeouts = ProbeTimeouts::new();
+        timeouts.insert("tls", Duration::from_millis(50));
+        let scan = |selection: ProbeSelection| {
+            Scanner::builder()
+                .target("192.0.2.1")
+                .ports(vec![22, 80])
+                .banner_timeout(Duration::from_millis(50))
+                .probe_budget(Duration::from_secs(10))
+                .probe_timeouts(timeouts.clone())
+                .probe_selection(selection)
+                .connector(mock.clone())
+                .probe(Hangs)
+                .probe(Named("after", Transport::Shared))
+                .evidence()
+                .stats()
+                .build()
+                .unwrap()
+        };
+        let summary = scan(ProbeSelection::default()).scan_collect().await;
+        let results: HashMap<u16, &PortResult> =
+            summary.results.iter().map(|r| (r.port, r)).collect();
+        for port in [22, 80] {
+            assert_eq!(results[&port].status, PortStatus::Open);
+        }
+        assert_eq!(results[&22].service.as_deref(), Some("SSH"));
+        // Guessed from the port number.
+        assert_eq!(results[&80].service.as_deref(), Some("HTTP"));
+        let probes = |port: u16| {
+            results[&port]
+                .evidence
+                .iter()
+                .filter(|e| e.step == Step::Probe)
+                .map(|e| (e.outcome, e.detail.clone().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            probes(22),
+            [
+                (
+                    Outcome::Timeout,
+                    "hangs on connection 1: probe timeout of 50ms".to_string()
+                ),
+                (Outcome::Matched, "after on connection 2: ok".to_string()),
+            ]
+        );
+        let stats = summary.stats.unwrap();
+        assert_eq!(
+            stats.probes.failures["hangs"],
+            crate::stats::ProbeFailures {
+                timed_out: 2,
+                failed: 0
+            }
+        );
+
+        // Without TLS probes, the hanging one doesn't run at all.
+        let summary = scan(ProbeSelection {
+            only: Vec::new(),
+            disabled: vec!["tls".to_string()],
+        })
+        .scan_collect()
+        .await;
+        let r = summary.results.iter().find(|r| r.port == 22).unwrap();
+        let probes: Vec<_> = r
+            .evidence
+            .iter()
+            .filter(|e| e.step == Step::Probe)
+            .collect();
+        assert_eq!(probes.len(), 1);
+        assert_eq!(
+            probes[0].detail.as_deref(),
+            Some("after on connection 1: ok")
+        );
+        assert!(summary.stats.unwrap().probes.failures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_panicking_probe_is_reported_on_its_port_alone() {
+        let mock = MockConnector::new()
+            .port(21, MockBehavior::Open(b"220 ftp\r\n".to_vec()))
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(25, MockBehavior::Open(b"220 smtp\r\n".to_vec()));
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(20..=25)
+            .banner_timeout(Duration::from_millis(50))
+            .connector(mock)
+            .probe(PanicsOn(22))
+            .stats()
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+
+        assert_eq!(summary.results.len(), 6);
+        assert_eq!(summary.scanned_ports, 6);
+        let results: HashMap<u16, &PortResult> =
+            summary.results.iter().map(|r| (r.port, r)).collect();
+        assert_eq!(results[&21].status, PortStatus::Open);
+        assert_eq!(results[&25].status, PortStatus::Open);
+        assert_eq!(results[&23].status, PortStatus::Closed);
+        assert_eq!(results[&22].status, PortStatus::Filtered);
+        assert_eq!(
+            results[&22].error,
+            Some(PortError::Panicked("parser bug on port 22".to_string()))
+        );
+        assert_eq!(
+            (
+                summary.open_ports,
+                summary.closed_ports,
+                summary.filtered_ports
+            ),
+            (2, 3, 1)
+        );
+        assert_eq!(summary.panicked_ports, 1);
+        assert_eq!(summary.unscanned_ports, 1);
+        let report = serde_json::to_value(&summary).unwrap();
+        assert_eq!(report["panicked_ports"], 1);
+        assert_eq!(summary.stats.unwrap().panics, 1);
+    }
+
+    #[tokio::test]
+    async fn follow_up_probes_reconnect_only_when_they_must() {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(80, MockBehavior::Open(Vec::new()));
+        let scanner = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(vec![22, 80])
+            .banner_timeout(Duration::from_millis(50))
+            .connector(mock)
+            .probe(Named("shared", Transport::Shared))
+            .probe(Named("fresh", Transport::Fresh))
+            .evidence()
+            .stats()
+            .build()
+            .unwrap();
+        let summary = scanner.scan_collect().await;
+        let steps = |port: u16| {
+            let r = summary.results.iter().find(|r| r.port == port).unwrap();
+            r.evidence[3..]
+                .iter()
+                .map(|e| (e.step, e.detail.clone().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let step = |step, detail: &str| (step, detail.to_string());
+
+        // The banner read leaves the connection open but used: the shared
+        // probe follows it, the fresh one needs a new one.
+        assert_eq!(
+            steps(22),
+            [
+                step(Step::Probe, "shared on connection 1: ok"),
+                step(Step::TcpConnect, "for fresh"),
+                step(Step::Probe, "fresh on connection 2: ok"),
+            ]
+        );
+        // Closed straight away: both need a connection of their own.
+        assert_eq!(
+            steps(80),
+            [
+                step(Step::TcpConnect, "for shared"),
+                step(Step::Probe, "shared on connection 2: ok"),
+                step(Step::TcpConnect, "for fresh"),
+                step(Step::Probe, "fresh on connection 3: ok"),
+            ]
+        );
+        let stats = summary.stats.unwrap();
+        assert_eq!(
+            (
+                stats.probes.run,
+                stats.probes.shared,
+                stats.probes.reconnects
+            ),
+            (4, 1, 3)
+        );
+        assert_eq!(stats.connects.attempted, 2);
+    }
+
+    #[tokio::test]
+    async fn evidence_traces_each_step_of_the_probe() {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(80, MockBehavior::Open(Vec::new()))
+            .port(81, MockBehavior::Hang)
+            .port(
+                82,
+                MockBehavior::Error(std::io::ErrorKind::PermissionDenied),
+            );
+        let scanner = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(vec![22, 23, 80, 81, 82, 9999])
+            .timeout(Duration::from_millis(50))
+            .banner_timeout(Duration::from_millis(50))
+            .connector(mock.clone())
+            .clock(crate::clock::FixedClock::default())
+            .evidence()
+            .build()
+            .unwrap();
+        let summary = scanner.scan_collect().await;
+        let steps = |port: u16| {
+            let r = summary.results.iter().find(|r| r.port == port).unwrap();
+            r.evidence
+                .iter()
+                .map(|e| (e.step, e.outcome, e.detail.as_deref()))
+                .collect::<Vec<_>>()
+        };
+        let connected = (Step::TcpConnect, Outcome::Success, None);
+        assert_eq!(
+            steps(22),
+            [
+                connected,
+                (Step::BannerRead, Outcome::Success, Some("14 bytes")),
+                (
+                    Step::ServiceMatch,
+                    Outcome::Matched,
+                    Some("SSH identification")
+                ),
+            ]
+        );
+        assert_eq!(
+            steps(80),
+            [
+                connected,
+                (Step::BannerRead, Outcome::Empty, None),
+                (
+                    Step::ServiceMatch,
+                    Outcome::Matched,
+                    Some("well-known port")
+                ),
+            ]
+        );
+        assert_eq!(steps(23), [(Step::TcpConnect, Outcome::Refused, None)]);
+        assert_eq!(steps(81), [(Step::TcpConnect, Outcome::Timeout, None)]);
+        assert_eq!(steps(82)[0].1, Outcome::Error);
+        assert_eq!(steps(9999), [(Step::TcpConnect, Outcome::Refused, None)]);
+        let connect = &summary.results[0].evidence[0];
+        assert_eq!(connect.elapsed_ms, Some(0), "timed by the scan's clock");
+
+        let results = scan_mock(mock, vec![22]).await;
+        assert!(results[&22].evidence.is_empty(), "only when asked for");
+    }
+
+    #[tokio::test]
+    async fn the_longest_timeouts_and_budgets_do_not_overflow_deadlines() {
+        let ssh = || MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec());
+        let mock = MockConnector::new()
+            .port(22, ssh())
+            .sequence(24, vec![MockBehavior::Open(Vec::new()), ssh()]);
+        let mut timeouts = ProbeTimeouts::new();
+        timeouts.set_default(Duration::MAX);
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(vec![22, 23, 24])
+            .timeout(Duration::MAX)
+            .banner_timeout(Duration::MAX)
+            .probe_budget(Duration::MAX)
+            .probe_timeouts(timeouts)
+            .banner_retry()
+            .connector(mock)
+            .probe(Named("after", Transport::Shared))
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+        let results: HashMap<u16, &PortResult> =
+            summary.results.iter().map(|r| (r.port, r)).collect();
+        for port in [22, 24] {
+            assert_eq!(results[&port].status, PortStatus::Open, "{}", port);
+            assert_eq!(results[&port].error, None, "{}", port);
+            assert_eq!(results[&port].banner_str().as_deref(), Some("SSH-2.0-Mock"));
+        }
+        assert_eq!(results[&23].status, PortStatus::Closed);
+    }
+
+    #[tokio::test]
+    async fn failed_banner_reads_are_retried_over_a_new_connection() {
+        let ssh = || MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec());
+        let mock = || {
+            MockConnector::new()
+                .sequence(22, vec![MockBehavior::Open(Vec::new()), ssh()])
+                .sequence(23, vec![MockBehavior::Open(Vec::new()), MockBehavior::Hang])
+                .port(24, ssh())
+        };
+        let scan = |mock: MockConnector, budget: Duration, retry: bool| async move {
+            let builder = Scanner::builder()
+                .target("192.0.2.1")
+                .ports(22..=24)
+                .timeout(Duration::from_millis(50))
+                .banner_timeout(Duration::from_millis(50))
+                .probe_budget(budget)
+                .connector(mock)
+                .evidence();
+            let builder = if retry {
+                builder.banner_retry()
+            } else {
+                builder
+            };
+            let summary = builder.build().unwrap().scan_collect().await;
+            let ports: HashMap<u16, PortResult> = summary
+                .results
+                .iter()
+                .map(|r| (r.port, r.clone()))
+                .collect();
+            (summary, ports)
+        };
+        let steps = |r: &PortResult| {
+            r.evidence
+                .iter()
+                .map(|e| (e.step, e.outcome, e.detail.clone()))
+                .collect::<Vec<_>>()
+        };
+        let step = |step, outcome, detail: Option<&str>| (step, outcome, detail.map(String::from));
+
+        let (summary, ports) = scan(mock(), Duration::from_secs(3), true).await;
+        assert!(ports[&22].banner_retried);
+        assert_eq!(ports[&22].banner_str().as_deref(), Some("SSH-2.0-Mock"));
+        assert_eq!(
+            steps(&ports[&22])[..4],
+            [
+                step(Step::TcpConnect, Outcome::Success, None),
+                step(Step::BannerRead, Outcome::Empty, None),
+                step(Step::TcpConnect, Outcome::Success, Some("banner retry")),
+                step(Step::BannerRead, Outcome::Success, Some("14 bytes")),
+            ]
+        );
+        // A retry that isn't answered leaves what the first read found.
+        assert!(ports[&23].banner_retried);
+        assert!(ports[&23].banner.is_none());
+        assert_eq!(
+            steps(&ports[&23])[2],
+            step(Step::TcpConnect, Outcome::Timeout, Some("banner retry"))
+        );
+        assert!(!ports[&24].banner_retried);
+        assert_eq!((summary.retries, summary.connection_attempts), (2, 5));
+
+        // Out of probe budget before the delay is up, there is no retry.
+        let (summary, ports) = scan(mock(), Duration::from_millis(100), true).await;
+        assert!(ports[&22].banner.is_none());
+        assert_eq!(
+            steps(&ports[&22])[2],
+            step(
+                Step::TcpConnect,
+                Outcome::Skipped,
+                Some("banner retry: no probe budget left")
+            )
+        );
+        assert!(!ports[&22].banner_retried);
+        assert_eq!(summary.retries, 0);
+
+        let (summary, ports) = scan(mock(), Duration::from_secs(3), false).await;
+        assert!(!ports[&22].banner_retried);
+        assert!(ports[&22].banner.is_none());
+        assert_eq!(summary.retries, 0);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_for_every_port_once_the_retry_budget_is_spent() {
+        let mock = (1..=8).fold(MockConnector::new(), |m, port| {
+            m.port(port, MockBehavior::Open(Vec::new()))
+        });
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(1..=8)
+            .banner_timeout(Duration::from_millis(50))
+            .connector(mock)
+            .banner_retry()
+            .max_total_retries(3)
+            .evidence()
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+        let retried = summary.results.iter().filter(|r| r.banner_retried).count();
+        let exhausted: Vec<&PortResult> = summary
+            .results
+            .iter()
+            .filter(|r| r.retries_exhausted)
+            .collect();
+        assert_eq!((retried, exhausted.len()), (3, 5));
+        assert!(exhausted.iter().all(|r| !r.banner_retried
+            && r.evidence[2].detail.as_deref() == Some("banner retry: retry budget spent")));
+        assert_eq!(summary.retries, 3);
+        assert_eq!(
+            summary.retry_budget,
+            Some(RetryBudgetReport {
+                limit: 3,
+                used: 3,
+                exhausted_ports: 5,
+            })
+        );
+
+        // Connects out of local ports give up early too, with what their
+        // last attempt gave.
+        let mock =
+            MockConnector::new().port(9, MockBehavior::Error(std::io::ErrorKind::AddrNotAvailable));
+        let summary = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(vec![9])
+            .connector(mock)
+            .max_total_retries(2)
+            .build()
+            .unwrap()
+            .scan_collect()
+            .await;
+        let r = &summary.results[0];
+        assert!(r.retries_exhausted);
+        assert!(matches!(r.error, Some(PortError::LocalResource(_))));
+        assert_eq!((summary.retries, summary.connection_attempts), (2, 3));
+        assert_eq!(summary.retry_budget.unwrap().exhausted_ports, 1);
+    }
+
+    #[tokio::test]
+    async fn sniffed_banners_are_told_by_their_first_bytes() {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"RFB 003.008\n".to_vec()))
+            .port(23, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(24, MockBehavior::Open(b"hello".to_vec()));
+        let scanner = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(vec![22, 23, 24])
+            .banner_timeout(Duration::from_millis(50))
+            .connector(mock.clone())
+            .sniff()
+            .evidence()
+            .build()
+            .unwrap();
+        let summary = scanner.scan_collect().await;
+        let matched = |port: u16| {
+            let r = summary.results.iter().find(|r| r.port == port).unwrap();
+            let step = r.evidence.last().unwrap();
+            assert_eq!(step.step, Step::ServiceMatch);
+            (r.service.clone(), step.detail.clone())
+        };
+        assert_eq!(
+            matched(22),
+            (Some("VNC".into()), Some("RFB handshake".into())),
+            "not SSH, as the port number would have it"
+        );
+        assert_eq!(
+            matched(23),
+            (Some("SSH".into()), Some("SSH identification".into()))
+        );
+        assert_eq!(matched(24), (None, None));
+
+        let results = scan_mock(mock, vec![22]).await;
+        assert_eq!(
+            results[&22].service.as_deref(),
+            Some("SSH"),
+            "only when asked for"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn local_exhaustion_is_reported_as_unscanned() {
+        let mock = MockConnector::new()
+            .port(1, MockBehavior::Os(libc::EMFILE))
+            .port(2, MockBehavior::Os(libc::EMFILE))
+            .port(3, MockBehavior::Hang);
+        let scanner = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(1..=3)
+            .timeout(Duration::from_millis(50))
+            .connector(mock)
+            .build()
+            .unwrap();
+
+        let summary = scanner.scan_collect().await;
+        assert_eq!(summary.filtered_ports, 3);
+        assert_eq!(summary.unscanned_ports, 2);
+        let reasons = summary.unscanned_reasons();
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].0.starts_with("local resources exhausted"));
+        assert_eq!(reasons[0].1, 2);
+
+        let kinds: HashMap<u16, String> = summary
+            .results
+            .iter()
+            .map(|r| {
+                let json = serde_json::to_value(r).unwrap();
+                (r.port, json["error"]["kind"].as_str().unwrap().to_string())
+            })
+            .collect();
+        assert_eq!(kinds[&1], "local_resource");
+        assert_eq!(kinds[&3], "timeout");
+    }
+
+    #[tokio::test]
+    async fn the_throttle_pauses_and_resizes_a_running_scan() {
+        let scanner = Scanner::builder()
+            .target("127.0.0.1")
+            .ports(1..=10)
+            .concurrency(2)
+            .connector(
+                MockConnector::new()
+                    .port(1, MockBehavior::Hang)
+                    .port(2, MockBehavior::Hang),
+            )
+            .build()
+            .unwrap();
+        let metrics = scanner.metrics();
+        let mut stream = scanner.scan();
+        let throttle = stream.throttle();
+        let settle = || tokio::time::sleep(Duration::from_millis(50));
+
+        throttle.pause();
+        settle().await;
+        assert_eq!(metrics.connection_attempts(), 0);
+
+        throttle.resume();
+        settle().await;
+        assert_eq!(
+            metrics.connection_attempts(),
+            2,
+            "both permits held by hung ports"
+        );
+
+        throttle.set_concurrency(3);
+        let first = stream.next().await.unwrap();
+        assert_eq!(throttle.concurrency(), 3);
+        assert_eq!(first.port, 3);
+        assert!(!throttle.is_paused());
+    }
+
+    #[tokio::test]
+    async fn a_fixed_clock_zeroes_every_duration() {
+        let scanner = Scanner::builder()
+            .target("192.0.2.1")
+            .ports(1..=3)
+            .timeout(Duration::from_millis(50))
+            .connector(MockConnector::new().port(2, MockBehavior::Hang))
+            .clock(crate::clock::FixedClock::default())
+            .build()
+            .unwrap();
+
+        let summary = scanner.scan_collect().await;
+        assert_eq!(summary.scanned_ports, 3);
+        assert_eq!(summary.total_time_ms, 0);
+        assert_eq!(summary.ports_per_second, 0.0);
+        assert!(summary.results.iter().all(|r| r.duration_ms == 0));
+    }
+
+    #[tokio::test]
+    async fn audited_probes_record_their_times_and_source_port() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = {
+            let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            unused.local_addr().unwrap().port()
+        };
+        let scanner = Scanner::builder()
+            .target("127.0.0.1")
+            .ports(vec![open, closed])
+            .banner_timeout(Duration::from_millis(50))
+            .audit()
+            .build()
+            .unwrap();
+        let summary = scanner.scan_collect().await;
+        for r in &summary.results {
+            let audit = r.audit.as_ref().expect("audited");
+            assert!(audit.started_at <= audit.finished_at);
+            assert!(audit.source_port.is_some_and(|p| p != 0), "{:?}", r);
+            assert_eq!(audit.attempt, 1);
+        }
+        assert_eq!(summary.open_ports, 1);
+
+        let results = scan_mock(MockConnector::new(), vec![1]).await;
+        assert_eq!(results[&1].audit, None);
+    }
+
+    #[tokio::test]
+    async fn banners_are_truncated_to_one_read() {
+        let mock = MockConnector::new().port(25, MockBehavior::Open(vec![b'a'; 5000]));
+        let results = scan_mock(mock, vec![25]).await;
+        assert_eq!(results[&25].banner.as_ref().map(Banner::len), Some(4096));
+    }
+
+    #[test]
+    fn targets_are_told_apart_by_how_far_they_got() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let results = vec![
+            PortResult::new(ip("10.0.0.1"), 22, PortStatus::Closed),
+            PortResult::new(ip("10.0.0.1"), 80, PortStatus::Filtered),
+            PortResult::new(ip("10.0.1.7"), 22, PortStatus::Filtered),
+        ];
+        let mut summary = ScanSummary::from_results(
+            "id".into(),
+            "10.0.0.0/16".into(),
+            results,
+            0,
+            &ScanMetrics::new("test"),
+            true,
+        );
+        let given = |spec: &str| {
+            let mut hosts = TargetSet::new();
+            hosts.insert(spec).unwrap();
+            TargetReport::new(spec, hosts, Vec::new())
+        };
+        let mut scanned = TargetSet::new();
+        scanned.insert("10.0.0.0-10.0.2.255").unwrap();
+        summary.report_targets(
+            vec![
+                given("10.0.0.0/24"),
+                given("10.0.1.0/24"),
+                given("10.0.2.0/24"),
+                given("10.0.3.1"),
+                TargetReport::failed("bad/99", TargetStatus::Invalid, "Invalid target".into()),
+            ],
+            &scanned,
+        );
+        let statuses: Vec<TargetStatus> = summary.targets.iter().map(|t| t.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                TargetStatus::Scanned,
+                TargetStatus::Unresponsive,
+                TargetStatus::Resolved,
+                TargetStatus::Excluded,
+                TargetStatus::Invalid,
+            ]
+        );
+        let failed: Vec<&str> = summary
+            .failed_targets()
+            .map(|t| t.target.as_str())
+            .collect();
+        assert_eq!(failed, ["bad/99"]);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["targets"][1]["status"], "unresponsive");
+        assert_eq!(json["targets"][4]["error"], "Invalid target");
+    }
+
+    #[test]
+    fn hosts_named_by_several_targets_get_them_as_aliases() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let given = |spec: &str| {
+            let mut hosts = TargetSet::new();
+            hosts.insert(spec).unwrap();
+            TargetReport::new(spec, hosts, Vec::new())
+        };
+        let mut named = TargetSet::new();
+        named.insert_addr(ip("10.0.0.5"));
+        let targets = vec![
+            TargetReport::new("web1.corp", named, vec![ip("10.0.0.5")]),
+            given("10.0.0.0/24"),
+            given("10.0.1.0/24"),
+            given("10.0.0.4-10.0.0.5"),
+            TargetReport::failed("bad/99", TargetStatus::Invalid, "Invalid target".into()),
+        ];
+        let overlaps: Vec<(usize, usize, String)> = overlapping_targets(&targets)
+            .into_iter()
+            .map(|(i, j, shared)| (i, j, shared.to_string()))
+            .collect();
+        assert_eq!(
+            overlaps,
+            [
+                (0, 1, "10.0.0.5".to_string()),
+                (0, 3, "10.0.0.5".to_string()),
+                (1, 3, "10.0.0.4/31".to_string()),
+            ]
+        );
+
+        let results = ["10.0.0.4", "10.0.0.5", "10.0.0.6", "10.0.1.1"]
+            .map(|a| PortResult::new(ip(a), 80, PortStatus::Closed))
+            .to_vec();
+        let mut summary = ScanSummary::from_results(
+            "id".into(),
+            "t".into(),
+            results,
+            0,
+            &ScanMetrics::new("test"),
+            false,
+        );
+        let mut scanned = TargetSet::new();
+        scanned.insert("10.0.0.0-10.0.1.255").unwrap();
+        summary.report_targets(targets, &scanned);
+        summary.annotate_hosts(None, &[]);
+        let aliases: Vec<Vec<String>> = summary.hosts.iter().map(|h| h.aliases.clone()).collect();
+        assert_eq!(
+            aliases,
+            [
+                vec!["10.0.0.0/24", "10.0.0.4-10.0.0.5"],
+                vec!["web1.corp", "10.0.0.0/24", "10.0.0.4-10.0.0.5"],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+}
@@ -0,0 +1,1163 @@
+//! The JSON Schema of the `--json` report, printed by `port-scanner schema`,
+//! and a validator for the subset of JSON Schema it uses.
+//!
+//! The schema follows the serde attributes of [`ScanSummary`] and the types
+//! within it: a field is `required` when it is always written, even if as
+//! `null`, and left out of `required` when the report omits it while it is
+//! empty. No object has properties beyond those listed, so a report with a
+//! field the schema doesn't know fails [`validate`]; the tests run real
+//! scans through it to keep the two in step. Each result is also defined
+//! on its own as `#/$defs/PortResult`, the shape of an NDJSON line.
+//!
+//! The schema is versioned with the crate: its `$id` ends in [`VERSION`].
+//!
+//! [`ScanSummary`]: crate::scanner::ScanSummary
+
+use serde_json::{json, Map, Value};
+
+/// The version of the schema, that of the crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The schema of the JSON report, a [`ScanSummary`](crate::scanner::ScanSummary).
+pub fn scan_summary() -> Value {
+    let mut schema = object(
+        &[
+            ("scan_id", string()),
+            ("target", string()),
+            ("scanned_ports", count()),
+            ("open_ports", count()),
+            ("closed_ports", count()),
+            ("filtered_ports", count()),
+            ("unscanned_ports", count()),
+            ("reset_on_read_ports", count()),
+            ("blocked_hosts", count()),
+            ("total_time_ms", count()),
+            ("ports_per_second", json!({"type": "number", "minimum": 0})),
+            ("concurrency", count()),
+            ("connection_attempts", count()),
+            ("retries", count()),
+            ("banner_probes", count()),
+            ("banner_bytes", count()),
+            ("interrupted", boolean()),
+            ("findings", reference("FindingCounts")),
+            ("policy", nullable(reference("PolicyResult"))),
+            ("results", array(reference("PortResult"))),
+        ],
+        &[
+            ("metadata", reference("Metadata")),
+            ("preset", reference("AppliedPreset")),
+            ("protocols", array(reference("ProtocolCounts"))),
+            ("capture_truncated_ports", count()),
+            ("completion", reference("Completion")),
+            ("panicked_ports", count()),
+            ("stats", reference("Stats")),
+            ("established", reference("EstablishedReport")),
+            ("retry_budget", reference("RetryBudgetReport")),
+            ("heatmap", reference("Heatmap")),
+            ("deviations", reference("DeviationCounts")),
+            ("compliance", reference("Compliance")),
+            ("script_summary", string()),
+            ("reanalysis", reference("Reanalysis")),
+            ("tls_report", reference("TlsReport")),
+            ("rescan", reference("Rescan")),
+            ("identity", strings(&["address", "name"])),
+            ("targets", array(reference("TargetReport"))),
+            ("hosts", array(reference("HostInfo"))),
+            (
+                "shared_responses",
+                json!({
+                    "type": "object",
+                    "additionalProperties": reference("SharedResponse"),
+                    "description": "Banners many open ports of a host answered with, kept \
+                                    once by the key the results name as their shared_response",
+                }),
+            ),
+        ],
+    );
+    let root = schema.as_object_mut().expect("an object schema");
+    root.insert(
+        "$schema".to_string(),
+        json!("https://json-schema.org/draft/2020-12/schema"),
+    );
+    root.insert(
+        "$id".to_string(),
+        json!(format!("urn:port-scanner:scan-summary:{}", VERSION)),
+    );
+    root.insert("title".to_string(), json!("port-scanner scan summary"));
+    root.insert(
+        "description".to_string(),
+        json!("The --json report of a port-scanner scan"),
+    );
+    root.insert("$defs".to_string(), Value::Object(definitions()));
+    schema
+}
+
+fn definitions() -> Map<String, Value> {
+    let defs = [
+        (
+            "PortResult",
+            object(
+                &[
+                    ("target", ip()),
+                    ("port", port()),
+                    ("protocol", reference("Protocol")),
+                    ("status", reference("PortStatus")),
+                    ("banner", nullable(string())),
+                    ("service", nullable(string())),
+                    ("product", nullable(string())),
+                    ("version", nullable(string())),
+                    ("extra_info", nullable(string())),
+                    ("cpe", nullable(string())),
+                    ("duration_ms", count()),
+                    ("error", nullable(reference("PortError"))),
+                ],
+                &[
+                    (
+                        "banner_encoding",
+                        strings(&["windows-1252", "Shift_JIS", "escaped"]),
+                    ),
+                    ("redactions", count()),
+                    ("capture_truncated", boolean()),
+                    ("timeout_ms", count()),
+                    (
+                        "retime",
+                        object(
+                            &[
+                                ("first_ms", json!({"type": "number", "minimum": 0})),
+                                (
+                                    "second_ms",
+                                    nullable(json!({"type": "number", "minimum": 0})),
+                                ),
+                            ],
+                            &[],
+                        ),
+                    ),
+                    (
+                        "timing",
+                        object(
+                            &[("connect_ms", json!({"type": "number", "minimum": 0}))],
+                            &[("banner_ms", json!({"type": "number", "minimum": 0}))],
+                        ),
+                    ),
+                    ("reset_on_read", boolean()),
+                    ("banner_retried", boolean()),
+                    ("retries_exhausted", boolean()),
+                    ("audit", reference("ProbeAudit")),
+                    ("evidence", array(reference("Evidence"))),
+                    ("exec", reference("ExecResult")),
+                    ("notes", array(string())),
+                    ("findings", array(reference("Finding"))),
+                    ("cluster", count()),
+                    ("open_proxy", boolean()),
+                    ("tls", reference("TlsDetails")),
+                    ("previous_status", reference("PortStatus")),
+                    ("stale", timestamp()),
+                    (
+                        "shared_response",
+                        json!({
+                            "type": "string",
+                            "description": "The key in shared_responses of the banner the \
+                                            port sent, which it then has no banner of its own",
+                        }),
+                    ),
+                    ("response_file", string()),
+                    ("note", string()),
+                    ("expected_status", reference("PortStatus")),
+                ],
+            ),
+        ),
+        ("Protocol", strings(&["tcp", "udp"])),
+        ("PortStatus", strings(&["Open", "Closed", "Filtered"])),
+        (
+            "PortError",
+            object(
+                &[(
+                    "kind",
+                    strings(&[
+                        "timeout",
+                        "unreachable",
+                        "unreachable_cached",
+                        "local_resource",
+                        "connect",
+                        "banner",
+                        "task",
+                        "panicked",
+                    ]),
+                )],
+                &[("detail", string())],
+            ),
+        ),
+        (
+            "ProbeAudit",
+            object(
+                &[
+                    ("started_at", timestamp()),
+                    ("finished_at", timestamp()),
+                    ("source_port", nullable(port())),
+                    ("attempt", count()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "Evidence",
+            object(
+                &[
+                    (
+                        "step",
+                        strings(&[
+                            "reach_cache",
+                            "tcp_connect",
+                            "banner_read",
+                            "service_match",
+                            "proxy_check",
+                            "probe",
+                        ]),
+                    ),
+                    (
+                        "outcome",
+                        strings(&[
+                            "success", "refused", "reset", "timeout", "error", "empty", "matched",
+                            "no_match", "skipped",
+                        ]),
+                    ),
+                ],
+                &[("elapsed_ms", count()), ("detail", string())],
+            ),
+        ),
+        (
+            "ExecResult",
+            object(
+                &[
+                    ("command", string()),
+                    ("exit_code", nullable(json!({"type": "integer"}))),
+                    ("stdout", string()),
+                    ("timed_out", boolean()),
+                    ("error", nullable(string())),
+                ],
+                &[],
+            ),
+        ),
+        ("Severity", strings(&["low", "medium", "high", "critical"])),
+        (
+            "Finding",
+            object(
+                &[
+                    ("id", string()),
+                    ("severity", reference("Severity")),
+                    ("summary", string()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "FindingCounts",
+            counts(&["critical", "high", "medium", "low"]),
+        ),
+        (
+            "DeviationCounts",
+            counts(&["annotated", "unexpected_open", "not_open", "other"]),
+        ),
+        (
+            "ProtocolCounts",
+            object(
+                &[
+                    ("protocol", reference("Protocol")),
+                    ("scanned", count()),
+                    ("open", count()),
+                    ("closed", count()),
+                    ("filtered", count()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "PolicyResult",
+            object(
+                &[
+                    ("passed", boolean()),
+                    ("violations", count()),
+                    (
+                        "assertions",
+                        array(object(
+                            &[
+                                ("rule", string()),
+                                ("target", ip()),
+                                ("port", port()),
+                                ("passed", boolean()),
+                                ("message", string()),
+                            ],
+                            &[],
+                        )),
+                    ),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "Compliance",
+            object(
+                &[
+                    ("baseline", string()),
+                    ("expected_open", count()),
+                    ("checked", count()),
+                    ("matched", count()),
+                    (
+                        "drift",
+                        array(object(
+                            &[
+                                ("kind", strings(&["new-open", "closed", "changed"])),
+                                ("severity", reference("Severity")),
+                                ("target", ip()),
+                                ("port", port()),
+                                ("message", string()),
+                            ],
+                            &[],
+                        )),
+                    ),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "SharedResponse",
+            object(
+                &[
+                    ("banner", string()),
+                    ("hosts", array(ip())),
+                    ("ports", count()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "Completion",
+            object(
+                &[(
+                    "reason",
+                    strings(&[
+                        "completed",
+                        "cancelled_by_user",
+                        "deadline_exceeded",
+                        "error",
+                    ]),
+                )],
+                &[("detail", string())],
+            ),
+        ),
+        (
+            "Reanalysis",
+            object(
+                &[(
+                    "changes",
+                    array(object(
+                        &[
+                            ("target", ip()),
+                            ("port", port()),
+                            ("field", string()),
+                            ("before", nullable(string())),
+                            ("after", nullable(string())),
+                        ],
+                        &[],
+                    )),
+                )],
+                &[],
+            ),
+        ),
+        (
+            "Rescan",
+            object(
+                &[
+                    ("from", string()),
+                    ("from_started_at", timestamp()),
+                    ("first_time", count()),
+                    ("reverified", count()),
+                    ("changed", count()),
+                    ("skipped", count()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "TlsVersion",
+            strings(&["SSLv3", "TLSv1.0", "TLSv1.1", "TLSv1.2", "TLSv1.3"]),
+        ),
+        (
+            "TlsDetails",
+            object(
+                &[],
+                &[
+                    ("versions", array(reference("TlsVersion"))),
+                    ("issuer", string()),
+                    ("not_after", timestamp()),
+                    ("key_type", string()),
+                    ("key_bits", count()),
+                    ("alpn", array(string())),
+                ],
+            ),
+        ),
+        (
+            "TlsReport",
+            object(
+                &[
+                    ("at", timestamp()),
+                    ("expiring_days", count()),
+                    (
+                        "endpoints",
+                        array(object(
+                            &[("target", ip()), ("port", port())],
+                            &[
+                                ("min_version", reference("TlsVersion")),
+                                ("max_version", reference("TlsVersion")),
+                                ("issuer", string()),
+                                ("not_after", timestamp()),
+                                ("days_left", json!({"type": "integer"})),
+                                ("key_type", string()),
+                                ("key_bits", count()),
+                                ("alpn", array(string())),
+                                (
+                                    "outliers",
+                                    array(object(
+                                        &[(
+                                            "kind",
+                                            strings(&[
+                                                "legacy",
+                                                "behind",
+                                                "expired",
+                                                "expiring",
+                                                "short_key",
+                                            ]),
+                                        )],
+                                        &[
+                                            ("version", reference("TlsVersion")),
+                                            ("others", reference("TlsVersion")),
+                                            ("days", json!({"type": "integer"})),
+                                            ("bits", count()),
+                                        ],
+                                    )),
+                                ),
+                            ],
+                        )),
+                    ),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "Metadata",
+            object(
+                &[],
+                &[
+                    ("operator", string()),
+                    ("reason", string()),
+                    (
+                        "tags",
+                        json!({"type": "object", "additionalProperties": string()}),
+                    ),
+                ],
+            ),
+        ),
+        (
+            "AppliedPreset",
+            object(
+                &[("name", string()), ("args", array(string()))],
+                &[("overridden", array(string()))],
+            ),
+        ),
+        (
+            "EstablishedReport",
+            object(
+                &[
+                    ("peak", count()),
+                    (
+                        "hosts",
+                        json!({"type": "object", "additionalProperties": count()}),
+                    ),
+                    ("deferred", count()),
+                ],
+                &[("max_per_host", count()), ("max_total", count())],
+            ),
+        ),
+        (
+            "RetryBudgetReport",
+            object(
+                &[
+                    ("limit", count()),
+                    ("used", count()),
+                    ("exhausted_ports", count()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "Heatmap",
+            object(
+                &[
+                    (
+                        "bins_ms",
+                        array(json!({"type": "number", "exclusiveMinimum": 0})),
+                    ),
+                    (
+                        "port_width",
+                        json!({"type": "integer", "minimum": 1, "maximum": 65536}),
+                    ),
+                    (
+                        "rows",
+                        array(object(
+                            &[
+                                ("first_port", port()),
+                                ("last_port", port()),
+                                ("connect", array(count())),
+                                ("banner", array(count())),
+                            ],
+                            &[("host", ip())],
+                        )),
+                    ),
+                ],
+                &[("coarsened", boolean())],
+            ),
+        ),
+        (
+            "TargetReport",
+            object(
+                &[
+                    ("target", string()),
+                    (
+                        "status",
+                        strings(&[
+                            "scanned",
+                            "unresponsive",
+                            "resolved",
+                            "excluded",
+                            "blocked",
+                            "unresolvable",
+                            "invalid",
+                        ]),
+                    ),
+                ],
+                &[("addresses", array(ip())), ("error", string())],
+            ),
+        ),
+        (
+            "HostInfo",
+            object(
+                &[("address", ip())],
+                &[
+                    ("aliases", array(string())),
+                    ("scope", string()),
+                    ("country_code", string()),
+                    ("country", string()),
+                    ("city", string()),
+                    ("asn", count()),
+                    ("as_name", string()),
+                    (
+                        "dns",
+                        array(object(
+                            &[
+                                ("name", string()),
+                                ("server", string()),
+                                (
+                                    "records",
+                                    array(object(&[("address", ip())], &[("ttl", count())])),
+                                ),
+                            ],
+                            &[],
+                        )),
+                    ),
+                    ("names", array(string())),
+                    ("key", string()),
+                    (
+                        "mac",
+                        json!({"type": "string", "pattern": "^([0-9a-f]{2}:){5}[0-9a-f]{2}$"}),
+                    ),
+                    ("vendor", string()),
+                    ("interface", string()),
+                    (
+                        "route",
+                        array(object(
+                            &[("ttl", count())],
+                            &[
+                                ("address", ip()),
+                                ("rtt_ms", json!({"type": "number", "minimum": 0})),
+                            ],
+                        )),
+                    ),
+                    (
+                        "device",
+                        object(
+                            &[],
+                            &[
+                                ("name", string()),
+                                ("model", string()),
+                                ("server", string()),
+                                ("services", array(string())),
+                            ],
+                        ),
+                    ),
+                    (
+                        "os_guess",
+                        object(
+                            &[
+                                ("os", string()),
+                                ("confidence", strings(&["low", "medium", "high"])),
+                                ("reasons", array(string())),
+                            ],
+                            &[],
+                        ),
+                    ),
+                    ("timing", counts(&["started_ms", "duration_ms"])),
+                    ("uniform_responses", boolean()),
+                ],
+            ),
+        ),
+        (
+            "Stats",
+            object(
+                &[
+                    (
+                        "connects",
+                        counts(&[
+                            "attempted",
+                            "succeeded",
+                            "refused",
+                            "timed_out",
+                            "reset",
+                            "failed",
+                        ]),
+                    ),
+                    (
+                        "banners",
+                        counts(&[
+                            "attempted",
+                            "succeeded",
+                            "empty",
+                            "timed_out",
+                            "failed",
+                            "bytes",
+                        ]),
+                    ),
+                    (
+                        "probes",
+                        object(
+                            &[
+                                ("run", count()),
+                                ("shared", count()),
+                                ("reconnects", count()),
+                            ],
+                            &[(
+                                "failures",
+                                json!({
+                                    "type": "object",
+                                    "additionalProperties": counts(&["timed_out", "failed"]),
+                                }),
+                            )],
+                        ),
+                    ),
+                    ("retries", count()),
+                    ("unreachable_cached", count()),
+                    ("ports_exhausted", count()),
+                    ("panics", count()),
+                    (
+                        "connect_time_us",
+                        counts(&["count", "p50", "p90", "p99", "max"]),
+                    ),
+                    (
+                        "permit_wait_us",
+                        counts(&["count", "p50", "p90", "p99", "max"]),
+                    ),
+                ],
+                &[(
+                    "phases_ms",
+                    object(
+                        &[
+                            ("planning", count()),
+                            ("scanning", count()),
+                            ("verification", count()),
+                        ],
+                        &[("output", count())],
+                    ),
+                )],
+            ),
+        ),
+    ];
+    defs.into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect()
+}
+
+/// An object with exactly these properties, the `required` ones always
+/// present.
+fn object(required: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = required
+        .iter()
+        .chain(optional)
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    let names: Vec<&str> = required.iter().map(|(name, _)| *name).collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": names,
+        "additionalProperties": false,
+    })
+}
+
+/// An object of counts, all of them present.
+fn counts(names: &[&str]) -> Value {
+    let fields: Vec<(&str, Value)> = names.iter().map(|name| (*name, count())).collect();
+    object(&fields, &[])
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn strings(values: &[&str]) -> Value {
+    json!({"type": "string", "enum": values})
+}
+
+fn count() -> Value {
+    json!({"type": "integer", "minimum": 0})
+}
+
+fn port() -> Value {
+    json!({"type": "integer", "minimum": 0, "maximum": 65535})
+}
+
+fn boolean() -> Value {
+    json!({"type": "boolean"})
+}
+
+fn ip() -> Value {
+    json!({"type": "string", "anyOf": [{"format": "ipv4"}, {"format": "ipv6"}]})
+}
+
+fn timestamp() -> Value {
+    json!({"type": "string", "format": "date-time"})
+}
+
+fn array(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({"anyOf": [schema, {"type": "null"}]})
+}
+
+fn reference(name: &str) -> Value {
+    json!({"$ref": format!("#/$defs/{}", name)})
+}
+
+/// Checks `value` against `schema`, one of [`scan_summary`] or its
+/// definitions, and lists every mismatch as the JSON pointer of the value
+/// and what was wrong with it.
+///
+/// Only the keywords the schema uses are checked: `$ref` within the
+/// document, `type`, `enum`, `minimum`, `maximum`, `properties`,
+/// `required`, `additionalProperties` (`false` or a schema for every
+/// other property), `items` and `anyOf`. Formats
+/// and patterns are taken on trust.
+pub fn validate(schema: &Value, value: &Value) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    check(schema, schema, value, "", &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn check(root: &Value, schema: &Value, value: &Value, at: &str, errors: &mut Vec<String>) {
+    let path = if at.is_empty() { "/" } else { at };
+    if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+        match target
+            .strip_prefix("#/$defs/")
+            .and_then(|name| root.get("$defs")?.get(name))
+        {
+            Some(def) => check(root, def, value, at, errors),
+            None => errors.push(format!("{}: unknown $ref {}", path, target)),
+        }
+        return;
+    }
+    if let Some(ty) = schema.get("type").and_then(Value::as_str) {
+        let matches = match ty {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => false,
+        };
+        if !matches {
+            errors.push(format!("{}: expected {}, found {}", path, ty, value));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: {} is not one of {:?}", path, value, allowed));
+        }
+    }
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+            if n < min {
+                errors.push(format!("{}: {} is below {}", path, value, min));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+            if n > max {
+                errors.push(format!("{}: {} is above {}", path, value, max));
+            }
+        }
+    }
+    if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+        let any = options.iter().any(|option| {
+            let mut option_errors = Vec::new();
+            check(root, option, value, at, &mut option_errors);
+            option_errors.is_empty()
+        });
+        if !any {
+            errors.push(format!(
+                "{}: {} matches none of the allowed schemas",
+                path, value
+            ));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for name in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(name) {
+                errors.push(format!("{}: missing required property \"{}\"", path, name));
+            }
+        }
+        let additional = schema.get("additionalProperties");
+        for (name, field) in object {
+            let field_at = format!("{}/{}", at, name);
+            match (properties.and_then(|p| p.get(name)), additional) {
+                (Some(property), _) => check(root, property, field, &field_at, errors),
+                (None, Some(Value::Bool(false))) => {
+                    errors.push(format!("{}: property not in the schema", field_at))
+                }
+                (None, Some(other @ Value::Object(_))) => {
+                    check(root, other, field, &field_at, errors)
+                }
+                (None, _) => {}
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            check(root, items, item, &format!("{}/{}", at, i), errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotations::DeviationCounts;
+    use crate::baseline::{Compliance, Drift, DriftKind};
+    use crate::completion::Completion;
+    use crate::discover::Device;
+    use crate::established::EstablishedReport;
+    use crate::evidence::{Evidence, Outcome, Step};
+    use crate::exec::ExecResult;
+    use crate::geoip::GeoInfo;
+    use crate::identity::IdentityMode;
+    use crate::metadata::Metadata;
+    use crate::metrics::{HostTime, ScanMetrics};
+    use crate::osguess::{Confidence, OsGuess};
+    use crate::oui::MacAddr;
+    use crate::policy::{PolicyAssertion, PolicyResult};
+    use crate::preset::AppliedPreset;
+    use crate::reanalysis::{Change, Reanalysis};
+    use crate::rescan::Rescan;
+    use crate::resolve::{DnsRecord, Resolution};
+    use crate::retry::RetryBudgetReport;
+    use crate::scanner::{
+        HostInfo, PortError, PortResult, PortStatus, ProbeAudit, ScanSummary, TargetReport,
+        TargetStatus,
+    };
+    use crate::shared::{self, SharedResponse};
+    use crate::stats::{Phases, ProbeFailures};
+    use crate::tlsreport::{TlsDetails, TlsReport, TlsVersion};
+    use crate::traceroute::Hop;
+    use crate::vuln::{Finding, Severity};
+    use chrono::{TimeZone, Utc};
+
+    /// A summary with every optional block filled in.
+    fn full_summary() -> ScanSummary {
+        let ip = "10.0.0.1".parse().unwrap();
+        let mut r = PortResult::new(ip, 22, PortStatus::Open);
+        r.banner = Some("SSH-2.0-OpenSSH_9.6".into());
+        r.banner_encoding = Some(crate::charset::BannerEncoding::Escaped);
+        r.redactions = 1;
+        r.capture_truncated = true;
+        r.service = Some("SSH".to_string());
+        r.product = Some("OpenSSH".to_string());
+        r.version = Some("9.6".to_string());
+        r.extra_info = Some("Ubuntu".to_string());
+        r.cpe = Some("cpe:2.3:a:openbsd:openssh:9.6:*:*:*:*:*:*:*".to_string());
+        r.duration_ms = 12;
+        r.timeout_ms = Some(800);
+        r.retime = Some(crate::latency::Retime {
+            first_ms: 250.5,
+            second_ms: Some(1.25),
+        });
+        r.timing = Some(crate::heatmap::Timing {
+            connect_ms: 0.25,
+            banner_ms: Some(3.5),
+        });
+        r.reset_on_read = true;
+        r.banner_retried = true;
+        r.retries_exhausted = true;
+        let at = Utc.with_ymd_and_hms(2026, 10, 15, 12, 0, 0).unwrap();
+        r.audit = Some(ProbeAudit {
+            started_at: at,
+            finished_at: at,
+            source_port: Some(40000),
+            attempt: 1,
+        });
+        r.evidence = vec![Evidence::new(Step::TcpConnect, Outcome::Success).detail("ok")];
+        r.error = Some(PortError::Banner("reset".to_string()));
+        r.exec = Some(ExecResult {
+            command: "true".to_string(),
+            exit_code: Some(0),
+            stdout: String::new(),
+            timed_out: false,
+            error: None,
+        });
+        r.notes = vec!["note".to_string()];
+        r.findings = vec![Finding {
+            id: "CVE-2024-6387".to_string(),
+            severity: Severity::High,
+            summary: "regreSSHion".to_string(),
+        }];
+        r.cluster = Some(1);
+        r.open_proxy = Some(false);
+        r.tls = Some(TlsDetails {
+            versions: vec![TlsVersion::Tls10, TlsVersion::Tls12],
+            issuer: Some("R3".to_string()),
+            not_after: Some(chrono::DateTime::UNIX_EPOCH),
+            key_type: Some("rsa".to_string()),
+            key_bits: Some(1024),
+            alpn: vec!["h2".to_string()],
+        });
+        r.response_file = Some("responses/10.0.0.1_22.bin".into());
+        r.note = Some("bastion".to_string());
+        r.expected_status = Some(PortStatus::Open);
+        r.previous_status = Some(PortStatus::Closed);
+        r.stale = Some(at);
+        // Set by hand, so that the result also keeps its banner.
+        r.shared_response = Some(shared::key(r.banner.as_ref().unwrap()));
+        let mut timeout = PortResult::new(ip, 23, PortStatus::Filtered);
+        timeout.error = Some(PortError::Timeout);
+        let mut panicked = PortResult::new(ip, 24, PortStatus::Filtered);
+        panicked.error = Some(PortError::Panicked("bug".to_string()));
+
+        let metrics = ScanMetrics::new("10.0.0.1").with_stats();
+        let mut summary = ScanSummary::from_results(
+            "id".into(),
+            "10.0.0.1".into(),
+            vec![r, timeout, panicked],
+            5,
+            &metrics,
+            false,
+        );
+        let stats = summary.stats.as_mut().unwrap();
+        stats.probes.failures = [("ladder tls".to_string(), ProbeFailures::default())].into();
+        stats.phases_ms = Some(Phases {
+            planning: 1,
+            scanning: 2,
+            verification: 3,
+            output: Some(4),
+        });
+        summary.deviations = Some(DeviationCounts::default());
+        summary.policy = Some(PolicyResult {
+            passed: false,
+            violations: 1,
+            assertions: vec![PolicyAssertion {
+                rule: "fail-on 22".to_string(),
+                target: ip,
+                port: 22,
+                passed: false,
+                message: "open".to_string(),
+            }],
+        });
+        summary.compliance = Some(Compliance {
+            baseline: "baseline.toml".to_string(),
+            expected_open: 1,
+            checked: 1,
+            matched: 0,
+            drift: vec![Drift {
+                kind: DriftKind::NewOpen,
+                severity: Severity::Critical,
+                target: ip,
+                port: 22,
+                message: "new".to_string(),
+            }],
+        });
+        summary.script_summary = Some("done".to_string());
+        summary.reanalysis = Some(Reanalysis {
+            changes: vec![Change {
+                target: ip,
+                port: 22,
+                field: "product".to_string(),
+                before: None,
+                after: Some("OpenSSH".to_string()),
+            }],
+        });
+        summary.tls_report = Some(TlsReport::of(
+            &summary.results,
+            chrono::DateTime::UNIX_EPOCH,
+            30,
+        ));
+        summary.rescan = Some(Rescan {
+            from: "before.json".to_string(),
+            from_started_at: chrono::DateTime::UNIX_EPOCH,
+            first_time: 1,
+            reverified: 1,
+            changed: 1,
+            skipped: 1,
+        });
+        summary.identity = IdentityMode::Name;
+        summary.completion = Some(Completion::deadline(std::time::Duration::from_secs(60)));
+        summary.metadata = Metadata::new(
+            Some("jsmith".to_string()),
+            Some("change CHG-1234".to_string()),
+            ["env=prod".parse().unwrap()],
+        )
+        .unwrap();
+        summary.preset = Some(AppliedPreset {
+            name: "web-audit".to_string(),
+            args: vec!["--http-probe".to_string()],
+            overridden: vec!["--ports".to_string()],
+        });
+        summary.heatmap = Some(crate::heatmap::heatmap(
+            &summary.results,
+            &Default::default(),
+        ));
+        summary.established = Some(EstablishedReport {
+            max_per_host: Some(2),
+            max_total: Some(100),
+            peak: 2,
+            hosts: [(ip, 2)].into(),
+            deferred: 1,
+        });
+        summary.retry_budget = Some(RetryBudgetReport {
+            limit: 10,
+            used: 10,
+            exhausted_ports: 1,
+        });
+        let mut target = TargetReport::new("10.0.0.1", Default::default(), vec![ip]);
+        target.status = TargetStatus::Scanned;
+        target.error = Some("none".to_string());
+        summary.targets = vec![target];
+        let mut device = Device::default();
+        device.name = Some("printer".to_string());
+        device.services = vec!["_ipp._tcp".to_string()];
+        summary.hosts = vec![HostInfo {
+            address: ip,
+            aliases: vec!["host.example".to_string(), "10.0.0.1".to_string()],
+            geo: GeoInfo {
+                scope: Some("private".to_string()),
+                country_code: Some("NL".to_string()),
+                country: Some("Netherlands".to_string()),
+                city: Some("Amsterdam".to_string()),
+                asn: Some(64500),
+                as_name: Some("Example".to_string()),
+            },
+            dns: vec![Resolution {
+                name: "host.example".to_string(),
+                server: "10.0.0.53:53".to_string(),
+                records: vec![DnsRecord {
+                    address: ip,
+                    ttl: Some(60),
+                }],
+            }],
+            names: vec!["host.example".to_string()],
+            key: Some("host.example".to_string()),
+            mac: Some("00:11:22:33:44:55".parse::<MacAddr>().unwrap()),
+            vendor: Some("Vendor".to_string()),
+            interface: Some("eth0".to_string()),
+            route: vec![Hop {
+                ttl: 1,
+                address: Some(ip),
+                rtt_ms: Some(0.5),
+            }],
+            device: Some(device),
+            os_guess: Some(OsGuess {
+                os: "Linux".to_string(),
+                confidence: Confidence::Medium,
+                reasons: vec!["OpenSSH".to_string()],
+            }),
+            timing: Some(HostTime {
+                started_ms: 0,
+                duration_ms: 5,
+            }),
+            uniform_responses: true,
+        }];
+        let banner = summary.results[0].banner.clone().unwrap();
+        let shared = SharedResponse {
+            hosts: [ip].into(),
+            ports: 1,
+            banner,
+        };
+        summary.shared_responses = [(shared::key(&shared.banner), shared)].into();
+        summary
+    }
+
+    #[test]
+    fn every_block_of_a_full_summary_matches_the_schema() {
+        let schema = scan_summary();
+        let report = serde_json::to_value(full_summary()).unwrap();
+        assert_eq!(validate(&schema, &report), Ok(()));
+
+        // The schema lists every field the report writes, optional or not.
+        let result = &report["results"][0];
+        let defined = &schema["$defs"]["PortResult"]["properties"];
+        assert_eq!(
+            result.as_object().unwrap().len(),
+            defined.as_object().unwrap().len()
+        );
+        assert_eq!(
+            schema["$id"],
+            format!("urn:port-scanner:scan-summary:{}", VERSION)
+        );
+    }
+
+    #[test]
+    fn the_validator_names_what_does_not_match() {
+        let schema = scan_summary();
+        let mut report = serde_json::to_value(full_summary()).unwrap();
+        report["open_ports"] = json!(-1);
+        report["results"][0]["status"] = json!("Ajar");
+        report["results"][1]["service"] = json!(7);
+        report["results"][1]["shiny"] = json!(true);
+        report["policy"] = Value::Null;
+        report["metadata"]["tags"]["env"] = json!(["prod"]);
+        report.as_object_mut().unwrap().remove("interrupted");
+
+        let errors = validate(&schema, &report).unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "/: missing required property \"interrupted\"",
+                "/metadata/tags/env: expected string, found [\"prod\"]",
+                "/open_ports: -1 is below 0",
+                "/results/0/status: \"Ajar\" is not one of [String(\"Open\"), \
+                 String(\"Closed\"), String(\"Filtered\")]",
+                "/results/1/service: 7 matches none of the allowed schemas",
+                "/results/1/shiny: property not in the schema",
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Which addresses belong to the public internet. Scanning those by accident
+//! is the mistake the CLI guards against, so everything else is spelled out
+//! here: private networks, and ranges that are reserved and never routed.
+
+use once_cell::sync::Lazy;
+use std::net::IpAddr;
+
+use crate::plan::TargetSet;
+
+/// Networks owned by whoever runs the scan: RFC 1918, unique local (ULA),
+/// loopback and link-local addresses.
+pub const PRIVATE: &[&str] = &[
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "fc00::/7",
+    "::1/128",
+    "fe80::/10",
+];
+
+/// Special-purpose ranges that are not reachable hosts on the internet:
+/// "this network", carrier-grade NAT, documentation, benchmarking,
+/// multicast and reserved space.
+pub const RESERVED: &[&str] = &[
+    "0.0.0.0/8",
+    "100.64.0.0/10",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "100::/64",
+    "2001:db8::/32",
+    "ff00::/8",
+];
+
+static PRIVATE_SET: Lazy<TargetSet> = Lazy::new(|| set_of(PRIVATE));
+static NON_PUBLIC_SET: Lazy<TargetSet> = Lazy::new(|| set_of(&[PRIVATE, RESERVED].concat()));
+
+fn set_of(blocks: &[&str]) -> TargetSet {
+    let mut set = TargetSet::new();
+    for block in blocks {
+        set.insert(block).expect("valid CIDR block");
+    }
+    set
+}
+
+/// True for addresses in a [`PRIVATE`] network.
+pub fn is_private(ip: IpAddr) -> bool {
+    PRIVATE_SET.contains(ip)
+}
+
+/// True for addresses in a [`RESERVED`] range.
+pub fn is_reserved(ip: IpAddr) -> bool {
+    !is_private(ip) && NON_PUBLIC_SET.contains(ip)
+}
+
+/// True for addresses on the public internet: neither private nor reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+    !NON_PUBLIC_SET.contains(ip)
+}
+
+/// The public hosts of `targets`, kept as ranges like the set itself.
+pub fn public_hosts(targets: &TargetSet) -> TargetSet {
+    let mut public = targets.clone();
+    public.remove_all(&NON_PUBLIC_SET);
+    public
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn addresses_are_classified_by_range() {
+        for private in [
+            "10.1.2.3",
+            "172.31.255.255",
+            "192.168.0.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "fd12::1",
+            "::1",
+            "fe80::1",
+        ] {
+            assert!(is_private(ip(private)), "{}", private);
+            assert!(!is_reserved(ip(private)) && !is_public(ip(private)));
+        }
+        for reserved in [
+            "0.0.0.0",
+            "100.64.0.1",
+            "100.127.255.255",
+            "192.0.2.10",
+            "198.51.100.1",
+            "203.0.113.255",
+            "198.19.0.1",
+            "224.0.0.251",
+            "255.255.255.255",
+            "2001:db8::1",
+            "ff02::1",
+        ] {
+            assert!(is_reserved(ip(reserved)), "{}", reserved);
+            assert!(!is_private(ip(reserved)) && !is_public(ip(reserved)));
+        }
+        for public in [
+            "8.8.8.8",
+            "172.32.0.1",
+            "100.128.0.1",
+            "192.0.3.1",
+            "11.0.0.1",
+            "2606:4700::1111",
+        ] {
+            assert!(is_public(ip(public)), "{}", public);
+        }
+    }
+
+    #[test]
+    fn public_hosts_are_cut_out_of_ranges() {
+        let mut targets = TargetSet::new();
+        targets.insert("9.255.255.254-10.0.0.1").unwrap();
+        targets.insert("192.168.1.1").unwrap();
+        targets.insert("fc00::/127").unwrap();
+        let public = public_hosts(&targets);
+        assert_eq!(public.to_string(), "9.255.255.254/31");
+    }
+}
@@ -0,0 +1,308 @@
+//! User scripts (`--script`) for custom detection, filtering and summaries.
+//!
+//! A script is a [rhai](https://rhai.rs) file defining any of:
+//!
+//! - `on_open(result)` — called for every open port; return the (possibly
+//!   modified) result map to change `service` or add strings to `notes`.
+//! - `filter(result)` — return `false` to drop the result from the report.
+//! - `summarize(summary)` — return a string to print after the scan.
+//!
+//! Results and summaries are passed as plain maps. Scripts can't import
+//! modules or touch files or the network, and each call is capped in the
+//! number of operations it may run.
+
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::path::Path;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+
+/// Upper bound on operations per call, so a runaway loop can't stall a scan.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Why a script could not be loaded.
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    /// The file could not be read.
+    #[error("Cannot read script {path}: {source}")]
+    Read {
+        /// The script path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The script does not compile, or its top-level statements failed.
+    #[error("{path}:{line}:{column}: {message}")]
+    Compile {
+        /// The script path.
+        path: String,
+        /// 1-based line of the error, 0 if unknown.
+        line: usize,
+        /// 1-based column of the error, 0 if unknown.
+        column: usize,
+        /// What went wrong.
+        message: String,
+    },
+}
+
+/// A compiled script and the hooks it defines.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    on_open: bool,
+    filter: bool,
+    summarize: bool,
+}
+
+impl Script {
+    /// Reads and compiles the script at `path`.
+    pub fn load(path: &Path) -> Result<Self, ScriptError> {
+        let source = std::fs::read_to_string(path).map_err(|source| ScriptError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        Self::compile(&path.display().to_string(), &source)
+    }
+
+    /// Compiles `source`; `name` is used in error messages.
+    pub fn compile(name: &str, source: &str) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|s| info!("script: {}", s));
+        engine.on_debug(|s, _, pos| debug!("script ({}): {}", pos, s));
+
+        let compile_error = |message: String, pos: rhai::Position| ScriptError::Compile {
+            path: name.to_string(),
+            line: pos.line().unwrap_or(0),
+            column: pos.position().unwrap_or(0),
+            message,
+        };
+        let ast = engine
+            .compile(source)
+            .map_err(|e| compile_error(e.err_type().to_string(), e.position()))?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| compile_error(e.to_string(), e.position()))?;
+
+        let defines = |name: &str| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == 1)
+        };
+        Ok(Script {
+            on_open: defines("on_open"),
+            filter: defines("filter"),
+            summarize: defines("summarize"),
+            engine,
+            ast,
+            scope,
+        })
+    }
+
+    fn call(&mut self, hook: &str, arg: Map) -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options(options, &mut self.scope, &self.ast, hook, (arg,))
+    }
+
+    /// Runs `on_open` for an open port, applying the service and notes it
+    /// returns. Errors are logged and leave the result unchanged.
+    pub fn on_open(&mut self, r: &mut PortResult) {
+        if !self.on_open || r.status != PortStatus::Open {
+            return;
+        }
+        let returned = match self.call("on_open", result_map(r)) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Script on_open failed for port {}: {}", r.port, e);
+                return;
+            }
+        };
+        let Some(map) = returned.try_cast::<Map>() else {
+            return;
+        };
+        if let Some(service) = map.get("service") {
+            r.service = service.clone().into_string().ok();
+        }
+        if let Some(notes) = map
+            .get("notes")
+            .and_then(|n| n.clone().try_cast::<rhai::Array>())
+        {
+            r.notes = notes.into_iter().map(|n| n.to_string()).collect();
+        }
+    }
+
+    /// Runs `filter`; results are kept when there is no filter or it fails.
+    pub fn keep(&mut self, r: &PortResult) -> bool {
+        if !self.filter {
+            return true;
+        }
+        match self.call("filter", result_map(r)) {
+            Ok(v) => v.as_bool().unwrap_or(true),
+            Err(e) => {
+                warn!("Script filter failed for port {}: {}", r.port, e);
+                true
+            }
+        }
+    }
+
+    /// Runs `summarize` and returns its text, if any.
+    pub fn summarize(&mut self, summary: &ScanSummary) -> Option<String> {
+        if !self.summarize {
+            return None;
+        }
+        match self.call("summarize", summary_map(summary)) {
+            Ok(v) if v.is_unit() => None,
+            Ok(v) => Some(v.to_string()),
+            Err(e) => {
+                warn!("Script summarize failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+fn optional(v: Option<&str>) -> Dynamic {
+    v.map_or(Dynamic::UNIT, |s| s.into())
+}
+
+fn status_name(status: &PortStatus) -> &'static str {
+    match status {
+        PortStatus::Open => "open",
+        PortStatus::Closed => "closed",
+        PortStatus::Filtered => "filtered",
+    }
+}
+
+/// The script's view of a result.
+fn result_map(r: &PortResult) -> Map {
+    let mut m = Map::new();
+    m.insert("target".into(), r.target.to_string().into());
+    m.insert("port".into(), (r.port as i64).into());
+    m.insert("status".into(), status_name(&r.status).into());
+    m.insert("service".into(), optional(r.service.as_deref()));
+    m.insert("product".into(), optional(r.product.as_deref()));
+    m.insert("version".into(), optional(r.version.as_deref()));
+    m.insert("banner".into(), optional(r.banner_str().as_deref()));
+    m.insert("duration_ms".into(), (r.duration_ms as i64).into());
+    m.insert(
+        "error".into(),
+        optional(r.error.as_ref().map(|e| e.to_string()).as_deref()),
+    );
+    let notes: rhai::Array = r.notes.iter().map(|n| n.as_str().into()).collect();
+    m.insert("notes".into(), notes.into());
+    m
+}
+
+/// The script's view of a summary, results included.
+fn summary_map(s: &ScanSummary) -> Map {
+    let mut m = Map::new();
+    m.insert("scan_id".into(), s.scan_id.as_str().into());
+    m.insert("target".into(), s.target.as_str().into());
+    m.insert("scanned_ports".into(), (s.scanned_ports as i64).into());
+    m.insert("open_ports".into(), (s.open_ports as i64).into());
+    m.insert("closed_ports".into(), (s.closed_ports as i64).into());
+    m.insert("filtered_ports".into(), (s.filtered_ports as i64).into());
+    m.insert("total_time_ms".into(), (s.total_time_ms as i64).into());
+    m.insert("interrupted".into(), s.interrupted.into());
+    let results: rhai::Array = s.results.iter().map(|r| result_map(r).into()).collect();
+    m.insert("results".into(), results.into());
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banner::Banner;
+    use crate::metrics::ScanMetrics;
+
+    fn result(port: u16, status: PortStatus, banner: Option<&str>) -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), port, status);
+        r.banner = banner.map(Banner::from);
+        r.duration_ms = 3;
+        r
+    }
+
+    fn example(name: &str) -> Script {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("scripts")
+            .join(name);
+        Script::load(&path).unwrap()
+    }
+
+    #[test]
+    fn detect_example_reclassifies_and_annotates() {
+        let mut script = example("detect.rhai");
+        let mut redis = result(
+            6379,
+            PortStatus::Open,
+            Some("-NOAUTH Authentication required."),
+        );
+        script.on_open(&mut redis);
+        assert_eq!(redis.service.as_deref(), Some("Redis"));
+        assert_eq!(redis.notes, ["authentication required"]);
+
+        let mut closed = result(6379, PortStatus::Closed, None);
+        script.on_open(&mut closed);
+        assert_eq!(closed.service, None);
+    }
+
+    #[test]
+    fn filter_example_drops_closed_ports_and_summarizes() {
+        let mut script = example("filter.rhai");
+        let open = result(22, PortStatus::Open, None);
+        let closed = result(23, PortStatus::Closed, None);
+        assert!(script.keep(&open));
+        assert!(!script.keep(&closed));
+
+        let summary = ScanSummary::from_results(
+            "id".into(),
+            "10.0.0.1".into(),
+            vec![open],
+            5,
+            &ScanMetrics::new("10.0.0.1"),
+            false,
+        );
+        assert_eq!(
+            script.summarize(&summary).as_deref(),
+            Some("10.0.0.1: 1 open (22)")
+        );
+    }
+
+    #[test]
+    fn compile_errors_carry_the_position() {
+        let err = Script::compile("bad.rhai", "fn on_open(r) {\n  r.port +\n}")
+            .err()
+            .unwrap();
+        match err {
+            ScriptError::Compile { line, column, .. } => {
+                assert_eq!(line, 3);
+                assert!(column > 0);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn runtime_errors_are_skipped() {
+        let mut script = Script::compile(
+            "boom.rhai",
+            "fn on_open(r) { throw \"boom\" }\nfn filter(r) { r.nope.call() }",
+        )
+        .unwrap();
+        let mut r = result(80, PortStatus::Open, None);
+        script.on_open(&mut r);
+        assert_eq!(r.service, None);
+        assert!(script.keep(&r));
+    }
+
+    #[test]
+    fn scripts_cannot_import_or_loop_forever() {
+        assert!(Script::compile("import.rhai", "import \"std\" as s;").is_err());
+        let mut script = Script::compile("loop.rhai", "fn filter(r) { loop {} }").unwrap();
+        assert!(script.keep(&result(1, PortStatus::Open, None)));
+    }
+}
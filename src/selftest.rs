@@ -0,0 +1,357 @@
+//! `port-scanner self-test`: scans listeners of known behavior on the
+//! loopback interface with the real engine and checks what it makes of
+//! them, to tell a scanner problem from a network one.
+//!
+//! Each [`Case`] starts a listener and describes what a scan of it should
+//! find, in the words [`describe`] uses for the result the scan returns:
+//! the status, the banner, the service matched from it, a reset, and
+//! whether the port answered within the timeout. A check passes when the
+//! two descriptions are the same. The filtered case is a listener whose
+//! accept queue is already full, so the kernel drops the scanner's SYN as
+//! a firewall would.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener as StdListener, TcpStream as StdStream};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::scanner::{PortResult, PortStatus, ScanError, Scanner};
+
+/// The banner the banner case sends.
+const BANNER: &str = "SSH-2.0-OpenSSH_9.6";
+
+/// Why the self-test could not run at all, as opposed to a failed check.
+#[derive(Error, Debug)]
+pub enum SelfTestError {
+    /// A loopback listener could not be set up.
+    #[error("Could not start a loopback listener: {0}")]
+    Listener(#[from] io::Error),
+
+    /// The scanner refused the settings.
+    #[error(transparent)]
+    Scan(#[from] ScanError),
+}
+
+/// The behaviors the self-test scans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    /// Accepts and stays silent: open, without a banner.
+    Accept,
+    /// Accepts and sends an SSH banner: open, matched as OpenSSH.
+    Banner,
+    /// Accepts and resets the connection: open, reset on read.
+    Reset,
+    /// A port nothing listens on: closed.
+    Unbound,
+    /// Never accepts: filtered once the connect times out.
+    NeverAccept,
+}
+
+impl Case {
+    /// Every case, in the order of the report.
+    pub const ALL: [Case; 5] = [
+        Case::Accept,
+        Case::Banner,
+        Case::Reset,
+        Case::Unbound,
+        Case::NeverAccept,
+    ];
+
+    /// The name of the case in the report.
+    pub fn name(self) -> &'static str {
+        match self {
+            Case::Accept => "instant accept",
+            Case::Banner => "banner",
+            Case::Reset => "accept then reset",
+            Case::Unbound => "unbound port",
+            Case::NeverAccept => "never accept",
+        }
+    }
+
+    /// What a scan of the case's listener should find.
+    pub fn expected(self) -> String {
+        match self {
+            Case::Accept => "open, no banner, in time".to_string(),
+            Case::Banner => format!("open, banner \"{}\", SSH OpenSSH 9.6, in time", BANNER),
+            Case::Reset => "open, no banner, reset, in time".to_string(),
+            Case::Unbound => "closed, in time".to_string(),
+            Case::NeverAccept => "filtered, timed out".to_string(),
+        }
+    }
+}
+
+/// How a scan's result for a case compared with what was expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The case checked.
+    pub case: Case,
+    /// The loopback port its listener had.
+    pub port: u16,
+    /// What the scan should have found.
+    pub expected: String,
+    /// What it found, or that it returned no result for the port.
+    pub found: String,
+}
+
+impl Check {
+    /// Whether the scan found what was expected.
+    pub fn passed(&self) -> bool {
+        self.expected == self.found
+    }
+}
+
+/// The checks of a self-test run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// One check per [`Case`], in the order of [`Case::ALL`].
+    pub checks: Vec<Check>,
+    /// Wall time of the scan.
+    pub total_time_ms: u128,
+}
+
+impl Report {
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(Check::passed)
+    }
+}
+
+/// A table of the checks, then how many passed.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = |column: fn(&Check) -> &str, title: &str| {
+            self.checks
+                .iter()
+                .map(|c| column(c).len())
+                .fold(title.len(), usize::max)
+        };
+        let name = width(|c| c.case.name(), "Check");
+        let expected = width(|c| &c.expected, "Expected");
+        let found = width(|c| &c.found, "Found");
+        writeln!(
+            f,
+            "{:<name$}  {:>5}  {:<expected$}  {:<found$}  Result",
+            "Check", "Port", "Expected", "Found"
+        )?;
+        for c in &self.checks {
+            writeln!(
+                f,
+                "{:<name$}  {:>5}  {:<expected$}  {:<found$}  {}",
+                c.case.name(),
+                c.port,
+                c.expected,
+                c.found,
+                if c.passed() { "pass" } else { "FAIL" }
+            )?;
+        }
+        writeln!(
+            f,
+            "{} of {} checks passed in {}ms",
+            self.checks.iter().filter(|c| c.passed()).count(),
+            self.checks.len(),
+            self.total_time_ms
+        )
+    }
+}
+
+/// Describes a result the way [`Case::expected`] does: the status, for an
+/// open port its banner and the service matched from it and any reset,
+/// and whether the port answered within `timeout`.
+pub fn describe(r: &PortResult, timeout: Duration) -> String {
+    let mut parts = vec![format!("{:?}", r.status).to_lowercase()];
+    if r.status == PortStatus::Open {
+        match r.banner_str() {
+            Some(banner) => {
+                parts.push(format!("banner {:?}", banner));
+                let service = [&r.service, &r.product, &r.version]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                parts.push(if service.is_empty() {
+                    "no service".to_string()
+                } else {
+                    service
+                });
+            }
+            None => parts.push("no banner".to_string()),
+        }
+        if r.reset_on_read {
+            parts.push("reset".to_string());
+        }
+    }
+    parts.push(if r.duration_ms < timeout.as_millis() {
+        "in time".to_string()
+    } else {
+        "timed out".to_string()
+    });
+    parts.join(", ")
+}
+
+/// Starts a listener for every case, scans them all at once with
+/// `timeout` for the connect and the banner read, and checks the results.
+pub async fn run(timeout: Duration) -> Result<Report, SelfTestError> {
+    let mut listeners = Vec::new();
+    for case in Case::ALL {
+        listeners.push(Listener::start(case).await?);
+    }
+    let ports: Vec<u16> = listeners.iter().map(|l| l.port).collect();
+    let scanner = Scanner::builder()
+        .target_addr(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        .ports(ports)
+        .timeout(timeout)
+        .banner_timeout(timeout)
+        .build()?;
+    let summary = scanner.scan_collect().await;
+    let checks = listeners
+        .iter()
+        .map(|l| Check {
+            case: l.case,
+            port: l.port,
+            expected: l.case.expected(),
+            found: summary
+                .results
+                .iter()
+                .find(|r| r.port == l.port)
+                .map_or_else(|| "no result".to_string(), |r| describe(r, timeout)),
+        })
+        .collect();
+    Ok(Report {
+        checks,
+        total_time_ms: summary.total_time_ms,
+    })
+}
+
+/// A case's listener, kept up until it is dropped.
+struct Listener {
+    case: Case,
+    port: u16,
+    task: Option<JoinHandle<()>>,
+    // The full accept queue of the never-accepting listener.
+    _held: Option<(socket2::Socket, StdStream)>,
+}
+
+impl Listener {
+    async fn start(case: Case) -> io::Result<Self> {
+        let loopback = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let mut listener = Listener {
+            case,
+            port: 0,
+            task: None,
+            _held: None,
+        };
+        match case {
+            Case::Unbound => {
+                // Nothing listens once the port is let go again.
+                listener.port = StdListener::bind(loopback)?.local_addr()?.port();
+            }
+            Case::NeverAccept => {
+                use socket2::{Domain, Socket, Type};
+                let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
+                socket.bind(&loopback.into())?;
+                socket.listen(0)?;
+                let addr = socket.local_addr()?.as_socket().expect("an IPv4 listener");
+                // One connection fills a queue of no backlog; it is never
+                // accepted, and later SYNs are dropped.
+                let filler = StdStream::connect(addr)?;
+                listener.port = addr.port();
+                listener._held = Some((socket, filler));
+            }
+            Case::Accept | Case::Banner | Case::Reset => {
+                let tcp = TcpListener::bind(loopback).await?;
+                listener.port = tcp.local_addr()?.port();
+                listener.task = Some(tokio::spawn(serve(tcp, case)));
+            }
+        }
+        Ok(listener)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, case: Case) {
+    let mut open = Vec::new();
+    while let Ok((mut stream, _)) = listener.accept().await {
+        match case {
+            Case::Banner => {
+                let _ = stream.write_all(format!("{}\r\n", BANNER).as_bytes()).await;
+            }
+            Case::Reset => {
+                let _ = stream.set_zero_linger();
+                continue;
+            }
+            _ => {}
+        }
+        // Held open until the listener is dropped, so the banner read
+        // sees silence rather than the end of the stream.
+        open.push(stream);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_are_described_as_the_cases_expect_them() {
+        let timeout = Duration::from_millis(500);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut ssh = PortResult::new(ip, 40022, PortStatus::Open);
+        ssh.banner = Some(BANNER.into());
+        ssh.service = Some("SSH".to_string());
+        ssh.product = Some("OpenSSH".to_string());
+        ssh.version = Some("9.6".to_string());
+        ssh.duration_ms = 3;
+        assert_eq!(describe(&ssh, timeout), Case::Banner.expected());
+
+        let mut reset = PortResult::new(ip, 40023, PortStatus::Open);
+        reset.reset_on_read = true;
+        assert_eq!(describe(&reset, timeout), Case::Reset.expected());
+
+        let mut filtered = PortResult::new(ip, 40024, PortStatus::Filtered);
+        filtered.duration_ms = 500;
+        assert_eq!(describe(&filtered, timeout), Case::NeverAccept.expected());
+        filtered.status = PortStatus::Closed;
+        assert_eq!(describe(&filtered, timeout), "closed, timed out");
+    }
+
+    #[test]
+    fn the_table_marks_each_check() {
+        let report = Report {
+            checks: vec![
+                Check {
+                    case: Case::Unbound,
+                    port: 40001,
+                    expected: Case::Unbound.expected(),
+                    found: "closed, in time".to_string(),
+                },
+                Check {
+                    case: Case::NeverAccept,
+                    port: 40002,
+                    expected: Case::NeverAccept.expected(),
+                    found: "closed, in time".to_string(),
+                },
+            ],
+            total_time_ms: 510,
+        };
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "Check          Port  Expected             Found            Result\n\
+             unbound port  40001  closed, in time      closed, in time  pass\n\
+             never accept  40002  filtered, timed out  closed, in time  FAIL\n\
+             1 of 2 checks passed in 510ms\n"
+        );
+    }
+}
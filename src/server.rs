@@ -0,0 +1,437 @@
+//! HTTP API for driving scans from other services (`serve`).
+//!
+//! - `POST /scans` submits a job; the body names `targets` and optionally
+//!   `ports` (a `--ports` string, a number or a list), `concurrency`,
+//!   `timeout_ms` and `banner_timeout_ms`. Answers `201` with the job.
+//! - `GET /scans/{id}` returns the status and the results so far, or the
+//!   full summary once the job has finished.
+//! - `GET /scans/{id}/events` streams the results as server-sent `result`
+//!   events, starting with those already collected, then a `done` event.
+//! - `DELETE /scans/{id}` cancels a queued or running job (`202`) or
+//!   forgets a finished one (`204`).
+//!
+//! At most `max_jobs` scans run at once; later jobs wait as `queued`.
+//! Finished jobs are kept in memory, oldest dropped first beyond `retain`.
+
+use axum::extract::{Path, Request, State};
+use axum::http::header::{AUTHORIZATION, LOCATION, WWW_AUTHENTICATE};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::connector::{Connector, TcpConnector};
+use crate::scanner::{new_scan_id, PortResult, ScanError, ScanSummary, Scanner};
+
+/// Results buffered per job for event-stream subscribers that fall behind.
+const EVENT_BUFFER: usize = 1024;
+
+/// Settings of the API server.
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// Jobs allowed to scan at the same time, at least one.
+    pub max_jobs: usize,
+    /// Finished jobs kept for `GET`, oldest dropped first.
+    pub retain: usize,
+    /// Required as `Authorization: Bearer <token>` on every request when set.
+    pub token: Option<String>,
+    /// Opens the connections of every job.
+    pub connector: Arc<dyn Connector>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_jobs: 4,
+            retain: 100,
+            token: None,
+            connector: Arc::new(TcpConnector::default()),
+        }
+    }
+}
+
+/// Serves the API on `listener` until `shutdown` is cancelled, then cancels
+/// the jobs still queued or running.
+pub async fn serve(
+    listener: TcpListener,
+    config: ServerConfig,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    let state = Arc::new(AppState::new(config));
+    let jobs = state.clone();
+    axum::serve(listener, routes(state))
+        .with_graceful_shutdown(async move {
+            shutdown.cancelled().await;
+            jobs.cancel_all();
+        })
+        .await
+}
+
+/// The API as a router, for embedding into another axum application.
+pub fn router(config: ServerConfig) -> Router {
+    routes(Arc::new(AppState::new(config)))
+}
+
+fn routes(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/scans", post(create))
+        .route("/scans/{id}", get(show).delete(remove))
+        .route("/scans/{id}/events", get(events))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state)
+}
+
+/// The body of `POST /scans`; unset fields take the scanner's defaults.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScanRequest {
+    targets: Vec<String>,
+    ports: Option<PortSpec>,
+    concurrency: Option<usize>,
+    timeout_ms: Option<u64>,
+    banner_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PortSpec {
+    Spec(String),
+    Single(u16),
+    List(Vec<u16>),
+}
+
+impl ScanRequest {
+    fn build(self, connector: Arc<dyn Connector>) -> Result<Scanner, ScanError> {
+        let mut builder = self
+            .targets
+            .iter()
+            .fold(Scanner::builder(), |b, t| b.target(t))
+            .connector(connector);
+        builder = match self.ports {
+            Some(PortSpec::Spec(s)) => builder.ports(&s),
+            Some(PortSpec::Single(p)) => builder.ports(p),
+            Some(PortSpec::List(list)) => builder.ports(list),
+            None => builder,
+        };
+        if let Some(concurrency) = self.concurrency {
+            builder = builder.concurrency(concurrency);
+        }
+        if let Some(ms) = self.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.banner_timeout_ms {
+            builder = builder.banner_timeout(Duration::from_millis(ms));
+        }
+        builder.build()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum JobEvent {
+    Result(Box<PortResult>),
+    Done(JobStatus),
+}
+
+struct Job {
+    status: JobStatus,
+    total_ports: u64,
+    /// Results so far; moved into `summary` when the job finishes.
+    results: Vec<PortResult>,
+    summary: Option<ScanSummary>,
+    cancel: CancellationToken,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl Job {
+    fn results(&self) -> &[PortResult] {
+        self.summary.as_ref().map_or(&self.results, |s| &s.results)
+    }
+
+    fn view(&self, id: &str) -> Value {
+        json!({
+            "id": id,
+            "status": self.status,
+            "total_ports": self.total_ports,
+            "scanned_ports": self.results().len(),
+            "results": self.summary.is_none().then_some(&self.results),
+            "summary": self.summary,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Jobs {
+    by_id: HashMap<String, Job>,
+    /// Finished job ids, oldest first.
+    finished: VecDeque<String>,
+}
+
+struct AppState {
+    token: Option<String>,
+    retain: usize,
+    connector: Arc<dyn Connector>,
+    slots: Arc<Semaphore>,
+    next_id: AtomicU64,
+    jobs: Mutex<Jobs>,
+}
+
+impl AppState {
+    fn new(config: ServerConfig) -> Self {
+        AppState {
+            token: config.token,
+            retain: config.retain,
+            connector: config.connector,
+            slots: Arc::new(Semaphore::new(config.max_jobs.max(1))),
+            next_id: AtomicU64::new(1),
+            jobs: Mutex::new(Jobs::default()),
+        }
+    }
+
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Jobs> {
+        self.jobs.lock().expect("job table lock poisoned")
+    }
+
+    fn with_job(&self, id: &str, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs().by_id.get_mut(id) {
+            f(job);
+        }
+    }
+
+    fn finish(&self, id: &str, summary: ScanSummary) {
+        let mut jobs = self.jobs();
+        let Some(job) = jobs.by_id.get_mut(id) else {
+            return;
+        };
+        job.status = if summary.interrupted {
+            JobStatus::Cancelled
+        } else {
+            JobStatus::Completed
+        };
+        job.summary = Some(summary);
+        let _ = job.events.send(JobEvent::Done(job.status));
+        jobs.finished.push_back(id.to_string());
+        while jobs.finished.len() > self.retain {
+            if let Some(old) = jobs.finished.pop_front() {
+                jobs.by_id.remove(&old);
+            }
+        }
+    }
+
+    fn cancel_all(&self) {
+        for job in self.jobs().by_id.values() {
+            job.cancel.cancel();
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+enum ApiError {
+    #[error("No scan with id {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    Scan(#[from] ScanError),
+    #[error("Missing or invalid bearer token")]
+    Unauthorized,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Scan(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+        };
+        let body = Json(json!({ "error": self.to_string() }));
+        match self {
+            ApiError::Unauthorized => {
+                (status, [(WWW_AUTHENTICATE, "Bearer")], body).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
+    }
+}
+
+async fn authorize(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    if let Some(token) = &state.token {
+        let given = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if given != Some(token.as_str()) {
+            return ApiError::Unauthorized.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+async fn create(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ScanRequest>,
+) -> Result<Response, ApiError> {
+    let scanner = request.build(state.connector.clone())?;
+    let id = format!(
+        "{}-{}",
+        new_scan_id(),
+        state.next_id.fetch_add(1, Ordering::Relaxed)
+    );
+    let job = Job {
+        status: JobStatus::Queued,
+        total_ports: scanner.total_ports(),
+        results: Vec::new(),
+        summary: None,
+        cancel: scanner.cancel_token(),
+        events: broadcast::channel(EVENT_BUFFER).0,
+    };
+    let view = job.view(&id);
+    state.jobs().by_id.insert(id.clone(), job);
+    info!(job = %id, ports = scanner.total_ports(), "scan job submitted");
+    tokio::spawn(run_job(state, id.clone(), scanner));
+
+    let location = format!("/scans/{}", id);
+    Ok((StatusCode::CREATED, [(LOCATION, location)], Json(view)).into_response())
+}
+
+/// Waits for a free slot, scans, and records the results as they arrive.
+async fn run_job(state: Arc<AppState>, id: String, scanner: Scanner) {
+    let cancel = scanner.cancel_token();
+    let label = scanner
+        .config()
+        .targets
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let metrics = scanner.metrics();
+    let start_time = Instant::now();
+
+    let slot = tokio::select! {
+        slot = state.slots.clone().acquire_owned() => Some(slot.expect("job semaphore is never closed")),
+        _ = cancel.cancelled() => None,
+    };
+    let mut results = Vec::new();
+    if slot.is_some() {
+        state.with_job(&id, |job| job.status = JobStatus::Running);
+        info!(job = %id, "scan job started");
+        let mut stream = scanner.scan();
+        while let Some(r) = stream.next().await {
+            state.with_job(&id, |job| {
+                let _ = job.events.send(JobEvent::Result(Box::new(r.clone())));
+                job.results.push(r);
+            });
+        }
+        state.with_job(&id, |job| results = std::mem::take(&mut job.results));
+    }
+
+    let total_time = start_time.elapsed().as_millis();
+    metrics.finish(total_time);
+    let summary = ScanSummary::from_results(
+        id.clone(),
+        label,
+        results,
+        total_time,
+        &metrics,
+        cancel.is_cancelled(),
+    );
+    info!(job = %id, open = summary.open_ports, interrupted = summary.interrupted, "scan job finished");
+    state.finish(&id, summary);
+}
+
+async fn show(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let jobs = state.jobs();
+    let job = jobs.by_id.get(&id).ok_or(ApiError::NotFound(id.clone()))?;
+    Ok(Json(job.view(&id)))
+}
+
+async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let mut jobs = state.jobs();
+    let job = jobs.by_id.get(&id).ok_or(ApiError::NotFound(id.clone()))?;
+    if !job.status.is_finished() {
+        job.cancel.cancel();
+        info!(job = %id, "scan job cancelled");
+        return Ok((StatusCode::ACCEPTED, Json(job.view(&id))).into_response());
+    }
+    jobs.by_id.remove(&id);
+    jobs.finished.retain(|f| *f != id);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    // Copy the backlog and subscribe under one lock so no result is missed
+    // or sent twice.
+    let (backlog, done, live) = {
+        let jobs = state.jobs();
+        let job = jobs.by_id.get(&id).ok_or(ApiError::NotFound(id.clone()))?;
+        let finished = job.status.is_finished();
+        (
+            job.results().to_vec(),
+            finished.then_some(job.status),
+            (!finished).then(|| job.events.subscribe()),
+        )
+    };
+
+    let backlog = stream::iter(backlog).map(|r| result_event(&r));
+    let done = stream::iter(done).map(done_event);
+    let live = stream::unfold(live, |rx| async move {
+        let mut rx = rx?;
+        let event = match rx.recv().await {
+            Ok(JobEvent::Result(r)) => result_event(&r),
+            Ok(JobEvent::Done(status)) => return Some((done_event(status), None)),
+            Err(RecvError::Lagged(missed)) => {
+                Ok(Event::default().event("lagged").data(missed.to_string()))
+            }
+            Err(RecvError::Closed) => return None,
+        };
+        Some((event, Some(rx)))
+    });
+    Ok(Sse::new(backlog.chain(done).chain(live)).keep_alive(KeepAlive::default()))
+}
+
+fn result_event(r: &PortResult) -> Result<Event, axum::Error> {
+    Event::default().event("result").json_data(r)
+}
+
+fn done_event(status: JobStatus) -> Result<Event, axum::Error> {
+    Event::default()
+        .event("done")
+        .json_data(json!({ "status": status }))
+}
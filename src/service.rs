@@ -0,0 +1,394 @@
+//! Service detection: what answers on an open port, read from its banner
+//! where possible and guessed from the well-known port otherwise.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+/// Well-known ports the service guess falls back to.
+static SERVICES: Lazy<HashMap<u16, &'static str>> = Lazy::new(|| {
+    HashMap::from([
+        (22, "SSH"),
+        (80, "HTTP"),
+        (443, "HTTPS"),
+        (21, "FTP"),
+        (25, "SMTP"),
+        (3306, "MySQL"),
+        (5432, "PostgreSQL"),
+        (3389, "RDP"),
+        (5900, "VNC"),
+    ])
+});
+
+/// A product recognized in banners.
+struct Product {
+    /// How the product names itself, compared case-insensitively.
+    token: &'static str,
+    /// Its display name.
+    name: &'static str,
+    /// The protocol it speaks.
+    service: &'static str,
+    /// `vendor:product` in the CPE dictionary.
+    cpe: &'static str,
+}
+
+const PRODUCTS: &[Product] = &[
+    Product {
+        token: "OpenSSH",
+        name: "OpenSSH",
+        service: "SSH",
+        cpe: "openbsd:openssh",
+    },
+    Product {
+        token: "dropbear",
+        name: "Dropbear sshd",
+        service: "SSH",
+        cpe: "dropbear_ssh_project:dropbear_ssh",
+    },
+    Product {
+        token: "nginx",
+        name: "nginx",
+        service: "HTTP",
+        cpe: "f5:nginx",
+    },
+    Product {
+        token: "Apache",
+        name: "Apache httpd",
+        service: "HTTP",
+        cpe: "apache:http_server",
+    },
+    Product {
+        token: "Microsoft-IIS",
+        name: "Microsoft IIS httpd",
+        service: "HTTP",
+        cpe: "microsoft:internet_information_services",
+    },
+    Product {
+        token: "lighttpd",
+        name: "lighttpd",
+        service: "HTTP",
+        cpe: "lighttpd:lighttpd",
+    },
+    Product {
+        token: "Caddy",
+        name: "Caddy",
+        service: "HTTP",
+        cpe: "caddyserver:caddy",
+    },
+    Product {
+        token: "vsFTPd",
+        name: "vsftpd",
+        service: "FTP",
+        cpe: "vsftpd_project:vsftpd",
+    },
+    Product {
+        token: "ProFTPD",
+        name: "ProFTPD",
+        service: "FTP",
+        cpe: "proftpd:proftpd",
+    },
+    Product {
+        token: "Pure-FTPd",
+        name: "Pure-FTPd",
+        service: "FTP",
+        cpe: "pureftpd:pure-ftpd",
+    },
+    Product {
+        token: "Postfix",
+        name: "Postfix",
+        service: "SMTP",
+        cpe: "postfix:postfix",
+    },
+    Product {
+        token: "Exim",
+        name: "Exim",
+        service: "SMTP",
+        cpe: "exim:exim",
+    },
+    Product {
+        token: "MySQL",
+        name: "MySQL",
+        service: "MySQL",
+        cpe: "oracle:mysql",
+    },
+    Product {
+        token: "MariaDB",
+        name: "MariaDB",
+        service: "MySQL",
+        cpe: "mariadb:mariadb",
+    },
+];
+
+/// What was learned about the service on a port.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceInfo {
+    /// Protocol name, such as `SSH` or `HTTP`.
+    pub service: Option<String>,
+    /// Software speaking it, such as `OpenSSH` or `nginx`.
+    pub product: Option<String>,
+    /// Version of `product`, such as `8.9p1`.
+    pub version: Option<String>,
+    /// Anything else the banner said about the product, such as the OS build.
+    pub extra_info: Option<String>,
+    /// CPE 2.3 name of a recognized product, for vulnerability matching.
+    pub cpe: Option<String>,
+}
+
+impl ServiceInfo {
+    pub(crate) fn new(service: &str) -> Self {
+        ServiceInfo {
+            service: Some(service.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Fills in the product from the name and version a banner gave.
+    pub(crate) fn with_product(
+        mut self,
+        name: &str,
+        version: Option<&str>,
+        extra: Option<&str>,
+    ) -> Self {
+        let known = PRODUCTS.iter().find(|p| p.token.eq_ignore_ascii_case(name));
+        let version = version.filter(|v| !v.is_empty());
+        self.product = Some(known.map_or(name, |p| p.name).to_string());
+        self.version = version.map(str::to_string);
+        self.extra_info = extra
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(str::to_string);
+        self.cpe = known.map(|p| {
+            format!(
+                "cpe:2.3:a:{}:{}:*:*:*:*:*:*:*",
+                p.cpe,
+                version.map_or("*".to_string(), cpe_escape)
+            )
+        });
+        self
+    }
+}
+
+/// Works out the service on `port` from its banner, falling back to the
+/// well-known port assignment for the protocol name.
+pub fn detect_service(port: u16, banner: Option<&str>) -> ServiceInfo {
+    detect_service_by(port, banner).0
+}
+
+/// [`detect_service`], with what told the service: the banner rule that
+/// matched, such as `SSH identification`, or `well-known port`; none when
+/// nothing did.
+pub fn detect_service_by(port: u16, banner: Option<&str>) -> (ServiceInfo, Option<&'static str>) {
+    let (mut info, mut basis) = banner.map(from_banner).unwrap_or_default();
+    if info.service.is_none() {
+        info.service = SERVICES.get(&port).map(|s| s.to_string());
+        basis = info.service.as_ref().map(|_| "well-known port");
+    }
+    (info, basis)
+}
+
+fn from_banner(banner: &str) -> (ServiceInfo, Option<&'static str>) {
+    if let Some(at) = banner.find("SSH-") {
+        let info = ssh(banner[at..].lines().next().unwrap_or(""));
+        return (info, Some("SSH identification"));
+    }
+    if banner.contains("HTTP/") || banner.contains("Server:") {
+        return (http(banner), Some("HTTP response"));
+    }
+    if banner.starts_with("220 ") || banner.starts_with("220-") {
+        let info = greeting(banner.lines().next().unwrap_or("")[4..].trim());
+        return (info, Some("FTP/SMTP greeting"));
+    }
+    (ServiceInfo::default(), None)
+}
+
+/// `SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1`: software `name_version`, then
+/// free-form comments.
+fn ssh(line: &str) -> ServiceInfo {
+    let info = ServiceInfo::new("SSH");
+    let Some(software) = line.splitn(3, '-').nth(2) else {
+        return info;
+    };
+    let (software, comments) = software.split_once(' ').unwrap_or((software, ""));
+    if software.is_empty() {
+        return info;
+    }
+    let (name, version) = match software.split_once('_') {
+        Some((name, version)) => (name, Some(version)),
+        None => (software, None),
+    };
+    info.with_product(name, version, Some(comments))
+}
+
+/// `Server: nginx/1.18.0 (Ubuntu)`: product `name/version`, then details.
+fn http(banner: &str) -> ServiceInfo {
+    let info = ServiceInfo::new("HTTP");
+    let server = banner.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("server")
+            .then(|| value.trim())
+    });
+    let Some(server) = server.filter(|s| !s.is_empty()) else {
+        return info;
+    };
+    let (software, details) = server.split_once(' ').unwrap_or((server, ""));
+    let (name, version) = match software.split_once('/') {
+        Some((name, version)) => (name, Some(version)),
+        None => (software, None),
+    };
+    info.with_product(name, version, Some(unwrap_parens(details.trim())))
+}
+
+/// An FTP or SMTP greeting such as `(vsFTPd 3.0.3)` or
+/// `mail.example.com ESMTP Postfix (Ubuntu)`: a known product name anywhere,
+/// its version right after it.
+fn greeting(text: &str) -> ServiceInfo {
+    let words: Vec<&str> = text
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|w| !w.is_empty())
+        .collect();
+    let known = words.iter().enumerate().find_map(|(i, w)| {
+        PRODUCTS
+            .iter()
+            .find(|p| p.token.eq_ignore_ascii_case(w))
+            .map(|p| (i, p))
+    });
+    if let Some((i, product)) = known {
+        let version = words
+            .get(i + 1)
+            .copied()
+            .filter(|v| v.starts_with(|c: char| c.is_ascii_digit()));
+        return ServiceInfo::new(product.service).with_product(product.token, version, None);
+    }
+    let upper = text.to_ascii_uppercase();
+    if upper.contains("FTP") {
+        ServiceInfo::new("FTP")
+    } else if upper.contains("SMTP") {
+        ServiceInfo::new("SMTP")
+    } else {
+        ServiceInfo::new("SMTP/FTP")
+    }
+}
+
+/// `(Ubuntu)` without its parentheses; anything else as is.
+fn unwrap_parens(s: &str) -> &str {
+    match s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        Some(inner) if !inner.contains(['(', ')']) => inner,
+        _ => s,
+    }
+}
+
+/// Escapes a value for a CPE 2.3 formatted string.
+pub(crate) fn cpe_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(banner: &str) -> ServiceInfo {
+        detect_service(0, Some(banner))
+    }
+
+    #[test]
+    fn banners_yield_product_version_and_cpe() {
+        assert_eq!(
+            detect("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1\r\n"),
+            ServiceInfo {
+                service: Some("SSH".into()),
+                product: Some("OpenSSH".into()),
+                version: Some("8.9p1".into()),
+                extra_info: Some("Ubuntu-3ubuntu0.1".into()),
+                cpe: Some("cpe:2.3:a:openbsd:openssh:8.9p1:*:*:*:*:*:*:*".into()),
+            }
+        );
+
+        let nginx = detect("HTTP/1.1 400 Bad Request\r\nServer: nginx/1.18.0 (Ubuntu)\r\n");
+        assert_eq!(nginx.service.as_deref(), Some("HTTP"));
+        assert_eq!(nginx.product.as_deref(), Some("nginx"));
+        assert_eq!(nginx.version.as_deref(), Some("1.18.0"));
+        assert_eq!(nginx.extra_info.as_deref(), Some("Ubuntu"));
+
+        let apache = detect("HTTP/1.1 200 OK\r\nserver: Apache/2.4.41 (Ubuntu) OpenSSL/1.1.1\r\n");
+        assert_eq!(apache.product.as_deref(), Some("Apache httpd"));
+        assert_eq!(apache.extra_info.as_deref(), Some("(Ubuntu) OpenSSL/1.1.1"));
+        assert_eq!(
+            apache.cpe.as_deref(),
+            Some("cpe:2.3:a:apache:http_server:2.4.41:*:*:*:*:*:*:*")
+        );
+
+        let ftp = detect("220 (vsFTPd 3.0.3)\r\n");
+        assert_eq!(ftp.service.as_deref(), Some("FTP"));
+        assert_eq!(
+            (ftp.product.as_deref(), ftp.version.as_deref()),
+            (Some("vsftpd"), Some("3.0.3"))
+        );
+
+        let smtp = detect("220 mail.example.com ESMTP Postfix (Ubuntu)\r\n");
+        assert_eq!(smtp.service.as_deref(), Some("SMTP"));
+        assert_eq!(smtp.product.as_deref(), Some("Postfix"));
+        assert_eq!(
+            smtp.cpe.as_deref(),
+            Some("cpe:2.3:a:postfix:postfix:*:*:*:*:*:*:*:*")
+        );
+    }
+
+    #[test]
+    fn unknown_software_keeps_its_own_name_without_cpe() {
+        let fake = detect("HTTP/1.1 408 Request Timeout\r\nServer: fake\r\n");
+        assert_eq!(fake.product.as_deref(), Some("fake"));
+        assert_eq!((fake.version, fake.cpe), (None, None));
+
+        let ssh = detect("SSH-2.0-Fake_1.0\r\n");
+        assert_eq!(
+            (
+                ssh.product.as_deref(),
+                ssh.version.as_deref(),
+                ssh.extra_info
+            ),
+            (Some("Fake"), Some("1.0"), None)
+        );
+
+        assert_eq!(detect("220 ready\r\n").service.as_deref(), Some("SMTP/FTP"));
+        assert_eq!(detect("HTTP/1.0 200 OK\r\n").product, None);
+        assert_eq!(detect("SSH-2.0-\r\n").product, None);
+    }
+
+    #[test]
+    fn silent_ports_fall_back_to_the_well_known_service() {
+        assert_eq!(detect_service(443, None), ServiceInfo::new("HTTPS"));
+        assert_eq!(detect_service(22, Some("garbage")), ServiceInfo::new("SSH"));
+        assert_eq!(detect_service(1, None), ServiceInfo::default());
+    }
+
+    #[test]
+    fn the_basis_names_the_rule_that_matched() {
+        let basis = |port, banner| detect_service_by(port, banner).1;
+        assert_eq!(
+            basis(2222, Some("SSH-2.0-dropbear\r\n")),
+            Some("SSH identification")
+        );
+        assert_eq!(
+            basis(8080, Some("HTTP/1.1 200 OK\r\n")),
+            Some("HTTP response")
+        );
+        assert_eq!(
+            basis(21, Some("220 (vsFTPd 3.0.3)")),
+            Some("FTP/SMTP greeting")
+        );
+        assert_eq!(basis(22, Some("garbage")), Some("well-known port"));
+        assert_eq!(basis(1, Some("garbage")), None);
+    }
+
+    #[test]
+    fn cpe_values_are_escaped() {
+        assert_eq!(cpe_escape("1.0:beta+1"), "1.0\\:beta\\+1");
+    }
+}
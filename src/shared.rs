@@ -0,0 +1,227 @@
+//! Responses many open ports of a host answer with alike
+//! (`--shared-responses`).
+//!
+//! A transparent proxy or captive portal in the path answers every port it
+//! intercepts with the same page, so a scan through one finds thousands of
+//! open ports with the same 4 KiB banner. Once more than the threshold of
+//! open ports of one host send byte-identical banners, the banner is kept
+//! once in the JSON report's `shared_responses` table, under a key made
+//! from its hash, and each of those ports names the key as its
+//! `shared_response` instead of carrying a copy. The host is flagged with
+//! `uniform_responses`, as a probable middlebox.
+//!
+//! Only the JSON report is shortened: results keep their banners in
+//! memory, and every other report shows them in full. Reports read back
+//! (`--from`, `--baseline`, `history`, `reanalyze`) have the banners put
+//! back with [`ScanSummary::expand_shared_responses`].
+
+use serde::{Deserialize, Serialize};
+use std::borrow::{Borrow, Cow};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+
+use crate::banner::Banner;
+use crate::hash::fnv1a64;
+use crate::scanner::{PortResult, PortStatus, ScanSummary};
+
+/// Default for `--shared-responses`: more identical banners than this on a
+/// host are shared.
+pub const DEFAULT_THRESHOLD: usize = 16;
+
+/// A banner that many ports of a host answered with, stored once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedResponse {
+    /// The banner.
+    pub banner: Banner,
+    /// The hosts whose ports share it.
+    pub hosts: BTreeSet<IpAddr>,
+    /// How many ports share it, over every host.
+    pub ports: usize,
+}
+
+/// The key of `banner` in the table: its 64-bit FNV-1a hash, as 16
+/// lowercase hex digits.
+pub fn key(banner: &Banner) -> String {
+    format!("{:016x}", fnv1a64(banner.as_bytes()))
+}
+
+/// The banners more than `threshold` open ports of one host answered with,
+/// by key; none with a threshold of 0.
+pub fn share<R: Borrow<PortResult>>(
+    results: impl IntoIterator<Item = R>,
+    threshold: usize,
+) -> BTreeMap<String, SharedResponse> {
+    let mut table: BTreeMap<String, SharedResponse> = BTreeMap::new();
+    if threshold == 0 {
+        return table;
+    }
+    let mut alike: HashMap<(IpAddr, Banner), usize> = HashMap::new();
+    for r in results {
+        let r = r.borrow();
+        match &r.banner {
+            Some(banner) if r.status == PortStatus::Open && !banner.is_empty() => {
+                *alike.entry((r.target, banner.clone())).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+    let mut shared: Vec<((IpAddr, Banner), usize)> = alike
+        .into_iter()
+        .filter(|&(_, ports)| ports > threshold)
+        .collect();
+    shared.sort_by_key(|&((host, _), _)| host);
+    for ((host, banner), ports) in shared {
+        let entry = table.entry(key(&banner)).or_insert_with(|| SharedResponse {
+            banner: banner.clone(),
+            hosts: BTreeSet::new(),
+            ports: 0,
+        });
+        // Two banners with one hash: the first keeps the key.
+        if entry.banner == banner {
+            entry.hosts.insert(host);
+            entry.ports += ports;
+        }
+    }
+    table
+}
+
+/// The shares of `table` the open ports of `target` among `results` have,
+/// counting only those ports.
+pub fn for_host(
+    table: &BTreeMap<String, SharedResponse>,
+    target: IpAddr,
+    results: &[PortResult],
+) -> BTreeMap<String, SharedResponse> {
+    table
+        .iter()
+        .filter(|(_, shared)| shared.hosts.contains(&target))
+        .map(|(key, shared)| {
+            let ports = results
+                .iter()
+                .filter(|r| r.target == target && r.status == PortStatus::Open)
+                .filter(|r| r.banner.as_ref() == Some(&shared.banner))
+                .count();
+            let shared = SharedResponse {
+                banner: shared.banner.clone(),
+                hosts: BTreeSet::from([target]),
+                ports,
+            };
+            (key.clone(), shared)
+        })
+        .collect()
+}
+
+impl ScanSummary {
+    /// `r` as the JSON report has it: without its banner, naming the
+    /// shared response instead, when its host shares the banner.
+    pub fn shared_view<'a>(&self, r: &'a PortResult) -> Cow<'a, PortResult> {
+        if self.shared_responses.is_empty() || r.shared_response.is_some() {
+            return Cow::Borrowed(r);
+        }
+        let Some(banner) = r.banner.as_ref().filter(|_| r.status == PortStatus::Open) else {
+            return Cow::Borrowed(r);
+        };
+        let key = key(banner);
+        match self.shared_responses.get(&key) {
+            Some(shared) if shared.hosts.contains(&r.target) && shared.banner == *banner => {
+                let mut r = r.clone();
+                r.banner = None;
+                r.shared_response = Some(key);
+                Cow::Owned(r)
+            }
+            _ => Cow::Borrowed(r),
+        }
+    }
+
+    /// Puts the shared banners of a report read back into the results that
+    /// name them.
+    pub fn expand_shared_responses(&mut self) {
+        for r in &mut self.results {
+            let shared = r
+                .shared_response
+                .take()
+                .and_then(|key| self.shared_responses.get(&key));
+            if let Some(shared) = shared {
+                r.banner.get_or_insert_with(|| shared.banner.clone());
+            }
+        }
+    }
+
+    /// The hosts that answered many ports with one banner.
+    pub fn uniform_hosts(&self) -> BTreeSet<IpAddr> {
+        self.shared_responses
+            .values()
+            .flat_map(|s| s.hosts.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORTAL: &str = "HTTP/1.1 200 OK\r\n\r\n<html>Sign in to the guest network</html>";
+
+    fn open(target: &str, port: u16, banner: &str) -> PortResult {
+        let mut r = PortResult::new(target.parse().unwrap(), port, PortStatus::Open);
+        r.banner = Some(Banner::from(banner));
+        r
+    }
+
+    #[test]
+    fn banners_past_the_threshold_on_one_host_are_shared() {
+        let mut results: Vec<PortResult> = (1..=4).map(|p| open("10.0.0.5", p, PORTAL)).collect();
+        results.push(open("10.0.0.5", 22, "SSH-2.0-OpenSSH_9.6"));
+        // Three on another host, at the threshold: not shared there.
+        results.extend((1..=3).map(|p| open("10.0.0.6", p, PORTAL)));
+
+        assert!(share(&results, 0).is_empty());
+        let table = share(&results, 3);
+        assert_eq!(table.len(), 1);
+        let (key, shared) = table.iter().next().unwrap();
+        assert_eq!(*key, super::key(&Banner::from(PORTAL)));
+        assert_eq!(key.len(), 16);
+        assert_eq!(shared.banner, Banner::from(PORTAL));
+        assert_eq!(shared.ports, 4);
+        assert_eq!(
+            shared.hosts,
+            BTreeSet::from(["10.0.0.5".parse::<IpAddr>().unwrap()])
+        );
+    }
+
+    #[test]
+    fn the_json_report_names_the_shared_response_and_reads_back_whole() {
+        let mut results: Vec<PortResult> = (1..=3).map(|p| open("10.0.0.5", p, PORTAL)).collect();
+        results.push(open("10.0.0.6", 1, PORTAL));
+        let mut summary = ScanSummary::from_results(
+            "shared-test".to_string(),
+            "10.0.0.0/24".to_string(),
+            results,
+            0,
+            &crate::metrics::ScanMetrics::new("10.0.0.5"),
+            false,
+        );
+        summary.shared_responses = share(&summary.results, 2);
+        assert_eq!(summary.uniform_hosts().len(), 1);
+
+        let json = serde_json::to_value(&summary).unwrap();
+        let key = key(&Banner::from(PORTAL));
+        assert_eq!(json["shared_responses"][&key]["banner"], PORTAL);
+        assert_eq!(json["shared_responses"][&key]["ports"], 3);
+        assert_eq!(json["results"][0]["shared_response"], key.as_str());
+        assert!(json["results"][0]["banner"].is_null());
+        // The other host's port keeps its own copy.
+        assert_eq!(json["results"][3]["banner"], PORTAL);
+        assert!(json["results"][3].get("shared_response").is_none());
+
+        let mut read: ScanSummary = serde_json::from_value(json).unwrap();
+        read.expand_shared_responses();
+        let banners: Vec<Option<String>> = read
+            .results
+            .iter()
+            .map(|r| r.banner.as_ref().map(|b| b.to_string()))
+            .collect();
+        assert_eq!(banners, vec![Some(PORTAL.to_string()); 4]);
+        assert!(read.results.iter().all(|r| r.shared_response.is_none()));
+    }
+}
@@ -0,0 +1,554 @@
+//! Telling the protocol on a port from the first bytes it sends (`--sniff`).
+//!
+//! A port that speaks first is matched against [`SIGNATURES`], a table of
+//! prefixes and byte layouts such as `SSH-`, a `220` greeting, `RFB`, a TLS
+//! record or a MySQL greeting, and the first that fits reads the service,
+//! product and version from the bytes. A port that stays silent is asked
+//! by the [`Rung`]s of a ladder in turn, by default a TLS ClientHello, an
+//! HTTP GET and a bare CRLF, until one gets an answer a signature knows or
+//! the probe budget runs out. The signature, and the rung that drew the
+//! answer, are in the evidence, so detection on odd ports no longer rests
+//! on the port number alone.
+
+use bytes::BytesMut;
+use futures::future::BoxFuture;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+
+use crate::banner::{read_banner, Banner};
+use crate::connector::Connection;
+use crate::probe::{http_get, read_head, Probe, Probed, Transport};
+use crate::scanner::{PortResult, PortStatus};
+use crate::service::{detect_service, ServiceInfo};
+
+/// A protocol recognized by the first bytes it sends.
+pub struct Signature {
+    /// What the bytes are, for the evidence, such as `MySQL greeting`.
+    pub name: &'static str,
+    /// Whether `bytes` start the way the protocol does.
+    matches: fn(&[u8]) -> bool,
+    /// Reads what the bytes say about the service.
+    identify: fn(&[u8]) -> ServiceInfo,
+}
+
+/// The signatures [`sniff`] tries, in order.
+pub static SIGNATURES: &[Signature] = &[
+    Signature {
+        name: "SSH identification",
+        matches: |b| b.starts_with(b"SSH-"),
+        identify: text,
+    },
+    Signature {
+        name: "HTTP response",
+        matches: |b| b.starts_with(b"HTTP/"),
+        identify: text,
+    },
+    Signature {
+        name: "FTP/SMTP greeting",
+        matches: |b| b.starts_with(b"220 ") || b.starts_with(b"220-"),
+        identify: text,
+    },
+    Signature {
+        name: "RFB handshake",
+        matches: |b| rfb_version(b).is_some(),
+        identify: rfb,
+    },
+    Signature {
+        name: "TLS record",
+        matches: is_tls,
+        identify: |_| ServiceInfo::new("TLS"),
+    },
+    Signature {
+        name: "MySQL greeting",
+        matches: |b| mysql_version(b).is_some(),
+        identify: mysql,
+    },
+    Signature {
+        name: "HTTP request",
+        matches: is_http_request,
+        identify: http_client,
+    },
+];
+
+/// The first of [`SIGNATURES`] that `bytes` fit, if any.
+pub fn sniff(bytes: &[u8]) -> Option<&'static Signature> {
+    SIGNATURES.iter().find(|s| (s.matches)(bytes))
+}
+
+/// What `bytes` say about the service, with the name of the signature
+/// that read it; none when no signature fits.
+pub fn identify(bytes: &[u8]) -> Option<(ServiceInfo, &'static str)> {
+    sniff(bytes).map(|s| ((s.identify)(bytes), s.name))
+}
+
+/// Text protocols [`crate::service`] already reads.
+fn text(bytes: &[u8]) -> ServiceInfo {
+    detect_service(0, Some(&String::from_utf8_lossy(bytes)))
+}
+
+/// `RFB 003.008\n`: the protocol version a VNC server offers.
+fn rfb_version(bytes: &[u8]) -> Option<(u32, u32)> {
+    let line = bytes.get(..11)?.strip_prefix(b"RFB ")?;
+    let line = std::str::from_utf8(line).ok()?;
+    let (major, minor) = line.split_once('.')?;
+    if major.len() != 3 || minor.len() != 3 {
+        return None;
+    }
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+fn rfb(bytes: &[u8]) -> ServiceInfo {
+    let mut info = ServiceInfo::new("VNC");
+    info.extra_info = rfb_version(bytes).map(|(major, minor)| format!("RFB {}.{}", major, minor));
+    info
+}
+
+/// A TLS handshake or alert record: what a TLS server sends, whether it
+/// speaks first or answers a ClientHello.
+fn is_tls(bytes: &[u8]) -> bool {
+    matches!(bytes, [0x15 | 0x16, 0x03, 0..=0x04, _, _, ..])
+}
+
+/// The server version of a MySQL handshake: a packet of sequence 0 and
+/// protocol 10 whose header length covers the bytes read, then the version
+/// as a NUL-terminated string starting with a digit.
+fn mysql_version(bytes: &[u8]) -> Option<&str> {
+    let [l0, l1, l2, 0, 0x0a, rest @ ..] = bytes else {
+        return None;
+    };
+    let len = u32::from_le_bytes([*l0, *l1, *l2, 0]) as usize;
+    if len == 0 || bytes.len() > len + 4 {
+        return None;
+    }
+    let end = rest.iter().position(|&b| b == 0)?;
+    let version = std::str::from_utf8(&rest[..end]).ok()?;
+    let printable = version.bytes().all(|b| b.is_ascii_graphic());
+    (version.starts_with(|c: char| c.is_ascii_digit()) && printable).then_some(version)
+}
+
+/// `8.0.36`, `5.7.33-0ubuntu0.18.04.1`, or MariaDB's
+/// `5.5.5-10.6.12-MariaDB-0ubuntu0.22.04.1`, whose `5.5.5-` older clients
+/// need and newer releases leave out.
+fn mysql(bytes: &[u8]) -> ServiceInfo {
+    let info = ServiceInfo::new("MySQL");
+    let Some(version) = mysql_version(bytes) else {
+        return info;
+    };
+    let version = version.strip_prefix("5.5.5-").unwrap_or(version);
+    if let Some((version, extra)) = version.split_once("-MariaDB") {
+        return info.with_product(
+            "MariaDB",
+            Some(version),
+            Some(extra.trim_start_matches('-')),
+        );
+    }
+    let (version, extra) = version.split_once('-').unwrap_or((version, ""));
+    info.with_product("MySQL", Some(version), Some(extra))
+}
+
+/// Methods a client that connects back to the scanner, or a port that
+/// relays one, opens an HTTP request with.
+const HTTP_METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "CONNECT", "PATCH",
+];
+
+/// `GET /path HTTP/1.1`: a request line rather than a response.
+fn is_http_request(bytes: &[u8]) -> bool {
+    let line = bytes.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut parts = line.trim_end().split(' ');
+    let (Some(method), Some(_), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    HTTP_METHODS.contains(&method) && version.starts_with("HTTP/")
+}
+
+/// An HTTP client, its product from `User-Agent: curl/8.5.0`.
+fn http_client(bytes: &[u8]) -> ServiceInfo {
+    let info = ServiceInfo::new("HTTP client");
+    let text = String::from_utf8_lossy(bytes);
+    let agent = text.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("user-agent")
+            .then(|| value.trim())
+    });
+    let Some(agent) = agent.filter(|a| !a.is_empty()) else {
+        return info;
+    };
+    let (software, details) = agent.split_once(' ').unwrap_or((agent, ""));
+    let (name, version) = match software.split_once('/') {
+        Some((name, version)) => (name, Some(version)),
+        None => (software, None),
+    };
+    info.with_product(name, version, Some(details))
+}
+
+/// A step of the ladder silent open ports are asked in turn with
+/// `--sniff`: each sends its request and matches the answer against
+/// [`SIGNATURES`], until one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rung {
+    /// A TLS 1.2 ClientHello; any handshake or alert in answer is TLS.
+    Tls,
+    /// An HTTP GET of `/`.
+    Http,
+    /// A bare CRLF, which line-based services answer with an error or a
+    /// prompt.
+    Crlf,
+}
+
+/// The ladder without `--probe-ladder`.
+pub const DEFAULT_LADDER: [Rung; 3] = [Rung::Tls, Rung::Http, Rung::Crlf];
+
+impl Rung {
+    const ALL: [Rung; 3] = [Rung::Tls, Rung::Http, Rung::Crlf];
+
+    /// The rung's name on the command line, such as `tls`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rung::Tls => "tls",
+            Rung::Http => "http",
+            Rung::Crlf => "crlf",
+        }
+    }
+}
+
+impl fmt::Display for Rung {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Rung {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Rung::ALL
+            .into_iter()
+            .find(|r| r.as_str() == name)
+            .ok_or_else(|| format!("unknown rung '{}' (valid rungs: tls, http, crlf)", s.trim()))
+    }
+}
+
+impl Probe for Rung {
+    fn name(&self) -> &'static str {
+        match self {
+            Rung::Tls => "ladder tls",
+            Rung::Http => "ladder http",
+            Rung::Crlf => "ladder crlf",
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        self.as_str()
+    }
+
+    fn transport(&self) -> Transport {
+        match self {
+            // A ClientHello has to open the conversation.
+            Rung::Tls => Transport::Fresh,
+            Rung::Http | Rung::Crlf => Transport::Shared,
+        }
+    }
+
+    fn applies(&self, r: &PortResult) -> bool {
+        r.status == PortStatus::Open && r.banner.is_none() && !r.reset_on_read
+    }
+
+    fn run<'a>(
+        &'a self,
+        stream: &'a mut Connection,
+        r: &'a mut PortResult,
+    ) -> BoxFuture<'a, io::Result<Probed>> {
+        Box::pin(async move {
+            let answer = match self {
+                Rung::Tls => {
+                    stream.write_all(&client_hello()).await?;
+                    read_once(stream).await?
+                }
+                Rung::Http => {
+                    stream.write_all(http_get(r).as_bytes()).await?;
+                    read_head(stream).await?
+                }
+                Rung::Crlf => {
+                    stream.write_all(b"\r\n\r\n").await?;
+                    read_once(stream).await?
+                }
+            };
+            // What the rung sent may have left the service anywhere, so
+            // the connection goes no further.
+            let Some((info, signature)) = identify(&answer) else {
+                return Ok(Probed {
+                    found: None,
+                    keep: false,
+                });
+            };
+            r.service = info.service;
+            r.product = info.product;
+            r.version = info.version;
+            r.extra_info = info.extra_info;
+            r.cpe = info.cpe;
+            r.banner = Some(Banner::from(bytes::Bytes::from(answer)));
+            Ok(Probed {
+                found: Some(signature.to_string()),
+                keep: false,
+            })
+        })
+    }
+}
+
+/// One read of at most [`crate::banner::BANNER_CAP`] bytes, trailing
+/// whitespace trimmed; nothing when the port closed without a word.
+async fn read_once(stream: &mut Connection) -> io::Result<Vec<u8>> {
+    let mut buf = BytesMut::new();
+    Ok(read_banner(stream, &mut buf)
+        .await?
+        .map(|b| b.as_bytes().to_vec())
+        .unwrap_or_default())
+}
+
+/// A TLS 1.2 ClientHello without a server name, offering the common
+/// ECDHE and RSA suites over the P-256 and X25519 curves. Its random is
+/// all zeros: the handshake never gets far enough for it to matter.
+fn client_hello() -> Vec<u8> {
+    const SUITES: [u16; 5] = [0xc02f, 0xc02b, 0xc030, 0x009c, 0x002f];
+    let extensions: &[(u16, &[u8])] = &[
+        // supported_groups: x25519, secp256r1.
+        (0x000a, &[0x00, 0x04, 0x00, 0x1d, 0x00, 0x17]),
+        // ec_point_formats: uncompressed.
+        (0x000b, &[0x01, 0x00]),
+        // signature_algorithms: rsa_pkcs1_sha256, rsa_pss_rsae_sha256,
+        // ecdsa_secp256r1_sha256.
+        (0x000d, &[0x00, 0x06, 0x04, 0x01, 0x08, 0x04, 0x04, 0x03]),
+    ];
+
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0; 32]);
+    body.push(0);
+    body.extend_from_slice(&(SUITES.len() as u16 * 2).to_be_bytes());
+    for suite in SUITES {
+        body.extend_from_slice(&suite.to_be_bytes());
+    }
+    body.extend_from_slice(&[0x01, 0x00]);
+    let mut ext = Vec::new();
+    for (kind, data) in extensions {
+        ext.extend_from_slice(&kind.to_be_bytes());
+        ext.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        ext.extend_from_slice(data);
+    }
+    body.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+    body.extend_from_slice(&ext);
+
+    let mut handshake = vec![0x01];
+    handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&body);
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// A MySQL handshake packet announcing `version`.
+    fn mysql_greeting(version: &str) -> Vec<u8> {
+        let mut payload = vec![0x0a];
+        payload.extend_from_slice(version.as_bytes());
+        payload.push(0);
+        payload.extend_from_slice(&[0x2a, 0, 0, 0]);
+        payload.extend_from_slice(b"abcdefgh\0");
+        payload.extend_from_slice(&[0xff, 0xf7, 0x08, 0x02, 0x00, 0xff, 0x81, 0x15]);
+        payload.extend_from_slice(&[0; 10]);
+        payload.extend_from_slice(b"ijklmnopqrst\0mysql_native_password\0");
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(0);
+        packet.extend_from_slice(&payload);
+        packet
+    }
+
+    fn matched(bytes: &[u8]) -> Option<&'static str> {
+        sniff(bytes).map(|s| s.name)
+    }
+
+    #[test]
+    fn each_signature_knows_its_protocol_by_the_first_bytes() {
+        let fixtures: &[(&[u8], &str)] = &[
+            (b"SSH-2.0-OpenSSH_9.6\r\n", "SSH identification"),
+            (b"HTTP/1.1 400 Bad Request\r\n", "HTTP response"),
+            (b"220 (vsFTPd 3.0.3)\r\n", "FTP/SMTP greeting"),
+            (b"220-mail.example.com ESMTP\r\n", "FTP/SMTP greeting"),
+            (b"RFB 003.008\n", "RFB handshake"),
+            (&[0x16, 0x03, 0x03, 0x00, 0x31, 0x02], "TLS record"),
+            (&[0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x28], "TLS record"),
+            (b"GET /callback HTTP/1.1\r\nHost: x\r\n\r\n", "HTTP request"),
+            (b"CONNECT example.com:443 HTTP/1.1\r\n", "HTTP request"),
+        ];
+        for (bytes, name) in fixtures {
+            assert_eq!(matched(bytes), Some(*name), "{:?}", bytes);
+        }
+        assert_eq!(matched(&mysql_greeting("8.0.36")), Some("MySQL greeting"));
+    }
+
+    #[test]
+    fn near_misses_match_nothing() {
+        let fixtures: &[&[u8]] = &[
+            b"",
+            b"garbage",
+            b"ssh-2.0-lowercase",
+            b"2200 not a greeting",
+            b"RFB 3.8\n",
+            b"RFB abc.def\n",
+            &[0x16, 0x03],
+            &[0x17, 0x03, 0x03, 0x00, 0x10],
+            &[0x16, 0x02, 0x00, 0x00, 0x10],
+            b"GET / HTTP/1.1 extra\r\n",
+            b"FETCH / HTTP/1.1\r\n",
+            b"GET /\r\n",
+            // A MySQL layout whose header is shorter than the bytes read.
+            &[0x01, 0x00, 0x00, 0x00, 0x0a, b'8', 0x00, 0x01],
+            // Sequence 1 is not a greeting.
+            &[0x05, 0x00, 0x00, 0x01, 0x0a, b'8', 0x00],
+            // Neither is a version that doesn't start with a digit.
+            &[0x05, 0x00, 0x00, 0x00, 0x0a, b'x', 0x00],
+        ];
+        for bytes in fixtures {
+            assert_eq!(matched(bytes), None, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn signatures_read_product_and_version() {
+        let info = |bytes: &[u8]| identify(bytes).unwrap().0;
+
+        let mysql = info(&mysql_greeting("5.7.33-0ubuntu0.18.04.1"));
+        assert_eq!(mysql.service.as_deref(), Some("MySQL"));
+        assert_eq!(
+            (mysql.product.as_deref(), mysql.version.as_deref()),
+            (Some("MySQL"), Some("5.7.33"))
+        );
+        assert_eq!(mysql.extra_info.as_deref(), Some("0ubuntu0.18.04.1"));
+        assert_eq!(
+            mysql.cpe.as_deref(),
+            Some("cpe:2.3:a:oracle:mysql:5.7.33:*:*:*:*:*:*:*")
+        );
+
+        let maria = info(&mysql_greeting("5.5.5-10.6.12-MariaDB-0ubuntu0.22.04.1"));
+        assert_eq!(
+            (maria.product.as_deref(), maria.version.as_deref()),
+            (Some("MariaDB"), Some("10.6.12"))
+        );
+        assert_eq!(maria.extra_info.as_deref(), Some("0ubuntu0.22.04.1"));
+        let maria = info(&mysql_greeting("11.2.2-MariaDB"));
+        assert_eq!(
+            (maria.version.as_deref(), maria.extra_info),
+            (Some("11.2.2"), None)
+        );
+
+        let vnc = info(b"RFB 003.008\n");
+        assert_eq!(vnc.service.as_deref(), Some("VNC"));
+        assert_eq!(vnc.extra_info.as_deref(), Some("RFB 3.8"));
+
+        assert_eq!(
+            info(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x46]),
+            ServiceInfo::new("TLS")
+        );
+
+        let client = info(b"POST /hook HTTP/1.1\r\nUser-Agent: curl/8.5.0\r\n\r\n");
+        assert_eq!(client.service.as_deref(), Some("HTTP client"));
+        assert_eq!(
+            (client.product.as_deref(), client.version.as_deref()),
+            (Some("curl"), Some("8.5.0"))
+        );
+
+        let ssh = info(b"SSH-2.0-OpenSSH_9.6\r\n");
+        assert_eq!(ssh.product.as_deref(), Some("OpenSSH"));
+    }
+
+    #[test]
+    fn the_client_hello_is_one_well_formed_record() {
+        let hello = client_hello();
+        assert_eq!(&hello[..3], [0x16, 0x03, 0x01]);
+        let record = u16::from_be_bytes([hello[3], hello[4]]) as usize;
+        assert_eq!(record, hello.len() - 5);
+        assert_eq!(hello[5], 0x01);
+        let handshake = u32::from_be_bytes([0, hello[6], hello[7], hello[8]]) as usize;
+        assert_eq!(handshake, record - 4);
+        // The server would take it for a TLS record, too.
+        assert!(is_tls(&hello));
+    }
+
+    #[test]
+    fn rungs_parse_by_name() {
+        assert_eq!(" TLS".parse(), Ok(Rung::Tls));
+        assert_eq!("crlf".parse(), Ok(Rung::Crlf));
+        assert!("ping".parse::<Rung>().is_err());
+        assert_eq!(Rung::Http.to_string(), "http");
+    }
+
+    /// Runs `rung` against a service that answers whatever it is sent with
+    /// `answer`, and returns what the service was sent.
+    async fn climb(rung: Rung, answer: &'static [u8], r: &mut PortResult) -> (Probed, Vec<u8>) {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut stream: Connection = Box::new(client);
+        let serve = tokio::spawn(async move {
+            let mut request = vec![0; 1024];
+            let n = server.read(&mut request).await.unwrap();
+            server.write_all(answer).await.unwrap();
+            request.truncate(n);
+            request
+        });
+        let probed = rung.run(&mut stream, r).await.unwrap();
+        (probed, serve.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_rung_names_the_service_its_answer_matches() {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), 4433, PortStatus::Open);
+        assert!(Rung::Tls.applies(&r));
+
+        let alert = &[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28];
+        let (probed, sent) = climb(Rung::Tls, alert, &mut r).await;
+        assert_eq!(sent, client_hello());
+        assert_eq!(
+            probed,
+            Probed {
+                found: Some("TLS record".to_string()),
+                keep: false
+            }
+        );
+        assert_eq!(r.service.as_deref(), Some("TLS"));
+        assert!(
+            !Rung::Http.applies(&r),
+            "the ladder stops once a rung matched"
+        );
+
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), 9000, PortStatus::Open);
+        let (probed, sent) = climb(Rung::Crlf, b"-ERR unknown command\r\n", &mut r).await;
+        assert_eq!(sent, b"\r\n\r\n");
+        assert_eq!(probed.found, None);
+        assert_eq!((&r.service, &r.banner), (&None, &None));
+
+        let (probed, sent) = climb(
+            Rung::Http,
+            b"HTTP/1.1 200 OK\r\nServer: nginx/1.18.0\r\n\r\n<html>",
+            &mut r,
+        )
+        .await;
+        assert!(sent.starts_with(b"GET / HTTP/1.1\r\nHost: 10.0.0.1:9000\r\n"));
+        assert_eq!(probed.found.as_deref(), Some("HTTP response"));
+        assert_eq!(r.product_version().as_deref(), Some("nginx 1.18.0"));
+        assert_eq!(
+            r.banner_str().as_deref(),
+            Some("HTTP/1.1 200 OK\r\nServer: nginx/1.18.0")
+        );
+    }
+}
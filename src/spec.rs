@@ -0,0 +1,392 @@
+//! The grammar of port and target specifications, such as `--ports
+//! 22,80,8000-8100,@web` and `--target 10.0.0.0/24`. Errors point at the
+//! part of the specification that was rejected.
+//!
+//! Ports may be prefixed nmap-style with the protocol of the items that
+//! follow, `T:` or `U:`, as in `T:22,80,U:53,161`; items before any prefix
+//! are TCP. A prefix inside a `@group` only holds within the group.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::groups::PortGroups;
+
+/// What was wrong with a specification.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecErrorKind {
+    /// Not a number from 0 to 65535.
+    #[error("Invalid port")]
+    InvalidPort,
+    /// A range with more or fewer than two ends.
+    #[error("Invalid range format")]
+    InvalidRange,
+    /// A range whose start comes after its end.
+    #[error("Start > end")]
+    StartAfterEnd,
+    /// Nothing left to scan once port 0 is dropped.
+    #[error("No valid ports")]
+    NoPorts,
+    /// Not an IPv4 or IPv6 address.
+    #[error("Invalid address")]
+    InvalidAddress,
+    /// A CIDR prefix length longer than the address.
+    #[error("Invalid prefix length")]
+    InvalidPrefix,
+    /// A range from an IPv4 to an IPv6 address or back.
+    #[error("Range mixes IPv4 and IPv6")]
+    MixedFamilies,
+    /// An `@name` that is neither built in nor defined.
+    #[error("Unknown port group")]
+    UnknownGroup,
+    /// An `@name` whose ports end up naming it again.
+    #[error("Port group includes itself")]
+    GroupCycle,
+    /// A protocol prefix other than `T:` or `U:`.
+    #[error("Unknown protocol (expected T: or U:)")]
+    UnknownProtocol,
+    /// A `U:` prefix where only TCP ports are taken.
+    #[error("Only TCP ports are allowed here")]
+    TcpOnly,
+}
+
+/// The transport protocol of a port.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// TCP, the default.
+    #[default]
+    Tcp,
+    /// UDP.
+    Udp,
+}
+
+impl Protocol {
+    /// The name, as reports write it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tcp" | "t" => Ok(Protocol::Tcp),
+            "udp" | "u" => Ok(Protocol::Udp),
+            _ => Err(format!("unknown protocol '{}' (expected tcp or udp)", s)),
+        }
+    }
+}
+
+/// A rejected specification: what was wrong, the offending part and its
+/// byte offset in the whole specification.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub struct SpecError {
+    /// What was wrong.
+    pub kind: SpecErrorKind,
+    /// The offending part of the specification.
+    pub fragment: String,
+    /// Byte offset of `fragment` in the specification.
+    pub position: usize,
+    /// More on what to do about it, such as the group names that come close.
+    pub hint: Option<String>,
+}
+
+impl SpecError {
+    /// An error about `fragment`, found at `position`.
+    pub fn new(kind: SpecErrorKind, fragment: &str, position: usize) -> Self {
+        SpecError {
+            kind,
+            fragment: fragment.to_string(),
+            position,
+            hint: None,
+        }
+    }
+
+    /// Adds a hint, shown after the error.
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.kind, self.fragment.is_empty()) {
+            (SpecErrorKind::NoPorts, true) => write!(f, "{}", self.kind)?,
+            (_, true) => write!(f, "{} at position {}", self.kind, self.position)?,
+            _ => write!(
+                f,
+                "{} in {:?} at position {}",
+                self.kind, self.fragment, self.position
+            )?,
+        }
+        match &self.hint {
+            Some(hint) => write!(f, "; {}", hint),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses a comma-separated list of ports, `start-end` ranges and built-in
+/// `@group`s, in the order given. Empty entries are skipped; port 0 is left
+/// to the caller. The ports are TCP: a `T:` prefix is allowed, `U:` is not.
+pub fn port_ranges(spec: &str) -> Result<Vec<RangeInclusive<u16>>, SpecError> {
+    port_ranges_with(spec, &PortGroups::new())
+}
+
+/// Like [`port_ranges`], expanding the `@group`s of `groups`.
+pub fn port_ranges_with(
+    spec: &str,
+    groups: &PortGroups,
+) -> Result<Vec<RangeInclusive<u16>>, SpecError> {
+    let ranges = expand(spec, groups, &mut Vec::new(), Protocol::Tcp, false)?;
+    Ok(ranges.into_iter().map(|(_, range)| range).collect())
+}
+
+/// Like [`port_ranges_with`], taking `T:` and `U:` prefixes and returning
+/// the protocol of each range.
+pub fn protocol_port_ranges_with(
+    spec: &str,
+    groups: &PortGroups,
+) -> Result<Vec<(Protocol, RangeInclusive<u16>)>, SpecError> {
+    expand(spec, groups, &mut Vec::new(), Protocol::Tcp, true)
+}
+
+/// Parses `spec` within the groups of `expanding`, outermost first, its
+/// items being of `protocol` until a prefix says otherwise.
+fn expand<'a>(
+    spec: &'a str,
+    groups: &'a PortGroups,
+    expanding: &mut Vec<&'a str>,
+    mut protocol: Protocol,
+    udp: bool,
+) -> Result<Vec<(Protocol, RangeInclusive<u16>)>, SpecError> {
+    let mut ranges = Vec::new();
+    for (offset, part) in pieces(spec, ',') {
+        let (mut part, mut offset) = trimmed(part, offset);
+        if let Some((prefix, rest)) = part.split_once(':') {
+            protocol = match prefix.trim() {
+                "T" | "t" => Protocol::Tcp,
+                "U" | "u" if udp => Protocol::Udp,
+                "U" | "u" => return Err(SpecError::new(SpecErrorKind::TcpOnly, part, offset)),
+                _ => {
+                    return Err(SpecError::new(
+                        SpecErrorKind::UnknownProtocol,
+                        prefix,
+                        offset,
+                    ))
+                }
+            };
+            (part, offset) = trimmed(rest, offset + prefix.len() + 1);
+        }
+        if part.is_empty() {
+            continue;
+        }
+        if let Some(name) = part.strip_prefix('@') {
+            let Some(ports) = groups.get(name) else {
+                return Err(SpecError::new(SpecErrorKind::UnknownGroup, part, offset)
+                    .with_hint(groups.suggest(name)));
+            };
+            if expanding.contains(&name) {
+                let chain: Vec<String> = expanding.iter().map(|g| format!("@{}", g)).collect();
+                return Err(SpecError::new(SpecErrorKind::GroupCycle, part, offset)
+                    .with_hint(format!("{} -> @{}", chain.join(" -> "), name)));
+            }
+            expanding.push(name);
+            // A mistake inside the group is reported where it was named.
+            let inner = expand(ports, groups, expanding, protocol, udp).map_err(|e| SpecError {
+                fragment: part.to_string(),
+                position: offset,
+                ..e
+            })?;
+            expanding.pop();
+            ranges.extend(inner);
+            continue;
+        }
+        if !part.contains('-') {
+            let port = port(part, offset)?;
+            ranges.push((protocol, port..=port));
+            continue;
+        }
+        let ends: Vec<(usize, &str)> = pieces(part, '-').collect();
+        let [(start_at, start), (end_at, end)] = ends[..] else {
+            return Err(SpecError::new(SpecErrorKind::InvalidRange, part, offset));
+        };
+        let start = port(start, offset + start_at)?;
+        let end = port(end, offset + end_at)?;
+        if start > end {
+            return Err(SpecError::new(SpecErrorKind::StartAfterEnd, part, offset));
+        }
+        ranges.push((protocol, start..=end));
+    }
+    Ok(ranges)
+}
+
+/// An inclusive range of hosts of one address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetRange {
+    /// IPv4 hosts from the first to the second address.
+    V4(Ipv4Addr, Ipv4Addr),
+    /// IPv6 hosts from the first to the second address.
+    V6(Ipv6Addr, Ipv6Addr),
+}
+
+/// Parses one target: an address, a CIDR block such as `10.0.0.0/24` or a
+/// range such as `10.0.0.5-10.0.0.20`. Surrounding whitespace is ignored.
+pub fn target_range(spec: &str) -> Result<TargetRange, SpecError> {
+    let (spec, offset) = trimmed(spec, 0);
+    if let Some((ip, len)) = spec.split_once('/') {
+        let len_at = offset + ip.len() + 1;
+        let (ip, ip_at) = trimmed(ip, offset);
+        let ip = address(ip, ip_at)?;
+        let (len, len_at) = trimmed(len, len_at);
+        let bad_len = || SpecError::new(SpecErrorKind::InvalidPrefix, len, len_at);
+        let bits: u32 = len.parse().map_err(|_| bad_len())?;
+        return match ip {
+            IpAddr::V4(ip) if bits <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+                let start = u32::from(ip) & mask;
+                Ok(TargetRange::V4(start.into(), (start | !mask).into()))
+            }
+            IpAddr::V6(ip) if bits <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+                let start = u128::from(ip) & mask;
+                Ok(TargetRange::V6(start.into(), (start | !mask).into()))
+            }
+            _ => Err(bad_len()),
+        };
+    }
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => {
+            let end_at = offset + start.len() + 1;
+            let (start, start_at) = trimmed(start, offset);
+            let (end, end_at) = trimmed(end, end_at);
+            (address(start, start_at)?, address(end, end_at)?)
+        }
+        None => {
+            let ip = address(spec, offset)?;
+            (ip, ip)
+        }
+    };
+    match (start, end) {
+        (IpAddr::V4(a), IpAddr::V4(b)) if a <= b => Ok(TargetRange::V4(a, b)),
+        (IpAddr::V6(a), IpAddr::V6(b)) if a <= b => Ok(TargetRange::V6(a, b)),
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+            Err(SpecError::new(SpecErrorKind::StartAfterEnd, spec, offset))
+        }
+        _ => Err(SpecError::new(SpecErrorKind::MixedFamilies, spec, offset)),
+    }
+}
+
+/// `s` split on `sep`, each piece with its byte offset in `s`.
+fn pieces(s: &str, sep: char) -> impl Iterator<Item = (usize, &str)> {
+    s.split(sep).scan(0, move |at, piece| {
+        let start = *at;
+        *at += piece.len() + sep.len_utf8();
+        Some((start, piece))
+    })
+}
+
+/// `s` without surrounding whitespace, and the offset that moves it to.
+fn trimmed(s: &str, offset: usize) -> (&str, usize) {
+    let start = s.len() - s.trim_start().len();
+    (s.trim(), offset + start)
+}
+
+fn port(s: &str, offset: usize) -> Result<u16, SpecError> {
+    s.parse()
+        .map_err(|_| SpecError::new(SpecErrorKind::InvalidPort, s, offset))
+}
+
+fn address(s: &str, offset: usize) -> Result<IpAddr, SpecError> {
+    s.parse()
+        .map_err(|_| SpecError::new(SpecErrorKind::InvalidAddress, s, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_point_at_the_offending_part() {
+        let err = |spec: &str| port_ranges(spec).unwrap_err();
+        assert_eq!(
+            err("22, 80-x,443"),
+            SpecError::new(SpecErrorKind::InvalidPort, "x", 7)
+        );
+        assert_eq!(
+            err("1,90-80"),
+            SpecError::new(SpecErrorKind::StartAfterEnd, "90-80", 2)
+        );
+        assert_eq!(
+            err("1-2-3"),
+            SpecError::new(SpecErrorKind::InvalidRange, "1-2-3", 0)
+        );
+        assert_eq!(
+            err("65536").to_string(),
+            "Invalid port in \"65536\" at position 0"
+        );
+
+        let err = |spec: &str| target_range(spec).unwrap_err();
+        assert_eq!(
+            err(" 10.0.0.1/33"),
+            SpecError::new(SpecErrorKind::InvalidPrefix, "33", 10)
+        );
+        assert_eq!(
+            err("10.0.0.1 - nope"),
+            SpecError::new(SpecErrorKind::InvalidAddress, "nope", 11)
+        );
+        assert_eq!(err("10.0.0.1-::2").kind, SpecErrorKind::MixedFamilies);
+        assert_eq!(err("10.0.0.9-10.0.0.1").kind, SpecErrorKind::StartAfterEnd);
+    }
+
+    #[test]
+    fn prefixes_switch_the_protocol_of_what_follows() {
+        let parse = |spec| protocol_port_ranges_with(spec, &PortGroups::new()).unwrap();
+        use Protocol::{Tcp, Udp};
+        assert_eq!(
+            parse("22,T:80,U:53,161-162, t: 443,u:500"),
+            [
+                (Tcp, 22..=22),
+                (Tcp, 80..=80),
+                (Udp, 53..=53),
+                (Udp, 161..=162),
+                (Tcp, 443..=443),
+                (Udp, 500..=500),
+            ]
+        );
+        assert_eq!(parse("U:,T:1-2"), [(Tcp, 1..=2)]);
+
+        let groups = PortGroups::with(&["dns=U:53,T:53".parse().unwrap()]).unwrap();
+        let ranges = protocol_port_ranges_with("U:@dns,53", &groups).unwrap();
+        assert_eq!(ranges, [(Udp, 53..=53), (Tcp, 53..=53), (Udp, 53..=53)]);
+
+        assert_eq!(
+            protocol_port_ranges_with("22,S:1", &PortGroups::new()).unwrap_err(),
+            SpecError::new(SpecErrorKind::UnknownProtocol, "S", 3)
+        );
+        assert_eq!(port_ranges("T:22,80").unwrap(), [22..=22, 80..=80]);
+        assert_eq!(
+            port_ranges("22, U:53").unwrap_err(),
+            SpecError::new(SpecErrorKind::TcpOnly, "U:53", 4)
+        );
+        assert_eq!("UDP".parse(), Ok(Udp));
+    }
+}
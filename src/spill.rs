@@ -0,0 +1,277 @@
+//! Results kept on disk for scans too large to hold in memory
+//! (`--low-memory`): every result is appended to a temporary NDJSON file and
+//! only the counters and the open ports stay in RAM.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::annotations::DeviationCounts;
+use crate::capture::CaptureBudget;
+use crate::metrics::ScanMetrics;
+use crate::scanner::{PortError, PortResult, PortStatus, ProtocolCounts, ScanSummary};
+
+/// A finished spill file, deleted when dropped.
+#[derive(Debug)]
+pub struct Spill {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Spill {
+    /// Number of results in the file.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True when no result was spilled.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the results back in the order they were written.
+    pub fn iter(&self) -> io::Result<SpillIter> {
+        Ok(SpillIter {
+            lines: BufReader::new(File::open(&self.path)?).lines(),
+        })
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The results of a [`Spill`], one line at a time.
+pub struct SpillIter {
+    lines: io::Lines<BufReader<File>>,
+}
+
+impl Iterator for SpillIter {
+    type Item = io::Result<PortResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        Some(line.and_then(|l| serde_json::from_str(&l).map_err(io::Error::from)))
+    }
+}
+
+struct SpillWriter {
+    out: BufWriter<File>,
+    spill: Spill,
+}
+
+#[derive(Default)]
+struct Counts {
+    closed: usize,
+    filtered: usize,
+    unscanned: usize,
+    capture_truncated: usize,
+    panicked: usize,
+    protocols: Vec<ProtocolCounts>,
+    deviations: Option<DeviationCounts>,
+}
+
+/// Where a scan's results are collected until the summary is built: all in
+/// memory, or spilled to disk with only the open ports kept.
+pub struct ResultLog {
+    kept: Vec<PortResult>,
+    spill: Option<SpillWriter>,
+    scanned: usize,
+    counts: Counts,
+    budget: Option<CaptureBudget>,
+}
+
+impl ResultLog {
+    /// Keeps every result in memory.
+    pub fn in_memory() -> Self {
+        ResultLog {
+            kept: Vec::new(),
+            spill: None,
+            scanned: 0,
+            counts: Counts::default(),
+            budget: None,
+        }
+    }
+
+    /// Spills results to a new temporary file in `dir`.
+    pub fn spill_in(dir: &Path) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(format!(
+            "port-scanner-{}-{}.ndjson",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let out = BufWriter::new(File::create(&path)?);
+        Ok(ResultLog {
+            spill: Some(SpillWriter {
+                out,
+                spill: Spill { path, len: 0 },
+            }),
+            ..Self::in_memory()
+        })
+    }
+
+    /// Gives the bytes of each result written to disk, and no longer kept,
+    /// back to `budget`.
+    pub fn release_to(mut self, budget: CaptureBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// True when results go to disk.
+    pub fn is_spilling(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// Number of results collected.
+    pub fn len(&self) -> usize {
+        self.scanned
+    }
+
+    /// True when nothing was collected yet.
+    pub fn is_empty(&self) -> bool {
+        self.scanned == 0
+    }
+
+    /// The results held in memory: all of them, or the open ports when
+    /// spilling.
+    pub fn kept_mut(&mut self) -> &mut Vec<PortResult> {
+        &mut self.kept
+    }
+
+    /// Records a result.
+    pub fn push(&mut self, r: PortResult) -> io::Result<()> {
+        self.scanned += 1;
+        match r.status {
+            PortStatus::Open => {}
+            PortStatus::Closed => self.counts.closed += 1,
+            PortStatus::Filtered => self.counts.filtered += 1,
+        }
+        if r.error.as_ref().is_some_and(PortError::is_local) {
+            self.counts.unscanned += 1;
+        }
+        if r.capture_truncated {
+            self.counts.capture_truncated += 1;
+        }
+        if matches!(r.error, Some(PortError::Panicked(_))) {
+            self.counts.panicked += 1;
+        }
+        ProtocolCounts::record(&mut self.counts.protocols, &r);
+        DeviationCounts::record(&mut self.counts.deviations, &r);
+        let Some(w) = &mut self.spill else {
+            self.kept.push(r);
+            return Ok(());
+        };
+        serde_json::to_writer(&mut w.out, &r)?;
+        w.out.write_all(b"\n")?;
+        w.spill.len += 1;
+        if r.status == PortStatus::Open {
+            self.kept.push(r);
+        } else if let Some(budget) = &self.budget {
+            budget.release(&r);
+        }
+        Ok(())
+    }
+
+    /// Builds the summary; a spilled log hands its file to the summary.
+    pub fn into_summary(
+        self,
+        scan_id: String,
+        target: String,
+        total_time_ms: u128,
+        metrics: &ScanMetrics,
+        interrupted: bool,
+    ) -> io::Result<ScanSummary> {
+        let mut summary = ScanSummary::from_results(
+            scan_id,
+            target,
+            self.kept,
+            total_time_ms,
+            metrics,
+            interrupted,
+        );
+        if let Some(mut w) = self.spill {
+            w.out.flush()?;
+            summary.scanned_ports = self.scanned;
+            summary.closed_ports = self.counts.closed;
+            summary.filtered_ports = self.counts.filtered;
+            summary.unscanned_ports = self.counts.unscanned;
+            summary.capture_truncated_ports = self.counts.capture_truncated;
+            summary.panicked_ports = self.counts.panicked;
+            summary.protocols = self.counts.protocols;
+            summary.deviations = self.counts.deviations;
+            summary.spill = Some(w.spill);
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::{MockBehavior, MockConnector};
+    use crate::output::{JsonWriter, OutputWriter};
+    use crate::Scanner;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    async fn collect(mut log: ResultLog) -> ScanSummary {
+        let mock = MockConnector::new()
+            .port(22, MockBehavior::Open(b"SSH-2.0-Mock\r\n".to_vec()))
+            .port(25, MockBehavior::Hang)
+            .port(80, MockBehavior::Open(Vec::new()));
+        let scanner = Scanner::builder()
+            .target("192.0.2.0/30")
+            .ports(1..=100)
+            .timeout(Duration::from_millis(20))
+            .banner_timeout(Duration::from_millis(20))
+            .connector(mock)
+            .clock(crate::clock::FixedClock::default())
+            .build()
+            .unwrap();
+        let mut results: Vec<PortResult> = scanner.scan().collect().await;
+        results.sort_by_key(|r| (r.target, r.port, r.protocol));
+        for r in results {
+            log.push(r).unwrap();
+        }
+        log.into_summary(
+            "id".into(),
+            "192.0.2.0/30".into(),
+            0,
+            &scanner.metrics(),
+            false,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn spilled_summaries_match_the_in_memory_ones() {
+        let dir = std::env::temp_dir();
+        let memory = collect(ResultLog::in_memory()).await;
+        let spilled = collect(ResultLog::spill_in(&dir).unwrap()).await;
+
+        assert_eq!(spilled.scanned_ports, 400);
+        assert_eq!(spilled.scanned_ports, memory.scanned_ports);
+        assert_eq!(spilled.open_ports, memory.open_ports);
+        assert_eq!(spilled.closed_ports, memory.closed_ports);
+        assert_eq!(spilled.filtered_ports, memory.filtered_ports);
+        assert_eq!(spilled.results.len(), 8);
+
+        let render = |summary: &ScanSummary, name: &str| {
+            let path = dir.join(format!("spill-{}-{}.json", std::process::id(), name));
+            JsonWriter::new(&path).finish(summary).unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            text
+        };
+        assert_eq!(render(&spilled, "spilled"), render(&memory, "memory"));
+
+        let file = spilled.spill.as_ref().unwrap().path.clone();
+        assert!(file.exists());
+        drop(spilled);
+        assert!(!file.exists());
+    }
+}
@@ -0,0 +1,203 @@
+//! SSDP, the discovery half of UPnP: the M-SEARCH that asks every device on
+//! the network to answer, the answers and announcements it gets, and the
+//! device description their `LOCATION` points to.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// Where M-SEARCH requests go and NOTIFY announcements arrive.
+pub const GROUP: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+
+/// An M-SEARCH for every device and service, answered within `mx` seconds.
+pub fn search(mx: u64) -> Vec<u8> {
+    format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: 239.255.255.250:1900\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: {}\r\n\
+         ST: ssdp:all\r\n\r\n",
+        mx.clamp(1, 5)
+    )
+    .into_bytes()
+}
+
+/// What an M-SEARCH answer or a NOTIFY says about its sender.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Announcement {
+    /// The device or service type (`ST` or `NT`), such as
+    /// `urn:schemas-upnp-org:device:MediaRenderer:1`.
+    pub service: Option<String>,
+    /// The `SERVER` header: operating system, UPnP version and product.
+    pub server: Option<String>,
+    /// URL of the device description.
+    pub location: Option<String>,
+}
+
+/// Reads an M-SEARCH answer or a NOTIFY; `None` for searches, byes and
+/// anything else.
+pub fn parse(datagram: &[u8]) -> Option<Announcement> {
+    let text = std::str::from_utf8(datagram).ok()?;
+    let mut lines = text.split("\r\n");
+    let start = lines.next()?;
+    let notify = start.starts_with("NOTIFY ");
+    if !(start.starts_with("HTTP/1.1 200") || notify) {
+        return None;
+    }
+    let mut found = Announcement::default();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        match name.trim().to_ascii_uppercase().as_str() {
+            "ST" | "NT" => found.service = value,
+            "SERVER" => found.server = value,
+            "LOCATION" => found.location = value,
+            "NTS" if value.as_deref() == Some("ssdp:byebye") => return None,
+            _ => {}
+        }
+    }
+    Some(found)
+}
+
+/// The parts of a UPnP device description worth reporting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Description {
+    /// `friendlyName`, such as `Living Room`.
+    pub name: Option<String>,
+    /// `manufacturer` and `modelName` together, such as `Signify BSB002`.
+    pub model: Option<String>,
+}
+
+/// Reads the root device of a description document.
+pub fn parse_description(xml: &str) -> Description {
+    let model = match (element(xml, "manufacturer"), element(xml, "modelName")) {
+        // "Sonos Play:1" by "Sonos, Inc." already names its maker.
+        (Some(maker), Some(model)) if model.starts_with(brand(&maker)) => Some(model),
+        (Some(maker), Some(model)) => Some(format!("{} {}", maker, model)),
+        (maker, model) => model.or(maker),
+    };
+    Description {
+        name: element(xml, "friendlyName"),
+        model,
+    }
+}
+
+/// The first word of a manufacturer's name.
+fn brand(maker: &str) -> &str {
+    maker
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or(maker)
+}
+
+/// The text of the first `<tag>` in `xml`, entities decoded.
+fn element(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find("</")?;
+    let text = xml[start..end]
+        .trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    Some(text).filter(|t| !t.is_empty())
+}
+
+/// Host, port and path of a plain `http://` description URL.
+pub fn split_location(url: &str) -> Option<(&str, u16, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, "/"),
+    };
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host, port.parse().ok()?, path)),
+        None => Some((authority, 80, path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Philips Hue bridge answering an M-SEARCH.
+    const HUE_ANSWER: &[u8] = b"HTTP/1.1 200 OK\r\n\
+        HOST: 239.255.255.250:1900\r\n\
+        EXT:\r\n\
+        CACHE-CONTROL: max-age=100\r\n\
+        LOCATION: http://192.168.1.20:80/description.xml\r\n\
+        SERVER: Hue/1.0 UPnP/1.0 IpBridge/1.56.0\r\n\
+        hue-bridgeid: 001788FFFE255ACC\r\n\
+        ST: upnp:rootdevice\r\n\
+        USN: uuid:2f402f80-da50-11e1-9b23-001788255acc::upnp:rootdevice\r\n\r\n";
+
+    /// A Sonos speaker announcing itself, then leaving.
+    const SONOS_NOTIFY: &[u8] = b"NOTIFY * HTTP/1.1\r\n\
+        HOST: 239.255.255.250:1900\r\n\
+        CACHE-CONTROL: max-age = 1800\r\n\
+        LOCATION: http://192.168.1.31:1400/xml/device_description.xml\r\n\
+        NT: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+        NTS: ssdp:alive\r\n\
+        SERVER: Linux UPnP/1.0 Sonos/70.3-35220 (ZPS1)\r\n\
+        USN: uuid:RINCON_000E58A0B1C201400::urn:schemas-upnp-org:device:ZonePlayer:1\r\n\r\n";
+
+    #[test]
+    fn answers_and_notifies_give_type_server_and_location() {
+        assert_eq!(
+            parse(HUE_ANSWER),
+            Some(Announcement {
+                service: Some("upnp:rootdevice".into()),
+                server: Some("Hue/1.0 UPnP/1.0 IpBridge/1.56.0".into()),
+                location: Some("http://192.168.1.20:80/description.xml".into()),
+            })
+        );
+        let sonos = parse(SONOS_NOTIFY).unwrap();
+        assert_eq!(
+            sonos.service.as_deref(),
+            Some("urn:schemas-upnp-org:device:ZonePlayer:1")
+        );
+        let bye = String::from_utf8_lossy(SONOS_NOTIFY).replace("ssdp:alive", "ssdp:byebye");
+        assert_eq!(parse(bye.as_bytes()), None);
+        assert_eq!(parse(&search(2)), None);
+    }
+
+    #[test]
+    fn descriptions_give_name_and_model() {
+        let xml = r#"<?xml version="1.0"?>
+            <root xmlns="urn:schemas-upnp-org:device-1-0">
+              <device>
+                <deviceType>urn:schemas-upnp-org:device:ZonePlayer:1</deviceType>
+                <friendlyName>192.168.1.31 - Sonos Play:1</friendlyName>
+                <manufacturer>Sonos, Inc.</manufacturer>
+                <modelName>Sonos Play:1</modelName>
+                <deviceList><device><friendlyName>Inner</friendlyName></device></deviceList>
+              </device>
+            </root>"#;
+        assert_eq!(
+            parse_description(xml),
+            Description {
+                name: Some("192.168.1.31 - Sonos Play:1".into()),
+                model: Some("Sonos Play:1".into()),
+            }
+        );
+        let hue = "<friendlyName>Hue &amp; Co</friendlyName>\
+                   <manufacturer>Signify</manufacturer><modelName>BSB002</modelName>";
+        assert_eq!(
+            parse_description(hue).model.as_deref(),
+            Some("Signify BSB002")
+        );
+        assert_eq!(parse_description(hue).name.as_deref(), Some("Hue & Co"));
+    }
+
+    #[test]
+    fn locations_split_into_host_port_and_path() {
+        assert_eq!(
+            split_location("http://192.168.1.31:1400/xml/device_description.xml"),
+            Some(("192.168.1.31", 1400, "/xml/device_description.xml"))
+        );
+        assert_eq!(split_location("http://router"), Some(("router", 80, "/")));
+        assert_eq!(split_location("https://router/desc.xml"), None);
+    }
+}
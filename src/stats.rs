@@ -0,0 +1,510 @@
+//! Internal performance counters for `--stats`: how connects and banner
+//! reads ended, how follow-up probes got their connections, how long probes
+//! waited for a connection slot, and where the run's wall time went.
+//!
+//! The counters are atomics bumped on the probe path, and only exist when a
+//! scan asks for them; without `--stats` the path pays one `None` check.
+//! Failed follow-up probes, which are few, are counted by probe under a
+//! lock.
+//! Every connect ends in exactly one of the connect counters, so with the
+//! ports skipped on unreachable hosts they add up to the summary's port
+//! counts; the connections follow-up probes open are counted apart.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How a connect attempt ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectOutcome {
+    /// Accepted: the port is open.
+    Succeeded,
+    /// Refused: the port is closed.
+    Refused,
+    /// No answer before the connect timeout.
+    TimedOut,
+    /// Reset before the connect returned, after the handshake: open.
+    Reset,
+    /// Any other error: filtered, or not scanned at all.
+    Failed,
+}
+
+/// How a banner read on an open port ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BannerOutcome {
+    /// The service sent something.
+    Succeeded,
+    /// The service closed the connection without sending anything.
+    Empty,
+    /// Nothing came before the banner timeout.
+    TimedOut,
+    /// The read failed, e.g. on a reset.
+    Failed,
+}
+
+/// Live counters behind `--stats`.
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    connects: [AtomicU64; 5],
+    banners: [AtomicU64; 4],
+    banner_bytes: AtomicU64,
+    unreachable_cached: AtomicU64,
+    ports_exhausted: AtomicU64,
+    panics: AtomicU64,
+    probes: AtomicU64,
+    probe_connects: AtomicU64,
+    probe_failures: Mutex<BTreeMap<&'static str, ProbeFailures>>,
+    connect_time: Histogram,
+    permit_wait: Histogram,
+}
+
+impl ScanStats {
+    /// Creates zeroed counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a finished connect attempt.
+    pub fn record_connect(&self, outcome: ConnectOutcome) {
+        self.connects[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a finished banner read and the bytes it kept.
+    pub fn record_banner(&self, outcome: BannerOutcome, bytes: usize) {
+        self.banners[outcome as usize].fetch_add(1, Ordering::Relaxed);
+        self.banner_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a port left unprobed because its host was unreachable.
+    pub fn record_unreachable_cached(&self) {
+        self.unreachable_cached.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connect that failed for want of a free local port, and is
+    /// retried.
+    pub fn record_ports_exhausted(&self) {
+        self.ports_exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a probe that panicked.
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a follow-up probe run, and whether it needed a connection of
+    /// its own rather than sharing the port's last one.
+    pub fn record_probe(&self, connected: bool) {
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        if connected {
+            self.probe_connects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a follow-up probe, by its name, that timed out or else
+    /// failed.
+    pub fn record_probe_failure(&self, probe: &'static str, timed_out: bool) {
+        let mut failures = self
+            .probe_failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let counts = failures.entry(probe).or_default();
+        if timed_out {
+            counts.timed_out += 1;
+        } else {
+            counts.failed += 1;
+        }
+    }
+
+    /// Records how long a connect took that was answered, open or closed.
+    pub fn record_connect_time(&self, took: Duration) {
+        self.connect_time.record(took.as_micros() as u64);
+    }
+
+    /// Records how long a probe waited for a connection slot.
+    pub fn record_permit_wait(&self, waited: Duration) {
+        self.permit_wait.record(waited.as_micros() as u64);
+    }
+
+    /// The counters as they are now, with `retries` from the scan metrics;
+    /// the phases are the caller's to fill in.
+    pub fn snapshot(&self, retries: u64) -> Stats {
+        let connect = |o: ConnectOutcome| self.connects[o as usize].load(Ordering::Relaxed);
+        let banner = |o: BannerOutcome| self.banners[o as usize].load(Ordering::Relaxed);
+        let connects = ConnectStats {
+            attempted: self
+                .connects
+                .iter()
+                .map(|c| c.load(Ordering::Relaxed))
+                .sum(),
+            succeeded: connect(ConnectOutcome::Succeeded),
+            refused: connect(ConnectOutcome::Refused),
+            timed_out: connect(ConnectOutcome::TimedOut),
+            reset: connect(ConnectOutcome::Reset),
+            failed: connect(ConnectOutcome::Failed),
+        };
+        let banners = BannerStats {
+            attempted: self.banners.iter().map(|b| b.load(Ordering::Relaxed)).sum(),
+            succeeded: banner(BannerOutcome::Succeeded),
+            empty: banner(BannerOutcome::Empty),
+            timed_out: banner(BannerOutcome::TimedOut),
+            failed: banner(BannerOutcome::Failed),
+            bytes: self.banner_bytes.load(Ordering::Relaxed),
+        };
+        let run = self.probes.load(Ordering::Relaxed);
+        let reconnects = self.probe_connects.load(Ordering::Relaxed);
+        let failures = self
+            .probe_failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(&probe, &counts)| (probe.to_string(), counts))
+            .collect();
+        Stats {
+            connects,
+            banners,
+            probes: ProbeStats {
+                run,
+                shared: run - reconnects,
+                reconnects,
+                failures,
+            },
+            retries,
+            unreachable_cached: self.unreachable_cached.load(Ordering::Relaxed),
+            ports_exhausted: self.ports_exhausted.load(Ordering::Relaxed),
+            panics: self.panics.load(Ordering::Relaxed),
+            connect_time_us: self.connect_time.summary(),
+            permit_wait_us: self.permit_wait.summary(),
+            phases_ms: None,
+        }
+    }
+}
+
+/// Power-of-two buckets of microseconds: bucket `b` holds values below
+/// `2^b`, so a percentile is accurate to within a factor of two.
+#[derive(Debug)]
+struct Histogram {
+    buckets: [AtomicU64; 65],
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> WaitStats {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let max = self.max.load(Ordering::Relaxed);
+        // The upper bound of the bucket holding the value at `rank`.
+        let percentile = |p: u64| {
+            if count == 0 {
+                return 0;
+            }
+            let rank = (count * p).div_ceil(100).max(1);
+            let mut seen = 0;
+            for (bucket, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    let bound = 1u64.checked_shl(bucket as u32).map_or(u64::MAX, |b| b - 1);
+                    return bound.min(max);
+                }
+            }
+            max
+        };
+        WaitStats {
+            count,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max,
+        }
+    }
+}
+
+/// The `--stats` counters of a scan, as printed and kept in the JSON
+/// report's `stats`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    /// How connect attempts ended.
+    pub connects: ConnectStats,
+    /// How banner reads on open ports ended.
+    pub banners: BannerStats,
+    /// How follow-up probes got their connections.
+    #[serde(default)]
+    pub probes: ProbeStats,
+    /// Connection attempts that were retries.
+    pub retries: u64,
+    /// Ports left unprobed, and filtered, because their host was
+    /// unreachable.
+    #[serde(default)]
+    pub unreachable_cached: u64,
+    /// Connects that found no free local port to connect from, each
+    /// retried, so they are among the retries rather than the connects.
+    #[serde(default)]
+    pub ports_exhausted: u64,
+    /// Probes that panicked; their ports are reported with the panic as
+    /// their error.
+    #[serde(default)]
+    pub panics: u64,
+    /// How long the connects that were answered, open or closed, took, in
+    /// microseconds; a re-timed outlier counts with its faster time.
+    #[serde(default)]
+    pub connect_time_us: WaitStats,
+    /// How long probes waited for a connection slot, in microseconds.
+    pub permit_wait_us: WaitStats,
+    /// Where the run's wall time went.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phases_ms: Option<Phases>,
+}
+
+/// Connect attempts by how they ended; `attempted` is the sum of the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectStats {
+    /// Every connect attempt.
+    pub attempted: u64,
+    /// Accepted.
+    pub succeeded: u64,
+    /// Refused.
+    pub refused: u64,
+    /// Unanswered before the connect timeout.
+    pub timed_out: u64,
+    /// Reset after the handshake, before the connect returned.
+    pub reset: u64,
+    /// Failed otherwise.
+    pub failed: u64,
+}
+
+/// Banner reads by how they ended; `attempted` is the sum of the outcomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BannerStats {
+    /// Every banner read.
+    pub attempted: u64,
+    /// Got something.
+    pub succeeded: u64,
+    /// Closed without sending anything.
+    pub empty: u64,
+    /// Nothing before the banner timeout.
+    pub timed_out: u64,
+    /// The read failed.
+    pub failed: u64,
+    /// Bytes read.
+    pub bytes: u64,
+}
+
+/// Follow-up probes by the connection they ran on, `run` being the sum of
+/// `shared` and `reconnects`, and the probes that failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeStats {
+    /// Every probe run.
+    pub run: u64,
+    /// Run on the connection of the banner read or an earlier probe.
+    pub shared: u64,
+    /// Run on a new connection, as the old one was closed or used.
+    pub reconnects: u64,
+    /// The probes that timed out or failed, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failures: BTreeMap<String, ProbeFailures>,
+}
+
+/// How often a follow-up probe timed out or failed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeFailures {
+    /// Ran out of its timeout or the port's probe budget.
+    pub timed_out: u64,
+    /// Failed otherwise, e.g. on a reset.
+    pub failed: u64,
+}
+
+/// A distribution of waits, in microseconds. Percentiles are the upper
+/// bound of a power-of-two bucket, capped at `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WaitStats {
+    /// Waits recorded.
+    pub count: u64,
+    /// Median.
+    pub p50: u64,
+    /// 90th percentile.
+    pub p90: u64,
+    /// 99th percentile.
+    pub p99: u64,
+    /// Longest.
+    pub max: u64,
+}
+
+/// Wall time of each phase of a run, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Phases {
+    /// Resolving and discovering targets and building the scan.
+    pub planning: u64,
+    /// Probing.
+    pub scanning: u64,
+    /// Checking and enriching the results: traceroutes, OS guesses,
+    /// clustering, scripts and policy.
+    pub verification: u64,
+    /// Writing, uploading and mailing the reports. Unknown while the JSON
+    /// report itself is being written, so only ever in the printed stats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<u64>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.connects;
+        writeln!(
+            f,
+            "connects     {} attempted: {} succeeded, {} refused, {} timed out, {} reset, {} failed",
+            c.attempted, c.succeeded, c.refused, c.timed_out, c.reset, c.failed
+        )?;
+        let b = &self.banners;
+        writeln!(
+            f,
+            "banners      {} attempted: {} succeeded, {} empty, {} timed out, {} failed, {} bytes",
+            b.attempted, b.succeeded, b.empty, b.timed_out, b.failed, b.bytes
+        )?;
+        let p = &self.probes;
+        write!(
+            f,
+            "probes       {} run: {} on a shared connection, {} reconnected",
+            p.run, p.shared, p.reconnects
+        )?;
+        for (probe, failed) in &p.failures {
+            write!(
+                f,
+                "; {} {} timed out, {} failed",
+                probe, failed.timed_out, failed.failed
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "retries      {}", self.retries)?;
+        writeln!(
+            f,
+            "unreachable  {} ports skipped on unreachable hosts",
+            self.unreachable_cached
+        )?;
+        writeln!(
+            f,
+            "exhausted    {} connects found no free local port",
+            self.ports_exhausted
+        )?;
+        writeln!(f, "panics       {} probes panicked", self.panics)?;
+        let t = &self.connect_time_us;
+        writeln!(
+            f,
+            "connect time {} answered: p50 {}us, p90 {}us, p99 {}us, max {}us",
+            t.count, t.p50, t.p90, t.p99, t.max
+        )?;
+        let w = &self.permit_wait_us;
+        write!(
+            f,
+            "permit wait  {} waits: p50 {}us, p90 {}us, p99 {}us, max {}us",
+            w.count, w.p50, w.p90, w.p99, w.max
+        )?;
+        if let Some(p) = &self.phases_ms {
+            write!(
+                f,
+                "\nphases       planning {}ms, scanning {}ms, verification {}ms",
+                p.planning, p.scanning, p.verification
+            )?;
+            if let Some(output) = p.output {
+                write!(f, ", output {}ms", output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_are_bucket_bounds_capped_at_the_max() {
+        let stats = ScanStats::new();
+        for us in [0, 1, 3, 5, 6, 7, 100, 120, 130, 5000] {
+            stats.record_permit_wait(Duration::from_micros(us));
+        }
+        let wait = stats.snapshot(0).permit_wait_us;
+        assert_eq!(wait.count, 10);
+        assert_eq!(wait.p50, 7);
+        assert_eq!(wait.p90, 255);
+        assert_eq!(wait.p99, 5000);
+        assert_eq!(wait.max, 5000);
+
+        let none = ScanStats::new().snapshot(0).permit_wait_us;
+        assert_eq!((none.count, none.p50, none.max), (0, 0, 0));
+    }
+
+    #[test]
+    fn attempts_are_the_sum_of_the_outcomes() {
+        let stats = ScanStats::new();
+        stats.record_connect(ConnectOutcome::Succeeded);
+        stats.record_connect(ConnectOutcome::Refused);
+        stats.record_connect(ConnectOutcome::Refused);
+        stats.record_connect(ConnectOutcome::TimedOut);
+        stats.record_banner(BannerOutcome::Succeeded, 12);
+        stats.record_banner(BannerOutcome::TimedOut, 0);
+        stats.record_probe(false);
+        stats.record_probe(true);
+        stats.record_probe_failure("ladder tls", true);
+        stats.record_probe_failure("ladder tls", false);
+        stats.record_probe_failure("http", true);
+        let snapshot = stats.snapshot(0);
+        assert_eq!(snapshot.connects.attempted, 4);
+        assert_eq!(snapshot.connects.refused, 2);
+        assert_eq!(snapshot.banners.attempted, 2);
+        assert_eq!(snapshot.banners.bytes, 12);
+        assert_eq!(
+            snapshot.probes,
+            ProbeStats {
+                run: 2,
+                shared: 1,
+                reconnects: 1,
+                failures: [
+                    (
+                        "http".to_string(),
+                        ProbeFailures {
+                            timed_out: 1,
+                            failed: 0
+                        }
+                    ),
+                    (
+                        "ladder tls".to_string(),
+                        ProbeFailures {
+                            timed_out: 1,
+                            failed: 1
+                        }
+                    ),
+                ]
+                .into()
+            }
+        );
+        assert_eq!(
+            snapshot.to_string().lines().nth(2).unwrap(),
+            "probes       2 run: 1 on a shared connection, 1 reconnected; \
+             http 1 timed out, 0 failed; ladder tls 1 timed out, 1 failed"
+        );
+        assert_eq!(
+            snapshot.to_string().lines().next().unwrap(),
+            "connects     4 attempted: 1 succeeded, 2 refused, 1 timed out, 0 reset, 0 failed"
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! The hosts and ports a scan's command line names, worked out before it
+//! starts. Host names among the targets and exclusions are resolved once;
+//! a target that can't be scanned, being unresolvable, invalid or blocked,
+//! is reported and left out, so one typo doesn't cost the rest of the run.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::arp::{self, Discovery};
+use crate::audit;
+use crate::blocklist::Blocklist;
+use crate::discover::{self, Device};
+use crate::groups::PortGroups;
+use crate::ndp::{self, Neighbor};
+use crate::plan::{PortSet, ProtocolPorts, TargetSet};
+use crate::resolve::{is_host_name, Resolution, ResolveError, Resolver};
+use crate::scanner::{overlapping_targets, ScanError, ScannerBuilder, TargetReport, TargetStatus};
+use crate::spec::{SpecError, SpecErrorKind};
+
+/// Why a scan has nothing to scan, or refuses to start.
+#[derive(Error, Debug)]
+pub enum TargetError {
+    /// A host name no server could resolve, or a DoH server that can't be
+    /// used.
+    #[error(transparent)]
+    Unresolvable(#[from] ResolveError),
+    /// A target or port specification that can't be parsed.
+    #[error(transparent)]
+    Invalid(#[from] ScanError),
+    /// No target can be scanned; the error of the first.
+    #[error("{0}")]
+    Failed(String),
+    /// Targets naming blocked hosts on their own, which are not quietly
+    /// dropped.
+    #[error(
+        "Targets on the blocklist {}: {} (--skip-blocked leaves them out)",
+        .list.display(),
+        .targets.join(", ")
+    )]
+    Blocked {
+        /// The blocklist file.
+        list: PathBuf,
+        /// The targets.
+        targets: Vec<String>,
+    },
+}
+
+/// Where the hosts of a scan come from.
+#[derive(Debug, Clone, Default)]
+pub struct TargetOptions {
+    /// The targets, as given: addresses, CIDR blocks, ranges or host names.
+    pub targets: Vec<String>,
+    /// Hosts to leave out, in the same forms.
+    pub exclude: Vec<String>,
+    /// DNS servers asked instead of the system resolver.
+    pub resolvers: Vec<SocketAddr>,
+    /// How long each server gets to answer.
+    pub resolver_timeout: Duration,
+    /// DNS-over-HTTPS servers, asked after `resolvers`.
+    pub doh: Vec<String>,
+    /// Whether a target naming a blocked host on its own is left out
+    /// rather than refused.
+    pub skip_blocked: bool,
+    /// How long local discovery listens, when it runs.
+    pub local_discovery: Option<Duration>,
+    /// How long IPv6 neighbor discovery waits, when it runs.
+    pub ipv6_discovery: Option<Duration>,
+    /// The interface IPv6 neighbor discovery runs on; every one without.
+    pub ipv6_interface: Option<String>,
+}
+
+/// The targets of a scan, resolved.
+#[derive(Debug, Default)]
+pub struct Targets {
+    /// What became of each target, in the order given; a repeated one once.
+    pub reports: Vec<TargetReport>,
+    /// The host names among the targets and exclusions that resolved.
+    pub resolved: Vec<Resolution>,
+    /// Those no server could resolve.
+    pub unresolved: Vec<ResolveError>,
+    /// Devices local discovery found.
+    pub devices: BTreeMap<IpAddr, Device>,
+    /// Hosts IPv6 neighbor discovery found.
+    pub neighbors: Vec<Neighbor>,
+    /// Targets naming some of the same hosts, as [`overlapping_targets`]
+    /// gives them.
+    pub overlaps: Vec<(usize, usize, TargetSet)>,
+    /// Hosts left out for the blocklist.
+    pub blocked_hosts: u128,
+}
+
+impl Targets {
+    /// Resolves the targets of `options` and runs the discovery asked for,
+    /// adding the hosts to scan and those to leave out to `builder`.
+    ///
+    /// Fails when no target can be scanned and discovery found nothing, or
+    /// when a target on its own names a host of `blocklist` (given with its
+    /// path) and `skip_blocked` is off.
+    pub async fn resolve(
+        options: &TargetOptions,
+        blocklist: Option<(&Blocklist, &Path)>,
+        mut builder: ScannerBuilder,
+    ) -> Result<(Targets, ScannerBuilder), TargetError> {
+        let mut targets = Targets::default();
+        (targets.resolved, targets.unresolved) = resolve_names(options).await?;
+        if let Some(window) = options.local_discovery {
+            targets.devices = discover_devices(window).await;
+        }
+        if let Some(wait) = options.ipv6_discovery {
+            targets.neighbors = discover_neighbors(options.ipv6_interface.as_deref(), wait).await;
+        }
+        for t in &options.targets {
+            // Names are resolved once, so a repeated one is the same target.
+            if targets.reports.iter().any(|r| r.target == *t) {
+                info!("{} is given more than once; scanning it once", t);
+                continue;
+            }
+            if let Some(e) = targets.unresolved.iter().find(|e| e.name == *t) {
+                targets.reports.push(TargetReport::failed(
+                    t,
+                    TargetStatus::Unresolvable,
+                    e.to_string(),
+                ));
+            } else if is_host_name(t) {
+                let addresses = targets.addresses_of(t);
+                let mut hosts = TargetSet::new();
+                addresses.iter().for_each(|&a| hosts.insert_addr(a));
+                builder = addresses.iter().fold(builder, |b, &a| b.target_addr(a));
+                targets.reports.push(TargetReport::new(t, hosts, addresses));
+            } else {
+                let mut hosts = TargetSet::new();
+                match hosts.insert(t) {
+                    Ok(()) => {
+                        builder = builder.target(t);
+                        targets
+                            .reports
+                            .push(TargetReport::new(t, hosts, Vec::new()));
+                    }
+                    Err(e) => {
+                        targets.reports.push(TargetReport::failed(
+                            t,
+                            TargetStatus::Invalid,
+                            ScanError::InvalidTarget(e).to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        if let Some((blocklist, path)) = blocklist {
+            targets.block(blocklist, path, options.skip_blocked)?;
+        }
+        targets.overlaps = overlapping_targets(&targets.reports);
+        for (i, j, shared) in &targets.overlaps {
+            info!(
+                "{} and {} both name {}; scanning it once",
+                targets.reports[*i].target, targets.reports[*j].target, shared
+            );
+        }
+        targets.skip_failed()?;
+        builder = targets
+            .devices
+            .keys()
+            .fold(builder, |b, &a| b.target_addr(a));
+        builder = targets.neighbors.iter().fold(builder, |b, n| {
+            b.target_addr(n.address.into()).scope_id(n.address, n.index)
+        });
+        if let Some((blocklist, path)) = blocklist {
+            let mut requested = TargetSet::new();
+            targets
+                .reports
+                .iter()
+                .for_each(|t| requested.insert_all(&t.hosts));
+            targets
+                .devices
+                .keys()
+                .for_each(|&a| requested.insert_addr(a));
+            targets
+                .neighbors
+                .iter()
+                .for_each(|n| requested.insert_addr(n.address.into()));
+            targets.blocked_hosts = blocklist.blocked(&requested).len();
+            if targets.blocked_hosts > 0 {
+                info!(
+                    "Leaving out {} blocked hosts (--blocklist {})",
+                    targets.blocked_hosts,
+                    path.display()
+                );
+            }
+            builder = builder.exclude_hosts(blocklist.hosts());
+        }
+        let mut excluded_names = TargetSet::new();
+        for name in options.exclude.iter().filter(|t| is_host_name(t)) {
+            targets
+                .addresses_of(name)
+                .into_iter()
+                .for_each(|a| excluded_names.insert_addr(a));
+        }
+        let builder = options
+            .exclude
+            .iter()
+            .filter(|t| !is_host_name(t))
+            .fold(builder, |b, t| b.exclude_target(t))
+            .exclude_hosts(&excluded_names);
+        Ok((targets, builder))
+    }
+
+    /// The addresses `name` resolved to.
+    fn addresses_of(&self, name: &str) -> Vec<IpAddr> {
+        self.resolved
+            .iter()
+            .filter(|d| d.name == name)
+            .flat_map(|d| d.addresses())
+            .collect()
+    }
+
+    /// How the host names `given` as targets resolved, leaving out those
+    /// only excluded.
+    pub fn named(&self, given: &[String]) -> Vec<Resolution> {
+        self.resolved
+            .iter()
+            .filter(|d| given.contains(&d.name))
+            .cloned()
+            .collect()
+    }
+
+    /// Marks the targets whose hosts are all blocked. Blocked hosts go the
+    /// way of excluded ones, but a target naming one on its own was asked
+    /// for on purpose and isn't quietly dropped.
+    fn block(
+        &mut self,
+        blocklist: &Blocklist,
+        path: &Path,
+        skip_blocked: bool,
+    ) -> Result<(), TargetError> {
+        let mut refused = Vec::new();
+        for t in self
+            .reports
+            .iter_mut()
+            .filter(|t| t.status == TargetStatus::Resolved)
+        {
+            let blocked = blocklist.blocked(&t.hosts);
+            if blocked.is_empty() {
+                continue;
+            }
+            info!(target: audit::TARGET, target = %t.target, blocked = blocked.len() as u64, "target blocked");
+            if !skip_blocked && (is_host_name(&t.target) || t.hosts.len() == 1) {
+                refused.push(t.target.clone());
+            } else if blocked.len() == t.hosts.len() {
+                t.status = TargetStatus::Blocked;
+                t.error = Some(format!(
+                    "{} is on the blocklist {}",
+                    t.target,
+                    path.display()
+                ));
+            }
+        }
+        if !refused.is_empty() {
+            return Err(TargetError::Blocked {
+                list: path.to_path_buf(),
+                targets: refused,
+            });
+        }
+        Ok(())
+    }
+
+    /// Warns of the targets that can't be scanned, or fails with the first
+    /// one's error when that is all of them and discovery found nothing.
+    fn skip_failed(&self) -> Result<(), TargetError> {
+        let failed: Vec<&TargetReport> = self
+            .reports
+            .iter()
+            .filter(|t| t.status.is_failure() || t.status == TargetStatus::Blocked)
+            .collect();
+        if self.devices.is_empty()
+            && self.neighbors.is_empty()
+            && failed.len() == self.reports.len()
+        {
+            if let Some(first) = failed.first() {
+                // The error itself rather than its text, for the hint under it.
+                if let Some(e) = self.unresolved.iter().find(|e| e.name == first.target) {
+                    return Err(e.clone().into());
+                }
+                if first.status == TargetStatus::Invalid {
+                    if let Err(e) = TargetSet::new().insert(&first.target) {
+                        return Err(ScanError::InvalidTarget(e).into());
+                    }
+                }
+                return Err(TargetError::Failed(first.error.clone().unwrap_or_default()));
+            }
+        }
+        for t in failed {
+            warn!("{}; skipping it", t.error.as_deref().unwrap_or(&t.target));
+        }
+        Ok(())
+    }
+}
+
+/// The TCP ports of `ports` less those of `exclude`, with `@group`s of
+/// `groups` expanded. UDP ports are refused: they can't be scanned yet.
+pub fn tcp_ports(
+    ports: &str,
+    exclude: Option<&str>,
+    groups: &PortGroups,
+) -> Result<PortSet, ScanError> {
+    let mut parsed = ProtocolPorts::parse_with(ports, groups).map_err(ScanError::InvalidPorts)?;
+    parsed.remove_all(
+        &ProtocolPorts::parse_with(exclude.unwrap_or(""), groups)
+            .map_err(ScanError::InvalidPorts)?,
+    );
+    if !parsed.udp.is_empty() {
+        let udp = format!("U:{}", parsed.udp);
+        let at = ports.to_ascii_uppercase().find("U:").unwrap_or(0);
+        return Err(ScanError::InvalidPorts(
+            SpecError::new(SpecErrorKind::TcpOnly, &udp, at)
+                .with_hint("UDP scanning is not supported yet"),
+        ));
+    }
+    Ok(parsed.tcp)
+}
+
+/// Resolves the host names among the targets and exclusions of `options`
+/// with its resolvers and DoH servers, or the system resolver when there
+/// are none. Names no server could resolve are returned apart, to be
+/// skipped.
+async fn resolve_names(
+    options: &TargetOptions,
+) -> Result<(Vec<Resolution>, Vec<ResolveError>), ResolveError> {
+    let names: Vec<&String> = options
+        .targets
+        .iter()
+        .chain(&options.exclude)
+        .filter(|t| is_host_name(t))
+        .collect();
+    if names.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let mut resolver = Resolver::servers(&options.resolvers, options.resolver_timeout);
+    for url in &options.doh {
+        resolver.add_doh(url).await?;
+    }
+    let mut found = Vec::new();
+    let mut failed = Vec::new();
+    for name in names {
+        let resolution = match resolver.resolve(name).await {
+            Ok(resolution) => resolution,
+            Err(e) => {
+                if !options.targets.contains(name) {
+                    warn!("{}; not excluding it", e);
+                }
+                failed.push(e);
+                continue;
+            }
+        };
+        info!(
+            "Resolved {} to {}",
+            name,
+            resolution
+                .addresses()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        found.push(resolution);
+    }
+    Ok((found, failed))
+}
+
+/// Runs ARP discovery over `targets`. When it can't run, says why and
+/// returns `None`, so that every host is scanned.
+pub async fn discover_hosts(targets: &TargetSet, wait: Duration) -> Option<Discovery> {
+    match arp::discover(targets, wait).await {
+        Ok(found) => {
+            for (ip, mac) in &found.found {
+                debug!(
+                    "ARP: {} is at {} ({})",
+                    ip,
+                    mac,
+                    mac.vendor().unwrap_or("unknown vendor")
+                );
+            }
+            info!(
+                "ARP: {} local hosts answered, {} didn't",
+                found.found.len(),
+                found.silent.len()
+            );
+            Some(found)
+        }
+        Err(e) => {
+            warn!("{}; scanning every host", e);
+            None
+        }
+    }
+}
+
+/// Leaves the local hosts that didn't answer ARP out of `builder`.
+pub fn without_silent(builder: ScannerBuilder, arp: &Discovery) -> ScannerBuilder {
+    if arp.silent.is_empty() {
+        return builder;
+    }
+    info!(
+        "Leaving out {} local hosts that didn't answer ARP (--no-ping scans them)",
+        arp.silent.len()
+    );
+    let mut silent = TargetSet::new();
+    arp.silent
+        .iter()
+        .for_each(|&ip| silent.insert_addr(ip.into()));
+    builder.exclude_hosts(&silent)
+}
+
+/// Runs local discovery. When it can't run, says why and returns no
+/// devices, so that only the given targets are scanned.
+async fn discover_devices(window: Duration) -> BTreeMap<IpAddr, Device> {
+    match discover::discover(window).await {
+        Ok(found) => {
+            for (ip, device) in &found {
+                debug!("Discovered {}: {}", ip, device);
+            }
+            info!("Local discovery found {} devices", found.len());
+            found
+        }
+        Err(e) => {
+            warn!("{}; scanning the given targets only", e);
+            BTreeMap::new()
+        }
+    }
+}
+
+/// Runs IPv6 neighbor discovery. When it can't run, says why and returns
+/// no hosts, so that only the given targets are scanned.
+async fn discover_neighbors(interface: Option<&str>, wait: Duration) -> Vec<Neighbor> {
+    match ndp::discover(interface, wait).await {
+        Ok(found) => {
+            for n in &found {
+                match n.mac {
+                    Some(mac) => debug!(
+                        "NDP: {}%{} is at {} ({})",
+                        n.address,
+                        n.interface,
+                        mac,
+                        mac.vendor().unwrap_or("unknown vendor")
+                    ),
+                    None => debug!("NDP: {}%{} answered", n.address, n.interface),
+                }
+            }
+            info!("IPv6 neighbor discovery found {} hosts", found.len());
+            found
+        }
+        Err(e) => {
+            warn!("{}; scanning the given targets only", e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn options(targets: &[&str]) -> TargetOptions {
+        TargetOptions {
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            ..TargetOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_targets_are_reported_and_left_out() {
+        let (targets, builder) = Targets::resolve(
+            &options(&["10.0.0.1", "10.0.0.300", "10.0.0.1"]),
+            None,
+            Scanner::builder(),
+        )
+        .await
+        .unwrap();
+        let statuses: Vec<_> = targets.reports.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [TargetStatus::Resolved, TargetStatus::Invalid],
+            "a repeated target is reported once"
+        );
+        let scanner = builder.ports("80").build().unwrap();
+        assert_eq!(scanner.config().targets.to_string(), "10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn no_target_left_is_the_first_ones_error() {
+        let Err(e) = Targets::resolve(&options(&["10.0.0.300"]), None, Scanner::builder()).await
+        else {
+            panic!("resolved a target that isn't one");
+        };
+        assert!(matches!(
+            e,
+            TargetError::Invalid(ScanError::InvalidTarget(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_blocked_host_named_on_its_own_is_refused_unless_skipped() {
+        let blocklist = Blocklist::parse("blocked.txt", "10.0.0.5\n").unwrap();
+        let list = Path::new("blocked.txt");
+        let mut asked = options(&["10.0.0.5", "10.0.0.0/29"]);
+        let Err(e) = Targets::resolve(&asked, Some((&blocklist, list)), Scanner::builder()).await
+        else {
+            panic!("scanned a blocked host");
+        };
+        assert_eq!(
+            e.to_string(),
+            "Targets on the blocklist blocked.txt: 10.0.0.5 (--skip-blocked leaves them out)"
+        );
+
+        asked.skip_blocked = true;
+        let (targets, builder) =
+            Targets::resolve(&asked, Some((&blocklist, list)), Scanner::builder())
+                .await
+                .unwrap();
+        assert_eq!(targets.reports[0].status, TargetStatus::Blocked);
+        assert_eq!(targets.blocked_hosts, 1);
+        let scanner = builder.ports("80").build().unwrap();
+        assert!(!scanner
+            .config()
+            .targets
+            .contains("10.0.0.5".parse().unwrap()));
+    }
+
+    #[test]
+    fn udp_ports_are_refused() {
+        let groups = PortGroups::new();
+        assert_eq!(
+            tcp_ports("1-100", Some("50-100"), &groups)
+                .unwrap()
+                .to_string(),
+            "1-49"
+        );
+        let e = tcp_ports("80,U:53", None, &groups).unwrap_err();
+        assert!(
+            matches!(&e, ScanError::InvalidPorts(e) if e.kind == SpecErrorKind::TcpOnly),
+            "{e}"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! File name templates for per-host output files.
+
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const PLACEHOLDERS: [&str; 4] = ["target", "date", "time", "scan_id"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+/// A `--filename-template` such as `{target}_{date}.json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilenameTemplate {
+    segments: Vec<Segment>,
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                segments.push(Segment::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed '{{' in '{}'", s))?;
+            let name = &rest[open + 1..open + close];
+            let placeholder = PLACEHOLDERS.iter().find(|p| **p == name).ok_or_else(|| {
+                format!(
+                    "unknown placeholder '{{{}}}' (expected one of: {})",
+                    name,
+                    PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                )
+            })?;
+            segments.push(Segment::Placeholder(placeholder));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        if segments.is_empty() {
+            return Err("filename template is empty".to_string());
+        }
+        Ok(FilenameTemplate { segments })
+    }
+}
+
+impl FilenameTemplate {
+    /// Expands the template for one host; substituted values are sanitized so
+    /// they can't introduce separators or characters invalid on any platform.
+    pub fn render(&self, target: &str, scan_id: &str, started: DateTime<Utc>) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => out.push_str(s),
+                Segment::Placeholder(name) => {
+                    let value = match *name {
+                        "target" => target.to_string(),
+                        "date" => started.format("%Y-%m-%d").to_string(),
+                        "time" => started.format("%H%M%S").to_string(),
+                        _ => scan_id.to_string(),
+                    };
+                    out.push_str(&sanitize(&value));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Replaces characters that are invalid in file names (colons from IPv6
+/// addresses in particular) with underscores.
+pub fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Assigns each name a unique path under `dir`, suffixing repeats as
+/// `name-1.ext`, `name-2.ext`, ... in input order. A path `exists` says is
+/// there already, such as a report of an earlier run, counts as taken.
+pub fn unique_paths(dir: &Path, names: &[String], exists: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut out = Vec::with_capacity(names.len());
+    for name in names {
+        let mut candidate = name.clone();
+        let count = seen.entry(name.clone()).or_insert(0);
+        while taken.contains(&candidate) || exists(&dir.join(&candidate)) {
+            *count += 1;
+            candidate = with_suffix(name, *count);
+        }
+        out.push(dir.join(&candidate));
+        taken.insert(candidate);
+    }
+    out
+}
+
+fn with_suffix(name: &str, n: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &name[..dot], n, &name[dot..]),
+        _ => format!("{}-{}", name, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn started() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap()
+    }
+
+    #[test]
+    fn renders_all_placeholders() {
+        let t: FilenameTemplate = "{target}_{date}_{time}_{scan_id}.json".parse().unwrap();
+        assert_eq!(
+            t.render("10.0.0.1", "abc", started()),
+            "10.0.0.1_2024-03-09_140507_abc.json"
+        );
+    }
+
+    #[test]
+    fn sanitizes_ipv6_targets() {
+        let t: FilenameTemplate = "{target}.json".parse().unwrap();
+        assert_eq!(t.render("fe80::1", "x", started()), "fe80__1.json");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!("{host}.json".parse::<FilenameTemplate>().is_err());
+        assert!("{target.json".parse::<FilenameTemplate>().is_err());
+        assert!("".parse::<FilenameTemplate>().is_err());
+    }
+
+    #[test]
+    fn suffixes_collisions() {
+        let names = vec![
+            "a.json".to_string(),
+            "a.json".to_string(),
+            "b.json".to_string(),
+            "a.json".to_string(),
+        ];
+        let paths = unique_paths(Path::new("out"), &names, |_| false);
+        let names: Vec<_> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(
+            names,
+            ["out/a.json", "out/a-1.json", "out/b.json", "out/a-2.json"]
+        );
+    }
+
+    #[test]
+    fn existing_files_are_not_taken_over() {
+        let names = vec!["a.json".to_string(), "a.json".to_string()];
+        let earlier = [Path::new("out/a.json"), Path::new("out/a-2.json")];
+        let paths = unique_paths(Path::new("out"), &names, |p| earlier.contains(&p));
+        let names: Vec<_> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(names, ["out/a-1.json", "out/a-3.json"]);
+    }
+}
@@ -0,0 +1,150 @@
+//! Connect timeouts for particular ports, from `--port-timeout
+//! 1433:3s,3389:2s`, for the services that need longer than the rest of the
+//! scan.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::duration::parse_millis;
+use crate::plan::PortSet;
+use crate::spec;
+
+/// Connect timeouts overriding the scan's own for some ports or ranges.
+///
+/// Where overrides overlap, the most specific one wins: the narrowest range,
+/// so a single port beats any range around it, and between ranges of the
+/// same width the one given last.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortTimeouts {
+    overrides: Vec<(RangeInclusive<u16>, Duration)>,
+}
+
+impl PortTimeouts {
+    /// No overrides: every port gets the scan's timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an override for `ports`.
+    pub fn insert(&mut self, ports: RangeInclusive<u16>, timeout: Duration) {
+        self.overrides.push((ports, timeout));
+    }
+
+    /// Whether there are no overrides.
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty()
+    }
+
+    /// The timeout of the most specific override covering `port`, if any.
+    pub fn get(&self, port: u16) -> Option<Duration> {
+        self.overrides
+            .iter()
+            .rev()
+            .filter(|(ports, _)| ports.contains(&port))
+            .min_by_key(|(ports, _)| ports.end() - ports.start())
+            .map(|&(_, timeout)| timeout)
+    }
+
+    /// The connect timeout for `port`: its override or else `default`.
+    pub fn resolve(&self, port: u16, default: Duration) -> Duration {
+        self.get(port).unwrap_or(default)
+    }
+
+    /// Whether any override is zero.
+    pub fn has_zero(&self) -> bool {
+        self.overrides.iter().any(|(_, timeout)| timeout.is_zero())
+    }
+
+    /// The overridden ranges with no port in `ports`, which will never apply.
+    pub fn unused<'a>(
+        &'a self,
+        ports: &'a PortSet,
+    ) -> impl Iterator<Item = &'a RangeInclusive<u16>> + 'a {
+        self.overrides
+            .iter()
+            .map(|(range, _)| range)
+            .filter(|&range| !range.clone().any(|port| ports.contains(port)))
+    }
+}
+
+impl FromStr for PortTimeouts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut timeouts = PortTimeouts::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (ports, timeout) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' is not PORTS:TIMEOUT, e.g. 1433:3s", entry))?;
+            let timeout = parse_millis(timeout).map_err(|e| e.to_string())?;
+            let ranges = spec::port_ranges(ports).map_err(|e| e.to_string())?;
+            if ranges.is_empty() {
+                return Err(format!("'{}' names no port", entry));
+            }
+            for range in ranges {
+                timeouts.insert(range, timeout);
+            }
+        }
+        Ok(timeouts)
+    }
+}
+
+impl fmt::Display for PortTimeouts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (ports, timeout)) in self.overrides.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if ports.start() == ports.end() {
+                write!(f, "{}", ports.start())?;
+            } else {
+                write!(f, "{}-{}", ports.start(), ports.end())?;
+            }
+            write!(f, ":{}ms", timeout.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_most_specific_override_wins() {
+        let timeouts: PortTimeouts =
+            "1000-2000:1s, 1433:3s, 1400-1500:2s, 1-65535:5s, 1450-1550:4s"
+                .parse()
+                .unwrap();
+        let ms = Duration::from_millis;
+        let default = ms(800);
+        assert_eq!(timeouts.resolve(1433, default), ms(3000));
+        assert_eq!(timeouts.resolve(1420, default), ms(2000));
+        // Two ranges of the same width: the later one.
+        assert_eq!(timeouts.resolve(1460, default), ms(4000));
+        assert_eq!(timeouts.resolve(1999, default), ms(1000));
+        assert_eq!(timeouts.resolve(22, default), ms(5000));
+        assert_eq!(PortTimeouts::new().resolve(22, default), default);
+        assert_eq!(
+            timeouts.to_string(),
+            "1000-2000:1000ms,1433:3000ms,1400-1500:2000ms,1-65535:5000ms,1450-1550:4000ms"
+        );
+    }
+
+    #[test]
+    fn overrides_outside_the_scan_are_reported() {
+        let timeouts: PortTimeouts = "22:2s,3389:2s,8000-8100:1500".parse().unwrap();
+        let ports = PortSet::parse("1-1024,8080").unwrap();
+        let unused: Vec<_> = timeouts.unused(&ports).collect();
+        assert_eq!(unused, [&(3389..=3389)]);
+        assert_eq!(timeouts.get(8050), Some(Duration::from_millis(1500)));
+
+        assert!("1433".parse::<PortTimeouts>().is_err());
+        assert!("1433:soon".parse::<PortTimeouts>().is_err());
+        assert!("70000:1s".parse::<PortTimeouts>().is_err());
+        assert!("2000-1000:1s".parse::<PortTimeouts>().is_err());
+        assert!("1433:0".parse::<PortTimeouts>().unwrap().has_zero());
+    }
+}
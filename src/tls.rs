@@ -0,0 +1,42 @@
+//! TLS client connections for `--smtp-starttls` and `--upload-s3`, checked
+//! against the bundled web roots. Needs the `tls` feature.
+
+use std::io;
+
+use crate::connector::Connection;
+
+/// Whether this build can make TLS connections.
+pub const SUPPORTED: bool = cfg!(feature = "tls");
+
+/// Runs a TLS handshake over `stream` with the server `host`, verifying its
+/// certificate, and returns the encrypted connection.
+#[cfg(feature = "tls")]
+pub async fn connect(stream: Connection, host: &str) -> io::Result<Connection> {
+    use std::sync::Arc;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{crypto, ClientConfig, RootCertStore};
+
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await?;
+    Ok(Box::new(stream))
+}
+
+/// Fails: this build has no TLS.
+#[cfg(not(feature = "tls"))]
+pub async fn connect(_stream: Connection, _host: &str) -> io::Result<Connection> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without the tls feature",
+    ))
+}
@@ -0,0 +1,631 @@
+//! `port-scanner tls-report`: the TLS of every port that has it, side by
+//! side, so that the odd one stands out: the host still accepting TLS 1.0,
+//! the one behind the others on the newest version, a certificate about to
+//! expire, a short key.
+//!
+//! The scanner doesn't negotiate TLS itself. The details come with each
+//! result's [`TlsDetails`], which an nmap report fills from the output of
+//! its `ssl-enum-ciphers`, `ssl-cert` and `tls-alpn` scripts; everything
+//! here works on results already collected.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::scanner::PortResult;
+
+/// Days within which an expiring certificate stands out, by default.
+pub const DEFAULT_EXPIRING_DAYS: u32 = 30;
+
+/// The oldest version a port may accept without standing out.
+const CURRENT: TlsVersion = TlsVersion::Tls12;
+
+/// Bits below which a key of the type is short; keys of other types aren't
+/// judged.
+const MIN_KEY_BITS: &[(&str, u32)] = &[("rsa", 2048), ("dsa", 2048), ("dh", 2048), ("ec", 224)];
+
+/// A version of SSL or TLS, named as nmap names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TlsVersion {
+    /// SSL 3.0.
+    #[serde(rename = "SSLv3")]
+    Ssl3,
+    /// TLS 1.0.
+    #[serde(rename = "TLSv1.0")]
+    Tls10,
+    /// TLS 1.1.
+    #[serde(rename = "TLSv1.1")]
+    Tls11,
+    /// TLS 1.2.
+    #[serde(rename = "TLSv1.2")]
+    Tls12,
+    /// TLS 1.3.
+    #[serde(rename = "TLSv1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    const ALL: [TlsVersion; 5] = [
+        TlsVersion::Ssl3,
+        TlsVersion::Tls10,
+        TlsVersion::Tls11,
+        TlsVersion::Tls12,
+        TlsVersion::Tls13,
+    ];
+
+    /// The version's name, such as `TLSv1.2`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TlsVersion::Ssl3 => "SSLv3",
+            TlsVersion::Tls10 => "TLSv1.0",
+            TlsVersion::Tls11 => "TLSv1.1",
+            TlsVersion::Tls12 => "TLSv1.2",
+            TlsVersion::Tls13 => "TLSv1.3",
+        }
+    }
+
+    /// The version named `name`, if it is one.
+    pub fn from_name(name: &str) -> Option<TlsVersion> {
+        TlsVersion::ALL.into_iter().find(|v| v.name() == name)
+    }
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What is known of the TLS of a port; any of it may be missing when the
+/// script that finds it out didn't run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsDetails {
+    /// The versions the port accepted, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<TlsVersion>,
+    /// Who issued the certificate: its common name, else its organization.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// When the certificate expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<DateTime<Utc>>,
+    /// The type of the certificate's key, such as `rsa` or `ec`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+    /// The size of the certificate's key in bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_bits: Option<u32>,
+    /// The protocols the port offered over ALPN, such as `h2`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alpn: Vec<String>,
+}
+
+/// Why a port stands out from the others.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outlier {
+    /// It accepts a version older than TLS 1.2.
+    Legacy {
+        /// The oldest version it accepts.
+        version: TlsVersion,
+    },
+    /// Its newest version is older than that of most of the ports.
+    Behind {
+        /// Its newest version.
+        version: TlsVersion,
+        /// The newest version of most of the ports.
+        others: TlsVersion,
+    },
+    /// Its certificate has expired.
+    Expired {
+        /// Whole days since.
+        days: i64,
+    },
+    /// Its certificate expires within the report's window.
+    Expiring {
+        /// Whole days until it does.
+        days: i64,
+    },
+    /// Its certificate's key is shorter than its type should have.
+    ShortKey {
+        /// The size of the key.
+        bits: u32,
+    },
+}
+
+impl fmt::Display for Outlier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outlier::Legacy { version } => write!(f, "accepts {}", version),
+            Outlier::Behind { version, others } => {
+                write!(f, "tops out at {} where most reach {}", version, others)
+            }
+            Outlier::Expired { days } => write!(f, "certificate expired {} days ago", days),
+            Outlier::Expiring { days } => write!(f, "certificate expires in {} days", days),
+            Outlier::ShortKey { bits } => write!(f, "{}-bit key", bits),
+        }
+    }
+}
+
+/// A column of the report an outlier is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Min,
+    Max,
+    Expires,
+    Key,
+}
+
+impl Outlier {
+    fn column(&self) -> Column {
+        match self {
+            Outlier::Legacy { .. } => Column::Min,
+            Outlier::Behind { .. } => Column::Max,
+            Outlier::Expired { .. } | Outlier::Expiring { .. } => Column::Expires,
+            Outlier::ShortKey { .. } => Column::Key,
+        }
+    }
+}
+
+/// The TLS of one port, as a row of the report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsEndpoint {
+    /// Host the port belongs to.
+    pub target: IpAddr,
+    /// The port.
+    pub port: u16,
+    /// The oldest version the port accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<TlsVersion>,
+    /// The newest version the port accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_version: Option<TlsVersion>,
+    /// Who issued the certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// When the certificate expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<DateTime<Utc>>,
+    /// Whole days from the report's time until the certificate expires;
+    /// negative once it has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_left: Option<i64>,
+    /// The type of the certificate's key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+    /// The size of the certificate's key in bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_bits: Option<u32>,
+    /// The protocols offered over ALPN.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alpn: Vec<String>,
+    /// Why the port stands out, if it does.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outliers: Vec<Outlier>,
+}
+
+impl TlsEndpoint {
+    fn flagged(&self, column: Column) -> bool {
+        self.outliers.iter().any(|o| o.column() == column)
+    }
+
+    fn cells(&self) -> [(String, Option<Column>); 8] {
+        let or_dash = |s: Option<String>| s.unwrap_or_else(|| "-".to_string());
+        let expires = self.not_after.map(|at| match self.days_left {
+            Some(days) if days < 0 => format!("{} (expired)", at.format("%Y-%m-%d")),
+            Some(days) => format!("{} ({}d)", at.format("%Y-%m-%d"), days),
+            None => at.format("%Y-%m-%d").to_string(),
+        });
+        let key = match (&self.key_type, self.key_bits) {
+            (Some(kind), Some(bits)) => Some(format!("{} {}", kind, bits)),
+            (Some(kind), None) => Some(kind.clone()),
+            (None, Some(bits)) => Some(bits.to_string()),
+            (None, None) => None,
+        };
+        [
+            (self.target.to_string(), None),
+            (self.port.to_string(), None),
+            (
+                or_dash(self.min_version.map(|v| v.to_string())),
+                Some(Column::Min),
+            ),
+            (
+                or_dash(self.max_version.map(|v| v.to_string())),
+                Some(Column::Max),
+            ),
+            (or_dash(self.issuer.clone()), None),
+            (or_dash(expires), Some(Column::Expires)),
+            (or_dash(key), Some(Column::Key)),
+            (
+                match self.alpn.as_slice() {
+                    [] => "-".to_string(),
+                    alpn => alpn.join(","),
+                },
+                None,
+            ),
+        ]
+    }
+
+    fn notes(&self) -> String {
+        let notes: Vec<String> = self.outliers.iter().map(Outlier::to_string).collect();
+        notes.join("; ")
+    }
+}
+
+/// The TLS of the ports of a scan, compared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsReport {
+    /// The time the certificates were checked against.
+    pub at: DateTime<Utc>,
+    /// Days within which an expiring certificate stands out.
+    pub expiring_days: u32,
+    /// One row per port with TLS details, by host and port.
+    pub endpoints: Vec<TlsEndpoint>,
+}
+
+const HEADERS: [&str; 8] = [
+    "HOST", "PORT", "MIN", "MAX", "ISSUER", "EXPIRES", "KEY", "ALPN",
+];
+
+impl TlsReport {
+    /// Compares the TLS of those of `results` that have it, checking the
+    /// certificates against `at`.
+    pub fn of(results: &[PortResult], at: DateTime<Utc>, expiring_days: u32) -> TlsReport {
+        let mut endpoints: Vec<TlsEndpoint> = results
+            .iter()
+            .filter_map(|r| {
+                let tls = r.tls.as_ref()?;
+                Some(TlsEndpoint {
+                    target: r.target,
+                    port: r.port,
+                    min_version: tls.versions.iter().min().copied(),
+                    max_version: tls.versions.iter().max().copied(),
+                    issuer: tls.issuer.clone(),
+                    not_after: tls.not_after,
+                    days_left: tls.not_after.map(|end| (end - at).num_days()),
+                    key_type: tls.key_type.clone(),
+                    key_bits: tls.key_bits,
+                    alpn: tls.alpn.clone(),
+                    outliers: Vec::new(),
+                })
+            })
+            .collect();
+        endpoints.sort_by_key(|e| (e.target, e.port));
+
+        let most_reach = most_common(endpoints.iter().filter_map(|e| e.max_version));
+        for e in &mut endpoints {
+            if let Some(version) = e.min_version.filter(|&v| v < CURRENT) {
+                e.outliers.push(Outlier::Legacy { version });
+            }
+            if let (Some(version), Some(others)) = (e.max_version, most_reach) {
+                if version < others {
+                    e.outliers.push(Outlier::Behind { version, others });
+                }
+            }
+            match (e.not_after, e.days_left) {
+                (Some(end), _) if end <= at => e.outliers.push(Outlier::Expired {
+                    days: (at - end).num_days(),
+                }),
+                (_, Some(days)) if days < i64::from(expiring_days) => {
+                    e.outliers.push(Outlier::Expiring { days })
+                }
+                _ => {}
+            }
+            if let (Some(kind), Some(bits)) = (e.key_type.as_deref(), e.key_bits) {
+                if MIN_KEY_BITS.iter().any(|&(k, min)| k == kind && bits < min) {
+                    e.outliers.push(Outlier::ShortKey { bits });
+                }
+            }
+        }
+        TlsReport {
+            at,
+            expiring_days,
+            endpoints,
+        }
+    }
+
+    /// The rows of `target` alone, for its own report.
+    pub fn for_target(&self, target: IpAddr) -> TlsReport {
+        TlsReport {
+            at: self.at,
+            expiring_days: self.expiring_days,
+            endpoints: self
+                .endpoints
+                .iter()
+                .filter(|e| e.target == target)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// How many ports stand out.
+    pub fn outliers(&self) -> usize {
+        self.endpoints
+            .iter()
+            .filter(|e| !e.outliers.is_empty())
+            .count()
+    }
+
+    /// The report as a Markdown table, the cells that stand out in bold.
+    pub fn markdown(&self) -> String {
+        let mut out = self.headline() + "\n";
+        if self.endpoints.is_empty() {
+            return out;
+        }
+        out.push('\n');
+        let header: Vec<String> = HEADERS.iter().map(|h| title(h)).collect();
+        out.push_str(&format!("| {} | Notes |\n", header.join(" | ")));
+        out.push_str(&format!("|{}---|\n", "---|".repeat(HEADERS.len())));
+        for e in &self.endpoints {
+            let cells: Vec<String> = e
+                .cells()
+                .into_iter()
+                .map(|(text, column)| {
+                    let text = text.replace('|', "\\|");
+                    match column {
+                        Some(column) if e.flagged(column) => format!("**{}**", text),
+                        _ => text,
+                    }
+                })
+                .collect();
+            out.push_str(&format!(
+                "| {} | {} |\n",
+                cells.join(" | "),
+                e.notes().replace('|', "\\|")
+            ));
+        }
+        out
+    }
+
+    fn headline(&self) -> String {
+        format!(
+            "{} TLS ports, {} standing out (certificates checked against {}, expiring within {} days)",
+            self.endpoints.len(),
+            self.outliers(),
+            self.at.format("%Y-%m-%d %H:%M"),
+            self.expiring_days
+        )
+    }
+}
+
+/// The value most of `values` have, unless another is as common.
+fn most_common<T: Copy + Eq + std::hash::Hash>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: HashMap<T, usize> = HashMap::new();
+    for v in values {
+        *counts.entry(v).or_default() += 1;
+    }
+    let top = counts.values().copied().max()?;
+    let mut tops = counts.into_iter().filter(|&(_, n)| n == top);
+    match (tops.next(), tops.next()) {
+        (Some((value, _)), None) => Some(value),
+        _ => None,
+    }
+}
+
+/// `ISSUER` as `Issuer`, and `ALPN` as it is.
+fn title(header: &str) -> String {
+    match header {
+        "ALPN" => header.to_string(),
+        _ => header[..1].to_string() + &*header[1..].to_lowercase(),
+    }
+}
+
+/// An aligned table, a `!` after each cell that stands out and the reasons
+/// at the end of the line.
+impl fmt::Display for TlsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.headline())?;
+        if self.endpoints.is_empty() {
+            return Ok(());
+        }
+        let rows: Vec<Vec<String>> = self
+            .endpoints
+            .iter()
+            .map(|e| {
+                e.cells()
+                    .into_iter()
+                    .map(|(text, column)| match column {
+                        Some(column) if e.flagged(column) => text + "!",
+                        _ => text,
+                    })
+                    .collect()
+            })
+            .collect();
+        let widths: Vec<usize> = HEADERS
+            .iter()
+            .enumerate()
+            .map(|(i, h)| rows.iter().map(|r| r[i].len()).fold(h.len(), usize::max))
+            .collect();
+        let line = |f: &mut fmt::Formatter, cells: &[&str], notes: &str| {
+            let mut text = String::new();
+            for (cell, width) in cells.iter().zip(&widths) {
+                text.push_str(&format!("{:<width$}  ", cell, width = width));
+            }
+            text.push_str(notes);
+            writeln!(f, "{}", text.trim_end())
+        };
+        line(f, &HEADERS, "NOTES")?;
+        for (row, e) in rows.iter().zip(&self.endpoints) {
+            let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+            line(f, &cells, &e.notes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+    use chrono::TimeZone;
+
+    fn day(month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, month, day, 0, 0, 0).unwrap()
+    }
+
+    fn port(host: &str, port: u16, tls: TlsDetails) -> PortResult {
+        let mut r = PortResult::new(host.parse().unwrap(), port, PortStatus::Open);
+        r.tls = Some(tls);
+        r
+    }
+
+    fn modern(issuer: &str, not_after: DateTime<Utc>) -> TlsDetails {
+        TlsDetails {
+            versions: vec![TlsVersion::Tls12, TlsVersion::Tls13],
+            issuer: Some(issuer.to_string()),
+            not_after: Some(not_after),
+            key_type: Some("rsa".to_string()),
+            key_bits: Some(2048),
+            alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+        }
+    }
+
+    /// Four web servers behind the same CA, and the odd ones among them.
+    fn fleet() -> Vec<PortResult> {
+        let legacy = TlsDetails {
+            versions: vec![TlsVersion::Tls10, TlsVersion::Tls11, TlsVersion::Tls12],
+            alpn: Vec::new(),
+            ..modern("R3", day(9, 1))
+        };
+        let short = TlsDetails {
+            key_bits: Some(1024),
+            ..modern("Internal CA", day(3, 20))
+        };
+        vec![
+            port("10.0.0.7", 443, modern("R3", day(2, 1))),
+            port("10.0.0.5", 443, modern("R3", day(9, 1))),
+            port("10.0.0.6", 443, legacy),
+            port("10.0.0.6", 22, TlsDetails::default()),
+            port("10.0.0.8", 8443, short),
+            PortResult::new("10.0.0.9".parse().unwrap(), 80, PortStatus::Open),
+        ]
+    }
+
+    #[test]
+    fn the_odd_ones_out_are_found() {
+        let report = TlsReport::of(&fleet(), day(3, 10), DEFAULT_EXPIRING_DAYS);
+
+        let outliers: Vec<(String, u16, Vec<Outlier>)> = report
+            .endpoints
+            .iter()
+            .map(|e| (e.target.to_string(), e.port, e.outliers.clone()))
+            .collect();
+        assert_eq!(
+            outliers,
+            [
+                ("10.0.0.5".to_string(), 443, vec![]),
+                ("10.0.0.6".to_string(), 22, vec![]),
+                (
+                    "10.0.0.6".to_string(),
+                    443,
+                    vec![
+                        Outlier::Legacy {
+                            version: TlsVersion::Tls10
+                        },
+                        Outlier::Behind {
+                            version: TlsVersion::Tls12,
+                            others: TlsVersion::Tls13
+                        },
+                    ]
+                ),
+                (
+                    "10.0.0.7".to_string(),
+                    443,
+                    vec![Outlier::Expired { days: 37 }]
+                ),
+                (
+                    "10.0.0.8".to_string(),
+                    8443,
+                    vec![
+                        Outlier::Expiring { days: 10 },
+                        Outlier::ShortKey { bits: 1024 }
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(report.outliers(), 3);
+        assert_eq!(report.endpoints[0].days_left, Some(175));
+        assert_eq!(report.endpoints[3].days_left, Some(-37));
+    }
+
+    #[test]
+    fn no_version_is_the_norm_when_two_are_as_common() {
+        let results = [
+            port("10.0.0.1", 443, modern("R3", day(9, 1))),
+            port(
+                "10.0.0.2",
+                443,
+                TlsDetails {
+                    versions: vec![TlsVersion::Tls12],
+                    ..modern("R3", day(9, 1))
+                },
+            ),
+        ];
+        let report = TlsReport::of(&results, day(3, 10), DEFAULT_EXPIRING_DAYS);
+        assert_eq!(report.outliers(), 0);
+    }
+
+    #[test]
+    fn the_table_marks_the_cells_that_stand_out() {
+        let report = TlsReport::of(&fleet(), day(3, 10), DEFAULT_EXPIRING_DAYS);
+        assert_eq!(
+            report.to_string(),
+            "5 TLS ports, 3 standing out (certificates checked against 2026-03-10 00:00, expiring within 30 days)\n\
+             HOST      PORT  MIN       MAX       ISSUER       EXPIRES                KEY        ALPN         NOTES\n\
+             10.0.0.5  443   TLSv1.2   TLSv1.3   R3           2026-09-01 (175d)      rsa 2048   h2,http/1.1\n\
+             10.0.0.6  22    -         -         -            -                      -          -\n\
+             10.0.0.6  443   TLSv1.0!  TLSv1.2!  R3           2026-09-01 (175d)      rsa 2048   -            \
+             accepts TLSv1.0; tops out at TLSv1.2 where most reach TLSv1.3\n\
+             10.0.0.7  443   TLSv1.2   TLSv1.3   R3           2026-02-01 (expired)!  rsa 2048   h2,http/1.1  \
+             certificate expired 37 days ago\n\
+             10.0.0.8  8443  TLSv1.2   TLSv1.3   Internal CA  2026-03-20 (10d)!      rsa 1024!  h2,http/1.1  \
+             certificate expires in 10 days; 1024-bit key\n"
+        );
+    }
+
+    #[test]
+    fn markdown_bolds_the_cells_that_stand_out() {
+        let results = [
+            port("10.0.0.5", 443, modern("R3", day(9, 1))),
+            port(
+                "10.0.0.6",
+                443,
+                TlsDetails {
+                    issuer: Some("A|B".to_string()),
+                    ..modern("R3", day(3, 1))
+                },
+            ),
+        ];
+        let report = TlsReport::of(&results, day(3, 10), 30);
+        assert_eq!(
+            report.markdown(),
+            "2 TLS ports, 1 standing out (certificates checked against 2026-03-10 00:00, expiring within 30 days)\n\
+             \n\
+             | Host | Port | Min | Max | Issuer | Expires | Key | ALPN | Notes |\n\
+             |---|---|---|---|---|---|---|---|---|\n\
+             | 10.0.0.5 | 443 | TLSv1.2 | TLSv1.3 | R3 | 2026-09-01 (175d) | rsa 2048 | h2,http/1.1 |  |\n\
+             | 10.0.0.6 | 443 | TLSv1.2 | TLSv1.3 | A\\|B | **2026-03-01 (expired)** | rsa 2048 | h2,http/1.1 | \
+             certificate expired 9 days ago |\n"
+        );
+    }
+
+    #[test]
+    fn details_round_trip_through_json() {
+        let details = modern("R3", day(9, 1));
+        let json = serde_json::to_value(&details).unwrap();
+        assert_eq!(json["versions"], serde_json::json!(["TLSv1.2", "TLSv1.3"]));
+        assert_eq!(json["not_after"], "2026-09-01T00:00:00Z");
+        assert_eq!(serde_json::from_value::<TlsDetails>(json).unwrap(), details);
+
+        let outlier = serde_json::to_value(Outlier::Legacy {
+            version: TlsVersion::Ssl3,
+        })
+        .unwrap();
+        assert_eq!(
+            outlier,
+            serde_json::json!({"kind": "legacy", "version": "SSLv3"})
+        );
+    }
+}
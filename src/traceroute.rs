@@ -0,0 +1,356 @@
+//! A TCP traceroute to a target (`--traceroute`): connects to one of its
+//! ports with the IP TTL raised by one per attempt, and records the router
+//! whose ICMP time-exceeded message ended each attempt.
+//!
+//! The routers are read from the connecting socket's own error queue
+//! (`IP_RECVERR`), so no raw socket and no privileges are needed, but only
+//! Linux offers that and only builds with the `traceroute` feature use it.
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::scanner::{PortResult, PortStatus};
+
+/// One step on the path to a target.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hop {
+    /// The TTL of the attempt, counting from 1.
+    pub ttl: u8,
+    /// Who answered: a router, or the target itself on the last hop. `None`
+    /// when nothing answered before the timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    /// Round-trip time of the answer in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+}
+
+impl fmt::Display for Hop {
+    /// `3  10.0.0.1  1.25 ms`, or `3  *` for a silent hop.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.address, self.rtt_ms) {
+            (Some(address), Some(rtt)) => write!(f, "{:>2}  {}  {:.2} ms", self.ttl, address, rtt),
+            (Some(address), None) => write!(f, "{:>2}  {}", self.ttl, address),
+            (None, _) => write!(f, "{:>2}  *", self.ttl),
+        }
+    }
+}
+
+/// Why a path could not be traced.
+#[derive(Error, Debug)]
+pub enum TraceError {
+    /// This build or platform cannot read ICMP errors.
+    #[error("Traceroute unavailable: {0}")]
+    Unsupported(String),
+    /// A socket could not be set up.
+    #[error("Traceroute to {addr} failed: {source}")]
+    Io {
+        /// The target.
+        addr: SocketAddr,
+        /// The error.
+        source: std::io::Error,
+    },
+}
+
+/// Traces the path to `addr` over at most `max_hops` hops, waiting `timeout`
+/// for each. The last hop is the target when it was reached.
+pub async fn trace(
+    addr: SocketAddr,
+    max_hops: u8,
+    timeout: Duration,
+) -> Result<Vec<Hop>, TraceError> {
+    let mut hops = Vec::new();
+    for ttl in 1..=max_hops {
+        let (hop, reached) = imp::probe(addr, ttl, timeout)
+            .await
+            .map_err(|source| TraceError::Io { addr, source })?;
+        hops.push(hop);
+        if reached {
+            break;
+        }
+    }
+    Ok(hops)
+}
+
+/// Traces the path to every host with a result, to an open port if it has
+/// one, else to a closed one, else to any port scanned. Hosts whose trace
+/// fails are left out with a warning.
+pub async fn trace_hosts(
+    results: &[PortResult],
+    max_hops: u8,
+    hop_timeout: Duration,
+) -> HashMap<IpAddr, Vec<Hop>> {
+    if let Err(e) = check_supported() {
+        warn!("{}; hosts get no route", e);
+        return HashMap::new();
+    }
+    let mut ports: HashMap<IpAddr, (u8, u16)> = HashMap::new();
+    for r in results {
+        let rank = match r.status {
+            PortStatus::Open => 0,
+            PortStatus::Closed => 1,
+            PortStatus::Filtered => 2,
+        };
+        let best = ports.entry(r.target).or_insert((rank, r.port));
+        if rank < best.0 {
+            *best = (rank, r.port);
+        }
+    }
+    futures::stream::iter(ports)
+        .map(|(host, (_, port))| async move {
+            let addr = SocketAddr::new(host, port);
+            info!("Tracing the route to {}", addr);
+            match trace(addr, max_hops, hop_timeout).await {
+                Ok(hops) => {
+                    for hop in &hops {
+                        debug!("{}: {}", host, hop);
+                    }
+                    Some((host, hops))
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(16)
+        .filter_map(|traced| async move { traced })
+        .collect()
+        .await
+}
+
+/// Fails with [`TraceError::Unsupported`] when this build or platform cannot
+/// trace, so the caller can say so once instead of once per target.
+pub fn check_supported() -> Result<(), TraceError> {
+    imp::check_supported()
+}
+
+#[cfg(all(feature = "traceroute", target_os = "linux"))]
+mod imp {
+    use super::Hop;
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpSocket;
+
+    pub fn check_supported() -> Result<(), super::TraceError> {
+        let socket = TcpSocket::new_v4().map_err(|e| unsupported(&e))?;
+        set(socket.as_raw_fd(), libc::SOL_IP, libc::IP_RECVERR, 1).map_err(|e| unsupported(&e))
+    }
+
+    fn unsupported(e: &io::Error) -> super::TraceError {
+        super::TraceError::Unsupported(format!("cannot read ICMP errors: {}", e))
+    }
+
+    /// One connect with `ttl`; true when it reached the target.
+    pub async fn probe(addr: SocketAddr, ttl: u8, timeout: Duration) -> io::Result<(Hop, bool)> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        let fd = socket.as_raw_fd();
+        match addr {
+            SocketAddr::V4(_) => {
+                set(fd, libc::SOL_IP, libc::IP_TTL, ttl.into())?;
+                set(fd, libc::SOL_IP, libc::IP_RECVERR, 1)?;
+            }
+            SocketAddr::V6(_) => {
+                set(fd, libc::SOL_IPV6, libc::IPV6_UNICAST_HOPS, ttl.into())?;
+                set(fd, libc::SOL_IPV6, libc::IPV6_RECVERR, 1)?;
+            }
+        }
+        // The connect consumes the socket; a duplicate keeps its error queue
+        // readable after a failure.
+        // SAFETY: dup returns a new descriptor we own, or -1.
+        let dup = unsafe { libc::dup(fd) };
+        if dup < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: dup is a fresh, valid descriptor nothing else owns.
+        let dup = unsafe { OwnedFd::from_raw_fd(dup) };
+
+        let started = Instant::now();
+        let outcome = tokio::time::timeout(timeout, socket.connect(addr)).await;
+        let rtt = Some(started.elapsed().as_secs_f64() * 1000.0);
+        let hop = |address| Hop {
+            ttl,
+            address,
+            rtt_ms: rtt,
+        };
+        Ok(match outcome {
+            // Accepted or refused: either way the target itself answered.
+            Ok(Ok(_)) => (hop(Some(addr.ip())), true),
+            Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                (hop(Some(addr.ip())), true)
+            }
+            Ok(Err(_)) => match offender(dup.as_raw_fd()) {
+                Some(from) => (hop(Some(from)), from == addr.ip()),
+                None => (hop(None), false),
+            },
+            Err(_) => (
+                Hop {
+                    ttl,
+                    address: None,
+                    rtt_ms: None,
+                },
+                false,
+            ),
+        })
+    }
+
+    fn set(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+        // SAFETY: the option value is a c_int that outlives the call.
+        let rc = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if rc == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// The sender of the ICMP error waiting in the socket's error queue.
+    fn offender(fd: RawFd) -> Option<IpAddr> {
+        let mut data = [0u8; 512];
+        let mut control = [0u64; 64];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        // SAFETY: msghdr is plain data; zeroed is a valid empty header.
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        // SAFETY: every buffer msg points to lives until the call returns.
+        let n = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+        if n < 0 {
+            return None;
+        }
+        // SAFETY: the CMSG macros walk the control buffer recvmsg filled in,
+        // bounded by the msg_controllen it set.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let ours = matches!(
+                    ((*cmsg).cmsg_level, (*cmsg).cmsg_type),
+                    (libc::SOL_IP, libc::IP_RECVERR) | (libc::SOL_IPV6, libc::IPV6_RECVERR)
+                );
+                if ours {
+                    let err = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+                    let origin = (*err).ee_origin;
+                    if origin == libc::SO_EE_ORIGIN_ICMP || origin == libc::SO_EE_ORIGIN_ICMP6 {
+                        return address(libc::SO_EE_OFFENDER(err));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        None
+    }
+
+    /// # Safety
+    /// `sa` must point to a socket address of the family it names.
+    unsafe fn address(sa: *const libc::sockaddr) -> Option<IpAddr> {
+        match (*sa).sa_family as libc::c_int {
+            libc::AF_INET => {
+                let sin = &*(sa as *const libc::sockaddr_in);
+                Some(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)).into())
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(sa as *const libc::sockaddr_in6);
+                Some(Ipv6Addr::from(sin6.sin6_addr.s6_addr).into())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(all(feature = "traceroute", target_os = "linux")))]
+mod imp {
+    use super::{Hop, TraceError};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    pub fn check_supported() -> Result<(), TraceError> {
+        Err(TraceError::Unsupported(
+            if cfg!(target_os = "linux") {
+                "built without the traceroute feature"
+            } else {
+                "only supported on Linux"
+            }
+            .to_string(),
+        ))
+    }
+
+    pub async fn probe(_: SocketAddr, _: u8, _: Duration) -> std::io::Result<(Hop, bool)> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "traceroute unavailable",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hops_read_like_traceroute() {
+        let hop = Hop {
+            ttl: 3,
+            address: Some("10.0.0.1".parse().unwrap()),
+            rtt_ms: Some(1.254),
+        };
+        assert_eq!(hop.to_string(), " 3  10.0.0.1  1.25 ms");
+        let silent = Hop {
+            ttl: 12,
+            address: None,
+            rtt_ms: None,
+        };
+        assert_eq!(silent.to_string(), "12  *");
+        assert_eq!(
+            serde_json::to_value(&silent).unwrap(),
+            serde_json::json!({"ttl": 12})
+        );
+    }
+
+    #[cfg(all(feature = "traceroute", target_os = "linux"))]
+    #[tokio::test]
+    async fn loopback_targets_are_one_hop_away() {
+        check_supported().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hops = trace(addr, 5, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].address, Some(addr.ip()));
+
+        drop(listener);
+        let refused = trace(addr, 5, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0].address, Some(addr.ip()));
+    }
+
+    #[cfg(not(all(feature = "traceroute", target_os = "linux")))]
+    #[test]
+    fn builds_without_support_say_so() {
+        let err = check_supported().unwrap_err();
+        assert!(err.to_string().starts_with("Traceroute unavailable: "));
+    }
+}
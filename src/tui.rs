@@ -0,0 +1,360 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::metrics::ScanMetrics;
+use port_scanner::{PortResult, PortStatus, Throttle};
+
+/// Log lines kept for the log pane.
+const LOG_LINES: usize = 200;
+
+/// The full-screen front-end of `--tui`: a table of open ports, a progress
+/// gauge and the log, with keys to steer the running scan.
+pub struct Tui {
+    terminal: DefaultTerminal,
+    view: View,
+    throttle: Throttle,
+    metrics: Arc<ScanMetrics>,
+    logs: LogPane,
+}
+
+/// What the user asked for with a key press.
+pub enum Key {
+    /// Stop the scan and save what was found.
+    Quit,
+    /// Anything handled by the TUI itself.
+    Handled,
+}
+
+#[derive(Default)]
+struct View {
+    open: Vec<PortResult>,
+    sort: SortBy,
+    total: u64,
+    finished: bool,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+enum SortBy {
+    #[default]
+    Port,
+    Service,
+    Time,
+}
+
+impl SortBy {
+    fn next(self) -> Self {
+        match self {
+            SortBy::Port => SortBy::Service,
+            SortBy::Service => SortBy::Time,
+            SortBy::Time => SortBy::Port,
+        }
+    }
+}
+
+impl Tui {
+    /// Takes over the terminal and starts reading keys into the returned
+    /// channel. The terminal is restored on drop and, through the panic hook
+    /// `ratatui::try_init` installs, on panic.
+    pub fn start(
+        throttle: Throttle,
+        metrics: Arc<ScanMetrics>,
+        total: u64,
+        logs: LogPane,
+    ) -> std::io::Result<(Self, mpsc::Receiver<KeyEvent>)> {
+        let terminal = ratatui::try_init()?;
+        logs.attach();
+        let (tx, keys) = mpsc::channel(16);
+        std::thread::spawn(move || read_keys(tx));
+        let tui = Tui {
+            terminal,
+            view: View {
+                total,
+                ..View::default()
+            },
+            throttle,
+            metrics,
+            logs,
+        };
+        Ok((tui, keys))
+    }
+
+    /// Adds a result to the table if the port is open.
+    pub fn record(&mut self, r: &PortResult) {
+        if r.status == PortStatus::Open {
+            self.view.open.push(r.clone());
+        }
+    }
+
+    /// Marks the scan as over; the TUI stays up until the user quits.
+    pub fn finish(&mut self) {
+        self.view.finished = true;
+    }
+
+    /// Acts on a key press.
+    pub fn on_key(&mut self, key: KeyEvent) -> Key {
+        if key.kind != KeyEventKind::Press {
+            return Key::Handled;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Key::Quit,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Key::Quit
+            }
+            KeyCode::Char('p') | KeyCode::Char(' ') if !self.view.finished => {
+                if self.throttle.is_paused() {
+                    self.throttle.resume();
+                } else {
+                    self.throttle.pause();
+                }
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                let c = self.throttle.concurrency();
+                self.throttle.set_concurrency(c + (c / 10).max(1));
+            }
+            KeyCode::Char('-') => {
+                let c = self.throttle.concurrency();
+                self.throttle
+                    .set_concurrency(c - (c / 10).max(1).min(c - 1));
+            }
+            KeyCode::Char('s') => self.view.sort = self.view.sort.next(),
+            _ => {}
+        }
+        Key::Handled
+    }
+
+    /// Redraws the screen.
+    pub fn draw(&mut self) -> std::io::Result<()> {
+        let view = &mut self.view;
+        match view.sort {
+            SortBy::Port => view.open.sort_by_key(|r| (r.port, r.target)),
+            SortBy::Service => view
+                .open
+                .sort_by(|a, b| (&a.service, a.port).cmp(&(&b.service, b.port))),
+            SortBy::Time => view.open.sort_by_key(|r| (r.duration_ms, r.port)),
+        }
+        let status = Status {
+            scanned: self.metrics.scanned(),
+            rate: self.metrics.ports_per_second(),
+            concurrency: self.throttle.concurrency(),
+            paused: self.throttle.is_paused(),
+        };
+        let logs = self.logs.lines();
+        self.terminal
+            .draw(|frame| render(frame, view, &status, &logs))?;
+        Ok(())
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+        self.logs.detach();
+    }
+}
+
+struct Status {
+    scanned: u64,
+    rate: f64,
+    concurrency: usize,
+    paused: bool,
+}
+
+fn render(frame: &mut Frame, view: &View, status: &Status, logs: &[String]) {
+    let [gauge_area, table_area, log_area, help_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(5),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let ratio = match view.total {
+        0 => 1.0,
+        total => (status.scanned as f64 / total as f64).min(1.0),
+    };
+    let state = if view.finished {
+        "finished, press q to save and exit"
+    } else if status.paused {
+        "paused"
+    } else {
+        "scanning"
+    };
+    let label = format!(
+        "{}/{} ports · {:.0} ports/s · concurrency {} · {}",
+        status.scanned, view.total, status.rate, status.concurrency, state
+    );
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(" Progress "))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(ratio)
+            .label(label),
+        gauge_area,
+    );
+
+    let sort_name = match view.sort {
+        SortBy::Port => "port",
+        SortBy::Service => "service",
+        SortBy::Time => "time",
+    };
+    let rows = view.open.iter().map(|r| {
+        Row::new([
+            r.target.to_string(),
+            r.port.to_string(),
+            r.service.clone().unwrap_or_else(|| "-".into()),
+            r.product_version().unwrap_or_else(|| "-".into()),
+            r.duration_ms.to_string(),
+            r.banner_str()
+                .map_or_else(|| "-".into(), |b| b.replace(['\r', '\n'], " ")),
+        ])
+    });
+    let header = Row::new(["Host", "Port", "Service", "Product", "Time (ms)", "Banner"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(39),
+                Constraint::Length(6),
+                Constraint::Length(12),
+                Constraint::Length(24),
+                Constraint::Length(10),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title(format!(
+            " Open ports: {} (sorted by {}) ",
+            view.open.len(),
+            sort_name
+        ))),
+        table_area,
+    );
+
+    let shown = log_area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = logs
+        .iter()
+        .skip(logs.len().saturating_sub(shown))
+        .map(|l| Line::raw(l.as_str()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Log ")),
+        log_area,
+    );
+
+    frame.render_widget(
+        Line::raw(" q quit and save · p pause/resume · +/- concurrency · s sort").dark_gray(),
+        help_area,
+    );
+}
+
+/// The next key press, or never if there is no TUI.
+pub async fn next_key(keys: &mut Option<mpsc::Receiver<KeyEvent>>) -> Option<KeyEvent> {
+    match keys {
+        Some(keys) => keys.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Forwards key presses until the TUI goes away.
+fn read_keys(tx: mpsc::Sender<KeyEvent>) {
+    while !tx.is_closed() {
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(_) => return,
+        }
+        if let Ok(Event::Key(key)) = event::read() {
+            if tx.blocking_send(key).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Where log lines go while the TUI is up: kept for the log pane instead of
+/// being written over the screen. Before and after, they go to stderr.
+#[derive(Clone, Default)]
+pub struct LogPane {
+    inner: Arc<Mutex<PaneState>>,
+}
+
+#[derive(Default)]
+struct PaneState {
+    attached: bool,
+    lines: VecDeque<String>,
+}
+
+impl LogPane {
+    fn attach(&self) {
+        self.inner.lock().unwrap().attached = true;
+    }
+
+    fn detach(&self) {
+        self.inner.lock().unwrap().attached = false;
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.inner.lock().unwrap().lines.iter().cloned().collect()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogPane {
+    type Writer = PaneLine;
+
+    fn make_writer(&'a self) -> PaneLine {
+        PaneLine {
+            pane: self.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// One formatted event, added to the pane (or written to stderr) when
+/// dropped.
+pub struct PaneLine {
+    pane: LogPane,
+    buf: Vec<u8>,
+}
+
+impl Write for PaneLine {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PaneLine {
+    fn drop(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let mut state = self.pane.inner.lock().unwrap();
+        if !state.attached {
+            drop(state);
+            let _ = std::io::stderr().lock().write_all(&self.buf);
+            return;
+        }
+        let text = String::from_utf8_lossy(&self.buf);
+        for line in text.lines() {
+            if state.lines.len() == LOG_LINES {
+                state.lines.pop_front();
+            }
+            state.lines.push_back(line.to_string());
+        }
+    }
+}
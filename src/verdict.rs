@@ -0,0 +1,126 @@
+//! What a finished scan amounts to, which the CLI turns into its exit code.
+//!
+//! More than one thing can go wrong in a scan; the verdict is the first of
+//! them in the order of [`Verdict`], so that a partial scan is never taken
+//! for a clean one and a policy violation isn't hidden by a failed target.
+
+use crate::completion::CompletionReason;
+use crate::scanner::ScanSummary;
+use crate::vuln::Severity;
+
+/// The outcome of a scan, worst first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The scan stopped on an error; its results are partial.
+    Failed,
+    /// The scan was stopped by the user or its time limit; its results are
+    /// partial.
+    Interrupted,
+    /// A policy rule was violated, or the scan drifted from its baseline.
+    PolicyFailed,
+    /// An exec hook failed, with failed hooks counting against the scan.
+    ExecFailed,
+    /// A probe panicked.
+    ProbePanicked,
+    /// Some targets could not be scanned; the rest were.
+    TargetsFailed,
+    /// The scan completed and found open ports.
+    OpenFound,
+    /// The scan completed without finding an open port.
+    NoneOpen,
+}
+
+impl Verdict {
+    /// The verdict on `summary`, with drift of `fail_on_drift` or worse
+    /// failing it like a policy, and failed exec hooks failing it with
+    /// `exec_fail_fatal`.
+    pub fn of(
+        summary: &ScanSummary,
+        fail_on_drift: Option<Severity>,
+        exec_fail_fatal: bool,
+    ) -> Verdict {
+        let completion = summary.completion.as_ref();
+        let exec_failed = summary
+            .results
+            .iter()
+            .filter_map(|r| r.exec.as_ref())
+            .any(|e| !e.succeeded());
+        let drifted = fail_on_drift
+            .is_some_and(|min| summary.compliance.as_ref().is_some_and(|c| c.fails(min)));
+        if completion.is_some_and(|c| c.reason == CompletionReason::Error) {
+            Verdict::Failed
+        } else if completion.is_some_and(|c| c.is_partial()) {
+            Verdict::Interrupted
+        } else if summary.policy.as_ref().is_some_and(|p| !p.passed) || drifted {
+            Verdict::PolicyFailed
+        } else if exec_fail_fatal && exec_failed {
+            Verdict::ExecFailed
+        } else if summary.panicked_ports > 0 {
+            Verdict::ProbePanicked
+        } else if summary.failed_targets().next().is_some() {
+            Verdict::TargetsFailed
+        } else if summary.open_ports > 0 {
+            Verdict::OpenFound
+        } else {
+            Verdict::NoneOpen
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion::Completion;
+    use crate::metrics::ScanMetrics;
+    use crate::policy::Policy;
+    use crate::scanner::{PortResult, PortStatus};
+
+    fn summary(status: PortStatus) -> ScanSummary {
+        let r = PortResult::new("10.0.0.1".parse().unwrap(), 23, status);
+        let mut summary = ScanSummary::from_results(
+            "id".into(),
+            "10.0.0.1".into(),
+            vec![r],
+            10,
+            &ScanMetrics::new("10.0.0.1"),
+            false,
+        );
+        summary.completion = Some(Completion::completed());
+        summary
+    }
+
+    #[test]
+    fn a_completed_scan_is_judged_by_its_open_ports() {
+        assert_eq!(
+            Verdict::of(&summary(PortStatus::Open), None, false),
+            Verdict::OpenFound
+        );
+        assert_eq!(
+            Verdict::of(&summary(PortStatus::Closed), None, false),
+            Verdict::NoneOpen
+        );
+    }
+
+    #[test]
+    fn a_partial_scan_outranks_a_policy_violation() {
+        let mut s = summary(PortStatus::Open);
+        Policy {
+            fail_on: vec!["open:23".parse().unwrap()],
+            allow_open: None,
+        }
+        .apply(&mut s);
+        assert_eq!(Verdict::of(&s, None, false), Verdict::PolicyFailed);
+
+        s.completion = Some(Completion::cancelled("Ctrl-C"));
+        assert_eq!(Verdict::of(&s, None, false), Verdict::Interrupted);
+        s.completion = Some(Completion::error("disk full"));
+        assert_eq!(Verdict::of(&s, None, false), Verdict::Failed);
+    }
+
+    #[test]
+    fn a_panicked_probe_outranks_open_ports() {
+        let mut s = summary(PortStatus::Open);
+        s.panicked_ports = 1;
+        assert_eq!(Verdict::of(&s, None, false), Verdict::ProbePanicked);
+    }
+}
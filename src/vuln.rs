@@ -0,0 +1,547 @@
+//! Offline vulnerability tagging (`--vuln-db`): advisories from a
+//! user-supplied JSON file matched against the product and version read from
+//! banners. Nothing is looked up over the network.
+//!
+//! The file is an array of advisories:
+//!
+//! ```json
+//! [
+//!   {
+//!     "id": "CVE-2023-38408",
+//!     "severity": "critical",
+//!     "summary": "ssh-agent remote code execution",
+//!     "product": "OpenSSH",
+//!     "versions": ">=5.5, <9.3p2"
+//!   }
+//! ]
+//! ```
+//!
+//! `product` is compared case-insensitively with the detected product;
+//! `cpe: "vendor:product"` may be given instead (or as well) to match on the
+//! CPE name. Without `versions`, every version of the product matches.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::scanner::PortResult;
+
+/// How bad an advisory is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Low.
+    Low,
+    /// Medium.
+    Medium,
+    /// High.
+    High,
+    /// Critical.
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!(
+                "unknown severity '{}' (expected: low, medium, high, critical)",
+                s
+            )),
+        }
+    }
+}
+
+/// An advisory that applies to the service on a port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    /// Advisory identifier, such as a CVE id.
+    pub id: String,
+    /// How bad it is.
+    pub severity: Severity,
+    /// One-line description.
+    pub summary: String,
+}
+
+/// Number of findings at each severity, as recorded in the scan summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FindingCounts {
+    /// Critical findings.
+    pub critical: usize,
+    /// High findings.
+    pub high: usize,
+    /// Medium findings.
+    pub medium: usize,
+    /// Low findings.
+    pub low: usize,
+}
+
+impl FindingCounts {
+    /// Counts the findings of every result.
+    pub fn of<'a>(results: impl IntoIterator<Item = &'a PortResult>) -> Self {
+        let mut counts = FindingCounts::default();
+        for f in results.into_iter().flat_map(|r| &r.findings) {
+            *match f.severity {
+                Severity::Critical => &mut counts.critical,
+                Severity::High => &mut counts.high,
+                Severity::Medium => &mut counts.medium,
+                Severity::Low => &mut counts.low,
+            } += 1;
+        }
+        counts
+    }
+
+    /// Findings of any severity.
+    pub fn total(&self) -> usize {
+        self.critical + self.high + self.medium + self.low
+    }
+}
+
+impl fmt::Display for FindingCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} critical, {} high, {} medium, {} low",
+            self.critical, self.high, self.medium, self.low
+        )
+    }
+}
+
+/// Why a vulnerability database could not be loaded.
+#[derive(Error, Debug)]
+pub enum VulnDbError {
+    /// The file could not be read.
+    #[error("Cannot read vulnerability database {path}: {source}")]
+    Read {
+        /// The database path.
+        path: String,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+    /// The file is not an array of advisories.
+    #[error("{path}: {source}")]
+    Parse {
+        /// The database path.
+        path: String,
+        /// The underlying error.
+        source: serde_json::Error,
+    },
+    /// An advisory is unusable.
+    #[error("{path}: advisory {id}: {message}")]
+    Advisory {
+        /// The database path.
+        path: String,
+        /// The advisory's id.
+        id: String,
+        /// What is wrong with it.
+        message: String,
+    },
+}
+
+/// An advisory as written in the database file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawAdvisory {
+    id: String,
+    severity: Severity,
+    summary: String,
+    #[serde(default)]
+    product: Option<String>,
+    #[serde(default)]
+    cpe: Option<String>,
+    #[serde(default)]
+    versions: Option<String>,
+}
+
+#[derive(Debug)]
+struct Advisory {
+    finding: Finding,
+    product: Option<String>,
+    cpe: Option<String>,
+    versions: VersionRange,
+}
+
+/// Advisories loaded from a `--vuln-db` file.
+#[derive(Debug, Default)]
+pub struct VulnDb {
+    advisories: Vec<Advisory>,
+}
+
+impl VulnDb {
+    /// Reads the database at `path`.
+    pub fn load(path: &Path) -> Result<Self, VulnDbError> {
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|source| VulnDbError::Read {
+            path: name.clone(),
+            source,
+        })?;
+        Self::parse(&name, &text)
+    }
+
+    /// Parses a database from its JSON text; `name` appears in errors.
+    pub fn parse(name: &str, json: &str) -> Result<Self, VulnDbError> {
+        let raw: Vec<RawAdvisory> =
+            serde_json::from_str(json).map_err(|source| VulnDbError::Parse {
+                path: name.to_string(),
+                source,
+            })?;
+        let invalid = |id: &str, message: String| VulnDbError::Advisory {
+            path: name.to_string(),
+            id: id.to_string(),
+            message,
+        };
+        let mut advisories = Vec::with_capacity(raw.len());
+        for a in raw {
+            if a.product.is_none() && a.cpe.is_none() {
+                return Err(invalid(&a.id, "needs a product or a cpe".into()));
+            }
+            let versions = match a.versions.as_deref() {
+                Some(range) => range.parse().map_err(|e| invalid(&a.id, e))?,
+                None => VersionRange::default(),
+            };
+            advisories.push(Advisory {
+                finding: Finding {
+                    id: a.id,
+                    severity: a.severity,
+                    summary: a.summary,
+                },
+                product: a.product,
+                cpe: a.cpe.map(|c| c.to_ascii_lowercase()),
+                versions,
+            });
+        }
+        Ok(VulnDb { advisories })
+    }
+
+    /// Number of advisories.
+    pub fn len(&self) -> usize {
+        self.advisories.len()
+    }
+
+    /// True when the database holds no advisories.
+    pub fn is_empty(&self) -> bool {
+        self.advisories.is_empty()
+    }
+
+    /// The advisories that apply to `r`, most severe first. A result without
+    /// a readable version only matches advisories for every version.
+    pub fn findings(&self, r: &PortResult) -> Vec<Finding> {
+        let Some(product) = r.product.as_deref() else {
+            return Vec::new();
+        };
+        let cpe = r.cpe.as_deref().and_then(cpe_vendor_product);
+        let version = r.version.as_deref().and_then(Version::parse);
+        let mut findings: Vec<Finding> = self
+            .advisories
+            .iter()
+            .filter(|a| {
+                a.product
+                    .as_deref()
+                    .is_some_and(|p| p.eq_ignore_ascii_case(product))
+                    || a.cpe.is_some() && a.cpe.as_deref() == cpe.as_deref()
+            })
+            .filter(|a| match &version {
+                Some(v) => a.versions.contains(v),
+                None => a.versions.is_any(),
+            })
+            .map(|a| a.finding.clone())
+            .collect();
+        findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.id.cmp(&b.id)));
+        findings
+    }
+
+    /// Sets the findings of `r`.
+    pub fn tag(&self, r: &mut PortResult) {
+        r.findings = self.findings(r);
+    }
+}
+
+/// `vendor:product` of a CPE 2.3 name, lowercased.
+fn cpe_vendor_product(cpe: &str) -> Option<String> {
+    let mut parts = cpe.strip_prefix("cpe:2.3:")?.splitn(4, ':').skip(1);
+    let (vendor, product) = (parts.next()?, parts.next()?);
+    Some(format!("{}:{}", vendor, product).to_ascii_lowercase())
+}
+
+/// A software version as banners give them, compared leniently:
+/// `1.18.0 (Ubuntu)` is `1.18.0`, `8.9p1` comes after `8.9` and before
+/// `8.10`, `1.0rc1` comes before `1.0`, and `1.0` equals `1.0.0`.
+#[derive(Debug, Clone)]
+pub struct Version(Vec<Part>);
+
+#[derive(Debug, Clone)]
+enum Part {
+    Number(u64),
+    /// A pre-release tag such as `rc`, sorting before the release.
+    Pre(String),
+    /// Any other letters, such as the `p` of OpenSSH portable releases,
+    /// sorting after the release.
+    Text(String),
+}
+
+const PRE_RELEASE: &[&str] = &["dev", "pre", "preview", "alpha", "a", "beta", "b", "rc"];
+
+impl Version {
+    /// Reads the version at the start of `s`: anything after whitespace or a
+    /// `+` is dropped, as are a leading `v` and a Debian-style `1:` epoch.
+    /// `None` unless it starts with a digit.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.split_whitespace().next()?;
+        let s = s.split('+').next().unwrap_or(s);
+        let s = s.strip_prefix(['v', 'V']).unwrap_or(s);
+        let s = match s.split_once(':') {
+            Some((epoch, rest)) if epoch.bytes().all(|b| b.is_ascii_digit()) => rest,
+            _ => s,
+        };
+        if !s.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut parts = Vec::new();
+        for chunk in s.split(|c: char| !c.is_ascii_alphanumeric()) {
+            let mut rest = chunk;
+            while let Some(c) = rest.chars().next() {
+                let digits = c.is_ascii_digit();
+                let end = rest
+                    .find(|c: char| c.is_ascii_digit() != digits)
+                    .unwrap_or(rest.len());
+                let (run, tail) = rest.split_at(end);
+                parts.push(if digits {
+                    Part::Number(run.parse().unwrap_or(u64::MAX))
+                } else {
+                    let word = run.to_ascii_lowercase();
+                    if PRE_RELEASE.contains(&word.as_str()) {
+                        Part::Pre(word)
+                    } else {
+                        Part::Text(word)
+                    }
+                });
+                rest = tail;
+            }
+        }
+        Some(Version(parts))
+    }
+}
+
+/// Orders a part against a missing one, which acts as a `0`: pre-release
+/// tags before it, other letters after it.
+fn cmp_part(a: Option<&Part>, b: Option<&Part>) -> Ordering {
+    fn rank(p: Option<&Part>) -> (u8, u64, &str) {
+        match p {
+            Some(Part::Pre(s)) => (0, 0, s),
+            None => (1, 0, ""),
+            Some(Part::Number(n)) => (1, *n, ""),
+            Some(Part::Text(s)) => (2, 0, s),
+        }
+    }
+    rank(a).cmp(&rank(b))
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|i| cmp_part(self.0.get(i), other.0.get(i)))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+/// Versions an advisory applies to, such as `>=5.5, <9.3p2`: every
+/// comma-separated constraint must hold. Empty or `*` means every version.
+#[derive(Debug, Clone, Default)]
+pub struct VersionRange(Vec<(Ordering, bool, Version)>);
+
+impl VersionRange {
+    /// True when `v` satisfies every constraint.
+    pub fn contains(&self, v: &Version) -> bool {
+        self.0
+            .iter()
+            .all(|(want, or_equal, bound)| match v.cmp(bound) {
+                Ordering::Equal => *or_equal,
+                other => other == *want,
+            })
+    }
+
+    /// True when the range has no constraints.
+    pub fn is_any(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for VersionRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "*" {
+            return Ok(VersionRange::default());
+        }
+        let mut constraints = Vec::new();
+        for c in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let (want, or_equal, version) = if let Some(v) = c.strip_prefix(">=") {
+                (Ordering::Greater, true, v)
+            } else if let Some(v) = c.strip_prefix("<=") {
+                (Ordering::Less, true, v)
+            } else if let Some(v) = c.strip_prefix('>') {
+                (Ordering::Greater, false, v)
+            } else if let Some(v) = c.strip_prefix('<') {
+                (Ordering::Less, false, v)
+            } else {
+                (Ordering::Equal, true, c.strip_prefix('=').unwrap_or(c))
+            };
+            let version = Version::parse(version.trim())
+                .ok_or_else(|| format!("invalid version constraint '{}'", c))?;
+            constraints.push((want, or_equal, version));
+        }
+        Ok(VersionRange(constraints))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::PortStatus;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap_or_else(|| panic!("{:?} should parse", s))
+    }
+
+    fn result(product: &str, version: Option<&str>, cpe: Option<&str>) -> PortResult {
+        let mut r = PortResult::new("10.0.0.1".parse().unwrap(), 22, PortStatus::Open);
+        r.product = Some(product.into());
+        r.version = version.map(Into::into);
+        r.cpe = cpe.map(Into::into);
+        r
+    }
+
+    #[test]
+    fn versions_compare_the_way_releases_are_numbered() {
+        let ascending = [
+            "1.0rc1", "1.0rc2", "1.0", "1.0.1", "1.9", "1.10", "8.9", "8.9p1", "8.9p2", "8.10",
+            "9.3p2",
+        ];
+        for pair in ascending.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert_eq!(v("1.18.0 (Ubuntu)"), v("1.18.0"));
+        assert_eq!(v("v2.4"), v("2.4"));
+        assert_eq!(v("1:8.9p1-3ubuntu0.1"), v("8.9p1-3ubuntu0.1"));
+        assert!(v("8.9p1-3ubuntu0.1") > v("8.9p1"));
+        assert_eq!(v("2.4.41+deb11"), v("2.4.41"));
+        assert_eq!(v("1.0-RC1"), v("1.0rc1"));
+        assert!(v("99999999999999999999999") > v("1"));
+    }
+
+    #[test]
+    fn unversioned_strings_are_not_versions() {
+        for s in ["", "Ubuntu", "(1.0)", "-", "v", "unknown 1.0"] {
+            assert!(Version::parse(s).is_none(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn ranges_hold_every_constraint() {
+        let range: VersionRange = ">=5.5, <9.3p2".parse().unwrap();
+        assert!(range.contains(&v("5.5")));
+        assert!(range.contains(&v("8.9p1")));
+        assert!(range.contains(&v("9.3p1")));
+        assert!(!range.contains(&v("9.3p2")));
+        assert!(!range.contains(&v("5.4")));
+
+        let exact: VersionRange = "1.18.0".parse().unwrap();
+        assert!(exact.contains(&v("1.18.0 (Ubuntu)")));
+        assert!(!exact.contains(&v("1.18.1")));
+        assert!("<=2".parse::<VersionRange>().unwrap().contains(&v("2.0")));
+        assert!("*".parse::<VersionRange>().unwrap().is_any());
+        assert!(">=".parse::<VersionRange>().is_err());
+        assert!("~1.2".parse::<VersionRange>().is_err());
+    }
+
+    #[test]
+    fn advisories_match_by_product_or_cpe_and_version() {
+        let db = VulnDb::parse(
+            "test.json",
+            r#"[
+                {"id": "A", "severity": "high", "summary": "a", "product": "openssh",
+                 "versions": ">=5.5, <9.3p2"},
+                {"id": "B", "severity": "critical", "summary": "b",
+                 "cpe": "OpenBSD:OpenSSH", "versions": "<9.0"},
+                {"id": "C", "severity": "low", "summary": "c", "product": "OpenSSH"},
+                {"id": "D", "severity": "medium", "summary": "d", "product": "nginx"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(db.len(), 4);
+
+        let cpe = "cpe:2.3:a:openbsd:openssh:8.9p1:*:*:*:*:*:*:*";
+        let ids =
+            |r: &PortResult| -> Vec<String> { db.findings(r).into_iter().map(|f| f.id).collect() };
+        assert_eq!(
+            ids(&result("OpenSSH", Some("8.9p1"), Some(cpe))),
+            ["B", "A", "C"]
+        );
+        assert_eq!(ids(&result("OpenSSH", Some("9.3p2"), None)), ["C"]);
+        assert_eq!(ids(&result("OpenSSH", None, None)), ["C"]);
+        assert!(ids(&result("Dropbear sshd", Some("2020.81"), None)).is_empty());
+
+        let mut r = result("nginx", Some("1.18.0"), None);
+        db.tag(&mut r);
+        assert_eq!(r.findings[0].severity, Severity::Medium);
+        let counts = FindingCounts::of([&r]);
+        assert_eq!((counts.medium, counts.total()), (1, 1));
+    }
+
+    #[test]
+    fn bad_databases_name_the_problem() {
+        let err = VulnDb::parse(
+            "db.json",
+            r#"[{"id": "X", "severity": "high", "summary": ""}]"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "db.json: advisory X: needs a product or a cpe"
+        );
+        let err = VulnDb::parse(
+            "db.json",
+            r#"[{"id": "X", "severity": "high", "summary": "", "product": "p", "versions": "<x"}]"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("'<x'"), "{}", err);
+        assert!(VulnDb::parse("db.json", r#"[{"id": "X", "severity": "severe"}]"#).is_err());
+        assert_eq!("HIGH".parse::<Severity>(), Ok(Severity::High));
+    }
+}
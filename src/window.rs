@@ -0,0 +1,256 @@
+//! Scan windows for `--window`: the hours of the day, and optionally the
+//! days of the week, when probing is allowed.
+//!
+//! A window is written `[DAYS] HH:MM-HH:MM [ZONE]`, such as `01:00-05:00`,
+//! `mon-fri 22:00-06:00` or `sat,sun 00:00-24:00 UTC`. Days take the names
+//! and numbers of a cron weekday field and name the day the window opens
+//! on, so `fri 23:00-01:00` runs into Saturday. The zone is one a
+//! [`Zone`] parses and defaults to local time. When the clock skips the
+//! opening or closing time for daylight saving, the window moves an hour
+//! later with it.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::clock::Clock;
+use crate::cron::{Field, Zone, DAYS};
+
+const DAY_MINUTES: u32 = 24 * 60;
+
+/// When a scan may probe; see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    text: String,
+    /// Days the window opens on, bit 0 for Sunday.
+    days: u8,
+    /// Minutes after midnight the window opens.
+    start: u32,
+    /// Minutes after midnight the window closes, up to 24:00; the next day
+    /// when not after `start`.
+    end: u32,
+    zone: Zone,
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim();
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let times = tokens
+            .iter()
+            .position(|t| t.starts_with(|c: char| c.is_ascii_digit()) && t.contains(':'))
+            .ok_or_else(|| format!("'{}' has no hours such as 01:00-05:00", text))?;
+        let (days, zone) = match (&tokens[..times], &tokens[times + 1..]) {
+            ([], []) => ("*", "local"),
+            ([days], []) => (*days, "local"),
+            ([], [zone]) => ("*", *zone),
+            ([days], [zone]) => (*days, *zone),
+            _ => {
+                return Err(format!(
+                    "'{}' is not [DAYS] HH:MM-HH:MM [ZONE], e.g. \"mon-fri 01:00-05:00 UTC\"",
+                    text
+                ))
+            }
+        };
+
+        let (start, end) = tokens[times]
+            .split_once('-')
+            .ok_or_else(|| format!("'{}' is not a range such as 01:00-05:00", tokens[times]))?;
+        let (start, end) = (minutes(start, false)?, minutes(end, true)?);
+        if start == end {
+            return Err(format!("'{}' is empty", tokens[times]));
+        }
+
+        let mut days = Field::parse(days, 0, 7, &DAYS)
+            .map_err(|e| format!("days of '{}': {}", text, e))?
+            .bits;
+        if days & (1 << 7) != 0 {
+            days = (days & !(1 << 7)) | 1;
+        }
+        Ok(Window {
+            text: text.to_string(),
+            days: days as u8,
+            start,
+            end,
+            zone: zone.parse()?,
+        })
+    }
+}
+
+/// Minutes after midnight of `HH:MM`; `24:00` only as an `end`.
+fn minutes(time: &str, end: bool) -> Result<u32, String> {
+    let parsed = time.split_once(':').and_then(|(h, m)| {
+        let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+        match (h, m) {
+            (24, 0) if end => Some(DAY_MINUTES),
+            (0..=23, 0..=59) => Some(h * 60 + m),
+            _ => None,
+        }
+    });
+    parsed.ok_or_else(|| format!("'{}' is not a time from 00:00 to 23:59", time))
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Window {
+    /// Whether the window is open at `t`.
+    pub fn contains(&self, t: DateTime<Utc>) -> bool {
+        self.opening_around(t)
+            .any(|(open, close)| open <= t && t < close)
+    }
+
+    /// Whether the window is open at the time `clock` reads.
+    pub fn is_open(&self, clock: &dyn Clock) -> bool {
+        self.contains(clock.now())
+    }
+
+    /// `t` as the window's clock reads it, such as `Fri 01:00`.
+    pub fn clock_time(&self, t: DateTime<Utc>) -> String {
+        self.zone.local(t).format("%a %H:%M").to_string()
+    }
+
+    /// When the window next closes if it is open at `t`, or next opens if
+    /// it is not.
+    pub fn next_change(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut spans = self.opening_around(t);
+        match spans.clone().find(|&(open, close)| open <= t && t < close) {
+            Some((_, close)) => Some(close),
+            None => spans.find(|&(open, _)| open > t).map(|(open, _)| open),
+        }
+    }
+
+    /// The opening and closing instants of the window, in order, for each
+    /// day it opens from the day before `t` to a week after.
+    fn opening_around(
+        &self,
+        t: DateTime<Utc>,
+    ) -> impl Iterator<Item = (DateTime<Utc>, DateTime<Utc>)> + Clone + '_ {
+        let today = self.zone.local(t).date();
+        (-1..=7)
+            .filter_map(move |offset| today.checked_add_signed(Duration::days(offset)))
+            .filter(|day| self.days & (1 << day.weekday().num_days_from_sunday()) != 0)
+            .filter_map(|day| {
+                let end = if self.end <= self.start {
+                    self.end + DAY_MINUTES
+                } else {
+                    self.end
+                };
+                Some((self.instant(day, self.start)?, self.instant(day, end)?))
+            })
+    }
+
+    /// The instant `day`'s clock reads `minutes` past midnight, an hour
+    /// later if the clock skips it.
+    fn instant(&self, day: NaiveDate, minutes: u32) -> Option<DateTime<Utc>> {
+        let local: NaiveDateTime =
+            day.and_hms_opt(0, 0, 0)? + Duration::minutes(i64::from(minutes));
+        self.zone
+            .utc(local)
+            .or_else(|| self.zone.utc(local + Duration::hours(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().to_utc()
+    }
+
+    fn window(spec: &str) -> Window {
+        spec.parse().unwrap()
+    }
+
+    #[test]
+    fn windows_parse_with_days_and_zones() {
+        let w = window("01:00-05:00");
+        assert_eq!(
+            (w.days, w.start, w.end, w.zone),
+            (0x7f, 60, 300, Zone::Local)
+        );
+        let w = window(" mon-fri 22:30-06:00 +02:00 ");
+        assert_eq!((w.days, w.start, w.end), (0b0111110, 22 * 60 + 30, 360));
+        assert_eq!(w.to_string(), "mon-fri 22:30-06:00 +02:00");
+        assert_eq!(window("0,7 00:00-24:00 UTC").days, 1);
+
+        for (spec, error) in [
+            ("", "has no hours"),
+            ("01:00", "is not a range"),
+            ("01:00-01:00", "is empty"),
+            ("24:00-05:00", "'24:00' is not a time"),
+            ("01:00-05:60", "'05:60' is not a time"),
+            ("funday 01:00-05:00", "days of 'funday 01:00-05:00'"),
+            ("01:00-05:00 Mars/Olympus", "unknown time zone"),
+            ("mon tue 01:00-05:00", "is not [DAYS] HH:MM-HH:MM [ZONE]"),
+        ] {
+            let err = spec.parse::<Window>().unwrap_err();
+            assert!(err.contains(error), "{}: {}", spec, err);
+        }
+    }
+
+    #[test]
+    fn the_window_is_read_on_its_own_clock() {
+        let w = window("01:00-05:00 +02:00");
+        for (now, open) in [
+            ("2026-03-10T22:59:59Z", false),
+            ("2026-03-10T23:00:00Z", true),
+            ("2026-03-11T02:59:59Z", true),
+            ("2026-03-11T03:00:00Z", false),
+            ("2026-03-11T01:00:00Z", true),
+            ("2026-03-11T12:00:00Z", false),
+        ] {
+            let clock = FixedClock(at(now));
+            assert_eq!(w.is_open(&clock), open, "{}", now);
+        }
+    }
+
+    #[test]
+    fn days_name_the_day_the_window_opens() {
+        // 2026-10-16 is a Friday.
+        let w = window("fri 23:00-01:00 UTC");
+        assert!(w.contains(at("2026-10-16T23:30:00Z")));
+        assert!(w.contains(at("2026-10-17T00:30:00Z")));
+        assert!(!w.contains(at("2026-10-17T23:30:00Z")));
+        assert!(!w.contains(at("2026-10-15T23:30:00Z")));
+
+        let weekends = window("sat,sun 00:00-24:00 -05:00");
+        assert!(!weekends.contains(at("2026-10-17T04:59:59Z")));
+        assert!(weekends.contains(at("2026-10-17T05:00:00Z")));
+        assert!(weekends.contains(at("2026-10-19T04:59:59Z")));
+        assert!(!weekends.contains(at("2026-10-19T05:00:00Z")));
+    }
+
+    #[test]
+    fn next_change_is_the_next_opening_or_closing() {
+        let w = window("mon-fri 01:00-05:00 UTC");
+        // Thursday afternoon: Friday's window opens next.
+        assert_eq!(
+            w.next_change(at("2026-10-15T14:00:00Z")),
+            Some(at("2026-10-16T01:00:00Z"))
+        );
+        assert_eq!(
+            w.next_change(at("2026-10-16T01:00:00Z")),
+            Some(at("2026-10-16T05:00:00Z"))
+        );
+        // Friday after closing: Monday's.
+        assert_eq!(
+            w.next_change(at("2026-10-16T05:00:00Z")),
+            Some(at("2026-10-19T01:00:00Z"))
+        );
+        // Back to back days close at midnight and open again straight away.
+        let always = window("00:00-24:00 UTC");
+        assert_eq!(
+            always.next_change(at("2026-10-15T14:00:00Z")),
+            Some(at("2026-10-16T00:00:00Z"))
+        );
+        assert!(always.contains(at("2026-10-16T00:00:00Z")));
+    }
+}
@@ -0,0 +1,3759 @@
+mod support;
+
+use assert_cmd::Command;
+use std::process::Output;
+use std::time::Duration;
+#[cfg(feature = "nats")]
+use support::NatsServer;
+use support::{closed_port, temp_path, FakeService, ObjectStore, SmtpSink};
+
+fn scan(args: &[&str]) -> Output {
+    Command::cargo_bin("port-scanner")
+        .unwrap()
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
+
+/// `line` with the latency of a detailed console line, which varies, as
+/// `Latency: _`.
+fn without_latency(line: &str) -> String {
+    match line.split_once(" | Latency: ") {
+        Some((head, tail)) => {
+            let rest = tail.find(" | ").map_or("", |i| &tail[i..]);
+            format!("{} | Latency: _{}", head, rest)
+        }
+        None => line.to_string(),
+    }
+}
+
+fn sorted_lines(bytes: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(bytes)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn quiet_scan_prints_open_ports_and_writes_reports() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port();
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let closed = closed_port();
+    let json = temp_path("quiet.json");
+    let txt = temp_path("quiet.txt");
+    let ports = format!("{},{},{}", open, ssh, closed);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "-T",
+        "500",
+        "--json",
+        json.to_str().unwrap(),
+        "--output",
+        txt.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let mut expected = vec![
+        format!("{:>5} open   -", open),
+        format!("{:>5} open   SSH (Test)", ssh),
+    ];
+    expected.sort();
+    assert_eq!(sorted_lines(&out.stdout), expected);
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["target"], "127.0.0.1");
+    assert_eq!(summary["scanned_ports"], 3);
+    assert_eq!(summary["open_ports"], 2);
+    assert_eq!(summary["closed_ports"], 1);
+    let banner = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["port"] == ssh)
+        .unwrap();
+    assert_eq!(banner["status"], "Open");
+    assert_eq!(banner["banner"], "SSH-2.0-Test");
+    assert_eq!(banner["service"], "SSH");
+
+    let report = std::fs::read_to_string(&txt).unwrap();
+    let mut lines = report.lines();
+    let header = lines.next().unwrap();
+    assert!(header.starts_with("Scan of 127.0.0.1 | Ports: 3 | Time: "));
+    assert!(header.ends_with("ms"));
+    assert_eq!(lines.next(), Some(""));
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort();
+    let mut expected = vec![
+        format!("Port {:>5} | open | Service: -            | Banner: -", open),
+        format!(
+            "Port {:>5} | open | Service: SSH          | Banner: SSH-2.0-Test | Product: Test",
+            ssh
+        ),
+        format!("Port {:>5} | closed | Service: -            | Banner: -", closed),
+    ];
+    expected.sort();
+    assert_eq!(rows, expected);
+
+    let _ = std::fs::remove_file(json);
+    let _ = std::fs::remove_file(txt);
+}
+
+#[test]
+fn ports_with_the_same_banner_share_a_line_of_the_text_report() {
+    let proxies = [
+        FakeService::banner(b"SSH-2.0-Proxy_1.0\r\n").start(),
+        FakeService::banner(b"SSH-2.0-Proxy_1.0\r\n").start(),
+    ];
+    let mut same: Vec<u16> = proxies.iter().map(|p| p.port()).collect();
+    same.sort();
+    let other_service = FakeService::banner(b"SSH-2.0-Other_2.0\r\n").start();
+    let other = other_service.port();
+    let json = temp_path("clusters.json");
+    let txt = temp_path("clusters.txt");
+    let ports = format!("{},{},{}", same[0], same[1], other);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+        "--output",
+        txt.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+
+    let report = std::fs::read_to_string(&txt).unwrap();
+    let rows: Vec<&str> = report.lines().skip(2).collect();
+    assert_eq!(rows.len(), 2, "{}", report);
+    let cluster = rows
+        .iter()
+        .find(|r| r.starts_with("Cluster 1 | ports "))
+        .unwrap();
+    assert!(
+        cluster.ends_with(": identical service (Proxy 1.0)"),
+        "{}",
+        cluster
+    );
+    assert!(
+        same.iter().all(|p| cluster.contains(&p.to_string())),
+        "{}",
+        cluster
+    );
+    assert!(rows.iter().any(|r| r.contains("Banner: SSH-2.0-Other_2.0")));
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    for r in summary["results"].as_array().unwrap() {
+        let expected = if r["port"] == other { None } else { Some(1) };
+        assert_eq!(r["cluster"].as_u64(), expected, "{}", r);
+    }
+
+    let _ = std::fs::remove_file(json);
+    let _ = std::fs::remove_file(txt);
+}
+
+#[test]
+fn port_groups_expand_in_the_port_spec() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port();
+    let group = format!("probe={},{}", open, closed_port());
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        "@probe,@web",
+        "--exclude-ports",
+        "@web",
+        "--port-group",
+        &group,
+        "-q",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout).trim(),
+        format!("{} open   -", open)
+    );
+
+    let out = scan(&["--list-port-groups", "--port-group", "admin=8080-8090,@web"]);
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.starts_with("@web "), "{}", stdout);
+    assert!(stdout.contains("@db "), "{}", stdout);
+    assert!(
+        stdout.lines().last().unwrap().starts_with("@admin ")
+            && stdout.trim_end().ends_with("user-defined"),
+        "{}",
+        stdout
+    );
+
+    let out = scan(&["-t", "127.0.0.1", "-p", "22,@dbs"]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("did you mean @db?"), "{}", stderr);
+}
+
+#[test]
+fn presets_fill_in_the_options_that_were_not_given() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let closed = closed_port().to_string();
+    let config = temp_path("preset-config");
+    std::fs::create_dir_all(config.join("port-scanner")).unwrap();
+    std::fs::write(
+        config.join("port-scanner/presets.toml"),
+        format!(
+            "[loopback]\ndescription = \"Both test ports\"\nports = \"{open},{closed}\"\nfail_on = [\"open:{open}\"]\n"
+        ),
+    )
+    .unwrap();
+    let json = temp_path("preset.json");
+    let scan = |args: &[&str]| {
+        Command::cargo_bin("port-scanner")
+            .unwrap()
+            .args(args)
+            .env("NO_COLOR", "1")
+            .env("XDG_CONFIG_HOME", &config)
+            .output()
+            .unwrap()
+    };
+    let report = || -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap()
+    };
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "--preset",
+        "loopback",
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(4));
+    let summary = report();
+    assert_eq!(summary["scanned_ports"], 2);
+    assert_eq!(summary["preset"]["name"], "loopback");
+    assert_eq!(
+        summary["preset"]["args"],
+        serde_json::json!([
+            "--ports",
+            format!("{},{}", open, closed),
+            "--fail-on",
+            format!("open:{}", open)
+        ])
+    );
+
+    // An explicit flag wins over the preset's.
+    let out = scan(&[
+        "scan",
+        "-t",
+        "127.0.0.1",
+        "--preset",
+        "loopback",
+        "-p",
+        &closed,
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let summary = report();
+    assert_eq!(summary["scanned_ports"], 1);
+    assert_eq!(
+        summary["preset"]["overridden"],
+        serde_json::json!(["--ports"])
+    );
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "--preset",
+        "web-audit",
+        "-p",
+        "22",
+        "--dry-run",
+        "--json",
+        "-",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(plan["ports"], "22");
+    assert_eq!(plan["banner_timeout_ms"], 2000);
+    assert_eq!(plan["preset"]["name"], "web-audit");
+    assert_eq!(plan["preset"]["overridden"], serde_json::json!(["--ports"]));
+
+    let out = scan(&["--list-presets"]);
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.starts_with("web-audit "), "{}", stdout);
+    assert!(stdout.contains("\nloopback "), "{}", stdout);
+    assert!(stdout.contains("Both test ports (from "), "{}", stdout);
+
+    let out = scan(&["-t", "127.0.0.1", "--preset", "nope"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Unknown preset 'nope'"));
+    let _ = std::fs::remove_dir_all(config);
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn established_connections_to_a_host_stay_within_the_limit() {
+    // Silent services keep every connection open until the banner read
+    // gives up, so all four would be held at once without the limit.
+    let services: Vec<FakeService> = (0..4).map(|_| FakeService::silent().start()).collect();
+    let ports = services
+        .iter()
+        .map(|s| s.port().to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let json = temp_path("established.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--banner-timeout",
+        "300ms",
+        "--max-established-per-host",
+        "1",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(report["open_ports"], 4);
+    let established = &report["established"];
+    assert_eq!(established["max_per_host"], 1);
+    assert_eq!(established["peak"], 1);
+    assert_eq!(established["hosts"]["127.0.0.1"], 1);
+    assert!(
+        established["deferred"].as_u64().unwrap() > 0,
+        "{}",
+        established
+    );
+}
+
+#[test]
+fn heatmap_bins_connect_and_banner_times_by_port_range() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let closed = closed_port();
+    let json = temp_path("heatmap.json");
+    let txt = temp_path("heatmap.txt");
+    let ports = format!("{},{}", ssh, closed);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--heatmap",
+        "--heatmap-bins",
+        "100000",
+        "--heatmap-port-width",
+        "65536",
+        "--json",
+        json.to_str().unwrap(),
+        "--output",
+        txt.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let open = report["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["port"] == ssh)
+        .unwrap();
+    assert!(open["timing"]["banner_ms"].is_number(), "{}", open);
+    let heatmap = &report["heatmap"];
+    assert_eq!(heatmap["bins_ms"], serde_json::json!([100000.0]));
+    assert_eq!(
+        heatmap["rows"],
+        serde_json::json!([{
+            "host": "127.0.0.1",
+            "first_port": 0,
+            "last_port": 65535,
+            "connect": [2, 0],
+            "banner": [1, 0],
+        }])
+    );
+    let text = std::fs::read_to_string(&txt).unwrap();
+    assert!(
+        text.contains("127.0.0.1 | 0-65535     | connect        2        0"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn sniff_tells_odd_ports_by_their_bytes_and_climbs_the_ladder() {
+    let vnc_service = FakeService::banner(b"RFB 003.008\n").start();
+    let web_service = FakeService::http().start();
+    let mute_service = FakeService::close().start();
+    let (vnc, web, mute) = (vnc_service.port(), web_service.port(), mute_service.port());
+    let json = temp_path("sniff.json");
+    let ports = format!("{},{},{}", vnc, web, mute);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--banner-timeout",
+        "50ms",
+        "--sniff",
+        "--probe-ladder",
+        "tls,crlf",
+        "--evidence",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let result = |port: u16| {
+        report["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["port"] == port)
+            .unwrap()
+            .clone()
+    };
+    let probes = |port: u16| -> Vec<String> {
+        result(port)["evidence"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["step"] == "probe")
+            .map(|e| format!("{} {}", e["outcome"], e["detail"]))
+            .collect()
+    };
+
+    assert_eq!(result(vnc)["service"], "VNC");
+    assert_eq!(result(vnc)["extra_info"], "RFB 3.8");
+    assert!(probes(vnc).is_empty(), "it spoke first");
+
+    // The web server takes the ClientHello for a request it is still
+    // waiting on, and times it out with a 408.
+    assert_eq!(result(web)["service"], "HTTP");
+    assert_eq!(result(web)["product"], "fake");
+    assert_eq!(
+        probes(web),
+        [r#""matched" "ladder tls on connection 1: HTTP response""#]
+    );
+
+    // Each rung that gets nothing out of the port leaves it to the next,
+    // on a new connection.
+    assert_eq!(result(mute)["service"], serde_json::Value::Null);
+    let climbed = probes(mute);
+    assert_eq!(climbed.len(), 2, "{:?}", climbed);
+    assert!(
+        climbed[0].contains("\"ladder tls on connection 2"),
+        "{:?}",
+        climbed
+    );
+    assert!(
+        climbed[1].contains("\"ladder crlf on connection 3"),
+        "{:?}",
+        climbed
+    );
+}
+
+#[test]
+fn protocol_prefixes_pick_tcp_and_udp_ports() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port();
+
+    let tcp = format!("T:{},U:53", open);
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &tcp,
+        "--exclude-ports",
+        "U:53",
+        "-q",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout).trim(),
+        format!("{} open   -", open)
+    );
+
+    let out = scan(&["-t", "127.0.0.1", "-p", &tcp]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("UDP scanning is not supported yet"),
+        "{}",
+        stderr
+    );
+
+    let out = scan(&["-t", "127.0.0.1", "-p", "X:22"]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Unknown protocol"), "{}", stderr);
+}
+
+#[test]
+fn verbose_scan_prints_every_port() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let reset_service = FakeService::reset().start();
+    let reset = reset_service.port();
+    let closed = closed_port();
+    let ports = format!("{},{},{}", ssh, reset, closed);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-v",
+        "verbose",
+        "--status-interval",
+        "60",
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let mut expected = vec![
+        format!(
+            "{:>5} | open | Service: SSH | Product: Test | Latency: _ | Banner: SSH-2.0-Test",
+            ssh
+        ),
+        format!(
+            "{:>5} | closed | Service: - | Latency: _ | Banner: -",
+            closed
+        ),
+    ];
+    expected.sort();
+    let (reset_lines, lines): (Vec<String>, Vec<String>) = sorted_lines(&out.stdout)
+        .iter()
+        .map(|l| without_latency(l))
+        .partition(|l| l.starts_with(&format!("{:>5} |", reset)));
+    assert_eq!(lines, expected);
+    assert!(
+        reset_lines[0].starts_with(&format!(
+            "{:>5} | open | Service: - | Latency: _ | Banner: - | Reset on read | Error: banner read failed",
+            reset
+        )),
+        "{:?}",
+        reset_lines
+    );
+}
+
+#[test]
+fn banner_retry_reads_the_banner_over_a_second_connection() {
+    let service = FakeService::reset_first(b"SSH-2.0-Late\r\n").start();
+    let port = service.port().to_string();
+    let json = temp_path("banner-retry.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "-q",
+        "--banner-retry",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(service.connections(), 2);
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let result = &summary["results"][0];
+    assert_eq!(result["banner"], "SSH-2.0-Late");
+    assert_eq!(result["banner_retried"], true);
+    assert_eq!(summary["retries"], 1);
+}
+
+#[test]
+fn the_retry_budget_is_shared_by_every_port() {
+    let services: Vec<_> = (0..3).map(|_| FakeService::close().start()).collect();
+    let ports: Vec<String> = services.iter().map(|s| s.port().to_string()).collect();
+    let ports = ports.join(",");
+    let json = temp_path("retry-budget.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--banner-retry",
+        "--max-total-retries",
+        "1",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let connections: usize = services.iter().map(|s| s.connections()).sum();
+    assert_eq!(connections, 4);
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let results = summary["results"].as_array().unwrap();
+    let count = |field: &str| results.iter().filter(|r| r[field] == true).count();
+    assert_eq!(count("banner_retried"), 1);
+    assert_eq!(count("retries_exhausted"), 2);
+    assert_eq!(
+        summary["retry_budget"],
+        serde_json::json!({"limit": 1, "used": 1, "exhausted_ports": 2})
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Retry budget of 1 spent: 2 ports were not retried"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn rescans_carry_the_ports_they_skip_forward_from_the_earlier_report() {
+    let kept_service = FakeService::silent().start();
+    let kept = kept_service.port();
+    let added_service = FakeService::silent().start();
+    let added = added_service.port();
+    let closed = closed_port();
+    let before = temp_path("rescan-before.json");
+    let after = temp_path("rescan-after.json");
+    let text = temp_path("rescan-after.txt");
+
+    let ports = format!("{},{}", kept, closed);
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--json",
+        before.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let earlier: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&before).unwrap()).unwrap();
+
+    let ports = format!("{},{}", closed, added);
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--from",
+        before.to_str().unwrap(),
+        "--json",
+        after.to_str().unwrap(),
+        "--output",
+        text.to_str().unwrap(),
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&after).unwrap()).unwrap();
+    let rescan = &summary["rescan"];
+    assert_eq!(rescan["from"], before.to_str().unwrap());
+    assert_eq!(rescan["first_time"], 1);
+    assert_eq!(rescan["reverified"], 1);
+    assert_eq!(rescan["changed"], 0);
+    assert_eq!(rescan["skipped"], 1);
+    assert_eq!(summary["scanned_ports"], 3);
+    assert_eq!(summary["open_ports"], 2);
+    let result = |port: u16| {
+        summary["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["port"] == port)
+            .unwrap()
+            .clone()
+    };
+    let (kept, added, closed) = (result(kept), result(added), result(closed));
+    assert_eq!(kept["status"], "Open");
+    assert_eq!(kept["stale"], rescan["from_started_at"]);
+    assert_eq!(closed["previous_status"], "Closed");
+    assert!(closed.get("stale").is_none());
+    assert!(added.get("previous_status").is_none() && added.get("stale").is_none());
+
+    // The earlier scan id starts with its start time, in hex milliseconds.
+    let id = earlier["scan_id"].as_str().unwrap();
+    let started = i64::from_str_radix(id.split('-').next().unwrap(), 16).unwrap();
+    let stale: chrono::DateTime<chrono::Utc> = kept["stale"].as_str().unwrap().parse().unwrap();
+    assert_eq!(stale.timestamp_millis(), started);
+    let report = std::fs::read_to_string(&text).unwrap();
+    assert!(report.contains(" | Stale since: "), "{}", report);
+}
+
+#[test]
+fn collapse_runs_prints_consecutive_closed_ports_as_one_line() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port();
+    let ports = format!("{}-{}", open - 3, open + 2);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-v",
+        "verbose",
+        "--collapse-runs",
+        "--status-interval",
+        "60",
+    ]);
+
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let lines: Vec<String> = stdout.lines().map(without_latency).collect();
+    assert_eq!(
+        lines,
+        [
+            format!("{:>5} | open | Service: - | Latency: _ | Banner: -", open),
+            format!("{:>5}-{} | closed | 3 ports", open - 3, open - 1),
+            format!("{:>5}-{} | closed | 2 ports", open + 1, open + 2),
+        ]
+    );
+}
+
+#[test]
+fn exit_codes_follow_the_contract() {
+    let closed = closed_port().to_string();
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+
+    let none_open = scan(&["-t", "127.0.0.1", "-p", &closed, "-q"]);
+    assert_eq!(none_open.status.code(), Some(1));
+    assert!(none_open.stdout.is_empty());
+
+    let bad_target = scan(&["-t", "not-an-ip", "-q"]);
+    assert_eq!(bad_target.status.code(), Some(2));
+
+    let bad_ports = scan(&["-t", "127.0.0.1", "-p", "20-10", "-q"]);
+    assert_eq!(bad_ports.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&bad_ports.stderr);
+    assert!(stderr.contains("Start > end"));
+    assert!(
+        stderr.contains(
+            "  --ports 20-10\n          ^^^^^\n\
+             hint: a range runs from the lower end to the higher: 10-20\n"
+        ),
+        "{}",
+        stderr
+    );
+
+    let all_excluded = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        "22,80",
+        "--exclude-ports",
+        "1-100",
+        "-q",
+    ]);
+    assert_eq!(all_excluded.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&all_excluded.stderr).contains(
+        "hint: --exclude-ports \"1-100\" leaves none of --ports \"22,80\":\n  1-100 excludes 22,80\n"
+    ));
+
+    let no_concurrency = scan(&["-t", "127.0.0.1", "-p", &open, "-q", "-c", "0"]);
+    assert_eq!(no_concurrency.status.code(), Some(2));
+
+    let violation = scan(&["-t", "127.0.0.1", "-p", &open, "-q", "--allow-open", "22"]);
+    assert_eq!(violation.status.code(), Some(4));
+
+    let no_terminal = scan(&["-t", "127.0.0.1", "-p", &open, "--tui"]);
+    assert_eq!(no_terminal.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&no_terminal.stderr).contains("needs a terminal"));
+}
+
+#[test]
+fn minimal_console_prints_one_token_per_open_port() {
+    let open_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let open = open_service.port();
+    let ports = format!("{},{}", open, closed_port());
+
+    for extra in [&[][..], &["-v", "verbose"][..]] {
+        let mut args = vec!["-t", "127.0.0.1", "-p", &ports, "--console", "minimal"];
+        args.extend_from_slice(extra);
+        let out = scan(&args);
+        assert_eq!(out.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            format!("127.0.0.1:{}\n", open)
+        );
+    }
+
+    let silent = scan(&["-t", "127.0.0.1", "-p", &ports, "--console", "silent"]);
+    assert_eq!(silent.status.code(), Some(0));
+    assert!(silent.stdout.is_empty());
+
+    let unknown = scan(&["-t", "127.0.0.1", "-p", &ports, "--console", "brief"]);
+    assert_eq!(unknown.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("unknown console mode 'brief'"));
+}
+
+#[test]
+fn fields_pick_the_console_columns_and_unknown_ones_are_named() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-OpenSSH_9.6\r\n").start();
+    let ssh = ssh_service.port().to_string();
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ssh,
+        "-v",
+        "verbose",
+        "--fields",
+        "service,port,product,version",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l == format!("SSH | {} | OpenSSH | 9.6", ssh)),
+        "{}",
+        stdout
+    );
+
+    let unknown = scan(&["-t", "127.0.0.1", "-p", &ssh, "--fields", "port,latncy"]);
+    assert_eq!(unknown.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&unknown.stderr);
+    assert!(
+        stderr.contains("unknown field 'latncy' (valid fields: target, port, protocol, status,"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn filters_pick_the_reported_results_but_not_the_counts() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-OpenSSH_9.6\r\n").start();
+    let silent_service = FakeService::silent().start();
+    let (ssh, silent) = (ssh_service.port(), silent_service.port());
+    let closed = closed_port();
+    let json = temp_path("filter.json");
+    let ports = format!("{},{},{}", ssh, silent, closed);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "-T",
+        "500",
+        "--filter",
+        "status == 'open' && service != \"ssh\"",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        sorted_lines(&out.stdout),
+        [format!("{:>5} open   -", silent)]
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        (
+            summary["scanned_ports"].clone(),
+            summary["open_ports"].clone()
+        ),
+        (3.into(), 2.into())
+    );
+    let reported: Vec<&serde_json::Value> = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| &r["port"])
+        .collect();
+    assert_eq!(reported, [&serde_json::Value::from(silent)]);
+
+    let invalid = scan(&["-t", "127.0.0.1", "-p", &ports, "--filter", "port <"]);
+    assert_eq!(invalid.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&invalid.stderr);
+    assert!(
+        stderr.contains("expected a field or value instead of the end at position 6"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("  port <\n        ^"), "{}", stderr);
+}
+
+#[test]
+fn metadata_goes_into_every_report() {
+    let closed = closed_port().to_string();
+    let (json, csv, txt) = (
+        temp_path("tagged.json"),
+        temp_path("tagged.csv"),
+        temp_path("tagged.txt"),
+    );
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed,
+        "-q",
+        "--operator",
+        "jsmith",
+        "--reason",
+        "change CHG-1234",
+        "--tag",
+        "env=prod",
+        "--tag",
+        "team=net",
+        "--json",
+        json.to_str().unwrap(),
+        "--csv",
+        csv.to_str().unwrap(),
+        "--output",
+        txt.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        summary["metadata"],
+        serde_json::json!({
+            "operator": "jsmith",
+            "reason": "change CHG-1234",
+            "tags": {"env": "prod", "team": "net"},
+        })
+    );
+    let csv = std::fs::read_to_string(&csv).unwrap();
+    let mut rows = csv.lines();
+    assert!(rows
+        .next()
+        .unwrap()
+        .ends_with(",protocol,operator,reason,tag:env,tag:team"));
+    assert!(rows
+        .next()
+        .unwrap()
+        .ends_with(",tcp,jsmith,change CHG-1234,prod,net"));
+    let txt = std::fs::read_to_string(&txt).unwrap();
+    assert_eq!(
+        txt.lines().nth(1),
+        Some("Operator: jsmith | Reason: change CHG-1234 | Tags: env=prod, team=net")
+    );
+
+    let twice = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed,
+        "--tag",
+        "env=prod",
+        "--tag",
+        "env=dev",
+    ]);
+    assert_eq!(twice.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&twice.stderr);
+    assert!(
+        stderr.contains("--tag env is given more than once"),
+        "{}",
+        stderr
+    );
+    let control = scan(&["-t", "127.0.0.1", "-p", &closed, "--tag", "e\u{7}nv=prod"]);
+    assert_eq!(control.status.code(), Some(2));
+}
+
+#[test]
+fn self_test_passes_on_loopback() {
+    let out = scan(&["self-test", "--timeout", "300"]);
+    let table = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(out.status.code(), Some(0), "{}", table);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 7, "{}", table);
+    assert!(lines[0].starts_with("Check"), "{}", table);
+    assert!(
+        lines[1..6].iter().all(|l| l.ends_with("  pass")),
+        "{}",
+        table
+    );
+    assert!(lines[5].contains("filtered, timed out"), "{}", table);
+    assert!(lines[6].starts_with("5 of 5 checks passed"), "{}", table);
+}
+
+#[test]
+fn reanalyze_upgrades_an_old_report_without_scanning() {
+    let rules = temp_path("reanalyze-rules.toml");
+    std::fs::write(
+        &rules,
+        "[[fingerprint]]\nmatch = \"dropbear\"\nservice = \"SSH\"\nproduct = \"Dropbear\"\n",
+    )
+    .unwrap();
+    let report = temp_path("reanalyze.json");
+    let out = scan(&[
+        "reanalyze",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/old-scan.json"),
+        "--fingerprints",
+        rules.to_str().unwrap(),
+        "-o",
+        report.to_str().unwrap(),
+    ]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert!(
+        stderr.starts_with("6 classifications changed on 2 ports\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("127.0.0.1:8080 product: - -> nginx\n"),
+        "{}",
+        stderr
+    );
+
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let ssh = &json["results"][0];
+    assert_eq!(ssh["target"], "127.0.0.1");
+    assert_eq!(ssh["product"], "Dropbear");
+    assert_eq!(ssh["version"], "2020.81");
+    assert_eq!(json["results"][1]["service"], serde_json::Value::Null);
+    let web = &json["results"][2];
+    assert_eq!(web["service"], "HTTP");
+    assert_eq!(web["product"], "nginx");
+    assert_eq!(web["version"], "1.18.0");
+    assert_eq!(web["extra_info"], "Ubuntu");
+    assert_eq!(web["cpe"], "cpe:2.3:a:f5:nginx:1.18.0:*:*:*:*:*:*:*");
+    let changes = json["reanalysis"]["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 6);
+    assert_eq!(changes[0]["field"], "product");
+    assert_eq!(changes[0]["before"], serde_json::Value::Null);
+    assert_eq!(changes[0]["after"], "Dropbear");
+    // Everything else of the report is as it was.
+    assert_eq!(json["target"], "127.0.0.1");
+    assert_eq!(json["open_ports"], 2);
+
+    let _ = std::fs::remove_file(rules);
+    let _ = std::fs::remove_file(report);
+}
+
+#[test]
+fn reports_match_the_printed_schema() {
+    let out = scan(&["schema"]);
+    assert_eq!(out.status.code(), Some(0));
+    let schema: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(
+        schema["$id"],
+        format!(
+            "urn:port-scanner:scan-summary:{}",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+
+    let ssh_service = FakeService::banner(b"SSH-2.0-OpenSSH_9.6\r\n").start();
+    let reset_service = FakeService::reset().start();
+    let ports = format!(
+        "{},{},{}",
+        ssh_service.port(),
+        reset_service.port(),
+        closed_port()
+    );
+    let json = temp_path("schema.json");
+    let ndjson = temp_path("schema.ndjson");
+    let out = scan(&[
+        "-t",
+        "127.0.0.1,192.0.2.1",
+        "-p",
+        &ports,
+        "-q",
+        "-T",
+        "300",
+        "--evidence",
+        "--audit",
+        "--stats",
+        "--fail-on",
+        &format!("open:{}", ssh_service.port()),
+        "--json",
+        json.to_str().unwrap(),
+        "--ndjson",
+        ndjson.to_str().unwrap(),
+    ]);
+    assert_eq!(
+        out.status.code(),
+        Some(4),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert!(report["stats"].is_object() && report["policy"].is_object());
+    assert_eq!(port_scanner::schema::validate(&schema, &report), Ok(()));
+
+    let mut line_schema = schema.clone();
+    line_schema["$ref"] = "#/$defs/PortResult".into();
+    let lines = std::fs::read_to_string(&ndjson).unwrap();
+    for line in lines.lines() {
+        let result: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(
+            port_scanner::schema::validate(&line_schema, &result),
+            Ok(())
+        );
+    }
+    assert_eq!(lines.lines().count(), 6);
+}
+
+#[test]
+fn public_targets_need_consent() {
+    let closed = closed_port().to_string();
+
+    let refused = scan(&["-t", "127.0.0.1,8.8.8.8", "-p", &closed, "-q"]);
+    assert_eq!(refused.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&refused.stderr);
+    assert!(stderr.contains("1 public hosts (8.8.8.8)"), "{}", stderr);
+    assert!(stderr.contains("--allow-public"), "{}", stderr);
+
+    let private_only = scan(&["-t", "127.0.0.1,8.8.8.8", "-p", &closed, "--private-only"]);
+    assert_eq!(private_only.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&private_only.stderr);
+    assert!(stderr.contains("Leaving out 1 public hosts"), "{}", stderr);
+    assert!(!String::from_utf8_lossy(&private_only.stdout).contains("8.8.8.8"));
+}
+
+#[test]
+fn dry_run_prints_the_plan_without_connecting() {
+    let out = scan(&[
+        "-t",
+        "10.0.0.0/30,8.8.8.8",
+        "--exclude",
+        "10.0.0.1",
+        "-p",
+        "1-100",
+        "--exclude-ports",
+        "50-100",
+        "--per-host-concurrency",
+        "4",
+        "--window",
+        "01:00-05:00 UTC",
+        "--deterministic",
+        "--fake-time",
+        "2026-01-05T06:00:00Z",
+        "--dry-run",
+        "--json",
+        "-",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(plan["targets"], "8.8.8.8,10.0.0.0,10.0.0.2/31");
+    assert_eq!(plan["target_count"], 4);
+    assert_eq!(plan["public_hosts"], 1);
+    assert_eq!(plan["ports"], "1-49");
+    assert_eq!(plan["total_pairs"], 196);
+    let limits = &plan["rate_limits"];
+    assert_eq!(limits["per_host_concurrency"], 4);
+    assert_eq!(limits["window"], "01:00-05:00 UTC");
+    assert_eq!(limits["paused"], true);
+    assert_eq!(limits["until"], "Tue 01:00");
+}
+
+#[test]
+fn json_logs_carry_the_port_span() {
+    let closed = closed_port();
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed.to_string(),
+        "-q",
+        "-v",
+        "verbose",
+        "--log-format",
+        "json",
+    ]);
+
+    assert_eq!(out.status.code(), Some(1));
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&out.stderr)
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("one JSON object per line"))
+        .collect();
+    let finished = events
+        .iter()
+        .find(|e| e["fields"]["message"] == "connect finished")
+        .expect("a connect event");
+    assert_eq!(finished["span"]["port"], closed);
+    assert_eq!(finished["fields"]["status"], "Closed");
+}
+
+#[cfg(unix)]
+#[test]
+fn exec_results_land_in_the_report() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let json = temp_path("exec.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ssh.to_string(),
+        "-q",
+        "--exec",
+        "echo {service} on {port}",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let exec = &summary["results"][0]["exec"];
+    assert_eq!(exec["exit_code"], 0);
+    assert_eq!(exec["stdout"], format!("SSH on {}", ssh));
+
+    let failing = ["--exec", "false", "--exec-fail-fatal"];
+    let ports = ssh.to_string();
+    let mut args = vec!["-t", "127.0.0.1", "-p", &ports, "-q"];
+    args.extend(failing);
+    assert_eq!(scan(&args).status.code(), Some(5));
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn proxy_check_reports_an_open_proxy_as_a_finding() {
+    let proxy_service = FakeService::proxy().start();
+    let proxy = proxy_service.port().to_string();
+    let json = temp_path("proxy.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &proxy,
+        "-q",
+        "--proxy-check",
+        "--proxy-ports",
+        &proxy,
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let result = &summary["results"][0];
+    assert_eq!(result["open_proxy"], true);
+    assert_eq!(result["findings"][0]["id"], "OPEN-PROXY");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &proxy,
+        "-q",
+        "--proxy-check",
+        "--proxy-ports",
+        &proxy,
+        "--fail-on",
+        "severity:high",
+    ]);
+    assert_eq!(out.status.code(), Some(4));
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn saved_responses_are_the_exact_bytes_sent() {
+    const SENT: &[u8] = b"\x00\xffSSH-2.0-Test\r\nsecond line \xc3\x28\r\n\r\n";
+    let service = FakeService::banner(SENT).start();
+    let port = service.port();
+    let closed = closed_port();
+    let dir = temp_path("responses");
+    let json = temp_path("responses.json");
+    let ports = format!("{},{}", port, closed);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--banner-timeout",
+        "200ms",
+        "--save-responses",
+        dir.to_str().unwrap(),
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let saved = dir.join("127.0.0.1").join(format!("{}.bin", port));
+    assert_eq!(std::fs::read(&saved).unwrap(), SENT);
+    assert!(!dir
+        .join("127.0.0.1")
+        .join(format!("{}.bin", closed))
+        .exists());
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let result = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["port"] == port)
+        .unwrap();
+    assert_eq!(result["response_file"], saved.to_str().unwrap());
+
+    let capped = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "-q",
+        "--banner-timeout",
+        "200ms",
+        "--save-responses",
+        dir.to_str().unwrap(),
+        "--response-cap",
+        "6",
+    ]);
+    assert_eq!(capped.status.code(), Some(0));
+    assert_eq!(std::fs::read(&saved).unwrap(), &SENT[..6]);
+    let _ = std::fs::remove_dir_all(dir);
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn redaction_reaches_every_report_but_not_raw_responses() {
+    const SENT: &[u8] = b"220 mail ready, ops@corp.example.com, spool /srv/billing\r\n";
+    let service = FakeService::banner(SENT).start();
+    let port = service.port().to_string();
+    let dir = temp_path("redacted-responses");
+    let files = ["json", "ndjson", "csv", "junit", "output"]
+        .map(|format| (format, temp_path(&format!("redacted-{}", format))));
+    let mut args = vec![
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "-v",
+        "verbose",
+        "--banner-timeout",
+        "200ms",
+        "--redact",
+        "/srv/[a-z]+",
+        "--save-responses",
+        dir.to_str().unwrap(),
+    ];
+    let flags = files.clone().map(|(format, _)| format!("--{}", format));
+    for ((_, path), flag) in files.iter().zip(&flags) {
+        args.extend([flag.as_str(), path.to_str().unwrap()]);
+    }
+
+    let out = scan(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let console = String::from_utf8_lossy(&out.stdout);
+    let mut reports = vec![("console", console.into_owned())];
+    for (format, path) in &files {
+        reports.push((format, std::fs::read_to_string(path).unwrap()));
+    }
+    for (format, report) in &reports {
+        // JUnit only names the open port; the rest show its banner.
+        assert!(
+            *format == "junit" || report.contains("[REDACTED]"),
+            "{}: {}",
+            format,
+            report
+        );
+        assert!(
+            !report.contains("corp.example.com") && !report.contains("billing"),
+            "{}: {}",
+            format,
+            report
+        );
+    }
+    let summary: serde_json::Value = serde_json::from_str(&reports[1].1).unwrap();
+    assert_eq!(
+        summary["results"][0]["banner"],
+        "220 mail ready, [REDACTED], spool [REDACTED]"
+    );
+    assert_eq!(summary["results"][0]["redactions"], 2);
+    let saved = dir.join("127.0.0.1").join(format!("{}.bin", port));
+    assert_eq!(std::fs::read(&saved).unwrap(), SENT);
+
+    let raw = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "-q",
+        "--banner-timeout",
+        "200ms",
+        "--redact",
+        "--redact-raw",
+        "--save-responses",
+        dir.to_str().unwrap(),
+    ]);
+    assert_eq!(raw.status.code(), Some(0));
+    assert_eq!(
+        std::fs::read(&saved).unwrap(),
+        b"220 mail ready, [REDACTED], spool /srv/billing\r\n"
+    );
+
+    let invalid = scan(&["-t", "127.0.0.1", "-p", &port, "--redact", "("]);
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&invalid.stderr).contains("Invalid --redact pattern '('"));
+
+    let _ = std::fs::remove_dir_all(dir);
+    for (_, path) in files {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn capture_memory_budget_cuts_results_to_previews() {
+    static BANNER: [u8; 600] = [b'x'; 600];
+    let services: Vec<FakeService> = (0..8)
+        .map(|_| FakeService::banner(&BANNER).start())
+        .collect();
+    let ports = services
+        .iter()
+        .map(|s| s.port().to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    for low_memory in [false, true] {
+        let json = temp_path(&format!("capture-budget-{}.json", low_memory));
+        let mut args = vec![
+            "-t",
+            "127.0.0.1",
+            "-p",
+            &ports,
+            "--banner-timeout",
+            "200ms",
+            "--max-capture-mem",
+            "1K",
+            "--json",
+            json.to_str().unwrap(),
+        ];
+        if low_memory {
+            args.push("--low-memory");
+        }
+        let out = scan(&args);
+        assert_eq!(out.status.code(), Some(0));
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        std::fs::remove_file(&json).unwrap();
+
+        // Whichever came first, only one full banner fits in 1KiB.
+        let results = summary["results"].as_array().unwrap();
+        let banner = |r: &serde_json::Value| r["banner"].as_str().unwrap().len();
+        let (cut, whole): (Vec<_>, Vec<_>) =
+            results.iter().partition(|r| r["capture_truncated"] == true);
+        assert_eq!(cut.len(), 7, "{:#}", summary);
+        assert_eq!(summary["capture_truncated_ports"], 7);
+        assert!(cut.iter().all(|r| banner(r) == 64));
+        assert_eq!(whole.iter().map(|r| banner(r)).sum::<usize>(), 600);
+        assert!(String::from_utf8_lossy(&out.stderr).contains("7 results were cut to previews"));
+    }
+}
+
+#[test]
+fn annotations_are_merged_and_deviations_counted() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port();
+    let closed = closed_port();
+    let inventory = temp_path("inventory.toml");
+    let json = temp_path("annotated.json");
+    let txt = temp_path("annotated.txt");
+    std::fs::write(
+        &inventory,
+        format!(
+            "[[annotation]]\ntarget = \"127.0.0.*\"\nport = {}\nnote = \"old telnet\"\n\
+             expected = \"closed\"\n\n\
+             [[annotation]]\nport = \"{}\"\nnote = \"prod postgres\"\nexpected = \"open\"\n",
+            open, closed
+        ),
+    )
+    .unwrap();
+    let ports = format!("{},{}", open, closed);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--annotations",
+        inventory.to_str().unwrap(),
+        "--json",
+        json.to_str().unwrap(),
+        "--output",
+        txt.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        summary["deviations"],
+        serde_json::json!({"annotated": 2, "unexpected_open": 1, "not_open": 1, "other": 0})
+    );
+    let postgres = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["port"] == closed)
+        .unwrap();
+    assert_eq!(postgres["note"], "prod postgres");
+    assert_eq!(postgres["expected_status"], "Open");
+    let text = std::fs::read_to_string(&txt).unwrap();
+    assert!(
+        text.contains("Annotations: 2 of 2 annotated ports deviate"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("| Note: old telnet | Expected: closed (DEVIATION)"),
+        "{}",
+        text
+    );
+
+    std::fs::write(&inventory, "[[annotation]]\nport = 22\nexpected = \"up\"\n").unwrap();
+    let bad = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--annotations",
+        inventory.to_str().unwrap(),
+    ]);
+    assert_eq!(bad.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&bad.stderr).contains("annotation 1 (line 1): unknown status 'up'")
+    );
+    for path in [inventory, json, txt] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn baseline_drift_is_reported_and_fails_when_asked_to() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port();
+    let closed = closed_port();
+    let baseline = temp_path("baseline.toml");
+    let json = temp_path("drift.json");
+    let ports = format!("{},{}", open, closed);
+    let run = |extra: &[&str]| {
+        let mut args = vec![
+            "-t",
+            "127.0.0.1",
+            "-p",
+            &ports,
+            "-q",
+            "--baseline",
+            baseline.to_str().unwrap(),
+            "--json",
+            json.to_str().unwrap(),
+        ];
+        args.extend_from_slice(extra);
+        let out = scan(&args);
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        (out, summary)
+    };
+
+    // Drift both ways: the expected port is closed, another is open.
+    std::fs::write(
+        &baseline,
+        format!("[[host]]\ntarget = \"127.0.0.1\"\nopen = [{}]\n", closed),
+    )
+    .unwrap();
+    let (out, summary) = run(&["--fail-on-drift", "high"]);
+    assert_eq!(out.status.code(), Some(4));
+    let drift: Vec<(&str, &str, u64)> = summary["compliance"]["drift"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| {
+            (
+                d["kind"].as_str().unwrap(),
+                d["severity"].as_str().unwrap(),
+                d["port"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        drift,
+        [
+            ("new-open", "high", open as u64),
+            ("closed", "medium", closed as u64)
+        ]
+    );
+    assert_eq!(summary["findings"]["high"], 1);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Compliance:"), "{}", stderr);
+
+    // Only new open ports matter at this severity.
+    let (out, _) = run(&[
+        "--drift-severity",
+        "new-open=low",
+        "--fail-on-drift",
+        "high",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+
+    // An exact match: no drift, and the scan passes.
+    std::fs::write(
+        &baseline,
+        format!("[[host]]\ntarget = \"127.0.0.1\"\nopen = \"{}\"\n", open),
+    )
+    .unwrap();
+    let (out, summary) = run(&["--fail-on-drift"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(summary["compliance"]["matched"], 1);
+    assert_eq!(summary["compliance"]["drift"], serde_json::json!([]));
+
+    // The report of that scan is itself a baseline.
+    let report = temp_path("drift-baseline.json");
+    std::fs::copy(&json, &report).unwrap();
+    std::fs::copy(&report, &baseline).unwrap();
+    let (out, summary) = run(&["--fail-on-drift"]);
+    assert_eq!(
+        out.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(summary["compliance"]["expected_open"], 1);
+
+    for path in [baseline, json, report] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn os_guesses_head_the_text_report_and_follow_the_rules_given() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-OpenSSH_9.2p1 Debian-2+deb12u3\r\n").start();
+    let ssh = ssh_service.port().to_string();
+    let txt = temp_path("os-guess.txt");
+    let json = temp_path("os-guess.json");
+    let rules = temp_path("os-rules.json");
+    let base = ["-t", "127.0.0.1", "-p", &ssh, "-q", "--os-guess"];
+
+    let mut args = base.to_vec();
+    args.extend([
+        "--output",
+        txt.to_str().unwrap(),
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(scan(&args).status.code(), Some(0));
+    let text = std::fs::read_to_string(&txt).unwrap();
+    assert!(
+        text.lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("OS: Linux (guess, medium confidence: \"Debian\" in the banner on port"),
+        "{}",
+        text
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["hosts"][0]["os_guess"]["os"], "Linux");
+
+    std::fs::write(
+        &rules,
+        r#"[{"os": "TempleOS", "banner": "deb12", "weight": 9}]"#,
+    )
+    .unwrap();
+    let mut args = base.to_vec();
+    args.extend([
+        "--os-rules",
+        rules.to_str().unwrap(),
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(scan(&args).status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["hosts"][0]["os_guess"]["os"], "TempleOS");
+    assert_eq!(summary["hosts"][0]["os_guess"]["confidence"], "high");
+
+    std::fs::write(&rules, r#"[{"os": "Nothing"}]"#).unwrap();
+    let mut args = base.to_vec();
+    args.extend(["--os-rules", rules.to_str().unwrap()]);
+    assert_eq!(scan(&args).status.code(), Some(2));
+
+    for path in [txt, json, rules] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn vuln_db_findings_are_reported_and_can_fail_the_scan() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test_1.2p1\r\n").start();
+    let ssh = ssh_service.port().to_string();
+    let db = temp_path("vulns.json");
+    let json = temp_path("vulns-report.json");
+    std::fs::write(
+        &db,
+        r#"[
+            {"id": "TEST-1", "severity": "high", "summary": "old Test",
+             "product": "test", "versions": "<1.10"},
+            {"id": "TEST-2", "severity": "low", "summary": "any Test", "product": "Test"},
+            {"id": "TEST-3", "severity": "critical", "summary": "new Test",
+             "product": "Test", "versions": ">=2"}
+        ]"#,
+    )
+    .unwrap();
+    let base = ["-t", "127.0.0.1", "-p", &ssh, "-q", "--vuln-db"];
+
+    let mut args = base.to_vec();
+    args.extend([db.to_str().unwrap(), "--json", json.to_str().unwrap()]);
+    let out = scan(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("HIGH TEST-1 old Test"), "{}", stdout);
+    assert!(!stdout.contains("TEST-3"));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["findings"]["high"], 1);
+    assert_eq!(summary["findings"]["low"], 1);
+    assert_eq!(summary["results"][0]["findings"][0]["id"], "TEST-1");
+
+    let mut args = base.to_vec();
+    args.extend([db.to_str().unwrap(), "--fail-on", "severity:high"]);
+    assert_eq!(scan(&args).status.code(), Some(4));
+    let mut args = base.to_vec();
+    args.extend([db.to_str().unwrap(), "--fail-on", "severity:critical"]);
+    assert_eq!(scan(&args).status.code(), Some(0));
+    // Without a source of findings the rule could never fail.
+    let out = scan(&["-t", "127.0.0.1", "-p", &ssh, "--fail-on", "severity:high"]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("needs --vuln-db"), "{}", stderr);
+
+    std::fs::write(&db, "{}").unwrap();
+    let mut args = base.to_vec();
+    args.push(db.to_str().unwrap());
+    assert_eq!(scan(&args).status.code(), Some(2));
+    let _ = std::fs::remove_file(db);
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn unusable_geo_databases_leave_hosts_labeled_by_scope() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let csv = temp_path("geo.csv");
+    let json = temp_path("geo.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--geoip-db",
+        "/nonexistent/GeoLite2-City.mmdb",
+        "--csv",
+        csv.to_str().unwrap(),
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("hosts get no country"));
+    let rows = std::fs::read_to_string(&csv).unwrap();
+    assert!(rows
+        .lines()
+        .next()
+        .unwrap()
+        .ends_with(",country,asn,as_name,protocol"));
+    assert!(
+        rows.lines().nth(1).unwrap().ends_with(",private,,,tcp"),
+        "{}",
+        rows
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        summary["hosts"],
+        serde_json::json!([{"address": "127.0.0.1", "scope": "private"}])
+    );
+    let _ = std::fs::remove_file(csv);
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn scan_subcommand_matches_the_bare_invocation() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let ports = ssh.to_string();
+
+    let bare = scan(&["-t", "127.0.0.1", "-p", &ports, "-q"]);
+    let explicit = scan(&["scan", "-t", "127.0.0.1", "-p", &ports, "-q"]);
+
+    assert_eq!(bare.status.code(), Some(0));
+    assert_eq!(explicit.status.code(), Some(0));
+    assert_eq!(bare.stdout, explicit.stdout);
+}
+
+#[test]
+fn completions_and_manpage_are_generated() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        Command::cargo_bin("port-scanner")
+            .unwrap()
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("port-scanner"));
+    }
+
+    Command::cargo_bin("port-scanner")
+        .unwrap()
+        .arg("examples")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "  port-scanner -t 192.168.1.10 -p 22,80,443,8000-8100\n",
+        ));
+
+    Command::cargo_bin("port-scanner")
+        .unwrap()
+        .arg("manpage")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(".TH port-scanner 1"))
+        .stdout(predicates::str::contains("completions"));
+
+    Command::cargo_bin("port-scanner")
+        .unwrap()
+        .args(["completions", "bash", "-t", "127.0.0.1"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn deterministic_reports_are_byte_identical() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let web_service = FakeService::silent().start();
+    let web = web_service.port();
+    let closed = closed_port();
+    let ports = format!("{},{},{}", ssh, web, closed);
+
+    let run = |name: &str| {
+        let report = |ext: &str| temp_path(&format!("determ-{}.{}", name, ext));
+        let paths = [report("json"), report("csv"), report("xml"), report("txt")];
+        let args = [
+            "-t",
+            "127.0.0.1",
+            "-p",
+            &ports,
+            "-q",
+            "--deterministic",
+            "--fake-time",
+            "2026-01-02T03:04:05Z",
+            "--json",
+            paths[0].to_str().unwrap(),
+            "--csv",
+            paths[1].to_str().unwrap(),
+            "--junit",
+            paths[2].to_str().unwrap(),
+            "--output",
+            paths[3].to_str().unwrap(),
+        ];
+        assert_eq!(scan(&args).status.code(), Some(0));
+        paths.map(|p| {
+            let bytes = std::fs::read(&p).unwrap();
+            let _ = std::fs::remove_file(p);
+            bytes
+        })
+    };
+    let first = run("a");
+    let second = run("b");
+    assert_eq!(first, second);
+
+    let summary: serde_json::Value = serde_json::from_slice(&first[0]).unwrap();
+    assert_eq!(summary["scan_id"], "19b7ca98c88");
+    assert_eq!(summary["total_time_ms"], 0);
+    let ports: Vec<u64> = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["port"].as_u64().unwrap())
+        .collect();
+    let mut sorted = ports.clone();
+    sorted.sort();
+    assert_eq!(ports, sorted);
+}
+
+#[test]
+fn stability_is_worked_out_over_a_directory_of_reports() {
+    let steady_service = FakeService::silent().start();
+    let steady = steady_service.port().to_string();
+    let flaky_service = FakeService::silent().start();
+    let flaky = flaky_service.port().to_string();
+    let dir = temp_path("history");
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |day: u32, ports: &str| {
+        let report = |ext: &str| dir.join(format!("day{}.{}", day, ext));
+        let env = if day % 2 == 1 {
+            "env=prod"
+        } else {
+            "env=stage"
+        };
+        let time = format!("2026-03-0{}T00:00:00Z", day);
+        let out = scan(&[
+            "-t",
+            "127.0.0.1",
+            "-p",
+            ports,
+            "-q",
+            "--deterministic",
+            "--fake-time",
+            &time,
+            "--tag",
+            env,
+            "--json",
+            report("json").to_str().unwrap(),
+            "--output",
+            report("txt").to_str().unwrap(),
+        ]);
+        assert_eq!(out.status.code(), Some(0));
+    };
+    let both = format!("{},{}", steady, flaky);
+    // Dated out of order: the flaky port is open on days 1 and 3, closed
+    // on day 2 and not scanned on day 4.
+    run(1, &both);
+    run(3, &both);
+    drop(flaky_service);
+    run(2, &both);
+    run(4, &steady);
+
+    let history = |args: &[&str]| {
+        let mut all = vec!["history", "stability", dir.to_str().unwrap()];
+        all.extend_from_slice(args);
+        let out = scan(&all);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let csv = history(&["-t", "127.0.0.0/8", "--format", "csv", "--flapping", "2"]);
+    let mut lines = csv.lines();
+    assert!(lines.next().unwrap().starts_with("target,protocol,port,"));
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_by_key(|row| row.contains(&flaky));
+    assert_eq!(
+        rows,
+        [
+            format!(
+                "127.0.0.1,tcp,{},4,4,4,100.0,2026-03-01T00:00:00+00:00,2026-03-04T00:00:00+00:00,open,0,false",
+                steady
+            ),
+            format!(
+                "127.0.0.1,tcp,{},4,3,2,66.7,2026-03-01T00:00:00+00:00,2026-03-03T00:00:00+00:00,open,2,true",
+                flaky
+            ),
+        ]
+    );
+
+    let table = history(&[]);
+    assert!(
+        table.starts_with("4 scans, 2 ports open at least once, 0 flapping (3+ changes)\n"),
+        "{}",
+        table
+    );
+    assert!(table.contains("unobserved in 1 of 4 scans"), "{}", table);
+
+    let elsewhere = history(&["-t", "10.0.0.1", "--format", "json"]);
+    let report: serde_json::Value = serde_json::from_str(&elsewhere).unwrap();
+    assert_eq!(report["scans"], 4);
+    assert_eq!(report["ports"], serde_json::json!([]));
+
+    // Days 1 and 3, when the flaky port was open.
+    let prod = history(&["--tag", "env=prod", "--format", "json"]);
+    let report: serde_json::Value = serde_json::from_str(&prod).unwrap();
+    assert_eq!(report["scans"], 2);
+    for port in report["ports"].as_array().unwrap() {
+        assert_eq!((&port["open"], &port["changes"]), (&2.into(), &0.into()));
+    }
+    let none = history(&["--tag", "env=prod", "--tag", "env=stage"]);
+    assert!(none.starts_with("0 scans"), "{}", none);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn hosts_named_by_several_targets_are_scanned_once_with_their_aliases() {
+    let service = FakeService::silent().start();
+    let open = service.port().to_string();
+    let json = temp_path("aliases.json");
+
+    let out = scan(&[
+        "-t",
+        "localhost,127.0.0.1",
+        "-t",
+        "127.0.0.0/30,127.0.0.0/31",
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("127.0.0.1 is given more than once"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("127.0.0.0/30 and 127.0.0.0/31 both name 127.0.0.0/31"),
+        "{}",
+        stderr
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let _ = std::fs::remove_file(json);
+
+    let scanned = |address: &str| {
+        summary["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|r| r["target"] == address)
+            .count()
+    };
+    for address in ["127.0.0.0", "127.0.0.1", "127.0.0.2", "127.0.0.3"] {
+        assert_eq!(scanned(address), 1, "{}", address);
+    }
+    let host = |address: &str| {
+        summary["hosts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["address"] == address)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(
+        host("127.0.0.1")["aliases"],
+        serde_json::json!(["localhost", "127.0.0.1", "127.0.0.0/30", "127.0.0.0/31"])
+    );
+    assert_eq!(
+        host("127.0.0.0")["aliases"],
+        serde_json::json!(["127.0.0.0/30", "127.0.0.0/31"])
+    );
+    assert!(host("127.0.0.3").get("aliases").is_none());
+    assert_eq!(summary["targets"].as_array().unwrap().len(), 4);
+}
+
+#[test]
+fn hosts_scanned_by_ip_are_matched_by_name_when_scanned_by_name() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let before = temp_path("identity-before.json");
+    let after = temp_path("identity-after.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--json",
+        before.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+
+    let out = scan(&[
+        "-t",
+        "localhost",
+        "-p",
+        &open,
+        "--identity-by",
+        "name",
+        "--from",
+        before.to_str().unwrap(),
+        "--json",
+        after.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("identified hosts by address, this run by name"),
+        "{}",
+        stderr
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&after).unwrap()).unwrap();
+    assert_eq!(summary["identity"], "name");
+    assert_eq!(summary["rescan"]["skipped"], 0);
+    let host = summary["hosts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["address"] == "127.0.0.1")
+        .unwrap();
+    assert_eq!(host["names"], serde_json::json!(["localhost"]));
+    let _ = std::fs::remove_file(before);
+    let _ = std::fs::remove_file(after);
+}
+
+#[test]
+fn host_name_targets_are_resolved_and_reported_with_their_answers() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let json = temp_path("dns.json");
+
+    let out = scan(&[
+        "-t",
+        "localhost",
+        "-p",
+        &open,
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let host = summary["hosts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["address"] == "127.0.0.1")
+        .unwrap();
+    assert_eq!(host["dns"][0]["name"], "localhost");
+    assert_eq!(host["dns"][0]["server"], "system");
+    let _ = std::fs::remove_file(json);
+
+    let dead = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let unresolved = scan(&[
+        "-t",
+        "scan-target.invalid",
+        "-q",
+        "--resolver",
+        &dead,
+        "--resolver-timeout",
+        "200ms",
+    ]);
+    assert_eq!(unresolved.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&unresolved.stderr).contains(&format!(
+            "Could not resolve scan-target.invalid (tried {})",
+            dead
+        ))
+    );
+}
+
+#[test]
+fn tls_reports_compare_the_ports_of_an_nmap_report() {
+    let report = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/nmap-tls.xml");
+    let tls_report = |format: &str| {
+        let out = scan(&[
+            "tls-report",
+            report,
+            "--at",
+            "2026-03-10T00:00:00Z",
+            "--format",
+            format,
+        ]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let table = tls_report("table");
+    assert!(
+        table.starts_with("3 TLS ports, 2 standing out (certificates checked against 2026-03-10 00:00, expiring within 30 days)\n"),
+        "{}",
+        table
+    );
+    assert!(
+        table.contains("accepts TLSv1.0; tops out at TLSv1.2 where most reach TLSv1.3"),
+        "{}",
+        table
+    );
+
+    let markdown = tls_report("markdown");
+    assert!(
+        markdown.contains(
+            "| 10.0.0.7 | 443 | TLSv1.2 | TLSv1.3 | Lab Internal | **2026-03-01 (expired)** \
+             | ec 256 | http/1.1 | certificate expired 8 days ago |\n"
+        ),
+        "{}",
+        markdown
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&tls_report("json")).unwrap();
+    assert_eq!(json["results"][1]["tls"]["versions"][1], "TLSv1.3");
+    let block = &json["tls_report"];
+    assert_eq!(block["expiring_days"], 30);
+    assert_eq!(block["endpoints"].as_array().unwrap().len(), 3);
+    assert_eq!(block["endpoints"][0]["days_left"], 83);
+    assert_eq!(
+        block["endpoints"][1]["outliers"][0],
+        serde_json::json!({"kind": "legacy", "version": "TLSv1.0"})
+    );
+}
+
+#[test]
+fn tls_reports_read_the_reports_of_the_first_release() {
+    let out = scan(&[
+        "tls-report",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/old-scan.json"),
+    ]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(
+        stdout.starts_with("0 TLS ports, 0 standing out"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn ptr_sweeps_tell_unanswered_lookups_apart() {
+    let dead = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let out = scan(&[
+        "ptr",
+        "-t",
+        "192.0.2.1-192.0.2.3",
+        "--exclude",
+        "192.0.2.2",
+        "--resolver",
+        &dead,
+        "--timeout",
+        "200ms",
+        "--format",
+        "csv",
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!(
+            "address,name,status,server,message\n\
+             192.0.2.1,,timeout,{0},\n\
+             192.0.2.3,,timeout,{0},\n",
+            dead
+        )
+    );
+
+    let out = scan(&["ptr", "-t", "example.com", "--resolver", &dead]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("Invalid target:"));
+}
+
+#[test]
+fn bad_targets_are_reported_and_the_rest_scanned() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let json = temp_path("targets.json");
+    let text = temp_path("targets.txt");
+    let dead = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let out = scan(&[
+        "-t",
+        "scan-target.invalid,127.0.0.1,10.0.0.1/33",
+        "-t",
+        "127.0.0.2",
+        "--exclude",
+        "127.0.0.2",
+        "-p",
+        &open,
+        "-q",
+        "--resolver",
+        &dead,
+        "--resolver-timeout",
+        "200ms",
+        "--json",
+        json.to_str().unwrap(),
+        "--output",
+        text.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(6));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(
+        stdout.trim(),
+        format!("{} open   -", open),
+        "the rest is scanned"
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("2 of 4 targets could not be scanned: scan-target.invalid, 10.0.0.1/33"),
+        "{}",
+        stderr
+    );
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["open_ports"], 1);
+    let targets: Vec<(&str, &str)> = summary["targets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| (t["target"].as_str().unwrap(), t["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            ("scan-target.invalid", "unresolvable"),
+            ("127.0.0.1", "scanned"),
+            ("10.0.0.1/33", "invalid"),
+            ("127.0.0.2", "excluded"),
+        ]
+    );
+    assert!(summary["targets"][0]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Could not resolve scan-target.invalid"));
+    let report = std::fs::read_to_string(&text).unwrap();
+    assert!(report.contains("Target 10.0.0.1/33: not scanned, invalid (Invalid target:"));
+    assert!(report.contains("Target 127.0.0.2: not scanned, excluded\n"));
+    let _ = std::fs::remove_file(json);
+    let _ = std::fs::remove_file(text);
+}
+
+#[test]
+fn traceroute_adds_the_path_or_says_why_not() {
+    let closed = closed_port().to_string();
+    let json = temp_path("route.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed,
+        "-q",
+        "--traceroute",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    if cfg!(all(feature = "traceroute", target_os = "linux")) {
+        let route = &summary["hosts"][0]["route"];
+        assert_eq!(route.as_array().unwrap().len(), 1, "{}", route);
+        assert_eq!(route[0]["address"], "127.0.0.1");
+    } else {
+        assert!(String::from_utf8_lossy(&out.stderr).contains("hosts get no route"));
+        assert!(summary.get("hosts").is_none());
+    }
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn local_discovery_adds_to_the_targets_it_is_given() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "--local-discovery",
+        "--discovery-window",
+        "0.3",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Local discovery found") || stderr.contains("Local discovery failed"),
+        "{}",
+        stderr
+    );
+
+    let out = scan(&["discover", "--window", "0.3", "--json"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(serde_json::from_slice::<serde_json::Value>(&out.stdout)
+        .unwrap()
+        .is_object());
+}
+
+#[test]
+fn arp_discovery_leaves_loopback_alone_or_says_why_not() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+
+    let out = scan(&["-t", "127.0.0.1", "-p", &open, "-q", "--arp-discovery"]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    if cfg!(all(feature = "arp", target_os = "linux")) {
+        assert!(
+            stderr.contains("ARP: 0 local hosts answered, 0 didn't")
+                || stderr.contains("needs root or CAP_NET_RAW"),
+            "{}",
+            stderr
+        );
+    } else {
+        assert!(stderr.contains("scanning every host"), "{}", stderr);
+    }
+}
+
+#[test]
+fn ipv6_local_discovery_checks_the_interface_or_says_why_not() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--ipv6-local-discovery",
+        "--ipv6-interface",
+        "lo",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("scanning the given targets only"),
+        "{}",
+        stderr
+    );
+    if cfg!(all(feature = "ndp", target_os = "linux")) {
+        assert!(
+            stderr.contains("lo is not an up interface with an IPv6 link-local address")
+                || stderr.contains("needs root or CAP_NET_RAW"),
+            "{}",
+            stderr
+        );
+    } else {
+        assert!(
+            stderr.contains("IPv6 neighbor discovery unavailable"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+/// The decoded JSON attachment of a mailed report.
+fn attached_json(message: &str) -> serde_json::Value {
+    use base64::Engine;
+    let part = message
+        .split("Content-Disposition: attachment;")
+        .nth(1)
+        .expect("message has an attachment");
+    let encoded: String = part
+        .split("\r\n\r\n")
+        .nth(1)
+        .unwrap()
+        .lines()
+        .take_while(|l| !l.starts_with("--"))
+        .collect();
+    let json = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .unwrap();
+    serde_json::from_slice(&json).unwrap()
+}
+
+#[test]
+fn the_report_is_mailed_when_ports_are_open() {
+    let relay = SmtpSink::start();
+    let open_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let open = open_service.port().to_string();
+    let server = relay.server();
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--email-to",
+        "ops@example.com,oncall@example.com",
+        "--smtp-server",
+        &server,
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let message = relay
+        .message(Duration::from_secs(5))
+        .expect("report mailed");
+    assert!(message.contains("To: ops@example.com, oncall@example.com\r\n"));
+    assert!(message.contains("Subject: Scan of 127.0.0.1: 1 open port\r\n"));
+    assert!(message.contains("\r\nScan of 127.0.0.1 | Ports: 1 |"));
+    assert!(message.contains(&format!("Port {:>5} | open | Service: SSH", open)));
+    let summary = attached_json(&message);
+    assert_eq!(summary["open_ports"], 1);
+    assert_eq!(summary["results"][0]["banner"], "SSH-2.0-Test");
+}
+
+#[test]
+fn quiet_scans_mail_only_when_asked_and_a_dead_relay_keeps_the_exit_code() {
+    let relay = SmtpSink::start();
+    let closed = closed_port().to_string();
+    let server = relay.server();
+    let args = [
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed,
+        "-q",
+        "--email-to",
+        "ops@example.com",
+        "--smtp-server",
+        &server,
+    ];
+
+    let out = scan(&args);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(relay.message(Duration::from_millis(500)), None);
+
+    let out = scan(&[&args[..], &["--email-always"]].concat());
+    assert_eq!(out.status.code(), Some(1));
+    let message = relay
+        .message(Duration::from_secs(5))
+        .expect("report mailed");
+    assert!(message.contains("Subject: Scan of 127.0.0.1: 0 open ports\r\n"));
+
+    let dead = format!("127.0.0.1:{}", closed_port());
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed,
+        "-q",
+        "--email-to",
+        "ops@example.com",
+        "--smtp-server",
+        &dead,
+        "--email-always",
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Email attempt 1 of 3 failed"), "{}", stderr);
+    assert!(stderr.contains("Failed to mail the report"), "{}", stderr);
+}
+
+#[test]
+fn gauges_are_pushed_to_the_gateway_and_failed_pushes_are_only_warned_about() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    // The object store mock answers any HTTP request, as a gateway would.
+    let gateway = ObjectStore::start(&[503]);
+    let url = format!(
+        "{}/metrics/job/portscan/instance/{{target}}/env/{{tag:env}}",
+        gateway.endpoint()
+    );
+    let mut args = vec![
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--tag",
+        "env=lab",
+        "--pushgateway",
+        &url,
+        "--pushgateway-username",
+        "ops",
+    ];
+
+    let out = Command::cargo_bin("port-scanner")
+        .unwrap()
+        .args(&args)
+        .env("PUSHGATEWAY_PASSWORD", "s3cret")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let requests = gateway.requests();
+    // The 503 is retried.
+    assert_eq!(requests.len(), 2, "{:?}", requests);
+    let push = &requests[1];
+    assert_eq!(push.method, "PUT");
+    assert_eq!(
+        push.path,
+        "/metrics/job/portscan/instance/127.0.0.1/env/lab"
+    );
+    assert_eq!(
+        push.header("content-type"),
+        Some("text/plain; version=0.0.4")
+    );
+    assert_eq!(push.header("authorization"), Some("Basic b3BzOnMzY3JldA=="));
+    let body = String::from_utf8(push.body.clone()).unwrap();
+    let scrape = prometheus_parse::Scrape::parse(body.lines().map(|l| Ok(l.to_string()))).unwrap();
+    let open_ports = scrape
+        .samples
+        .iter()
+        .find(|s| s.metric == "scanner_open_ports")
+        .unwrap();
+    assert_eq!(open_ports.value, prometheus_parse::Value::Gauge(1.0));
+
+    args.push("--pushgateway-delete");
+    let out = scan(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let requests = gateway.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2].method, "DELETE");
+    assert_eq!(requests[2].path, push.path);
+
+    let refusing = ObjectStore::start(&[400; 4]);
+    let url = format!("{}/metrics/job/portscan", refusing.endpoint());
+    let out = scan(&["-t", "127.0.0.1", "-p", &open, "-q", "--pushgateway", &url]);
+    assert_eq!(out.status.code(), Some(0), "the scan itself went fine");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Pushing metrics to the gateway failed: HTTP 400"),
+        "{}",
+        stderr
+    );
+    // Refusals aren't retried.
+    assert_eq!(refusing.requests().len(), 1);
+}
+
+#[cfg(feature = "s3")]
+fn scan_uploading(args: &[&str]) -> Output {
+    Command::cargo_bin("port-scanner")
+        .unwrap()
+        .args(args)
+        .env("NO_COLOR", "1")
+        .env("AWS_ACCESS_KEY_ID", "AKIDTEST")
+        .env("AWS_SECRET_ACCESS_KEY", "secret")
+        .env("AWS_REGION", "eu-west-1")
+        .output()
+        .unwrap()
+}
+
+#[cfg(feature = "s3")]
+#[test]
+fn reports_are_uploaded_and_failed_uploads_only_fail_when_required() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let json = temp_path("upload.json");
+    let csv = temp_path("upload.csv");
+    let store = ObjectStore::start(&[503]);
+    let endpoint = store.endpoint();
+    let mut args = vec![
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--deterministic",
+        "--fake-time",
+        "2024-03-09T14:05:07Z",
+        "--json",
+        json.to_str().unwrap(),
+        "--csv",
+        csv.to_str().unwrap(),
+        "--upload-s3",
+        "s3://scans/nightly",
+        "--s3-endpoint",
+        &endpoint,
+    ];
+
+    let out = scan_uploading(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let folder = format!(
+        "/scans/nightly/2024-03-09/{}",
+        summary["scan_id"].as_str().unwrap()
+    );
+    let requests = store.requests();
+    let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+    let json_key = format!("{}/{}", folder, json.file_name().unwrap().to_str().unwrap());
+    let csv_key = format!("{}/{}", folder, csv.file_name().unwrap().to_str().unwrap());
+    // The 503 is retried.
+    assert_eq!(paths, [&json_key, &json_key, &csv_key]);
+    assert!(requests.iter().all(|r| r.method == "PUT"));
+    assert_eq!(requests[1].header("content-type"), Some("application/json"));
+    assert_eq!(requests[1].body, std::fs::read(&json).unwrap());
+    assert_eq!(requests[2].header("content-type"), Some("text/csv"));
+    assert_eq!(requests[2].body, std::fs::read(&csv).unwrap());
+    let authorization = requests[2].header("authorization").unwrap();
+    assert!(
+        authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDTEST/"),
+        "{}",
+        authorization
+    );
+    assert!(authorization.contains("/eu-west-1/s3/aws4_request,"));
+
+    let refusing = ObjectStore::start(&[403; 8]);
+    let endpoint = refusing.endpoint();
+    let last = args.len() - 1;
+    args[last] = &endpoint;
+    let out = scan_uploading(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("HTTP 403 Mock403"), "{}", stderr);
+    // Refusals aren't retried.
+    assert_eq!(refusing.requests().len(), 2);
+
+    args.push("--upload-required");
+    let out = scan_uploading(&args);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Failed to write 2 of 2 reports to s3://scans/nightly/"),
+        "{}",
+        stderr
+    );
+    std::fs::remove_file(json).unwrap();
+    std::fs::remove_file(csv).unwrap();
+}
+
+#[cfg(not(feature = "s3"))]
+#[test]
+fn uploads_need_the_s3_feature() {
+    let out = scan(&["-t", "127.0.0.1", "-p", "1", "--upload-s3", "s3://scans/"]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("--upload-s3: built without the s3 feature"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(feature = "nats")]
+#[test]
+fn open_ports_and_the_summary_are_published_and_lost_messages_fail_the_scan() {
+    let open_service = FakeService::silent().start();
+    let open = open_service.port().to_string();
+    let nats = NatsServer::start();
+    let url = format!("nats://{}/scans.results", nats.server());
+    let closed = closed_port().to_string();
+    let ports = format!("{},{}", open, closed);
+    let out = scan(&["-t", "127.0.0.1", "-p", &ports, "-q", "--publish", &url]);
+    assert_eq!(out.status.code(), Some(0));
+    let published = nats.published();
+    assert_eq!(published.len(), 2, "{:?}", published);
+    assert!(published
+        .iter()
+        .all(|(subject, _)| subject == "scans.results"));
+    let port: serde_json::Value = serde_json::from_str(&published[0].1).unwrap();
+    assert_eq!(port["kind"], "port");
+    assert_eq!(port["result"]["port"], open_service.port());
+    let summary: serde_json::Value = serde_json::from_str(&published[1].1).unwrap();
+    assert_eq!(summary["kind"], "summary");
+    assert_eq!(summary["scan_id"], port["scan_id"]);
+    assert_eq!(summary["summary"]["open_ports"], 1);
+
+    let dead = format!("nats://127.0.0.1:{}/scans.results", closed_port());
+    let out = scan(&["-t", "127.0.0.1", "-p", &open, "-q", "--publish", &dead]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("2 of 2 messages were not delivered"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(not(feature = "kafka"))]
+#[test]
+fn publishing_needs_the_client_feature() {
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        "1",
+        "--publish",
+        "kafka://127.0.0.1:9092/scans",
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("--publish: built without the kafka feature"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn the_daemon_scans_on_schedule_and_keeps_a_status_file() {
+    let open_service = FakeService::silent().start();
+    let dir = temp_path("daemon");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("nightly.toml");
+    let status = dir.join("status.json");
+    std::fs::write(
+        &config,
+        format!(
+            "target = \"127.0.0.1\"\nports = \"{}\"\nquiet = true\njson = \"{}/scan-{{time}}.json\"\n",
+            open_service.port(),
+            dir.display()
+        ),
+    )
+    .unwrap();
+    let out = scan(&[
+        "daemon",
+        "--schedule",
+        "* * * * * *",
+        "--timezone",
+        "UTC",
+        "--config",
+        config.to_str().unwrap(),
+        "--status-file",
+        status.to_str().unwrap(),
+        "--max-runs",
+        "2",
+    ]);
+    assert_eq!(
+        out.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let reports = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|e| {
+            e.as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with("scan-")
+        })
+        .count();
+    assert_eq!(reports, 2);
+    let status: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&status).unwrap()).unwrap();
+    assert_eq!(status["schedule"], "* * * * * *");
+    assert_eq!(status["runs"], 2);
+    assert_eq!(status["last_run"]["exit_code"], 0);
+    assert_eq!(status["last_run"]["open_ports"], 1);
+    assert!(status["running_since"].is_null());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let bad = temp_path("daemon-bad.toml");
+    std::fs::write(&bad, "target = \"127.0.0.1\"\nbogus = 1\n").unwrap();
+    let out = scan(&[
+        "daemon",
+        "--schedule",
+        "@daily",
+        "--config",
+        bad.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("unexpected argument '--bogus'"),
+        "{}",
+        stderr
+    );
+    std::fs::remove_file(&bad).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn scans_wait_for_their_window_and_its_time_does_not_count() {
+    use chrono::Timelike;
+
+    let open_service = FakeService::silent().start();
+    let port = open_service.port().to_string();
+    let json = temp_path("window.json");
+
+    // A window twelve hours away: nothing is probed, and the paused time
+    // doesn't run down --max-scan-time.
+    let away = (chrono::Utc::now().hour() + 12) % 24;
+    let closed = format!("{:02}:00-{:02}:01 UTC", away, away);
+    let scanner = std::process::Command::new(assert_cmd::cargo::cargo_bin("port-scanner"))
+        .args(["-t", "127.0.0.1", "-p", &port, "-q", "--max-scan-time", "1"])
+        .args(["--window", &closed, "--json"])
+        .arg(&json)
+        .env("NO_COLOR", "1")
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(2000));
+    // SAFETY: kill has no memory-safety preconditions.
+    unsafe {
+        libc::kill(scanner.id() as libc::pid_t, libc::SIGINT);
+    }
+    let out = scanner.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(3), "{}", stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Outside the scan window {}, pausing until",
+            closed
+        )),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("Interrupted"), "{}", stderr);
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["scanned_ports"], 0);
+    assert_eq!(
+        summary["completion"],
+        serde_json::json!({"reason": "cancelled_by_user", "detail": "Ctrl-C"})
+    );
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "-q",
+        "--window",
+        "00:00-24:00 UTC",
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Scan window 00:00-24:00 UTC is open, scanning until"),
+        "{}",
+        stderr
+    );
+
+    let bad = scan(&["-t", "127.0.0.1", "-p", &port, "--window", "9-17"]);
+    assert_eq!(bad.status.code(), Some(2));
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn the_reason_a_scan_ended_is_printed_and_recorded() {
+    let silent_service = FakeService::silent().start();
+    let port = silent_service.port().to_string();
+    let json = temp_path("completion.json");
+
+    let args = [
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "--json",
+        json.to_str().unwrap(),
+    ];
+    let out = scan(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("banners), completed\n"), "{}", stderr);
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(
+        summary["completion"],
+        serde_json::json!({"reason": "completed"})
+    );
+
+    // The banner read outlasts --max-scan-time.
+    let out = scan(&[&args[..], &["--timeout", "10000", "--max-scan-time", "1"]].concat());
+    assert_eq!(out.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("banners), deadline exceeded (--max-scan-time 1s)"),
+        "{}",
+        stderr
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["completion"]["reason"], "deadline_exceeded");
+    assert_eq!(summary["interrupted"], true);
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn identical_banners_on_many_ports_are_kept_once() {
+    const PORTAL: &[u8] = b"HTTP/1.1 200 OK\r\n\r\n<html>Sign in to the guest network</html>";
+    let services: Vec<_> = (0..3)
+        .map(|_| FakeService::banner(PORTAL).start())
+        .collect();
+    let ssh_service = FakeService::banner(b"SSH-2.0-OpenSSH_9.6\r\n").start();
+    let ports: Vec<String> = services
+        .iter()
+        .chain([&ssh_service])
+        .map(|s| s.port().to_string())
+        .collect();
+    let json = temp_path("shared-responses.json");
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports.join(","),
+        "--shared-responses",
+        "2",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("127.0.0.1: more than 2 open ports answered with the same"),
+        "{}",
+        stderr
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let table = summary["shared_responses"].as_object().unwrap();
+    assert_eq!(table.len(), 1);
+    let (key, shared) = table.iter().next().unwrap();
+    assert_eq!(shared["banner"], String::from_utf8_lossy(PORTAL).as_ref());
+    assert_eq!(shared["hosts"], serde_json::json!(["127.0.0.1"]));
+    assert_eq!(shared["ports"], 3);
+    assert_eq!(summary["hosts"][0]["uniform_responses"], true);
+    for r in summary["results"].as_array().unwrap() {
+        if r["port"] == ssh_service.port() {
+            assert_eq!(r["banner"], "SSH-2.0-OpenSSH_9.6");
+            assert!(r.get("shared_response").is_none());
+        } else {
+            assert!(r["banner"].is_null(), "{}", r);
+            assert_eq!(r["shared_response"], key.as_str());
+        }
+    }
+
+    // Three is not more than three: every port keeps its banner.
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports.join(","),
+        "--shared-responses",
+        "3",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert!(summary.get("shared_responses").is_none());
+    assert!(summary.get("hosts").is_none());
+    let results = summary["results"].as_array().unwrap();
+    assert!(results.iter().all(|r| r["banner"].is_string()));
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn the_daemon_stops_on_sigterm() {
+    let config = temp_path("daemon-idle.toml");
+    std::fs::write(&config, "target = \"127.0.0.1\"\nports = \"1\"\n").unwrap();
+    let daemon = std::process::Command::new(assert_cmd::cargo::cargo_bin("port-scanner"))
+        .args(["daemon", "--schedule", "0 0 1 1 *", "--config"])
+        .arg(&config)
+        .env("NO_COLOR", "1")
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    // SAFETY: kill has no memory-safety preconditions.
+    unsafe {
+        libc::kill(daemon.id() as libc::pid_t, libc::SIGTERM);
+    }
+    let out = daemon.wait_with_output().unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Shutting down"), "{}", stderr);
+    std::fs::remove_file(&config).unwrap();
+}
+
+#[test]
+fn a_batch_runs_each_job_and_rolls_them_up() {
+    let web_service = FakeService::banner(b"HTTP/1.0 200 OK\r\n\r\n").start();
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let (web, ssh) = (web_service.port(), ssh_service.port());
+    let jobs = temp_path("batch-jobs.toml");
+    let reports = temp_path("batch-reports");
+    let rollup = temp_path("batch-rollup.json");
+    let write_jobs = |fail_on: &str| {
+        let text = format!(
+            "[[job]]\nname = \"web\"\ntarget = \"127.0.0.1\"\nports = \"{}\"\n{}\n\
+             [[job]]\nname = \"ssh\"\ntarget = \"127.0.0.1\"\nports = \"{}\"\n",
+            web, fail_on, ssh
+        );
+        std::fs::write(&jobs, text).unwrap();
+    };
+    let batch = |extra: &[&str]| {
+        let mut args = vec!["batch", jobs.to_str().unwrap()];
+        args.extend(["--report-dir", reports.to_str().unwrap()]);
+        args.extend(["--json", rollup.to_str().unwrap()]);
+        args.extend(extra);
+        let out = scan(&args);
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&rollup).unwrap()).unwrap();
+        (out, json)
+    };
+
+    write_jobs("");
+    let (out, json) = batch(&["--parallel-jobs", "2"]);
+    assert_eq!(out.status.code(), Some(0), "{:?}", out);
+    assert_eq!(json["jobs"], 2);
+    assert_eq!(json["succeeded"], 2);
+    assert_eq!(json["open_ports"], 2);
+    let results = json["results"].as_array().unwrap();
+    for (job, name) in results.iter().zip(["web", "ssh"]) {
+        assert_eq!(job["name"], name);
+        assert_eq!(job["status"], "succeeded");
+        assert_eq!(job["exit_code"], 0);
+        let report = std::path::Path::new(job["report"].as_str().unwrap());
+        assert_eq!(report, reports.join(format!("{}.json", name)));
+        assert!(report.exists());
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.contains("2 jobs: 2 succeeded, 0 failed, 0 skipped; 2 open ports"),
+        "{}",
+        stdout
+    );
+
+    // The first job breaks its policy: with --fail-fast the second never
+    // starts.
+    write_jobs(&format!("fail_on = [\"open:{}\"]", web));
+    let (out, json) = batch(&["--fail-fast"]);
+    assert_eq!(out.status.code(), Some(4), "{:?}", out);
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["skipped"], 1);
+    assert_eq!(json["results"][0]["exit_code"], 4);
+    assert_eq!(json["results"][1]["status"], "skipped");
+
+    std::fs::remove_dir_all(&reports).unwrap();
+    std::fs::remove_file(&jobs).unwrap();
+    std::fs::remove_file(&rollup).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn progress_json_streams_events_to_a_fifo_while_the_scan_runs() {
+    use std::io::BufRead;
+
+    let fifo = temp_path("progress.fifo");
+    let json = temp_path("progress.json");
+    let _ = std::fs::remove_file(&fifo);
+    let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+    // SAFETY: path is a valid NUL-terminated string.
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+    let mut scanner = std::process::Command::new(assert_cmd::cargo::cargo_bin("port-scanner"))
+        .args(["-t", "127.0.0.1", "-p", "1-10000", "-q"])
+        .args(["--progress-json-interval", "100ms", "--progress-json"])
+        .arg(&fifo)
+        .arg("--json")
+        .arg(&json)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let reader = std::io::BufReader::new(std::fs::File::open(&fifo).unwrap());
+    let mut events = Vec::new();
+    let mut read_while_scanning = false;
+    for line in reader.lines() {
+        let event: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        if events.is_empty() {
+            read_while_scanning = scanner.try_wait().unwrap().is_none();
+        }
+        events.push(event);
+    }
+    scanner.wait().unwrap();
+
+    assert!(read_while_scanning);
+    let (last, progress) = events.split_last().unwrap();
+    assert!(!progress.is_empty());
+    let mut completed = 0;
+    for event in progress {
+        assert_eq!(event["event"], "progress", "{}", event);
+        assert_eq!(event["total"], 10000);
+        let now = event["completed"].as_u64().unwrap();
+        assert!(now >= completed, "{:?}", events);
+        completed = now;
+    }
+    assert_eq!(last["event"], "finished", "{}", last);
+    assert_eq!(last["completed"], 10000);
+    assert_eq!(last["hosts_completed"], 1);
+    assert_eq!(last["interrupted"], false);
+    assert_eq!(last["summary"], json.to_str().unwrap());
+    assert_eq!(last["reports"][0], json.to_str().unwrap());
+    std::fs::remove_file(&fifo).unwrap();
+    std::fs::remove_file(&json).unwrap();
+}
+
+#[test]
+fn blocklisted_hosts_are_left_out_and_explicit_targets_fail_loudly() {
+    let closed = closed_port().to_string();
+    let blocklist = temp_path("blocked.txt");
+    let json = temp_path("blocked.json");
+    let log = temp_path("blocked.log");
+    let _ = std::fs::remove_file(&log);
+    std::fs::write(&blocklist, "# never scanned\n127.0.0.2\n10.0.0.0/8\n").unwrap();
+    let blocklist_arg = blocklist.to_str().unwrap();
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.2",
+        "-p",
+        &closed,
+        "-q",
+        "--blocklist",
+        blocklist_arg,
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Targets on the blocklist {}: 127.0.0.2 (--skip-blocked leaves them out)",
+            blocklist_arg
+        )),
+        "{}",
+        stderr
+    );
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.2",
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed,
+        "-q",
+        "--blocklist",
+        blocklist_arg,
+        "--skip-blocked",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["targets"][0]["status"], "blocked");
+    assert_eq!(summary["targets"][1]["status"], "scanned");
+    assert_eq!(summary["blocked_hosts"], 1);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1-127.0.0.3",
+        "-p",
+        &closed,
+        "-q",
+        "--blocklist",
+        blocklist_arg,
+        "--json",
+        json.to_str().unwrap(),
+        "--log-format",
+        "json",
+        "--log-file",
+        log.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(summary["blocked_hosts"], 1);
+    assert_eq!(summary["targets"][0]["status"], "scanned");
+    let mut hosts: Vec<&str> = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["target"].as_str().unwrap())
+        .collect();
+    hosts.sort();
+    assert_eq!(hosts, ["127.0.0.1", "127.0.0.3"]);
+    let blocked: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|e| e["target"] == "audit" && e["fields"]["message"] == "target blocked")
+        .collect();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0]["fields"]["target"], "127.0.0.1-127.0.0.3");
+    assert_eq!(blocked[0]["fields"]["blocked"], 1);
+    let _ = std::fs::remove_file(blocklist);
+    let _ = std::fs::remove_file(json);
+    let _ = std::fs::remove_file(log);
+}
+
+#[test]
+fn write_on_change_skips_the_reports_of_a_scan_that_found_the_same() {
+    let open_service = FakeService::silent().start();
+    let closed = closed_port();
+    let state = temp_path("change-state.json");
+    let json = temp_path("change.json");
+    let csv = temp_path("change.csv");
+    let _ = std::fs::remove_file(&state);
+    let run = |ports: &str| {
+        let _ = std::fs::remove_file(&json);
+        let _ = std::fs::remove_file(&csv);
+        scan(&[
+            "-t",
+            "127.0.0.1",
+            "-p",
+            ports,
+            "-q",
+            "--json",
+            json.to_str().unwrap(),
+            "--csv",
+            csv.to_str().unwrap(),
+            "--write-on-change",
+            state.to_str().unwrap(),
+        ])
+    };
+    let read_state = || -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(&state).unwrap()).unwrap()
+    };
+
+    let out = run(&closed.to_string());
+    assert_eq!(out.status.code(), Some(1));
+    assert!(json.exists() && csv.exists());
+    let first = read_state();
+    assert!(first["fingerprint"].as_str().unwrap().starts_with("v1:"));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(first["changed_scan_id"], report["scan_id"]);
+
+    let out = run(&closed.to_string());
+    assert_eq!(out.status.code(), Some(1), "the exit code still tells");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!json.exists() && !csv.exists(), "{}", stderr);
+    let second = read_state();
+    assert_eq!(second["fingerprint"], first["fingerprint"]);
+    assert_eq!(second["changed_scan_id"], first["changed_scan_id"]);
+    assert_eq!(second["changed_at"], first["changed_at"]);
+    let verified_at = |state: &serde_json::Value| {
+        state["verified_at"]
+            .as_str()
+            .unwrap()
+            .parse::<chrono::DateTime<chrono::Utc>>()
+            .unwrap()
+    };
+    assert!(verified_at(&second) > verified_at(&first));
+
+    let out = run(&format!("{},{}", closed, open_service.port()));
+    assert_eq!(out.status.code(), Some(0));
+    assert!(json.exists() && csv.exists());
+    let rows = std::fs::read_to_string(&csv).unwrap();
+    assert_eq!(rows.lines().count(), 3, "{}", rows);
+    assert_ne!(read_state()["fingerprint"], first["fingerprint"]);
+    for path in [state, json, csv] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn banners_in_legacy_charsets_are_decoded_and_labelled() {
+    let latin1 = FakeService::banner(include_bytes!("fixtures/banner-latin1.txt")).start();
+    let sjis = FakeService::banner(include_bytes!("fixtures/banner-shift_jis.txt")).start();
+    let binary = FakeService::banner(b"\xff\xfb\x01\xff\xfb\x03").start();
+    let json = temp_path("charsets.json");
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &format!("{},{},{}", latin1.port(), sjis.port(), binary.port()),
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+
+    let text = std::fs::read_to_string(&json).unwrap();
+    assert!(!text.contains('\u{fffd}'), "{}", text);
+    let report: serde_json::Value = serde_json::from_str(&text).unwrap();
+    let result = |port: u16| {
+        report["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["port"] == port)
+            .unwrap()
+            .clone()
+    };
+    let r = result(latin1.port());
+    assert_eq!(
+        r["banner"],
+        "220 Bienvenue sur le serveur FTP de la société Müller"
+    );
+    assert_eq!(r["banner_encoding"], "windows-1252");
+    let r = result(sjis.port());
+    assert_eq!(r["banner"], "220 プリンタ FTP サーバー 準備完了");
+    assert_eq!(r["banner_encoding"], "Shift_JIS");
+    let r = result(binary.port());
+    assert_eq!(r["banner"], "\\xff\\xfb\\x01\\xff\\xfb\\x03");
+    assert_eq!(r["banner_encoding"], "escaped");
+}
+
+#[test]
+fn stats_are_printed_and_kept_in_the_json_report() {
+    let open_service = FakeService::silent().start();
+    let closed = closed_port();
+    let json = temp_path("stats.json");
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &format!("{},{}", open_service.port(), closed),
+        "-q",
+        "--stats",
+        "--retime-outliers",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("connect time 2 answered: p50 "),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "connects     2 attempted: 1 succeeded, 1 refused, 0 timed out, 0 reset, 0 failed"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains(", output "), "{}", stderr);
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let stats = &report["stats"];
+    assert_eq!(
+        stats["connects"]["attempted"],
+        report["connection_attempts"]
+    );
+    assert_eq!(stats["connects"]["succeeded"], report["open_ports"]);
+    assert_eq!(stats["connects"]["refused"], report["closed_ports"]);
+    assert_eq!(stats["banners"]["attempted"], report["banner_probes"]);
+    assert_eq!(stats["permit_wait_us"]["count"], 2);
+    assert_eq!(stats["connect_time_us"]["count"], 2);
+    let phases = &stats["phases_ms"];
+    assert!(phases["scanning"].is_u64() && phases["verification"].is_u64());
+    assert!(phases.get("output").is_none(), "{}", phases);
+
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &closed.to_string(),
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("connects "));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert!(report.get("stats").is_none());
+}
+
+#[test]
+fn the_log_file_records_the_scan_lifecycle_and_rolls_over() {
+    let open_service = FakeService::silent().start();
+    let ports = format!("{},{}", open_service.port(), closed_port());
+    let log = temp_path("audit.log");
+    let rolled = temp_path("audit.log.1");
+    let _ = std::fs::remove_file(&log);
+    let _ = std::fs::remove_file(&rolled);
+    let args = [
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--log-format",
+        "json",
+        "--log-file",
+        log.to_str().unwrap(),
+    ];
+    let out = scan(&args);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(!stderr.contains("host started"), "{}", stderr);
+
+    let events: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let audit: Vec<&str> = events
+        .iter()
+        .filter(|e| e["target"] == "audit")
+        .map(|e| e["fields"]["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        audit,
+        [
+            "scan started",
+            "host started",
+            "host finished",
+            "scan finished"
+        ]
+    );
+    let started = &events.iter().find(|e| e["target"] == "audit").unwrap()["fields"];
+    let command = started["command"].as_str().unwrap();
+    assert!(command.contains(&format!("-p {} -q", ports)), "{}", command);
+    assert_eq!(started["ports"], 2);
+    assert!(events.iter().all(|e| e["timestamp"].is_string()));
+
+    let mut rolling = args.to_vec();
+    rolling.extend(["--log-max-size", "1K"]);
+    assert_eq!(scan(&rolling).status.code(), Some(0));
+    assert!(std::fs::metadata(&rolled).unwrap().len() > 0);
+    assert!(std::fs::metadata(&log).unwrap().len() <= 1024);
+    std::fs::remove_file(&log).unwrap();
+    std::fs::remove_file(&rolled).unwrap();
+}
+
+#[test]
+fn port_timeouts_are_recorded_per_result_and_unused_ones_warned_about() {
+    let open_service = FakeService::silent().start();
+    let (open, closed) = (open_service.port(), closed_port());
+    let json = temp_path("port-timeout.json");
+    let out = scan(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &format!("{},{}", open, closed),
+        "-q",
+        "--port-timeout",
+        &format!("{}:2s,1-65535:1500ms,9:1s", open),
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("--port-timeout for port 9 which is not scanned"),
+        "{}",
+        stderr
+    );
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let timeout_of = |port: u16| {
+        summary["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["port"] == port)
+            .unwrap()["timeout_ms"]
+            .clone()
+    };
+    assert_eq!(timeout_of(open), 2000);
+    assert_eq!(timeout_of(closed), 1500);
+
+    let rejected = scan(&["-t", "127.0.0.1", "--port-timeout", "1433"]);
+    assert_eq!(rejected.status.code(), Some(2));
+    let _ = std::fs::remove_file(json);
+}
+
+#[test]
+fn the_audit_flag_adds_probe_times_and_source_ports_to_the_reports() {
+    let open_service = FakeService::silent().start();
+    let ports = format!("{},{}", open_service.port(), closed_port());
+    let json = temp_path("audit.json");
+    let csv = temp_path("audit.csv");
+    let args = [
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+        "--csv",
+        csv.to_str().unwrap(),
+    ];
+
+    assert_eq!(scan(&args).status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert!(summary["results"][0].get("audit").is_none());
+    let header = std::fs::read_to_string(&csv).unwrap();
+    assert!(header.starts_with("target,port,status,service,banner,duration_ms,error,product,version,cpe,country,asn,as_name,protocol\n"));
+
+    let mut audited = args.to_vec();
+    audited.push("--audit");
+    assert_eq!(scan(&audited).status.code(), Some(0));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    for r in summary["results"].as_array().unwrap() {
+        let audit = &r["audit"];
+        let started = audit["started_at"].as_str().unwrap();
+        let finished = audit["finished_at"].as_str().unwrap();
+        assert!(started.ends_with('Z') && started <= finished, "{}", audit);
+        assert!(audit["source_port"].as_u64().unwrap() > 0, "{}", audit);
+        assert_eq!(audit["attempt"], 1);
+    }
+    let rows = std::fs::read_to_string(&csv).unwrap();
+    let mut lines = rows.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .ends_with(",as_name,protocol,started_at,finished_at,source_port,attempt"));
+    for row in lines {
+        let columns: Vec<&str> = row.split(',').collect();
+        assert_eq!(columns.len(), 18, "{}", row);
+        assert!(columns[16].parse::<u16>().is_ok(), "{}", row);
+        assert_eq!(columns[17], "1");
+    }
+    let _ = std::fs::remove_file(json);
+    let _ = std::fs::remove_file(csv);
+}
+
+#[test]
+fn evidence_explains_each_classification_when_asked_for() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").start();
+    let ssh = ssh_service.port();
+    let closed = closed_port();
+    let ports = format!("{},{}", ssh, closed);
+    let json = temp_path("evidence.json");
+    let args = [
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "--json",
+        json.to_str().unwrap(),
+    ];
+    let results = |extra: &[&str]| {
+        let mut args = args.to_vec();
+        args.extend_from_slice(extra);
+        assert_eq!(scan(&args).status.code(), Some(0));
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        summary["results"].as_array().unwrap().clone()
+    };
+
+    for r in results(&[]) {
+        assert!(r.get("evidence").is_none(), "{}", r);
+    }
+    for extra in [&["--evidence"][..], &["-v", "verbose"][..]] {
+        let results = results(extra);
+        let of = |port: u16| {
+            let r = results.iter().find(|r| r["port"] == port).unwrap();
+            r["evidence"].as_array().unwrap().clone()
+        };
+        let open = of(ssh);
+        assert_eq!(open[0]["step"], "tcp_connect");
+        assert_eq!(open[0]["outcome"], "success");
+        assert!(open[0]["elapsed_ms"].is_u64());
+        assert_eq!(open[1]["step"], "banner_read");
+        assert_eq!(open[1]["outcome"], "success");
+        assert_eq!(
+            open[2],
+            serde_json::json!({
+                "step": "service_match",
+                "outcome": "matched",
+                "detail": "SSH identification"
+            })
+        );
+        let refused = of(closed);
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0]["outcome"], "refused");
+    }
+    let _ = std::fs::remove_file(json);
+}
+
+#[cfg(all(feature = "ssh-jump", unix))]
+#[test]
+fn ports_are_scanned_through_the_jump_host() {
+    let ssh = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fake-ssh");
+    let log = temp_path("fake-ssh.log");
+    let bastion = FakeService::banner(b"SSH-2.0-Fake\r\n").start();
+    let open_service = FakeService::banner(b"220 relay ready\r\n").start();
+    let closed = closed_port();
+    let jump = format!("tester@127.0.0.1:{}", bastion.port());
+    let ports = format!("{},{}", open_service.port(), closed);
+    let json = temp_path("jump.json");
+    let scan_via = |jump: &str| {
+        Command::cargo_bin("port-scanner")
+            .unwrap()
+            .args(["-t", "127.0.0.1", "-p", &ports, "--banner-timeout", "300ms"])
+            .args(["--timeout", "3s", "--ssh-jump", jump, "--ssh-command", ssh])
+            .args(["--json", json.to_str().unwrap()])
+            .env("NO_COLOR", "1")
+            .env("FAKE_SSH_LOG", &log)
+            .output()
+            .unwrap()
+    };
+
+    let out = scan_via(&jump);
+    assert_eq!(
+        out.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    let result = |port: u16| {
+        summary["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["port"] == port)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(result(open_service.port())["status"], "Open");
+    assert_eq!(result(open_service.port())["banner"], "220 relay ready");
+    assert_eq!(result(closed)["status"], "Closed");
+    // One login, whose check reaches the bastion's own SSH port before any
+    // target is opened as a channel of it.
+    let calls = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = calls.lines().collect();
+    assert!(
+        lines[0].contains(&format!("-p {} -l tester -M -S ", bastion.port()))
+            && lines[0].ends_with(" -N 127.0.0.1"),
+        "{}",
+        calls
+    );
+    assert_eq!(lines[1], format!("open localhost:{}", bastion.port()));
+    assert_eq!(lines.len(), 4, "{}", calls);
+    assert!(calls.contains(&format!("open 127.0.0.1:{}\n", closed)));
+
+    let out = scan_via("tester@bastion.invalid");
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains(
+            "SSH connection to tester@bastion.invalid:22 failed: ssh: Could not resolve hostname"
+        ),
+        "{}",
+        stderr
+    );
+    assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 5);
+    let _ = std::fs::remove_file(json);
+    let _ = std::fs::remove_file(log);
+}
@@ -0,0 +1,484 @@
+//! The scan engine and the binary against real sockets: one fake service of
+//! each kind on loopback.
+
+mod support;
+
+use assert_cmd::Command;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use port_scanner::connector::{Connection, Connector, TcpConnector};
+use port_scanner::evidence::{Outcome, Step};
+use port_scanner::probe::HttpProbe;
+use port_scanner::{PortError, PortStatus, Scanner};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::task::Poll;
+use std::time::Duration;
+use support::{closed_port, temp_path, FakeService};
+
+#[tokio::test]
+async fn the_engine_classifies_every_kind_of_service() {
+    let close = FakeService::close().spawn().await;
+    let ssh = FakeService::banner(b"SSH-2.0-Fake_1.0\r\n").spawn().await;
+    let http = FakeService::http().spawn().await;
+    let silent = FakeService::silent().spawn().await;
+    let reset = FakeService::reset().spawn().await;
+    let closed = closed_port();
+
+    let scanner = Scanner::builder()
+        .target("127.0.0.1")
+        .ports(vec![
+            close.port(),
+            ssh.port(),
+            http.port(),
+            silent.port(),
+            reset.port(),
+            closed,
+        ])
+        .timeout(Duration::from_millis(500))
+        .banner_timeout(Duration::from_millis(400))
+        .build()
+        .unwrap();
+    let summary = scanner.scan_collect().await;
+    let results: HashMap<u16, _> = summary.results.iter().map(|r| (r.port, r)).collect();
+
+    assert_eq!(summary.scanned_ports, 6);
+    assert_eq!(summary.open_ports, 5);
+    assert_eq!(summary.closed_ports, 1);
+    assert_eq!(summary.reset_on_read_ports, 1);
+
+    let r = results[&close.port()];
+    assert_eq!(
+        (&r.status, &r.banner, &r.error),
+        (&PortStatus::Open, &None, &None)
+    );
+
+    let r = results[&ssh.port()];
+    assert_eq!(r.banner_str().as_deref(), Some("SSH-2.0-Fake_1.0"));
+    assert_eq!(r.service.as_deref(), Some("SSH"));
+    assert_eq!(
+        (r.product.as_deref(), r.version.as_deref()),
+        (Some("Fake"), Some("1.0"))
+    );
+
+    let r = results[&http.port()];
+    assert!(r.banner_str().unwrap().starts_with("HTTP/1.1 408"));
+    assert_eq!(r.service.as_deref(), Some("HTTP"));
+
+    let r = results[&silent.port()];
+    assert_eq!(
+        (&r.status, &r.banner, &r.error),
+        (&PortStatus::Open, &None, &None)
+    );
+    assert!(r.duration_ms < 400, "connect time, not banner time");
+    assert!(!r.reset_on_read && !results[&close.port()].reset_on_read);
+
+    let r = results[&reset.port()];
+    assert_eq!((&r.status, r.reset_on_read), (&PortStatus::Open, true));
+    assert!(
+        matches!(&r.error, Some(PortError::Banner(_))),
+        "{:?}",
+        r.error
+    );
+
+    assert_eq!(results[&closed].status, PortStatus::Closed);
+}
+
+#[tokio::test]
+async fn the_http_probe_shares_the_connection_of_the_banner_read() {
+    let http = FakeService::http().spawn().await;
+
+    // Quicker than the 100 ms the server waits for a request, so the
+    // banner read times out with the connection still clean.
+    let scanner = Scanner::builder()
+        .target("127.0.0.1")
+        .ports(http.port())
+        .timeout(Duration::from_millis(500))
+        .banner_timeout(Duration::from_millis(30))
+        .probe(HttpProbe)
+        .evidence()
+        .stats()
+        .build()
+        .unwrap();
+    let summary = scanner.scan_collect().await;
+    let r = &summary.results[0];
+
+    assert_eq!(http.connections(), 1);
+    assert_eq!(
+        r.banner_str().as_deref(),
+        Some("HTTP/1.1 200 OK\r\nServer: fake\r\nConnection: close\r\nContent-Length: 2")
+    );
+    assert_eq!(r.service.as_deref(), Some("HTTP"));
+    let steps: Vec<_> = r.evidence.iter().map(|e| (e.step, e.outcome)).collect();
+    assert_eq!(
+        steps,
+        [
+            (Step::TcpConnect, Outcome::Success),
+            (Step::BannerRead, Outcome::Timeout),
+            (Step::ServiceMatch, Outcome::NoMatch),
+            (Step::Probe, Outcome::Matched),
+        ]
+    );
+    assert_eq!(
+        r.evidence[3].detail.as_deref(),
+        Some("http on connection 1: HTTP/1.1 200 OK")
+    );
+    let probes = summary.stats.unwrap().probes;
+    assert_eq!((probes.run, probes.shared, probes.reconnects), (1, 1, 0));
+}
+
+/// Fifty silent services, each holding its banner read for the whole
+/// timeout: a probe stage of ten reads them ten at a time, while one as wide
+/// as the scan reads them all at once once the connect sweep is through.
+#[tokio::test]
+async fn the_probe_stage_lets_the_connect_sweep_run_ahead_of_slow_banners() {
+    let mut services = Vec::new();
+    for _ in 0..50 {
+        services.push(FakeService::silent().spawn().await);
+    }
+    let ports: Vec<u16> = services.iter().map(FakeService::port).collect();
+    let scan = |probe_concurrency| {
+        Scanner::builder()
+            .target("127.0.0.1")
+            .ports(ports.clone())
+            .concurrency(10)
+            .probe_concurrency(probe_concurrency)
+            .banner_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap()
+    };
+
+    let started = std::time::Instant::now();
+    let narrow = scan(10).scan_collect().await;
+    let narrow_time = started.elapsed();
+    assert_eq!(narrow.open_ports, 50);
+    assert!(
+        narrow_time >= Duration::from_millis(1000),
+        "{:?}",
+        narrow_time
+    );
+
+    let scanner = scan(50);
+    let metrics = scanner.metrics();
+    let started = std::time::Instant::now();
+    let mut stream = scanner.scan();
+    let first = stream.next().await.unwrap();
+    assert_eq!(first.status, PortStatus::Open);
+    assert_eq!(metrics.connected(), 50, "connected before the first banner");
+    let mut open = 1;
+    while let Some(r) = stream.next().await {
+        open += usize::from(r.status == PortStatus::Open);
+    }
+    let wide_time = started.elapsed();
+    assert_eq!((open, metrics.probing()), (50, 0));
+    assert!(
+        wide_time * 2 < narrow_time,
+        "{:?} with a wide probe stage, {:?} with a narrow one",
+        wide_time,
+        narrow_time
+    );
+}
+
+/// Twenty silent services behind two connect slots and one probe slot: the
+/// open ports waiting for the probe slot don't hold the connect slots, so
+/// the sweep reaches every port while the first banner is still being read.
+#[tokio::test]
+async fn the_connect_sweep_finishes_while_open_ports_wait_for_a_probe_slot() {
+    let mut services = Vec::new();
+    for _ in 0..20 {
+        services.push(FakeService::silent().spawn().await);
+    }
+    let scanner = Scanner::builder()
+        .target("127.0.0.1")
+        .ports(services.iter().map(FakeService::port).collect::<Vec<_>>())
+        .concurrency(2)
+        .probe_concurrency(1)
+        .banner_timeout(Duration::from_millis(200))
+        .build()
+        .unwrap();
+    let metrics = scanner.metrics();
+    let mut stream = scanner.scan();
+
+    let first = stream.next().await.unwrap();
+    assert_eq!(first.status, PortStatus::Open);
+    assert_eq!(metrics.connected(), 20, "connected before the first banner");
+    let mut open = 1;
+    while let Some(r) = stream.next().await {
+        open += usize::from(r.status == PortStatus::Open);
+    }
+    assert_eq!((open, metrics.probing()), (20, 0));
+}
+
+/// Open, closed and filtered on loopback, as every platform classifies
+/// them. The timeout leaves room for Windows retrying a refused SYN for
+/// about a second. The filtered port is a listener whose accept queue one
+/// connection fills: Linux and macOS drop further SYNs, as a firewall
+/// would, while Windows refuses them, so there the port is closed.
+#[tokio::test]
+async fn connect_outcomes_classify_alike_on_every_platform() {
+    use socket2::{Domain, Socket, Type};
+
+    let open = FakeService::silent().spawn().await;
+    let closed = closed_port();
+    let full = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+    full.bind(&std::net::SocketAddr::from(([127, 0, 0, 1], 0)).into())
+        .unwrap();
+    full.listen(0).unwrap();
+    let full_addr = full.local_addr().unwrap().as_socket().unwrap();
+    let _filler = std::net::TcpStream::connect(full_addr).unwrap();
+
+    let summary = Scanner::builder()
+        .target("127.0.0.1")
+        .ports(vec![open.port(), closed, full_addr.port()])
+        .timeout(Duration::from_millis(1500))
+        .banner_timeout(Duration::from_millis(100))
+        .build()
+        .unwrap()
+        .scan_collect()
+        .await;
+    let results: HashMap<u16, _> = summary.results.iter().map(|r| (r.port, r)).collect();
+    let r = results[&open.port()];
+    assert_eq!((&r.status, &r.error), (&PortStatus::Open, &None));
+    let r = results[&closed];
+    assert_eq!((&r.status, &r.error), (&PortStatus::Closed, &None));
+    let r = results[&full_addr.port()];
+    if cfg!(windows) {
+        assert_eq!((&r.status, &r.error), (&PortStatus::Closed, &None));
+    } else {
+        assert_eq!(
+            (&r.status, &r.error),
+            (&PortStatus::Filtered, &Some(PortError::Timeout))
+        );
+    }
+}
+
+/// Connects as a busy scan may: the connect is started, then only looked
+/// at again once the listener has had time to accept and reset, so that
+/// the connect itself fails with the reset.
+struct LateConnector;
+
+impl Connector for LateConnector {
+    fn connect(&self, addr: SocketAddr) -> BoxFuture<'_, std::io::Result<Connection>> {
+        Box::pin(async move {
+            let tcp = TcpConnector::default();
+            let mut connecting = tcp.connect(addr);
+            if let Poll::Ready(done) = futures::poll!(&mut connecting) {
+                return done;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            connecting.await
+        })
+    }
+}
+
+/// A listener that completes the handshake and resets at once. Whether the
+/// reset meets the banner read or, on a busy scan, the connect itself, the
+/// handshake went through and the port is open, marked reset on read. A
+/// reset in answer to the SYN is reported as refused, and closed.
+#[tokio::test]
+async fn a_reset_after_accept_leaves_the_port_open() {
+    let reset_service = FakeService::reset().spawn().await;
+    let reset = reset_service.port();
+    let closed = closed_port();
+    let scan = |late: bool| async move {
+        let builder = Scanner::builder()
+            .target("127.0.0.1")
+            .ports(vec![reset, closed])
+            .timeout(Duration::from_millis(1500))
+            .banner_timeout(Duration::from_millis(400))
+            .evidence();
+        let builder = if late {
+            builder.connector(LateConnector)
+        } else {
+            builder
+        };
+        builder.build().unwrap().scan_collect().await
+    };
+    for late in [false, true] {
+        let summary = scan(late).await;
+        let results: HashMap<u16, _> = summary.results.iter().map(|r| (r.port, r)).collect();
+        let r = results[&reset];
+        assert_eq!(
+            (&r.status, r.reset_on_read),
+            (&PortStatus::Open, true),
+            "late: {}",
+            late
+        );
+        let connect = (r.evidence[0].step, r.evidence[0].outcome);
+        let expected = if late {
+            (Step::TcpConnect, Outcome::Reset)
+        } else {
+            (Step::TcpConnect, Outcome::Success)
+        };
+        assert_eq!(connect, expected);
+        assert_eq!(results[&closed].status, PortStatus::Closed);
+    }
+}
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::cargo_bin("port-scanner")
+        .unwrap()
+        .args(args)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
+
+/// Asserts that `value` is of the JSON type named by `ty`, `?` for nullable.
+fn assert_type(value: &Value, ty: &str, field: &str) {
+    let (ty, nullable) = match ty.strip_suffix('?') {
+        Some(ty) => (ty, true),
+        None => (ty, false),
+    };
+    let ok = match ty {
+        _ if nullable && value.is_null() => true,
+        "string" => value.is_string(),
+        "uint" => value.is_u64(),
+        "number" => value.is_number(),
+        "bool" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        _ => unreachable!("unknown type {}", ty),
+    };
+    assert!(ok, "{} should be {}, got {}", field, ty, value);
+}
+
+#[test]
+fn the_binary_reports_every_kind_of_service() {
+    let ssh = FakeService::banner(b"SSH-2.0-Fake_1.0\r\n").start();
+    let http = FakeService::http().start();
+    let silent = FakeService::silent().start();
+    let reset = FakeService::reset().start();
+    let closed = closed_port();
+    let json = temp_path("e2e.json");
+    let ports = format!(
+        "{},{},{},{},{}",
+        ssh.port(),
+        http.port(),
+        silent.port(),
+        reset.port(),
+        closed
+    );
+
+    let out = run(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &ports,
+        "-q",
+        "-T",
+        "500",
+        "--json",
+        json.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(0), "open ports found");
+    let summary: Value = serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    std::fs::remove_file(&json).unwrap();
+
+    let fields = [
+        ("scan_id", "string"),
+        ("target", "string"),
+        ("scanned_ports", "uint"),
+        ("open_ports", "uint"),
+        ("closed_ports", "uint"),
+        ("filtered_ports", "uint"),
+        ("unscanned_ports", "uint"),
+        ("reset_on_read_ports", "uint"),
+        ("blocked_hosts", "uint"),
+        ("total_time_ms", "uint"),
+        ("ports_per_second", "number"),
+        ("concurrency", "uint"),
+        ("connection_attempts", "uint"),
+        ("retries", "uint"),
+        ("banner_probes", "uint"),
+        ("banner_bytes", "uint"),
+        ("interrupted", "bool"),
+        ("findings", "object"),
+        ("policy", "object?"),
+        ("results", "array"),
+    ];
+    for (field, ty) in fields {
+        assert_type(&summary[field], ty, field);
+    }
+    assert_eq!(summary["open_ports"], 4);
+    assert_eq!(summary["closed_ports"], 1);
+
+    let results = summary["results"].as_array().unwrap();
+    assert_eq!(results.len(), 5);
+    let result_fields = [
+        ("target", "string"),
+        ("port", "uint"),
+        ("status", "string"),
+        ("banner", "string?"),
+        ("service", "string?"),
+        ("product", "string?"),
+        ("version", "string?"),
+        ("extra_info", "string?"),
+        ("cpe", "string?"),
+        ("duration_ms", "uint"),
+        ("error", "object?"),
+    ];
+    for r in results {
+        for (field, ty) in result_fields {
+            assert_type(&r[field], ty, field);
+        }
+        if let Some(error) = r["error"].as_object() {
+            assert_type(&error["kind"], "string", "error.kind");
+        }
+    }
+
+    let by_port = |port: u16| results.iter().find(|r| r["port"] == port).unwrap();
+    assert_eq!(by_port(ssh.port())["service"], "SSH");
+    assert_eq!(by_port(ssh.port())["version"], "1.0");
+    assert_eq!(by_port(http.port())["service"], "HTTP");
+    assert_eq!(by_port(silent.port())["banner"], Value::Null);
+    assert_eq!(by_port(reset.port())["error"]["kind"], "banner");
+    assert_eq!(by_port(reset.port())["reset_on_read"], true);
+    assert_eq!(by_port(silent.port())["reset_on_read"], Value::Null);
+    assert_eq!(summary["reset_on_read_ports"], 1);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("1 open ports reset the connection"),
+        "{}",
+        stderr
+    );
+    assert_eq!(by_port(closed)["status"], "Closed");
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(
+        stdout.lines().count(),
+        4,
+        "quiet mode prints open ports only"
+    );
+}
+
+#[test]
+fn the_binary_exit_code_tells_what_was_found() {
+    let silent = FakeService::silent().start();
+    let closed = closed_port().to_string();
+    let open = silent.port().to_string();
+
+    assert_eq!(
+        run(&["-t", "127.0.0.1", "-p", &open, "-q"]).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        run(&["-t", "127.0.0.1", "-p", &closed, "-q"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        run(&["-t", "127.0.0.1/33", "-p", &open]).status.code(),
+        Some(2)
+    );
+    let policy = run(&[
+        "-t",
+        "127.0.0.1",
+        "-p",
+        &open,
+        "-q",
+        "--fail-on",
+        &format!("open:{}", open),
+    ]);
+    assert_eq!(policy.status.code(), Some(4));
+}
@@ -0,0 +1,54 @@
+//! Planning a huge scan must not expand it up front. This binary counts
+//! every allocation, so it holds a single test.
+
+use port_scanner::Scanner;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn planning_a_slash_16_against_every_port_stays_flat() {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+
+    let scanner = Scanner::builder()
+        .target("10.0.0.0/16")
+        .exclude_target("10.0.255.255")
+        .ports("1-65535")
+        .exclude_ports("25")
+        .build()
+        .unwrap();
+    assert_eq!(scanner.total_ports(), 65535 * 65534);
+
+    // Walk well past the first host: the iterator must not allocate either.
+    let mut plan = scanner.config().plan();
+    let first = plan.next().unwrap();
+    assert_eq!(first.to_string(), "10.0.0.0:1");
+    let walked = plan.by_ref().take(5_000_000).count();
+    assert_eq!(walked, 5_000_000);
+    let next = plan.next().unwrap();
+    assert_eq!(next.ip().to_string(), "10.0.0.76");
+
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    assert!(
+        allocated < 64 * 1024,
+        "planning allocated {} bytes",
+        allocated
+    );
+}
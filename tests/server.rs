@@ -0,0 +1,211 @@
+mod support;
+
+use port_scanner::connector::{MockBehavior, MockConnector};
+use port_scanner::server::{self, ServerConfig};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use support::{closed_port, FakeService};
+use tokio_util::sync::CancellationToken;
+
+/// Serves the API on a loopback port and returns its base URL.
+async fn start(config: ServerConfig) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(server::serve(listener, config, CancellationToken::new()));
+    base
+}
+
+async fn submit(client: &Client, base: &str, body: Value) -> String {
+    let response = client
+        .post(format!("{}/scans", base))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let job: Value = response.json().await.unwrap();
+    job["id"].as_str().unwrap().to_string()
+}
+
+async fn job(client: &Client, base: &str, id: &str) -> Value {
+    let response = client
+        .get(format!("{}/scans/{}", base, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+async fn wait_for(client: &Client, base: &str, id: &str, status: &str) -> Value {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let job = job(client, base, id).await;
+        if job["status"] == status {
+            return job;
+        }
+        assert!(Instant::now() < deadline, "job stuck in {}", job["status"]);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn jobs_run_report_and_stream_their_results() {
+    let ssh_service = FakeService::banner(b"SSH-2.0-Test\r\n").spawn().await;
+    let ssh = ssh_service.port();
+    let closed = closed_port();
+    let base = start(ServerConfig::default()).await;
+    let client = Client::new();
+
+    let id = submit(
+        &client,
+        &base,
+        json!({ "targets": ["127.0.0.1"], "ports": [ssh, closed], "timeout_ms": 500 }),
+    )
+    .await;
+    let done = wait_for(&client, &base, &id, "completed").await;
+    assert_eq!(done["total_ports"], 2);
+    assert_eq!(done["summary"]["open_ports"], 1);
+    assert_eq!(done["summary"]["scan_id"], id.as_str());
+    let results = done["summary"]["results"].as_array().unwrap();
+    let open = results.iter().find(|r| r["status"] == "Open").unwrap();
+    assert_eq!(open["port"], ssh);
+    assert_eq!(open["service"], "SSH");
+
+    let events = client
+        .get(format!("{}/scans/{}/events", base, id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(events.matches("event: result").count(), 2);
+    assert!(events.contains("event: done\ndata: {\"status\":\"completed\"}"));
+
+    let deleted = client
+        .delete(format!("{}/scans/{}", base, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+    let gone = client
+        .get(format!("{}/scans/{}", base, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(gone.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn jobs_queue_beyond_the_limit_and_can_be_cancelled() {
+    let connector = MockConnector::new()
+        .port(1, MockBehavior::Hang)
+        .port(2, MockBehavior::Open(b"hello\r\n".to_vec()));
+    let base = start(ServerConfig {
+        max_jobs: 1,
+        connector: Arc::new(connector),
+        ..ServerConfig::default()
+    })
+    .await;
+    let client = Client::new();
+    let body = json!({ "targets": ["10.0.0.1"], "ports": "1-2", "timeout_ms": 60000 });
+
+    let first = submit(&client, &base, body.clone()).await;
+    wait_for(&client, &base, &first, "running").await;
+    let second = submit(&client, &base, body).await;
+    assert_eq!(job(&client, &base, &second).await["status"], "queued");
+
+    // The live stream sees the open port, then the cancellation.
+    let events = client
+        .get(format!("{}/scans/{}/events", base, first))
+        .send()
+        .await
+        .unwrap();
+    let cancelled = client
+        .delete(format!("{}/scans/{}", base, first))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(cancelled.status(), StatusCode::ACCEPTED);
+    let events = events.text().await.unwrap();
+    assert!(events.contains("\"port\":2"));
+    assert!(events.contains("event: done\ndata: {\"status\":\"cancelled\"}"));
+
+    let first = wait_for(&client, &base, &first, "cancelled").await;
+    assert_eq!(first["summary"]["interrupted"], true);
+    wait_for(&client, &base, &second, "running").await;
+}
+
+#[tokio::test]
+async fn bearer_token_is_required_and_bad_requests_are_rejected() {
+    let base = start(ServerConfig {
+        token: Some("s3cret".to_string()),
+        connector: Arc::new(MockConnector::new()),
+        ..ServerConfig::default()
+    })
+    .await;
+    let client = Client::new();
+    let body = json!({ "targets": ["10.0.0.1"], "ports": 80 });
+
+    let anonymous = client
+        .post(format!("{}/scans", base))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    let wrong = client
+        .get(format!("{}/scans/x", base))
+        .bearer_auth("guess")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    let accepted = client
+        .post(format!("{}/scans", base))
+        .bearer_auth("s3cret")
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(accepted.status(), StatusCode::CREATED);
+
+    let invalid = client
+        .post(format!("{}/scans", base))
+        .bearer_auth("s3cret")
+        .json(&json!({ "targets": ["not-an-ip"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    let error: Value = invalid.json().await.unwrap();
+    assert!(error["error"].as_str().unwrap().contains("target"));
+}
+
+#[tokio::test]
+async fn only_the_newest_finished_jobs_are_retained() {
+    let base = start(ServerConfig {
+        retain: 1,
+        connector: Arc::new(MockConnector::new()),
+        ..ServerConfig::default()
+    })
+    .await;
+    let client = Client::new();
+    let body = json!({ "targets": ["10.0.0.1"], "ports": [80, 443] });
+
+    let old = submit(&client, &base, body.clone()).await;
+    wait_for(&client, &base, &old, "completed").await;
+    let new = submit(&client, &base, body).await;
+    wait_for(&client, &base, &new, "completed").await;
+
+    let evicted = client
+        .get(format!("{}/scans/{}", base, old))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(evicted.status(), StatusCode::NOT_FOUND);
+}
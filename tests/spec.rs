@@ -0,0 +1,100 @@
+//! Invariants of the port and target grammar over random specifications.
+
+use port_scanner::plan::TargetSet;
+use port_scanner::spec::SpecErrorKind;
+use port_scanner::{parse_ports, ScanError};
+use proptest::prelude::*;
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// A port or a `start-end` range, and the ports it stands for.
+fn entry() -> impl Strategy<Value = (String, Vec<u16>)> {
+    prop_oneof![
+        any::<u16>().prop_map(|p| (p.to_string(), vec![p])),
+        (any::<u16>(), 0..2000u16).prop_map(|(start, width)| {
+            let end = start.saturating_add(width);
+            (format!("{}-{}", start, end), (start..=end).collect())
+        }),
+    ]
+}
+
+fn separator() -> impl Strategy<Value = &'static str> {
+    prop_oneof![Just(","), Just(", "), Just(" ,"), Just(",,")]
+}
+
+proptest! {
+    #[test]
+    fn valid_port_specs_parse_to_exactly_their_ports(
+        entries in prop::collection::vec((entry(), separator()), 1..8)
+    ) {
+        let spec: String = entries.iter().map(|((e, _), sep)| format!("{}{}", e, sep)).collect();
+        let expected: BTreeSet<u16> = entries
+            .iter()
+            .flat_map(|((_, ports), _)| ports.iter().copied())
+            .filter(|&p| p != 0)
+            .collect();
+
+        match parse_ports(&spec) {
+            Ok(ports) => {
+                prop_assert!(ports.windows(2).all(|w| w[0] < w[1]), "not sorted and deduplicated");
+                prop_assert!(ports.iter().all(|&p| p >= 1));
+                prop_assert_eq!(ports, expected.into_iter().collect::<Vec<_>>());
+            }
+            Err(ScanError::InvalidPorts(e)) => {
+                prop_assert_eq!(e.kind, SpecErrorKind::NoPorts);
+                prop_assert!(expected.is_empty());
+            }
+            Err(e) => prop_assert!(false, "unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn port_errors_point_into_the_spec(spec in "[0-9, -]{0,24}|\\PC{0,24}") {
+        if let Err(ScanError::InvalidPorts(e)) = parse_ports(&spec) {
+            prop_assert!(
+                spec.get(e.position..).is_some_and(|rest| rest.starts_with(&e.fragment)),
+                "{:?} is not at {} in {:?}", e.fragment, e.position, spec
+            );
+        }
+    }
+
+    #[test]
+    fn target_errors_point_into_the_spec(spec in "[0-9a-f.:/ -]{0,40}|\\PC{0,40}") {
+        if let Err(e) = TargetSet::new().insert(&spec) {
+            prop_assert!(
+                spec.get(e.position..).is_some_and(|rest| rest.starts_with(&e.fragment)),
+                "{:?} is not at {} in {:?}", e.fragment, e.position, spec
+            );
+        }
+    }
+
+    #[test]
+    fn cidr_blocks_hold_their_address(ip in any::<u32>(), v4_len in 0..=32u32, ip6 in any::<u128>(), v6_len in 1..=128u32) {
+        let mut v4 = TargetSet::new();
+        v4.insert(&format!("{}/{}", Ipv4Addr::from(ip), v4_len)).unwrap();
+        prop_assert_eq!(v4.len(), 1u128 << (32 - v4_len));
+        prop_assert!(v4.contains(Ipv4Addr::from(ip).into()));
+
+        let mut v6 = TargetSet::new();
+        v6.insert(&format!("{}/{}", Ipv6Addr::from(ip6), v6_len)).unwrap();
+        prop_assert_eq!(v6.len(), 1u128 << (128 - v6_len));
+        prop_assert!(v6.contains(Ipv6Addr::from(ip6).into()));
+    }
+
+    #[test]
+    fn address_ranges_hold_both_ends(a in any::<u32>(), b in any::<u32>()) {
+        let (start, end) = (Ipv4Addr::from(a), Ipv4Addr::from(b));
+        let mut targets = TargetSet::new();
+        match targets.insert(&format!("{}-{}", start, end)) {
+            Ok(()) => {
+                prop_assert!(a <= b);
+                prop_assert_eq!(targets.len(), (b - a) as u128 + 1);
+                prop_assert!(targets.contains(start.into()) && targets.contains(end.into()));
+            }
+            Err(e) => {
+                prop_assert!(a > b);
+                prop_assert_eq!(e.kind, SpecErrorKind::StartAfterEnd);
+            }
+        }
+    }
+}
@@ -0,0 +1,533 @@
+//! Fake services on loopback ports for end-to-end tests.
+//!
+//! ```ignore
+//! let ssh = FakeService::banner(b"SSH-2.0-Test\r\n").spawn().await;
+//! let web = FakeService::http().start(); // from a synchronous test
+//! ```
+//!
+//! A service runs until its [`FakeService`] handle is dropped.
+
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, TcpListener as StdListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+/// What a fake service does with each connection it accepts.
+#[derive(Debug, Clone)]
+pub enum Behavior {
+    /// Close the connection straight away.
+    Close,
+    /// Send these bytes, then keep the connection open.
+    Banner(&'static [u8]),
+    /// Answer a request with a `200`, or a client that stays silent for
+    /// `idle` with a `408`, as real web servers do.
+    Http { idle: Duration },
+    /// Forward absolute-form `GET http://…` requests, like an open HTTP
+    /// proxy.
+    Proxy,
+    /// Keep the connection open without ever sending anything.
+    Silent,
+    /// Reset the connection (`SO_LINGER` 0, then close).
+    Reset,
+    /// Reset the first connection after a moment, then greet later ones with
+    /// these bytes, like a service that is still starting up.
+    ResetFirst(&'static [u8]),
+}
+
+/// A loopback listener with a scripted [`Behavior`].
+#[derive(Debug, Clone)]
+pub struct ServiceBuilder {
+    behavior: Behavior,
+    hold: Duration,
+}
+
+impl ServiceBuilder {
+    /// How long connections are kept open after the greeting (default 3 s).
+    pub fn hold(mut self, hold: Duration) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    /// Starts the service on the current Tokio runtime.
+    pub async fn spawn(self) -> FakeService {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let count = accepted.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let nth = count.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(serve(stream, self.behavior.clone(), self.hold, nth));
+            }
+        });
+        FakeService {
+            addr,
+            task,
+            accepted,
+        }
+    }
+
+    /// Starts the service on a shared background runtime, for tests that are
+    /// not async themselves. Must not be called from inside a runtime.
+    pub fn start(self) -> FakeService {
+        background().block_on(self.spawn())
+    }
+}
+
+/// A running fake service, stopped when dropped.
+#[derive(Debug)]
+pub struct FakeService {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+    accepted: Arc<AtomicUsize>,
+}
+
+impl FakeService {
+    /// A service that accepts and closes immediately.
+    pub fn close() -> ServiceBuilder {
+        Self::builder(Behavior::Close)
+    }
+
+    /// A service that greets with `banner`, like SSH or SMTP.
+    pub fn banner(banner: &'static [u8]) -> ServiceBuilder {
+        Self::builder(Behavior::Banner(banner))
+    }
+
+    /// A web server that answers a silent client after 100 ms.
+    pub fn http() -> ServiceBuilder {
+        Self::builder(Behavior::Http {
+            idle: Duration::from_millis(100),
+        })
+    }
+
+    /// An open HTTP proxy that forwards whatever it is asked to fetch.
+    pub fn proxy() -> ServiceBuilder {
+        Self::builder(Behavior::Proxy)
+    }
+
+    /// A service that accepts and never says a word.
+    pub fn silent() -> ServiceBuilder {
+        Self::builder(Behavior::Silent)
+    }
+
+    /// A service that resets every connection after accepting it.
+    pub fn reset() -> ServiceBuilder {
+        Self::builder(Behavior::Reset)
+    }
+
+    /// A service that resets the first connection and greets the ones after
+    /// it with `banner`.
+    pub fn reset_first(banner: &'static [u8]) -> ServiceBuilder {
+        Self::builder(Behavior::ResetFirst(banner))
+    }
+
+    /// A service with any behavior.
+    pub fn builder(behavior: Behavior) -> ServiceBuilder {
+        ServiceBuilder {
+            behavior,
+            hold: Duration::from_secs(3),
+        }
+    }
+
+    /// The port the service listens on.
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// The address the service listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// How many connections the service has accepted.
+    pub fn connections(&self) -> usize {
+        self.accepted.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for FakeService {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// An SMTP relay that accepts every message and hands over what was sent
+/// after DATA, stopped when dropped.
+#[derive(Debug)]
+pub struct SmtpSink {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+    messages: mpsc::Receiver<String>,
+}
+
+impl SmtpSink {
+    /// Starts the relay on the shared background runtime.
+    pub fn start() -> SmtpSink {
+        let (tx, messages) = mpsc::channel();
+        background().block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let task = tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(smtp_session(stream, tx.clone()));
+                }
+            });
+            SmtpSink {
+                addr,
+                task,
+                messages,
+            }
+        })
+    }
+
+    /// The relay's address as `--smtp-server` takes it.
+    pub fn server(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// The next message, unstuffed and with CRLF line ends, if one
+    /// arrives within `wait`.
+    pub fn message(&self, wait: Duration) -> Option<String> {
+        self.messages.recv_timeout(wait).ok()
+    }
+}
+
+impl Drop for SmtpSink {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn smtp_session(stream: TcpStream, messages: mpsc::Sender<String>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let _ = write.write_all(b"220 sink ESMTP\r\n").await;
+    while let Ok(Some(line)) = lines.next_line().await {
+        let verb = line.split(' ').next().unwrap_or("").to_ascii_uppercase();
+        let reply: &[u8] = match verb.as_str() {
+            "EHLO" => b"250-sink\r\n250 8BITMIME\r\n",
+            "DATA" => {
+                let _ = write.write_all(b"354 go ahead\r\n").await;
+                let mut message = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    message.push_str(line.strip_prefix('.').unwrap_or(&line));
+                    message.push_str("\r\n");
+                }
+                let _ = messages.send(message);
+                b"250 queued\r\n"
+            }
+            "QUIT" => {
+                let _ = write.write_all(b"221 bye\r\n").await;
+                return;
+            }
+            _ => b"250 ok\r\n",
+        };
+        let _ = write.write_all(reply).await;
+    }
+}
+
+/// One request an [`ObjectStore`] received.
+#[derive(Debug, Clone)]
+pub struct StoredRequest {
+    /// The request line's method, such as `PUT`.
+    pub method: String,
+    /// The request path, still percent-encoded.
+    pub path: String,
+    /// Header names lower-cased, in the order sent.
+    pub headers: Vec<(String, String)>,
+    /// The request body.
+    pub body: Vec<u8>,
+}
+
+impl StoredRequest {
+    /// The value of header `name` (lower-case), if sent.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// An S3-compatible endpoint that records every request and answers with
+/// scripted statuses, stopped when dropped.
+#[derive(Debug)]
+pub struct ObjectStore {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+    requests: Arc<Mutex<Vec<StoredRequest>>>,
+}
+
+impl ObjectStore {
+    /// Answers the first requests with `statuses` in turn and `200`
+    /// afterwards. Starts on the shared background runtime.
+    pub fn start(statuses: &[u16]) -> ObjectStore {
+        let statuses = statuses.to_vec();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        background().block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let task = tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(store_request(stream, log.clone(), statuses.clone()));
+                }
+            });
+            ObjectStore {
+                addr,
+                task,
+                requests,
+            }
+        })
+    }
+
+    /// The `--s3-endpoint` of the store.
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The requests received so far.
+    pub fn requests(&self) -> Vec<StoredRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for ObjectStore {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn store_request(stream: TcpStream, log: Arc<Mutex<Vec<StoredRequest>>>, statuses: Vec<u16>) {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await.is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line == "\r\n" => break,
+            Ok(_) => {
+                if let Some((name, value)) = line.trim_end().split_once(':') {
+                    headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+                }
+            }
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(n, _)| n == "content-length")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    if reader.read_exact(&mut body).await.is_err() {
+        return;
+    }
+    let status = {
+        let mut log = log.lock().unwrap();
+        let status = statuses.get(log.len()).copied().unwrap_or(200);
+        log.push(StoredRequest {
+            method,
+            path,
+            headers,
+            body,
+        });
+        status
+    };
+    let body = if status == 200 {
+        String::new()
+    } else {
+        format!("<Error><Code>Mock{}</Code></Error>", status)
+    };
+    let reply = format!(
+        "HTTP/1.1 {} Mock\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = write.write_all(reply.as_bytes()).await;
+    let _ = write.shutdown().await;
+}
+
+/// A NATS server that keeps what is published to it, stopped when
+/// dropped.
+#[derive(Debug)]
+pub struct NatsServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+    published: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl NatsServer {
+    /// Starts the server on the shared background runtime.
+    pub fn start() -> NatsServer {
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let log = published.clone();
+        background().block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let task = tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(nats_session(stream, log.clone()));
+                }
+            });
+            NatsServer {
+                addr,
+                task,
+                published,
+            }
+        })
+    }
+
+    /// The server's address as a `--publish` URL takes it.
+    pub fn server(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// The subjects and payloads published so far, in order.
+    pub fn published(&self) -> Vec<(String, String)> {
+        self.published.lock().unwrap().clone()
+    }
+}
+
+impl Drop for NatsServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn nats_session(stream: TcpStream, log: Arc<Mutex<Vec<(String, String)>>>) {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let _ = write
+        .write_all(b"INFO {\"server_id\":\"fake\",\"max_payload\":1048576}\r\n")
+        .await;
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("PUB") => {
+                let subject = parts.next().unwrap_or("").to_string();
+                let len: usize = parts.last().and_then(|n| n.parse().ok()).unwrap_or(0);
+                let mut payload = vec![0; len + 2];
+                if reader.read_exact(&mut payload).await.is_err() {
+                    return;
+                }
+                payload.truncate(len);
+                let payload = String::from_utf8_lossy(&payload).into_owned();
+                log.lock().unwrap().push((subject, payload));
+            }
+            Some("PING") => {
+                let _ = write.write_all(b"PONG\r\n").await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A loopback port with nothing listening on it.
+pub fn closed_port() -> u16 {
+    StdListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// A unique path in the temp directory for this test binary.
+pub fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("scanner-test-{}-{}", std::process::id(), name))
+}
+
+fn background() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap()
+    })
+}
+
+/// Plays `behavior` on the `nth` connection the service accepted, from 0.
+async fn serve(mut stream: TcpStream, behavior: Behavior, hold: Duration, nth: usize) {
+    match behavior {
+        Behavior::Close => return,
+        Behavior::Banner(banner) => {
+            let _ = stream.write_all(banner).await;
+        }
+        Behavior::Http { idle } => {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            let response: &[u8] = loop {
+                match tokio::time::timeout(idle, stream.read(&mut buf)).await {
+                    Err(_) => break b"HTTP/1.1 408 Request Timeout\r\nServer: fake\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                    Ok(Ok(0)) | Ok(Err(_)) => return,
+                    Ok(Ok(n)) => {
+                        request.extend_from_slice(&buf[..n]);
+                        if request.windows(4).any(|w| w == b"\r\n\r\n") {
+                            break b"HTTP/1.1 200 OK\r\nServer: fake\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok";
+                        }
+                    }
+                }
+            };
+            let _ = stream.write_all(response).await;
+            return;
+        }
+        Behavior::Proxy => {
+            let mut buf = [0u8; 2048];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            let Some(authority) = request
+                .strip_prefix("GET http://")
+                .and_then(|target| target.split('/').next())
+            else {
+                return;
+            };
+            if let Ok(mut upstream) = TcpStream::connect(authority).await {
+                let _ = upstream.write_all(request.as_bytes()).await;
+                let mut answer = Vec::new();
+                let _ = upstream.read_to_end(&mut answer).await;
+                let _ = stream.write_all(&answer).await;
+            }
+            return;
+        }
+        Behavior::Silent => {}
+        Behavior::Reset => {
+            let _ = stream.set_zero_linger();
+            return;
+        }
+        Behavior::ResetFirst(_) if nth == 0 => {
+            // Once the client is past its connect, so that the reset meets
+            // its banner read.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let _ = stream.set_zero_linger();
+            return;
+        }
+        Behavior::ResetFirst(banner) => {
+            let _ = stream.write_all(banner).await;
+        }
+    }
+    tokio::time::sleep(hold).await;
+}